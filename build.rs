@@ -0,0 +1,20 @@
+//! Embed build metadata shown by `/version`: the build time and the latest
+//! migration.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Without `rerun-if-changed`, this script reruns on every change of the
+    // package, so the build time stays current.
+    let build_time =
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    println!("cargo:rustc-env=BUILD_TIME={build_time}");
+
+    let latest_migration = std::fs::read_dir("migrations")
+        .expect("Failed to read migrations")
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.contains('_'))
+        .max()
+        .unwrap_or_default();
+    println!("cargo:rustc-env=LATEST_MIGRATION={latest_migration}");
+}
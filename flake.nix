@@ -31,7 +31,8 @@
             # rust-src is required for rust-analyzer
             extensions = [ "rust-src" ];
           };
-          baseRuntimeDeps = [ pkgs.bash pkgs.imagemagick pkgs.sqlite ];
+          baseRuntimeDeps =
+            [ pkgs.bash pkgs.imagemagick pkgs.sqlite pkgs.weasyprint ];
           allRuntimeDeps = baseRuntimeDeps
             ++ [ residents-admin-table residents-timeline ];
           buildDeps = [ pkgs.openssl pkgs.perl pkgs.pkg-config pkgs.sqlite ];
@@ -79,8 +80,15 @@
           packages.f0bot-unwrapped = crane.lib.${system}.buildPackage {
             src = nix-filter.lib {
               root = ./.;
-              include =
-                [ "src" "Cargo.toml" "Cargo.lock" "config.example.yaml" ];
+              include = [
+                "src"
+                "migrations"
+                "build.rs"
+                "Cargo.toml"
+                "Cargo.lock"
+                "CHANGELOG.md"
+                "config.example.yaml"
+              ];
             };
             nativeBuildInputs = buildDeps;
           };
@@ -0,0 +1,7 @@
+CREATE TABLE mail_messages (
+  message_id TEXT PRIMARY KEY NOT NULL, -- RFC 5322 Message-ID
+  inbound BOOLEAN NOT NULL,
+  chat_id BIGINT NOT NULL,
+  tg_message_id INTEGER NOT NULL,
+  created_at DATETIME NOT NULL
+);
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS presence_log;
+ALTER TABLE needed_items DROP COLUMN created_at;
+ALTER TABLE needed_items DROP COLUMN bought_at;
+ALTER TABLE borrowed_items DROP COLUMN created_at;
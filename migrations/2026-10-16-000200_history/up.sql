@@ -0,0 +1,10 @@
+CREATE TABLE presence_log (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  timestamp DATETIME NOT NULL,
+  person_count INTEGER NOT NULL
+);
+
+-- NULL for rows created before this migration
+ALTER TABLE needed_items ADD COLUMN created_at DATETIME;
+ALTER TABLE needed_items ADD COLUMN bought_at DATETIME;
+ALTER TABLE borrowed_items ADD COLUMN created_at DATETIME;
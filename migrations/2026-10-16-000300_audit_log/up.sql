@@ -0,0 +1,7 @@
+CREATE TABLE audit_log (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  timestamp DATETIME NOT NULL,
+  actor_id BIGINT /* REFERENCES tg_users(id) */, -- NULL for the bot itself
+  action TEXT NOT NULL,
+  payload TEXT NOT NULL -- JSON
+);
@@ -0,0 +1,11 @@
+CREATE TABLE pending_approvals (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL,
+  requester_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  message TEXT NOT NULL, -- JSON
+  created_at DATETIME NOT NULL,
+  approver_id BIGINT /* REFERENCES tg_users(id) */, -- NULL until approved
+  executed BOOLEAN NOT NULL,
+  UNIQUE (chat_id, message_id)
+);
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS ballot_tallies;
+DROP TABLE IF EXISTS ballot_voters;
+DROP TABLE IF EXISTS ballots;
@@ -0,0 +1,27 @@
+CREATE TABLE ballots (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL, -- Public progress message
+  creator_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  question TEXT NOT NULL,
+  options TEXT NOT NULL, -- JSON
+  created_at DATETIME NOT NULL,
+  closed_at DATETIME, -- NULL means "open"
+  UNIQUE (chat_id, message_id)
+);
+
+-- Who voted, without their choices.
+CREATE TABLE ballot_voters (
+  ballot_id INTEGER NOT NULL /* REFERENCES ballots(rowid) */,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  PRIMARY KEY (ballot_id, user_id)
+);
+
+-- Sealed choices: only aggregate counters are stored, so individual votes
+-- can't be recovered even with access to the database.
+CREATE TABLE ballot_tallies (
+  ballot_id INTEGER NOT NULL /* REFERENCES ballots(rowid) */,
+  option INTEGER NOT NULL,
+  votes INTEGER NOT NULL,
+  PRIMARY KEY (ballot_id, option)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE reimbursements (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  requester_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  amount_cents BIGINT NOT NULL,
+  description TEXT NOT NULL,
+  receipt_file_id TEXT, -- Telegram file id of the receipt photo
+  chat_id BIGINT NOT NULL, -- Message posted to the treasury thread
+  message_id INTEGER NOT NULL,
+  status TEXT NOT NULL, -- 'pending', 'approved', 'rejected', or 'paid'
+  created_at DATETIME NOT NULL,
+  decided_at DATETIME,
+  decided_by BIGINT /* REFERENCES tg_users(id) */,
+  paid_at DATETIME
+);
@@ -0,0 +1,8 @@
+CREATE TABLE donations (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  donor TEXT NOT NULL,
+  amount_cents BIGINT NOT NULL,
+  private BOOLEAN NOT NULL, -- Don't mention the donor publicly
+  recorded_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  created_at DATETIME NOT NULL
+);
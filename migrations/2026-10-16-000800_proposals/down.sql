@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS proposal_options;
+DROP TABLE IF EXISTS proposals;
@@ -0,0 +1,17 @@
+CREATE TABLE proposals (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL, -- Bot message collecting suggestions
+  creator_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  question TEXT NOT NULL,
+  created_at DATETIME NOT NULL,
+  started_at DATETIME, -- NULL while suggestions are open
+  UNIQUE (chat_id, message_id)
+);
+
+CREATE TABLE proposal_options (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  proposal_id INTEGER NOT NULL /* REFERENCES proposals(rowid) */,
+  text TEXT NOT NULL,
+  author_id BIGINT NOT NULL /* REFERENCES tg_users(id) */
+);
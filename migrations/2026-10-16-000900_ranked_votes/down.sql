@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS ranked_ballots;
+DROP TABLE IF EXISTS ranked_votes;
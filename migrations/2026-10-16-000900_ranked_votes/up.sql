@@ -0,0 +1,19 @@
+CREATE TABLE ranked_votes (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL, -- Public progress message
+  creator_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  question TEXT NOT NULL,
+  options TEXT NOT NULL, -- JSON
+  created_at DATETIME NOT NULL,
+  closed_at DATETIME, -- NULL means "open"
+  UNIQUE (chat_id, message_id)
+);
+
+CREATE TABLE ranked_ballots (
+  vote_id INTEGER NOT NULL /* REFERENCES ranked_votes(rowid) */,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  ranking TEXT NOT NULL, -- JSON, option indices from the most preferred
+  submitted BOOLEAN NOT NULL,
+  PRIMARY KEY (vote_id, user_id)
+);
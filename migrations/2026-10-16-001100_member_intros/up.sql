@@ -0,0 +1,10 @@
+CREATE TABLE member_intros (
+  user_id BIGINT PRIMARY KEY NOT NULL /* REFERENCES tg_users(id) */,
+  skills TEXT NOT NULL,
+  interests TEXT NOT NULL,
+  pronouns TEXT NOT NULL,
+  updated_at DATETIME NOT NULL,
+  -- Message in the introductions topic, NULL if not posted yet
+  chat_id BIGINT,
+  message_id INTEGER
+);
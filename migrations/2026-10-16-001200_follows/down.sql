@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS follow_mutes;
+DROP TABLE IF EXISTS follows;
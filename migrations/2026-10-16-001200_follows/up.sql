@@ -0,0 +1,10 @@
+CREATE TABLE follows (
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  tag TEXT NOT NULL,
+  PRIMARY KEY (user_id, tag)
+);
+
+CREATE TABLE follow_mutes (
+  user_id BIGINT PRIMARY KEY NOT NULL /* REFERENCES tg_users(id) */,
+  until DATETIME -- NULL means "until unmuted"
+);
@@ -0,0 +1,26 @@
+CREATE TABLE projects (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL UNIQUE,
+  status TEXT NOT NULL,
+  owner_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  -- Linked forum topic, NULL if the project was created outside of a topic
+  chat_id BIGINT,
+  thread_id INTEGER,
+  created_at DATETIME NOT NULL,
+  updated_at DATETIME NOT NULL,
+  nudged_at DATETIME -- Last "stale project" reminder
+);
+
+CREATE TABLE project_members (
+  project_id INTEGER NOT NULL /* REFERENCES projects(rowid) */,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  PRIMARY KEY (project_id, user_id)
+);
+
+CREATE TABLE project_log (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  project_id INTEGER NOT NULL /* REFERENCES projects(rowid) */,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  text TEXT NOT NULL,
+  created_at DATETIME NOT NULL
+);
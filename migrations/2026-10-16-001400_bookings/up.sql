@@ -0,0 +1,11 @@
+CREATE TABLE bookings (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  resource TEXT NOT NULL,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  start_at DATETIME NOT NULL, -- UTC
+  end_at DATETIME NOT NULL, -- UTC
+  reminded BOOLEAN NOT NULL,
+  created_at DATETIME NOT NULL
+);
+
+CREATE INDEX bookings_resource_start_at ON bookings (resource, start_at);
@@ -0,0 +1,11 @@
+CREATE TABLE duty_assignments (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  week TEXT NOT NULL, -- ISO week, e.g. "2026-W42"
+  duty TEXT NOT NULL,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  status TEXT NOT NULL, -- "pending", "done", or "skipped"
+  proof_file_id TEXT, -- Photo proof, if provided
+  chat_id BIGINT NOT NULL, -- Weekly assignments message
+  message_id INTEGER NOT NULL,
+  UNIQUE (week, duty, user_id)
+);
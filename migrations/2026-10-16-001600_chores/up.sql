@@ -0,0 +1,12 @@
+CREATE TABLE chores (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL UNIQUE,
+  due_at DATETIME NOT NULL, -- UTC
+  -- 0: not posted, 1: posted to the topic, 2: residents pinged,
+  -- 3: reported to admins
+  stage INTEGER NOT NULL,
+  last_done_at DATETIME, -- UTC
+  last_done_by BIGINT /* REFERENCES tg_users(id) */,
+  chat_id BIGINT,
+  message_id INTEGER
+);
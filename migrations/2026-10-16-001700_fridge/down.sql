@@ -0,0 +1 @@
+DROP TABLE IF EXISTS fridge_items;
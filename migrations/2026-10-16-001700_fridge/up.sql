@@ -0,0 +1,11 @@
+CREATE TABLE fridge_items (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  owner BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  name TEXT NOT NULL,
+  expires_on DATE NOT NULL,
+  -- "active", "claimed", or "discarded"
+  status TEXT NOT NULL,
+  added_at DATETIME NOT NULL
+);
+
+CREATE INDEX fridge_items_status_expires_on ON fridge_items (status, expires_on);
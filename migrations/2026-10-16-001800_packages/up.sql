@@ -0,0 +1,8 @@
+CREATE TABLE packages (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  recipient BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  description TEXT NOT NULL,
+  accepted_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  accepted_at DATETIME NOT NULL, -- UTC
+  picked_up_at DATETIME -- UTC, NULL while unclaimed
+);
@@ -0,0 +1,20 @@
+CREATE TABLE incidents (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  reporter BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  -- "injury", "near_miss", "damage", or "other"
+  kind TEXT NOT NULL,
+  description TEXT NOT NULL,
+  -- "open", "investigating", or "resolved"
+  status TEXT NOT NULL,
+  created_at DATETIME NOT NULL, -- UTC
+  updated_at DATETIME NOT NULL -- UTC
+);
+
+CREATE TABLE incident_updates (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  incident_id INTEGER NOT NULL REFERENCES incidents(rowid),
+  author BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  status TEXT NOT NULL,
+  note TEXT NOT NULL,
+  created_at DATETIME NOT NULL -- UTC
+);
@@ -0,0 +1,10 @@
+CREATE TABLE faq_entries (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  -- A regex if `is_regex`, otherwise comma-separated keywords.
+  pattern TEXT NOT NULL,
+  is_regex BOOLEAN NOT NULL,
+  answer TEXT NOT NULL,
+  uses INTEGER NOT NULL DEFAULT 0,
+  created_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  created_at DATETIME NOT NULL -- UTC
+);
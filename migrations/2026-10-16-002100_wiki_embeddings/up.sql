@@ -0,0 +1,9 @@
+CREATE TABLE wiki_embeddings (
+  -- Page path including the locale, e.g. `/en/rules`.
+  path TEXT NOT NULL,
+  chunk INTEGER NOT NULL,
+  content TEXT NOT NULL,
+  -- Little-endian f32 vector.
+  embedding BLOB NOT NULL,
+  PRIMARY KEY (path, chunk)
+);
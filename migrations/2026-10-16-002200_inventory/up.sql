@@ -0,0 +1,16 @@
+CREATE TABLE inventory_items (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL UNIQUE,
+  quantity INTEGER NOT NULL,
+  added_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  updated_at DATETIME NOT NULL -- UTC
+);
+
+-- Items recognized from photos, waiting for confirmation.
+CREATE TABLE inventory_intakes (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  -- JSON list of `{"name": ..., "quantity": ...}` objects.
+  items TEXT NOT NULL,
+  created_at DATETIME NOT NULL -- UTC
+);
@@ -0,0 +1,16 @@
+CREATE TABLE moderation_cases (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  text TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  -- One of 'pending', 'deleted', 'banned', 'ignored'.
+  status TEXT NOT NULL,
+  moderator BIGINT NULL /* REFERENCES tg_users(id) */,
+  appeal TEXT NULL,
+  created_at DATETIME NOT NULL, -- UTC
+  resolved_at DATETIME NULL -- UTC
+);
+
+CREATE INDEX moderation_cases_user_id ON moderation_cases(user_id);
@@ -0,0 +1,12 @@
+CREATE TABLE spam_suspects (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  score INTEGER NOT NULL,
+  -- Comma-separated list of triggered heuristics.
+  reasons TEXT NOT NULL,
+  -- One of 'pending', 'unmuted', 'banned'.
+  status TEXT NOT NULL,
+  reviewed_by BIGINT NULL /* REFERENCES tg_users(id) */,
+  created_at DATETIME NOT NULL -- UTC
+);
@@ -0,0 +1,9 @@
+CREATE TABLE archived_links (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  url TEXT NOT NULL UNIQUE,
+  archive_url TEXT NOT NULL,
+  -- The message the link was first seen in.
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL,
+  archived_at DATETIME NOT NULL -- UTC
+);
@@ -0,0 +1,19 @@
+CREATE TABLE feeds (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  url TEXT NOT NULL,
+  chat_id BIGINT NOT NULL,
+  thread_id INTEGER NOT NULL,
+  -- Comma-separated lowercase keywords, empty to accept all entries.
+  include TEXT NOT NULL,
+  -- Comma-separated lowercase keywords, empty to reject none.
+  exclude TEXT NOT NULL,
+  added_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  created_at DATETIME NOT NULL -- UTC
+);
+
+CREATE TABLE feed_entries (
+  feed_id INTEGER NOT NULL /* REFERENCES feeds(rowid) */,
+  guid TEXT NOT NULL,
+  seen_at DATETIME NOT NULL, -- UTC
+  PRIMARY KEY (feed_id, guid)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE service_status (
+  name TEXT PRIMARY KEY NOT NULL,
+  up BOOLEAN NOT NULL,
+  -- When the service went up or down.
+  since DATETIME NOT NULL, -- UTC
+  checked_at DATETIME NOT NULL, -- UTC
+  error TEXT NULL
+);
@@ -0,0 +1,14 @@
+CREATE TABLE door_credentials (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  -- One of 'keycode', 'nfc'.
+  kind TEXT NOT NULL,
+  code TEXT NOT NULL,
+  provisioned_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  created_at DATETIME NOT NULL, -- UTC
+  revoked_at DATETIME NULL, -- UTC
+  -- NULL if revoked automatically on offboarding.
+  revoked_by BIGINT NULL /* REFERENCES tg_users(id) */
+);
+
+CREATE INDEX door_credentials_user_id ON door_credentials(user_id);
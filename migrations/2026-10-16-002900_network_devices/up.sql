@@ -0,0 +1,11 @@
+CREATE TABLE network_devices (
+  mac TEXT PRIMARY KEY NOT NULL,
+  host_name TEXT NULL,
+  address TEXT NULL,
+  dhcp_server TEXT NULL,
+  first_seen DATETIME NOT NULL, -- UTC
+  last_seen DATETIME NOT NULL, -- UTC
+  infrastructure BOOLEAN NOT NULL,
+  -- Alert message in the 'network_devices.thread', if sent.
+  alert_message_id INTEGER NULL
+);
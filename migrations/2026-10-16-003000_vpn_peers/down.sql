@@ -0,0 +1 @@
+DROP TABLE IF EXISTS vpn_peers;
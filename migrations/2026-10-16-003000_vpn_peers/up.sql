@@ -0,0 +1,12 @@
+CREATE TABLE vpn_peers (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  address TEXT NOT NULL,
+  public_key TEXT NOT NULL,
+  -- Peer ID in the MikroTik REST API.
+  router_id TEXT NOT NULL,
+  created_at DATETIME NOT NULL, -- UTC
+  revoked_at DATETIME NULL -- UTC
+);
+
+CREATE INDEX vpn_peers_user_id ON vpn_peers(user_id);
@@ -0,0 +1,12 @@
+CREATE TABLE guest_vouchers (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  issued_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  username TEXT NOT NULL,
+  -- Hotspot user ID in the MikroTik REST API.
+  router_id TEXT NOT NULL,
+  created_at DATETIME NOT NULL, -- UTC
+  expires_at DATETIME NOT NULL, -- UTC
+  removed_at DATETIME NULL -- UTC
+);
+
+CREATE INDEX guest_vouchers_issued_by ON guest_vouchers(issued_by);
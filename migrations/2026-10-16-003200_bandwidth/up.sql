@@ -0,0 +1,16 @@
+-- Last seen values of the router traffic counters.
+CREATE TABLE bandwidth_counters (
+  mac TEXT PRIMARY KEY NOT NULL,
+  bytes_down BIGINT NOT NULL,
+  bytes_up BIGINT NOT NULL,
+  updated_at DATETIME NOT NULL -- UTC
+);
+
+-- Traffic per device per day.
+CREATE TABLE bandwidth_usage (
+  date DATE NOT NULL, -- UTC
+  mac TEXT NOT NULL,
+  bytes_down BIGINT NOT NULL,
+  bytes_up BIGINT NOT NULL,
+  PRIMARY KEY (date, mac)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE user_preferences (
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  name TEXT NOT NULL,
+  -- JSON-encoded value.
+  value TEXT NOT NULL,
+  PRIMARY KEY (user_id, name)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE archived_links DROP COLUMN deleted_at;
+ALTER TABLE audit_log DROP COLUMN deleted_at;
+ALTER TABLE mail_messages DROP COLUMN deleted_at;
+ALTER TABLE presence_log DROP COLUMN deleted_at;
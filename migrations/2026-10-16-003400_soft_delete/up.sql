@@ -0,0 +1,6 @@
+-- UTC time when the row was soft-deleted by the 'retention' module, it is
+-- purged after the grace period.
+ALTER TABLE archived_links ADD COLUMN deleted_at DATETIME;
+ALTER TABLE audit_log ADD COLUMN deleted_at DATETIME;
+ALTER TABLE mail_messages ADD COLUMN deleted_at DATETIME;
+ALTER TABLE presence_log ADD COLUMN deleted_at DATETIME;
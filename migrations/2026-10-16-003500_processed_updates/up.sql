@@ -0,0 +1,4 @@
+CREATE TABLE processed_updates (
+  update_id INTEGER NOT NULL PRIMARY KEY,
+  processed_at TIMESTAMP NOT NULL
+);
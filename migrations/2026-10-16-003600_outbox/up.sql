@@ -0,0 +1,11 @@
+CREATE TABLE outbox (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  action TEXT NOT NULL, -- JSON
+  -- JSON list of actions to perform if the action fails permanently.
+  compensation TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TIMESTAMP NOT NULL,
+  last_error TEXT,
+  failed_at TIMESTAMP
+);
@@ -0,0 +1,2 @@
+ALTER TABLE tracked_polls DROP COLUMN poll_message_id;
+ALTER TABLE tracked_polls DROP COLUMN closed_at;
@@ -0,0 +1,4 @@
+-- The poll message in the chat of the info message, NULL for polls tracked
+-- before this column was added.
+ALTER TABLE tracked_polls ADD COLUMN poll_message_id INTEGER;
+ALTER TABLE tracked_polls ADD COLUMN closed_at DATETIME;
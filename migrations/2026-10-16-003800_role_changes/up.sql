@@ -0,0 +1,10 @@
+CREATE TABLE role_changes (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  tg_id BIGINT NOT NULL,
+  -- `visitor`, `resident`, or `former`.
+  old_role TEXT NOT NULL,
+  new_role TEXT NOT NULL,
+  changed_at TIMESTAMP NOT NULL,
+  -- Set after the hooks were run.
+  handled_at TIMESTAMP
+);
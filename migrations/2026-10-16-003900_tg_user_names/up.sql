@@ -0,0 +1,11 @@
+-- Previous names of users in tg_users.
+CREATE TABLE tg_user_names (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  username TEXT,
+  first_name TEXT NOT NULL,
+  last_name TEXT,
+  -- When the bot noticed the user changed this name.
+  replaced_at TIMESTAMP NOT NULL
+);
+CREATE INDEX tg_user_names_username ON tg_user_names(username);
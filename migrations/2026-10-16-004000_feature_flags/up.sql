@@ -0,0 +1,6 @@
+CREATE TABLE feature_flags (
+  name TEXT NOT NULL,
+  chat_id BIGINT NOT NULL,
+  enabled BOOLEAN NOT NULL,
+  PRIMARY KEY (name, chat_id)
+);
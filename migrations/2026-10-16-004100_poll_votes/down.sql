@@ -0,0 +1,2 @@
+DROP TABLE tracked_poll_votes;
+ALTER TABLE tracked_polls DROP COLUMN options;
@@ -0,0 +1,14 @@
+-- Option texts of the poll as a JSON list, NULL for polls tracked before this
+-- column was added.
+ALTER TABLE tracked_polls ADD COLUMN options TEXT;
+
+-- Every answer to tracked polls.  A retracted vote has no options.
+CREATE TABLE tracked_poll_votes (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  tg_poll_id TEXT NOT NULL /* REFERENCES tracked_polls(tg_poll_id) */,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  -- JSON list of zero-based option indexes.
+  option_ids TEXT NOT NULL,
+  voted_at DATETIME NOT NULL -- UTC
+);
+CREATE INDEX tracked_poll_votes_tg_poll_id ON tracked_poll_votes(tg_poll_id);
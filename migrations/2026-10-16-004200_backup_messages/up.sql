@@ -0,0 +1,8 @@
+-- Copies of messages in the backup channel, see the 'backup' module.
+CREATE TABLE backup_messages (
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL,
+  backup_chat_id BIGINT NOT NULL,
+  backup_message_id INTEGER NOT NULL,
+  PRIMARY KEY (chat_id, message_id)
+);
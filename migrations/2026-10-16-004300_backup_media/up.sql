@@ -0,0 +1,12 @@
+-- Media of mirrored messages stored by the 'backup' module.
+CREATE TABLE backup_media (
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL,
+  file_unique_id TEXT NOT NULL,
+  file_name TEXT,
+  mime_type TEXT,
+  size BIGINT NOT NULL,
+  storage_key TEXT NOT NULL,
+  stored_at TIMESTAMP NOT NULL,
+  PRIMARY KEY (chat_id, message_id)
+);
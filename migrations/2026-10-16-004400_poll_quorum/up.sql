@@ -0,0 +1,3 @@
+-- When the poll reached the quorum of the 'poll_webhooks' module, so the
+-- webhook is sent once.
+ALTER TABLE tracked_polls ADD COLUMN quorum_reached_at DATETIME; -- UTC
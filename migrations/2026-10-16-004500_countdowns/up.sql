@@ -0,0 +1,12 @@
+-- Pinned messages counting down to a moment, see the 'countdowns' module.
+CREATE TABLE countdowns (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL,
+  title TEXT NOT NULL,
+  target_at DATETIME NOT NULL, -- UTC
+  created_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  -- Text of the message after the last edit.
+  text TEXT NOT NULL,
+  finished BOOLEAN NOT NULL
+);
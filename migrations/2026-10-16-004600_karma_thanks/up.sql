@@ -0,0 +1,13 @@
+-- Thanks given by replying '/thanks' or '++', see the 'karma' module.
+CREATE TABLE karma_thanks (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  -- The message that was thanked for.
+  message_id INTEGER NOT NULL,
+  from_user BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  to_user BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  created_at DATETIME NOT NULL, -- UTC
+  UNIQUE (chat_id, message_id, from_user)
+);
+
+CREATE INDEX karma_thanks_chat_id_to_user ON karma_thanks (chat_id, to_user);
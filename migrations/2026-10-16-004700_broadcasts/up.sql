@@ -0,0 +1,22 @@
+-- Private messages sent by admins with '/dm', see the 'broadcasts' module.
+CREATE TABLE broadcasts (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  created_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  -- The '/dm' command, the delivery report replies to it.
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL,
+  audience TEXT NOT NULL, -- e.g. "role:resident"
+  text TEXT NOT NULL, -- HTML, as sent
+  created_at DATETIME NOT NULL, -- UTC
+  finished_at DATETIME -- UTC, NULL until all deliveries are done
+);
+
+CREATE TABLE broadcast_deliveries (
+  broadcast_id INTEGER NOT NULL /* REFERENCES broadcasts(rowid) */,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  -- pending, sent, failed, not_started, or opted_out
+  status TEXT NOT NULL,
+  error TEXT,
+  updated_at DATETIME NOT NULL, -- UTC
+  PRIMARY KEY (broadcast_id, user_id)
+);
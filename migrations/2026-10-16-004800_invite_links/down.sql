@@ -0,0 +1,2 @@
+DROP TABLE invite_link_joins;
+DROP TABLE invite_links;
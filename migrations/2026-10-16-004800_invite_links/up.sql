@@ -0,0 +1,22 @@
+-- Invite links created with '/invite create', see the 'invite_links' module.
+CREATE TABLE invite_links (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  invite_link TEXT NOT NULL UNIQUE,
+  created_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  created_at DATETIME NOT NULL, -- UTC
+  expires_at DATETIME, -- UTC, NULL if the link doesn't expire
+  member_limit INTEGER,
+  revoked_at DATETIME -- UTC
+);
+
+-- Users who joined using a link from 'invite_links'.
+CREATE TABLE invite_link_joins (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  invite_link_id INTEGER NOT NULL /* REFERENCES invite_links(rowid) */,
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  joined_at DATETIME NOT NULL -- UTC
+);
+
+CREATE INDEX invite_link_joins_invite_link_id
+  ON invite_link_joins (invite_link_id);
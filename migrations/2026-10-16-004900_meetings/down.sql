@@ -0,0 +1,2 @@
+DROP TABLE meeting_notes;
+DROP TABLE meetings;
@@ -0,0 +1,26 @@
+-- Meetings held with '/meeting start', see the 'meetings' module.
+CREATE TABLE meetings (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  thread_id INTEGER NOT NULL,
+  started_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  started_at DATETIME NOT NULL, -- UTC
+  ended_at DATETIME -- UTC, NULL while the meeting goes on
+);
+
+-- At most one meeting at a time in a topic.
+CREATE UNIQUE INDEX meetings_active
+  ON meetings (chat_id, thread_id) WHERE ended_at IS NULL;
+
+-- Messages, decisions and action items recorded during a meeting.
+CREATE TABLE meeting_notes (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  meeting_id INTEGER NOT NULL /* REFERENCES meetings(rowid) */,
+  kind TEXT NOT NULL, -- 'message', 'decision' or 'action'
+  user_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  assignee_id BIGINT /* REFERENCES tg_users(id) */, -- for 'action'
+  text TEXT NOT NULL,
+  created_at DATETIME NOT NULL -- UTC
+);
+
+CREATE INDEX meeting_notes_meeting_id ON meeting_notes (meeting_id);
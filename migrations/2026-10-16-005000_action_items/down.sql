@@ -0,0 +1,3 @@
+ALTER TABLE meeting_notes DROP COLUMN message_id;
+ALTER TABLE meeting_notes ADD COLUMN assignee_id BIGINT;
+DROP TABLE action_items;
@@ -0,0 +1,34 @@
+-- Action items created with '/action', see the 'action_items' module.
+CREATE TABLE action_items (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  thread_id INTEGER,
+  message_id INTEGER, -- the bot message with the button to mark it done
+  created_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  assignee_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  text TEXT NOT NULL,
+  created_at DATETIME NOT NULL, -- UTC
+  due_at DATETIME, -- UTC
+  reminded BOOLEAN NOT NULL DEFAULT FALSE,
+  done_at DATETIME, -- UTC
+  done_by BIGINT /* REFERENCES tg_users(id) */,
+  meeting_id INTEGER /* REFERENCES meetings(rowid) */,
+  decision_id INTEGER /* REFERENCES meeting_notes(rowid) */
+);
+
+CREATE INDEX action_items_done_at_due_at ON action_items (done_at, due_at);
+
+-- Action items of meetings were recorded as meeting notes before.
+INSERT INTO action_items (
+  chat_id, thread_id, created_by, assignee_id, text, created_at, meeting_id
+)
+SELECT m.chat_id, m.thread_id, n.user_id, n.assignee_id, n.text,
+  n.created_at, n.meeting_id
+FROM meeting_notes n JOIN meetings m ON m.rowid = n.meeting_id
+WHERE n.kind = 'action' AND n.assignee_id IS NOT NULL;
+DELETE FROM meeting_notes WHERE kind = 'action';
+ALTER TABLE meeting_notes DROP COLUMN assignee_id;
+
+-- The message the note was recorded from, so that replying with '/action'
+-- to a '/decision' message links the action item to the decision.
+ALTER TABLE meeting_notes ADD COLUMN message_id INTEGER;
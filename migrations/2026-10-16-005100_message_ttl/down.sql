@@ -0,0 +1,2 @@
+DROP TABLE expiring_messages;
+DROP TABLE message_ttl_policies;
@@ -0,0 +1,20 @@
+-- Auto-delete policies of topics set with '/ttl', see the 'message_ttl'
+-- module.
+CREATE TABLE message_ttl_policies (
+  chat_id BIGINT NOT NULL,
+  thread_id INTEGER NOT NULL,
+  ttl_seconds BIGINT NOT NULL,
+  include_others BOOLEAN NOT NULL, -- delete messages of users, not only of the bot
+  set_by BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  set_at DATETIME NOT NULL, -- UTC
+  PRIMARY KEY (chat_id, thread_id)
+);
+
+CREATE TABLE expiring_messages (
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL,
+  delete_at DATETIME NOT NULL, -- UTC
+  PRIMARY KEY (chat_id, message_id)
+);
+
+CREATE INDEX expiring_messages_delete_at ON expiring_messages (delete_at);
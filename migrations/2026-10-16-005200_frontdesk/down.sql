@@ -0,0 +1 @@
+DROP TABLE frontdesk_threads;
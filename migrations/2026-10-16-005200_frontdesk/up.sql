@@ -0,0 +1,8 @@
+-- Topics of visitors in the frontdesk forum, see the 'frontdesk' module.
+CREATE TABLE frontdesk_threads (
+  visitor_id BIGINT NOT NULL PRIMARY KEY /* REFERENCES tg_users(id) */,
+  chat_id BIGINT NOT NULL,
+  thread_id INTEGER NOT NULL,
+  created_at DATETIME NOT NULL, -- UTC
+  UNIQUE (chat_id, thread_id)
+);
@@ -0,0 +1,13 @@
+-- Inquiries of visitors, see the 'frontdesk' module.  An inquiry starts with
+-- the first message of a visitor after the last reply of residents.
+CREATE TABLE frontdesk_inquiries (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  visitor_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  asked_at DATETIME NOT NULL, -- UTC
+  answered_at DATETIME, -- UTC
+  answered_by BIGINT /* REFERENCES tg_users(id) */,
+  pinged_at DATETIME -- UTC, when residents on duty were pinged
+);
+
+CREATE INDEX frontdesk_inquiries_visitor_id ON frontdesk_inquiries (visitor_id);
+CREATE INDEX frontdesk_inquiries_asked_at ON frontdesk_inquiries (asked_at);
@@ -0,0 +1,9 @@
+-- Values of the key-value HTTP API, see 'web_srv/kv.rs'.
+CREATE TABLE kv_entries (
+  namespace TEXT NOT NULL,
+  key TEXT NOT NULL,
+  value BLOB NOT NULL,
+  content_type TEXT NOT NULL,
+  updated_at DATETIME NOT NULL, -- UTC
+  PRIMARY KEY (namespace, key)
+);
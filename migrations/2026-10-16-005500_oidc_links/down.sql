@@ -0,0 +1,2 @@
+DROP TABLE oidc_link_codes;
+DROP TABLE oidc_links;
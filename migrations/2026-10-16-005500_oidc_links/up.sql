@@ -0,0 +1,13 @@
+-- OpenID Connect subjects linked to Telegram users, see 'web_srv/oidc.rs'.
+CREATE TABLE oidc_links (
+  subject TEXT NOT NULL PRIMARY KEY,
+  tg_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  linked_at DATETIME NOT NULL -- UTC
+);
+
+-- One-time codes issued by the '/link' command.
+CREATE TABLE oidc_link_codes (
+  code TEXT NOT NULL PRIMARY KEY,
+  tg_id BIGINT NOT NULL /* REFERENCES tg_users(id) */,
+  expires_at DATETIME NOT NULL -- UTC
+);
@@ -0,0 +1,14 @@
+-- Chronological feed of what happens in the bot, see the 'activity_feed'
+-- module.  Entries read as "<actor> <verb> <object>".
+CREATE TABLE activity_feed (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  timestamp DATETIME NOT NULL, -- UTC
+  module TEXT NOT NULL,
+  actor_id BIGINT /* REFERENCES tg_users(id) */, -- NULL for the bot itself
+  verb TEXT NOT NULL,
+  object TEXT NOT NULL,
+  url TEXT
+);
+
+CREATE INDEX activity_feed_timestamp ON activity_feed (timestamp);
+CREATE INDEX activity_feed_module ON activity_feed (module);
@@ -0,0 +1,12 @@
+-- Compensating actions of recent bot actions, see the 'undo' module.
+CREATE TABLE undo_stack (
+  rowid INTEGER PRIMARY KEY NOT NULL,
+  chat_id BIGINT NOT NULL,
+  user_id BIGINT NOT NULL, -- the affected user
+  description TEXT NOT NULL,
+  actions TEXT NOT NULL, -- JSON
+  created_at DATETIME NOT NULL, -- UTC
+  undone_at DATETIME -- UTC
+);
+
+CREATE INDEX undo_stack_chat_id ON undo_stack (chat_id, created_at);
@@ -0,0 +1 @@
+ALTER TABLE tracked_polls DROP COLUMN private;
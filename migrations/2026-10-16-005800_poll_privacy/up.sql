@@ -0,0 +1,3 @@
+-- Whether the info message shows only counts of voters, see the 'polls'
+-- module.
+ALTER TABLE tracked_polls ADD COLUMN private BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+-- Announcements of tracked polls cross-posted to other topics, edited with
+-- the outcome when the poll closes, see the 'poll_announcements' module.
+CREATE TABLE poll_announcements (
+  chat_id BIGINT NOT NULL,
+  message_id INTEGER NOT NULL,
+  tg_poll_id TEXT NOT NULL /* REFERENCES tracked_polls(tg_poll_id) */,
+  question TEXT NOT NULL,
+  poll_url TEXT NOT NULL,
+  PRIMARY KEY (chat_id, message_id)
+);
+
+CREATE INDEX poll_announcements_tg_poll_id ON poll_announcements (tg_poll_id);
@@ -0,0 +1,195 @@
+//! In-process cache of frequently read database state.
+//!
+//! Some queries run on nearly every update, e.g. checking whether the sender
+//! is a resident for command filters.  [`DbCache`] lives in
+//! [`BotEnv::cache`] and keeps the results in memory.  Each entry is reloaded
+//! after its TTL, and code that changes the underlying tables invalidates it
+//! explicitly after committing, so changes are visible immediately.
+//!
+//! Loading an entry locks the database connection, so don't read the cache
+//! while holding it, e.g. inside [`BotEnv::transaction`].
+//!
+//! [`BotEnv::cache`]: crate::common::BotEnv::cache
+//! [`BotEnv::transaction`]: crate::common::BotEnv::transaction
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use diesel::prelude::*;
+use teloxide::types::{ChatId, UserId};
+
+use crate::db::{DbChatId, DbThreadId, DbUserId};
+use crate::{models, schema};
+
+/// How long residents and roles are kept.  Covers changes made outside the
+/// bot, e.g. with the `sqlite3` shell.
+const STATE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How long feature flags are kept.  Only changed by `/flags`.
+const FLAGS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How long auto-delete policies are kept.  Only changed by `/ttl`.
+const TTL_POLICIES_TTL: Duration = Duration::from_secs(60 * 60);
+
+pub struct DbCache {
+    /// Users with an ongoing residency.
+    pub residents: Cached<HashSet<UserId>>,
+    /// Users by role, from `user_roles`.
+    pub roles: Cached<HashMap<String, Vec<UserId>>>,
+    /// Values set for feature flags, by flag name and chat.
+    pub feature_flags: Cached<HashMap<(String, ChatId), bool>>,
+    /// Auto-delete policies, by chat and topic.
+    pub ttl_policies:
+        Cached<HashMap<(DbChatId, DbThreadId), models::MessageTtlPolicy>>,
+}
+
+impl Default for DbCache {
+    fn default() -> Self {
+        Self {
+            residents: Cached::new(STATE_TTL),
+            roles: Cached::new(STATE_TTL),
+            feature_flags: Cached::new(FLAGS_TTL),
+            ttl_policies: Cached::new(TTL_POLICIES_TTL),
+        }
+    }
+}
+
+impl DbCache {
+    pub fn residents(
+        &self,
+        conn: &Mutex<SqliteConnection>,
+    ) -> QueryResult<Arc<HashSet<UserId>>> {
+        self.residents.get(conn, |conn| {
+            schema::residents::table
+                .filter(schema::residents::end_date.is_null())
+                .select(schema::residents::tg_id)
+                .load::<DbUserId>(conn)
+                .map(|ids| ids.into_iter().map(UserId::from).collect())
+        })
+    }
+
+    pub fn roles(
+        &self,
+        conn: &Mutex<SqliteConnection>,
+    ) -> QueryResult<Arc<HashMap<String, Vec<UserId>>>> {
+        self.roles.get(conn, |conn| {
+            let rows = schema::user_roles::table
+                .select((schema::user_roles::role, schema::user_roles::user_id))
+                .load::<(String, DbUserId)>(conn)?;
+            let mut roles: HashMap<String, Vec<UserId>> = HashMap::new();
+            for (role, user) in rows {
+                roles.entry(role).or_default().push(user.into());
+            }
+            Ok(roles)
+        })
+    }
+
+    pub fn feature_flags(
+        &self,
+        conn: &Mutex<SqliteConnection>,
+    ) -> QueryResult<Arc<HashMap<(String, ChatId), bool>>> {
+        self.feature_flags.get(conn, |conn| {
+            let rows = schema::feature_flags::table
+                .select(models::FeatureFlag::as_select())
+                .load(conn)?;
+            Ok(rows
+                .into_iter()
+                .map(|f| ((f.name, f.chat_id.into()), f.enabled))
+                .collect())
+        })
+    }
+
+    pub fn ttl_policies(
+        &self,
+        conn: &Mutex<SqliteConnection>,
+    ) -> QueryResult<
+        Arc<HashMap<(DbChatId, DbThreadId), models::MessageTtlPolicy>>,
+    > {
+        self.ttl_policies.get(conn, |conn| {
+            let rows = schema::message_ttl_policies::table
+                .select(models::MessageTtlPolicy::as_select())
+                .load(conn)?;
+            Ok(rows
+                .into_iter()
+                .map(|p| ((p.chat_id, p.thread_id), p))
+                .collect())
+        })
+    }
+}
+
+/// A value loaded from the database on demand.
+pub struct Cached<T> {
+    ttl: Duration,
+    state: Mutex<State<T>>,
+}
+
+struct State<T> {
+    value: Option<(Instant, Arc<T>)>,
+    /// Incremented on each invalidation, so that a value loaded concurrently
+    /// with an invalidation is not stored.
+    generation: u64,
+}
+
+impl<T> Cached<T> {
+    pub const fn new(ttl: Duration) -> Self {
+        Self { ttl, state: Mutex::new(State { value: None, generation: 0 }) }
+    }
+
+    /// Get the cached value, or load it with `load` if it's missing or
+    /// expired.
+    pub fn get(
+        &self,
+        conn: &Mutex<SqliteConnection>,
+        load: impl FnOnce(&mut SqliteConnection) -> QueryResult<T>,
+    ) -> QueryResult<Arc<T>> {
+        let generation = {
+            let state = self.state.lock().unwrap();
+            match &state.value {
+                Some((loaded_at, value)) if loaded_at.elapsed() < self.ttl => {
+                    return Ok(Arc::clone(value));
+                }
+                _ => state.generation,
+            }
+        };
+
+        let value = Arc::new(load(&mut conn.lock().unwrap())?);
+        let mut state = self.state.lock().unwrap();
+        if state.generation == generation {
+            state.value = Some((Instant::now(), Arc::clone(&value)));
+        }
+        Ok(value)
+    }
+
+    /// Drop the cached value.  Call after committing changes to the tables
+    /// it is loaded from.
+    pub fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.value = None;
+        state.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestBot;
+
+    #[test]
+    fn test_residents() {
+        let t = TestBot::new();
+        let cache = DbCache::default();
+        t.add_resident(1, "alice", "Alice");
+        let residents = cache.residents(&t.env.conn).unwrap();
+        assert!(residents.contains(&UserId(1)));
+
+        // Changes are not seen until invalidated.
+        diesel::update(schema::residents::table)
+            .set(schema::residents::end_date.eq(diesel::dsl::now))
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        assert!(cache.residents(&t.env.conn).unwrap().contains(&UserId(1)));
+        cache.residents.invalidate();
+        assert!(cache.residents(&t.env.conn).unwrap().is_empty());
+    }
+}
@@ -5,20 +5,31 @@ use std::fmt::Write;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex, MutexGuard};
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use base64::Engine as _;
 use diesel::{
-    ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl, SqliteConnection,
+    EscapeExpressionMethods as _, ExpressionMethods, OptionalExtension as _,
+    QueryDsl, QueryResult, RunQueryDsl, SqliteConnection,
+    TextExpressionMethods as _,
 };
 use itertools::Itertools;
+use tap::Tap as _;
+use teloxide::net::Download as _;
 use teloxide::requests::Requester;
-use teloxide::types::{Me, Message, StickerKind, User, UserId};
+use teloxide::types::{
+    Me, Message, MessageEntityKind, PhotoSize, StickerKind, User, UserId,
+};
 use teloxide::utils::command::BotCommands;
-use teloxide::utils::html::escape;
-use teloxide::Bot;
+use teloxide::{Bot, RequestError};
 
+use crate::cache::DbCache;
 use crate::config::Config;
+use crate::cooldowns::{self, Cooldowns};
 use crate::db::DbUserId;
-use crate::utils::{BotExt, GENERAL_THREAD_ID};
+use crate::events;
+use crate::health::Health;
+use crate::outbox;
+use crate::utils::{html, BotExt, ResultExt as _, GENERAL_THREAD_ID};
 
 /// Wrapper around [`teloxide::dispatching::UpdateHandler`] to be used in this
 /// crate.
@@ -42,6 +53,13 @@ impl CommandAccessRules {
     pub const fn new() -> Self {
         Self { admin: false, resident: false, in_private: true, in_group: true }
     }
+
+    /// Whether the user has the role required by these rules.  Don't call
+    /// while holding the connection.
+    pub fn allows(&self, env: &BotEnv, user: UserId) -> bool {
+        (!self.admin || env.config.telegram.admins.contains(&user))
+            && (!self.resident || env.is_resident(user))
+    }
 }
 
 impl Default for CommandAccessRules {
@@ -56,16 +74,53 @@ impl Default for CommandAccessRules {
 /// [`BotCommands`]: teloxide::utils::command::BotCommands
 pub trait BotCommandsExtTrait: BotCommands {
     const COMMAND_RULES: &'static [CommandAccessRules];
+    /// Whether each command takes arguments, in the same order.
+    const COMMAND_ARGS: &'static [bool];
     fn command_rules(&self) -> CommandAccessRules;
 }
 
+/// Metadata of a bot command, used to render `/help` and the command lists
+/// shown by Telegram.  See [`crate::modules::commands`].
+#[derive(Debug)]
+pub struct CommandInfo {
+    /// Name of the command, without the slash.
+    pub name: String,
+    /// Description in HTML.
+    pub description: String,
+    pub takes_args: bool,
+    pub rules: &'static CommandAccessRules,
+}
+
+impl CommandInfo {
+    /// Metadata of all commands of `T`.
+    pub fn of<T: BotCommandsExtTrait>() -> Vec<Self> {
+        itertools::izip!(T::bot_commands(), T::COMMAND_RULES, T::COMMAND_ARGS)
+            .map(|(cmd, rules, &takes_args)| Self {
+                name: cmd.command.trim_start_matches('/').to_string(),
+                description: cmd.description,
+                takes_args,
+                rules,
+            })
+            .collect()
+    }
+}
+
 /// Bot environment: global state shared between all handlers.
 pub struct BotEnv {
     pub conn: Mutex<SqliteConnection>,
     pub config: Arc<Config>,
     pub config_path: PathBuf,
+    /// Name of the instance, see [`crate::config::Instances`].
+    pub instance: String,
+    /// Path to the database file behind `conn`.
+    pub db_path: PathBuf,
     pub reqwest_client: reqwest::Client,
     pub openai_client: async_openai::Client<async_openai::config::OpenAIConfig>,
+    pub cache: DbCache,
+    pub cooldowns: Cooldowns,
+    pub health: Health,
+    pub events: events::Bus,
+    pub outbox: outbox::Wake,
 }
 
 impl BotEnv {
@@ -78,6 +133,18 @@ impl BotEnv {
     ) -> QueryResult<T> {
         self.conn().exclusive_transaction(f)
     }
+
+    /// Same as [`is_resident`], but served from [`DbCache`].  Don't call while
+    /// holding the connection.
+    pub fn is_resident(&self, user: UserId) -> bool {
+        match self.cache.residents(&self.conn) {
+            Ok(residents) => residents.contains(&user),
+            Err(e) => {
+                log::error!("Failed to load residents: {e}");
+                false
+            }
+        }
+    }
 }
 
 /// Derive macro for [`BotCommandsExtTrait`] trait. Should be applied with
@@ -106,6 +173,12 @@ macro_rules! BotCommandsExt {
                     meta
                 }),*]
             ;
+            const COMMAND_ARGS: &'static [bool] = &[$(
+                BotCommandsExt!(
+                    impl has_args;
+                    $( ( $($item_args)* ) )?
+                )
+            ),*];
             fn command_rules(&self) -> $crate::common::CommandAccessRules {
                 match self {$(
                     BotCommandsExt!(
@@ -154,6 +227,10 @@ macro_rules! BotCommandsExt {
     // skip_item_args
     (impl skip_item_args; $v:ident ) => { Self::$v };
     (impl skip_item_args; $v:ident($($t:ty),+) ) => { Self::$v(..) };
+
+    // has_args
+    (impl has_args; ) => { false };
+    (impl has_args; ($($t:ty),+) ) => { true };
 }
 
 pub(crate) use BotCommandsExt;
@@ -192,18 +269,17 @@ pub fn format_user<'a>(
             write!(out, "id={} (unknown)", tg_id.into().0).unwrap();
         }
         Some(u) => {
-            if link {
-                if let Some(username) = &u.username {
-                    write!(out, "<a href=\"https://t.me/{username}\">")
-                        .unwrap();
-                }
-            }
-            write!(out, "{}", escape(&u.first_name)).unwrap();
+            let mut name = u.first_name.clone();
             if let Some(last_name) = &u.last_name {
-                write!(out, " {}", escape(last_name)).unwrap();
+                name.push(' ');
+                name.push_str(last_name);
             }
-            if link && u.username.is_some() {
-                write!(out, "</a>").unwrap();
+            match &u.username {
+                Some(username) if link => out.push_str(&html::link(
+                    &format!("https://t.me/{username}"),
+                    &name,
+                )),
+                _ => out.push_str(&html::escape(&name)),
             }
         }
     }
@@ -228,7 +304,8 @@ async fn filter_command_impl<C>(
 where
     C: BotCommands + BotCommandsExtTrait + Send + Sync + 'static,
 {
-    let cmd = C::parse(msg.text()?, &me.user.username?).ok()?;
+    let text = msg.text().or_else(|| msg.caption())?;
+    let cmd = C::parse(text, &me.user.username?).ok()?;
     let rules = cmd.command_rules();
 
     let error_text = if !rules.in_group
@@ -241,9 +318,7 @@ where
         && !env.config.telegram.admins.contains(&msg.from.as_ref()?.id)
     {
         Some("You must be an admin to execute this command")
-    } else if rules.resident
-        && !is_resident(&mut env.conn(), msg.from.as_ref()?)
-    {
+    } else if rules.resident && !env.is_resident(msg.from.as_ref()?.id) {
         Some("You must be a resident to execute this command")
     } else {
         None
@@ -254,9 +329,52 @@ where
         return None;
     }
 
+    if rules.admin {
+        match crate::modules::approvals::check(&bot, &env, &msg).await {
+            Ok(true) => (),
+            Ok(false) => return None,
+            Err(e) => {
+                log::error!("Failed to check command approval: {e:?}");
+                return None;
+            }
+        }
+
+        // Recorded once the command is executed, i.e. after an approval.
+        crate::modules::audit::record(
+            &mut env.conn(),
+            msg.from.as_ref().map(|u| u.id),
+            "admin_command",
+            &serde_json::json!({
+                "chat_id": msg.chat.id.0,
+                "message_id": msg.id.0,
+                "text": msg.text(),
+            }),
+        )
+        .log_error("audit admin command");
+    }
+
+    if let Some(name) = command_name(&msg) {
+        if let Some(&secs) = env.config.telegram.command_cooldowns.get(name) {
+            let cooldown = std::time::Duration::from_secs(secs);
+            if let Err(left) = env.cooldowns.start(name, cooldown) {
+                let _ = bot
+                    .reply_message(&msg, cooldowns::refusal(name, left))
+                    .await;
+                return None;
+            }
+        }
+    }
+
     Some(cmd)
 }
 
+/// Extract the command name from a message, e.g. `sban` from `/sban@bot 1`.
+pub fn command_name(msg: &Message) -> Option<&str> {
+    let text = msg.text().or_else(|| msg.caption())?;
+    let word = text.split_whitespace().next()?.strip_prefix('/')?;
+    word.split('@').next()
+}
+
 pub fn is_resident(conn: &mut SqliteConnection, user: &User) -> bool {
     crate::schema::residents::table
         .filter(crate::schema::residents::end_date.is_null())
@@ -268,6 +386,144 @@ pub fn is_resident(conn: &mut SqliteConnection, user: &User) -> bool {
         > 0
 }
 
+/// Resolve a user argument of a command: a numeric ID, a mention of a user
+/// without a username in `msg`, or `@username`.  Usernames are looked up
+/// among known users, then among their previous usernames, then with
+/// `getChat`.
+pub async fn resolve_user(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    arg: &str,
+) -> Result<Option<UserId>> {
+    if let Ok(id) = arg.parse() {
+        return Ok(Some(UserId(id)));
+    }
+
+    let text_mention = msg.parse_entities().and_then(|entities| {
+        entities.iter().find_map(|e| match e.kind() {
+            MessageEntityKind::TextMention { user } if e.text() == arg => {
+                Some(user.id)
+            }
+            _ => None,
+        })
+    });
+    if text_mention.is_some() {
+        return Ok(text_mention);
+    }
+
+    let Some(username) = arg.strip_prefix('@') else { return Ok(None) };
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Ok(None);
+    }
+    if let Some(id) = find_username(&mut env.conn(), username)? {
+        return Ok(Some(id));
+    }
+
+    let chat = match bot.get_chat(format!("@{username}")).await {
+        Ok(chat) => chat,
+        Err(RequestError::Api(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let Some(id) = u64::try_from(chat.id.0).ok().filter(|_| chat.is_private())
+    else {
+        return Ok(None);
+    };
+    let user = crate::models::NewTgUser {
+        id: UserId(id).into(),
+        username: chat.username(),
+        first_name: chat.first_name().unwrap_or_default(),
+        last_name: chat.last_name(),
+    };
+    env.transaction(|conn| {
+        crate::modules::tg_scraper::store_users(conn, &[user])
+    })?;
+    Ok(Some(UserId(id)))
+}
+
+/// Find a known user by the current or a previous username, ignoring case.
+fn find_username(
+    conn: &mut SqliteConnection,
+    username: &str,
+) -> QueryResult<Option<UserId>> {
+    // Only underscores are special among characters allowed in usernames.
+    let pattern = username.replace('_', "\\_");
+    let current = crate::schema::tg_users::table
+        .filter(crate::schema::tg_users::username.like(&pattern).escape('\\'))
+        .select(crate::schema::tg_users::id)
+        .first::<DbUserId>(conn)
+        .optional()?;
+    if current.is_some() {
+        return Ok(current.map(UserId::from));
+    }
+    crate::schema::tg_user_names::table
+        .filter(
+            crate::schema::tg_user_names::username.like(&pattern).escape('\\'),
+        )
+        .order(crate::schema::tg_user_names::replaced_at.desc())
+        .select(crate::schema::tg_user_names::user_id)
+        .first::<DbUserId>(conn)
+        .optional()
+        .map(|id| id.map(UserId::from))
+}
+
+const VISION_URL: &str = "https://api.openai.com/v1/chat/completions";
+const VISION_MODEL: &str = "gpt-4o";
+
+/// Ask the `OpenAI` vision model about the photo.  Returns the response text
+/// with a surrounding JSON code fence, if any, stripped.
+pub async fn read_photo(
+    bot: &Bot,
+    env: &BotEnv,
+    photo: &PhotoSize,
+    prompt: &str,
+    max_tokens: u16,
+) -> Result<String> {
+    let file = bot.get_file(&photo.file.id).await?;
+    let mut image = Vec::new();
+    bot.download_file(&file.path, &mut image).await?;
+    let image = base64::engine::general_purpose::STANDARD.encode(image);
+
+    let request = serde_json::json!({
+        "model": VISION_MODEL,
+        "max_tokens": max_tokens,
+        "messages": [
+            { "role": "system", "content": prompt },
+            {
+                "role": "user",
+                "content": [{
+                    "type": "image_url",
+                    "image_url": {
+                        "url": format!("data:image/jpeg;base64,{image}"),
+                    },
+                }],
+            },
+        ],
+    });
+    let response: serde_json::Value = env
+        .reqwest_client
+        .post(VISION_URL)
+        .bearer_auth(&env.config.services.openai.api_key)
+        .json(&request)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .tap(|r| {
+            crate::metrics::update_service(&env.health, "openai", r.is_ok())
+        })?
+        .json()
+        .await?;
+    let content = response["choices"][0]["message"]["content"]
+        .as_str()
+        .context("No content in response")?;
+    Ok(content
+        .trim()
+        .trim_start_matches("```json")
+        .trim_matches('`')
+        .trim()
+        .to_string())
+}
+
 /// A container for associating emojis with topics.
 pub struct TopicEmojis(HashMap<String, String>);
 
@@ -366,5 +622,48 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            MyCommand::COMMAND_ARGS,
+            &[false, false, false, false, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_user() {
+        let t = crate::testing::TestBot::new();
+        t.add_resident(1, "alice_a", "Alice");
+        crate::modules::tg_scraper::store_users(
+            &mut t.env.conn(),
+            &[crate::models::NewTgUser {
+                id: UserId(1).into(),
+                username: Some("alice_b"),
+                first_name: "Alice",
+                last_name: None,
+            }],
+        )
+        .unwrap();
+        let bob = crate::testing::user_json(2, "Bob");
+        let mut msg = crate::mock_telegram::message_json(1, 1, None, &bob);
+        msg["text"] = "/cmd Bob".into();
+        msg["entities"] = serde_json::json!([
+            { "type": "text_mention", "offset": 5, "length": 3, "user": bob },
+        ]);
+        let msg: Message = serde_json::from_value(msg).unwrap();
+        let resolve = |arg: &'static str| {
+            let t = &t;
+            let msg = &msg;
+            async move { resolve_user(&t.bot, &t.env, msg, arg).await.unwrap() }
+        };
+
+        assert_eq!(resolve("42").await, Some(UserId(42)));
+        assert_eq!(resolve("Bob").await, Some(UserId(2)));
+        assert_eq!(resolve("@Alice_B").await, Some(UserId(1)));
+        // A previous username.
+        assert_eq!(resolve("@alice_a").await, Some(UserId(1)));
+        // `_` is not a wildcard.
+        t.telegram.fail("getChat", "Bad Request: chat not found");
+        assert_eq!(resolve("@aliceXb").await, None);
+        assert_eq!(resolve("@a%").await, None);
+        assert_eq!(t.telegram.calls("getChat").len(), 1);
     }
 }
@@ -7,7 +7,9 @@
 #![doc = include_str!("../config.example.yaml")]
 //! ```
 
-use std::net::SocketAddr;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 use teloxide::types::{ChatId, ThreadId, UserId};
@@ -19,29 +21,603 @@ use crate::utils::ThreadIdPair;
 pub struct Config {
     pub telegram: Telegram,
     pub server_addr: SocketAddr,
+    #[serde(default)]
+    pub server_api_token: Option<String>,
+    #[serde(default)]
+    pub server_dashboard: Option<Dashboard>,
+    #[serde(default)]
+    pub server_kv: Vec<KvNamespace>,
+    #[serde(default)]
+    pub server_mini_app: Option<MiniApp>,
+    #[serde(default)]
+    pub server_status_page: Option<StatusPage>,
     pub services: Services,
 }
 
+/// Several bots run by one process, e.g. a staging bot alongside production.
+/// The bot accepts either this or a plain [`Config`], which runs a single
+/// instance.
+///
+/// ```yaml
+/// instances:
+///   - name: production
+///     config: config.yaml
+///     db: db.sqlite3
+///   - name: staging
+///     config: config.staging.yaml
+///     db: staging.sqlite3
+/// ```
+///
+/// Paths are relative to the working directory.  Instances share the
+/// process, the schedulers and the Prometheus metrics, which have an
+/// `instance` label; everything else, e.g. the outbox, the event bus and the
+/// service health, is kept per instance.
+///
+/// The HTTP server listens on the [`server_addr`] of the first instance.
+/// With several instances, it serves the routes of each under `/<name>`,
+/// e.g. `/staging/dashboard`, so public URLs such as
+/// [`server_dashboard.public_url`] must include the prefix.  The
+/// `server_addr` of the other instances is ignored.
+///
+/// [`server_addr`]: Config::server_addr
+/// [`server_dashboard.public_url`]: Dashboard::public_url
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Instances {
+    pub instances: Vec<Instance>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Instance {
+    /// Used in logs and in the name of the trace file, `trace.<name>.jsonl`.
+    /// An instance named `default` uses `trace.jsonl`, like a plain config.
+    pub name: String,
+    /// Config file of the instance, in the format of `config.example.yaml`.
+    pub config: PathBuf,
+    /// Database file of the instance.  Instances can't share one.
+    pub db: PathBuf,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Dashboard {
+    pub public_url: String,
+    pub secret: String,
+    pub session_hours: u32,
+    /// Log in with an OpenID Connect provider, e.g. Authentik or Keycloak.
+    #[serde(default)]
+    pub oidc: Option<Oidc>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Oidc {
+    /// Issuer URL, the discovery document is read from
+    /// `<issuer>/.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KvNamespace {
+    pub name: String,
+    pub token: String,
+    /// The largest value, in bytes.
+    pub max_value_bytes: usize,
+    pub max_keys: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MiniApp {
+    /// Key to sign the JWTs issued to the mini app.
+    pub secret: String,
+    pub session_hours: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatusPage {
+    /// Name of the space shown in the page title.
+    pub title: String,
+    /// Contact info, shown as is.
+    pub contact: String,
+    /// Countdowns in these chats are public events.
+    #[serde(default)]
+    pub event_chats: Vec<ChatId>,
+    /// Requests allowed per client IP address and minute.
+    pub requests_per_minute: u32,
+    /// Addresses of reverse proxies in front of the bot.  For requests from
+    /// them, the client IP address is taken from `X-Forwarded-For`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Telegram {
     pub token: String,
     pub admins: Vec<UserId>,
     pub passive_mode: bool,
+    /// Time zone of times shown in groups, and of private messages to users
+    /// who haven't set their own.  UTC if not set.
+    #[serde(default)]
+    pub timezone: Option<chrono_tz::Tz>,
+    /// Local hours of users during which private messages that can wait are
+    /// delayed.
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+    #[serde(default)]
+    pub approvals: Option<Approvals>,
+    /// Seconds after a command is used during which it's refused, by command
+    /// name.  Commands not listed have no cooldown.
+    #[serde(default)]
+    pub command_cooldowns: BTreeMap<String, u64>,
+    /// Minutes during which `/undo` reverses recent actions of the bot, 10 by
+    /// default.
+    #[serde(default)]
+    pub undo_minutes: Option<u32>,
+    #[serde(default)]
+    pub treasury: Option<Treasury>,
+    #[serde(default)]
+    pub auto_admins: Option<AutoAdmins>,
+    #[serde(default)]
+    pub projects: Option<Projects>,
+    #[serde(default)]
+    pub bookings: Option<Bookings>,
+    #[serde(default)]
+    pub rotation: Option<Rotation>,
+    #[serde(default)]
+    pub chores: Option<Chores>,
+    #[serde(default)]
+    pub fridge: Option<Fridge>,
+    #[serde(default)]
+    pub packages: Option<Packages>,
+    #[serde(default)]
+    pub incidents: Option<Incidents>,
+    #[serde(default)]
+    pub faq: Option<Faq>,
+    #[serde(default)]
+    pub inventory: Option<Inventory>,
+    #[serde(default)]
+    pub moderation: Option<Moderation>,
+    #[serde(default)]
+    pub spam_protection: Option<SpamProtection>,
+    #[serde(default)]
+    pub link_archive: Option<LinkArchive>,
+    #[serde(default)]
+    pub feeds: Option<Feeds>,
+    #[serde(default)]
+    pub git_hooks: Option<GitHooks>,
+    #[serde(default)]
+    pub generic_hooks: Vec<GenericHook>,
+    #[serde(default)]
+    pub monitor: Option<Monitor>,
+    #[serde(default)]
+    pub door_access: Option<DoorAccess>,
+    #[serde(default)]
+    pub energy: Option<Energy>,
+    #[serde(default)]
+    pub network_devices: Option<NetworkDevices>,
+    #[serde(default)]
+    pub vpn: Option<Vpn>,
+    #[serde(default)]
+    pub guest_wifi: Option<GuestWifi>,
+    #[serde(default)]
+    pub bandwidth: Option<Bandwidth>,
+    #[serde(default)]
+    pub retention: Option<Retention>,
+    #[serde(default)]
+    pub export: Option<Export>,
+    #[serde(default)]
+    pub backup_media: Option<BackupMedia>,
+    #[serde(default)]
+    pub poll_webhooks: Option<PollWebhooks>,
+    #[serde(default)]
+    pub frontdesk: Option<Frontdesk>,
     pub chats: TelegramChats,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QuietHours {
+    /// The first quiet hour.
+    pub start_hour: u32,
+    /// The first hour after the quiet hours, may be less than `start_hour`.
+    pub end_hour: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Approvals {
+    pub commands: Vec<String>,
+    pub timeout_minutes: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Treasury {
+    pub treasurers: Vec<UserId>,
+    pub requests: ThreadIdPair,
+    pub donations: ThreadIdPair,
+    pub currency: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Bookings {
+    pub resources: Vec<String>,
+    pub thread: ThreadIdPair,
+    pub schedule_hour: u32,
+    pub remind_minutes: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Rotation {
+    pub thread: ThreadIdPair,
+    pub weekday: chrono::Weekday,
+    pub hour: u32,
+    pub require_photo: bool,
+    pub duties: Vec<Duty>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Duty {
+    pub name: String,
+    pub people: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Chores {
+    pub thread: ThreadIdPair,
+    pub report_to: ThreadIdPair,
+    pub ping_after_hours: u32,
+    pub report_after_hours: u32,
+    pub chores: Vec<Chore>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Chore {
+    pub name: String,
+    pub every_days: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Feeds {
+    pub interval_minutes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Fridge {
+    pub thread: ThreadIdPair,
+    pub warn_days: u32,
+    pub sweep_hour: u32,
+    #[serde(default)]
+    pub vision: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Faq {
+    pub chats: Vec<ChatId>,
+    pub cooldown_minutes: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Incidents {
+    pub safety_officers: Vec<UserId>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Inventory {
+    pub thread: ThreadIdPair,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Moderation {
+    pub chats: Vec<ChatId>,
+    pub thread: ThreadIdPair,
+    #[serde(default)]
+    pub rules: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpamProtection {
+    pub review: ThreadIdPair,
+    pub new_account_id: u64,
+    pub link_window_minutes: u32,
+    pub threshold: u32,
+    #[serde(default)]
+    pub chat_thresholds: Vec<SpamChatThreshold>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpamChatThreshold {
+    pub chat: ChatId,
+    pub threshold: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LinkArchive {
+    pub threads: Vec<ThreadIdPair>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GitHooks {
+    pub secret: String,
+    pub repos: Vec<GitRepo>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GitRepo {
+    pub name: String,
+    pub thread: ThreadIdPair,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GenericHook {
+    pub name: String,
+    pub token: String,
+    pub thread: ThreadIdPair,
+    pub template: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Monitor {
+    pub thread: ThreadIdPair,
+    pub interval_seconds: u64,
+    pub services: Vec<MonitoredService>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MonitoredService {
+    pub name: String,
+    /// HTTP(S) URL or `host:port` for a TCP check.
+    pub target: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DoorAccess {
+    pub role: String,
+    pub add_service: String,
+    pub remove_service: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Energy {
+    pub thread: ThreadIdPair,
+    pub weekday: chrono::Weekday,
+    pub hour: u32,
+    /// Home Assistant entity of the main meter, a cumulative kWh sensor.
+    pub meter: String,
+    /// Price per kWh.
+    pub tariff: f64,
+    pub currency: String,
+    #[serde(default)]
+    pub consumers: Vec<EnergyConsumer>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EnergyConsumer {
+    pub name: String,
+    pub entity_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NetworkDevices {
+    pub thread: ThreadIdPair,
+    /// DHCP servers to watch, e.g. all but the guest VLAN.  Empty to watch
+    /// all servers.
+    #[serde(default)]
+    pub dhcp_servers: Vec<String>,
+    /// MAC addresses of known infrastructure devices.
+    #[serde(default)]
+    pub infrastructure: Vec<macaddr::MacAddr6>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Vpn {
+    /// WireGuard interface on the router.
+    pub interface: String,
+    pub server_public_key: String,
+    /// Public `host:port` of the WireGuard interface.
+    pub endpoint: String,
+    /// Range of addresses assigned to peers, inclusive.
+    pub pool_start: Ipv4Addr,
+    pub pool_end: Ipv4Addr,
+    /// Networks routed through the VPN, e.g. `10.0.0.0/16`.
+    pub allowed_ips: String,
+    #[serde(default)]
+    pub dns: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GuestWifi {
+    /// Name of the guest network, shown in the voucher.
+    pub ssid: String,
+    /// Hotspot server and user profile on the router.
+    pub server: String,
+    pub profile: String,
+    pub default_hours: u32,
+    pub max_hours: u32,
+    /// Maximum number of vouchers a resident can issue in seven days.
+    pub weekly_quota: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Bandwidth {
+    /// Show only aggregated traffic, without per-resident numbers.
+    #[serde(default)]
+    pub privacy: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Retention {
+    /// Days before soft-deleted rows are purged.
+    pub grace_days: u32,
+    /// Days to keep rows, by table name.  Tables not listed are kept forever.
+    pub tables: BTreeMap<String, u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Export {
+    /// Directory for snapshots, one subdirectory per export.
+    pub directory: PathBuf,
+    pub interval_hours: u32,
+    /// Number of snapshots to keep.
+    pub keep: usize,
+    pub tables: Vec<String>,
+    /// Columns to pseudonymize, as `table.column`.
+    #[serde(default)]
+    pub mask: Vec<String>,
+    /// Key for pseudonymization.  Masked values are stable for the same key,
+    /// so joins still work.
+    pub mask_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupMedia {
+    /// Larger files are not downloaded.  The Bot API allows downloading files
+    /// of up to 20 MB.
+    pub max_size_mb: u32,
+    pub storage: MediaStorage,
+}
+
+/// Where media of mirrored messages is stored.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MediaStorage {
+    Directory { path: PathBuf },
+    S3(S3Storage),
+}
+
+/// An S3-compatible bucket, addressed as `{endpoint}/{bucket}/{key}`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct S3Storage {
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PollWebhooks {
+    /// Percentage of current residents who must vote for the `quorum` event.
+    pub quorum_percent: u32,
+    pub hooks: Vec<PollWebhook>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollWebhook {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature in the `X-Botka-Signature` header.
+    pub secret: String,
+    pub events: Vec<PollEvent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PollEvent {
+    Created,
+    Quorum,
+    Closed,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Frontdesk {
+    /// Forum where a topic is created for each visitor.
+    pub forum: ChatId,
+    #[serde(default)]
+    pub sla: Option<FrontdeskSla>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FrontdeskSla {
+    /// Minutes an inquiry may wait for a reply before residents on duty are
+    /// pinged.
+    pub respond_minutes: u32,
+    /// Role of residents on duty, see the `roles` module.
+    pub duty_role: String,
+    /// Where the weekly report is posted, on `weekday` after `hour` (UTC).
+    pub report: ThreadIdPair,
+    pub weekday: chrono::Weekday,
+    pub hour: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Packages {
+    pub thread: ThreadIdPair,
+    pub weekday: chrono::Weekday,
+    pub hour: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Projects {
+    pub thread: ThreadIdPair,
+    pub stale_days: u32,
+    /// Forum where a topic is created for each project started outside of
+    /// a topic.
+    #[serde(default)]
+    pub topics: Option<ChatId>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AutoAdmins {
+    pub role: String,
+    pub chats: Vec<ChatId>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TelegramChats {
     pub residential: Vec<ChatId>,
+    /// Thread of admins, where new versions of the bot are announced.
+    #[serde(default)]
+    pub admin: Option<ThreadIdPair>,
     pub borrowed_items: Vec<ThreadIdPair>,
+    /// Reaction to a borrow message that marks all its items as returned.
+    #[serde(default)]
+    pub borrowed_items_reaction: Option<String>,
     pub dashboard: ThreadIdPair,
     pub forward_channel: ChatId,
+    #[serde(default)]
+    pub backup: Vec<BackupRule>,
     pub forward_pins: Vec<FowardPins>,
+    #[serde(default)]
+    pub introductions: Option<ThreadIdPair>,
+    #[serde(default)]
+    pub membership_report: Option<ThreadIdPair>,
+    /// Where requests to join residential chats from non-residents are
+    /// posted for admins.
+    #[serde(default)]
+    pub join_requests: Option<ThreadIdPair>,
     pub needs: ThreadIdPair,
+    /// Chats where info messages of tracked polls show only counts of
+    /// voters, see [`crate::modules::polls`].
+    #[serde(default)]
+    pub private_polls: Vec<ChatId>,
+    /// Topics where tracked polls tagged `[announce]` are announced, see
+    /// [`crate::modules::poll_announcements`].
+    #[serde(default)]
+    pub poll_announcements: Vec<ThreadIdPair>,
     pub resident_owned: Vec<ResidentOwned>,
     pub wikijs_updates: ThreadIdPair,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupRule {
+    pub chat: ChatId,
+    /// Forum topic the rule applies to, or all topics if `None`.
+    pub thread: Option<ThreadId>,
+    /// Don't mirror matching messages.
+    pub exclude: bool,
+    /// Mirror only messages of this kind, or all messages if `None`.
+    pub only: Option<BackupOnly>,
+    pub skip_bots: bool,
+    /// Channel to mirror into instead of `forward_channel`.
+    pub channel: Option<ChatId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupOnly {
+    /// Photos, videos, files, voice messages, etc.
+    Media,
+    /// Messages posted on behalf of a chat, e.g. by the linked channel or
+    /// by anonymous admins.
+    Announcements,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ResidentOwned {
     pub id: ChatId,
@@ -61,6 +637,16 @@ pub struct Services {
     pub home_assistant: HomeAssistant,
     pub wikijs: WikiJs,
     pub openai: OpenAI,
+    #[serde(default)]
+    pub mail: Option<Mail>,
+    #[serde(default)]
+    pub matrix: Option<Matrix>,
+    #[serde(default)]
+    pub mqtt: Option<Mqtt>,
+    #[serde(default)]
+    pub resident_sync: Option<ResidentSync>,
+    #[serde(default)]
+    pub archivebox: Option<ArchiveBox>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -82,6 +668,11 @@ pub struct WikiJs {
     pub token: String,
     pub welcome_message_page: String,
     pub dashboard_page: String,
+    #[serde(default)]
+    pub needs_page: Option<String>,
+    /// Page the minutes of meetings are appended to.
+    #[serde(default)]
+    pub meetings_page: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -91,6 +682,125 @@ pub struct OpenAI {
     pub disable: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Mail {
+    pub smtp: MailServer,
+    pub imap: MailServer,
+    pub from: String,
+    pub mailing_list: String,
+    pub inbound_address: String,
+    pub announcements: Vec<ThreadIdPair>,
+    pub inbound: ThreadIdPair,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MailServer {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Matrix {
+    pub homeserver: String,
+    pub access_token: String,
+    pub rooms: Vec<MatrixRoom>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MatrixRoom {
+    pub from: ThreadIdPair,
+    pub room_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Mqtt {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: String,
+    pub password: String,
+    pub publish: MqttPublish,
+    pub subscriptions: Vec<MqttSubscription>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MqttPublish {
+    pub space_open: String,
+    pub person_count: String,
+    pub needs_count: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MqttSubscription {
+    pub topic: String,
+    pub to: ThreadIdPair,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchiveBox {
+    pub url: String,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResidentSync {
+    pub interval_minutes: u64,
+    pub report_to: ThreadIdPair,
+    pub source: ResidentSource,
+    /// Upload the current residents after each sync.
+    #[serde(default)]
+    pub export: Option<ResidentExport>,
+}
+
+/// Destination of the exported residents.  The export is a CSV document in
+/// the format read by [`ResidentSource::Csv`], uploaded with `PUT`, e.g. to a
+/// WebDAV file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResidentExport {
+    pub url: String,
+    /// Sent as `Authorization: Bearer <token>`.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Name of the column with Telegram user IDs.
+    pub column: String,
+}
+
+/// External source of truth for the list of residents.  Each source provides
+/// Telegram user IDs of residents, stored in `attribute` or `column`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ResidentSource {
+    Authentik {
+        url: String,
+        token: String,
+        group: String,
+        attribute: String,
+    },
+    Keycloak {
+        url: String,
+        realm: String,
+        client_id: String,
+        client_secret: String,
+        group_id: String,
+        attribute: String,
+    },
+    Ldap {
+        url: String,
+        bind_dn: String,
+        bind_password: String,
+        base_dn: String,
+        filter: String,
+        attribute: String,
+    },
+    Csv {
+        url: String,
+        column: String,
+    },
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,85 @@
+//! Cooldowns of commands calling slow or paid external services.
+//!
+//! A command listed in [`telegram.command_cooldowns`] is refused for the
+//! given number of seconds after each use, no matter who uses it and where,
+//! so that several residents sending `/status` at once cause a single
+//! MikroTik query.  Checked by [`filter_command`] after access rules.
+//!
+//! [`telegram.command_cooldowns`]: crate::config::Telegram::command_cooldowns
+//! [`filter_command`]: crate::common::filter_command
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Time of the last use of each command.  Lives in [`BotEnv::cooldowns`].
+///
+/// [`BotEnv::cooldowns`]: crate::common::BotEnv::cooldowns
+#[derive(Default)]
+pub struct Cooldowns {
+    last_used: Mutex<HashMap<String, Instant>>,
+}
+
+impl Cooldowns {
+    /// Record a use of `command` unless it's cooling down, otherwise return
+    /// the time left.
+    pub fn start(
+        &self,
+        command: &str,
+        cooldown: Duration,
+    ) -> Result<(), Duration> {
+        self.start_at(command, cooldown, Instant::now())
+    }
+
+    fn start_at(
+        &self,
+        command: &str,
+        cooldown: Duration,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut last_used = self.last_used.lock().unwrap();
+        if let Some(&at) = last_used.get(command) {
+            let left =
+                cooldown.saturating_sub(now.saturating_duration_since(at));
+            if !left.is_zero() {
+                return Err(left);
+            }
+        }
+        last_used.insert(command.to_string(), now);
+        Ok(())
+    }
+}
+
+/// Text of the reply to a command used during its cooldown.
+pub fn refusal(command: &str, left: Duration) -> String {
+    // Round up, so that it's never "try again in 0s".
+    let seconds = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+    format!("⏳ /{command} was used just now, try again in {seconds}s.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cooldowns() {
+        let cooldowns = Cooldowns::default();
+        let cooldown = Duration::from_secs(30);
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
+
+        assert_eq!(cooldowns.start_at("status", cooldown, t0), Ok(()));
+        assert_eq!(
+            cooldowns.start_at("status", cooldown, at(10)),
+            Err(Duration::from_secs(20)),
+        );
+        assert_eq!(cooldowns.start_at("energy", cooldown, at(10)), Ok(()));
+        assert_eq!(cooldowns.start_at("status", cooldown, at(30)), Ok(()));
+        assert!(cooldowns.start_at("status", cooldown, at(59)).is_err());
+
+        assert_eq!(
+            refusal("status", Duration::from_millis(12_300)),
+            "⏳ /status was used just now, try again in 13s.",
+        );
+    }
+}
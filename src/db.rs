@@ -1,5 +1,7 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::sync::Mutex;
 
 use diesel::result::Error::DeserializationError;
 use diesel::{
@@ -10,11 +12,24 @@ use diesel_derive_newtype::DieselNewType;
 use salvo_oapi::ToSchema;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use teloxide::types::{ChatId, MessageId, Recipient, ThreadId, UserId};
+use teloxide::types::{
+    ChatId, ChatMember, Message, MessageId, Recipient, ThreadId, UserId,
+};
+use tokio::sync::watch;
 
+use crate::common::BotEnv;
 use crate::utils::GENERAL_THREAD_ID;
 use crate::{models, schema};
 
+lazy_static::lazy_static! {
+    /// Change notification channels of options, by database file and option
+    /// name.  Instances can't share a database, so the file identifies the
+    /// instance.
+    static ref OPTION_WATCHERS:
+        Mutex<HashMap<(String, &'static str), watch::Sender<()>>> =
+            Mutex::default();
+}
+
 /// A definition for a typed value stored in the database table `options`.
 pub struct ConfigOptionDef<T: Serialize + DeserializeOwned> {
     key_name: &'static str,
@@ -72,12 +87,11 @@ impl<T: Serialize + DeserializeOwned> ConfigOptionDef<T> {
                 name: self.key_name.to_string(),
                 value,
             })
-            .execute(conn)
-            .map(|_| ())
+            .execute(conn)?;
+        self.notify(conn)
     }
 
     /// Unset the value of this option in the database.
-    #[allow(dead_code)]
     pub fn unset(
         &self,
         conn: &mut SqliteConnection,
@@ -86,8 +100,341 @@ impl<T: Serialize + DeserializeOwned> ConfigOptionDef<T> {
             schema::options::table
                 .filter(schema::options::name.eq(self.key_name)),
         )
-        .execute(conn)
-        .map(|_| ())
+        .execute(conn)?;
+        self.notify(conn)
+    }
+
+    /// Subscribe to changes of this option in the database of `conn`.  The
+    /// receiver is notified after each [`set`](Self::set) or
+    /// [`unset`](Self::unset), even if the surrounding transaction is rolled
+    /// back later.
+    pub fn watch(
+        &self,
+        conn: &mut SqliteConnection,
+    ) -> diesel::QueryResult<watch::Receiver<()>> {
+        let key = (database_file(conn)?, self.key_name);
+        let mut watchers = OPTION_WATCHERS.lock().unwrap();
+        Ok(watchers
+            .entry(key)
+            .or_insert_with(|| watch::channel(()).0)
+            .subscribe())
+    }
+
+    fn notify(&self, conn: &mut SqliteConnection) -> diesel::QueryResult<()> {
+        let key = (database_file(conn)?, self.key_name);
+        if let Some(sender) = OPTION_WATCHERS.lock().unwrap().get(&key) {
+            sender.send_replace(());
+        }
+        Ok(())
+    }
+}
+
+#[derive(diesel::QueryableByName)]
+struct DatabaseFile {
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Text>)]
+    file: Option<String>,
+}
+
+/// Path of the main database file of the connection, empty for in-memory
+/// databases.
+fn database_file(conn: &mut SqliteConnection) -> diesel::QueryResult<String> {
+    diesel::sql_query(
+        "SELECT file FROM pragma_database_list WHERE name = 'main'",
+    )
+    .get_result::<DatabaseFile>(conn)
+    .map(|d| d.file.unwrap_or_default())
+}
+
+/// Type-erased access to a [`ConfigOptionDef`], used by the `/option`
+/// command.  Values are passed as JSON.
+pub trait AnyConfigOption {
+    fn name(&self) -> &'static str;
+
+    /// Get the raw JSON value, if set.
+    fn get_json(
+        &self,
+        conn: &mut SqliteConnection,
+    ) -> diesel::QueryResult<Option<String>>;
+
+    /// Validate the JSON value against the option type and store it.
+    fn set_json(
+        &self,
+        conn: &mut SqliteConnection,
+        value: &str,
+    ) -> anyhow::Result<()>;
+
+    fn unset(&self, conn: &mut SqliteConnection) -> diesel::QueryResult<()>;
+}
+
+impl<T: Serialize + DeserializeOwned> AnyConfigOption for ConfigOptionDef<T> {
+    fn name(&self) -> &'static str {
+        self.key_name
+    }
+
+    fn get_json(
+        &self,
+        conn: &mut SqliteConnection,
+    ) -> diesel::QueryResult<Option<String>> {
+        schema::options::table
+            .filter(schema::options::name.eq(self.key_name))
+            .select(schema::options::value)
+            .first(conn)
+            .optional()
+    }
+
+    fn set_json(
+        &self,
+        conn: &mut SqliteConnection,
+        value: &str,
+    ) -> anyhow::Result<()> {
+        let value = serde_json::from_str::<T>(value)?;
+        self.set(conn, &value)?;
+        Ok(())
+    }
+
+    fn unset(&self, conn: &mut SqliteConnection) -> diesel::QueryResult<()> {
+        Self::unset(self, conn)
+    }
+}
+
+/// Rewrite all `Sqlizer` columns in the current format.  Used by the
+/// `migrate-blobs` CLI command.
+pub fn migrate_blobs(conn: &mut SqliteConnection) -> diesel::QueryResult<()> {
+    use crate::utils::migrate_column as m;
+    let results = [
+        (
+            "tg_users_in_chats.chat_member",
+            m::<ChatMember>(conn, "tg_users_in_chats", "chat_member")?,
+        ),
+        (
+            "tracked_polls.voted_users",
+            m::<Vec<DbUserId>>(conn, "tracked_polls", "voted_users")?,
+        ),
+        (
+            "borrowed_items.items",
+            m::<Vec<models::BorrowedItem>>(conn, "borrowed_items", "items")?,
+        ),
+        (
+            "tracked_polls.options",
+            m::<Vec<String>>(conn, "tracked_polls", "options")?,
+        ),
+        (
+            "tracked_poll_votes.option_ids",
+            m::<Vec<usize>>(conn, "tracked_poll_votes", "option_ids")?,
+        ),
+        (
+            "pending_approvals.message",
+            m::<Message>(conn, "pending_approvals", "message")?,
+        ),
+        ("ballots.options", m::<Vec<String>>(conn, "ballots", "options")?),
+        (
+            "ranked_votes.options",
+            m::<Vec<String>>(conn, "ranked_votes", "options")?,
+        ),
+        (
+            "ranked_ballots.ranking",
+            m::<Vec<usize>>(conn, "ranked_ballots", "ranking")?,
+        ),
+        (
+            "inventory_intakes.items",
+            m::<Vec<models::IntakeItem>>(conn, "inventory_intakes", "items")?,
+        ),
+        ("outbox.action", m::<models::OutboxAction>(conn, "outbox", "action")?),
+        (
+            "outbox.compensation",
+            m::<Vec<models::OutboxAction>>(conn, "outbox", "compensation")?,
+        ),
+    ];
+    for (column, stats) in results {
+        log::info!(
+            "{column}: {} rows, {} rewritten, {} failed",
+            stats.total,
+            stats.rewritten,
+            stats.failed,
+        );
+    }
+    Ok(())
+}
+
+/// A definition for a typed per-user value stored in the database table
+/// `user_preferences`.
+pub struct UserPreferenceDef<T: Serialize + DeserializeOwned> {
+    key_name: &'static str,
+    default: T,
+}
+
+/// A helper macro for defining a `UserPreferenceDef` constant.
+macro_rules! user_preference_def {
+    ($name:ident, $type:ty, $default:expr) => {
+        #[allow(non_upper_case_globals)]
+        pub const $name: crate::db::UserPreferenceDef<$type> =
+            crate::db::UserPreferenceDef::new(stringify!($name), $default);
+    };
+}
+pub(crate) use user_preference_def;
+
+impl<T: Serialize + DeserializeOwned + Clone> UserPreferenceDef<T> {
+    pub const fn new(key_name: &'static str, default: T) -> Self {
+        Self { key_name, default }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.key_name
+    }
+
+    /// Get the value of this preference for the user.
+    /// Returns the default if the preference is not set or deserialization
+    /// fails.
+    pub fn get(
+        &self,
+        conn: &mut SqliteConnection,
+        user: UserId,
+    ) -> diesel::QueryResult<T> {
+        let value: Option<String> = schema::user_preferences::table
+            .filter(schema::user_preferences::user_id.eq(DbUserId::from(user)))
+            .filter(schema::user_preferences::name.eq(self.key_name))
+            .select(schema::user_preferences::value)
+            .first(conn)
+            .optional()?;
+        let Some(value) = value else { return Ok(self.default.clone()) };
+        match serde_json::from_str::<T>(&value) {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                log::error!(
+                    "Error deserializing preference {} of {}: {e}",
+                    self.key_name,
+                    user.0,
+                );
+                Ok(self.default.clone())
+            }
+        }
+    }
+
+    /// Get users who have explicitly set this preference to `value`.
+    pub fn users_with(
+        &self,
+        conn: &mut SqliteConnection,
+        value: &T,
+    ) -> diesel::QueryResult<Vec<DbUserId>> {
+        let value = serde_json::to_string(value)
+            .map_err(|e| DeserializationError(Box::new(e)))?;
+        schema::user_preferences::table
+            .filter(schema::user_preferences::name.eq(self.key_name))
+            .filter(schema::user_preferences::value.eq(value))
+            .select(schema::user_preferences::user_id)
+            .load(conn)
+    }
+
+    /// Set the value of this preference for the user.
+    pub fn set(
+        &self,
+        conn: &mut SqliteConnection,
+        user: UserId,
+        value: &T,
+    ) -> diesel::QueryResult<()> {
+        let value = serde_json::to_string(value)
+            .map_err(|e| DeserializationError(Box::new(e)))?;
+        diesel::replace_into(schema::user_preferences::table)
+            .values(models::UserPreference {
+                user_id: user.into(),
+                name: self.key_name.to_string(),
+                value,
+            })
+            .execute(conn)
+            .map(|_| ())
+    }
+}
+
+/// A definition for a per-chat toggle stored in the database table
+/// `feature_flags`.
+pub struct FeatureFlagDef {
+    key_name: &'static str,
+    default: bool,
+    description: &'static str,
+}
+
+/// A helper macro for defining a `FeatureFlagDef` constant.
+macro_rules! feature_flag_def {
+    ($name:ident, $default:expr, $description:expr) => {
+        #[allow(non_upper_case_globals)]
+        pub const $name: crate::db::FeatureFlagDef =
+            crate::db::FeatureFlagDef::new(
+                stringify!($name),
+                $default,
+                $description,
+            );
+    };
+}
+pub(crate) use feature_flag_def;
+
+impl FeatureFlagDef {
+    pub const fn new(
+        key_name: &'static str,
+        default: bool,
+        description: &'static str,
+    ) -> Self {
+        Self { key_name, default, description }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.key_name
+    }
+
+    pub const fn default(&self) -> bool {
+        self.default
+    }
+
+    pub const fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /// Check whether the flag is enabled in the chat.  Served from
+    /// [`DbCache`], so it's cheap to call from handlers.  Don't call while
+    /// holding the database connection.
+    ///
+    /// [`DbCache`]: crate::cache::DbCache
+    pub fn enabled(&self, env: &BotEnv, chat: ChatId) -> bool {
+        self.get(env, chat).unwrap_or(self.default)
+    }
+
+    /// The value set for the chat, if any.
+    pub fn get(&self, env: &BotEnv, chat: ChatId) -> Option<bool> {
+        match env.cache.feature_flags(&env.conn) {
+            Ok(flags) => flags.get(&(self.key_name.to_string(), chat)).copied(),
+            Err(e) => {
+                log::error!("Error loading feature flags: {e}");
+                None
+            }
+        }
+    }
+
+    /// Set the value for the chat, or reset it to the default with `None`.
+    pub fn set(
+        &self,
+        env: &BotEnv,
+        chat: ChatId,
+        value: Option<bool>,
+    ) -> diesel::QueryResult<()> {
+        let conn = &mut *env.conn();
+        match value {
+            Some(enabled) => diesel::replace_into(schema::feature_flags::table)
+                .values(models::FeatureFlag {
+                    name: self.key_name.to_string(),
+                    chat_id: chat.into(),
+                    enabled,
+                })
+                .execute(conn)?,
+            None => diesel::delete(
+                schema::feature_flags::table
+                    .filter(schema::feature_flags::name.eq(self.key_name))
+                    .filter(
+                        schema::feature_flags::chat_id.eq(DbChatId::from(chat)),
+                    ),
+            )
+            .execute(conn)?,
+        };
+        env.cache.feature_flags.invalidate();
+        Ok(())
     }
 }
 
@@ -0,0 +1,45 @@
+//! In-process event bus.
+//!
+//! Modules [`publish`](Bus::publish) an [`Event`] after changing the state,
+//! so that other parts of the bot, e.g. streaming web endpoints, can react
+//! without polling the database.  Events carry no data; subscribers should
+//! re-read the state.
+
+use tokio::sync::broadcast;
+
+/// Number of events a slow subscriber may lag behind before it misses some.
+const CAPACITY: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// An item was added to the shopping list, bought, or un-bought.
+    NeedsChanged,
+    /// A role change was recorded by [`crate::modules::role_changes::track`].
+    RoleChanged,
+}
+
+/// Event bus of an instance.  Lives in [`BotEnv::events`].
+///
+/// [`BotEnv::events`]: crate::common::BotEnv::events
+pub struct Bus(broadcast::Sender<Event>);
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Bus {
+    /// Notify all current subscribers.
+    pub fn publish(&self, event: Event) {
+        // An error means there are no subscribers, which is fine.
+        self.0.send(event).ok();
+    }
+
+    /// Subscribe to events published after this call.  On
+    /// [`Lagged`](broadcast::error::RecvError::Lagged), the subscriber should
+    /// assume that anything could have changed.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.0.subscribe()
+    }
+}
@@ -0,0 +1,78 @@
+//! Registry of external service health.
+//!
+//! Every access to an external service is reported with
+//! [`crate::metrics::update_service`], which also records it here.  The
+//! registry is exposed by the `/readyz` HTTP endpoint.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use salvo_oapi::ToSchema;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ServiceHealth {
+    /// Whether the last access succeeded.
+    pub ok: bool,
+    pub last_check: DateTime<Utc>,
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+/// Health of the services used by an instance.  Lives in [`BotEnv::health`].
+///
+/// [`BotEnv::health`]: crate::common::BotEnv::health
+pub struct Health {
+    /// Name of the instance, the `instance` label of the service metrics.
+    instance: String,
+    services: Mutex<BTreeMap<&'static str, ServiceHealth>>,
+}
+
+impl Health {
+    pub fn new(instance: &str) -> Self {
+        Self { instance: instance.to_string(), services: Mutex::default() }
+    }
+
+    pub fn instance(&self) -> &str {
+        &self.instance
+    }
+
+    /// Record the result of an access to the service.
+    pub fn report(&self, name: &'static str, ok: bool) {
+        let now = Utc::now();
+        let mut services = self.services.lock().unwrap();
+        let entry = services.entry(name).or_insert(ServiceHealth {
+            ok,
+            last_check: now,
+            last_success: None,
+        });
+        entry.ok = ok;
+        entry.last_check = now;
+        if ok {
+            entry.last_success = Some(now);
+        }
+    }
+
+    /// Health of all services reported since the start.
+    pub fn services(&self) -> BTreeMap<&'static str, ServiceHealth> {
+        self.services.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let health = Health::new("test");
+        health.report("test_service", true);
+        let first = health.services()["test_service"];
+        assert!(first.ok);
+        health.report("test_service", false);
+        let second = health.services()["test_service"];
+        assert!(!second.ok);
+        assert_eq!(second.last_success, first.last_success);
+        assert!(Health::new("test").services().is_empty());
+    }
+}
@@ -0,0 +1,526 @@
+//! Import legacy data, e.g. spreadsheets kept before the bot.
+//!
+//! Each kind of data has its own subcommand.  Files are CSV with a header
+//! row, or JSON with an array of objects if the name ends with `.json`.
+//! Columns:
+//! - `residents`: `tg_id`, `begin_date`, optional `end_date`.  Dates are UTC,
+//!   either `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`.
+//! - `macs`: `tg_id`, `mac`.
+//! - `inventory`: `name`, `quantity`, optional `added_by`.
+//!
+//! All rows are validated first, and nothing is imported if any of them is
+//! invalid.  Rows conflicting with existing data are skipped and reported:
+//! users that already have residencies, MACs that are already assigned, and
+//! inventory items that already exist.  With `--dry-run`, the report is
+//! printed, but the database is not changed.
+//!
+//! Residency changes are recorded with [`role_changes::track`], so the bot
+//! runs the hooks after a restart.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Context as _, Result};
+use argh::FromArgs;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use teloxide::types::UserId;
+
+use crate::db::DbUserId;
+use crate::modules::role_changes;
+use crate::schema;
+use crate::utils::ResultExt as _;
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum Kind {
+    Residents(Residents),
+    Macs(Macs),
+    Inventory(Inventory),
+}
+
+/// import residencies: tg_id, begin_date, end_date
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "residents")]
+pub struct Residents {
+    /// CSV or JSON file
+    #[argh(positional)]
+    file: OsString,
+}
+
+/// import MAC addresses of users: tg_id, mac
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "macs")]
+pub struct Macs {
+    /// CSV or JSON file
+    #[argh(positional)]
+    file: OsString,
+}
+
+/// import inventory items: name, quantity, added_by
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "inventory")]
+pub struct Inventory {
+    /// CSV or JSON file
+    #[argh(positional)]
+    file: OsString,
+
+    /// user ID for rows without added_by
+    #[argh(option)]
+    added_by: Option<u64>,
+}
+
+/// A row of the input file, by column name.  Empty values are omitted.
+type Row = HashMap<String, String>;
+
+#[derive(Debug, Default)]
+struct Report {
+    imported: usize,
+    /// Reasons for skipped rows.
+    skipped: Vec<String>,
+}
+
+pub fn run(db_fpath: &str, dry_run: bool, kind: &Kind) -> Result<()> {
+    let mut conn = SqliteConnection::establish(db_fpath)?;
+    let report = match kind {
+        Kind::Residents(c) => {
+            let rows = read_rows(&c.file)?;
+            conn.exclusive_transaction(|conn| {
+                import_residents(conn, &rows, dry_run)
+            })?
+        }
+        Kind::Macs(c) => {
+            let rows = read_rows(&c.file)?;
+            conn.exclusive_transaction(|conn| {
+                import_macs(conn, &rows, dry_run)
+            })?
+        }
+        Kind::Inventory(c) => {
+            let rows = read_rows(&c.file)?;
+            let added_by = c.added_by.map(UserId);
+            conn.exclusive_transaction(|conn| {
+                import_inventory(conn, &rows, added_by, dry_run)
+            })?
+        }
+    };
+
+    for reason in &report.skipped {
+        println!("Skipped {reason}");
+    }
+    println!(
+        "{} {} rows, skipped {}.",
+        if dry_run { "Would import" } else { "Imported" },
+        report.imported,
+        report.skipped.len(),
+    );
+    Ok(())
+}
+
+fn read_rows(fpath: &OsStr) -> Result<Vec<Row>> {
+    let path = Path::new(fpath);
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    if path.extension() == Some(OsStr::new("json")) {
+        parse_json(BufReader::new(file))
+    } else {
+        parse_csv(file)
+    }
+}
+
+fn parse_csv(reader: impl Read) -> Result<Vec<Row>> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers =
+        reader.headers()?.iter().map(|h| h.trim().to_string()).collect_vec();
+    reader
+        .records()
+        .map(|record| {
+            let record = record.context("Failed to parse CSV")?;
+            Ok(headers
+                .iter()
+                .zip(record.iter().map(str::trim))
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(name, value)| (name.clone(), value.to_string()))
+                .collect())
+        })
+        .collect()
+}
+
+fn parse_json(reader: impl Read) -> Result<Vec<Row>> {
+    let objects: Vec<serde_json::Map<String, serde_json::Value>> =
+        serde_json::from_reader(reader).context("Failed to parse JSON")?;
+    Ok(objects
+        .into_iter()
+        .map(|object| {
+            object
+                .into_iter()
+                .filter_map(|(name, value)| match value {
+                    serde_json::Value::Null => None,
+                    serde_json::Value::String(s) => Some((name, s)),
+                    value => Some((name, value.to_string())),
+                })
+                .filter(|(_, value)| !value.trim().is_empty())
+                .collect()
+        })
+        .collect())
+}
+
+/// Parse every row with `parse`, failing with all errors if some rows are
+/// invalid.  Returns parsed rows with their one-based numbers.
+fn validate<T>(
+    rows: &[Row],
+    parse: impl Fn(&Row) -> Result<T, String>,
+) -> Result<Vec<(usize, T)>> {
+    let mut parsed = Vec::new();
+    let mut errors = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        match parse(row) {
+            Ok(value) => parsed.push((i + 1, value)),
+            Err(e) => errors.push(format!("row {}: {e}", i + 1)),
+        }
+    }
+    check(errors)?;
+    Ok(parsed)
+}
+
+fn check(errors: Vec<String>) -> Result<()> {
+    if !errors.is_empty() {
+        bail!("Invalid input, nothing imported:\n{}", errors.join("\n"));
+    }
+    Ok(())
+}
+
+fn field<'a>(row: &'a Row, name: &str) -> Result<&'a str, String> {
+    row.get(name).map(String::as_str).ok_or_else(|| format!("missing {name}"))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    value.parse().map_err(|e| format!("invalid {name} {value:?}: {e}"))
+}
+
+fn parse_field<T: FromStr>(row: &Row, name: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    parse_value(name, field(row, name)?)
+}
+
+fn parse_date(row: &Row, name: &str) -> Result<Option<NaiveDateTime>, String> {
+    let Some(value) = row.get(name) else { return Ok(None) };
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| date.and_time(NaiveTime::default()))
+        })
+        .map(Some)
+        .map_err(|_| format!("invalid {name} {value:?}"))
+}
+
+struct Residency {
+    user: UserId,
+    begin: NaiveDateTime,
+    end: Option<NaiveDateTime>,
+}
+
+fn import_residents(
+    conn: &mut SqliteConnection,
+    rows: &[Row],
+    dry_run: bool,
+) -> Result<Report> {
+    let residencies = validate(rows, |row| {
+        let residency = Residency {
+            user: UserId(parse_field(row, "tg_id")?),
+            begin: parse_date(row, "begin_date")?
+                .ok_or("missing begin_date")?,
+            end: parse_date(row, "end_date")?,
+        };
+        if residency.end.is_some_and(|end| end < residency.begin) {
+            return Err("end_date is before begin_date".to_string());
+        }
+        Ok(residency)
+    })?;
+
+    let mut by_user: BTreeMap<UserId, Vec<(usize, Residency)>> =
+        BTreeMap::new();
+    for (n, residency) in residencies {
+        by_user.entry(residency.user).or_default().push((n, residency));
+    }
+    check(
+        by_user
+            .iter()
+            .filter(|(_, residencies)| {
+                residencies.iter().filter(|(_, r)| r.end.is_none()).count() > 1
+            })
+            .map(|(user, _)| {
+                format!("user {user} has several ongoing residencies")
+            })
+            .collect(),
+    )?;
+
+    let existing: BTreeSet<UserId> = schema::residents::table
+        .select(schema::residents::tg_id)
+        .distinct()
+        .load::<DbUserId>(conn)?
+        .into_iter()
+        .map(UserId::from)
+        .collect();
+    let mut report = Report::default();
+    for (user, residencies) in by_user {
+        if existing.contains(&user) {
+            report.skipped.extend(residencies.iter().map(|(n, _)| {
+                format!("row {n}: user {user} already has residencies")
+            }));
+            continue;
+        }
+        report.imported += residencies.len();
+        if dry_run {
+            continue;
+        }
+        role_changes::track(conn, user, |conn| {
+            diesel::insert_into(schema::residents::table)
+                .values(
+                    residencies
+                        .iter()
+                        .map(|(_, r)| {
+                            (
+                                schema::residents::tg_id
+                                    .eq(DbUserId::from(user)),
+                                schema::residents::begin_date.eq(r.begin),
+                                schema::residents::end_date.eq(r.end),
+                            )
+                        })
+                        .collect_vec(),
+                )
+                .execute(conn)
+        })?;
+        crate::modules::audit::record(
+            conn,
+            None,
+            "resident_add",
+            &serde_json::json!({ "user_id": user.0, "source": "import" }),
+        )
+        .log_error("import: audit");
+    }
+    Ok(report)
+}
+
+fn import_macs(
+    conn: &mut SqliteConnection,
+    rows: &[Row],
+    dry_run: bool,
+) -> Result<Report> {
+    let macs = validate(rows, |row| {
+        let user = UserId(parse_field(row, "tg_id")?);
+        let mac: macaddr::MacAddr6 = parse_field(row, "mac")?;
+        Ok((user, mac.to_string()))
+    })?;
+    check(
+        macs.iter()
+            .map(|(_, (_, mac))| mac)
+            .duplicates()
+            .map(|mac| format!("MAC {mac} is listed several times"))
+            .collect(),
+    )?;
+
+    let owners: HashMap<String, UserId> = schema::user_macs::table
+        .select((schema::user_macs::mac, schema::user_macs::tg_id))
+        .load::<(String, DbUserId)>(conn)?
+        .into_iter()
+        .map(|(mac, user)| (mac, user.into()))
+        .collect();
+    let mut report = Report::default();
+    let mut new = Vec::new();
+    for (n, (user, mac)) in macs {
+        match owners.get(&mac) {
+            Some(&owner) if owner == user => report
+                .skipped
+                .push(format!("row {n}: MAC {mac} is already assigned")),
+            Some(owner) => report.skipped.push(format!(
+                "row {n}: MAC {mac} is assigned to user {owner}"
+            )),
+            None => new.push((
+                schema::user_macs::tg_id.eq(DbUserId::from(user)),
+                schema::user_macs::mac.eq(mac),
+            )),
+        }
+    }
+    report.imported = new.len();
+    if !dry_run {
+        diesel::insert_into(schema::user_macs::table)
+            .values(new)
+            .execute(conn)?;
+    }
+    Ok(report)
+}
+
+fn import_inventory(
+    conn: &mut SqliteConnection,
+    rows: &[Row],
+    added_by: Option<UserId>,
+    dry_run: bool,
+) -> Result<Report> {
+    let items = validate(rows, |row| {
+        let name = field(row, "name")?.trim().to_string();
+        let quantity: i32 = parse_field(row, "quantity")?;
+        if quantity <= 0 {
+            return Err(format!("invalid quantity {quantity}"));
+        }
+        let added_by = match row.get("added_by") {
+            Some(value) => UserId(parse_value("added_by", value)?),
+            None => added_by.ok_or("missing added_by, see --added-by")?,
+        };
+        Ok((name, quantity, added_by))
+    })?;
+    check(
+        items
+            .iter()
+            .map(|(_, (name, _, _))| name)
+            .duplicates()
+            .map(|name| format!("item {name:?} is listed several times"))
+            .collect(),
+    )?;
+
+    let existing: BTreeSet<String> = schema::inventory_items::table
+        .select(schema::inventory_items::name)
+        .load::<String>(conn)?
+        .into_iter()
+        .collect();
+    let now = chrono::Utc::now().naive_utc();
+    let mut report = Report::default();
+    let mut new = Vec::new();
+    for (n, (name, quantity, added_by)) in items {
+        if existing.contains(&name) {
+            report
+                .skipped
+                .push(format!("row {n}: item {name:?} already exists"));
+            continue;
+        }
+        new.push((
+            schema::inventory_items::name.eq(name),
+            schema::inventory_items::quantity.eq(quantity),
+            schema::inventory_items::added_by.eq(DbUserId::from(added_by)),
+            schema::inventory_items::updated_at.eq(now),
+        ));
+    }
+    report.imported = new.len();
+    if !dry_run {
+        diesel::insert_into(schema::inventory_items::table)
+            .values(new)
+            .execute(conn)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::memory_db;
+
+    #[test]
+    fn test_import_residents() {
+        let mut conn = memory_db();
+        let rows = parse_csv(
+            "tg_id,begin_date,end_date\n\
+             1,2020-01-01,2021-06-30 12:00:00\n\
+             1,2022-01-01,\n\
+             2,2023-05-01,\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let report = import_residents(&mut conn, &rows, true).unwrap();
+        assert_eq!((report.imported, report.skipped.len()), (3, 0));
+        let count = |conn: &mut SqliteConnection| -> i64 {
+            schema::residents::table.count().get_result(conn).unwrap()
+        };
+        assert_eq!(count(&mut conn), 0);
+
+        import_residents(&mut conn, &rows[2..], false).unwrap();
+        assert_eq!(count(&mut conn), 1);
+        let report = import_residents(&mut conn, &rows, false).unwrap();
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.skipped, ["row 3: user 2 already has residencies"]);
+        assert_eq!(count(&mut conn), 3);
+        let changes: i64 =
+            schema::role_changes::table.count().get_result(&mut conn).unwrap();
+        assert_eq!(changes, 2);
+
+        let rows = parse_json(
+            r#"[
+                {"tg_id": 3, "begin_date": "2020-02-01", "end_date": "2020"},
+                {"tg_id": 4},
+                {
+                    "tg_id": 5,
+                    "begin_date": "2020-02-01",
+                    "end_date": "2020-01-01 12:00:00"
+                }
+            ]"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let e = import_residents(&mut conn, &rows, false).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Invalid input, nothing imported:\n\
+             row 1: invalid end_date \"2020\"\n\
+             row 2: missing begin_date\n\
+             row 3: end_date is before begin_date",
+        );
+    }
+
+    #[test]
+    fn test_import_macs_and_inventory() {
+        let mut conn = memory_db();
+        let rows = parse_csv(
+            "tg_id,mac\n1,aa:bb:cc:dd:ee:ff\n2,00-11-22-33-44-55\n".as_bytes(),
+        )
+        .unwrap();
+        import_macs(&mut conn, &rows[..1], false).unwrap();
+        let report = import_macs(&mut conn, &rows, false).unwrap();
+        assert_eq!(report.imported, 1);
+        let mac = "AA:BB:CC:DD:EE:FF";
+        assert_eq!(
+            report.skipped,
+            [format!("row 1: MAC {mac} is already assigned")]
+        );
+        let rows =
+            parse_csv("tg_id,mac\n3,aabb.ccdd.eeff\n".as_bytes()).unwrap();
+        let report = import_macs(&mut conn, &rows, false).unwrap();
+        assert_eq!(
+            report.skipped,
+            [format!("row 1: MAC {mac} is assigned to user 1")]
+        );
+
+        let rows = parse_csv(
+            "name,quantity,added_by\nDrill,2,\nSoldering iron,1,5\n".as_bytes(),
+        )
+        .unwrap();
+        assert!(import_inventory(&mut conn, &rows, None, false).is_err());
+        let report =
+            import_inventory(&mut conn, &rows, Some(UserId(1)), false).unwrap();
+        assert_eq!(report.imported, 2);
+        let items: Vec<(String, DbUserId)> = schema::inventory_items::table
+            .order(schema::inventory_items::name)
+            .select((
+                schema::inventory_items::name,
+                schema::inventory_items::added_by,
+            ))
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(
+            items,
+            [
+                ("Drill".to_string(), DbUserId::from(UserId(1))),
+                ("Soldering iron".to_string(), DbUserId::from(UserId(5))),
+            ]
+        );
+    }
+}
@@ -24,15 +24,18 @@
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, OnceLock};
 
 use anyhow::{Context as _, Result};
 use argh::FromArgs;
 use diesel::sqlite::SqliteConnection;
 use diesel::Connection;
+use dptree::di::DependencyMap;
+use itertools::Itertools as _;
 use metrics_exporter_prometheus::PrometheusBuilder;
 use tap::Pipe as _;
-use teloxide::dispatching::{Dispatcher, UpdateFilterExt};
+use teloxide::dispatching::{Dispatcher, ShutdownToken, UpdateFilterExt};
 use teloxide::payloads::AnswerCallbackQuerySetters;
 use teloxide::requests::Requester;
 use teloxide::types::{CallbackQuery, Message, Update};
@@ -40,13 +43,23 @@ use teloxide::Bot;
 use tokio_util::sync::CancellationToken;
 use utils::HandlerExt as _;
 
+mod cache;
 mod common;
 mod config;
+mod cooldowns;
 mod db;
+mod events;
+mod health;
+mod import;
 mod metrics;
+mod mock_telegram;
 mod models;
 mod modules;
+mod outbox;
+mod replay;
 mod schema;
+#[cfg(test)]
+mod testing;
 mod tracing_proxy;
 mod utils;
 mod web_srv;
@@ -56,6 +69,10 @@ static VERSION: OnceLock<String> = OnceLock::new();
 static DB_FILENAME: &str = "db.sqlite3";
 static TRACE_FILENAME: &str = "trace.jsonl";
 
+/// Name of the instance run from a plain bot config, see
+/// [`config::Instances`].
+static DEFAULT_INSTANCE: &str = "default";
+
 fn version() -> &'static str {
     VERSION.get().expect("VERSION is not set")
 }
@@ -75,13 +92,16 @@ struct Args {
 enum SubCommand {
     Bot(SubCommandBot),
     Scrape(SubCommandScrape),
+    MigrateBlobs(SubCommandMigrateBlobs),
+    Replay(SubCommandReplay),
+    Import(SubCommandImport),
 }
 
 /// run the bot
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "bot")]
 struct SubCommandBot {
-    /// config file
+    /// config file, or a file listing instances
     #[argh(positional)]
     config_file: OsString,
 }
@@ -103,6 +123,49 @@ struct SubCommandScrape {
     residential_chats: Vec<i64>,
 }
 
+/// rewrite JSON columns in the current format
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "migrate-blobs")]
+struct SubCommandMigrateBlobs {
+    /// db file
+    #[argh(positional)]
+    db_file: String,
+}
+
+/// feed recorded updates through the handlers with Telegram API stubbed
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "replay")]
+struct SubCommandReplay {
+    /// config file
+    #[argh(positional)]
+    config_file: OsString,
+
+    /// updates file, e.g. trace.jsonl
+    #[argh(positional)]
+    updates_file: OsString,
+
+    /// db file to start from; a scratch copy is used, the file is not
+    /// modified
+    #[argh(option, default = "DB_FILENAME.to_string()")]
+    db: String,
+}
+
+/// import legacy data from CSV or JSON files
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "import")]
+struct SubCommandImport {
+    /// db file
+    #[argh(option, default = "DB_FILENAME.to_string()")]
+    db: String,
+
+    /// validate and report conflicts without changing the database
+    #[argh(switch)]
+    dry_run: bool,
+
+    #[argh(subcommand)]
+    kind: import::Kind,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     std::env::set_var("RUST_LOG", "info");
@@ -119,6 +182,14 @@ async fn main() -> Result<()> {
         SubCommand::Scrape(c) => {
             scrape_log(&c.db_file, &c.log_file, &c.residential_chats)?;
         }
+        SubCommand::MigrateBlobs(c) => {
+            let mut conn = SqliteConnection::establish(&c.db_file)?;
+            conn.exclusive_transaction(db::migrate_blobs)?;
+        }
+        SubCommand::Replay(c) => {
+            replay::run(&c.config_file, &c.updates_file, &c.db).await?;
+        }
+        SubCommand::Import(c) => import::run(&c.db, c.dry_run, &c.kind)?,
     }
     Ok(())
 }
@@ -126,100 +197,503 @@ async fn main() -> Result<()> {
 async fn run_bot(config_fpath: &OsStr) -> Result<()> {
     let prometheus = PrometheusBuilder::new().install_recorder()?;
     metrics::register_metrics();
-    modules::borrowed_items::register_metrics();
 
-    let config: crate::config::Config = File::open(config_fpath)
+    let cancel = CancellationToken::new();
+    let mut join_handles = Vec::new();
+    let mut shutdown_tokens = Vec::new();
+    let mut web_instances = Vec::new();
+    for instance in load_instances(config_fpath)? {
+        let (bot_env, bot, shutdown_token) =
+            start_instance(&instance, &cancel, &mut join_handles).await?;
+        shutdown_tokens.push(shutdown_token);
+        web_instances.push((bot, establish(&bot_env.db_path)?, bot_env));
+    }
+    anyhow::ensure!(!web_instances.is_empty(), "No instances are configured");
+
+    join_handles.push(tokio::spawn(web_srv::run(
+        web_instances,
+        prometheus,
+        cancel.clone(),
+    )));
+
+    run_signal_handler(shutdown_tokens, cancel);
+
+    futures::future::join_all(join_handles).await;
+
+    Ok(())
+}
+
+/// Read the instances to run from the config file.  A plain bot config is a
+/// single instance named [`DEFAULT_INSTANCE`] using [`DB_FILENAME`].
+fn load_instances(config_fpath: &OsStr) -> Result<Vec<config::Instance>> {
+    let value: serde_yaml::Value = File::open(config_fpath)
         .context("Failed to open config file")?
         .pipe(serde_yaml::from_reader)
         .context("Failed to parse config file")?;
+    if value.get("instances").is_none() {
+        return Ok(vec![config::Instance {
+            name: DEFAULT_INSTANCE.to_string(),
+            config: config_fpath.into(),
+            db: DB_FILENAME.into(),
+        }]);
+    }
 
-    if config.telegram.passive_mode {
-        log::info!("Running in passive mode");
+    let instances = serde_yaml::from_value::<config::Instances>(value)
+        .context("Failed to parse instances")?
+        .instances;
+    for instance in &instances {
+        anyhow::ensure!(
+            !instance.name.is_empty()
+                && instance.name.chars().all(|c| {
+                    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+                }),
+            "Invalid instance name {:?}",
+            instance.name,
+        );
     }
+    anyhow::ensure!(
+        instances.iter().map(|i| &i.name).all_unique(),
+        "Instance names must be unique"
+    );
+    anyhow::ensure!(
+        instances.iter().map(|i| &i.db).all_unique(),
+        "Instances must not share a database"
+    );
+    Ok(instances)
+}
 
-    let bot_env = Arc::new(common::BotEnv {
-        conn: Mutex::new(SqliteConnection::establish(&format!(
-            "sqlite://{DB_FILENAME}"
-        ))?),
-        reqwest_client: reqwest::ClientBuilder::new()
-            .danger_accept_invalid_certs(true)
-            .build()?,
-        openai_client: async_openai::Client::with_config(
-            async_openai::config::OpenAIConfig::new()
-                .with_api_key(config.services.openai.api_key.clone()),
-        ),
-        config: Arc::new(config),
-        config_path: config_fpath.into(),
-    });
+/// Trace file of an instance.  The default instance keeps the file name used
+/// before instances were introduced.
+fn trace_filename(instance: &str) -> PathBuf {
+    if instance == DEFAULT_INSTANCE {
+        TRACE_FILENAME.into()
+    } else {
+        format!("trace.{instance}.jsonl").into()
+    }
+}
+
+fn establish(db_path: &Path) -> Result<SqliteConnection> {
+    let db_path = db_path.to_str().context("Non-UTF-8 database path")?;
+    Ok(SqliteConnection::establish(&format!("sqlite://{db_path}"))?)
+}
+
+/// Start the dispatcher and the background tasks of an instance.  They stop
+/// when `cancel` is cancelled or the returned token is used.
+async fn start_instance(
+    instance: &config::Instance,
+    cancel: &CancellationToken,
+    join_handles: &mut Vec<tokio::task::JoinHandle<()>>,
+) -> Result<(Arc<common::BotEnv>, Bot, ShutdownToken)> {
+    log::info!("Starting instance {}", instance.name);
+    let bot_env = load_bot_env(
+        &instance.name,
+        instance.config.as_os_str(),
+        &instance.db,
+    )?;
+    modules::borrowed_items::register_metrics(&instance.name);
+
+    if bot_env.config.telegram.passive_mode {
+        log::info!("Instance {} is running in passive mode", instance.name);
+    }
 
-    let proxy_addr = tracing_proxy::start().await?;
+    let (reactions_tx, reactions_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (sent_tx, sent_rx) = tokio::sync::mpsc::unbounded_channel();
+    let proxy_addr = tracing_proxy::start(
+        Arc::clone(&bot_env),
+        &trace_filename(&instance.name),
+        reactions_tx,
+        sent_tx,
+    )
+    .await?;
     let bot = Bot::new(&bot_env.config.telegram.token).set_api_url(proxy_addr);
 
+    let command_handlers = command_handlers();
     let mut dispatcher = Dispatcher::builder(
         bot.clone(),
-        dptree::entry()
-            // should be the first handler
-            .inspect(modules::tg_scraper::inspect_update)
-            .inspect(modules::resident_tracker::inspect_update)
-            .branch(
-                Update::filter_message()
-                    .filter(|msg: Message, env: Arc<common::BotEnv>| {
-                        !msg.chat.is_channel()
-                            && !env.config.telegram.passive_mode
-                    })
-                    .inspect_err(modules::rename_closed_topics::inspect_message)
-                    .inspect_err(modules::forward_topic_pins::inspect_message)
-                    .branch(modules::basic::command_handler())
-                    .branch(modules::dashboard::command_handler())
-                    .branch(modules::userctl::command_handler())
-                    .branch(modules::polls::message_handler())
-                    .branch(modules::borrowed_items::command_handler())
-                    .branch(modules::needs::message_handler())
-                    .branch(modules::welcome::message_handler())
-                    .endpoint(drop_endpoint),
-            )
-            .branch(
-                Update::filter_callback_query()
-                    .branch(modules::needs::callback_handler())
-                    .branch(modules::polls::callback_handler())
-                    .branch(modules::borrowed_items::callback_handler())
-                    .endpoint(drop_callback_query),
-            )
-            .branch(modules::polls::poll_answer_handler())
-            .endpoint(drop_endpoint),
+        update_handler(command_handlers.clone()),
     )
-    .dependencies(dptree::deps![
-        modules::forward_topic_pins::state(),
-        modules::welcome::state(),
-        Arc::clone(&bot_env)
-    ])
+    .dependencies(dependencies(Arc::clone(&bot_env), command_handlers))
     .build();
     let bot_shutdown_token = dispatcher.shutdown_token().clone();
-    let mut join_handles = Vec::new();
     join_handles.push(tokio::spawn(async move { dispatcher.dispatch().await }));
 
-    let cancel = CancellationToken::new();
-
     if !bot_env.config.telegram.passive_mode {
+        join_handles.push(tokio::spawn(modules::action_items::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
         join_handles.push(tokio::spawn(modules::updates::task(
             Arc::clone(&bot_env),
             bot.clone(),
             cancel.clone(),
         )));
+        join_handles.push(tokio::spawn(modules::bandwidth::task(
+            Arc::clone(&bot_env),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::bookings::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(
+            modules::borrowed_items::reactions_task(
+                Arc::clone(&bot_env),
+                bot.clone(),
+                reactions_rx,
+                cancel.clone(),
+            ),
+        ));
+        join_handles.push(tokio::spawn(modules::chat_admins::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::chores::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::countdowns::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::door_access::task(
+            Arc::clone(&bot_env),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::energy::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::export::task(
+            Arc::clone(&bot_env),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::feeds::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::fridge::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::frontdesk::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::guest_wifi::task(
+            Arc::clone(&bot_env),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::incidents::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::message_ttl::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            sent_rx,
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::monitor::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::network_devices::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::polls::recover(
+            Arc::clone(&bot_env),
+            bot.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::command_scopes::sync(
+            Arc::clone(&bot_env),
+            bot.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::packages::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::retention::task(
+            Arc::clone(&bot_env),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::rotation::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::vpn::task(
+            Arc::clone(&bot_env),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::version::announce(
+            Arc::clone(&bot_env),
+            bot.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::mail_bridge::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::donations::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::mqtt::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::needs_wiki::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(
+            modules::membership_reconciliation::task(
+                Arc::clone(&bot_env),
+                bot.clone(),
+                cancel.clone(),
+            ),
+        ));
+        join_handles.push(tokio::spawn(outbox::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::projects::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::tg_users_refresh::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::role_changes::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
+        join_handles.push(tokio::spawn(modules::resident_sync::task(
+            Arc::clone(&bot_env),
+            bot.clone(),
+            cancel.clone(),
+        )));
     }
 
-    join_handles.push(tokio::spawn(web_srv::run(
-        SqliteConnection::establish(&format!("sqlite://{DB_FILENAME}"))?,
-        Arc::clone(&bot_env.config),
-        prometheus,
+    join_handles.push(tokio::spawn(modules::presence::task(
+        Arc::clone(&bot_env),
+        cancel.clone(),
+    )));
+
+    join_handles.push(tokio::spawn(modules::update_dedup::task(
+        Arc::clone(&bot_env),
         cancel.clone(),
     )));
 
-    run_signal_handler(bot_shutdown_token.clone(), cancel.clone());
+    Ok((bot_env, bot, bot_shutdown_token))
+}
 
-    futures::future::join_all(join_handles).await;
+fn load_bot_env(
+    instance: &str,
+    config_fpath: &OsStr,
+    db_path: &Path,
+) -> Result<Arc<common::BotEnv>> {
+    let config: crate::config::Config = File::open(config_fpath)
+        .context("Failed to open config file")?
+        .pipe(serde_yaml::from_reader)
+        .context("Failed to parse config file")?;
+    if let Some(approvals) = &config.telegram.approvals {
+        modules::approvals::check_config(approvals)?;
+    }
 
-    Ok(())
+    Ok(Arc::new(common::BotEnv {
+        conn: Mutex::new(establish(db_path)?),
+        reqwest_client: reqwest::ClientBuilder::new()
+            .danger_accept_invalid_certs(true)
+            .build()?,
+        openai_client: async_openai::Client::with_config(
+            async_openai::config::OpenAIConfig::new()
+                .with_api_key(config.services.openai.api_key.clone()),
+        ),
+        config: Arc::new(config),
+        config_path: config_fpath.into(),
+        instance: instance.to_string(),
+        db_path: db_path.into(),
+        cache: cache::DbCache::default(),
+        cooldowns: cooldowns::Cooldowns::default(),
+        health: health::Health::new(instance),
+        events: events::Bus::default(),
+        outbox: outbox::Wake::default(),
+    }))
+}
+
+/// Handlers of all bot commands.  They are also used to re-dispatch approved
+/// commands.
+fn command_handlers() -> common::UpdateHandler {
+    dptree::entry()
+        .branch(modules::action_items::command_handler())
+        .branch(modules::activity_feed::command_handler())
+        .branch(modules::ask::command_handler())
+        .branch(modules::audit::command_handler())
+        .branch(modules::backup::command_handler())
+        .branch(modules::ballots::command_handler())
+        .branch(modules::bandwidth::command_handler())
+        .branch(modules::basic::command_handler())
+        .branch(modules::bookings::command_handler())
+        .branch(modules::broadcasts::command_handler())
+        .branch(modules::chores::command_handler())
+        .branch(modules::countdowns::command_handler())
+        .branch(modules::dashboard::command_handler())
+        .branch(modules::donations::command_handler())
+        .branch(modules::door_access::command_handler())
+        .branch(modules::energy::command_handler())
+        .branch(modules::faq::command_handler())
+        .branch(modules::feature_flags::command_handler())
+        .branch(modules::feeds::command_handler())
+        .branch(modules::follows::command_handler())
+        .branch(modules::fridge::command_handler())
+        .branch(modules::guest_wifi::command_handler())
+        .branch(modules::help::command_handler())
+        .branch(modules::incidents::command_handler())
+        .branch(modules::intros::command_handler())
+        .branch(modules::inventory::command_handler())
+        .branch(modules::invite_links::command_handler())
+        .branch(modules::karma::command_handler())
+        .branch(modules::link_archive::command_handler())
+        .branch(modules::meetings::command_handler())
+        .branch(modules::mention_groups::command_handler())
+        .branch(modules::message_ttl::command_handler())
+        .branch(modules::moderation::command_handler())
+        .branch(modules::needs_wiki::command_handler())
+        .branch(modules::options::command_handler())
+        .branch(modules::packages::command_handler())
+        .branch(modules::polls::command_handler())
+        .branch(modules::print_sheet::command_handler())
+        .branch(modules::projects::command_handler())
+        .branch(modules::proposals::command_handler())
+        .branch(modules::ranked_votes::command_handler())
+        .branch(modules::reimbursements::command_handler())
+        .branch(modules::retention::command_handler())
+        .branch(modules::roles::command_handler())
+        .branch(modules::rotation::command_handler())
+        .branch(modules::settings::command_handler())
+        .branch(modules::timezones::command_handler())
+        .branch(modules::undo::command_handler())
+        .branch(modules::userctl::command_handler())
+        .branch(modules::version::command_handler())
+        .branch(modules::vpn::command_handler())
+        .branch(modules::web_login::command_handler())
+}
+
+/// The root handler for all updates.
+fn update_handler(
+    command_handlers: common::UpdateHandler,
+) -> common::UpdateHandler {
+    dptree::entry()
+        // should be the first handler
+        .inspect(modules::tg_scraper::inspect_update)
+        .inspect(modules::resident_tracker::inspect_update)
+        .branch(modules::update_dedup::duplicate_handler())
+        // counts joins, so must not see repeated updates
+        .inspect(modules::invite_links::inspect_update)
+        .branch(
+            Update::filter_message()
+                .filter(|msg: Message, env: Arc<common::BotEnv>| {
+                    !msg.chat.is_channel() && !env.config.telegram.passive_mode
+                })
+                .inspect_err(modules::rename_closed_topics::inspect_message)
+                .inspect_err(modules::forward_topic_pins::inspect_message)
+                .inspect_err(modules::backup::inspect_message)
+                .inspect_err(modules::mail_bridge::inspect_message)
+                .inspect_err(modules::matrix_bridge::inspect_message)
+                .inspect_err(modules::moderation::inspect_message)
+                .inspect_err(modules::spam_protection::inspect_message)
+                .inspect_err(modules::link_archive::inspect_message)
+                .inspect_err(modules::mention_groups::inspect_message)
+                .inspect(modules::meetings::inspect_message)
+                .inspect(modules::message_ttl::inspect_message)
+                .inspect_err(modules::network_devices::inspect_message)
+                .branch(command_handlers.clone())
+                .branch(modules::proposals::message_handler())
+                .branch(modules::rotation::message_handler())
+                .branch(modules::polls::message_handler())
+                .branch(modules::borrowed_items::command_handler())
+                .branch(modules::faq::message_handler())
+                .branch(modules::karma::message_handler())
+                .branch(modules::needs::message_handler())
+                .branch(modules::welcome::message_handler())
+                .branch(modules::timezones::message_handler())
+                .branch(modules::frontdesk::message_handler())
+                .endpoint(drop_endpoint),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .branch(modules::action_items::callback_handler())
+                .branch(modules::approvals::callback_handler())
+                .branch(modules::ballots::callback_handler())
+                .branch(modules::bookings::callback_handler())
+                .branch(modules::chores::callback_handler())
+                .branch(modules::membership_reconciliation::callback_handler())
+                .branch(modules::fridge::callback_handler())
+                .branch(modules::help::callback_handler())
+                .branch(modules::inventory::callback_handler())
+                .branch(modules::join_requests::callback_handler())
+                .branch(modules::link_archive::callback_handler())
+                .branch(modules::moderation::callback_handler())
+                .branch(modules::needs::callback_handler())
+                .branch(modules::network_devices::callback_handler())
+                .branch(modules::packages::callback_handler())
+                .branch(modules::polls::callback_handler())
+                .branch(modules::proposals::callback_handler())
+                .branch(modules::ranked_votes::callback_handler())
+                .branch(modules::reimbursements::callback_handler())
+                .branch(modules::rotation::callback_handler())
+                .branch(modules::settings::callback_handler())
+                .branch(modules::spam_protection::callback_handler())
+                .branch(modules::borrowed_items::callback_handler())
+                .endpoint(drop_callback_query),
+        )
+        .branch(modules::join_requests::join_request_handler())
+        .branch(modules::polls::poll_answer_handler())
+        .branch(modules::polls::poll_handler())
+        .endpoint(drop_endpoint)
+}
+
+/// Dependencies of [`update_handler`], except for the update itself, the
+/// [`Bot`], and [`Me`](teloxide::types::Me).
+fn dependencies(
+    bot_env: Arc<common::BotEnv>,
+    command_handlers: common::UpdateHandler,
+) -> DependencyMap {
+    let mut deps = dptree::deps![
+        modules::faq::state(),
+        modules::forward_topic_pins::state(),
+        modules::spam_protection::state(),
+        modules::welcome::state(),
+        bot_env
+    ];
+    deps.insert(modules::approvals::CommandHandlers::new(
+        command_handlers,
+        deps.clone(),
+    ));
+    deps
 }
 
 fn scrape_log(
@@ -275,34 +749,33 @@ async fn drop_endpoint() -> Result<()> {
 }
 
 fn run_signal_handler(
-    bot_shutdown_token: teloxide::dispatching::ShutdownToken,
+    shutdown_tokens: Vec<ShutdownToken>,
     cancel: CancellationToken,
 ) {
     tokio::spawn(async move {
         loop {
             tokio::signal::ctrl_c().await.expect("Failed to listen for SIGINT");
             cancel.cancel();
-            match bot_shutdown_token.shutdown() {
-                #[allow(
-                    clippy::redundant_pub_crate,
-                    // reason = "https://github.com/rust-lang/rust-clippy/issues/10636"
-                )]
-                Ok(f) => {
-                    log::info!(
-                        "^C received, trying to shutdown the dispatcher..."
-                    );
-                    tokio::select! {
-                        () = f => {
-                            log::info!("dispatcher is shutdown...");
-                        }
-                        _ = tokio::signal::ctrl_c() => {
-                            log::info!("Got another ^C, exiting immediately");
-                            std::process::exit(0);
-                        }
-                    }
+            let dispatchers = shutdown_tokens
+                .iter()
+                .filter_map(|token| token.shutdown().ok())
+                .collect::<Vec<_>>();
+            if dispatchers.is_empty() {
+                log::info!("^C received, the dispatchers aren't running, ignoring the signal");
+                continue;
+            }
+            log::info!("^C received, trying to shutdown the dispatchers...");
+            #[allow(
+                clippy::redundant_pub_crate,
+                // reason = "https://github.com/rust-lang/rust-clippy/issues/10636"
+            )]
+            tokio::select! {
+                _ = futures::future::join_all(dispatchers) => {
+                    log::info!("dispatchers are shutdown...");
                 }
-                Err(_) => {
-                    log::info!("^C received, the dispatcher isn't running, ignoring the signal");
+                _ = tokio::signal::ctrl_c() => {
+                    log::info!("Got another ^C, exiting immediately");
+                    std::process::exit(0);
                 }
             }
         }
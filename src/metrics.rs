@@ -1,5 +1,9 @@
+use std::path::Path;
+
 use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, SqliteConnection};
 
+use crate::health::Health;
+
 #[allow(clippy::module_name_repetitions)] // For conistency with other modules.
 pub fn register_metrics() {
     // Descriptions of labeled metrics
@@ -36,9 +40,9 @@ pub fn register_metrics() {
     );
 }
 
-/// Refresh some metrics before dumping them.
+/// Refresh some metrics of an instance before dumping them.
 #[allow(clippy::cast_precision_loss)] // Rounding errors are fine here.
-pub fn refresh(conn: &mut SqliteConnection) {
+pub fn refresh(conn: &mut SqliteConnection, db_path: &Path, instance: &str) {
     // botka_residents
     use crate::schema::residents::dsl as r;
     let resident_count = r::residents
@@ -47,28 +51,39 @@ pub fn refresh(conn: &mut SqliteConnection) {
         .get_result::<i64>(conn)
         .unwrap_or_default() as f64;
     metrics::describe_gauge!("botka_residents", "Number of residents.");
-    metrics::gauge!("botka_residents", resident_count);
+    metrics::gauge!(
+        "botka_residents",
+        resident_count,
+        "instance" => instance.to_string(),
+    );
 
     // botka_db_size_bytes
-    let db_size = std::fs::metadata(crate::DB_FILENAME)
-        .map(|m| m.len())
-        .unwrap_or_default() as f64;
+    let db_size =
+        std::fs::metadata(db_path).map(|m| m.len()).unwrap_or_default() as f64;
     metrics::describe_gauge!(
         "botka_db_size_bytes",
         "Size of the database file in bytes."
     );
-    metrics::gauge!("botka_db_size_bytes", db_size);
+    metrics::gauge!(
+        "botka_db_size_bytes",
+        db_size,
+        "instance" => instance.to_string(),
+    );
 }
 
-pub fn update_service(name: &'static str, success: bool) {
+/// Report an access to an external service, see also [`crate::health`].
+pub fn update_service(health: &Health, name: &'static str, success: bool) {
+    health.report(name, success);
     metrics::gauge!(
         "botka_service_access_success",
         if success { 1.0 } else { 0.0 },
+        "instance" => health.instance().to_string(),
         "service" => name,
     );
     metrics::gauge!(
         "botka_service_last_access_timestamp_seconds",
         std::time::UNIX_EPOCH.elapsed().unwrap_or_default().as_secs_f64(),
+        "instance" => health.instance().to_string(),
         "service" => name,
         "status" => if success { "success" } else { "failure" },
     );
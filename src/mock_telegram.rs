@@ -0,0 +1,355 @@
+//! Stub Telegram Bot API server.
+//!
+//! [`MockTelegram`] records requests made by the bot and replies with
+//! plausible responses, e.g. a message for `sendMessage`, so that handlers
+//! can run without a connection to Telegram.  Used by tests and by the
+//! `replay` subcommand.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use hyper::{Body, Response, Server};
+use itertools::Itertools as _;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::utils::parse_tgapi_method;
+
+pub const BOT_ID: u64 = 999_999;
+pub const BOT_USERNAME: &str = "botka_test";
+
+/// A request received by [`MockTelegram`].
+#[derive(Clone, Debug, Serialize)]
+pub struct Request {
+    /// Method name in lowercase, e.g. `sendmessage`.
+    pub method: String,
+    /// JSON payload, or `null` for multipart requests.
+    pub body: Value,
+    /// The `result` field of the response, or `null` on failure.
+    pub result: Value,
+}
+
+#[derive(Default)]
+struct MockState {
+    requests: Vec<Request>,
+    /// Scripted responses by lowercase method name, used before defaults.
+    scripted: HashMap<String, VecDeque<Value>>,
+    last_message_id: i32,
+}
+
+/// Poll ids are unique across servers, like in Telegram, as handlers may keep
+/// global state by poll id.
+static LAST_POLL_ID: AtomicU32 = AtomicU32::new(0);
+
+/// A mock Telegram Bot API server.
+pub struct MockTelegram {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    stop: Option<oneshot::Sender<()>>,
+}
+
+impl MockTelegram {
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockState {
+            last_message_id: 1000,
+            ..MockState::default()
+        }));
+        let state_clone = Arc::clone(&state);
+        let make_svc = hyper::service::make_service_fn(move |_| {
+            let state = Arc::clone(&state_clone);
+            async move {
+                Ok::<_, hyper::Error>(hyper::service::service_fn(move |req| {
+                    let state = Arc::clone(&state);
+                    async move {
+                        let method = parse_tgapi_method(req.uri().path())
+                            .unwrap_or_default()
+                            .to_ascii_lowercase();
+                        let body = hyper::body::to_bytes(req.into_body())
+                            .await
+                            .expect("Failed to read body");
+                        let body = serde_json::from_slice(&body)
+                            .unwrap_or(Value::Null);
+                        let response =
+                            state.lock().unwrap().handle(method, body);
+                        Ok::<_, hyper::Error>(Response::new(Body::from(
+                            response.to_string(),
+                        )))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        let (stop, rx) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            rx.await.ok();
+        }));
+        Self { addr, state, stop: Some(stop) }
+    }
+
+    pub fn url(&self) -> reqwest::Url {
+        reqwest::Url::parse(&format!("http://{}", self.addr)).unwrap()
+    }
+
+    /// Reply to the next call of `method` with `result`.
+    #[cfg(test)]
+    pub fn respond(&self, method: &str, result: Value) {
+        self.script(method, json!({ "ok": true, "result": result }));
+    }
+
+    /// Fail the next call of `method` with the given description.
+    #[cfg(test)]
+    pub fn fail(&self, method: &str, description: &str) {
+        self.script(
+            method,
+            json!({
+                "ok": false,
+                "error_code": 400,
+                "description": description,
+            }),
+        );
+    }
+
+    #[cfg(test)]
+    fn script(&self, method: &str, response: Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .scripted
+            .entry(method.to_ascii_lowercase())
+            .or_default()
+            .push_back(response);
+    }
+
+    /// Payloads of the requests to `method`, case-insensitive.
+    #[cfg(test)]
+    pub fn calls(&self, method: &str) -> Vec<Value> {
+        let method = method.to_ascii_lowercase();
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|r| r.method == method)
+            .map(|r| r.body.clone())
+            .collect()
+    }
+
+    /// Results returned for the requests to `method`, case-insensitive.
+    #[cfg(test)]
+    pub fn results(&self, method: &str) -> Vec<Value> {
+        let method = method.to_ascii_lowercase();
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|r| r.method == method)
+            .map(|r| r.result.clone())
+            .collect()
+    }
+
+    /// Forget the requests received so far.
+    #[cfg(test)]
+    pub fn clear(&self) {
+        self.take_requests();
+    }
+
+    /// Requests received since the last call.
+    pub fn take_requests(&self) -> Vec<Request> {
+        std::mem::take(&mut self.state.lock().unwrap().requests)
+    }
+}
+
+impl Drop for MockTelegram {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop.send(()).ok();
+        }
+    }
+}
+
+impl MockState {
+    fn handle(&mut self, method: String, body: Value) -> Value {
+        let scripted =
+            self.scripted.get_mut(&method).and_then(VecDeque::pop_front);
+        let response = scripted.unwrap_or_else(|| {
+            json!({ "ok": true, "result": self.default_result(&method, &body) })
+        });
+        let result = response["result"].clone();
+        self.requests.push(Request { method, body, result });
+        response
+    }
+
+    /// A plausible response for the method.
+    fn default_result(&mut self, method: &str, body: &Value) -> Value {
+        let chat_id = body["chat_id"].as_i64().unwrap_or_default();
+        match method {
+            "getme" => me_json(),
+            "sendmessage" | "forwardmessage" => {
+                self.last_message_id += 1;
+                let mut msg = message_json(
+                    chat_id,
+                    self.last_message_id,
+                    body["message_thread_id"].as_i64(),
+                    &bot_user_json(),
+                );
+                msg["text"] = body["text"].as_str().unwrap_or_default().into();
+                msg["reply_markup"] = body["reply_markup"].clone();
+                msg
+            }
+            "copymessage" => {
+                self.last_message_id += 1;
+                json!({ "message_id": self.last_message_id })
+            }
+            "createforumtopic" => {
+                self.last_message_id += 1;
+                json!({
+                    "message_thread_id": self.last_message_id,
+                    "name": body["name"],
+                    "icon_color": body["icon_color"],
+                })
+            }
+            "editmessagetext" | "editmessagereplymarkup" => {
+                let message_id =
+                    body["message_id"].as_i64().unwrap_or_default();
+                let mut msg = message_json(
+                    chat_id,
+                    i32::try_from(message_id).unwrap_or_default(),
+                    None,
+                    &bot_user_json(),
+                );
+                if let Some(text) = body["text"].as_str() {
+                    msg["text"] = text.into();
+                }
+                msg["reply_markup"] = body["reply_markup"].clone();
+                msg
+            }
+            "sendpoll" => {
+                self.last_message_id += 1;
+                let poll_id = LAST_POLL_ID.fetch_add(1, Ordering::Relaxed) + 1;
+                let mut msg = message_json(
+                    chat_id,
+                    self.last_message_id,
+                    body["message_thread_id"].as_i64(),
+                    &bot_user_json(),
+                );
+                let options = body["options"]
+                    .as_array()
+                    .map(|o| o.iter().filter_map(Value::as_str).collect_vec())
+                    .unwrap_or_default();
+                msg["poll"] = poll_json(
+                    &format!("poll-{poll_id}"),
+                    body["question"].as_str().unwrap_or_default(),
+                    &options,
+                );
+                msg
+            }
+            "getchatmember" => {
+                let user_id = body["user_id"].as_u64().unwrap_or_default();
+                if user_id == BOT_ID {
+                    bot_admin_json()
+                } else {
+                    json!({
+                        "status": "member",
+                        "user": {
+                            "id": user_id,
+                            "is_bot": false,
+                            "first_name": "User",
+                        },
+                    })
+                }
+            }
+            _ => Value::Bool(true),
+        }
+    }
+}
+
+fn bot_user_json() -> Value {
+    json!({
+        "id": BOT_ID,
+        "is_bot": true,
+        "first_name": "Botka",
+        "username": BOT_USERNAME,
+    })
+}
+
+/// The bot as an administrator with all rights.
+fn bot_admin_json() -> Value {
+    json!({
+        "status": "administrator",
+        "user": bot_user_json(),
+        "can_be_edited": false,
+        "is_anonymous": false,
+        "can_manage_chat": true,
+        "can_change_info": true,
+        "can_delete_messages": true,
+        "can_manage_video_chats": true,
+        "can_invite_users": true,
+        "can_restrict_members": true,
+        "can_pin_messages": true,
+        "can_manage_topics": true,
+        "can_promote_members": false,
+    })
+}
+
+pub fn me_json() -> Value {
+    let mut me = bot_user_json();
+    me["can_join_groups"] = true.into();
+    me["can_read_all_group_messages"] = true.into();
+    me["supports_inline_queries"] = false.into();
+    me
+}
+
+pub fn chat_json(id: i64) -> Value {
+    if id > 0 {
+        json!({ "id": id, "type": "private", "first_name": "User" })
+    } else {
+        json!({
+            "id": id,
+            "type": "supergroup",
+            "title": "Test chat",
+            "is_forum": true,
+        })
+    }
+}
+
+/// A message without content; set `text`, `poll`, etc. on the result.
+pub fn message_json(
+    chat_id: i64,
+    message_id: i32,
+    thread_id: Option<i64>,
+    from: &Value,
+) -> Value {
+    let mut msg = json!({
+        "message_id": message_id,
+        "date": 0,
+        "chat": chat_json(chat_id),
+        "from": from,
+        "text": "",
+    });
+    if let Some(thread_id) = thread_id {
+        msg["message_thread_id"] = thread_id.into();
+        msg["is_topic_message"] = true.into();
+    }
+    msg
+}
+
+pub fn poll_json(id: &str, question: &str, options: &[&str]) -> Value {
+    json!({
+        "id": id,
+        "question": question,
+        "options": options
+            .iter()
+            .map(|o| json!({ "text": o, "voter_count": 0 }))
+            .collect_vec(),
+        "total_voter_count": 0,
+        "is_closed": false,
+        "is_anonymous": false,
+        "type": "regular",
+        "allows_multiple_answers": false,
+    })
+}
@@ -5,12 +5,13 @@ use std::fmt::Debug;
 use diesel::prelude::*;
 use salvo_oapi::ToSchema;
 use serde::{Deserialize, Serialize};
-use teloxide::types::{ChatMember, MessageId, UserId};
+use teloxide::types::{ChatId, ChatMember, Message, MessageId, ThreadId, UserId};
 
 use crate::db::{
-    config_option_def, DbChatId, DbMessageId, DbThreadId, DbUserId,
+    config_option_def, feature_flag_def, user_preference_def, AnyConfigOption,
+    DbChatId, DbMessageId, DbThreadId, DbUserId, FeatureFlagDef,
 };
-use crate::utils::{Sqlizer, ThreadIdPair};
+use crate::utils::{sqlizer_type, Sqlizer, ThreadIdPair};
 
 // Database models
 
@@ -32,6 +33,17 @@ pub struct NewTgUser<'a> {
     pub last_name: Option<&'a str>,
 }
 
+/// A previous name of a user in [`TgUser`].
+#[derive(Clone, Debug, Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::tg_user_names)]
+pub struct TgUserName {
+    pub user_id: DbUserId,
+    pub username: Option<String>,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub replaced_at: chrono::NaiveDateTime,
+}
+
 #[derive(Clone, Debug, Queryable, Selectable)]
 #[diesel(table_name = crate::schema::tg_chats)]
 pub struct TgChat {
@@ -84,11 +96,32 @@ pub struct Resident {
     pub end_date: Option<chrono::NaiveDateTime>,
 }
 
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::role_changes)]
+pub struct NewRoleChange<'a> {
+    pub tg_id: DbUserId,
+    pub old_role: &'a str,
+    pub new_role: &'a str,
+    pub changed_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::role_changes)]
+pub struct RoleChange {
+    pub rowid: i32,
+    pub tg_id: DbUserId,
+    pub old_role: String,
+    pub new_role: String,
+    pub changed_at: chrono::NaiveDateTime,
+    pub handled_at: Option<chrono::NaiveDateTime>,
+}
+
 #[derive(Insertable, Queryable, Selectable)]
 #[diesel(table_name = crate::schema::user_macs)]
 pub struct UserMac {
     pub tg_id: DbUserId,
-    pub mac: Sqlizer<macaddr::MacAddr6>,
+    /// Uppercase, as formatted by [`macaddr::MacAddr6`].
+    pub mac: String,
 }
 
 #[derive(Clone, Debug, Insertable, Queryable, Selectable)]
@@ -99,6 +132,44 @@ pub struct TrackedPoll {
     pub info_chat_id: DbChatId,
     pub info_message_id: DbMessageId,
     pub voted_users: Sqlizer<Vec<DbUserId>>,
+    pub poll_message_id: Option<DbMessageId>,
+    pub closed_at: Option<chrono::NaiveDateTime>,
+    /// Option texts, `None` for polls tracked before they were stored.
+    pub options: Option<Sqlizer<Vec<String>>>,
+    /// When the poll reached the quorum of `telegram.poll_webhooks`.
+    pub quorum_reached_at: Option<chrono::NaiveDateTime>,
+    /// Whether the info message shows only counts of voters.
+    pub private: bool,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::poll_announcements)]
+pub struct PollAnnouncement {
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub tg_poll_id: String,
+    pub question: String,
+    pub poll_url: String,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::tracked_poll_votes)]
+pub struct NewTrackedPollVote<'a> {
+    pub tg_poll_id: &'a str,
+    pub user_id: DbUserId,
+    /// Empty if the vote was retracted.
+    pub option_ids: Sqlizer<Vec<usize>>,
+    pub voted_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::tracked_poll_votes)]
+pub struct TrackedPollVote {
+    pub rowid: i32,
+    pub tg_poll_id: String,
+    pub user_id: DbUserId,
+    pub option_ids: Sqlizer<Vec<usize>>,
+    pub voted_at: chrono::NaiveDateTime,
 }
 
 #[derive(Insertable, Queryable, Selectable)]
@@ -108,6 +179,44 @@ pub struct ConfigOption {
     pub value: String,
 }
 
+#[derive(Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::user_preferences)]
+pub struct UserPreference {
+    pub user_id: DbUserId,
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::feature_flags)]
+pub struct FeatureFlag {
+    pub name: String,
+    pub chat_id: DbChatId,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::backup_media)]
+pub struct BackupMedia {
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub file_unique_id: String,
+    pub file_name: Option<String>,
+    pub mime_type: Option<String>,
+    pub size: i64,
+    pub storage_key: String,
+    pub stored_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::backup_messages)]
+pub struct BackupMessage {
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub backup_chat_id: DbChatId,
+    pub backup_message_id: DbMessageId,
+}
+
 #[derive(Clone, Debug, Insertable, Queryable, Selectable)]
 #[diesel(table_name = crate::schema::borrowed_items)]
 pub struct BorrowedItems {
@@ -117,6 +226,7 @@ pub struct BorrowedItems {
     pub bot_message_id: DbMessageId,
     pub user_id: DbUserId,
     pub items: Sqlizer<Vec<BorrowedItem>>,
+    pub created_at: Option<chrono::NaiveDateTime>,
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BorrowedItem {
@@ -134,6 +244,7 @@ pub struct NewNeededItem<'a> {
     pub pinned_message_id: DbMessageId,
     pub buyer_user_id: Option<DbUserId>,
     pub item: &'a str,
+    pub created_at: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Clone, Debug, Queryable, Selectable)]
@@ -147,6 +258,8 @@ pub struct NeededItem {
     pub pinned_message_id: DbMessageId,
     pub buyer_user_id: Option<DbUserId>,
     pub item: String,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub bought_at: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Clone, Debug, Insertable)]
@@ -158,6 +271,672 @@ pub struct NewDashboardMessage<'a> {
     pub text: &'a str,
 }
 
+#[derive(Clone, Debug, Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::mail_messages)]
+pub struct MailMessage {
+    pub message_id: String,
+    pub inbound: bool,
+    pub chat_id: DbChatId,
+    pub tg_message_id: DbMessageId,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::presence_log)]
+pub struct PresenceLogEntry {
+    pub timestamp: chrono::NaiveDateTime,
+    pub person_count: i32,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::audit_log)]
+pub struct NewAuditLogEntry<'a> {
+    pub timestamp: chrono::NaiveDateTime,
+    pub actor_id: Option<DbUserId>,
+    pub action: &'a str,
+    pub payload: &'a str,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::audit_log)]
+pub struct AuditLogEntry {
+    pub rowid: i32,
+    pub timestamp: chrono::NaiveDateTime,
+    pub actor_id: Option<DbUserId>,
+    pub action: String,
+    /// JSON-encoded action details.
+    pub payload: String,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::activity_feed)]
+pub struct NewActivityEntry<'a> {
+    pub timestamp: chrono::NaiveDateTime,
+    pub module: &'a str,
+    pub actor_id: Option<DbUserId>,
+    pub verb: &'a str,
+    pub object: &'a str,
+    pub url: Option<&'a str>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::activity_feed)]
+pub struct ActivityEntry {
+    pub rowid: i32,
+    pub timestamp: chrono::NaiveDateTime,
+    /// Name of the module that recorded the entry, e.g. `needs`.
+    pub module: String,
+    /// `None` for actions of the bot itself.
+    pub actor_id: Option<DbUserId>,
+    pub verb: String,
+    pub object: String,
+    /// Link to the related message, if any.
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::outbox)]
+pub struct NewOutboxEntry {
+    pub action: Sqlizer<OutboxAction>,
+    pub compensation: Sqlizer<Vec<OutboxAction>>,
+    pub created_at: chrono::NaiveDateTime,
+    pub next_attempt_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::outbox)]
+pub struct OutboxEntry {
+    pub rowid: i32,
+    pub action: Sqlizer<OutboxAction>,
+    pub compensation: Sqlizer<Vec<OutboxAction>>,
+    pub created_at: chrono::NaiveDateTime,
+    pub attempts: i32,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    pub last_error: Option<String>,
+    pub failed_at: Option<chrono::NaiveDateTime>,
+}
+
+/// A Telegram API call performed by [`crate::outbox`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutboxAction {
+    DeleteMessage { chat_id: ChatId, message_id: MessageId },
+    PinMessage { chat_id: ChatId, message_id: MessageId },
+    UnpinMessage { chat_id: ChatId, message_id: MessageId },
+    /// Send a `/dm` broadcast to the user, see [`crate::modules::broadcasts`].
+    SendBroadcast { broadcast_id: i32, user_id: UserId },
+    /// Mark the delivery of a broadcast as failed.  No API call.
+    FailBroadcast { broadcast_id: i32, user_id: UserId },
+    /// Send a plain poll, not tracked by [`crate::modules::polls`].
+    SendPoll {
+        chat_id: ChatId,
+        thread_id: Option<ThreadId>,
+        question: String,
+        options: Vec<String>,
+        is_anonymous: bool,
+        allows_multiple_answers: bool,
+    },
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::undo_stack)]
+pub struct NewUndoEntry<'a> {
+    pub chat_id: DbChatId,
+    pub user_id: DbUserId,
+    pub description: &'a str,
+    pub actions: Sqlizer<Vec<UndoAction>>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::undo_stack)]
+pub struct UndoEntry {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub user_id: DbUserId,
+    pub description: String,
+    pub actions: Sqlizer<Vec<UndoAction>>,
+    pub created_at: chrono::NaiveDateTime,
+    pub undone_at: Option<chrono::NaiveDateTime>,
+}
+
+/// A compensating action performed by `/undo`, see [`crate::modules::undo`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UndoAction {
+    /// Perform the Telegram API call via [`crate::outbox`].
+    Telegram { action: OutboxAction },
+    /// Stop tracking the poll.
+    ClosePoll { poll_id: String },
+    /// Remove links archived from the message from `/search`.
+    ForgetArchivedLinks { chat_id: ChatId, message_id: MessageId },
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::pending_approvals)]
+pub struct NewPendingApproval {
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub requester_id: DbUserId,
+    pub message: Sqlizer<Message>,
+    pub created_at: chrono::NaiveDateTime,
+    pub executed: bool,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::pending_approvals)]
+pub struct PendingApproval {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub requester_id: DbUserId,
+    pub message: Sqlizer<Message>,
+    pub created_at: chrono::NaiveDateTime,
+    pub approver_id: Option<DbUserId>,
+    pub executed: bool,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::ballots)]
+pub struct NewBallot<'a> {
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub creator_id: DbUserId,
+    pub question: &'a str,
+    pub options: Sqlizer<Vec<String>>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::ballots)]
+pub struct Ballot {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub creator_id: DbUserId,
+    pub question: String,
+    pub options: Sqlizer<Vec<String>>,
+    pub created_at: chrono::NaiveDateTime,
+    pub closed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::reimbursements)]
+pub struct NewReimbursement<'a> {
+    pub requester_id: DbUserId,
+    pub amount_cents: i64,
+    pub description: &'a str,
+    pub receipt_file_id: Option<&'a str>,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub status: &'a str,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::reimbursements)]
+pub struct Reimbursement {
+    pub rowid: i32,
+    pub requester_id: DbUserId,
+    pub amount_cents: i64,
+    pub description: String,
+    pub receipt_file_id: Option<String>,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub status: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub decided_at: Option<chrono::NaiveDateTime>,
+    pub decided_by: Option<DbUserId>,
+    pub paid_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::donations)]
+pub struct NewDonation<'a> {
+    pub donor: &'a str,
+    pub amount_cents: i64,
+    pub private: bool,
+    pub recorded_by: DbUserId,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::proposals)]
+pub struct NewProposal<'a> {
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub creator_id: DbUserId,
+    pub question: &'a str,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::proposals)]
+pub struct Proposal {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub creator_id: DbUserId,
+    pub question: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub started_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::ranked_votes)]
+pub struct NewRankedVote<'a> {
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub creator_id: DbUserId,
+    pub question: &'a str,
+    pub options: Sqlizer<Vec<String>>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::ranked_votes)]
+pub struct RankedVote {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub creator_id: DbUserId,
+    pub question: String,
+    pub options: Sqlizer<Vec<String>>,
+    pub created_at: chrono::NaiveDateTime,
+    pub closed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::ranked_ballots)]
+pub struct RankedBallot {
+    pub vote_id: i32,
+    pub user_id: DbUserId,
+    pub ranking: Sqlizer<Vec<usize>>,
+    pub submitted: bool,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::action_items)]
+pub struct ActionItem {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub thread_id: Option<DbThreadId>,
+    pub message_id: Option<DbMessageId>,
+    pub created_by: DbUserId,
+    pub assignee_id: DbUserId,
+    pub text: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub due_at: Option<chrono::NaiveDateTime>,
+    pub reminded: bool,
+    pub done_at: Option<chrono::NaiveDateTime>,
+    pub done_by: Option<DbUserId>,
+    pub meeting_id: Option<i32>,
+    pub decision_id: Option<i32>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::archived_links)]
+pub struct ArchivedLink {
+    pub rowid: i32,
+    pub url: String,
+    pub archive_url: String,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub archived_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::bookings)]
+pub struct Booking {
+    pub rowid: i32,
+    pub resource: String,
+    pub user_id: DbUserId,
+    pub start_at: chrono::NaiveDateTime,
+    pub end_at: chrono::NaiveDateTime,
+    pub reminded: bool,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = crate::schema::broadcasts)]
+pub struct NewBroadcast<'a> {
+    pub created_by: DbUserId,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub audience: &'a str,
+    pub text: &'a str,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::countdowns)]
+pub struct Countdown {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub title: String,
+    pub target_at: chrono::NaiveDateTime,
+    pub created_by: DbUserId,
+    pub text: String,
+    pub finished: bool,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::chores)]
+pub struct Chore {
+    pub rowid: i32,
+    pub name: String,
+    pub due_at: chrono::NaiveDateTime,
+    pub stage: i32,
+    pub last_done_at: Option<chrono::NaiveDateTime>,
+    pub last_done_by: Option<DbUserId>,
+    pub chat_id: Option<DbChatId>,
+    pub message_id: Option<DbMessageId>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::door_credentials)]
+pub struct DoorCredential {
+    pub rowid: i32,
+    pub user_id: DbUserId,
+    pub kind: String,
+    pub code: String,
+    pub provisioned_by: DbUserId,
+    pub created_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    pub revoked_by: Option<DbUserId>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::duty_assignments)]
+pub struct DutyAssignment {
+    pub rowid: i32,
+    pub week: String,
+    pub duty: String,
+    pub user_id: DbUserId,
+    pub status: String,
+    pub proof_file_id: Option<String>,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::faq_entries)]
+pub struct FaqEntry {
+    pub rowid: i32,
+    pub pattern: String,
+    pub is_regex: bool,
+    pub answer: String,
+    pub uses: i32,
+    pub created_by: DbUserId,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::feeds)]
+pub struct Feed {
+    pub rowid: i32,
+    pub url: String,
+    pub chat_id: DbChatId,
+    pub thread_id: DbThreadId,
+    pub include: String,
+    pub exclude: String,
+    pub added_by: DbUserId,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::fridge_items)]
+pub struct FridgeItem {
+    pub rowid: i32,
+    pub owner: DbUserId,
+    pub name: String,
+    pub expires_on: chrono::NaiveDate,
+    pub status: String,
+    pub added_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::frontdesk_inquiries)]
+pub struct FrontdeskInquiry {
+    pub rowid: i32,
+    pub visitor_id: DbUserId,
+    pub asked_at: chrono::NaiveDateTime,
+    pub answered_at: Option<chrono::NaiveDateTime>,
+    pub answered_by: Option<DbUserId>,
+    pub pinged_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::frontdesk_threads)]
+pub struct FrontdeskThread {
+    pub visitor_id: DbUserId,
+    pub chat_id: DbChatId,
+    pub thread_id: DbThreadId,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::guest_vouchers)]
+pub struct GuestVoucher {
+    pub rowid: i32,
+    pub issued_by: DbUserId,
+    pub username: String,
+    pub router_id: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub removed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::incidents)]
+pub struct Incident {
+    pub rowid: i32,
+    pub reporter: DbUserId,
+    pub kind: String,
+    pub description: String,
+    pub status: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::inventory_intakes)]
+pub struct InventoryIntake {
+    pub rowid: i32,
+    pub user_id: DbUserId,
+    pub items: Sqlizer<Vec<IntakeItem>>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IntakeItem {
+    pub name: String,
+    pub quantity: i32,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::inventory_items)]
+pub struct InventoryItem {
+    pub rowid: i32,
+    pub name: String,
+    pub quantity: i32,
+    pub added_by: DbUserId,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::invite_links)]
+pub struct InviteLink {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub invite_link: String,
+    pub created_by: DbUserId,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub member_limit: Option<i32>,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::meetings)]
+pub struct Meeting {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub thread_id: DbThreadId,
+    pub started_by: DbUserId,
+    pub started_at: chrono::NaiveDateTime,
+    pub ended_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::meeting_notes)]
+pub struct MeetingNote {
+    pub rowid: i32,
+    pub meeting_id: i32,
+    pub kind: String,
+    pub user_id: DbUserId,
+    pub text: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub message_id: Option<DbMessageId>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::message_ttl_policies)]
+pub struct MessageTtlPolicy {
+    pub chat_id: DbChatId,
+    pub thread_id: DbThreadId,
+    pub ttl_seconds: i64,
+    pub include_others: bool,
+    pub set_by: DbUserId,
+    pub set_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::moderation_cases)]
+pub struct ModerationCase {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub message_id: DbMessageId,
+    pub user_id: DbUserId,
+    pub text: String,
+    pub reason: String,
+    pub status: String,
+    pub moderator: Option<DbUserId>,
+    pub appeal: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub resolved_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::network_devices)]
+pub struct NetworkDevice {
+    pub mac: String,
+    pub host_name: Option<String>,
+    pub address: Option<String>,
+    pub dhcp_server: Option<String>,
+    pub first_seen: chrono::NaiveDateTime,
+    pub last_seen: chrono::NaiveDateTime,
+    pub infrastructure: bool,
+    pub alert_message_id: Option<DbMessageId>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::packages)]
+pub struct Package {
+    pub rowid: i32,
+    pub recipient: DbUserId,
+    pub description: String,
+    pub accepted_by: DbUserId,
+    pub accepted_at: chrono::NaiveDateTime,
+    pub picked_up_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::projects)]
+pub struct Project {
+    pub rowid: i32,
+    pub name: String,
+    pub status: String,
+    pub owner_id: DbUserId,
+    pub chat_id: Option<DbChatId>,
+    pub thread_id: Option<DbThreadId>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    pub nudged_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable, Serialize, ToSchema)]
+#[diesel(table_name = crate::schema::service_status)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub up: bool,
+    /// When the service went up or down.
+    pub since: chrono::NaiveDateTime,
+    pub checked_at: chrono::NaiveDateTime,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::spam_suspects)]
+pub struct SpamSuspect {
+    pub rowid: i32,
+    pub chat_id: DbChatId,
+    pub user_id: DbUserId,
+    pub score: i32,
+    pub reasons: String,
+    pub status: String,
+    pub reviewed_by: Option<DbUserId>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::member_intros)]
+pub struct MemberIntro {
+    pub user_id: DbUserId,
+    pub skills: String,
+    pub interests: String,
+    pub pronouns: String,
+    pub updated_at: chrono::NaiveDateTime,
+    pub chat_id: Option<DbChatId>,
+    pub message_id: Option<DbMessageId>,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::user_roles)]
+pub struct UserRole {
+    pub user_id: DbUserId,
+    pub role: String,
+    pub granted_by: DbUserId,
+    pub granted_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::vpn_peers)]
+pub struct VpnPeer {
+    pub rowid: i32,
+    pub user_id: DbUserId,
+    pub address: String,
+    pub public_key: String,
+    pub router_id: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+// Types stored with `Sqlizer`.  Tags must be unique; bump the version and
+// implement `SqlizerType::migrate` when the serialized form changes.
+
+sqlizer_type!(ChatMember, "chat_member", 1);
+sqlizer_type!(Message, "message", 1);
+sqlizer_type!(Vec<DbUserId>, "user_ids", 1);
+sqlizer_type!(Vec<BorrowedItem>, "borrowed_items", 1);
+sqlizer_type!(Vec<IntakeItem>, "intake_items", 1);
+sqlizer_type!(OutboxAction, "outbox_action", 1);
+sqlizer_type!(Vec<OutboxAction>, "outbox_actions", 1);
+sqlizer_type!(Vec<UndoAction>, "undo_actions", 1);
+sqlizer_type!(Vec<String>, "strings", 1);
+sqlizer_type!(Vec<usize>, "indices", 1);
+
 // Database option models
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
@@ -169,8 +948,104 @@ pub struct NeedsLastPin {
 config_option_def!(wikijs_update_state, crate::utils::WikiJsUpdateState);
 config_option_def!(needs_last_pin, NeedsLastPin);
 
+/// State of the shopping list page synced by `needs_wiki`.  Contents are
+/// stored as SHA-256 hex digests.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NeedsWikiSync {
+    /// Content written by the last sync.
+    pub written: String,
+    /// Content of the manual edit that was already reported.
+    pub reported: Option<String>,
+}
+config_option_def!(needs_wiki_sync, NeedsWikiSync);
+// Last month (`YYYY-MM`) for which donors were thanked.
+config_option_def!(donations_last_thank_you, String);
+// Residents missing from the external source, as last reported by
+// `resident_sync`.
+config_option_def!(resident_sync_conflicts, Vec<UserId>);
+// Last date (`YYYY-MM-DD`) of the membership reconciliation run.
+config_option_def!(membership_reconciliation_last_run, String);
+// Chat members promoted to admins by `chat_admins`.
+config_option_def!(auto_admins_promoted, Vec<(ChatId, UserId)>);
+// Pinned `/projects` overview message in the projects thread.
+config_option_def!(projects_overview, MessageId);
+// Last date (`YYYY-MM-DD`, local time) of the posted booking schedule.
+config_option_def!(bookings_last_schedule, String);
+// Last date (`YYYY-MM-DD`) of the fridge expiry sweep.
+config_option_def!(fridge_last_sweep, String);
+// Last date (`YYYY-MM-DD`) of the weekly unclaimed packages reminder.
+config_option_def!(packages_last_reminder, String);
+// Last quarter (`YYYY-QN`) summarized by the `incidents` module.
+config_option_def!(incidents_last_summary, String);
+// Last date (`YYYY-MM-DD`) of the weekly energy report.
+config_option_def!(energy_last_report, String);
+// Last version announced to the admin thread by the `version` module.
+config_option_def!(version_last_announced, String);
+// Last date (`YYYY-MM-DD`) of the weekly frontdesk report.
+config_option_def!(frontdesk_last_report, String);
+
+/// Options available to the `/option` command.
+pub const CONFIG_OPTIONS: &[&dyn AnyConfigOption] = &[
+    &wikijs_update_state,
+    &needs_last_pin,
+    &needs_wiki_sync,
+    &donations_last_thank_you,
+    &resident_sync_conflicts,
+    &membership_reconciliation_last_run,
+    &auto_admins_promoted,
+    &projects_overview,
+    &bookings_last_schedule,
+    &fridge_last_sweep,
+    &packages_last_reminder,
+    &incidents_last_summary,
+    &energy_last_report,
+    &version_last_announced,
+    &frontdesk_last_report,
+];
+
+// User preferences, managed with `/settings`
+
+// Booking reminders from the `bookings` module.
+user_preference_def!(booking_reminders, bool, true);
+// Status updates of own incident reports from the `incidents` module.
+user_preference_def!(incident_updates, bool, true);
+// Notifications about followed tags from the `follows` module.
+user_preference_def!(follow_notifications, bool, true);
+// Time zone of times in private messages, from the `timezones` module.
+user_preference_def!(timezone, Option<chrono_tz::Tz>, None);
+// Messages from admins sent with `/dm` by the `broadcasts` module.
+user_preference_def!(broadcasts, bool, true);
+
+// Feature flags, toggled per chat with `/flags`
+
+feature_flag_def!(
+    borrowed_items_openai,
+    true,
+    "recognize borrowed items with OpenAI"
+);
+feature_flag_def!(link_archive, true, "archive links posted to the chat");
+feature_flag_def!(
+    mention_groups,
+    false,
+    "expand mentions of groups like @keyholders"
+);
+feature_flag_def!(moderation, true, "check messages with moderation rules");
+
+/// Flags available to the `/flags` command.
+pub const FEATURE_FLAGS: &[&FeatureFlagDef] =
+    &[&borrowed_items_openai, &link_archive, &mention_groups, &moderation];
+
 // Serde models
 
+#[derive(Serialize, Debug, Default, ToSchema)]
+pub struct DonationTotals {
+    pub currency: Option<String>,
+    pub this_month_cents: i64,
+    pub this_year_cents: i64,
+    pub total_cents: i64,
+    pub donors_this_month: usize,
+}
+
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct DataResident {
     #[salvo(schema(value_type = DbUserId))]
@@ -1,14 +1,146 @@
 //! Modules that define the bot's functionality.
 
+use crate::common::CommandInfo;
+
+pub mod action_items;
+pub mod activity_feed;
+pub mod approvals;
+pub mod ask;
+pub mod audit;
+pub mod backup;
+pub mod ballots;
+pub mod bandwidth;
 pub mod basic;
+pub mod bookings;
 pub mod borrowed_items;
+pub mod broadcasts;
+pub mod chat_admins;
+pub mod chores;
+pub mod command_scopes;
+pub mod countdowns;
 pub mod dashboard;
+pub mod donations;
+pub mod door_access;
+pub mod energy;
+pub mod export;
+pub mod faq;
+pub mod feature_flags;
+pub mod feeds;
+pub mod follows;
 pub mod forward_topic_pins;
+pub mod fridge;
+pub mod frontdesk;
+pub mod guest_wifi;
+pub mod help;
+pub mod incidents;
+pub mod intros;
+pub mod inventory;
+pub mod invite_links;
+pub mod join_requests;
+pub mod karma;
+pub mod link_archive;
+pub mod mail_bridge;
+pub mod matrix_bridge;
+pub mod meetings;
+pub mod membership_reconciliation;
+pub mod mention_groups;
+pub mod message_ttl;
+pub mod moderation;
+pub mod monitor;
+pub mod mqtt;
 pub mod needs;
+pub mod needs_wiki;
+pub mod network_devices;
+pub mod options;
+pub mod packages;
+pub mod poll_announcements;
+pub mod poll_webhooks;
 pub mod polls;
+pub mod presence;
+pub mod print_sheet;
+pub mod projects;
+pub mod proposals;
+pub mod ranked_votes;
+pub mod reimbursements;
 pub mod rename_closed_topics;
+pub mod resident_sync;
 pub mod resident_tracker;
+pub mod retention;
+pub mod role_changes;
+pub mod roles;
+pub mod rotation;
+pub mod settings;
+pub mod spam_protection;
 pub mod tg_scraper;
+pub mod tg_users_refresh;
+pub mod timezones;
+pub mod undo;
+pub mod update_dedup;
 pub mod updates;
 pub mod userctl;
+pub mod version;
+pub mod vpn;
+pub mod web_login;
 pub mod welcome;
+
+/// Metadata of the commands of all modules, in the order shown by `/help`.
+pub fn commands() -> Vec<CommandInfo> {
+    [
+        CommandInfo::of::<action_items::Commands>(),
+        CommandInfo::of::<activity_feed::Commands>(),
+        CommandInfo::of::<ask::Commands>(),
+        CommandInfo::of::<audit::Commands>(),
+        CommandInfo::of::<backup::Commands>(),
+        CommandInfo::of::<ballots::Commands>(),
+        CommandInfo::of::<bandwidth::Commands>(),
+        CommandInfo::of::<basic::Commands>(),
+        CommandInfo::of::<bookings::Commands>(),
+        CommandInfo::of::<broadcasts::Commands>(),
+        CommandInfo::of::<chores::Commands>(),
+        CommandInfo::of::<countdowns::Commands>(),
+        CommandInfo::of::<dashboard::Commands>(),
+        CommandInfo::of::<donations::Commands>(),
+        CommandInfo::of::<door_access::Commands>(),
+        CommandInfo::of::<energy::Commands>(),
+        CommandInfo::of::<faq::Commands>(),
+        CommandInfo::of::<feature_flags::Commands>(),
+        CommandInfo::of::<feeds::Commands>(),
+        CommandInfo::of::<follows::Commands>(),
+        CommandInfo::of::<fridge::Commands>(),
+        CommandInfo::of::<guest_wifi::Commands>(),
+        CommandInfo::of::<help::Commands>(),
+        CommandInfo::of::<incidents::Commands>(),
+        CommandInfo::of::<intros::Commands>(),
+        CommandInfo::of::<inventory::Commands>(),
+        CommandInfo::of::<invite_links::Commands>(),
+        CommandInfo::of::<karma::Commands>(),
+        CommandInfo::of::<link_archive::Commands>(),
+        CommandInfo::of::<meetings::Commands>(),
+        CommandInfo::of::<mention_groups::Commands>(),
+        CommandInfo::of::<message_ttl::Commands>(),
+        CommandInfo::of::<moderation::Commands>(),
+        CommandInfo::of::<needs::Commands>(),
+        CommandInfo::of::<needs_wiki::Commands>(),
+        CommandInfo::of::<options::Commands>(),
+        CommandInfo::of::<packages::Commands>(),
+        CommandInfo::of::<polls::Commands>(),
+        CommandInfo::of::<print_sheet::Commands>(),
+        CommandInfo::of::<projects::Commands>(),
+        CommandInfo::of::<proposals::Commands>(),
+        CommandInfo::of::<ranked_votes::Commands>(),
+        CommandInfo::of::<reimbursements::Commands>(),
+        CommandInfo::of::<retention::Commands>(),
+        CommandInfo::of::<roles::Commands>(),
+        CommandInfo::of::<rotation::Commands>(),
+        CommandInfo::of::<settings::Commands>(),
+        CommandInfo::of::<timezones::Commands>(),
+        CommandInfo::of::<undo::Commands>(),
+        CommandInfo::of::<userctl::Commands>(),
+        CommandInfo::of::<version::Commands>(),
+        CommandInfo::of::<vpn::Commands>(),
+        CommandInfo::of::<web_login::Commands>(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
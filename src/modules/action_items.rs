@@ -0,0 +1,559 @@
+//! Action items: `/action @user <text> [due <date>]` assigns a task to a
+//! user, e.g. `/action @bob order filament due 2026-10-20`.
+//!
+//! The item is posted with a button to mark it done, available to the
+//! assignee, the creator and admins.  The assignee is reminded in the chat a
+//! day before the due date.  Items created during a meeting of the `meetings`
+//! module are linked to it and listed in its minutes; replying with `/action`
+//! to a `/decision` message also links the item to the decision.  `/actions`
+//! lists open items of the user, `/actions all` lists open items of the
+//! chat.
+//!
+//! **Scope**: `/action` and `/actions` commands, available to residents.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode, ThreadId,
+};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_user, resolve_user, BotCommandsExt, BotEnv,
+    UpdateHandler,
+};
+use crate::db::{DbChatId, DbMessageId, DbThreadId, DbUserId};
+use crate::modules::{meetings, timezones};
+use crate::utils::{
+    format_to, html, parse_duration, BotExt as _, ChatIdExt as _,
+    ResultExt as _,
+};
+use crate::{models, schema};
+
+/// How long before the due date the assignee is reminded.
+const REMIND_BEFORE_HOURS: i64 = 24;
+
+/// Local time an item is due at when only the date is given.
+const DEFAULT_DUE_HOUR: u32 = 18;
+
+/// The maximum number of items listed by `/actions`.
+const LIST_LIMIT: usize = 30;
+
+const USAGE: &str = "Usage: /action @user <text> [due YYYY-MM-DD [HH:MM]]\n\
+                     The due date can also be a duration, e.g. due 3d.";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "assign an action item: <code>/action @user \
+                             text [due YYYY-MM-DD]</code>.")]
+    #[custom(resident = true)]
+    Action(String),
+    #[command(description = "list open action items: <code>/actions \
+                             [mine|all]</code>.")]
+    #[custom(resident = true)]
+    Actions(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(handle_command)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+async fn handle_command(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    command: Commands,
+) -> Result<()> {
+    match command {
+        Commands::Action(args) => cmd_action(bot, env, msg, &args).await,
+        Commands::Actions(args) => cmd_actions(bot, env, msg, &args).await,
+    }
+}
+
+/// Split `text due <when>` into the text and `<when>`.
+fn split_due(args: &str) -> (&str, Option<&str>) {
+    let args = args.trim();
+    if let Some(when) = args.strip_prefix("due ") {
+        return ("", Some(when.trim()));
+    }
+    match args.rsplit_once(" due ") {
+        Some((text, when)) => (text.trim(), Some(when.trim())),
+        None => (args, None),
+    }
+}
+
+/// Parse the due date, `YYYY-MM-DD [HH:MM]` or a duration like `3d`, as the
+/// local time.
+fn parse_due(when: &str, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    if let Ok(time) = NaiveDateTime::parse_from_str(when, "%Y-%m-%d %H:%M") {
+        return Some(time);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(when, "%Y-%m-%d") {
+        return date.and_hms_opt(DEFAULT_DUE_HOUR, 0, 0);
+    }
+    let duration = chrono::Duration::from_std(parse_duration(when)?).ok()?;
+    now.checked_add_signed(duration)
+}
+
+async fn cmd_action(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    args: &str,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let args = args.trim();
+    let (user, rest) =
+        args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let Some(assignee) = resolve_user(&bot, &env, &msg, user).await? else {
+        bot.reply_message(&msg, USAGE).await?;
+        return Ok(());
+    };
+    let (text, when) = split_due(rest);
+    let Some(text) = meetings::note_text(&msg, text) else {
+        bot.reply_message(&msg, USAGE).await?;
+        return Ok(());
+    };
+
+    // Due dates are entered and shown in the time zone of the chat.
+    let tz = timezones::chat_tz(&env, &mut env.conn(), msg.chat.id)?;
+    let now = chrono::Utc::now().naive_utc();
+    let due_at = match when {
+        None => None,
+        Some(when) => {
+            let Some(due) = parse_due(when, timezones::now(tz)) else {
+                bot.reply_message(&msg, USAGE).await?;
+                return Ok(());
+            };
+            let due = timezones::to_utc(tz, due);
+            if due <= now {
+                bot.reply_message(&msg, "The due date is in the past.").await?;
+                return Ok(());
+            }
+            Some(due)
+        }
+    };
+
+    let chat = DbChatId::from(msg.chat.id);
+    let remind_before = chrono::Duration::hours(REMIND_BEFORE_HOURS);
+    let rowid = env.transaction(|conn| {
+        let meeting = meetings::active(conn, chat, msg.thread_id.into())?;
+        let decision = match (&meeting, msg.reply_to_message()) {
+            (Some(meeting), Some(reply)) => {
+                meetings::decision_of_message(conn, meeting.rowid, reply.id)?
+            }
+            _ => None,
+        };
+        diesel::insert_into(schema::action_items::table)
+            .values((
+                schema::action_items::chat_id.eq(chat),
+                schema::action_items::thread_id
+                    .eq(msg.thread_id.map(DbThreadId::from)),
+                schema::action_items::created_by.eq(DbUserId::from(from.id)),
+                schema::action_items::assignee_id.eq(DbUserId::from(assignee)),
+                schema::action_items::text.eq(&text),
+                schema::action_items::created_at.eq(now),
+                schema::action_items::due_at.eq(due_at),
+                // The assignee is notified by the message below, so items
+                // due soon are not reminded of again.
+                schema::action_items::reminded
+                    .eq(due_at.is_some_and(|due| due <= now + remind_before)),
+                schema::action_items::meeting_id.eq(meeting.map(|m| m.rowid)),
+                schema::action_items::decision_id.eq(decision),
+            ))
+            .execute(conn)?;
+        schema::action_items::table
+            .select(schema::action_items::rowid)
+            .order(schema::action_items::rowid.desc())
+            .first::<i32>(conn)
+    })?;
+
+    let item = load_item(&mut env.conn(), rowid)?;
+    let (item, user) = item.expect("the item was just inserted");
+    let sent = bot
+        .reply_message(&msg, item_text(&item, user.as_ref(), tz))
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(done_keyboard(rowid))
+        .await?;
+    diesel::update(schema::action_items::table)
+        .filter(schema::action_items::rowid.eq(rowid))
+        .set(schema::action_items::message_id.eq(DbMessageId::from(sent.id)))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+async fn cmd_actions(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    args: &str,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let all = match args.trim() {
+        "" | "mine" => false,
+        "all" => true,
+        _ => {
+            bot.reply_message(&msg, "Usage: /actions [mine|all]").await?;
+            return Ok(());
+        }
+    };
+
+    let mut query = schema::action_items::table
+        .left_join(
+            schema::tg_users::table
+                .on(schema::tg_users::id.eq(schema::action_items::assignee_id)),
+        )
+        .filter(schema::action_items::done_at.is_null())
+        .select((
+            models::ActionItem::as_select(),
+            schema::tg_users::all_columns.nullable(),
+        ))
+        .into_boxed();
+    if all {
+        // In private chats, all open items.
+        if !msg.chat.is_private() {
+            query = query.filter(
+                schema::action_items::chat_id.eq(DbChatId::from(msg.chat.id)),
+            );
+        }
+    } else {
+        query = query.filter(
+            schema::action_items::assignee_id.eq(DbUserId::from(from.id)),
+        );
+    }
+    let mut items: Vec<(models::ActionItem, Option<models::TgUser>)> =
+        query.load(&mut *env.conn())?;
+    if items.is_empty() {
+        bot.reply_message(&msg, "No open action items.").await?;
+        return Ok(());
+    }
+    items.sort_by_key(|(i, _)| (i.due_at.is_none(), i.due_at, i.rowid));
+
+    let tz = timezones::chat_tz(&env, &mut env.conn(), msg.chat.id)?;
+    let now = chrono::Utc::now().naive_utc();
+    let mut text = String::from("📋 <b>Open action items</b>\n");
+    for (item, user) in items.iter().take(LIST_LIMIT) {
+        text.push_str("\n• ");
+        if all {
+            format_user(&mut text, item.assignee_id, user.as_ref(), false);
+            text.push_str(": ");
+        }
+        match item_link(item) {
+            Some(link) => text.push_str(&html::link(&link, &item.text)),
+            None => text.push_str(&html::escape(&item.text)),
+        }
+        if let Some(due_at) = item.due_at {
+            format_to!(
+                text,
+                ", due {}",
+                timezones::to_local(tz, due_at).format("%Y-%m-%d %H:%M"),
+            );
+            if due_at < now {
+                text.push_str(" ⚠️ <b>overdue</b>");
+            }
+        }
+    }
+    if items.len() > LIST_LIMIT {
+        format_to!(text, "\n\n…and {} more.", items.len() - LIST_LIMIT);
+    }
+    bot.reply_message(&msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+fn load_item(
+    conn: &mut SqliteConnection,
+    rowid: i32,
+) -> QueryResult<Option<(models::ActionItem, Option<models::TgUser>)>> {
+    schema::action_items::table
+        .left_join(
+            schema::tg_users::table
+                .on(schema::tg_users::id.eq(schema::action_items::assignee_id)),
+        )
+        .filter(schema::action_items::rowid.eq(rowid))
+        .select((
+            models::ActionItem::as_select(),
+            schema::tg_users::all_columns.nullable(),
+        ))
+        .first(conn)
+        .optional()
+}
+
+fn item_text(
+    item: &models::ActionItem,
+    assignee: Option<&models::TgUser>,
+    tz: Tz,
+) -> String {
+    let mut text = String::from("📌 <b>Action item</b> for ");
+    format_user(&mut text, item.assignee_id, assignee, true);
+    format_to!(text, ": {}", html::escape(&item.text));
+    if let Some(due_at) = item.due_at {
+        format_to!(
+            text,
+            "\nDue: {}",
+            timezones::to_local(tz, due_at).format("%Y-%m-%d %H:%M"),
+        );
+    }
+    text
+}
+
+/// Link to the message of the item, if it's in a supergroup.
+fn item_link(item: &models::ActionItem) -> Option<String> {
+    let chat = ChatId::from(item.chat_id).channel_t_me_id()?;
+    let message = MessageId::from(item.message_id?);
+    Some(format!("https://t.me/c/{chat}/{}", message.0))
+}
+
+fn done_keyboard(rowid: i32) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "✅ Done",
+        format!("ai:d:{rowid}"),
+    )]])
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<i32> {
+    parse_callback(callback.data.as_ref()?)
+}
+
+fn parse_callback(data: &str) -> Option<i32> {
+    data.strip_prefix("ai:d:")?.parse().ok()
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    rowid: i32,
+) -> Result<()> {
+    let is_admin = env.config.telegram.admins.contains(&callback.from.id);
+    let user = DbUserId::from(callback.from.id);
+    let result = env.transaction(|conn| {
+        let Some((item, assignee)) = load_item(conn, rowid)? else {
+            return Ok(Err("This action item no longer exists."));
+        };
+        if item.done_at.is_some() {
+            return Ok(Err("This action item is already done."));
+        }
+        if item.assignee_id != user && item.created_by != user && !is_admin {
+            return Ok(Err("Only the assignee can mark this item done."));
+        }
+        diesel::update(schema::action_items::table)
+            .filter(schema::action_items::rowid.eq(rowid))
+            .set((
+                schema::action_items::done_at
+                    .eq(chrono::Utc::now().naive_utc()),
+                schema::action_items::done_by.eq(user),
+            ))
+            .execute(conn)?;
+        let tz = timezones::chat_tz(&env, conn, item.chat_id.into())?;
+        Ok(Ok(item_text(&item, assignee.as_ref(), tz)))
+    })?;
+
+    let text = match result {
+        Ok(text) => text,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&callback.id).text("Done.").await?;
+    if let Some(message) = &callback.message {
+        bot.edit_message_text(
+            message.chat.id,
+            message.id,
+            format!(
+                "{text}\n✅ Done by {}.",
+                html::user_mention(&callback.from),
+            ),
+        )
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await
+        .log_error("action_items: edit message");
+    }
+    Ok(())
+}
+
+/// Remind assignees of items due soon.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(60)) => {}
+        }
+        send_reminders(&env, &bot)
+            .await
+            .log_error("action_items: send reminders");
+    }
+}
+
+async fn send_reminders(env: &BotEnv, bot: &Bot) -> Result<()> {
+    let soon = chrono::Utc::now().naive_utc()
+        + chrono::Duration::hours(REMIND_BEFORE_HOURS);
+    let due: Vec<(models::ActionItem, Option<models::TgUser>)> =
+        schema::action_items::table
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::tg_users::id
+                        .eq(schema::action_items::assignee_id)),
+            )
+            .filter(schema::action_items::done_at.is_null())
+            .filter(schema::action_items::reminded.eq(false))
+            .filter(schema::action_items::due_at.le(soon))
+            .select((
+                models::ActionItem::as_select(),
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+    for (item, user) in due {
+        let Some(due_at) = item.due_at else { continue };
+        let chat = ChatId::from(item.chat_id);
+        let tz = timezones::chat_tz(env, &mut env.conn(), chat)?;
+        let name = user.as_ref().map_or("user", |u| u.first_name.as_str());
+        let text = format!(
+            "⏰ {}, the action item is due {}: {}",
+            html::mention(item.assignee_id.into(), name),
+            timezones::to_local(tz, due_at).format("%Y-%m-%d %H:%M"),
+            html::escape(&item.text),
+        );
+        let mut request =
+            bot.send_message(chat, text).parse_mode(ParseMode::Html);
+        request.message_thread_id = item.thread_id.map(ThreadId::from);
+        request.reply_to_message_id = item.message_id.map(MessageId::from);
+        request.allow_sending_without_reply = Some(true);
+        request.await.log_error("action_items: send reminder");
+        diesel::update(schema::action_items::table)
+            .filter(schema::action_items::rowid.eq(item.rowid))
+            .set(schema::action_items::reminded.eq(true))
+            .execute(&mut *env.conn())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    #[test]
+    fn test_parse_callback() {
+        assert_eq!(parse_callback("ai:d:42"), Some(42));
+        assert_eq!(parse_callback("ai:x:42"), None);
+        assert_eq!(parse_callback("bk:cancel:42"), None);
+    }
+
+    #[test]
+    fn test_parse_due() {
+        assert_eq!(split_due("order it"), ("order it", None));
+        assert_eq!(
+            split_due("order it due 2026-10-20"),
+            ("order it", Some("2026-10-20")),
+        );
+        assert_eq!(split_due("due 3d"), ("", Some("3d")));
+
+        let now = NaiveDate::from_ymd_opt(2026, 10, 16)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let at = |d, h, m| {
+            NaiveDate::from_ymd_opt(2026, 10, d)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        assert_eq!(parse_due("2026-10-20", now), Some(at(20, 18, 0)));
+        assert_eq!(parse_due("2026-10-20 09:30", now), Some(at(20, 9, 30)));
+        assert_eq!(parse_due("3d", now), Some(at(19, 12, 0)));
+        assert_eq!(parse_due("next week", now), None);
+    }
+
+    #[tokio::test]
+    async fn test_action_items() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        t.add_resident(3, "carol", "Carol");
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+        let carol = testing::user_json(3, "Carol");
+        let handler = command_handler();
+
+        t.dispatch(
+            &handler,
+            testing::message(
+                CHAT,
+                None,
+                &alice,
+                "/action @bob order it due 1h",
+            ),
+        )
+        .await;
+        let posted = t.telegram.results("sendMessage");
+        let text = posted[0]["text"].as_str().unwrap();
+        assert!(text.contains("Bob: order it\nDue: "), "{text}");
+        let item: models::ActionItem = schema::action_items::table
+            .select(models::ActionItem::as_select())
+            .first(&mut *t.env.conn())
+            .unwrap();
+        assert_eq!(
+            item.message_id.map(|m| i64::from(MessageId::from(m).0)),
+            posted[0]["message_id"].as_i64(),
+        );
+        // Due within a day, so the message itself is the reminder.
+        assert!(item.reminded);
+
+        t.telegram.clear();
+        t.dispatch(&handler, testing::message(CHAT, None, &bob, "/actions"))
+            .await;
+        let sent = t.telegram.calls("sendMessage");
+        assert!(sent[0]["text"].as_str().unwrap().contains("order it"));
+
+        let data = format!("ai:d:{}", item.rowid);
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(&carol, &posted[0], &data),
+        )
+        .await;
+        let answers = t.telegram.calls("answerCallbackQuery");
+        assert_eq!(
+            answers[0]["text"],
+            "Only the assignee can mark this item done."
+        );
+
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(&bob, &posted[0], &data),
+        )
+        .await;
+        let edited = t.telegram.calls("editMessageText");
+        assert!(edited[0]["text"].as_str().unwrap().contains("✅ Done by"));
+
+        t.telegram.clear();
+        t.dispatch(&handler, testing::message(CHAT, None, &bob, "/actions"))
+            .await;
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent[0]["text"], "No open action items.");
+    }
+}
@@ -0,0 +1,209 @@
+//! Chronological feed of what happens in the bot: needs asked for and
+//! bought, items borrowed and returned, bookings, countdowns, and polls.
+//!
+//! Modules call [`record`] after changing the state, preferably in the same
+//! transaction.  Entries read as "<actor> <verb> <object>", e.g. "Alice
+//! borrowed drill".  The feed is shown by `/activity` and served at
+//! `GET /feed`.  The command isn't named `/feed` since that one manages RSS
+//! feeds, see [`feeds`](crate::modules::feeds).
+//!
+//! **Scope**: `/activity` command, available to residents.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{
+    filter_command, format_user, resolve_user, BotCommandsExt, BotEnv,
+    UpdateHandler,
+};
+use crate::db::DbUserId;
+use crate::modules::timezones;
+use crate::utils::{format_to, html, BotExt as _};
+use crate::{models, schema};
+
+/// Default number of entries shown by `/activity`.
+const DEFAULT_LIMIT: i64 = 20;
+
+/// Maximum number of entries shown at once.
+pub const MAX_LIMIT: i64 = 100;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show recent activity, served as \
+                             <code>GET /feed</code> by the API (the \
+                             <code>/feed</code> command manages RSS feeds): \
+                             <code>/activity [N] [module] [@user]</code>.")]
+    #[custom(resident = true)]
+    Activity(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_activity)
+}
+
+/// Record an entry.  `actor` is `None` for actions of the bot itself.
+pub fn record(
+    conn: &mut SqliteConnection,
+    module: &str,
+    actor: Option<UserId>,
+    verb: &str,
+    object: &str,
+    url: Option<&str>,
+) -> QueryResult<()> {
+    diesel::insert_into(schema::activity_feed::table)
+        .values(models::NewActivityEntry {
+            timestamp: chrono::Utc::now().naive_utc(),
+            module,
+            actor_id: actor.map(DbUserId::from),
+            verb,
+            object,
+            url,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Filters of the feed.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub module: Option<String>,
+    pub actor: Option<UserId>,
+    /// Only entries older than this one, for pagination.
+    pub before: Option<i32>,
+}
+
+/// Entries matching the filter, newest first.
+pub fn load(
+    conn: &mut SqliteConnection,
+    filter: &Filter,
+    limit: i64,
+) -> QueryResult<Vec<models::ActivityEntry>> {
+    let mut query = schema::activity_feed::table
+        .select(models::ActivityEntry::as_select())
+        .order(schema::activity_feed::rowid.desc())
+        .limit(limit.clamp(1, MAX_LIMIT))
+        .into_boxed();
+    if let Some(module) = &filter.module {
+        query = query.filter(schema::activity_feed::module.eq(module));
+    }
+    if let Some(actor) = filter.actor {
+        query = query
+            .filter(schema::activity_feed::actor_id.eq(DbUserId::from(actor)));
+    }
+    if let Some(before) = filter.before {
+        query = query.filter(schema::activity_feed::rowid.lt(before));
+    }
+    query.load(conn)
+}
+
+async fn cmd_activity(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Activity(args): Commands,
+) -> Result<()> {
+    let mut filter = Filter::default();
+    let mut limit = DEFAULT_LIMIT;
+    for arg in args.split_whitespace() {
+        if let Ok(n) = arg.parse::<i64>() {
+            limit = n;
+        } else if arg.starts_with('@') {
+            let Some(user) = resolve_user(&bot, &env, &msg, arg).await? else {
+                bot.reply_message(&msg, "Unknown user.").await?;
+                return Ok(());
+            };
+            filter.actor = Some(user);
+        } else {
+            filter.module = Some(arg.to_string());
+        }
+    }
+
+    let entries = load(&mut env.conn(), &filter, limit)?;
+    let users = schema::tg_users::table
+        .filter(
+            schema::tg_users::id
+                .eq_any(entries.iter().filter_map(|e| e.actor_id)),
+        )
+        .select(models::TgUser::as_select())
+        .load(&mut *env.conn())?;
+    let tz = timezones::chat_tz(&env, &mut env.conn(), msg.chat.id)?;
+
+    let mut text = String::new();
+    if entries.is_empty() {
+        text.push_str("No activity.");
+    }
+    for entry in entries.iter().rev() {
+        let timestamp = timezones::to_local(tz, entry.timestamp);
+        format_to!(text, "{} ", timestamp.format("%m-%d %H:%M"));
+        match entry.actor_id {
+            Some(actor_id) => format_user(
+                &mut text,
+                actor_id,
+                users.iter().find(|u| u.id == actor_id),
+                false,
+            ),
+            None => text.push_str("(bot)"),
+        }
+        format_to!(text, " {} ", html::escape(&entry.verb));
+        match &entry.url {
+            Some(url) => text.push_str(&html::link(url, &entry.object)),
+            None => text.push_str(&html::escape(&entry.object)),
+        }
+        text.push('\n');
+    }
+    bot.reply_message(&msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_load() {
+        let mut conn = testing::memory_db();
+        record(&mut conn, "needs", Some(UserId(1)), "asked for", "milk", None)
+            .unwrap();
+        record(
+            &mut conn,
+            "borrowed_items",
+            Some(UserId(2)),
+            "borrowed",
+            "saw",
+            None,
+        )
+        .unwrap();
+        record(&mut conn, "needs", Some(UserId(2)), "bought", "milk", None)
+            .unwrap();
+
+        let mut objects = |filter: &Filter, limit| {
+            load(&mut conn, filter, limit)
+                .unwrap()
+                .into_iter()
+                .map(|e| format!("{} {}", e.verb, e.object))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            objects(&Filter::default(), 2),
+            ["bought milk", "borrowed saw"]
+        );
+        let needs =
+            Filter { module: Some("needs".to_string()), ..Filter::default() };
+        assert_eq!(objects(&needs, 10), ["bought milk", "asked for milk"]);
+        let bob = Filter { actor: Some(UserId(2)), ..Filter::default() };
+        assert_eq!(objects(&bob, 10), ["bought milk", "borrowed saw"]);
+        let page = Filter { before: Some(3), ..Filter::default() };
+        assert_eq!(objects(&page, 10), ["borrowed saw", "asked for milk"]);
+    }
+}
@@ -0,0 +1,459 @@
+//! Two-person approval for destructive admin commands: a command listed in
+//! [`telegram.approvals.commands`] config option is executed only after
+//! another admin confirms it using an inline button.
+//!
+//! **Scope**: admin commands handled by [`CommandHandlers`].  Pending
+//! approvals are stored in the database, so they survive restarts.
+//!
+//! [`telegram.approvals.commands`]: crate::config::Approvals::commands
+
+use std::sync::Arc;
+
+use anyhow::{bail, Context as _, Result};
+use diesel::prelude::*;
+use dptree::di::DependencyMap;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Me};
+
+use crate::common::{command_name, BotEnv, UpdateHandler};
+use crate::config::Approvals;
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::utils::{html, BotExt as _, ResultExt as _, Sqlizer};
+use crate::{models, schema};
+
+/// Handlers of commands that may require an approval.  Once a command is
+/// approved, its original message is dispatched to these handlers again,
+/// with the same dependencies as the dispatcher.
+#[derive(Clone)]
+pub struct CommandHandlers {
+    handlers: UpdateHandler,
+    deps: DependencyMap,
+}
+
+impl CommandHandlers {
+    /// `deps` are the dependencies of the dispatcher, except for the update,
+    /// the [`Bot`], and [`Me`].
+    pub fn new(handlers: UpdateHandler, deps: DependencyMap) -> Self {
+        Self { handlers, deps }
+    }
+}
+
+/// Check that [`Approvals::commands`] are admin commands, as others are
+/// never gated.
+pub fn check_config(conf: &Approvals) -> Result<()> {
+    let commands = crate::modules::commands();
+    for name in &conf.commands {
+        match commands.iter().find(|c| &c.name == name) {
+            Some(c) if c.rules.admin => (),
+            Some(_) => {
+                bail!("telegram.approvals: /{name} is not an admin command")
+            }
+            None => bail!("telegram.approvals: unknown command /{name}"),
+        }
+    }
+    Ok(())
+}
+
+enum ApprovalState {
+    /// The command is approved and can be executed.
+    Approved,
+    /// The command is already waiting for an approval.
+    Pending,
+    /// A new approval request with the given rowid is created.
+    Created(i32),
+}
+
+/// Check whether an admin command in `msg` can be executed now.  If it
+/// requires an approval, ask for one and return `false`.
+pub async fn check(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<bool> {
+    let Some(conf) = &env.config.telegram.approvals else { return Ok(true) };
+    let Some(name) = command_name(msg) else { return Ok(true) };
+    if !conf.commands.iter().any(|c| c == name) {
+        return Ok(true);
+    }
+    let Some(from) = &msg.from else { return Ok(false) };
+
+    let chat_id = DbChatId::from(msg.chat.id);
+    let message_id = DbMessageId::from(msg.id);
+    let state = env.transaction(|conn| {
+        use schema::pending_approvals::dsl as a;
+        let approved = diesel::update(a::pending_approvals)
+            .filter(a::chat_id.eq(chat_id))
+            .filter(a::message_id.eq(message_id))
+            .filter(a::approver_id.is_not_null())
+            .filter(a::executed.eq(false))
+            .set(a::executed.eq(true))
+            .execute(conn)?
+            > 0;
+        if approved {
+            return Ok(ApprovalState::Approved);
+        }
+        let inserted = diesel::insert_or_ignore_into(a::pending_approvals)
+            .values(models::NewPendingApproval {
+                chat_id,
+                message_id,
+                requester_id: DbUserId::from(from.id),
+                message: Sqlizer::new(msg.clone()).map_err(|e| {
+                    diesel::result::Error::SerializationError(Box::new(e))
+                })?,
+                created_at: chrono::Utc::now().naive_utc(),
+                executed: false,
+            })
+            .execute(conn)?;
+        if inserted == 0 {
+            // E.g. the message was edited while the request is pending.
+            return Ok(ApprovalState::Pending);
+        }
+        a::pending_approvals
+            .filter(a::chat_id.eq(chat_id))
+            .filter(a::message_id.eq(message_id))
+            .select(a::rowid)
+            .first(conn)
+            .map(ApprovalState::Created)
+    })?;
+
+    let rowid = match state {
+        ApprovalState::Approved => return Ok(true),
+        ApprovalState::Pending => return Ok(false),
+        ApprovalState::Created(rowid) => rowid,
+    };
+
+    crate::modules::audit::record(
+        &mut env.conn(),
+        Some(from.id),
+        "approval_requested",
+        &serde_json::json!({
+            "chat_id": msg.chat.id.0,
+            "message_id": msg.id.0,
+            "text": msg.text(),
+        }),
+    )
+    .log_error("approvals: audit");
+
+    bot.reply_message(
+        msg,
+        format!(
+            "⚠️ This command must be approved by another admin within {} \
+             minutes.",
+            conf.timeout_minutes,
+        ),
+    )
+    .reply_markup(InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback("Approve", format!("a:ok:{rowid}")),
+        InlineKeyboardButton::callback("Cancel", format!("a:no:{rowid}")),
+    ]]))
+    .await?;
+
+    Ok(false)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+#[derive(Clone, Copy)]
+enum CallbackData {
+    Approve(i32),
+    Cancel(i32),
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<CallbackData> {
+    let data = callback.data.as_ref()?.strip_prefix("a:")?;
+    let (prefix, data) = data.split_once(':')?;
+    let data = data.parse().ok()?;
+    match prefix {
+        "ok" => Some(CallbackData::Approve(data)),
+        "no" => Some(CallbackData::Cancel(data)),
+        _ => None,
+    }
+}
+
+async fn handle_callback(
+    bot: Bot,
+    me: Me,
+    env: Arc<BotEnv>,
+    handlers: CommandHandlers,
+    callback: CallbackQuery,
+    data: CallbackData,
+) -> Result<()> {
+    if !env.config.telegram.admins.contains(&callback.from.id) {
+        bot.answer_callback_query(&callback.id)
+            .text("You must be an admin to do this.")
+            .await?;
+        return Ok(());
+    }
+    let timeout_minutes =
+        env.config.telegram.approvals.as_ref().map_or(0, |a| a.timeout_minutes);
+
+    let (rowid, approve) = match data {
+        CallbackData::Approve(rowid) => (rowid, true),
+        CallbackData::Cancel(rowid) => (rowid, false),
+    };
+    let result = env.transaction(|conn| {
+        use schema::pending_approvals::dsl as a;
+        let approval = a::pending_approvals
+            .filter(a::rowid.eq(rowid))
+            .select(models::PendingApproval::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(approval) = approval else {
+            return Ok(Err("This request no longer exists."));
+        };
+        if approval.approver_id.is_some() || approval.executed {
+            return Ok(Err("This request is already resolved."));
+        }
+        if !approve {
+            diesel::delete(a::pending_approvals)
+                .filter(a::rowid.eq(rowid))
+                .execute(conn)?;
+            return Ok(Ok(approval));
+        }
+        if UserId::from(approval.requester_id) == callback.from.id {
+            return Ok(Err("You can't approve your own command."));
+        }
+        let deadline = approval.created_at
+            + chrono::Duration::minutes(timeout_minutes.into());
+        if chrono::Utc::now().naive_utc() > deadline {
+            diesel::delete(a::pending_approvals)
+                .filter(a::rowid.eq(rowid))
+                .execute(conn)?;
+            return Ok(Err("This request has expired."));
+        }
+        diesel::update(a::pending_approvals)
+            .filter(a::rowid.eq(rowid))
+            .set(a::approver_id.eq(DbUserId::from(callback.from.id)))
+            .execute(conn)?;
+        Ok(Ok(approval))
+    })?;
+
+    let approval = match result {
+        Ok(approval) => approval,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            if let Some(message) = &callback.message {
+                bot.edit_message_reply_markup(message.chat.id, message.id)
+                    .await
+                    .log_error("approvals: remove buttons");
+            }
+            return Ok(());
+        }
+    };
+
+    crate::modules::audit::record(
+        &mut env.conn(),
+        Some(callback.from.id),
+        if approve { "approval_granted" } else { "approval_cancelled" },
+        &serde_json::json!({
+            "requester_id": approval.requester_id,
+            "text": approval.message.text(),
+        }),
+    )
+    .log_error("approvals: audit");
+
+    bot.answer_callback_query(&callback.id).await?;
+    if let Some(message) = &callback.message {
+        let status = if approve { "✅ Approved" } else { "❌ Cancelled" };
+        bot.edit_message_text(
+            message.chat.id,
+            message.id,
+            format!(
+                "{status} by {}.",
+                html::escape(&callback.from.full_name())
+            ),
+        )
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .await
+        .log_error("approvals: edit message");
+    }
+
+    if approve {
+        let mut deps = handlers.deps.clone();
+        deps.insert(bot);
+        deps.insert(me);
+        deps.insert(Message::clone(&approval.message));
+        match handlers.handlers.dispatch(deps).await {
+            std::ops::ControlFlow::Break(result) => {
+                result.context("Failed to execute approved command")?;
+            }
+            std::ops::ControlFlow::Continue(_) => {
+                log::warn!("approvals: approved command was not handled");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use macro_rules_attribute::derive;
+    use serde_json::Value;
+    use teloxide::dispatching::UpdateFilterExt as _;
+    use teloxide::macros::BotCommands;
+
+    use super::*;
+    use crate::common::{filter_command, BotCommandsExt};
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    #[derive(Clone, BotCommands, BotCommandsExt!)]
+    #[command(rename_rule = "snake_case")]
+    enum Commands {
+        #[custom(admin = true)]
+        Wipe,
+    }
+
+    /// A dependency that only the dispatcher provides.
+    #[derive(Clone)]
+    struct Marker;
+
+    fn handlers() -> UpdateHandler {
+        filter_command::<Commands>().endpoint(
+            |bot: Bot, msg: Message, _: Marker| async move {
+                bot.send_message(msg.chat.id, "Wiped.").await?;
+                Ok(())
+            },
+        )
+    }
+
+    fn test_bot() -> TestBot {
+        TestBot::with_config(|config| {
+            config.telegram.admins = vec![UserId(1), UserId(2)];
+            config.telegram.approvals = Some(Approvals {
+                commands: vec!["wipe".to_string()],
+                timeout_minutes: 30,
+            });
+        })
+    }
+
+    /// The handler of both commands and approval callbacks.
+    fn handler(t: &TestBot) -> UpdateHandler {
+        let deps = dptree::deps![Marker, Arc::clone(&t.env)];
+        dptree::entry()
+            .branch(Update::filter_message().branch(handlers()))
+            .branch(
+                Update::filter_callback_query().chain(
+                    dptree::map(move || {
+                        CommandHandlers::new(handlers(), deps.clone())
+                    })
+                    .branch(callback_handler()),
+                ),
+            )
+    }
+
+    /// Send `/wipe` from `from` and return the approval request.
+    async fn request(t: &TestBot, from: &Value) -> Value {
+        t.dispatch(&handler(t), testing::message(CHAT, None, from, "/wipe"))
+            .await;
+        let sent = t.telegram.results("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0]["text"].as_str().unwrap().contains("approved"));
+        t.telegram.clear();
+        sent[0].clone()
+    }
+
+    fn audit_actions(t: &TestBot) -> Vec<String> {
+        schema::audit_log::table
+            .order(schema::audit_log::rowid)
+            .select(schema::audit_log::action)
+            .load(&mut *t.env.conn())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_check_config() {
+        let approvals = |commands: &[&str]| Approvals {
+            commands: commands.iter().map(ToString::to_string).collect(),
+            timeout_minutes: 30,
+        };
+        let example: crate::config::Config =
+            serde_yaml::from_str(include_str!("../../config.example.yaml"))
+                .unwrap();
+        assert!(
+            check_config(example.telegram.approvals.as_ref().unwrap()).is_ok()
+        );
+        assert!(check_config(&approvals(&["role", "dm"])).is_ok());
+        assert!(check_config(&approvals(&["wipe_data"])).is_err());
+        assert!(check_config(&approvals(&["help"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_approve() {
+        let t = test_bot();
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+        let request = request(&t, &alice).await;
+
+        t.dispatch(&handler(&t), testing::callback(&bob, &request, "a:ok:1"))
+            .await;
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["text"], "Wiped.");
+        assert!(t.telegram.calls("editMessageText")[0]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("✅ Approved by Bob"));
+        assert_eq!(
+            audit_actions(&t),
+            ["approval_requested", "approval_granted", "admin_command"],
+        );
+
+        // The command is executed only once.
+        t.telegram.clear();
+        t.dispatch(&handler(&t), testing::callback(&bob, &request, "a:ok:1"))
+            .await;
+        assert_eq!(
+            t.telegram.calls("answerCallbackQuery")[0]["text"],
+            "This request is already resolved.",
+        );
+        assert!(t.telegram.calls("sendMessage").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_self_approve() {
+        let t = test_bot();
+        let alice = testing::user_json(1, "Alice");
+        let request = request(&t, &alice).await;
+
+        t.dispatch(&handler(&t), testing::callback(&alice, &request, "a:ok:1"))
+            .await;
+        assert_eq!(
+            t.telegram.calls("answerCallbackQuery")[0]["text"],
+            "You can't approve your own command.",
+        );
+        assert!(t.telegram.calls("sendMessage").is_empty());
+        assert_eq!(audit_actions(&t), ["approval_requested"]);
+    }
+
+    #[tokio::test]
+    async fn test_expired() {
+        let t = test_bot();
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+        let request = request(&t, &alice).await;
+        diesel::update(schema::pending_approvals::table)
+            .set(
+                schema::pending_approvals::created_at
+                    .eq(chrono::Utc::now().naive_utc()
+                        - chrono::Duration::minutes(31)),
+            )
+            .execute(&mut *t.env.conn())
+            .unwrap();
+
+        t.dispatch(&handler(&t), testing::callback(&bob, &request, "a:ok:1"))
+            .await;
+        assert_eq!(
+            t.telegram.calls("answerCallbackQuery")[0]["text"],
+            "This request has expired.",
+        );
+        assert!(t.telegram.calls("sendMessage").is_empty());
+        assert_eq!(
+            schema::pending_approvals::table
+                .count()
+                .get_result::<i64>(&mut *t.env.conn())
+                .unwrap(),
+            0,
+        );
+    }
+}
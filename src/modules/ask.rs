@@ -0,0 +1,368 @@
+//! Answer questions using the Wiki.js pages.
+//!
+//! `/ask <question>` searches the wiki, picks the page fragments most similar
+//! to the question using `OpenAI` embeddings, and asks the LLM to answer based
+//! on them, citing the pages.  Embeddings of page fragments are cached in the
+//! database and recomputed only when the fragment text changes.  Disabled
+//! when [`services.openai.disable`] is set.
+//!
+//! **Scope**: `/ask` command, available to residents.
+//!
+//! [`services.openai.disable`]: crate::config::OpenAI::disable
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use async_openai::types::{
+    ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs,
+    CreateEmbeddingRequestArgs,
+};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use tap::Tap as _;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::schema;
+use crate::utils::{
+    format_to, get_wikijs_page, html, search_wikijs_pages, BotExt as _,
+    WikiJsSearchResult,
+};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "ask a question, answered using the wiki.")]
+    #[custom(resident = true)]
+    Ask(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_ask)
+}
+
+const CHAT_MODEL: &str = "gpt-4";
+const EMBEDDING_MODEL: &str = "text-embedding-ada-002";
+
+/// Number of search results to consider.
+const SEARCH_PAGES: usize = 5;
+/// Maximum size of a page fragment, in bytes.
+const CHUNK_BYTES: usize = 1500;
+/// Maximum size of the question, in bytes.
+const QUESTION_BYTES: usize = 1000;
+/// Token budget for page fragments included into the prompt.
+const CONTEXT_TOKENS: usize = 3000;
+/// Token budget for the answer.
+const ANSWER_TOKENS: u16 = 500;
+
+async fn cmd_ask(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Ask(question): Commands,
+) -> Result<()> {
+    let question = question.trim();
+    if question.is_empty() {
+        bot.reply_message(&msg, "Usage: /ask <question>").await?;
+        return Ok(());
+    }
+    if env.config.services.openai.disable {
+        bot.reply_message(&msg, "Question answering is disabled.").await?;
+        return Ok(());
+    }
+    if question.len() > QUESTION_BYTES {
+        bot.reply_message(&msg, "The question is too long.").await?;
+        return Ok(());
+    }
+
+    let text = match answer(&env, question).await {
+        Ok(text) => text,
+        Err(e) => {
+            log::error!("ask: failed to answer: {e:?}");
+            "Failed to answer the question, try again later.".to_string()
+        }
+    };
+    bot.reply_message(&msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+/// A page fragment considered for the prompt.
+struct Fragment<'a> {
+    page: &'a WikiJsSearchResult,
+    text: String,
+    score: f32,
+}
+
+async fn answer(env: &BotEnv, question: &str) -> Result<String> {
+    let wikijs = &env.config.services.wikijs;
+    let mut pages =
+        search_wikijs_pages(&wikijs.url, &wikijs.token, question).await?;
+    pages.truncate(SEARCH_PAGES);
+    if pages.is_empty() {
+        return Ok("Nothing relevant found in the wiki.".to_string());
+    }
+
+    let question_embedding = embed(env, vec![question.to_string()])
+        .await?
+        .pop()
+        .context("Empty list of embeddings")?;
+    let mut fragments = Vec::new();
+    for page in &pages {
+        let content =
+            get_wikijs_page(&wikijs.url, &wikijs.token, &page.path).await?;
+        let chunks = split_chunks(&content);
+        let embeddings = cached_embeddings(env, &page.path, &chunks).await?;
+        for (text, embedding) in chunks.into_iter().zip(embeddings) {
+            let score = cosine_similarity(&question_embedding, &embedding);
+            fragments.push(Fragment { page, text, score });
+        }
+    }
+    fragments.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    // Take the most relevant fragments that fit into the budget, grouped by
+    // page so each page gets a single source number.
+    let mut budget = CONTEXT_TOKENS;
+    let mut sources: Vec<(&WikiJsSearchResult, Vec<&str>)> = Vec::new();
+    for fragment in &fragments {
+        let tokens = estimate_tokens(&fragment.text);
+        if tokens > budget {
+            continue;
+        }
+        budget -= tokens;
+        match sources.iter_mut().find(|(p, _)| p.path == fragment.page.path) {
+            Some((_, texts)) => texts.push(&fragment.text),
+            None => sources.push((fragment.page, vec![&fragment.text])),
+        }
+    }
+
+    let mut prompt = String::new();
+    for (i, (page, texts)) in sources.iter().enumerate() {
+        format_to!(
+            prompt,
+            "[{}] {}\n{}\n\n",
+            i + 1,
+            page.title,
+            texts.join("\n…\n")
+        );
+    }
+    format_to!(prompt, "Question: {question}");
+
+    let request = CreateChatCompletionRequestArgs::default()
+        .max_tokens(ANSWER_TOKENS)
+        .model(CHAT_MODEL)
+        .messages([
+            ChatCompletionRequestMessageArgs::default()
+                .role(async_openai::types::Role::System)
+                .content(PROMPT.trim())
+                .build()?,
+            ChatCompletionRequestMessageArgs::default()
+                .role(async_openai::types::Role::User)
+                .content(prompt)
+                .build()?,
+        ])
+        .build()?;
+    let response = env.openai_client.chat().create(request).await.tap(|r| {
+        crate::metrics::update_service(&env.health, "openai", r.is_ok())
+    })?;
+    let answer = response
+        .choices
+        .first()
+        .context("Empty list of choices")?
+        .message
+        .content
+        .as_ref()
+        .context("No content in response")?;
+
+    let mut text = html::escape(answer.trim());
+    text.push_str("\n\n<b>Sources:</b>");
+    for (i, (page, _)) in sources.iter().enumerate() {
+        format_to!(
+            text,
+            "\n[{}] {}",
+            i + 1,
+            html::link(
+                &format!("{}{}", wikijs.url.trim_end_matches('/'), page.path),
+                &page.title,
+            ),
+        );
+    }
+    Ok(text)
+}
+
+const PROMPT: &str = r"
+You answer questions of hackerspace residents using fragments of the hackerspace wiki.
+Each fragment is preceded by its source number, e.g. `[1]`.
+Answer briefly, using only the information from the fragments, and cite the sources by their numbers, e.g. `[1]`.
+If the fragments do not contain the answer, say that you don't know.
+Answer in the language of the question.
+";
+
+/// Get embeddings for the chunks of the page, using the database as a cache.
+async fn cached_embeddings(
+    env: &BotEnv,
+    path: &str,
+    chunks: &[String],
+) -> Result<Vec<Vec<f32>>> {
+    let cached: Vec<(String, Vec<u8>)> = schema::wiki_embeddings::table
+        .filter(schema::wiki_embeddings::path.eq(path))
+        .select((
+            schema::wiki_embeddings::content,
+            schema::wiki_embeddings::embedding,
+        ))
+        .load(&mut *env.conn())?;
+    let cached_len = cached.len();
+    let mut cache: HashMap<String, Vec<f32>> = cached
+        .into_iter()
+        .map(|(content, embedding)| (content, decode_embedding(&embedding)))
+        .collect();
+
+    let missing = chunks
+        .iter()
+        .filter(|c| !cache.contains_key(*c))
+        .cloned()
+        .collect_vec();
+    if missing.is_empty() && cached_len == chunks.len() {
+        return Ok(chunks.iter().map(|c| cache[c].clone()).collect());
+    }
+    if !missing.is_empty() {
+        let embeddings = embed(env, missing.clone()).await?;
+        cache.extend(missing.into_iter().zip(embeddings));
+    }
+
+    let result: Vec<Vec<f32>> =
+        chunks.iter().map(|c| cache[c].clone()).collect();
+    env.transaction(|conn| {
+        diesel::delete(schema::wiki_embeddings::table)
+            .filter(schema::wiki_embeddings::path.eq(path))
+            .execute(conn)?;
+        diesel::insert_into(schema::wiki_embeddings::table)
+            .values(
+                (0..)
+                    .zip(chunks.iter().zip(&result))
+                    .map(|(i, (content, embedding))| {
+                        (
+                            schema::wiki_embeddings::path.eq(path),
+                            schema::wiki_embeddings::chunk.eq(i),
+                            schema::wiki_embeddings::content.eq(content),
+                            schema::wiki_embeddings::embedding
+                                .eq(encode_embedding(embedding)),
+                        )
+                    })
+                    .collect_vec(),
+            )
+            .execute(conn)?;
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+async fn embed(env: &BotEnv, input: Vec<String>) -> Result<Vec<Vec<f32>>> {
+    let request = CreateEmbeddingRequestArgs::default()
+        .model(EMBEDDING_MODEL)
+        .input(input)
+        .build()?;
+    let response =
+        env.openai_client.embeddings().create(request).await.tap(|r| {
+            crate::metrics::update_service(&env.health, "openai", r.is_ok())
+        })?;
+    Ok(response
+        .data
+        .into_iter()
+        .sorted_by_key(|e| e.index)
+        .map(|e| e.embedding)
+        .collect())
+}
+
+fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+/// A rough, conservative estimate of the number of tokens in the text.
+const fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(3)
+}
+
+/// Split the markdown text into chunks of at most [`CHUNK_BYTES`] bytes,
+/// preferably on paragraph boundaries.
+fn split_chunks(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for mut paragraph in text.split("\n\n").map(str::trim) {
+        if !current.is_empty()
+            && current.len() + paragraph.len() + 2 > CHUNK_BYTES
+        {
+            chunks.push(std::mem::take(&mut current));
+        }
+        while paragraph.len() > CHUNK_BYTES {
+            let mut end = CHUNK_BYTES;
+            while !paragraph.is_char_boundary(end) {
+                end -= 1;
+            }
+            chunks.push(paragraph[..end].to_string());
+            paragraph = &paragraph[end..];
+        }
+        if paragraph.is_empty() {
+            continue;
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(paragraph);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_chunks() {
+        assert_eq!(split_chunks("a\n\n\n\nb\n"), vec!["a\n\nb"]);
+        assert!(split_chunks("  \n\n").is_empty());
+
+        let long = "ф".repeat(CHUNK_BYTES);
+        let chunks = split_chunks(&format!("a\n\n{long}\n\nb"));
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[0], "a");
+        assert!(chunks.iter().all(|c| c.len() <= CHUNK_BYTES));
+        assert_eq!(chunks[1..3].concat(), long);
+        assert_eq!(chunks[3], "b");
+    }
+
+    #[test]
+    fn test_embedding_roundtrip() {
+        let embedding = vec![0.5, -1.25, 3.0];
+        assert_eq!(decode_embedding(&encode_embedding(&embedding)), embedding);
+        assert!((cosine_similarity(&embedding, &embedding) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]).abs() < 1e-6);
+        assert!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]).abs() < 1e-6);
+    }
+}
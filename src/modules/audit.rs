@@ -0,0 +1,244 @@
+//! Audit log of privileged actions: admin commands, residency changes, option
+//! changes, bans, and HTTP API writes.
+//!
+//! Admin commands are recorded automatically by [`filter_command`]; other
+//! modules should call [`record`] explicitly.
+//!
+//! [`filter_command`]: crate::common::filter_command
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use serde::Serialize;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{
+    filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::db::DbUserId;
+use crate::modules::timezones;
+use crate::utils::{format_to, html, BotExt as _};
+use crate::{models, schema};
+
+/// Default number of entries shown by `/audit recent`.
+const DEFAULT_RECENT: i64 = 20;
+
+/// Maximum number of entries shown by `/audit recent`.
+const MAX_RECENT: i64 = 100;
+
+/// Longest payload shown by `/audit recent`, in characters.
+const MAX_PAYLOAD_CHARS: usize = 200;
+
+/// Limit of the entries of `/audit recent`, leaving room for the note about
+/// omitted entries within the Telegram limit of 4096 characters.
+const MAX_TEXT_CHARS: usize = 4000;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show audit log: <code>/audit recent [N]</code>.")]
+    #[custom(admin = true)]
+    Audit(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_audit)
+}
+
+/// Record a privileged action.  `actor` is `None` for actions performed by the
+/// bot itself.
+pub fn record(
+    conn: &mut SqliteConnection,
+    actor: Option<UserId>,
+    action: &str,
+    payload: &impl Serialize,
+) -> Result<()> {
+    let payload = serde_json::to_string(payload)?;
+    diesel::insert_into(schema::audit_log::table)
+        .values(models::NewAuditLogEntry {
+            timestamp: chrono::Utc::now().naive_utc(),
+            actor_id: actor.map(DbUserId::from),
+            action,
+            payload: &payload,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+async fn cmd_audit(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Audit(args): Commands,
+) -> Result<()> {
+    let mut args = args.split_whitespace();
+    let limit = match (args.next(), args.next(), args.next()) {
+        (Some("recent"), None, None) => DEFAULT_RECENT,
+        (Some("recent"), Some(n), None) => match n.parse::<i64>() {
+            Ok(n) if n > 0 => n.min(MAX_RECENT),
+            _ => {
+                bot.reply_message(&msg, "Invalid number of entries").await?;
+                return Ok(());
+            }
+        },
+        _ => {
+            bot.reply_message(&msg, "Usage: /audit recent [N]").await?;
+            return Ok(());
+        }
+    };
+
+    let entries: Vec<(models::AuditLogEntry, Option<models::TgUser>)> =
+        schema::audit_log::table
+            .left_join(schema::tg_users::table.on(
+                schema::audit_log::actor_id.eq(schema::tg_users::id.nullable()),
+            ))
+            .filter(schema::audit_log::deleted_at.is_null())
+            .order(schema::audit_log::rowid.desc())
+            .limit(limit)
+            .select((
+                models::AuditLogEntry::as_select(),
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+
+    let tz = timezones::chat_tz(&env, &mut env.conn(), msg.chat.id)?;
+    // Newest first, so that the oldest entries are dropped to fit the
+    // message.
+    let mut lines = Vec::new();
+    let mut length = 0;
+    for (entry, user) in &entries {
+        let mut line = String::new();
+        let timestamp = timezones::to_local(tz, entry.timestamp);
+        format_to!(line, "{} ", timestamp.format("%Y-%m-%d %H:%M"));
+        match entry.actor_id {
+            Some(actor_id) => {
+                format_user(&mut line, actor_id, user.as_ref(), false)
+            }
+            None => line.push_str("(bot)"),
+        }
+        format_to!(
+            line,
+            ": <b>{}</b> <code>{}</code>\n",
+            html::escape(&entry.action),
+            html::escape(&shorten(&entry.payload)),
+        );
+        length += line.chars().count();
+        if length > MAX_TEXT_CHARS {
+            break;
+        }
+        lines.push(line);
+    }
+
+    let mut text = String::new();
+    if entries.is_empty() {
+        text.push_str("The audit log is empty.");
+    } else if lines.len() < entries.len() {
+        format_to!(
+            text,
+            "<i>{} older entries are not shown.</i>\n",
+            entries.len() - lines.len(),
+        );
+    }
+    lines.iter().rev().for_each(|line| text.push_str(line));
+
+    bot.reply_message(&msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+
+    Ok(())
+}
+
+/// Cut the payload to [`MAX_PAYLOAD_CHARS`].
+fn shorten(payload: &str) -> String {
+    match payload.char_indices().nth(MAX_PAYLOAD_CHARS) {
+        Some((i, _)) => format!("{}…", &payload[..i]),
+        None => payload.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use teloxide::dispatching::UpdateFilterExt as _;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const ADMIN: u64 = 1_234_567_890;
+
+    #[tokio::test]
+    async fn test_audit_recent() {
+        let t = TestBot::new();
+        let admin = testing::user_json(ADMIN, "Admin");
+        let alice = testing::user_json(1, "Alice");
+        record(&mut t.env.conn(), None, "first", &json!({})).unwrap();
+        record(&mut t.env.conn(), None, "kv_put", &json!({ "key": "<k>" }))
+            .unwrap();
+
+        t.dispatch(
+            &command_handler(),
+            testing::message(ADMIN as i64, None, &admin, "/audit recent 2"),
+        )
+        .await;
+        let sent = t.telegram.calls("sendMessage");
+        let text = sent[0]["text"].as_str().unwrap();
+        // The command itself is recorded before it's executed.
+        assert!(!text.contains("first"), "{text}");
+        assert!(
+            text.contains(
+                "(bot): <b>kv_put</b> <code>{\"key\":\"&lt;k&gt;\"}</code>"
+            ),
+            "{text}",
+        );
+        assert!(text.contains("<b>admin_command</b>"), "{text}");
+        t.telegram.clear();
+
+        let payload = json!({ "text": "x".repeat(1000) });
+        for _ in 0..40 {
+            record(&mut t.env.conn(), None, "long", &payload).unwrap();
+        }
+        t.dispatch(
+            &command_handler(),
+            testing::message(ADMIN as i64, None, &admin, "/audit recent 50"),
+        )
+        .await;
+        let sent = t.telegram.calls("sendMessage");
+        let text = sent[0]["text"].as_str().unwrap();
+        assert!(text.chars().count() <= 4096, "{text}");
+        assert!(text.contains("older entries are not shown"), "{text}");
+        assert!(text.contains("…</code>"), "{text}");
+        t.telegram.clear();
+
+        t.dispatch(
+            &command_handler(),
+            testing::message(ADMIN as i64, None, &admin, "/audit recent x"),
+        )
+        .await;
+        assert_eq!(
+            t.telegram.calls("sendMessage")[0]["text"],
+            "Invalid number of entries",
+        );
+        t.telegram.clear();
+
+        // Only admins can read the log.
+        t.dispatch(
+            &Update::filter_message().branch(command_handler()).endpoint(
+                |bot: Bot, msg: Message| async move {
+                    bot.reply_message(&msg, "Not handled").await?;
+                    Ok(())
+                },
+            ),
+            testing::message(1, None, &alice, "/audit recent"),
+        )
+        .await;
+        assert_eq!(
+            t.telegram.calls("sendMessage")[0]["text"],
+            "You must be an admin to execute this command",
+        );
+    }
+}
@@ -0,0 +1,572 @@
+//! Mirror messages of chosen chats into the backup channel.
+//!
+//! Messages are re-sent to [`telegram.chats.forward_channel`], or to the
+//! channel set in the rule, as they arrive, prefixed with the author and a
+//! link to the original.  Text messages are sent anew, other messages are
+//! copied with the prefix in the caption when they can have one.  The copy
+//! of each message is recorded in `backup_messages`, so replies are mirrored
+//! as replies to the copies of their parents, and the channel reads as a
+//! conversation.
+//!
+//! Which messages are mirrored is decided by the first rule in
+//! [`telegram.chats.backup`] matching the chat and topic of the message.
+//! Rules can exclude a topic, skip bots, or keep only media or announcements.
+//!
+//! Copies in the channel refer to files by `file_id`, which only works for
+//! the bot that received them.  With [`telegram.backup_media`] configured,
+//! media of mirrored messages is also downloaded into a local directory or
+//! an S3 bucket and indexed in `backup_media`.  `/backup fetch <link>`
+//! sends the stored file back.
+//!
+//! **Scope**: chats and topics listed in [`telegram.chats.backup`], and
+//! `/backup` command, available to admins.
+//!
+//! [`telegram.chats.forward_channel`]: crate::config::TelegramChats::forward_channel
+//! [`telegram.chats.backup`]: crate::config::TelegramChats::backup
+//! [`telegram.backup_media`]: crate::config::Telegram::backup_media
+
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use diesel::prelude::*;
+use hmac::{Hmac, Mac as _};
+use macro_rules_attribute::derive;
+use sha2::{Digest as _, Sha256};
+use teloxide::macros::BotCommands;
+use teloxide::net::Download as _;
+use teloxide::prelude::*;
+use teloxide::types::{
+    FileMeta, InputFile, MessageId, MessageKind, ParseMode, ThreadId,
+};
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::config::{
+    BackupMedia, BackupOnly, BackupRule, MediaStorage, S3Storage,
+};
+use crate::db::{DbChatId, DbMessageId};
+use crate::utils::{
+    html, parse_tg_message_link, write_message_link, BotExt as _,
+    MessageExt as _,
+};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "get stored media of a backed up message: \
+                             <code>/backup fetch link</code>.")]
+    #[custom(admin = true)]
+    Backup(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_backup)
+}
+
+/// Maximum length of a caption, in characters after entities parsing.
+const CAPTION_LIMIT: usize = 1024;
+
+pub async fn inspect_message(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Result<()> {
+    if !matches!(msg.kind, MessageKind::Common(_)) {
+        return Ok(());
+    }
+    let chats = &env.config.telegram.chats;
+    let Some(channel) = target(&chats.backup, chats.forward_channel, &msg)
+    else {
+        return Ok(());
+    };
+
+    // Replies to messages that were not mirrored, e.g. to the service
+    // message of the forum topic, are sent as standalone messages.
+    let parent = match msg.reply_to_message() {
+        Some(reply) => find_copy(&mut env.conn(), reply.chat.id, reply.id)?
+            .filter(|&(chat, _)| chat == channel)
+            .map(|(_, id)| id),
+        None => None,
+    };
+    let copy = mirror(&bot, &msg, channel, parent).await?;
+
+    diesel::replace_into(schema::backup_messages::table)
+        .values(models::BackupMessage {
+            chat_id: msg.chat.id.into(),
+            message_id: msg.id.into(),
+            backup_chat_id: channel.into(),
+            backup_message_id: copy.into(),
+        })
+        .execute(&mut *env.conn())?;
+
+    if let Some(conf) = &env.config.telegram.backup_media {
+        store_media(&bot, &env, conf, &msg).await?;
+    }
+    Ok(())
+}
+
+async fn cmd_backup(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Backup(args): Commands,
+) -> Result<()> {
+    // Either a link to the original message, or a reply to it.
+    let target = match args.trim().strip_prefix("fetch").map(str::trim) {
+        Some("") => msg.reply_to_message().map(|m| (m.chat.id, m.id)),
+        Some(link) => parse_tg_message_link(link),
+        None => None,
+    };
+    let Some((chat, message)) = target else {
+        bot.reply_message(&msg, "Usage: /backup fetch <message link>").await?;
+        return Ok(());
+    };
+    let Some(conf) = &env.config.telegram.backup_media else {
+        bot.reply_message(&msg, "Media backups are not configured.").await?;
+        return Ok(());
+    };
+    let media = schema::backup_media::table
+        .find((DbChatId::from(chat), DbMessageId::from(message)))
+        .select(models::BackupMedia::as_select())
+        .first(&mut *env.conn())
+        .optional()?;
+    let Some(media) = media else {
+        bot.reply_message(&msg, "No stored media for this message.").await?;
+        return Ok(());
+    };
+
+    let data = load(&env, &conf.storage, &media.storage_key).await?;
+    let file_name = media.file_name.unwrap_or(media.file_unique_id);
+    bot.reply_document(&msg, InputFile::memory(data).file_name(file_name))
+        .await?;
+    Ok(())
+}
+
+/// The channel to mirror the message into, according to the first rule
+/// matching its chat and topic.
+fn target(
+    rules: &[BackupRule],
+    forward_channel: ChatId,
+    msg: &Message,
+) -> Option<ChatId> {
+    let thread = msg.thread_id_ext();
+    let rule = rules.iter().find(|rule| {
+        rule.chat == msg.chat.id
+            && rule.thread.map_or(true, |t| thread == Some(t))
+    })?;
+    let accepted = !rule.exclude
+        && !(rule.skip_bots && msg.from.as_ref().is_some_and(|u| u.is_bot))
+        && match rule.only {
+            None => true,
+            Some(BackupOnly::Media) => is_media(msg),
+            Some(BackupOnly::Announcements) => msg.sender_chat.is_some(),
+        };
+    accepted.then(|| rule.channel.unwrap_or(forward_channel))
+}
+
+/// The copy of the message in the backup channel, if any.
+pub fn find_copy(
+    conn: &mut SqliteConnection,
+    chat: ChatId,
+    message: MessageId,
+) -> QueryResult<Option<(ChatId, MessageId)>> {
+    schema::backup_messages::table
+        .filter(schema::backup_messages::chat_id.eq(DbChatId::from(chat)))
+        .filter(
+            schema::backup_messages::message_id.eq(DbMessageId::from(message)),
+        )
+        .select((
+            schema::backup_messages::backup_chat_id,
+            schema::backup_messages::backup_message_id,
+        ))
+        .first::<(DbChatId, DbMessageId)>(conn)
+        .optional()
+        .map(|copy| copy.map(|(chat, id)| (chat.into(), id.into())))
+}
+
+async fn mirror(
+    bot: &Bot,
+    msg: &Message,
+    channel: ChatId,
+    parent: Option<MessageId>,
+) -> Result<MessageId> {
+    let (header, header_len) = header(msg);
+
+    if let Some(text) = msg.text() {
+        let text =
+            html::format_entities(text, msg.entities().unwrap_or_default());
+        let mut request = bot
+            .send_message(channel, format!("{header}\n{text}"))
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true);
+        request.reply_to_message_id = parent;
+        request.allow_sending_without_reply = Some(true);
+        return Ok(request.await?.id);
+    }
+
+    let mut request = bot.copy_message(channel, msg.chat.id, msg.id);
+    let caption = msg.caption().unwrap_or_default();
+    if can_have_caption(msg)
+        && header_len + 1 + caption.chars().count() <= CAPTION_LIMIT
+    {
+        let entities = msg.caption_entities().unwrap_or_default();
+        request.caption = Some(format!(
+            "{header}\n{}",
+            html::format_entities(caption, entities),
+        ));
+        request.parse_mode = Some(ParseMode::Html);
+    }
+    request.reply_to_message_id = parent;
+    request.allow_sending_without_reply = Some(true);
+    Ok(request.await?)
+}
+
+/// The author with a link to the original message, and its length in
+/// characters after entities parsing.
+fn header(msg: &Message) -> (String, usize) {
+    let author = match (&msg.from, &msg.sender_chat) {
+        // Anonymous admins and channels post on behalf of a chat.
+        (_, Some(chat)) => chat.title().unwrap_or_default().to_string(),
+        (Some(user), None) => user.full_name(),
+        (None, None) => String::new(),
+    };
+    let mut header = format!("<b>{}</b> ", html::escape(&author));
+    write_message_link(&mut header, msg.chat.id, msg.id);
+    header.push_str("↗</a>");
+    (header, author.chars().count() + 2)
+}
+
+/// A file attached to a message.
+struct Media<'a> {
+    file: &'a FileMeta,
+    file_name: Option<String>,
+    mime_type: Option<String>,
+}
+
+fn media(msg: &Message) -> Option<Media<'_>> {
+    let (file, file_name, mime_type) =
+        if let Some(photo) = msg.photo().and_then(<[_]>::last) {
+            // Sizes are sorted, the last one is the original.
+            (&photo.file, None, Some("image/jpeg".to_owned()))
+        } else if let Some(d) = msg.document() {
+            (
+                &d.file,
+                d.file_name.clone(),
+                d.mime_type.as_ref().map(ToString::to_string),
+            )
+        } else if let Some(v) = msg.video() {
+            (
+                &v.file,
+                v.file_name.clone(),
+                v.mime_type.as_ref().map(ToString::to_string),
+            )
+        } else if let Some(a) = msg.animation() {
+            (
+                &a.file,
+                a.file_name.clone(),
+                a.mime_type.as_ref().map(ToString::to_string),
+            )
+        } else if let Some(a) = msg.audio() {
+            (
+                &a.file,
+                a.file_name.clone(),
+                a.mime_type.as_ref().map(ToString::to_string),
+            )
+        } else if let Some(v) = msg.voice() {
+            (&v.file, None, v.mime_type.as_ref().map(ToString::to_string))
+        } else if let Some(v) = msg.video_note() {
+            (&v.file, None, Some("video/mp4".to_owned()))
+        } else if let Some(s) = msg.sticker() {
+            (&s.file, None, None)
+        } else {
+            return None;
+        };
+    Some(Media { file, file_name, mime_type })
+}
+
+/// Download the media of the message into the storage.
+async fn store_media(
+    bot: &Bot,
+    env: &BotEnv,
+    conf: &BackupMedia,
+    msg: &Message,
+) -> Result<()> {
+    let Some(media) = media(msg) else { return Ok(()) };
+    if u64::from(media.file.size) > u64::from(conf.max_size_mb) << 20 {
+        log::info!(
+            "backup: not storing {} bytes of media of message {} in {}",
+            media.file.size,
+            msg.id,
+            msg.chat.id,
+        );
+        return Ok(());
+    }
+
+    let file = bot.get_file(&media.file.id).await?;
+    let mut data = Vec::new();
+    bot.download_file(&file.path, &mut data).await?;
+    let size = data.len().try_into()?;
+    // Unique IDs are URL-safe, so keys need no escaping.
+    let key = format!("{}/{}/{}", msg.chat.id, msg.id, media.file.unique_id);
+    store(env, &conf.storage, &key, data).await?;
+
+    diesel::replace_into(schema::backup_media::table)
+        .values(models::BackupMedia {
+            chat_id: msg.chat.id.into(),
+            message_id: msg.id.into(),
+            file_unique_id: media.file.unique_id.clone(),
+            file_name: media.file_name,
+            mime_type: media.mime_type,
+            size,
+            storage_key: key,
+            stored_at: chrono::Utc::now().naive_utc(),
+        })
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+async fn store(
+    env: &BotEnv,
+    storage: &MediaStorage,
+    key: &str,
+    data: Vec<u8>,
+) -> Result<()> {
+    match storage {
+        MediaStorage::Directory { path } => {
+            let path = path.join(key);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, data)
+                .with_context(|| format!("writing {}", path.display()))?;
+        }
+        MediaStorage::S3(conf) => {
+            s3_request(env, conf, reqwest::Method::PUT, key, data)?
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+    }
+    Ok(())
+}
+
+async fn load(
+    env: &BotEnv,
+    storage: &MediaStorage,
+    key: &str,
+) -> Result<Vec<u8>> {
+    match storage {
+        MediaStorage::Directory { path } => {
+            let path = path.join(key);
+            std::fs::read(&path)
+                .with_context(|| format!("reading {}", path.display()))
+        }
+        MediaStorage::S3(conf) => {
+            let response =
+                s3_request(env, conf, reqwest::Method::GET, key, Vec::new())?
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            Ok(response.bytes().await?.to_vec())
+        }
+    }
+}
+
+const S3_SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+/// Build a request to the object signed with AWS Signature Version 4.
+fn s3_request(
+    env: &BotEnv,
+    conf: &S3Storage,
+    method: reqwest::Method,
+    key: &str,
+    body: Vec<u8>,
+) -> Result<reqwest::RequestBuilder> {
+    let url = reqwest::Url::parse(&format!(
+        "{}/{}/{key}",
+        conf.endpoint.trim_end_matches('/'),
+        conf.bucket,
+    ))?;
+    let host = url.host_str().context("S3 endpoint has no host")?;
+    let host = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_owned(),
+    };
+
+    let now = chrono::Utc::now();
+    let date = now.format("%Y%m%d").to_string();
+    let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let payload_hash = hex::encode(Sha256::digest(&body));
+    let canonical_request = format!(
+        "{method}\n{}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\n\
+         x-amz-date:{timestamp}\n\n{S3_SIGNED_HEADERS}\n{payload_hash}",
+        url.path(),
+    );
+    let scope = format!("{date}/{}/s3/aws4_request", conf.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request)),
+    );
+    let signing_key = [date.as_str(), &conf.region, "s3", "aws4_request"]
+        .into_iter()
+        .fold(format!("AWS4{}", conf.secret_key).into_bytes(), |key, part| {
+            hmac_sha256(&key, part)
+        });
+    let signature = hex::encode(hmac_sha256(&signing_key, &string_to_sign));
+
+    Ok(env
+        .reqwest_client
+        .request(method, url)
+        .header("x-amz-content-sha256", payload_hash)
+        .header("x-amz-date", timestamp)
+        .header(
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{scope}, \
+                 SignedHeaders={S3_SIGNED_HEADERS}, Signature={signature}",
+                conf.access_key,
+            ),
+        )
+        .body(body))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn is_media(msg: &Message) -> bool {
+    media(msg).is_some()
+}
+
+fn can_have_caption(msg: &Message) -> bool {
+    msg.photo().is_some()
+        || msg.video().is_some()
+        || msg.animation().is_some()
+        || msg.audio().is_some()
+        || msg.document().is_some()
+        || msg.voice().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    fn message(update: &serde_json::Value) -> Message {
+        serde_json::from_value(update["message"].clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_backup() {
+        let t = TestBot::new();
+        let channel = t.env.config.telegram.chats.forward_channel;
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+
+        let first = testing::message(CHAT, None, &alice, "Hi <all>");
+        inspect_message(t.bot.clone(), Arc::clone(&t.env), message(&first))
+            .await
+            .unwrap();
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["chat_id"], channel.0);
+        let text = sent[0]["text"].as_str().unwrap();
+        assert!(text.starts_with("<b>Alice</b> <a href="), "{text}");
+        assert!(text.ends_with("↗</a>\nHi &lt;all&gt;"), "{text}");
+        assert!(sent[0]["reply_to_message_id"].is_null());
+        let first_copy = t.telegram.results("sendMessage")[0]["message_id"]
+            .as_i64()
+            .unwrap();
+
+        // Replies are linked to the copies of their parents.
+        let mut reply = testing::message(CHAT, None, &bob, "");
+        reply["message"].as_object_mut().unwrap().remove("text");
+        reply["message"]["location"] =
+            json!({ "latitude": 1.0, "longitude": 2.0 });
+        reply["message"]["reply_to_message"] = first["message"].clone();
+        t.telegram.respond("copyMessage", json!({ "message_id": 7000 }));
+        let reply = message(&reply);
+        inspect_message(t.bot.clone(), Arc::clone(&t.env), reply.clone())
+            .await
+            .unwrap();
+        let copies = t.telegram.calls("copyMessage");
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0]["reply_to_message_id"], first_copy);
+        assert!(copies[0]["caption"].is_null());
+        assert_eq!(
+            find_copy(&mut t.env.conn(), ChatId(CHAT), reply.id).unwrap(),
+            Some((channel, MessageId(7000))),
+        );
+
+        // Other chats are not mirrored.
+        t.telegram.clear();
+        let other = testing::message(-1, None, &alice, "Hi");
+        inspect_message(t.bot.clone(), Arc::clone(&t.env), message(&other))
+            .await
+            .unwrap();
+        assert!(t.telegram.calls("sendMessage").is_empty());
+    }
+
+    #[test]
+    fn test_target() {
+        let (default, media) = (ChatId(-100), ChatId(-200));
+        let rule = |thread: Option<i32>, exclude, only, skip_bots, channel| {
+            BackupRule {
+                chat: ChatId(CHAT),
+                thread: thread.map(|t| ThreadId(MessageId(t))),
+                exclude,
+                only,
+                skip_bots,
+                channel,
+            }
+        };
+        let rules = [
+            rule(Some(10), true, None, false, None),
+            rule(Some(20), false, Some(BackupOnly::Media), false, Some(media)),
+            rule(None, false, None, true, None),
+        ];
+        let alice = testing::user_json(1, "Alice");
+        let target_of = |thread: Option<i64>, from: &serde_json::Value| {
+            let update = testing::message(CHAT, thread, from, "Hi");
+            target(&rules, default, &message(&update))
+        };
+
+        assert_eq!(target_of(None, &alice), Some(default));
+        assert_eq!(target_of(Some(10), &alice), None);
+        // Text is not media.
+        assert_eq!(target_of(Some(20), &alice), None);
+        let mut photo = testing::message(CHAT, Some(20), &alice, "");
+        photo["message"].as_object_mut().unwrap().remove("text");
+        photo["message"]["photo"] = json!([{
+            "file_id": "a", "file_unique_id": "a", "width": 1, "height": 1,
+        }]);
+        assert_eq!(target(&rules, default, &message(&photo)), Some(media));
+        let mut bot = alice.clone();
+        bot["is_bot"] = true.into();
+        assert_eq!(target_of(Some(30), &bot), None);
+        let other = testing::message(-1, None, &alice, "Hi");
+        assert_eq!(target(&rules, default, &message(&other)), None);
+    }
+
+    #[tokio::test]
+    async fn test_directory_storage() {
+        let t = TestBot::new();
+        let path = std::env::temp_dir()
+            .join(format!("botka-media-{}", std::process::id()));
+        let storage = MediaStorage::Directory { path: path.clone() };
+        store(&t.env, &storage, "-100/5/abc", b"data".to_vec()).await.unwrap();
+        assert_eq!(
+            load(&t.env, &storage, "-100/5/abc").await.unwrap(),
+            b"data"
+        );
+        assert!(load(&t.env, &storage, "-100/6/abc").await.is_err());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,569 @@
+//! Secret ballots for sensitive decisions.  Each active resident receives a
+//! ballot in private messages; only the voting progress is shown publicly, and
+//! tallies are revealed when the creator closes the ballot.
+//!
+//! Individual choices are never stored: the database keeps only the list of
+//! residents who voted, and aggregate counters for each option.  Vote button
+//! presses are redacted with [`redact_update`] before they reach the trace
+//! log.
+//!
+//! **Scope**: `/ballot` command, available to residents in group chats.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+
+use crate::common::{
+    filter_command, is_resident, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _, Sqlizer};
+use crate::{models, schema};
+
+/// Maximum number of options in a ballot.
+const MAX_OPTIONS: usize = 10;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "create a secret ballot: <code>/ballot create \
+                       Question</code>, then one option per line.")]
+    #[custom(resident = true, in_private = false)]
+    Ballot(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_ballot)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+async fn cmd_ballot(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Ballot(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(args) = args.trim_start().strip_prefix("create") else {
+        bot.reply_message(
+            &msg,
+            "Usage: /ballot create Question\nOption 1\nOption 2\n...",
+        )
+        .await?;
+        return Ok(());
+    };
+    let mut lines = args.lines().map(str::trim).filter(|l| !l.is_empty());
+    let question = lines.next().unwrap_or_default().to_string();
+    let options = lines.map(str::to_string).collect::<Vec<_>>();
+    if question.is_empty() || !(2..=MAX_OPTIONS).contains(&options.len()) {
+        bot.reply_message(
+            &msg,
+            format!(
+                "A ballot must have a question and from 2 to {MAX_OPTIONS} \
+                 options, one per line."
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let residents: Vec<DbUserId> = schema::residents::table
+        .filter(schema::residents::end_date.is_null())
+        .select(schema::residents::tg_id)
+        .distinct()
+        .load(&mut *env.conn())?;
+
+    let public = bot
+        .reply_message(
+            &msg,
+            progress_text(&question, &options, 0, residents.len()),
+        )
+        .parse_mode(ParseMode::Html)
+        .reply_markup(close_keyboard())
+        .await?;
+
+    let ballot_id = env.transaction(|conn| {
+        diesel::insert_into(schema::ballots::table)
+            .values(models::NewBallot {
+                chat_id: public.chat.id.into(),
+                message_id: public.id.into(),
+                creator_id: from.id.into(),
+                question: &question,
+                options: Sqlizer::new(options.clone()).map_err(|e| {
+                    diesel::result::Error::SerializationError(Box::new(e))
+                })?,
+                created_at: chrono::Utc::now().naive_utc(),
+            })
+            .execute(conn)?;
+        schema::ballots::table
+            .filter(schema::ballots::chat_id.eq(DbChatId::from(public.chat.id)))
+            .filter(
+                schema::ballots::message_id.eq(DbMessageId::from(public.id)),
+            )
+            .select(schema::ballots::rowid)
+            .first::<i32>(conn)
+    })?;
+
+    let mut text = String::new();
+    format_to!(
+        text,
+        "🗳 <b>Secret ballot</b> by {}:\n\n{}\n\n\
+         Your choice is not stored and can't be changed later.",
+        html::escape(&from.full_name()),
+        html::escape(&question),
+    );
+    let keyboard = InlineKeyboardMarkup::new(options.iter().enumerate().map(
+        |(i, option)| {
+            [InlineKeyboardButton::callback(
+                option,
+                format!("{VOTE_PREFIX}{ballot_id}:{i}"),
+            )]
+        },
+    ));
+    let mut undelivered = 0;
+    for resident in residents {
+        let result = bot
+            .send_message(UserId::from(resident), &text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(keyboard.clone())
+            .await;
+        if let Err(e) = result {
+            log::warn!("ballots: failed to send ballot to {resident:?}: {e}");
+            undelivered += 1;
+        }
+    }
+    if undelivered > 0 {
+        bot.reply_message(
+            &public,
+            format!(
+                "Failed to deliver {undelivered} ballot(s). Residents should \
+                 start a private chat with the bot to receive them."
+            ),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum CallbackData {
+    Vote { ballot_id: i32, option: i32 },
+    Close,
+}
+
+/// Prefix of the callback data of vote buttons.
+const VOTE_PREFIX: &str = "sb:vote:";
+
+/// Strip a press of a vote button from `update`, a raw update as received
+/// from Telegram, so it can be logged.  The chosen option, the voter, and
+/// their private chat with the bot are all removed.
+pub fn redact_update(update: &mut serde_json::Value) {
+    let Some(callback) = update.get_mut("callback_query") else { return };
+    if !callback["data"].as_str().is_some_and(|d| d.starts_with(VOTE_PREFIX)) {
+        return;
+    }
+    let id = callback["id"].take();
+    *callback = serde_json::json!({
+        "id": id,
+        "from": { "id": 0, "is_bot": false, "first_name": "Redacted" },
+        "chat_instance": "",
+        "data": format!("{VOTE_PREFIX}redacted"),
+    });
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<CallbackData> {
+    let data = callback.data.as_ref()?.strip_prefix("sb:")?;
+    if data == "close" {
+        return Some(CallbackData::Close);
+    }
+    let (ballot_id, option) = data.strip_prefix("vote:")?.split_once(':')?;
+    Some(CallbackData::Vote {
+        ballot_id: ballot_id.parse().ok()?,
+        option: option.parse().ok()?,
+    })
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    data: CallbackData,
+) -> Result<()> {
+    match data {
+        CallbackData::Vote { ballot_id, option } => {
+            handle_vote(bot, env, callback, ballot_id, option).await
+        }
+        CallbackData::Close => handle_close(bot, env, callback).await,
+    }
+}
+
+async fn handle_vote(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    ballot_id: i32,
+    option: i32,
+) -> Result<()> {
+    let result = env.transaction(|conn| {
+        let ballot = schema::ballots::table
+            .filter(schema::ballots::rowid.eq(ballot_id))
+            .select(models::Ballot::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(ballot) = ballot else {
+            return Ok(Err("Unknown ballot."));
+        };
+        if ballot.closed_at.is_some() {
+            return Ok(Err("This ballot is closed."));
+        }
+        if usize::try_from(option).map_or(true, |o| o >= ballot.options.len()) {
+            return Ok(Err("Unknown option."));
+        }
+        if !is_resident(conn, &callback.from) {
+            return Ok(Err("Only residents can vote."));
+        }
+
+        let inserted =
+            diesel::insert_or_ignore_into(schema::ballot_voters::table)
+                .values((
+                    schema::ballot_voters::ballot_id.eq(ballot_id),
+                    schema::ballot_voters::user_id
+                        .eq(DbUserId::from(callback.from.id)),
+                ))
+                .execute(conn)?;
+        if inserted == 0 {
+            return Ok(Err("You have already voted."));
+        }
+
+        {
+            use schema::ballot_tallies::dsl as t;
+            diesel::insert_into(t::ballot_tallies)
+                .values((
+                    t::ballot_id.eq(ballot_id),
+                    t::option.eq(option),
+                    t::votes.eq(1),
+                ))
+                .on_conflict((t::ballot_id, t::option))
+                .do_update()
+                .set(t::votes.eq(t::votes + 1))
+                .execute(conn)?;
+        }
+
+        let voted = schema::ballot_voters::table
+            .filter(schema::ballot_voters::ballot_id.eq(ballot_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        let residents = schema::residents::table
+            .filter(schema::residents::end_date.is_null())
+            .select(schema::residents::tg_id)
+            .distinct()
+            .count()
+            .get_result::<i64>(conn)?;
+        Ok(Ok((ballot, voted, residents)))
+    })?;
+
+    let (ballot, voted, residents) = match result {
+        Ok(result) => result,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+
+    bot.answer_callback_query(&callback.id).text("Vote recorded.").await?;
+    if let Some(message) = &callback.message {
+        bot.edit_message_text(
+            message.chat.id,
+            message.id,
+            format!(
+                "🗳 Your vote in the ballot “{}” is recorded.",
+                html::escape(&ballot.question),
+            ),
+        )
+        .parse_mode(ParseMode::Html)
+        .await
+        .log_error("ballots: edit ballot message");
+    }
+
+    bot.edit_message_text(
+        ballot.chat_id,
+        ballot.message_id.into(),
+        progress_text(
+            &ballot.question,
+            &ballot.options,
+            voted.try_into()?,
+            residents.try_into()?,
+        ),
+    )
+    .parse_mode(ParseMode::Html)
+    .reply_markup(close_keyboard())
+    .await?;
+
+    Ok(())
+}
+
+async fn handle_close(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+) -> Result<()> {
+    let Some(message) = &callback.message else { return Ok(()) };
+    let result = env.transaction(|conn| {
+        let ballot = schema::ballots::table
+            .filter(
+                schema::ballots::chat_id.eq(DbChatId::from(message.chat.id)),
+            )
+            .filter(
+                schema::ballots::message_id.eq(DbMessageId::from(message.id)),
+            )
+            .select(models::Ballot::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(ballot) = ballot else {
+            return Ok(Err("Unknown ballot."));
+        };
+        if UserId::from(ballot.creator_id) != callback.from.id {
+            return Ok(Err("Only the creator can close this ballot."));
+        }
+        if ballot.closed_at.is_some() {
+            return Ok(Err("This ballot is already closed."));
+        }
+        diesel::update(schema::ballots::table)
+            .filter(schema::ballots::rowid.eq(ballot.rowid))
+            .set(schema::ballots::closed_at.eq(chrono::Utc::now().naive_utc()))
+            .execute(conn)?;
+        let tallies = schema::ballot_tallies::table
+            .filter(schema::ballot_tallies::ballot_id.eq(ballot.rowid))
+            .select((
+                schema::ballot_tallies::option,
+                schema::ballot_tallies::votes,
+            ))
+            .load::<(i32, i32)>(conn)?;
+        Ok(Ok((ballot, tallies)))
+    })?;
+
+    let (ballot, tallies) = match result {
+        Ok(result) => result,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+
+    bot.answer_callback_query(&callback.id).await?;
+    bot.edit_message_text(
+        message.chat.id,
+        message.id,
+        results_text(&ballot.question, &ballot.options, &tallies),
+    )
+    .parse_mode(ParseMode::Html)
+    .await?;
+
+    Ok(())
+}
+
+fn close_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "Close ballot",
+        "sb:close",
+    )]])
+}
+
+fn progress_text(
+    question: &str,
+    options: &[String],
+    voted: usize,
+    residents: usize,
+) -> String {
+    let mut text = String::new();
+    format_to!(text, "🗳 <b>Secret ballot</b>: {}\n\n", html::escape(question));
+    for option in options {
+        format_to!(text, "• {}\n", html::escape(option));
+    }
+    format_to!(
+        text,
+        "\nVoted {voted} of {residents} residents. Ballots are sent in \
+         private messages.",
+    );
+    text
+}
+
+fn results_text(
+    question: &str,
+    options: &[String],
+    tallies: &[(i32, i32)],
+) -> String {
+    let votes = |i: usize| {
+        tallies
+            .iter()
+            .find(|(o, _)| usize::try_from(*o).ok() == Some(i))
+            .map_or(0, |(_, v)| *v)
+    };
+    let total = tallies.iter().map(|(_, v)| v).sum::<i32>();
+
+    let mut text = String::new();
+    format_to!(
+        text,
+        "🗳 <b>Secret ballot (closed)</b>: {}\n\n",
+        html::escape(question),
+    );
+    for (i, option) in options.iter().enumerate() {
+        format_to!(text, "{:>3} — {}\n", votes(i), html::escape(option));
+    }
+    format_to!(text, "\nTotal voters: {total}");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    #[test]
+    fn test_results_text() {
+        let options = ["Yes".to_string(), "No".to_string(), "<3".to_string()];
+        assert_eq!(
+            results_text("Q?", &options, &[(2, 1), (0, 3)]),
+            "🗳 <b>Secret ballot (closed)</b>: Q?\n\n  3 — Yes\n  0 — No\n  \
+             1 — &lt;3\n\nTotal voters: 4",
+        );
+    }
+
+    #[test]
+    fn test_redact_update() {
+        let alice = testing::user_json(1, "Alice");
+        let message = json!({ "chat": { "id": 1, "type": "private" } });
+        let mut vote = testing::callback(&alice, &message, "sb:vote:3:1");
+        redact_update(&mut vote);
+        assert_eq!(vote["callback_query"]["data"], "sb:vote:redacted");
+        assert_eq!(vote["callback_query"]["from"]["id"], 0);
+        assert!(vote["callback_query"].get("message").is_none());
+        assert!(!vote.to_string().contains("Alice"));
+
+        let close = testing::callback(&alice, &message, "sb:close");
+        let mut redacted = close.clone();
+        redact_update(&mut redacted);
+        assert_eq!(redacted, close);
+    }
+
+    #[tokio::test]
+    async fn test_ballot() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+
+        t.dispatch(
+            &command_handler(),
+            testing::message(
+                CHAT,
+                None,
+                &alice,
+                "/ballot create Lunch?\nPizza\nSushi",
+            ),
+        )
+        .await;
+        let sent = t.telegram.results("sendMessage");
+        let public = sent.iter().find(|m| m["chat"]["id"] == CHAT).unwrap();
+        assert!(public["text"].as_str().unwrap().contains("Voted 0 of 2"));
+        let ballot_of = |id: i64| {
+            sent.iter().find(|m| m["chat"]["id"] == id).unwrap().clone()
+        };
+        let vote_data = |ballot: &serde_json::Value, option: usize| {
+            ballot["reply_markup"]["inline_keyboard"][option][0]
+                ["callback_data"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+        let alice_ballot = ballot_of(1);
+        let bob_ballot = ballot_of(2);
+
+        t.telegram.clear();
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(
+                &alice,
+                &alice_ballot,
+                &vote_data(&alice_ballot, 1),
+            ),
+        )
+        .await;
+        let answers = t.telegram.calls("answerCallbackQuery");
+        assert_eq!(answers[0]["text"], "Vote recorded.");
+        let edited = t.telegram.calls("editMessageText");
+        assert!(edited
+            .iter()
+            .any(|e| e["text"].as_str().unwrap().contains("Voted 1 of 2")));
+
+        // A second press, even of another option, is not counted.
+        t.telegram.clear();
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(
+                &alice,
+                &alice_ballot,
+                &vote_data(&alice_ballot, 0),
+            ),
+        )
+        .await;
+        let answers = t.telegram.calls("answerCallbackQuery");
+        assert_eq!(answers[0]["text"], "You have already voted.");
+
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(&bob, &bob_ballot, &vote_data(&bob_ballot, 1)),
+        )
+        .await;
+
+        // Only the creator can close the ballot.
+        t.telegram.clear();
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(&bob, public, "sb:close"),
+        )
+        .await;
+        let answers = t.telegram.calls("answerCallbackQuery");
+        assert_eq!(
+            answers[0]["text"],
+            "Only the creator can close this ballot."
+        );
+
+        t.telegram.clear();
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(&alice, public, "sb:close"),
+        )
+        .await;
+        let edited = t.telegram.calls("editMessageText");
+        assert_eq!(
+            edited[0]["text"],
+            "🗳 <b>Secret ballot (closed)</b>: Lunch?\n\n  0 — Pizza\n  \
+             2 — Sushi\n\nTotal voters: 2",
+        );
+
+        // No votes after the ballot is closed.
+        t.telegram.clear();
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(&bob, &bob_ballot, &vote_data(&bob_ballot, 0)),
+        )
+        .await;
+        let answers = t.telegram.calls("answerCallbackQuery");
+        assert_eq!(answers[0]["text"], "This ballot is closed.");
+    }
+}
@@ -0,0 +1,359 @@
+//! Bandwidth usage report.
+//!
+//! Traffic counters of kid control devices are periodically fetched from the
+//! `MikroTik` router, and the increments are accumulated per device per day.
+//! `/bandwidth [day|week]` shows the top consumers, mapping devices to
+//! residents with the MAC addresses set by `/userctl`.  With
+//! [`telegram.bandwidth.privacy`] enabled, only aggregated numbers are shown.
+//!
+//! **Scope**: `/bandwidth` command, available to residents; background task.
+//!
+//! [`telegram.bandwidth.privacy`]: crate::config::Bandwidth::privacy
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::db::DbUserId;
+use crate::utils::mikrotik::{self, DeviceTraffic};
+use crate::utils::{format_to, BotExt as _};
+use crate::{models, schema};
+
+/// How often to fetch the traffic counters.
+const PULL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum number of residents listed in a report.
+const TOP_USERS: usize = 10;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show top bandwidth consumers: \
+                             <code>/bandwidth [day|week]</code>.")]
+    #[custom(resident = true)]
+    Bandwidth(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_bandwidth)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Traffic {
+    down: i64,
+    up: i64,
+}
+
+impl Traffic {
+    const fn total(self) -> i64 {
+        self.down + self.up
+    }
+
+    fn add(&mut self, other: Self) {
+        self.down += other.down;
+        self.up += other.up;
+    }
+}
+
+async fn cmd_bandwidth(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Bandwidth(args): Commands,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.bandwidth else {
+        bot.reply_message(&msg, "Bandwidth reports are not configured.")
+            .await?;
+        return Ok(());
+    };
+    let today = chrono::Utc::now().date_naive();
+    let (since, period) = match args.trim() {
+        "day" => (today, "today"),
+        "" | "week" => (today - chrono::Duration::days(6), "last 7 days"),
+        _ => {
+            bot.reply_message(&msg, "Usage: /bandwidth [day|week]").await?;
+            return Ok(());
+        }
+    };
+
+    let text = env.transaction(|conn| {
+        let usage: Vec<(String, i64, i64)> = schema::bandwidth_usage::table
+            .filter(schema::bandwidth_usage::date.ge(since))
+            .select((
+                schema::bandwidth_usage::mac,
+                schema::bandwidth_usage::bytes_down,
+                schema::bandwidth_usage::bytes_up,
+            ))
+            .load(conn)?;
+        let owners: HashMap<String, DbUserId> = schema::user_macs::table
+            .filter(schema::user_macs::mac.eq_any(usage.iter().map(|u| &u.0)))
+            .select((schema::user_macs::mac, schema::user_macs::tg_id))
+            .load(conn)?
+            .into_iter()
+            .collect();
+        let (users, unassigned) = aggregate(
+            usage
+                .into_iter()
+                .map(|(mac, down, up)| (mac, Traffic { down, up })),
+            &owners,
+        );
+
+        let mut residents = Traffic::default();
+        users.iter().for_each(|u| residents.add(u.1));
+        let mut total = residents;
+        total.add(unassigned);
+
+        let mut text = format!("📶 <b>Bandwidth usage</b>, {period}\n");
+        format_to!(text, "\nTotal: {}", format_traffic(total));
+        if conf.privacy {
+            format_to!(
+                text,
+                "\nResidents ({}): {}\nOther devices: {}",
+                users.len(),
+                format_traffic(residents),
+                format_traffic(unassigned),
+            );
+            return Ok(text);
+        }
+
+        let top = &users[..users.len().min(TOP_USERS)];
+        let tg_users: HashMap<DbUserId, models::TgUser> =
+            schema::tg_users::table
+                .filter(schema::tg_users::id.eq_any(top.iter().map(|u| u.0)))
+                .select(models::TgUser::as_select())
+                .load(conn)?
+                .into_iter()
+                .map(|u| (u.id, u))
+                .collect();
+        if !top.is_empty() {
+            text.push_str("\n\nTop consumers:");
+        }
+        for (user, traffic) in top {
+            text.push_str("\n• ");
+            format_user(&mut text, *user, tg_users.get(user), false);
+            format_to!(text, ": {}", format_traffic(*traffic));
+        }
+        if unassigned.total() > 0 {
+            format_to!(
+                text,
+                "\n\nUnassigned devices: {}",
+                format_traffic(unassigned),
+            );
+        }
+        Ok(text)
+    })?;
+    bot.reply_message(&msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+/// Sum the traffic per user, sorted by the total in descending order, and
+/// the traffic of devices without an owner.
+fn aggregate(
+    usage: impl Iterator<Item = (String, Traffic)>,
+    owners: &HashMap<String, DbUserId>,
+) -> (Vec<(DbUserId, Traffic)>, Traffic) {
+    let mut users = HashMap::<DbUserId, Traffic>::new();
+    let mut unassigned = Traffic::default();
+    for (mac, traffic) in usage {
+        match owners.get(&mac) {
+            Some(user) => users.entry(*user).or_default().add(traffic),
+            None => unassigned.add(traffic),
+        }
+    }
+    let users = users
+        .into_iter()
+        .sorted_by_key(|(user, traffic)| {
+            (std::cmp::Reverse(traffic.total()), *user)
+        })
+        .collect_vec();
+    (users, unassigned)
+}
+
+fn format_traffic(traffic: Traffic) -> String {
+    format!(
+        "{} (↓ {}, ↑ {})",
+        format_bytes(traffic.total()),
+        format_bytes(traffic.down),
+        format_bytes(traffic.up),
+    )
+}
+
+/// Format the size with binary units and one decimal, e.g. `1.5 GiB`.
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut unit = 0;
+    let mut divisor = 1;
+    while unit + 1 < UNITS.len() && bytes >= divisor * 1024 {
+        divisor *= 1024;
+        unit += 1;
+    }
+    if unit == 0 {
+        return format!("{bytes} B");
+    }
+    let tenths = bytes * 10 / divisor;
+    format!("{}.{} {}", tenths / 10, tenths % 10, UNITS[unit])
+}
+
+pub async fn task(env: Arc<BotEnv>, shutdown: CancellationToken) {
+    if env.config.telegram.bandwidth.is_none() {
+        return;
+    }
+    loop {
+        if let Err(e) = pull(&env).await {
+            log::error!("bandwidth: pull failed: {e}");
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(PULL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Fetch the counters and add the increments to today's usage.
+async fn pull(env: &BotEnv) -> Result<()> {
+    let devices = mikrotik::get_device_traffic(
+        &env.health,
+        &env.reqwest_client,
+        &env.config.services.mikrotik,
+    )
+    .await?;
+    let now = chrono::Utc::now().naive_utc();
+    env.transaction(|conn| {
+        for device in &devices {
+            let mac = device.mac_address.to_uppercase();
+            let current = counters(device);
+            let prev: Option<(i64, i64)> = schema::bandwidth_counters::table
+                .filter(schema::bandwidth_counters::mac.eq(&mac))
+                .select((
+                    schema::bandwidth_counters::bytes_down,
+                    schema::bandwidth_counters::bytes_up,
+                ))
+                .first(conn)
+                .optional()?;
+            diesel::insert_into(schema::bandwidth_counters::table)
+                .values((
+                    schema::bandwidth_counters::mac.eq(&mac),
+                    schema::bandwidth_counters::bytes_down.eq(current.down),
+                    schema::bandwidth_counters::bytes_up.eq(current.up),
+                    schema::bandwidth_counters::updated_at.eq(now),
+                ))
+                .on_conflict(schema::bandwidth_counters::mac)
+                .do_update()
+                .set((
+                    schema::bandwidth_counters::bytes_down.eq(current.down),
+                    schema::bandwidth_counters::bytes_up.eq(current.up),
+                    schema::bandwidth_counters::updated_at.eq(now),
+                ))
+                .execute(conn)?;
+
+            // The first reading is only a baseline.
+            let Some((down, up)) = prev else { continue };
+            let delta = Traffic {
+                down: increment(down, current.down),
+                up: increment(up, current.up),
+            };
+            if delta.total() == 0 {
+                continue;
+            }
+            diesel::insert_into(schema::bandwidth_usage::table)
+                .values((
+                    schema::bandwidth_usage::date.eq(now.date()),
+                    schema::bandwidth_usage::mac.eq(&mac),
+                    schema::bandwidth_usage::bytes_down.eq(delta.down),
+                    schema::bandwidth_usage::bytes_up.eq(delta.up),
+                ))
+                .on_conflict((
+                    schema::bandwidth_usage::date,
+                    schema::bandwidth_usage::mac,
+                ))
+                .do_update()
+                .set((
+                    schema::bandwidth_usage::bytes_down
+                        .eq(schema::bandwidth_usage::bytes_down + delta.down),
+                    schema::bandwidth_usage::bytes_up
+                        .eq(schema::bandwidth_usage::bytes_up + delta.up),
+                ))
+                .execute(conn)?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn counters(device: &DeviceTraffic) -> Traffic {
+    Traffic {
+        down: i64::try_from(device.bytes_down).unwrap_or(i64::MAX),
+        up: i64::try_from(device.bytes_up).unwrap_or(i64::MAX),
+    }
+}
+
+/// Increment of a cumulative counter.  A decrease is treated as a reset.
+const fn increment(prev: i64, current: i64) -> i64 {
+    if current >= prev {
+        current - prev
+    } else {
+        current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate() {
+        let t = |down, up| Traffic { down, up };
+        let owners = HashMap::from([
+            ("AA".to_string(), DbUserId::from(UserId(1))),
+            ("BB".to_string(), DbUserId::from(UserId(2))),
+            ("CC".to_string(), DbUserId::from(UserId(2))),
+        ]);
+        let usage = [
+            ("AA".to_string(), t(100, 10)),
+            ("BB".to_string(), t(50, 5)),
+            ("CC".to_string(), t(60, 6)),
+            ("DD".to_string(), t(7, 1)),
+            ("AA".to_string(), t(1, 1)),
+        ];
+        let (users, unassigned) = aggregate(usage.into_iter(), &owners);
+        assert_eq!(
+            users,
+            [
+                (DbUserId::from(UserId(2)), t(110, 11)),
+                (DbUserId::from(UserId(1)), t(101, 11)),
+            ]
+        );
+        assert_eq!(unassigned, t(7, 1));
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_increment() {
+        assert_eq!(increment(100, 150), 50);
+        assert_eq!(increment(100, 30), 30);
+    }
+}
@@ -6,33 +6,31 @@ use std::io::Write as _;
 use std::path::Path;
 use std::process::Command;
 use std::sync::Arc;
-use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use chrono::{Datelike as _, NaiveDateTime, Utc};
 use diesel::prelude::*;
 use itertools::Itertools;
 use macro_rules_attribute::derive;
 use teloxide::prelude::*;
 use teloxide::types::{InputFile, ThreadId};
 use teloxide::utils::command::BotCommands;
-use teloxide::utils::html;
 
 use crate::common::{
-    filter_command, format_users, BotCommandsExt, BotCommandsExtTrait, BotEnv,
-    TopicEmojis, UpdateHandler,
+    filter_command, format_users, BotCommandsExt, BotEnv, TopicEmojis,
+    UpdateHandler,
 };
 use crate::db::{DbChatId, DbUserId};
-use crate::utils::{write_message_link, BotExt};
+use crate::utils::{html, mikrotik, write_message_link, BotExt};
 use crate::{models, schema};
 
 #[derive(Clone, BotCommands, BotCommandsExt!)]
 #[command(rename_rule = "snake_case")]
 pub enum Commands {
-    #[command(description = "display this text.")]
-    Help,
-
-    #[command(description = "list residents.")]
-    Residents,
+    #[command(description = "list residents, or show their history with \
+                             <code>/residents timeline</code> or \
+                             <code>/residents stats</code>.")]
+    Residents(String),
 
     #[command(description = "show residents admin table.")]
     #[custom(resident = true)]
@@ -42,15 +40,13 @@ pub enum Commands {
     #[custom(resident = true)]
     ResidentsTimeline,
 
-    #[command(description = "show status.")]
-    Status,
+    #[command(description = "show status, or service status with \
+                             <code>/status services</code>.")]
+    Status(String),
 
     #[command(description = "show topic list.")]
     #[custom(in_group = false)]
     Topics,
-
-    #[command(description = "show bot version.")]
-    Version,
 }
 
 pub fn command_handler() -> UpdateHandler {
@@ -64,66 +60,63 @@ async fn start<'a>(
     command: Commands,
 ) -> Result<()> {
     match command {
-        Commands::Help => cmd_help(bot, msg).await?,
-        Commands::Residents => cmd_list_residents(bot, env, msg).await?,
+        Commands::Residents(args) => {
+            cmd_residents(bot, env, msg, args.trim()).await?;
+        }
         Commands::ResidentsAdminTable => {
             cmd_residents_admin_table(bot, env, msg).await?;
         }
         Commands::ResidentsTimeline => {
-            cmd_show_residents_timeline(bot, msg).await?;
+            cmd_show_residents_timeline(bot, env, msg).await?;
         }
-        Commands::Status => cmd_status(bot, env, msg).await?,
-        Commands::Version => {
-            bot.reply_message(&msg, crate::version()).await?;
+        Commands::Status(args) if args.trim() == "services" => {
+            bot.reply_message(
+                &msg,
+                crate::modules::monitor::status_text(&env)?,
+            )
+            .parse_mode(teloxide::types::ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await?;
         }
+        Commands::Status(_) => cmd_status(bot, env, msg).await?,
         Commands::Topics => cmd_topics(bot, env, msg).await?,
     }
     Ok(())
 }
 
-async fn cmd_help(bot: Bot, msg: Message) -> Result<()> {
-    let mut text = String::new();
-    text.push_str("Available commands:\n\n");
-    text.push_str(&commands_help::<crate::modules::basic::Commands>());
-    text.push_str(&commands_help::<crate::modules::needs::Commands>());
-    text.push_str(&commands_help::<crate::modules::userctl::Commands>());
-    text.push_str("\nCommands marked with * are available only to residents.");
-    // "..., and with ** are available only to bot technicians."
-    bot.reply_message(&msg, text)
-        .parse_mode(teloxide::types::ParseMode::Html)
+async fn cmd_residents(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    args: &str,
+) -> Result<()> {
+    if args.is_empty() {
+        return cmd_list_residents(bot, env, msg).await;
+    }
+    if !["timeline", "stats"].contains(&args) {
+        bot.reply_message(
+            &msg,
+            "Usage: /residents, /residents timeline, or /residents stats.",
+        )
         .await?;
-    Ok(())
-}
-
-fn commands_help<T: BotCommands + BotCommandsExtTrait>() -> String {
-    let descriptions = T::descriptions().to_string();
-    let global_description =
-        descriptions.find("\n\n/").map(|i| &descriptions[..i]);
-
-    let mut result = String::new();
-    if let Some(global_description) = global_description {
-        result.push_str(global_description);
-        result.push('\n');
+        return Ok(());
     }
-    for (cmd, rules) in std::iter::zip(&T::bot_commands(), T::COMMAND_RULES) {
-        result.push_str(&cmd.command);
-        result.push_str(match (rules.admin, rules.resident) {
-            (true, _) => "**",
-            (false, true) => "*",
-            (false, false) => "",
-        });
-        result.push_str(match (rules.in_private, rules.in_group) {
-            (true, true) => "",
-            (true, false) => " (in private)",
-            (false, true) => " (not in private)",
-            (false, false) => " (disabled?)",
-        });
-        result.push_str(" — ");
-        result.push_str(&cmd.description);
-        result.push('\n');
+    let Some(from) = &msg.from else { return Ok(()) };
+    if !env.is_resident(from.id) {
+        bot.reply_message(&msg, "This command is only available to residents.")
+            .await?;
+        return Ok(());
+    }
+    if args == "timeline" {
+        cmd_show_residents_timeline(bot, env, msg).await
+    } else {
+        let periods = load_residency_periods(&env)?;
+        let text = residents_stats_text(&periods, Utc::now().naive_utc());
+        bot.reply_message(&msg, text)
+            .parse_mode(teloxide::types::ParseMode::Html)
+            .await?;
+        Ok(())
     }
-
-    result
 }
 
 async fn cmd_list_residents<'a>(
@@ -188,14 +181,38 @@ async fn cmd_residents_admin_table(
     Ok(())
 }
 
-async fn cmd_show_residents_timeline(bot: Bot, msg: Message) -> Result<()> {
+/// Reply with the timeline image, or with a text timeline if the image can't
+/// be rendered.
+async fn cmd_show_residents_timeline(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Result<()> {
+    match render_residents_timeline(&env.db_path) {
+        Ok(png) => {
+            bot.reply_photo(&msg, InputFile::memory(png)).await?;
+        }
+        Err(e) => {
+            log::warn!("Failed to render residents timeline: {e:#}");
+            let periods = load_residency_periods(&env)?;
+            let text =
+                residents_timeline_text(&periods, Utc::now().naive_utc());
+            bot.reply_message(&msg, text)
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Render the timeline as PNG using external tools.
+fn render_residents_timeline(db_path: &Path) -> Result<Vec<u8>> {
     let svg = Command::new("f0-residents-timeline")
         .arg("-sqlite")
-        .arg(crate::DB_FILENAME)
+        .arg(db_path)
         .output()?;
     if !svg.status.success() || !svg.stdout.starts_with(b"<svg") {
-        bot.reply_message(&msg, "Failed to generate timeline (svg).").await?;
-        return Ok(());
+        bail!("Failed to generate timeline (svg)");
     }
     let mut png = Command::new("convert")
         .arg("svg:-")
@@ -206,68 +223,160 @@ async fn cmd_show_residents_timeline(bot: Bot, msg: Message) -> Result<()> {
     png.stdin.take().unwrap().write_all(&svg.stdout)?;
     let png = png.wait_with_output()?;
     if !png.status.success() || !png.stdout.starts_with(b"\x89PNG") {
-        bot.reply_message(&msg, "Failed to generate timeline (png).").await?;
-        return Ok(());
+        bail!("Failed to generate timeline (png)");
     }
-    bot.reply_photo(&msg, InputFile::memory(png.stdout)).await?;
-    Ok(())
+    Ok(png.stdout)
 }
 
-async fn cmd_status(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
-    #[derive(serde::Deserialize, Debug)]
-    #[serde(rename_all = "kebab-case")]
-    struct Lease {
-        mac_address: String,
-        #[serde(deserialize_with = "crate::utils::deserealize_duration")]
-        last_seen: Duration,
+/// All residency periods, ordered by the begin date.
+fn load_residency_periods(
+    env: &BotEnv,
+) -> Result<Vec<(models::Resident, Option<models::TgUser>)>> {
+    Ok(schema::residents::table
+        .left_join(
+            schema::tg_users::table
+                .on(schema::residents::tg_id.eq(schema::tg_users::id)),
+        )
+        .select((
+            models::Resident::as_select(),
+            schema::tg_users::all_columns.nullable(),
+        ))
+        .order(schema::residents::begin_date)
+        .load(&mut *env.conn())?)
+}
+
+/// Width of the bars in [`residents_timeline_text`], in characters.
+const TIMELINE_WIDTH: u8 = 16;
+
+/// Width of the name column in [`residents_timeline_text`], in characters.
+const TIMELINE_NAME_WIDTH: usize = 8;
+
+/// A text timeline with a line per user, fitting a phone screen.
+fn residents_timeline_text(
+    periods: &[(models::Resident, Option<models::TgUser>)],
+    now: NaiveDateTime,
+) -> String {
+    let Some(start) = periods.iter().map(|(r, _)| r.begin_date).min() else {
+        return "No residents yet.".to_string();
+    };
+    let span = (now - start).num_seconds().max(1);
+    let column = |date: NaiveDateTime| {
+        let offset = (date - start).num_seconds().clamp(0, span - 1);
+        usize::try_from(offset * i64::from(TIMELINE_WIDTH) / span).unwrap()
+    };
+
+    let mut text = String::from("Residents timeline:\n<pre>");
+    writeln!(
+        text,
+        "{:name_width$} {:<half$}{:>half$}",
+        "",
+        start.year(),
+        now.year(),
+        name_width = TIMELINE_NAME_WIDTH,
+        half = usize::from(TIMELINE_WIDTH / 2),
+    )
+    .unwrap();
+    for tg_id in periods.iter().map(|(r, _)| r.tg_id).unique() {
+        let user_periods =
+            periods.iter().filter(|(r, _)| r.tg_id == tg_id).collect_vec();
+        let mut bar = vec![false; usize::from(TIMELINE_WIDTH)];
+        for (r, _) in &user_periods {
+            let end = r.end_date.unwrap_or(now);
+            for c in column(r.begin_date)..=column(end) {
+                bar[c] = true;
+            }
+        }
+        let name: String = user_periods[0].1.as_ref().map_or_else(
+            || UserId::from(tg_id).0.to_string(),
+            |u| u.first_name.chars().take(TIMELINE_NAME_WIDTH).collect(),
+        );
+        let last_end = user_periods.last().and_then(|(r, _)| r.end_date);
+        writeln!(
+            text,
+            "{} {} {}–{}",
+            html::escape(&format!(
+                "{name:width$}",
+                width = TIMELINE_NAME_WIDTH
+            )),
+            bar.iter().map(|&b| if b { '█' } else { '·' }).join(""),
+            user_periods[0].0.begin_date.format("%y.%m"),
+            last_end.map_or_else(
+                || "now".to_string(),
+                |d| d.format("%y.%m").to_string()
+            ),
+        )
+        .unwrap();
     }
+    text.push_str("</pre>");
+    text
+}
 
-    let conf = &env.config.services.mikrotik;
-    let leases = async {
-        env.reqwest_client
-            .post(format!(
-                "https://{}/rest/ip/dhcp-server/lease/print",
-                conf.host
-            ))
-            .timeout(Duration::from_secs(5))
-            .basic_auth(&conf.username, Some(&conf.password))
-            .json(&serde_json::json!({
-                ".proplist": [
-                    "mac-address",
-                    "last-seen",
-                ]
-            }))
-            .send()
-            .await?
-            .json::<Vec<Lease>>()
-            .await
+/// Counts by year and average tenure.
+fn residents_stats_text(
+    periods: &[(models::Resident, Option<models::TgUser>)],
+    now: NaiveDateTime,
+) -> String {
+    let Some(start) = periods.iter().map(|(r, _)| r.begin_date).min() else {
+        return "No residents yet.".to_string();
+    };
+
+    // Total tenure by user, with ongoing residencies counted until now.
+    let mut tenure_days: HashMap<DbUserId, (i64, bool)> = HashMap::new();
+    for (r, _) in periods {
+        let entry = tenure_days.entry(r.tg_id).or_default();
+        entry.0 += (r.end_date.unwrap_or(now) - r.begin_date).num_days();
+        entry.1 |= r.end_date.is_none();
     }
-    .await;
+    let current = tenure_days.values().filter(|(_, c)| *c).count();
+    let former = tenure_days.len() - current;
+
+    let mut text = String::new();
+    writeln!(text, "Current residents: {current}, former: {former}.").unwrap();
+    writeln!(
+        text,
+        "Average tenure: {:.1} years, {:.1} years for former residents.",
+        average_years(tenure_days.values().map(|(d, _)| *d)),
+        average_years(
+            tenure_days.values().filter(|(_, c)| !c).map(|(d, _)| *d)
+        ),
+    )
+    .unwrap();
+
+    text.push_str("\n<pre>Year  Joined  Left  Residents\n");
+    for year in start.year()..=now.year() {
+        let joined =
+            periods.iter().filter(|(r, _)| r.begin_date.year() == year).count();
+        let left = periods
+            .iter()
+            .filter(|(r, _)| r.end_date.is_some_and(|d| d.year() == year))
+            .count();
+        let residents = periods
+            .iter()
+            .filter(|(r, _)| {
+                r.begin_date.year() <= year
+                    && r.end_date.map_or(true, |d| d.year() >= year)
+            })
+            .map(|(r, _)| r.tg_id)
+            .unique()
+            .count();
+        writeln!(text, "{year}  {joined:>6}  {left:>4}  {residents:>9}")
+            .unwrap();
+    }
+    text.push_str("</pre>");
+    text
+}
 
-    crate::metrics::update_service("mikrotik", leases.is_ok());
+#[allow(clippy::cast_precision_loss)] // Rounding errors are fine here.
+fn average_years(tenure_days: impl Iterator<Item = i64>) -> f64 {
+    let (count, total) =
+        tenure_days.fold((0u32, 0), |(count, total), d| (count + 1, total + d));
+    total as f64 / 365.25 / f64::from(std::cmp::max(count, 1))
+}
 
+async fn cmd_status(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
     let mut text = String::new();
-    match leases {
-        Ok(leases) => {
-            let active_mac_addrs = leases
-                .into_iter()
-                .filter(|l| l.last_seen < Duration::from_secs(11 * 60))
-                .map(|l| l.mac_address)
-                .collect::<Vec<_>>();
-            let data: Vec<(DbUserId, Option<models::TgUser>)> =
-                schema::user_macs::table
-                    .left_join(
-                        schema::tg_users::table
-                            .on(schema::user_macs::tg_id
-                                .eq(schema::tg_users::id)),
-                    )
-                    .filter(schema::user_macs::mac.eq_any(&active_mac_addrs))
-                    .select((
-                        schema::user_macs::tg_id,
-                        schema::tg_users::all_columns.nullable(),
-                    ))
-                    .distinct()
-                    .load(&mut *env.conn())?;
+    match users_in_space(&env).await {
+        Ok(data) => {
             writeln!(&mut text, "Currently in space: ").unwrap();
             format_users(&mut text, data.iter().map(|(id, u)| (*id, u)));
         }
@@ -284,6 +393,32 @@ async fn cmd_status(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
     Ok(())
 }
 
+/// Get the list of users whose devices are currently connected to the space
+/// network.
+pub async fn users_in_space(
+    env: &BotEnv,
+) -> Result<Vec<(DbUserId, Option<models::TgUser>)>> {
+    let active_mac_addrs = mikrotik::get_active_macs(
+        &env.health,
+        &env.reqwest_client,
+        &env.config.services.mikrotik,
+    )
+    .await?;
+    let data = schema::user_macs::table
+        .left_join(
+            schema::tg_users::table
+                .on(schema::user_macs::tg_id.eq(schema::tg_users::id)),
+        )
+        .filter(schema::user_macs::mac.eq_any(&active_mac_addrs))
+        .select((
+            schema::user_macs::tg_id,
+            schema::tg_users::all_columns.nullable(),
+        ))
+        .distinct()
+        .load(&mut *env.conn())?;
+    Ok(data)
+}
+
 async fn cmd_topics(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
     let Some(user) = &msg.from else { return Ok(()) };
 
@@ -358,3 +493,49 @@ fn render_topic_link(
     }
     out.push_str("</a>\n");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{s} 00:00"), "%Y-%m-%d %H:%M")
+            .unwrap()
+    }
+
+    fn period(
+        tg_id: i64,
+        begin: &str,
+        end: Option<&str>,
+    ) -> (models::Resident, Option<models::TgUser>) {
+        let resident = models::Resident {
+            rowid: 0,
+            tg_id: DbUserId::from(UserId(tg_id.unsigned_abs())),
+            begin_date: date(begin),
+            end_date: end.map(date),
+        };
+        (resident, None)
+    }
+
+    #[test]
+    fn test_residents_history() {
+        let periods = [
+            period(1, "2020-01-01", Some("2021-01-01")),
+            period(2, "2020-07-01", None),
+            period(1, "2022-01-01", None),
+            period(3, "2021-01-01", Some("2022-01-01")),
+        ];
+        let now = date("2023-01-01");
+
+        let stats = residents_stats_text(&periods, now);
+        assert!(stats.contains("Current residents: 2, former: 1."), "{stats}");
+        assert!(stats.contains("tenure: 1.8 years, 1.0 years"), "{stats}");
+        assert!(stats.contains("2021       1     1          3"), "{stats}");
+
+        let timeline = residents_timeline_text(&periods, now);
+        let lines = timeline.lines().collect_vec();
+        assert_eq!(lines.len(), 6, "{timeline}");
+        assert!(lines[2].contains("██████····██████ 20.01–now"), "{timeline}");
+        assert_eq!(residents_stats_text(&[], now), "No residents yet.");
+    }
+}
@@ -0,0 +1,386 @@
+//! Reserve machine time, e.g. `/book laser 18:00-20:00`.
+//!
+//! The day's schedule is posted to the [`telegram.bookings.thread`] every
+//! morning, and bookers are reminded shortly before their slot.
+//!
+//! **Scope**: `/book` command, available to residents, for resources listed
+//! in [`telegram.bookings.resources`].
+//!
+//! [`telegram.bookings.thread`]: crate::config::Bookings::thread
+//! [`telegram.bookings.resources`]: crate::config::Bookings::resources
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveTime, Timelike as _};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::config::Bookings;
+use crate::db::DbUserId;
+use crate::modules::{activity_feed, timezones};
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "reserve machine time: <code>/book laser \
+                             18:00-20:00 [YYYY-MM-DD]</code>.")]
+    #[custom(resident = true)]
+    Book(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_book)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+/// Parse a time range like `18:00-20:00`.
+fn parse_slot(text: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = text.split_once('-')?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+    (start < end).then_some((start, end))
+}
+
+async fn cmd_book(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Book(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) = &env.config.telegram.bookings else {
+        bot.reply_message(&msg, "Bookings are not configured.").await?;
+        return Ok(());
+    };
+
+    // Times are entered and shown in the time zone of the chat.
+    let tz = timezones::chat_tz(&env, &mut env.conn(), msg.chat.id)?;
+    let args = args.split_whitespace().collect_vec();
+    let parsed = match args[..] {
+        [resource, slot] => {
+            parse_slot(slot).map(|s| (resource, s, timezones::now(tz).date()))
+        }
+        [resource, slot, date] => parse_slot(slot)
+            .zip(NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+            .map(|(s, d)| (resource, s, d)),
+        _ => None,
+    };
+    let Some((resource, (start, end), date)) = parsed else {
+        bot.reply_message(
+            &msg,
+            format!(
+                "Usage: /book <resource> HH:MM-HH:MM [YYYY-MM-DD]\n\
+                 Resources: {}",
+                conf.resources.join(", "),
+            ),
+        )
+        .await?;
+        return Ok(());
+    };
+    if !conf.resources.iter().any(|r| r == resource) {
+        bot.reply_message(
+            &msg,
+            format!(
+                "Unknown resource. Available: {}",
+                conf.resources.join(", ")
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+    if date.and_time(start) < timezones::now(tz) {
+        bot.reply_message(&msg, "This slot is in the past.").await?;
+        return Ok(());
+    }
+
+    let start_at = timezones::to_utc(tz, date.and_time(start));
+    let end_at = timezones::to_utc(tz, date.and_time(end));
+    let result = env.transaction(|conn| {
+        use schema::bookings::dsl as b;
+        let conflict = b::bookings
+            .filter(b::resource.eq(resource))
+            .filter(b::start_at.lt(end_at))
+            .filter(b::end_at.gt(start_at))
+            .select(models::Booking::as_select())
+            .first(conn)
+            .optional()?;
+        if let Some(conflict) = conflict {
+            return Ok(Err(conflict));
+        }
+        diesel::insert_into(b::bookings)
+            .values((
+                b::resource.eq(resource),
+                b::user_id.eq(DbUserId::from(from.id)),
+                b::start_at.eq(start_at),
+                b::end_at.eq(end_at),
+                b::reminded.eq(false),
+                b::created_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        activity_feed::record(
+            conn,
+            "bookings",
+            Some(from.id),
+            "booked",
+            &format!(
+                "{resource} on {date} {}–{}",
+                start.format("%H:%M"),
+                end.format("%H:%M"),
+            ),
+            msg.url().as_ref().map(|u| u.as_str()),
+        )?;
+        b::bookings
+            .filter(b::resource.eq(resource))
+            .filter(b::start_at.eq(start_at))
+            .select(b::rowid)
+            .first::<i32>(conn)
+            .map(Ok)
+    })?;
+
+    match result {
+        Ok(rowid) => {
+            bot.reply_message(
+                &msg,
+                format!(
+                    "Booked {} on {date} {}–{} (#{rowid}).",
+                    html::escape(resource),
+                    start.format("%H:%M"),
+                    end.format("%H:%M"),
+                ),
+            )
+            .parse_mode(ParseMode::Html)
+            .reply_markup(cancel_keyboard(rowid))
+            .await?;
+        }
+        Err(conflict) => {
+            let conflict_start = timezones::to_local(tz, conflict.start_at);
+            let conflict_end = timezones::to_local(tz, conflict.end_at);
+            bot.reply_message(
+                &msg,
+                format!(
+                    "{resource} is already booked from {} to {}.",
+                    conflict_start.format("%H:%M"),
+                    conflict_end.format("%H:%M"),
+                ),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+fn cancel_keyboard(rowid: i32) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "Cancel booking",
+        format!("bk:cancel:{rowid}"),
+    )]])
+}
+
+/// Post the day's schedule and send reminders.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.bookings else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(60)) => {}
+        }
+        send_reminders(&env, &bot, conf)
+            .await
+            .log_error("bookings: send reminders");
+
+        let now = timezones::now(timezones::space_tz(&env));
+        if now.hour() < conf.schedule_hour {
+            continue;
+        }
+        let today = now.date().to_string();
+        let last = models::bookings_last_schedule.get(&mut env.conn());
+        if last.as_ref().is_ok_and(|d| d.as_ref() == Some(&today)) {
+            continue;
+        }
+        if let Err(e) = post_schedule(&env, &bot, conf, now.date()).await {
+            log::error!("bookings: failed to post schedule: {e}");
+            continue;
+        }
+        models::bookings_last_schedule
+            .set(&mut env.conn(), &today)
+            .log_error("bookings: set last schedule");
+    }
+}
+
+async fn send_reminders(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Bookings,
+) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let soon = now + chrono::Duration::minutes(conf.remind_minutes.into());
+    let due: Vec<models::Booking> = schema::bookings::table
+        .filter(schema::bookings::reminded.eq(false))
+        .filter(schema::bookings::start_at.le(soon))
+        .filter(schema::bookings::end_at.gt(now))
+        .select(models::Booking::as_select())
+        .load(&mut *env.conn())?;
+    for booking in due {
+        let user = UserId::from(booking.user_id);
+        if models::booking_reminders.get(&mut env.conn(), user)? {
+            let tz = timezones::user_tz(env, &mut env.conn(), user)?;
+            let text = format!(
+                "⏰ Your {} booking starts at {}.",
+                booking.resource,
+                timezones::to_local(tz, booking.start_at).format("%H:%M"),
+            );
+            bot.send_message(user, text)
+                .reply_markup(cancel_keyboard(booking.rowid))
+                .await
+                .log_error("bookings: send reminder");
+        }
+        diesel::update(schema::bookings::table)
+            .filter(schema::bookings::rowid.eq(booking.rowid))
+            .set(schema::bookings::reminded.eq(true))
+            .execute(&mut *env.conn())?;
+    }
+    Ok(())
+}
+
+async fn post_schedule(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Bookings,
+    date: NaiveDate,
+) -> Result<()> {
+    let tz = timezones::space_tz(env);
+    let from = timezones::to_utc(tz, date.and_time(NaiveTime::default()));
+    let to = from + chrono::Duration::days(1);
+    let bookings: Vec<(models::Booking, Option<models::TgUser>)> =
+        schema::bookings::table
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::tg_users::id.eq(schema::bookings::user_id)),
+            )
+            .filter(schema::bookings::start_at.ge(from))
+            .filter(schema::bookings::start_at.lt(to))
+            .order((schema::bookings::resource, schema::bookings::start_at))
+            .select((
+                models::Booking::as_select(),
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+    if bookings.is_empty() {
+        return Ok(());
+    }
+
+    let mut text = format!("📅 <b>Bookings for {date}</b>\n");
+    for (resource, group) in
+        &bookings.iter().group_by(|(b, _)| b.resource.clone())
+    {
+        format_to!(text, "\n<b>{}</b>\n", html::escape(&resource));
+        for (booking, user) in group {
+            format_to!(
+                text,
+                "{}–{} ",
+                timezones::to_local(tz, booking.start_at).format("%H:%M"),
+                timezones::to_local(tz, booking.end_at).format("%H:%M"),
+            );
+            format_user(&mut text, booking.user_id, user.as_ref(), false);
+            text.push('\n');
+        }
+    }
+    bot.send_message(conf.thread.chat, text)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_notification(true)
+        .await?;
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<i32> {
+    callback.data.as_ref()?.strip_prefix("bk:cancel:")?.parse().ok()
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    rowid: i32,
+) -> Result<()> {
+    let is_admin = env.config.telegram.admins.contains(&callback.from.id);
+    let tz = timezones::space_tz(&env);
+    let result = env.transaction(|conn| {
+        let booking = schema::bookings::table
+            .filter(schema::bookings::rowid.eq(rowid))
+            .select(models::Booking::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(booking) = booking else {
+            return Ok(Err("This booking no longer exists."));
+        };
+        if UserId::from(booking.user_id) != callback.from.id && !is_admin {
+            return Ok(Err("Only the booker can cancel this booking."));
+        }
+        diesel::delete(schema::bookings::table)
+            .filter(schema::bookings::rowid.eq(rowid))
+            .execute(conn)?;
+        let start_at = timezones::to_local(tz, booking.start_at);
+        activity_feed::record(
+            conn,
+            "bookings",
+            Some(callback.from.id),
+            "cancelled",
+            &format!(
+                "{} on {}",
+                booking.resource,
+                start_at.format("%Y-%m-%d %H:%M"),
+            ),
+            None,
+        )?;
+        Ok(Ok(()))
+    })?;
+
+    let text = match result {
+        Ok(()) => "Booking cancelled.",
+        Err(error) => error,
+    };
+    bot.answer_callback_query(&callback.id).text(text).await?;
+    if result.is_ok() {
+        if let Some(message) = &callback.message {
+            bot.edit_message_text(message.chat.id, message.id, "❌ Cancelled.")
+                .await
+                .log_error("bookings: edit message");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_slot() {
+        let t = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert_eq!(parse_slot("18:00-20:30"), Some((t(18, 0), t(20, 30))));
+        assert_eq!(parse_slot("9:00 - 10:00"), Some((t(9, 0), t(10, 0))));
+        assert_eq!(parse_slot("20:00-18:00"), None);
+        assert_eq!(parse_slot("18:00"), None);
+        assert_eq!(parse_slot("18-20"), None);
+    }
+}
@@ -1,9 +1,13 @@
 //! A module to track borrowed items.
 //!
+//! Items are marked as returned with buttons, or all at once by reacting to
+//! the borrow message with [`telegram.chats.borrowed_items_reaction`].
+//!
 //! **Scope**: chat topic listed in the [`telegram.chats.borrowed_items`] config
 //! option.
 //!
 //! [`telegram.chats.borrowed_items`]: crate::config::TelegramChats::borrowed_items
+//! [`telegram.chats.borrowed_items_reaction`]: crate::config::TelegramChats::borrowed_items_reaction
 
 use std::sync::Arc;
 
@@ -12,18 +16,25 @@ use async_openai::types::{
     ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs,
 };
 use chrono::DateTime;
+use chrono_tz::Tz;
 use diesel::prelude::*;
 use itertools::Itertools;
+use serde::Deserialize;
 use tap::Tap as _;
 use teloxide::prelude::*;
 use teloxide::types::{
     InlineKeyboardButton, InlineKeyboardMarkup, MediaKind, MessageId,
     MessageKind, ParseMode, ReplyMarkup, User,
 };
-use teloxide::utils::html;
+use tokio::select;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 use crate::common::{BotEnv, UpdateHandler};
-use crate::utils::Sqlizer;
+use crate::models::{OutboxAction, UndoAction};
+use crate::modules::timezones::space_tz;
+use crate::modules::{activity_feed, undo};
+use crate::utils::{html, ResultExt as _, Sqlizer};
 use crate::{models, schema};
 
 pub fn command_handler() -> UpdateHandler {
@@ -45,11 +56,12 @@ async fn handle_message(
 ) -> Result<()> {
     let Some(user) = msg.from.as_ref() else { return Ok(()) };
     let Some(text) = textify_message(&msg) else { return Ok(()) };
-    let item_names = match classify(Arc::clone(&env), &text).await? {
-        ClassificationResult::Took(items) => items,
-        ClassificationResult::Returned => return Ok(()),
-        ClassificationResult::Unknown => return Ok(()),
-    };
+    let item_names =
+        match classify(Arc::clone(&env), msg.chat.id, &text).await? {
+            ClassificationResult::Took(items) => items,
+            ClassificationResult::Returned => return Ok(()),
+            ClassificationResult::Unknown => return Ok(()),
+        };
 
     if item_names.is_empty() {
         return Ok(());
@@ -61,7 +73,7 @@ async fn handle_message(
         .collect_vec();
 
     let bot_message = bot
-        .send_message(msg.chat.id, make_text(user, &items))
+        .send_message(msg.chat.id, make_text(user, &items, space_tz(&env)))
         .message_thread_id(msg.thread_id.unwrap())
         .parse_mode(ParseMode::Html)
         .reply_markup(ReplyMarkup::InlineKeyboard(make_keyboard(
@@ -72,6 +84,7 @@ async fn handle_message(
         .disable_notification(true)
         .await?;
 
+    let names = items.iter().map(|i| i.name.as_str()).join(", ");
     env.transaction(|conn| {
         diesel::insert_into(schema::borrowed_items::table)
             .values(models::BorrowedItems {
@@ -79,11 +92,31 @@ async fn handle_message(
                 thread_id: msg.thread_id.unwrap().into(),
                 user_message_id: msg.id.into(),
                 bot_message_id: bot_message.id.into(),
-                user_id: msg.from.unwrap().id.into(),
+                user_id: user.id.into(),
                 items: Sqlizer::new(items).unwrap(),
+                created_at: Some(chrono::Utc::now().naive_utc()),
             })
             .execute(conn)?;
-        Ok(())
+        activity_feed::record(
+            conn,
+            "borrowed_items",
+            Some(user.id),
+            "borrowed",
+            &names,
+            msg.url().as_ref().map(|u| u.as_str()),
+        )?;
+        undo::push(
+            conn,
+            msg.chat.id,
+            user.id,
+            "pin of the borrow message",
+            vec![UndoAction::Telegram {
+                action: OutboxAction::UnpinMessage {
+                    chat_id: msg.chat.id,
+                    message_id: msg.id,
+                },
+            }],
+        )
     })?;
 
     bot.pin_chat_message(msg.chat.id, msg.id)
@@ -161,6 +194,14 @@ async fn handle_callback(
             )
             .set(schema::borrowed_items::items.eq(&bi.items))
             .execute(conn)?;
+        activity_feed::record(
+            conn,
+            "borrowed_items",
+            Some(callback.from.id),
+            "returned",
+            &bi.items.as_ref()[cd.item_index].name,
+            None,
+        )?;
 
         Ok(CallbackResponse::Update(bi))
     });
@@ -180,28 +221,7 @@ async fn handle_callback(
         }
         Ok(CallbackResponse::Update(bi)) => {
             bot.answer_callback_query(callback.id).await?;
-            let all_returned = bi.items.iter().all(|i| i.returned.is_some());
-            let mut edit = bot
-                .edit_message_text(
-                    cd.chat_id,
-                    bi.bot_message_id.into(),
-                    make_text(&callback.from, &bi.items),
-                )
-                .parse_mode(ParseMode::Html);
-            if !all_returned {
-                edit = edit.reply_markup(make_keyboard(
-                    cd.chat_id,
-                    cd.user_message_id,
-                    &bi.items,
-                ));
-            }
-            edit.await.ok();
-            if all_returned {
-                bot.unpin_chat_message(cd.chat_id)
-                    .message_id(cd.user_message_id)
-                    .await?;
-            }
-            Ok(())
+            update_message(&bot, &env, &callback.from, &bi).await
         }
         Err(e) => {
             bot.answer_callback_query(callback.id)
@@ -212,6 +232,165 @@ async fn handle_callback(
     }
 }
 
+/// Show returned items in the bot message, and unpin the borrow message once
+/// everything is returned.
+async fn update_message(
+    bot: &Bot,
+    env: &BotEnv,
+    user: &User,
+    bi: &models::BorrowedItems,
+) -> Result<()> {
+    let chat_id = ChatId::from(bi.chat_id);
+    let user_message_id = MessageId::from(bi.user_message_id);
+    let all_returned = bi.items.iter().all(|i| i.returned.is_some());
+    let mut edit = bot
+        .edit_message_text(
+            chat_id,
+            bi.bot_message_id.into(),
+            make_text(user, &bi.items, space_tz(env)),
+        )
+        .parse_mode(ParseMode::Html);
+    if !all_returned {
+        edit = edit.reply_markup(make_keyboard(
+            chat_id,
+            user_message_id,
+            &bi.items,
+        ));
+    }
+    edit.await.ok();
+    if all_returned {
+        bot.unpin_chat_message(chat_id).message_id(user_message_id).await?;
+    }
+    Ok(())
+}
+
+/// Payload of a `message_reaction` update.  teloxide doesn't support these
+/// updates yet, see [`crate::tracing_proxy::start`].
+#[derive(Debug, Deserialize)]
+struct MessageReactionUpdated {
+    chat: ReactionChat,
+    message_id: MessageId,
+    /// `None` for anonymous reactions.
+    user: Option<User>,
+    old_reaction: Vec<ReactionType>,
+    new_reaction: Vec<ReactionType>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReactionChat {
+    id: ChatId,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ReactionType {
+    Emoji { emoji: String },
+    /// Custom emoji and other reactions.
+    #[serde(other)]
+    Other,
+}
+
+/// Handle reactions to borrow messages.
+pub async fn reactions_task(
+    env: Arc<BotEnv>,
+    bot: Bot,
+    mut reactions: mpsc::UnboundedReceiver<serde_json::Value>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let reaction = select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            reaction = reactions.recv() => reaction,
+        };
+        let Some(reaction) = reaction else { break };
+        handle_reaction(&bot, &env, reaction)
+            .await
+            .log_error("borrowed_items: reaction");
+    }
+}
+
+/// Mark all items as returned when the borrower reacts to the borrow message
+/// or the bot message with the configured emoji.
+async fn handle_reaction(
+    bot: &Bot,
+    env: &BotEnv,
+    reaction: serde_json::Value,
+) -> Result<()> {
+    let Some(emoji) = &env.config.telegram.chats.borrowed_items_reaction else {
+        return Ok(());
+    };
+    let reaction: MessageReactionUpdated = serde_json::from_value(reaction)?;
+    let Some(user) = reaction.user else { return Ok(()) };
+    let has_emoji = |reactions: &[ReactionType]| {
+        reactions.iter().any(
+            |r| matches!(r, ReactionType::Emoji { emoji: e } if e == emoji),
+        )
+    };
+    if !has_emoji(&reaction.new_reaction) || has_emoji(&reaction.old_reaction) {
+        return Ok(());
+    }
+
+    let bi = env.transaction(|conn| {
+        let message_id = reaction.message_id.0;
+        let bi: Option<models::BorrowedItems> = schema::borrowed_items::table
+            .filter(schema::borrowed_items::chat_id.eq(reaction.chat.id.0))
+            .filter(
+                schema::borrowed_items::user_message_id
+                    .eq(message_id)
+                    .or(schema::borrowed_items::bot_message_id.eq(message_id)),
+            )
+            .first(conn)
+            .optional()?;
+        let Some(mut bi) = bi else { return Ok(None) };
+        if user.id != UserId::from(bi.user_id)
+            || bi.items.iter().all(|i| i.returned.is_some())
+        {
+            return Ok(None);
+        }
+        let names = bi
+            .items
+            .iter()
+            .filter(|i| i.returned.is_none())
+            .map(|i| i.name.as_str())
+            .join(", ");
+        let now = chrono::Utc::now();
+        bi.items = bi
+            .items
+            .map(|items| {
+                let mut items = items.clone();
+                for item in &mut items {
+                    item.returned.get_or_insert(now);
+                }
+                items
+            })
+            .expect("Failed to serialize borrowed items");
+
+        diesel::update(schema::borrowed_items::table)
+            .filter(schema::borrowed_items::chat_id.eq(bi.chat_id))
+            .filter(
+                schema::borrowed_items::user_message_id.eq(bi.user_message_id),
+            )
+            .set(schema::borrowed_items::items.eq(&bi.items))
+            .execute(conn)?;
+        activity_feed::record(
+            conn,
+            "borrowed_items",
+            Some(user.id),
+            "returned",
+            &names,
+            None,
+        )?;
+        Ok(Some(bi))
+    })?;
+
+    if let Some(bi) = bi {
+        update_message(bot, env, &user, &bi).await?;
+    }
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 enum ClassificationResult {
     Took(Vec<String>),
@@ -221,9 +400,12 @@ enum ClassificationResult {
 
 async fn classify(
     env: Arc<BotEnv>,
+    chat: ChatId,
     text: &str,
 ) -> Result<ClassificationResult> {
-    if env.config.services.openai.disable {
+    if env.config.services.openai.disable
+        || !models::borrowed_items_openai.enabled(&env, chat)
+    {
         classify_dumb(text)
     } else {
         classify_openai(env, text).await
@@ -245,14 +427,16 @@ fn classify_dumb(text: &str) -> Result<ClassificationResult> {
 const MODEL: &str = "gpt-4";
 const METRIC_NAME: &str = "botka_openai_used_tokens_total";
 
-pub fn register_metrics() {
+pub fn register_metrics(instance: &str) {
     metrics::register_counter!(
         METRIC_NAME,
+        "instance" => instance.to_string(),
         "model" => MODEL,
         "type" => "prompt",
     );
     metrics::register_counter!(
         METRIC_NAME,
+        "instance" => instance.to_string(),
         "model" => MODEL,
         "type" => "completion",
     );
@@ -280,22 +464,21 @@ async fn classify_openai(
                 .build()?,
         ])
         .build()?;
-    let response = env
-        .openai_client
-        .chat()
-        .create(request)
-        .await
-        .tap(|r| crate::metrics::update_service("openai", r.is_ok()))?;
+    let response = env.openai_client.chat().create(request).await.tap(|r| {
+        crate::metrics::update_service(&env.health, "openai", r.is_ok())
+    })?;
     if let Some(usage) = response.usage {
         metrics::counter!(
             METRIC_NAME,
             usage.prompt_tokens.into(),
+            "instance" => env.instance.clone(),
             "model" => MODEL,
             "type" => "prompt",
         );
         metrics::counter!(
             METRIC_NAME,
             usage.completion_tokens.into(),
+            "instance" => env.instance.clone(),
             "model" => MODEL,
             "type" => "completion",
         );
@@ -346,7 +529,7 @@ fn textify_message(msg: &Message) -> Option<String> {
     Some(result)
 }
 
-fn make_text(user: &User, items: &[models::BorrowedItem]) -> String {
+fn make_text(user: &User, items: &[models::BorrowedItem], tz: Tz) -> String {
     let mut text = String::new();
     let mut prev_date: Option<DateTime<_>> = None;
     for (name, returned) in items
@@ -362,7 +545,8 @@ fn make_text(user: &User, items: &[models::BorrowedItem]) -> String {
                 if !text.is_empty() {
                     text.push('\n');
                 }
-                text.push_str(&returned.format("%Y-%m-%d %H:%M").to_string());
+                let local = returned.with_timezone(&tz);
+                text.push_str(&local.format("%Y-%m-%d %H:%M").to_string());
                 text.push_str(": returned ");
                 prev_date = Some(returned);
             }
@@ -370,7 +554,7 @@ fn make_text(user: &User, items: &[models::BorrowedItem]) -> String {
         text.push_str(&html::escape(name));
     }
     if text.is_empty() {
-        text.push_str(&html::user_mention(user.id, &user.full_name()));
+        text.push_str(&html::user_mention(user));
         text.push_str(", press a button to mark an item as returned.");
     }
     text
@@ -433,8 +617,11 @@ If a message does not contain any information about taking or returning items, r
 
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+
     use super::*;
     use crate::models::BorrowedItem;
+    use crate::testing::{self, TestBot};
 
     #[test]
     fn test_make_text() {
@@ -456,17 +643,70 @@ mod tests {
         assert_eq!(
             make_text(
                 &user,
-                &[item("hammer", Some(0)), item("screwdriver", Some(1))]
+                &[item("hammer", Some(0)), item("screwdriver", Some(1))],
+                Tz::UTC,
             ),
             "1970-01-01 00:00: returned hammer, screwdriver"
         );
         assert_eq!(
             make_text(
                 &user,
-                &[item("hammer", Some(0)), item("screwdriver", Some(60))]
+                &[item("hammer", Some(0)), item("screwdriver", Some(60))],
+                Tz::Europe__Berlin,
             ),
-            "1970-01-01 00:00: returned hammer\n\
-            1970-01-01 01:00: returned screwdriver"
+            "1970-01-01 01:00: returned hammer\n\
+            1970-01-01 02:00: returned screwdriver"
         );
     }
+
+    #[tokio::test]
+    async fn test_reaction() {
+        let t = TestBot::new();
+        let chat = t.env.config.telegram.chats.borrowed_items[0];
+        let emoji = t.env.config.telegram.chats.borrowed_items_reaction.clone();
+        let emoji = emoji.unwrap();
+        let item = |name: &str| BorrowedItem {
+            name: name.to_string(),
+            returned: None,
+        };
+        diesel::insert_into(schema::borrowed_items::table)
+            .values(models::BorrowedItems {
+                chat_id: chat.chat.into(),
+                thread_id: chat.thread.into(),
+                user_message_id: MessageId(10).into(),
+                bot_message_id: MessageId(11).into(),
+                user_id: UserId(1).into(),
+                items: Sqlizer::new(vec![item("hammer"), item("saw")]).unwrap(),
+                created_at: None,
+            })
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        let reaction = |user: i64, new: &str| {
+            json!({
+                "chat": {"id": chat.chat.0, "type": "supergroup"},
+                "message_id": 10,
+                "user": testing::user_json(user, "User"),
+                "date": 0,
+                "old_reaction": [],
+                "new_reaction": [{"type": "emoji", "emoji": new}],
+            })
+        };
+        let returned = |t: &TestBot| {
+            let bi: models::BorrowedItems = schema::borrowed_items::table
+                .first(&mut *t.env.conn())
+                .unwrap();
+            bi.items.iter().filter(|i| i.returned.is_some()).count()
+        };
+
+        // Other emoji, or someone else's reaction.
+        handle_reaction(&t.bot, &t.env, reaction(1, "👍")).await.unwrap();
+        handle_reaction(&t.bot, &t.env, reaction(2, &emoji)).await.unwrap();
+        assert_eq!(returned(&t), 0);
+
+        handle_reaction(&t.bot, &t.env, reaction(1, &emoji)).await.unwrap();
+        assert_eq!(returned(&t), 2);
+        let unpinned = t.telegram.calls("unpinChatMessage");
+        assert_eq!(unpinned.len(), 1);
+        assert_eq!(unpinned[0]["message_id"], 10);
+    }
 }
@@ -0,0 +1,430 @@
+//! Private messages from admins to a group of users.
+//!
+//! `/dm role:resident <text>` sends the text to every current resident, and
+//! `/dm role:<role> <text>` to every holder of the role.  The message is
+//! always sent under a header naming the admin who sent it, and the text is
+//! sent as plain text, so a broadcast can't pass for a message from someone
+//! else or hide links.
+//!
+//! Messages are sent through the [`outbox`], one entry per user, delayed
+//! until the end of [`telegram.quiet_hours`] in the time zone of the user.
+//! Users who turned broadcasts off in `/settings` are skipped.  Each delivery
+//! is tracked, and once all of them are done, the bot replies to the `/dm`
+//! command with a report: who got the message, who hasn't started the bot,
+//! and whose delivery failed.
+//!
+//! **Scope**: `/dm` command, available to admins.
+//!
+//! [`outbox`]: crate::outbox
+//! [`telegram.quiet_hours`]: crate::config::Telegram::quiet_hours
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{NaiveDateTime, Timelike as _};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use teloxide::{ApiError, RequestError};
+
+use crate::common::{
+    filter_command, format_users, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::config::QuietHours;
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::models::OutboxAction;
+use crate::modules::{roles, timezones};
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, outbox, schema};
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_SENT: &str = "sent";
+const STATUS_FAILED: &str = "failed";
+const STATUS_NOT_STARTED: &str = "not_started";
+const STATUS_OPTED_OUT: &str = "opted_out";
+
+const USAGE: &str = "Usage: /dm role:resident <text>, or /dm role:<role> \
+                     <text> to message holders of the role.";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "send a private message to users: \
+                             <code>/dm role:resident text</code>.")]
+    #[custom(admin = true)]
+    Dm(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_dm)
+}
+
+async fn cmd_dm(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Dm(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let parsed = args
+        .trim()
+        .split_once(char::is_whitespace)
+        .map(|(audience, text)| (audience, text.trim()))
+        .filter(|(_, text)| !text.is_empty());
+    let Some((audience, text)) = parsed else {
+        bot.reply_message(&msg, USAGE).await?;
+        return Ok(());
+    };
+    let Some(role) = audience.strip_prefix("role:") else {
+        bot.reply_message(&msg, USAGE).await?;
+        return Ok(());
+    };
+
+    let recipients = if role == "resident" {
+        env.cache.residents(&env.conn)?.iter().copied().sorted().collect()
+    } else {
+        roles::users_with_role(&env, role)?
+    };
+    if recipients.is_empty() {
+        bot.reply_message(&msg, format!("No one has the role {role}.")).await?;
+        return Ok(());
+    }
+
+    let text = format!(
+        "📢 <b>Message from the admins</b>, sent by {}:\n\n{}",
+        html::user_mention(from),
+        html::escape(text),
+    );
+    let now = chrono::Utc::now().naive_utc();
+    let (broadcast_id, delayed) = env.transaction(|conn| {
+        diesel::insert_into(schema::broadcasts::table)
+            .values(models::NewBroadcast {
+                created_by: from.id.into(),
+                chat_id: msg.chat.id.into(),
+                message_id: msg.id.into(),
+                audience,
+                text: &text,
+                created_at: now,
+            })
+            .execute(conn)?;
+        let broadcast_id = schema::broadcasts::table
+            .select(schema::broadcasts::rowid)
+            .order(schema::broadcasts::rowid.desc())
+            .first::<i32>(conn)?;
+
+        let mut delayed = 0;
+        for &user_id in &recipients {
+            let status = if models::broadcasts.get(conn, user_id)? {
+                let tz = timezones::user_tz(&env, conn, user_id)?;
+                let quiet_until = env
+                    .config
+                    .telegram
+                    .quiet_hours
+                    .as_ref()
+                    .and_then(|q| quiet_until(q, timezones::to_local(tz, now)))
+                    .map(|local| timezones::to_utc(tz, local));
+                delayed += usize::from(quiet_until.is_some());
+                outbox::enqueue_at(
+                    conn,
+                    OutboxAction::SendBroadcast { broadcast_id, user_id },
+                    vec![OutboxAction::FailBroadcast { broadcast_id, user_id }],
+                    quiet_until.unwrap_or(now),
+                )?;
+                STATUS_PENDING
+            } else {
+                STATUS_OPTED_OUT
+            };
+            diesel::insert_into(schema::broadcast_deliveries::table)
+                .values((
+                    schema::broadcast_deliveries::broadcast_id.eq(broadcast_id),
+                    schema::broadcast_deliveries::user_id
+                        .eq(DbUserId::from(user_id)),
+                    schema::broadcast_deliveries::status.eq(status),
+                    schema::broadcast_deliveries::updated_at.eq(now),
+                ))
+                .execute(conn)?;
+        }
+        Ok((broadcast_id, delayed))
+    })?;
+    outbox::wake(&env);
+
+    let mut reply = format!(
+        "📢 Broadcast #{broadcast_id} to {} users is queued",
+        recipients.len(),
+    );
+    if delayed > 0 {
+        format_to!(reply, ", {delayed} of them are delayed by quiet hours");
+    }
+    reply.push_str(".  I'll reply here when it's delivered.");
+    bot.reply_message(&msg, reply).await?;
+
+    // Everyone might have opted out.
+    report_if_done(&env, &bot, broadcast_id).await;
+    Ok(())
+}
+
+/// The end of the quiet hours in local time, if `local` is within them.
+fn quiet_until(
+    quiet: &QuietHours,
+    local: NaiveDateTime,
+) -> Option<NaiveDateTime> {
+    let hour = local.hour();
+    let quiet_now = if quiet.start_hour <= quiet.end_hour {
+        (quiet.start_hour..quiet.end_hour).contains(&hour)
+    } else {
+        hour >= quiet.start_hour || hour < quiet.end_hour
+    };
+    if !quiet_now {
+        return None;
+    }
+    let date = if hour < quiet.end_hour {
+        local.date()
+    } else {
+        local.date().succ_opt()?
+    };
+    date.and_hms_opt(quiet.end_hour, 0, 0)
+}
+
+/// Send the broadcast to the user.  Called by the [`outbox`], which retries
+/// on network errors.
+pub async fn deliver(
+    env: &BotEnv,
+    bot: &Bot,
+    broadcast_id: i32,
+    user_id: UserId,
+) -> Result<(), RequestError> {
+    let text = schema::broadcasts::table
+        .filter(schema::broadcasts::rowid.eq(broadcast_id))
+        .select(schema::broadcasts::text)
+        .first::<String>(&mut *env.conn());
+    let text = match text {
+        Ok(text) => text,
+        Err(e) => {
+            log::error!("broadcasts: failed to load #{broadcast_id}: {e}");
+            return Ok(());
+        }
+    };
+
+    let result =
+        bot.send_message(user_id, text).parse_mode(ParseMode::Html).await;
+    let status = match &result {
+        Ok(_) => STATUS_SENT,
+        Err(
+            RequestError::Network(_)
+            | RequestError::Io(_)
+            | RequestError::RetryAfter(_),
+        ) => return result.map(drop),
+        Err(RequestError::Api(e)) if not_started(e) => STATUS_NOT_STARTED,
+        Err(_) => STATUS_FAILED,
+    };
+    let error = result.err().map(|e| e.to_string());
+    set_status(env, bot, broadcast_id, user_id, status, error).await;
+    Ok(())
+}
+
+/// Mark the delivery as failed after the [`outbox`] gave up retrying.
+pub async fn give_up(
+    env: &BotEnv,
+    bot: &Bot,
+    broadcast_id: i32,
+    user_id: UserId,
+) {
+    let error = Some("too many network errors".to_string());
+    set_status(env, bot, broadcast_id, user_id, STATUS_FAILED, error).await;
+}
+
+/// Whether the error means that the user hasn't started a chat with the bot.
+fn not_started(error: &ApiError) -> bool {
+    match error {
+        ApiError::CantInitiateConversation | ApiError::ChatNotFound => true,
+        ApiError::Unknown(description) => {
+            description.contains("bot can't initiate conversation")
+        }
+        _ => false,
+    }
+}
+
+async fn set_status(
+    env: &BotEnv,
+    bot: &Bot,
+    broadcast_id: i32,
+    user_id: UserId,
+    status: &str,
+    error: Option<String>,
+) {
+    use schema::broadcast_deliveries::dsl as d;
+    diesel::update(d::broadcast_deliveries)
+        .filter(d::broadcast_id.eq(broadcast_id))
+        .filter(d::user_id.eq(DbUserId::from(user_id)))
+        .set((
+            d::status.eq(status),
+            d::error.eq(error),
+            d::updated_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(&mut *env.conn())
+        .log_error("broadcasts: update delivery");
+    report_if_done(env, bot, broadcast_id).await;
+}
+
+/// Reply to the `/dm` command with the report once no deliveries are
+/// pending.  Sent once.
+async fn report_if_done(env: &BotEnv, bot: &Bot, broadcast_id: i32) {
+    let report = env.transaction(|conn| finish(conn, broadcast_id));
+    match report {
+        Ok(Some((chat_id, message_id, text))) => {
+            bot.send_message(chat_id, text)
+                .reply_to_message_id(message_id)
+                .allow_sending_without_reply(true)
+                .parse_mode(ParseMode::Html)
+                .disable_web_page_preview(true)
+                .await
+                .log_error("broadcasts: send report");
+        }
+        Ok(None) => (),
+        Err(e) => log::error!("broadcasts: failed to finish: {e}"),
+    }
+}
+
+/// Mark the broadcast finished if no deliveries are pending, and return the
+/// report to send.
+fn finish(
+    conn: &mut SqliteConnection,
+    broadcast_id: i32,
+) -> QueryResult<Option<(ChatId, teloxide::types::MessageId, String)>> {
+    let deliveries: Vec<(String, DbUserId, Option<models::TgUser>)> =
+        schema::broadcast_deliveries::table
+            .left_join(schema::tg_users::table.on(
+                schema::tg_users::id.eq(schema::broadcast_deliveries::user_id),
+            ))
+            .filter(schema::broadcast_deliveries::broadcast_id.eq(broadcast_id))
+            .select((
+                schema::broadcast_deliveries::status,
+                schema::broadcast_deliveries::user_id,
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(conn)?;
+    if deliveries.iter().any(|(status, _, _)| status == STATUS_PENDING) {
+        return Ok(None);
+    }
+    let updated = diesel::update(schema::broadcasts::table)
+        .filter(schema::broadcasts::rowid.eq(broadcast_id))
+        .filter(schema::broadcasts::finished_at.is_null())
+        .set(schema::broadcasts::finished_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)?;
+    if updated == 0 {
+        return Ok(None);
+    }
+    let (chat_id, message_id) = schema::broadcasts::table
+        .filter(schema::broadcasts::rowid.eq(broadcast_id))
+        .select((schema::broadcasts::chat_id, schema::broadcasts::message_id))
+        .first::<(DbChatId, DbMessageId)>(conn)?;
+
+    let by_status =
+        deliveries.iter().into_group_map_by(|&(s, _, _)| s.as_str());
+    let count = |status: &str| by_status.get(status).map_or(0, Vec::len);
+    let mut text = format!(
+        "📢 Broadcast #{broadcast_id} is delivered to {} of {} users.",
+        count(STATUS_SENT),
+        deliveries.len(),
+    );
+    for (status, title) in [
+        (STATUS_NOT_STARTED, "Haven't started the bot"),
+        (STATUS_FAILED, "Failed"),
+        (STATUS_OPTED_OUT, "Turned broadcasts off"),
+    ] {
+        if let Some(users) = by_status.get(status) {
+            format_to!(text, "\n\n{title}: ");
+            format_users(
+                &mut text,
+                users.iter().map(|(_, id, user)| (*id, user.as_ref())),
+            );
+        }
+    }
+    Ok(Some((chat_id.into(), message_id.into(), text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    #[test]
+    fn test_quiet_until() {
+        let at = |d, h| {
+            NaiveDate::from_ymd_opt(2026, 10, d)
+                .unwrap()
+                .and_hms_opt(h, 30, 0)
+                .unwrap()
+        };
+        let nine = |d| at(d, 9) - chrono::Duration::minutes(30);
+        let night = QuietHours { start_hour: 22, end_hour: 9 };
+        assert_eq!(quiet_until(&night, at(16, 12)), None);
+        assert_eq!(quiet_until(&night, at(16, 23)), Some(nine(17)));
+        assert_eq!(quiet_until(&night, at(17, 3)), Some(nine(17)));
+        assert_eq!(quiet_until(&night, at(17, 9)), None);
+
+        let early = QuietHours { start_hour: 1, end_hour: 9 };
+        assert_eq!(quiet_until(&early, at(16, 3)), Some(nine(16)));
+        assert_eq!(quiet_until(&early, at(16, 23)), None);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        t.add_resident(3, "carol", "Carol");
+        models::broadcasts.set(&mut t.env.conn(), UserId(3), &false).unwrap();
+        let admin = t.env.config.telegram.admins[0];
+        let admin_json = testing::user_json(admin.0, "Admin");
+
+        let update = testing::message(
+            -1_001_234_567_890,
+            None,
+            &admin_json,
+            "/dm role:resident Meeting at <b>8</b>",
+        );
+        t.dispatch(&command_handler(), update).await;
+        let replies = t.telegram.calls("sendMessage");
+        assert!(replies[0]["text"].as_str().unwrap().contains("2 users"));
+        t.telegram.clear();
+
+        // The quiet hours may delay the messages, so don't wait for them.
+        diesel::update(schema::outbox::table)
+            .set(
+                schema::outbox::next_attempt_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            )
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        t.telegram.fail(
+            "sendMessage",
+            "Forbidden: bot can't initiate conversation with a user",
+        );
+        outbox::process(&t.env, &t.bot).await;
+
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[1]["chat_id"], 2);
+        assert!(sent[1]["text"].as_str().unwrap().contains("&lt;b&gt;8"));
+        let report = sent[2]["text"].as_str().unwrap();
+        assert!(report.contains("delivered to 1 of 3"), "{report}");
+        assert!(
+            report.contains(
+                "Haven't started the bot: <a href=\"https://t.me/alice\">"
+            ),
+            "{report}",
+        );
+        assert!(
+            report.contains(
+                "Turned broadcasts off: <a href=\"https://t.me/carol\">"
+            ),
+            "{report}",
+        );
+    }
+}
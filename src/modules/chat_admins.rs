@@ -0,0 +1,185 @@
+//! Automatically grant limited Telegram admin rights to residents holding a
+//! bot role, and revoke them when the role is revoked or the residency ends.
+//!
+//! **Scope**: chats listed in [`telegram.auto_admins.chats`].  Only admins
+//! promoted by this module are ever demoted.
+//!
+//! [`telegram.auto_admins.chats`]: crate::config::AutoAdmins::chats
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use teloxide::prelude::*;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::BotEnv;
+use crate::config::AutoAdmins;
+use crate::utils::{ResultExt as _, Sqlizer};
+use crate::{models, schema};
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.auto_admins else { return };
+    loop {
+        let result = sync(&env, &bot, conf).await;
+        crate::metrics::update_service(
+            &env.health,
+            "chat_admins",
+            result.is_ok(),
+        );
+        result.log_error("chat_admins: sync");
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(10 * 60)) => {}
+        }
+    }
+}
+
+async fn sync(env: &BotEnv, bot: &Bot, conf: &AutoAdmins) -> Result<()> {
+    let residents = env.cache.residents(&env.conn)?;
+    let wanted = crate::modules::roles::users_with_role(env, &conf.role)?
+        .into_iter()
+        .filter(|u| residents.contains(u))
+        .collect::<BTreeSet<_>>();
+    let mut promoted =
+        models::auto_admins_promoted.get(&mut env.conn())?.unwrap_or_default();
+
+    // Errors are collected, so that users promoted before an error are
+    // still recorded and demoted later.
+    let mut result = Ok(());
+    for &chat in &conf.chats {
+        for &user in &wanted {
+            if promoted.contains(&(chat, user)) {
+                continue;
+            }
+            let member = match bot.get_chat_member(chat, user).await {
+                Ok(member) => member,
+                Err(e) => {
+                    result = Err(e.into());
+                    continue;
+                }
+            };
+            // Skip users that are not in the chat, and do not touch rights
+            // of existing admins.
+            if !member.is_present() || member.is_privileged() {
+                continue;
+            }
+            let promotion = bot
+                .promote_chat_member(chat, user)
+                .can_pin_messages(true)
+                .can_manage_topics(true)
+                .await;
+            if let Err(e) = promotion {
+                result = Err(e.into());
+                continue;
+            }
+            log::info!("chat_admins: promoted {} in {}", user.0, chat.0);
+            audit(env, "chat_admin_promote", chat, user);
+            promoted.push((chat, user));
+            refresh_chat_member(env, bot, chat, user).await;
+        }
+    }
+
+    let mut still_promoted = Vec::new();
+    for (chat, user) in promoted {
+        if wanted.contains(&user) && conf.chats.contains(&chat) {
+            still_promoted.push((chat, user));
+            continue;
+        }
+        // Passing no rights demotes the user to a regular member.
+        match bot.promote_chat_member(chat, user).await {
+            Ok(_) => {
+                log::info!("chat_admins: demoted {} in {}", user.0, chat.0);
+                audit(env, "chat_admin_demote", chat, user);
+                refresh_chat_member(env, bot, chat, user).await;
+            }
+            Err(e) => {
+                still_promoted.push((chat, user));
+                result = Err(e.into());
+            }
+        }
+    }
+
+    models::auto_admins_promoted.set(&mut env.conn(), &still_promoted)?;
+    result
+}
+
+fn audit(env: &BotEnv, action: &str, chat: ChatId, user: UserId) {
+    crate::modules::audit::record(
+        &mut env.conn(),
+        None,
+        action,
+        &serde_json::json!({ "user_id": user.0, "chat_id": chat.0 }),
+    )
+    .log_error("chat_admins: audit");
+}
+
+/// Store the actual chat member status after a change, since Telegram does
+/// not always deliver `chat_member` updates for changes made by the bot.
+async fn refresh_chat_member(
+    env: &BotEnv,
+    bot: &Bot,
+    chat: ChatId,
+    user: UserId,
+) {
+    let member = match bot.get_chat_member(chat, user).await {
+        Ok(member) => member,
+        Err(e) => {
+            log::error!("chat_admins: get chat member: {e}");
+            return;
+        }
+    };
+    let seen = member.is_present();
+    let Ok(chat_member) = Sqlizer::new(member) else { return };
+    diesel::replace_into(schema::tg_users_in_chats::table)
+        .values(models::NewTgUserInChat {
+            chat_id: chat.into(),
+            user_id: user.into(),
+            chat_member: Some(chat_member),
+            seen,
+        })
+        .execute(&mut *env.conn())
+        .log_error("chat_admins: update tg_users_in_chats");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestBot;
+
+    const CHAT: ChatId = ChatId(-1_001_234_567_890);
+
+    #[tokio::test]
+    async fn test_promotions_are_kept_on_error() {
+        let t = TestBot::new();
+        for (id, name) in [(1, "alice"), (2, "bob")] {
+            t.add_resident(id, name, name);
+            diesel::insert_into(schema::user_roles::table)
+                .values(models::UserRole {
+                    user_id: UserId(id).into(),
+                    role: "moderator".to_string(),
+                    granted_by: UserId(id).into(),
+                    granted_at: chrono::Utc::now().naive_utc(),
+                })
+                .execute(&mut *t.env.conn())
+                .unwrap();
+        }
+        t.env.cache.roles.invalidate();
+        t.telegram.respond("promoteChatMember", true.into());
+        t.telegram.fail("promoteChatMember", "Bad Request: not enough rights");
+
+        let conf = t.env.config.telegram.auto_admins.as_ref().unwrap();
+        assert!(sync(&t.env, &t.bot, conf).await.is_err());
+        assert_eq!(t.telegram.calls("promoteChatMember").len(), 2);
+        let promoted =
+            models::auto_admins_promoted.get(&mut t.env.conn()).unwrap();
+        assert_eq!(promoted, Some(vec![(CHAT, UserId(1))]));
+    }
+}
@@ -0,0 +1,430 @@
+//! Recurring chores checklist, e.g. watering plants or taking out the trash.
+//!
+//! Due chores are posted to the [`telegram.chores.thread`] with a button to
+//! check them off.  Chores left unchecked escalate: first residents are
+//! pinged, then admins get a report in [`telegram.chores.report_to`].
+//!
+//! **Scope**: `/chores` command, available to residents, and the configured
+//! threads.
+//!
+//! [`telegram.chores.thread`]: crate::config::Chores::thread
+//! [`telegram.chores.report_to`]: crate::config::Chores::report_to
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_user, format_users, is_resident, BotCommandsExt,
+    BotEnv, UpdateHandler,
+};
+use crate::config::Chores;
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::modules::timezones;
+use crate::utils::{
+    format_to, html, write_message_link, BotExt as _, ResultExt as _,
+};
+use crate::{models, schema};
+
+/// The chore is not posted yet.
+const STAGE_IDLE: i32 = 0;
+/// The chore is posted to the chores thread.
+const STAGE_POSTED: i32 = 1;
+/// Residents were pinged about the chore.
+const STAGE_PINGED: i32 = 2;
+/// Admins were notified about the chore.
+const STAGE_REPORTED: i32 = 3;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show the recurring chores checklist.")]
+    #[custom(resident = true)]
+    Chores,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_chores)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+/// Return the stage a chore should escalate to, if any.
+fn next_stage(
+    conf: &Chores,
+    chore: &models::Chore,
+    now: NaiveDateTime,
+) -> Option<i32> {
+    if now < chore.due_at {
+        return None;
+    }
+    let overdue = now - chore.due_at;
+    let next = if overdue
+        >= chrono::Duration::hours(conf.report_after_hours.into())
+    {
+        STAGE_REPORTED
+    } else if overdue >= chrono::Duration::hours(conf.ping_after_hours.into()) {
+        STAGE_PINGED
+    } else {
+        STAGE_POSTED
+    };
+    // Always post the chore first, even if it is already overdue.
+    let next = if chore.stage == STAGE_IDLE { STAGE_POSTED } else { next };
+    (next > chore.stage).then_some(next)
+}
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.chores else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(5 * 60)) => {}
+        }
+        let result = escalate(&env, &bot, conf).await;
+        crate::metrics::update_service(&env.health, "chores", result.is_ok());
+        result.log_error("chores: escalate");
+    }
+}
+
+async fn escalate(env: &BotEnv, bot: &Bot, conf: &Chores) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let chores = env.transaction(|conn| {
+        for chore in &conf.chores {
+            diesel::insert_or_ignore_into(schema::chores::table)
+                .values((
+                    schema::chores::name.eq(&chore.name),
+                    schema::chores::due_at.eq(now),
+                    schema::chores::stage.eq(STAGE_IDLE),
+                ))
+                .execute(conn)?;
+        }
+        schema::chores::table
+            .filter(
+                schema::chores::name
+                    .eq_any(conf.chores.iter().map(|c| &c.name)),
+            )
+            .filter(schema::chores::due_at.le(now))
+            .select(models::Chore::as_select())
+            .load(conn)
+    })?;
+
+    for chore in chores {
+        match next_stage(conf, &chore, now) {
+            Some(STAGE_POSTED) => post(env, bot, conf, &chore).await?,
+            Some(STAGE_PINGED) => ping(env, bot, conf, &chore).await?,
+            Some(STAGE_REPORTED) => report(env, bot, conf, &chore).await?,
+            _ => continue,
+        }
+    }
+    Ok(())
+}
+
+async fn post(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Chores,
+    chore: &models::Chore,
+) -> Result<()> {
+    let mut text = format!("🧽 <b>{}</b> is due.", html::escape(&chore.name));
+    if let Some(last_done_at) = chore.last_done_at {
+        format_to!(text, "\nLast done on {}", last_done_at.format("%Y-%m-%d"));
+        if let Some(user_id) = chore.last_done_by {
+            let user = schema::tg_users::table
+                .filter(schema::tg_users::id.eq(user_id))
+                .first::<models::TgUser>(&mut *env.conn())
+                .optional()?;
+            text.push_str(" by ");
+            format_user(&mut text, user_id, user.as_ref(), false);
+        }
+        text.push('.');
+    }
+    let sent = bot
+        .send_message(conf.thread.chat, text)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(InlineKeyboardMarkup::new([[
+            InlineKeyboardButton::callback(
+                "✅ Done",
+                format!("ch:done:{}", chore.rowid),
+            ),
+        ]]))
+        .await?;
+    diesel::update(schema::chores::table)
+        .filter(schema::chores::rowid.eq(chore.rowid))
+        .set((
+            schema::chores::stage.eq(STAGE_POSTED),
+            schema::chores::chat_id.eq(DbChatId::from(sent.chat.id)),
+            schema::chores::message_id.eq(DbMessageId::from(sent.id)),
+        ))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+async fn ping(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Chores,
+    chore: &models::Chore,
+) -> Result<()> {
+    let residents: Vec<(DbUserId, Option<models::TgUser>)> =
+        schema::residents::table
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::tg_users::id.eq(schema::residents::tg_id)),
+            )
+            .filter(schema::residents::end_date.is_null())
+            .select((
+                schema::residents::tg_id,
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+
+    let mut text = format!(
+        "⏰ <b>{}</b> is still not done. Can someone take care of it?\n\n",
+        html::escape(&chore.name),
+    );
+    format_users(&mut text, residents.iter().map(|(id, u)| (*id, u.as_ref())));
+    let mut request = bot
+        .send_message(conf.thread.chat, text)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html);
+    if let Some(message_id) = chore.message_id {
+        request = request.reply_to_message_id(message_id.into());
+    }
+    request.await?;
+
+    diesel::update(schema::chores::table)
+        .filter(schema::chores::rowid.eq(chore.rowid))
+        .set(schema::chores::stage.eq(STAGE_PINGED))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+async fn report(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Chores,
+    chore: &models::Chore,
+) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let mut text = format!(
+        "⚠️ Chore <b>{}</b> is overdue by {} hours.",
+        html::escape(&chore.name),
+        (now - chore.due_at).num_hours(),
+    );
+    if let (Some(chat_id), Some(message_id)) = (chore.chat_id, chore.message_id)
+    {
+        text.push(' ');
+        write_message_link(&mut text, chat_id, message_id);
+        text.push_str("Open message</a>");
+    }
+    bot.send_message(conf.report_to.chat, text)
+        .message_thread_id(conf.report_to.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+
+    diesel::update(schema::chores::table)
+        .filter(schema::chores::rowid.eq(chore.rowid))
+        .set(schema::chores::stage.eq(STAGE_REPORTED))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<i32> {
+    callback.data.as_ref()?.strip_prefix("ch:done:")?.parse().ok()
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    rowid: i32,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.chores else { return Ok(()) };
+    let result = env.transaction(|conn| {
+        if !is_resident(conn, &callback.from) {
+            return Ok(Err("Only residents can check off chores."));
+        }
+        let chore = schema::chores::table
+            .filter(schema::chores::rowid.eq(rowid))
+            .select(models::Chore::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(chore) = chore else {
+            return Ok(Err("This chore no longer exists."));
+        };
+        if chore.stage == STAGE_IDLE {
+            return Ok(Err("This chore is already done."));
+        }
+        let Some(every_days) = conf
+            .chores
+            .iter()
+            .find(|c| c.name == chore.name)
+            .map(|c| c.every_days)
+        else {
+            return Ok(Err("This chore is no longer on the checklist."));
+        };
+        let now = chrono::Utc::now().naive_utc();
+        diesel::update(schema::chores::table)
+            .filter(schema::chores::rowid.eq(rowid))
+            .set((
+                schema::chores::stage.eq(STAGE_IDLE),
+                schema::chores::due_at
+                    .eq(now + chrono::Duration::days(every_days.into())),
+                schema::chores::last_done_at.eq(now),
+                schema::chores::last_done_by
+                    .eq(DbUserId::from(callback.from.id)),
+                schema::chores::chat_id.eq(None::<DbChatId>),
+                schema::chores::message_id.eq(None::<DbMessageId>),
+            ))
+            .execute(conn)?;
+        Ok(Ok(chore))
+    })?;
+
+    match result {
+        Ok(chore) => {
+            bot.answer_callback_query(&callback.id).text("Thank you!").await?;
+            if let Some(message) = &callback.message {
+                let mut text =
+                    format!("✅ <b>{}</b> done by ", html::escape(&chore.name));
+                let user = models::TgUser {
+                    id: callback.from.id.into(),
+                    username: callback.from.username.clone(),
+                    first_name: callback.from.first_name.clone(),
+                    last_name: callback.from.last_name.clone(),
+                };
+                format_user(&mut text, callback.from.id, &user, false);
+                text.push('.');
+                bot.edit_message_text(message.chat.id, message.id, text)
+                    .parse_mode(ParseMode::Html)
+                    .await
+                    .log_error("chores: edit message");
+            }
+        }
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn cmd_chores(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let Some(conf) = &env.config.telegram.chores else {
+        bot.reply_message(&msg, "Chores are not configured.").await?;
+        return Ok(());
+    };
+    let chores: Vec<(models::Chore, Option<models::TgUser>)> =
+        schema::chores::table
+            .left_join(
+                schema::tg_users::table.on(schema::tg_users::id
+                    .nullable()
+                    .eq(schema::chores::last_done_by)),
+            )
+            .select((
+                models::Chore::as_select(),
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let tz = timezones::chat_tz(&env, &mut env.conn(), msg.chat.id)?;
+    let mut text = String::from("🧽 <b>Chores</b>\n");
+    for chore_conf in &conf.chores {
+        format_to!(
+            text,
+            "\n<b>{}</b> (every {} days): ",
+            html::escape(&chore_conf.name),
+            chore_conf.every_days,
+        );
+        let Some((chore, user)) =
+            chores.iter().find(|(c, _)| c.name == chore_conf.name)
+        else {
+            text.push_str("not tracked yet");
+            continue;
+        };
+        if chore.due_at <= now {
+            text.push_str("⚠️ due now");
+        } else {
+            let due_at = timezones::to_local(tz, chore.due_at);
+            format_to!(text, "due {}", due_at.format("%Y-%m-%d %H:%M"));
+        }
+        if let (Some(at), Some(by)) = (chore.last_done_at, chore.last_done_by) {
+            let at = timezones::to_local(tz, at);
+            format_to!(text, ", last done {} by ", at.format("%Y-%m-%d"));
+            format_user(&mut text, by, user.as_ref(), false);
+        }
+    }
+    bot.reply_message(&msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::{MessageId, ThreadId};
+
+    use super::*;
+    use crate::utils::ThreadIdPair;
+
+    #[test]
+    fn test_next_stage() {
+        let thread =
+            ThreadIdPair { chat: ChatId(1), thread: ThreadId(MessageId(1)) };
+        let conf = Chores {
+            thread,
+            report_to: thread,
+            ping_after_hours: 12,
+            report_after_hours: 48,
+            chores: Vec::new(),
+        };
+        let due_at = chrono::NaiveDate::from_ymd_opt(2026, 10, 16)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let chore = |stage| models::Chore {
+            rowid: 1,
+            name: "Water plants".to_string(),
+            due_at,
+            stage,
+            last_done_at: None,
+            last_done_by: None,
+            chat_id: None,
+            message_id: None,
+        };
+        let at = |hours| due_at + chrono::Duration::hours(hours);
+
+        assert_eq!(next_stage(&conf, &chore(STAGE_IDLE), at(-1)), None);
+        assert_eq!(
+            next_stage(&conf, &chore(STAGE_IDLE), at(0)),
+            Some(STAGE_POSTED)
+        );
+        assert_eq!(
+            next_stage(&conf, &chore(STAGE_IDLE), at(100)),
+            Some(STAGE_POSTED)
+        );
+        assert_eq!(next_stage(&conf, &chore(STAGE_POSTED), at(11)), None);
+        assert_eq!(
+            next_stage(&conf, &chore(STAGE_POSTED), at(12)),
+            Some(STAGE_PINGED)
+        );
+        assert_eq!(
+            next_stage(&conf, &chore(STAGE_PINGED), at(48)),
+            Some(STAGE_REPORTED)
+        );
+        assert_eq!(next_stage(&conf, &chore(STAGE_REPORTED), at(100)), None);
+    }
+}
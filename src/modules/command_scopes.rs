@@ -0,0 +1,175 @@
+//! Command lists shown by Telegram, generated from [`modules::commands`].
+//!
+//! Each audience gets the commands it can run: everyone in private chats and
+//! groups, residents in residential chats and in their private chats, and
+//! admins everywhere.  The lists are pushed on startup, and the private list
+//! of a user is updated when their role changes.
+//!
+//! **Scope**: background, on startup and on role changes.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use itertools::Itertools as _;
+use teloxide::prelude::*;
+use teloxide::types::{BotCommand, BotCommandScope, Recipient};
+
+use crate::common::{BotEnv, CommandInfo};
+use crate::modules;
+use crate::modules::role_changes::{Role, RoleChange};
+use crate::utils::{html, ResultExt as _};
+
+/// Telegram limits the number of commands in a list.
+const MAX_COMMANDS: usize = 100;
+
+/// Telegram limits the length of a command description.
+const MAX_DESCRIPTION_CHARS: usize = 256;
+
+/// Audience of a command list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Audience {
+    admin: bool,
+    resident: bool,
+    in_group: bool,
+}
+
+/// Push the command lists for all scopes.
+pub async fn sync(env: Arc<BotEnv>, bot: Bot) {
+    sync_all(&env, &bot).await.log_error("command_scopes: sync");
+}
+
+async fn sync_all(env: &BotEnv, bot: &Bot) -> Result<()> {
+    let everyone = Audience { admin: false, resident: false, in_group: false };
+    set(bot, BotCommandScope::AllPrivateChats, everyone).await?;
+    let group = Audience { in_group: true, ..everyone };
+    set(bot, BotCommandScope::AllGroupChats, group).await?;
+
+    let admins = &env.config.telegram.admins;
+    for &chat in &env.config.telegram.chats.residential {
+        let residents = Audience { resident: true, ..group };
+        set(bot, chat_scope(chat), residents).await?;
+        for &admin in admins {
+            let scope = BotCommandScope::ChatMember {
+                chat_id: Recipient::Id(chat),
+                user_id: admin,
+            };
+            set(bot, scope, Audience { admin: true, ..residents }).await?;
+        }
+    }
+
+    let residents = env.cache.residents(&env.conn)?;
+    for &user in residents.iter().chain(admins).unique() {
+        sync_user(env, bot, user).await?;
+    }
+    Ok(())
+}
+
+/// Push the private command list of the user, or drop it if the user has
+/// no role.
+async fn sync_user(env: &BotEnv, bot: &Bot, user: UserId) -> Result<()> {
+    let audience = Audience {
+        admin: env.config.telegram.admins.contains(&user),
+        resident: env.is_resident(user),
+        in_group: false,
+    };
+    let scope = chat_scope(ChatId::from(user));
+    if audience.admin || audience.resident {
+        set(bot, scope, audience).await?;
+    } else {
+        bot.delete_my_commands().scope(scope).await?;
+    }
+    Ok(())
+}
+
+pub async fn on_role_change(
+    env: &Arc<BotEnv>,
+    bot: &Bot,
+    change: &RoleChange,
+) -> Result<()> {
+    if change.old != Role::Resident && change.new != Role::Resident {
+        return Ok(());
+    }
+    sync_user(env, bot, change.user).await
+}
+
+fn chat_scope(chat: ChatId) -> BotCommandScope {
+    BotCommandScope::Chat { chat_id: Recipient::Id(chat) }
+}
+
+async fn set(
+    bot: &Bot,
+    scope: BotCommandScope,
+    audience: Audience,
+) -> Result<()> {
+    let commands = commands_for(&modules::commands(), audience);
+    bot.set_my_commands(commands).scope(scope).await?;
+    Ok(())
+}
+
+/// The list of commands the audience can run.
+fn commands_for(
+    commands: &[CommandInfo],
+    audience: Audience,
+) -> Vec<BotCommand> {
+    let mut list: Vec<BotCommand> = commands
+        .iter()
+        .filter(|c| {
+            (!c.rules.admin || audience.admin)
+                && (!c.rules.resident || audience.resident)
+                && if audience.in_group {
+                    c.rules.in_group
+                } else {
+                    c.rules.in_private
+                }
+        })
+        .map(|c| {
+            let description: String = html::to_plain(&c.description)
+                .chars()
+                .take(MAX_DESCRIPTION_CHARS)
+                .collect();
+            BotCommand::new(&c.name, description)
+        })
+        .collect();
+    if list.len() > MAX_COMMANDS {
+        log::warn!(
+            "command_scopes: {} commands for {audience:?}, only {MAX_COMMANDS} \
+             are shown",
+            list.len(),
+        );
+        list.truncate(MAX_COMMANDS);
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_for() {
+        let all = modules::commands();
+        let names = |audience| {
+            commands_for(&all, audience)
+                .into_iter()
+                .map(|c| c.command)
+                .collect::<Vec<_>>()
+        };
+        let everyone =
+            Audience { admin: false, resident: false, in_group: false };
+        let resident = Audience { resident: true, ..everyone };
+        let admin = Audience { admin: true, ..resident };
+
+        assert!(names(everyone).contains(&"help".to_string()));
+        assert!(!names(everyone).contains(&"residents_timeline".to_string()));
+        assert!(names(resident).contains(&"residents_timeline".to_string()));
+        assert!(!names(resident).contains(&"dm".to_string()));
+        assert!(names(admin).contains(&"dm".to_string()));
+        // `/settings` is not available in groups.
+        assert!(!names(Audience { in_group: true, ..admin })
+            .contains(&"settings".to_string()));
+
+        for command in commands_for(&all, admin) {
+            assert!(!command.description.contains("<code>"), "{command:?}");
+        }
+    }
+}
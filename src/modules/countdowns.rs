@@ -0,0 +1,409 @@
+//! Pinned messages counting down to a moment, e.g.
+//! `/countdown 2026-12-31 23:00 New Year party`.
+//!
+//! The message shows the days left, then hours, then minutes in the last
+//! hours.  When the moment arrives, the message is unpinned and the moment is
+//! announced in a reply.  Dates are in the time zone of the space.  Instead
+//! of a date, `#<id>` counts down to the start of a booking of the `bookings`
+//! module.
+//!
+//! **Scope**: `/countdown` command, available to residents in groups;
+//! background task.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{MessageId, ParseMode};
+use teloxide::{ApiError, RequestError};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::models::{OutboxAction, UndoAction};
+use crate::modules::timezones::{self, space_tz};
+use crate::modules::{activity_feed, undo};
+use crate::outbox;
+use crate::utils::{html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "pin a countdown: <code>/countdown YYYY-MM-DD \
+                             [HH:MM] title</code> or <code>/countdown \
+                             #booking title</code>.")]
+    #[custom(resident = true, in_private = false)]
+    Countdown(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_countdown)
+}
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq, Eq)]
+enum Target {
+    /// Local time of the space.
+    At(NaiveDateTime),
+    Booking(i32),
+}
+
+/// Parse `YYYY-MM-DD [HH:MM] title` or `#id title`.
+fn parse_args(args: &str) -> Option<(Target, &str)> {
+    let (first, rest) = args.trim().split_once(char::is_whitespace)?;
+    let rest = rest.trim_start();
+    let (target, title) = if let Some(id) = first.strip_prefix('#') {
+        (Target::Booking(id.parse().ok()?), rest)
+    } else {
+        let date = NaiveDate::parse_from_str(first, "%Y-%m-%d").ok()?;
+        let midnight = NaiveTime::from_hms_opt(0, 0, 0)?;
+        let (time, title) = rest
+            .split_once(char::is_whitespace)
+            .and_then(|(time, title)| {
+                let time = NaiveTime::parse_from_str(time, "%H:%M").ok()?;
+                Some((time, title.trim_start()))
+            })
+            .unwrap_or((midnight, rest));
+        (Target::At(date.and_time(time)), title)
+    };
+    (!title.is_empty()).then_some((target, title))
+}
+
+async fn cmd_countdown(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Countdown(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some((target, title)) = parse_args(&args) else {
+        bot.reply_message(
+            &msg,
+            "Usage: /countdown YYYY-MM-DD [HH:MM] <title>\n\
+             Or: /countdown #<booking> <title>",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let target_at = match target {
+        Target::At(local) => timezones::to_utc(space_tz(&env), local),
+        Target::Booking(rowid) => {
+            let start_at = schema::bookings::table
+                .filter(schema::bookings::rowid.eq(rowid))
+                .select(schema::bookings::start_at)
+                .first(&mut *env.conn())
+                .optional()?;
+            let Some(start_at) = start_at else {
+                bot.reply_message(&msg, "Unknown booking.").await?;
+                return Ok(());
+            };
+            start_at
+        }
+    };
+    let now = chrono::Utc::now().naive_utc();
+    if target_at <= now {
+        bot.reply_message(&msg, "This moment is in the past.").await?;
+        return Ok(());
+    }
+
+    let text = render(space_tz(&env), title, target_at, now);
+    let sent =
+        bot.reply_message(&msg, &text).parse_mode(ParseMode::Html).await?;
+    env.transaction(|conn| {
+        use schema::countdowns::dsl as c;
+        diesel::insert_into(c::countdowns)
+            .values((
+                c::chat_id.eq(DbChatId::from(sent.chat.id)),
+                c::message_id.eq(DbMessageId::from(sent.id)),
+                c::title.eq(title),
+                c::target_at.eq(target_at),
+                c::created_by.eq(DbUserId::from(from.id)),
+                c::text.eq(&text),
+                c::finished.eq(false),
+            ))
+            .execute(conn)?;
+        activity_feed::record(
+            conn,
+            "countdowns",
+            Some(from.id),
+            "started a countdown to",
+            title,
+            sent.url().as_ref().map(|u| u.as_str()),
+        )?;
+        undo::push(
+            conn,
+            sent.chat.id,
+            from.id,
+            "countdown pin",
+            vec![UndoAction::Telegram {
+                action: OutboxAction::UnpinMessage {
+                    chat_id: sent.chat.id,
+                    message_id: sent.id,
+                },
+            }],
+        )?;
+        outbox::enqueue(
+            conn,
+            OutboxAction::PinMessage {
+                chat_id: sent.chat.id,
+                message_id: sent.id,
+            },
+            Vec::new(),
+        )
+    })?;
+    outbox::wake(&env);
+    Ok(())
+}
+
+/// Keep countdown messages up to date, and finish them when due.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    loop {
+        update(&env, &bot).await.log_error("countdowns: update");
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(UPDATE_INTERVAL) => {}
+        }
+    }
+}
+
+async fn update(env: &BotEnv, bot: &Bot) -> Result<()> {
+    let active: Vec<models::Countdown> = schema::countdowns::table
+        .filter(schema::countdowns::finished.eq(false))
+        .select(models::Countdown::as_select())
+        .load(&mut *env.conn())?;
+    let now = chrono::Utc::now().naive_utc();
+    for countdown in active {
+        if countdown.target_at <= now {
+            finish(env, bot, &countdown).await.log_error("countdowns: finish");
+        } else {
+            update_text(env, bot, &countdown, now)
+                .await
+                .log_error("countdowns: update text");
+        }
+    }
+    Ok(())
+}
+
+async fn update_text(
+    env: &BotEnv,
+    bot: &Bot,
+    countdown: &models::Countdown,
+    now: NaiveDateTime,
+) -> Result<()> {
+    let text =
+        render(space_tz(env), &countdown.title, countdown.target_at, now);
+    if text == countdown.text {
+        return Ok(());
+    }
+    let result = bot
+        .edit_message_text(
+            countdown.chat_id,
+            countdown.message_id.into(),
+            &text,
+        )
+        .parse_mode(ParseMode::Html)
+        .await;
+    let finished = match result {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => false,
+        // Deleted by an admin, so there is nothing left to update.
+        Err(RequestError::Api(ApiError::MessageToEditNotFound)) => true,
+        Err(e) => return Err(e.into()),
+    };
+    diesel::update(schema::countdowns::table)
+        .filter(schema::countdowns::rowid.eq(countdown.rowid))
+        .set((
+            schema::countdowns::text.eq(&text),
+            schema::countdowns::finished.eq(finished),
+        ))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+async fn finish(
+    env: &BotEnv,
+    bot: &Bot,
+    countdown: &models::Countdown,
+) -> Result<()> {
+    let chat_id = ChatId::from(countdown.chat_id);
+    let message_id = MessageId::from(countdown.message_id);
+    let title = html::escape(&countdown.title);
+    let text = format!("🎉 <b>{title}</b>: the time has come!");
+    match bot
+        .edit_message_text(chat_id, message_id, &text)
+        .parse_mode(ParseMode::Html)
+        .await
+    {
+        Ok(_)
+        | Err(RequestError::Api(
+            ApiError::MessageNotModified | ApiError::MessageToEditNotFound,
+        )) => (),
+        Err(e) => return Err(e.into()),
+    }
+    bot.send_message(chat_id, format!("🎉 It's time: <b>{title}</b>!"))
+        .reply_to_message_id(message_id)
+        .allow_sending_without_reply(true)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    env.transaction(|conn| {
+        diesel::update(schema::countdowns::table)
+            .filter(schema::countdowns::rowid.eq(countdown.rowid))
+            .set((
+                schema::countdowns::text.eq(&text),
+                schema::countdowns::finished.eq(true),
+            ))
+            .execute(conn)?;
+        outbox::enqueue(
+            conn,
+            OutboxAction::UnpinMessage { chat_id, message_id },
+            Vec::new(),
+        )
+    })?;
+    outbox::wake(&env);
+    Ok(())
+}
+
+fn render(
+    tz: Tz,
+    title: &str,
+    target_at: NaiveDateTime,
+    now: NaiveDateTime,
+) -> String {
+    let local = timezones::to_local(tz, target_at);
+    format!(
+        "⏳ <b>{}</b>, {}\n{} left.",
+        html::escape(title),
+        local.format("%Y-%m-%d %H:%M"),
+        remaining(target_at - now),
+    )
+}
+
+/// Days while more than two are left, then hours, then hours and minutes in
+/// the last three hours.  Rounded so that the text changes rarely.
+fn remaining(left: chrono::Duration) -> String {
+    let plural = |n: i64, unit: &str| {
+        format!("{n} {unit}{}", if n == 1 { "" } else { "s" })
+    };
+    // Rounded up, so that "0 minutes" is never shown before the moment.
+    let minutes = (left.num_seconds() + 59) / 60;
+    if minutes >= 2 * 24 * 60 {
+        plural(minutes / (24 * 60), "day")
+    } else if minutes >= 3 * 60 {
+        plural(minutes / 60, "hour")
+    } else if minutes >= 60 {
+        match minutes % 60 {
+            0 => plural(minutes / 60, "hour"),
+            m => format!(
+                "{} {}",
+                plural(minutes / 60, "hour"),
+                plural(m, "minute")
+            ),
+        }
+    } else {
+        plural(minutes, "minute")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::dispatching::UpdateFilterExt;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    fn date(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args("2026-12-31 23:00 New Year party"),
+            Some((Target::At(date("2026-12-31 23:00")), "New Year party")),
+        );
+        assert_eq!(
+            parse_args("2026-12-31  Last day"),
+            Some((Target::At(date("2026-12-31 00:00")), "Last day")),
+        );
+        assert_eq!(
+            parse_args("#12 Laser"),
+            Some((Target::Booking(12), "Laser"))
+        );
+        assert_eq!(parse_args("2026-12-31"), None);
+        assert_eq!(parse_args("#x Laser"), None);
+        assert_eq!(parse_args("tomorrow Party"), None);
+    }
+
+    #[test]
+    fn test_remaining() {
+        let left = |m: i64| remaining(chrono::Duration::minutes(m));
+        assert_eq!(left(12 * 24 * 60 + 5), "12 days");
+        assert_eq!(left(2 * 24 * 60), "2 days");
+        assert_eq!(left(2 * 24 * 60 - 1), "47 hours");
+        assert_eq!(left(3 * 60), "3 hours");
+        assert_eq!(left(2 * 60 + 5), "2 hours 5 minutes");
+        assert_eq!(left(60), "1 hour");
+        assert_eq!(left(1), "1 minute");
+        assert_eq!(remaining(chrono::Duration::seconds(1)), "1 minute");
+    }
+
+    #[tokio::test]
+    async fn test_countdown_flow() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let alice = testing::user_json(1, "Alice");
+        let handler = Update::filter_message().branch(command_handler());
+
+        t.dispatch(
+            &handler,
+            testing::message(
+                CHAT,
+                None,
+                &alice,
+                "/countdown 2099-01-01 Launch",
+            ),
+        )
+        .await;
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        let text = sent[0]["text"].as_str().unwrap();
+        assert!(text.contains("<b>Launch</b>, 2099-01-01 00:00"), "{text}");
+        outbox::process(&t.env, &t.bot).await;
+        assert_eq!(t.telegram.calls("pinChatMessage").len(), 1);
+        t.telegram.clear();
+
+        // The moment arrives.
+        diesel::update(schema::countdowns::table)
+            .set(schema::countdowns::target_at.eq(date("2000-01-01 00:00")))
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        update(&t.env, &t.bot).await.unwrap();
+        assert_eq!(t.telegram.calls("editMessageText").len(), 1);
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["text"], "🎉 It's time: <b>Launch</b>!");
+        outbox::process(&t.env, &t.bot).await;
+        assert_eq!(t.telegram.calls("unpinChatMessage").len(), 1);
+        t.telegram.clear();
+
+        // Finished countdowns are left alone.
+        update(&t.env, &t.bot).await.unwrap();
+        assert!(t.telegram.calls("editMessageText").is_empty());
+        assert!(t.telegram.calls("sendMessage").is_empty());
+    }
+}
@@ -0,0 +1,330 @@
+//! Track donations and thank supporters in a monthly post.
+//!
+//! **Scope**: donations are recorded in private chats by treasurers listed in
+//! [`telegram.treasury.treasurers`], so donor names aren't exposed in groups,
+//! and thank-you messages are posted to the
+//! [`telegram.treasury.donations`] thread.  Donors recorded as private are
+//! only counted, never named.
+//!
+//! [`telegram.treasury.treasurers`]: crate::config::Treasury::treasurers
+//! [`telegram.treasury.donations`]: crate::config::Treasury::donations
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{Datelike as _, NaiveDate, NaiveDateTime, NaiveTime};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::modules::reimbursements::{format_amount, parse_amount};
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(
+        description = "record a donation (treasurers only): <code>/donation \
+                       record from amount [private]</code>."
+    )]
+    #[custom(in_group = false)]
+    Donation(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_donation)
+}
+
+async fn cmd_donation(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Donation(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) = &env.config.telegram.treasury else {
+        bot.reply_message(&msg, "Donations are not configured.").await?;
+        return Ok(());
+    };
+    if !conf.treasurers.contains(&from.id) {
+        bot.reply_message(&msg, "Only treasurers can record donations.")
+            .await?;
+        return Ok(());
+    }
+
+    let mut args = args.split_whitespace().collect_vec();
+    let private = args.last() == Some(&"private");
+    if private {
+        args.pop();
+    }
+    let parsed = match args.as_slice() {
+        ["record", donor @ .., amount] if !donor.is_empty() => {
+            parse_amount(amount).map(|amount| (donor.join(" "), amount))
+        }
+        _ => None,
+    };
+    let Some((donor, amount_cents)) = parsed else {
+        bot.reply_message(
+            &msg,
+            "Usage: /donation record <from> <amount> [private]",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    env.transaction(|conn| {
+        diesel::insert_into(schema::donations::table)
+            .values(models::NewDonation {
+                donor: &donor,
+                amount_cents,
+                private,
+                recorded_by: from.id.into(),
+                created_at: chrono::Utc::now().naive_utc(),
+            })
+            .execute(conn)?;
+        crate::modules::audit::record(
+            conn,
+            Some(from.id),
+            "donation_record",
+            &serde_json::json!({
+                "donor": donor,
+                "amount_cents": amount_cents,
+                "private": private,
+            }),
+        )
+        .log_error("donations: audit");
+        Ok(())
+    })?;
+
+    bot.reply_message(
+        &msg,
+        format!(
+            "Recorded a{} donation of {} from {donor}.",
+            if private { " private" } else { "" },
+            format_amount(amount_cents, &conf.currency),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Compute donation totals relative to `now`.
+pub fn totals(
+    conn: &mut SqliteConnection,
+    now: NaiveDateTime,
+) -> QueryResult<models::DonationTotals> {
+    let rows: Vec<(String, i64, NaiveDateTime)> = schema::donations::table
+        .select((
+            schema::donations::donor,
+            schema::donations::amount_cents,
+            schema::donations::created_at,
+        ))
+        .load(conn)?;
+
+    let mut totals = models::DonationTotals::default();
+    let mut donors_this_month = Vec::new();
+    for (donor, amount, created_at) in rows {
+        totals.total_cents += amount;
+        if created_at.year() == now.year() {
+            totals.this_year_cents += amount;
+            if created_at.month() == now.month() {
+                totals.this_month_cents += amount;
+                donors_this_month.push(donor);
+            }
+        }
+    }
+    donors_this_month.sort();
+    donors_this_month.dedup();
+    totals.donors_this_month = donors_this_month.len();
+    Ok(totals)
+}
+
+/// Post a thank-you message for the previous month, once a month.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.treasury else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(60 * 60)) => {}
+        }
+
+        let today = chrono::Utc::now().date_naive();
+        let Some(this_month) = today.with_day(1) else { continue };
+        let Some(prev_month) =
+            this_month.pred_opt().and_then(|d| d.with_day(1))
+        else {
+            continue;
+        };
+        let month_key = prev_month.format("%Y-%m").to_string();
+        let last = models::donations_last_thank_you.get(&mut env.conn());
+        if last.as_ref().is_ok_and(|m| m.as_ref() == Some(&month_key)) {
+            continue;
+        }
+
+        let result =
+            thank_donors(&env, &bot, conf, prev_month, this_month).await;
+        if let Err(e) = result {
+            log::error!("donations: failed to post thank-you message: {e}");
+            continue;
+        }
+        models::donations_last_thank_you
+            .set(&mut env.conn(), &month_key)
+            .log_error("donations: set last thank-you month");
+    }
+}
+
+async fn thank_donors(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &crate::config::Treasury,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<()> {
+    let donations: Vec<(String, i64, bool)> = schema::donations::table
+        .filter(
+            schema::donations::created_at
+                .ge(from.and_time(NaiveTime::default())),
+        )
+        .filter(
+            schema::donations::created_at.lt(to.and_time(NaiveTime::default())),
+        )
+        .select((
+            schema::donations::donor,
+            schema::donations::amount_cents,
+            schema::donations::private,
+        ))
+        .load(&mut *env.conn())?;
+    if donations.is_empty() {
+        return Ok(());
+    }
+
+    bot.send_message(
+        conf.donations.chat,
+        thank_you_text(
+            &donations,
+            &from.format("%B %Y").to_string(),
+            &conf.currency,
+        ),
+    )
+    .message_thread_id(conf.donations.thread)
+    .parse_mode(ParseMode::Html)
+    .await?;
+
+    Ok(())
+}
+
+fn thank_you_text(
+    donations: &[(String, i64, bool)],
+    month: &str,
+    currency: &str,
+) -> String {
+    let public = donations
+        .iter()
+        .filter(|(_, _, private)| !private)
+        .map(|(donor, _, _)| donor.as_str())
+        .sorted()
+        .dedup()
+        .collect_vec();
+    let private = donations.iter().filter(|(_, _, private)| *private).count();
+    let total = donations.iter().map(|(_, amount, _)| amount).sum::<i64>();
+
+    let mut text = String::new();
+    format_to!(
+        text,
+        "💚 Thank you to everyone who supported the space in {month}!\n\n",
+    );
+    text.push_str(&public.iter().map(|d| html::escape(d)).join(", "));
+    match (public.is_empty(), private) {
+        (_, 0) => (),
+        (true, 1) => text.push_str("One anonymous supporter"),
+        (true, n) => format_to!(text, "{n} anonymous supporters"),
+        (false, 1) => text.push_str(", and one anonymous supporter"),
+        (false, n) => format_to!(text, ", and {n} anonymous supporters"),
+    }
+    format_to!(text, ".\n\nTotal: <b>{}</b>", format_amount(total, currency),);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::dispatching::UpdateFilterExt as _;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    #[tokio::test]
+    async fn test_donation_in_group() {
+        let t = TestBot::new();
+        let treasurer = testing::user_json(1, "Treasurer");
+        // The rejected command is left unhandled.
+        let handler: UpdateHandler = Update::filter_message()
+            .branch(command_handler())
+            .endpoint(|| async { Ok(()) });
+        t.dispatch(
+            &handler,
+            testing::message(
+                CHAT,
+                None,
+                &treasurer,
+                "/donation record Alice 10 private",
+            ),
+        )
+        .await;
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(
+            sent[0]["text"],
+            "This command is not allowed in group chats",
+        );
+        assert_eq!(
+            schema::donations::table
+                .count()
+                .get_result::<i64>(&mut *t.env.conn())
+                .unwrap(),
+            0,
+        );
+    }
+
+    #[test]
+    fn test_thank_you_text() {
+        let donation =
+            |donor: &str, amount, private| (donor.to_string(), amount, private);
+        assert_eq!(
+            thank_you_text(
+                &[
+                    donation("Bob", 1000, false),
+                    donation("Alice & Co", 500, false),
+                    donation("Bob", 250, false),
+                    donation("Eve", 100, true),
+                ],
+                "May 2026",
+                "EUR",
+            ),
+            "💚 Thank you to everyone who supported the space in May 2026!\n\n\
+             Alice &amp; Co, Bob, and one anonymous supporter.\n\n\
+             Total: <b>18.50 EUR</b>",
+        );
+        assert_eq!(
+            thank_you_text(
+                &[donation("Eve", 100, true), donation("Mallory", 1, true)],
+                "May 2026",
+                "EUR",
+            ),
+            "💚 Thank you to everyone who supported the space in May 2026!\n\n\
+             2 anonymous supporters.\n\nTotal: <b>1.01 EUR</b>",
+        );
+    }
+}
@@ -0,0 +1,428 @@
+//! Door keycode and NFC tag provisioning via Home Assistant.
+//!
+//! Admins and users with the [`telegram.door_access.role`] provision and
+//! revoke door credentials of residents with `/door`.  Credentials are pushed
+//! to the door controller by calling the [`telegram.door_access.add_service`]
+//! and [`telegram.door_access.remove_service`] Home Assistant services, with
+//! the credential ID as the `slot`.  A credential whose provisioning failed is
+//! revoked on the door controller, and stays listed until that succeeds.
+//! Credentials of former residents are revoked automatically.  All changes
+//! are recorded in the audit log.
+//!
+//! **Scope**: `/door` command in private chats, available to keyholders;
+//! background task.
+//!
+//! [`telegram.door_access.role`]: crate::config::DoorAccess::role
+//! [`telegram.door_access.add_service`]: crate::config::DoorAccess::add_service
+//! [`telegram.door_access.remove_service`]: crate::config::DoorAccess::remove_service
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_user, resolve_user, BotCommandsExt, BotEnv,
+    UpdateHandler,
+};
+use crate::db::DbUserId;
+use crate::utils::home_assistant::call_service;
+use crate::utils::{format_to, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+const KIND_KEYCODE: &str = "keycode";
+const KIND_NFC: &str = "nfc";
+
+/// How often to check for credentials of former residents.
+const REVOKE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(
+        description = "manage door credentials: <code>/door list</code>, \
+                             <code>/door add user keycode|nfc code</code> or \
+                             <code>/door revoke id</code>."
+    )]
+    #[custom(in_group = false)]
+    Door(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_door)
+}
+
+/// Check the code format: 4 to 10 digits for keycodes, an even number of hex
+/// digits for NFC tag UIDs.
+fn valid_code(kind: &str, code: &str) -> bool {
+    match kind {
+        KIND_KEYCODE => {
+            (4..=10).contains(&code.len())
+                && code.chars().all(|c| c.is_ascii_digit())
+        }
+        KIND_NFC => {
+            (8..=20).contains(&code.len())
+                && code.len() % 2 == 0
+                && code.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        _ => false,
+    }
+}
+
+/// Hide all but the last two characters of the code.
+fn mask_code(code: &str) -> String {
+    let visible = code.len().saturating_sub(2);
+    format!("{}{}", "•".repeat(visible), code.get(visible..).unwrap_or(""))
+}
+
+async fn cmd_door(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Door(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) = &env.config.telegram.door_access else {
+        bot.reply_message(&msg, "Door access is not configured.").await?;
+        return Ok(());
+    };
+    let allowed = env.config.telegram.admins.contains(&from.id)
+        || crate::modules::roles::users_with_role(&env, &conf.role)?
+            .contains(&from.id);
+    if !allowed {
+        bot.reply_message(&msg, "Only keyholders can manage door access.")
+            .await?;
+        return Ok(());
+    }
+
+    let args = args.split_whitespace().collect_vec();
+    match args.as_slice() {
+        [] | ["list"] => cmd_list(&bot, &env, &msg).await,
+        ["add", user, kind @ (KIND_KEYCODE | KIND_NFC), code] => {
+            cmd_add(&bot, &env, &msg, user, kind, code).await
+        }
+        ["revoke", id] => cmd_revoke(&bot, &env, &msg, id).await,
+        _ => {
+            bot.reply_message(
+                &msg,
+                "Usage: /door list|add <user> keycode|nfc <code>|revoke <id>",
+            )
+            .await?;
+            Ok(())
+        }
+    }
+}
+
+async fn cmd_list(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<()> {
+    let credentials: Vec<(models::DoorCredential, Option<models::TgUser>)> =
+        schema::door_credentials::table
+            .filter(schema::door_credentials::revoked_at.is_null())
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::door_credentials::user_id
+                        .eq(schema::tg_users::id)),
+            )
+            .order(schema::door_credentials::rowid)
+            .select((
+                models::DoorCredential::as_select(),
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+    if credentials.is_empty() {
+        bot.reply_message(msg, "No active door credentials.").await?;
+        return Ok(());
+    }
+
+    let mut text = String::from("🔑 <b>Door credentials</b>\n");
+    for (credential, user) in &credentials {
+        format_to!(text, "\n#{} ", credential.rowid);
+        format_user(&mut text, credential.user_id, user.as_ref(), false);
+        format_to!(
+            text,
+            ": {} <code>{}</code>, since {}",
+            credential.kind,
+            mask_code(&credential.code),
+            credential.created_at.format("%Y-%m-%d"),
+        );
+    }
+    bot.reply_message(msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+async fn cmd_add(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    user: &str,
+    kind: &str,
+    code: &str,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) = &env.config.telegram.door_access else { return Ok(()) };
+    let code = code.to_uppercase();
+    if !valid_code(kind, &code) {
+        bot.reply_message(
+            msg,
+            "Invalid code: keycodes are 4-10 digits, NFC tags are hex UIDs.",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(user) = resolve_user(bot, env, msg, user).await? else {
+        bot.reply_message(msg, "Unknown user.").await?;
+        return Ok(());
+    };
+    let result = env.transaction(|conn| {
+        let is_resident = schema::residents::table
+            .filter(schema::residents::tg_id.eq(DbUserId::from(user)))
+            .filter(schema::residents::end_date.is_null())
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if !is_resident {
+            return Ok(Err("Door access is only for residents."));
+        }
+        let taken = schema::door_credentials::table
+            .filter(schema::door_credentials::kind.eq(kind))
+            .filter(schema::door_credentials::code.eq(&code))
+            .filter(schema::door_credentials::revoked_at.is_null())
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if taken {
+            return Ok(Err("This code is already in use."));
+        }
+        diesel::insert_into(schema::door_credentials::table)
+            .values((
+                schema::door_credentials::user_id.eq(DbUserId::from(user)),
+                schema::door_credentials::kind.eq(kind),
+                schema::door_credentials::code.eq(&code),
+                schema::door_credentials::provisioned_by
+                    .eq(DbUserId::from(from.id)),
+                schema::door_credentials::created_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        let rowid = schema::door_credentials::table
+            .select(schema::door_credentials::rowid)
+            .order(schema::door_credentials::rowid.desc())
+            .first::<i32>(conn)?;
+        let tg_user = schema::tg_users::table
+            .filter(schema::tg_users::id.eq(DbUserId::from(user)))
+            .first::<models::TgUser>(conn)
+            .optional()?;
+        Ok(Ok((rowid, user, tg_user)))
+    })?;
+    let (rowid, user, tg_user) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            bot.reply_message(msg, e).await?;
+            return Ok(());
+        }
+    };
+    let name = tg_user.as_ref().map_or_else(String::new, |u| {
+        format!("{} {}", u.first_name, u.last_name.as_deref().unwrap_or(""))
+            .trim()
+            .to_string()
+    });
+
+    let called = call_service(
+        &env.health,
+        &env.reqwest_client,
+        &env.config.services.home_assistant,
+        &conf.add_service,
+        &serde_json::json!({
+            "slot": rowid,
+            "kind": kind,
+            "code": code,
+            "name": name,
+        }),
+    )
+    .await;
+    if let Err(e) = called {
+        log::error!("door_access: failed to provision #{rowid}: {e}");
+        // The controller may have applied the change despite the error, so
+        // keep the row until the remove service confirms the slot is empty.
+        let credential = schema::door_credentials::table
+            .filter(schema::door_credentials::rowid.eq(rowid))
+            .select(models::DoorCredential::as_select())
+            .first(&mut *env.conn())?;
+        let text = match revoke(env, &credential, Some(from.id)).await {
+            Ok(()) => "Failed to provision the credential.".to_string(),
+            Err(e) => {
+                log::error!("door_access: failed to roll back #{rowid}: {e}");
+                format!(
+                    "Failed to provision the credential. It may still be \
+                     active on the door; revoke it with /door revoke {rowid}."
+                )
+            }
+        };
+        bot.reply_message(msg, text).await?;
+        return Ok(());
+    }
+
+    crate::modules::audit::record(
+        &mut env.conn(),
+        Some(from.id),
+        "door_provision",
+        &serde_json::json!({
+            "credential_id": rowid,
+            "user_id": user.0,
+            "kind": kind,
+        }),
+    )
+    .log_error("door_access: audit");
+    bot.reply_message(msg, format!("Credential #{rowid} provisioned.")).await?;
+    Ok(())
+}
+
+async fn cmd_revoke(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    id: &str,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let credential = match id.trim_start_matches('#').parse::<i32>() {
+        Ok(id) => schema::door_credentials::table
+            .filter(schema::door_credentials::rowid.eq(id))
+            .filter(schema::door_credentials::revoked_at.is_null())
+            .select(models::DoorCredential::as_select())
+            .first(&mut *env.conn())
+            .optional()?,
+        Err(_) => None,
+    };
+    let Some(credential) = credential else {
+        bot.reply_message(msg, "No such active credential.").await?;
+        return Ok(());
+    };
+    let text = match revoke(env, &credential, Some(from.id)).await {
+        Ok(()) => "Credential revoked.",
+        Err(e) => {
+            log::error!("door_access: failed to revoke #{id}: {e}");
+            "Failed to revoke the credential."
+        }
+    };
+    bot.reply_message(msg, text).await?;
+    Ok(())
+}
+
+/// Remove the credential from the door controller and mark it as revoked.
+/// `actor` is `None` for automatic revocation.
+async fn revoke(
+    env: &BotEnv,
+    credential: &models::DoorCredential,
+    actor: Option<UserId>,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.door_access else { return Ok(()) };
+    call_service(
+        &env.health,
+        &env.reqwest_client,
+        &env.config.services.home_assistant,
+        &conf.remove_service,
+        &serde_json::json!({
+            "slot": credential.rowid,
+            "kind": credential.kind,
+            "code": credential.code,
+        }),
+    )
+    .await?;
+    env.transaction(|conn| {
+        diesel::update(schema::door_credentials::table)
+            .filter(schema::door_credentials::rowid.eq(credential.rowid))
+            .set((
+                schema::door_credentials::revoked_at
+                    .eq(chrono::Utc::now().naive_utc()),
+                schema::door_credentials::revoked_by
+                    .eq(actor.map(DbUserId::from)),
+            ))
+            .execute(conn)?;
+        crate::modules::audit::record(
+            conn,
+            actor,
+            "door_revoke",
+            &serde_json::json!({
+                "credential_id": credential.rowid,
+                "user_id": UserId::from(credential.user_id).0,
+                "kind": credential.kind,
+            }),
+        )
+        .log_error("door_access: audit");
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Revoke credentials of former residents.
+pub async fn task(env: Arc<BotEnv>, shutdown: CancellationToken) {
+    if env.config.telegram.door_access.is_none() {
+        return;
+    }
+    loop {
+        let credentials = schema::door_credentials::table
+            .filter(schema::door_credentials::revoked_at.is_null())
+            .filter(
+                schema::door_credentials::user_id.ne_all(
+                    schema::residents::table
+                        .filter(schema::residents::end_date.is_null())
+                        .select(schema::residents::tg_id),
+                ),
+            )
+            .select(models::DoorCredential::as_select())
+            .load(&mut *env.conn());
+        match credentials {
+            Ok(credentials) => {
+                for credential in &credentials {
+                    log::info!(
+                        "door_access: revoking #{} of former resident {}",
+                        credential.rowid,
+                        UserId::from(credential.user_id).0,
+                    );
+                    revoke(&env, credential, None)
+                        .await
+                        .log_error("door_access: auto-revoke");
+                }
+            }
+            Err(e) => log::error!("door_access: failed to load: {e}"),
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(REVOKE_INTERVAL) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_code() {
+        assert!(valid_code(KIND_KEYCODE, "1234"));
+        assert!(!valid_code(KIND_KEYCODE, "123"));
+        assert!(!valid_code(KIND_KEYCODE, "12a4"));
+        assert!(valid_code(KIND_NFC, "04A2B3C4"));
+        assert!(!valid_code(KIND_NFC, "04A2B3C"));
+        assert!(!valid_code(KIND_NFC, "04A2B3CG"));
+        assert!(!valid_code("other", "1234"));
+    }
+
+    #[test]
+    fn test_mask_code() {
+        assert_eq!(mask_code("123456"), "••••56");
+        assert_eq!(mask_code("1"), "1");
+    }
+}
@@ -0,0 +1,267 @@
+//! Energy usage reports.
+//!
+//! Sensor history is taken from Home Assistant.  A report for the last seven
+//! days, with a cost estimate, a comparison to the previous week, and the top
+//! [`telegram.energy.consumers`], is posted weekly to the
+//! [`telegram.energy.thread`] and on demand with `/energy`.
+//!
+//! **Scope**: `/energy` command, available to residents; background task.
+//!
+//! [`telegram.energy.consumers`]: crate::config::Energy::consumers
+//! [`telegram.energy.thread`]: crate::config::Energy::thread
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike as _, NaiveDate, Timelike as _, Utc};
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::config::Energy;
+use crate::models;
+use crate::utils::home_assistant::{get_history, HistoryState};
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+
+/// Maximum number of consumers listed in a report.
+const TOP_CONSUMERS: usize = 5;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show energy usage for the last week.")]
+    #[custom(resident = true)]
+    Energy,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_energy)
+}
+
+async fn cmd_energy(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let Some(conf) = &env.config.telegram.energy else {
+        bot.reply_message(&msg, "Energy reports are not configured.").await?;
+        return Ok(());
+    };
+    let text = match report(&env, conf, Utc::now()).await {
+        Ok(text) => text,
+        Err(e) => {
+            log::error!("energy: failed to build report: {e}");
+            "Failed to get energy data.".to_string()
+        }
+    };
+    bot.reply_message(&msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.energy else { return };
+    // Unsetting the option with `/option` re-posts today's report.
+    let mut last_report_changed =
+        match models::energy_last_report.watch(&mut env.conn()) {
+            Ok(receiver) => receiver,
+            Err(e) => {
+                log::error!("energy: failed to watch last report: {e}");
+                return;
+            }
+        };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(10 * 60)) => {}
+            _ = last_report_changed.changed() => {}
+        }
+
+        let now = Utc::now();
+        if now.weekday() != conf.weekday || now.hour() < conf.hour {
+            continue;
+        }
+        let today = now.date_naive().to_string();
+        let last = models::energy_last_report.get(&mut env.conn());
+        if last.as_ref().is_ok_and(|d| d.as_ref() == Some(&today)) {
+            continue;
+        }
+        if let Err(e) = post_report(&env, &bot, conf, now).await {
+            log::error!("energy: failed to post report: {e}");
+            continue;
+        }
+        models::energy_last_report
+            .set(&mut env.conn(), &today)
+            .log_error("energy: set last report");
+    }
+}
+
+async fn post_report(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Energy,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let text = report(env, conf, now).await?;
+    bot.send_message(conf.thread.chat, text)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+/// Build the report for the seven days before `now`.
+async fn report(
+    env: &BotEnv,
+    conf: &Energy,
+    now: DateTime<Utc>,
+) -> Result<String> {
+    let start = now - chrono::Duration::days(7);
+    let entities = std::iter::once(conf.meter.as_str())
+        .chain(conf.consumers.iter().map(|c| c.entity_id.as_str()))
+        .collect_vec();
+    let ha = &env.config.services.home_assistant;
+    let current = get_history(
+        &env.health,
+        &env.reqwest_client,
+        ha,
+        &entities,
+        start,
+        now,
+    )
+    .await?;
+    let previous = get_history(
+        &env.health,
+        &env.reqwest_client,
+        ha,
+        &[&conf.meter],
+        start - chrono::Duration::days(7),
+        start,
+    )
+    .await?;
+
+    let mut consumers = conf
+        .consumers
+        .iter()
+        .map(|c| (c.name.as_str(), usage(&current, &c.entity_id)))
+        .filter(|(_, kwh)| *kwh > 0.0)
+        .collect_vec();
+    consumers.sort_by(|a, b| b.1.total_cmp(&a.1));
+    consumers.truncate(TOP_CONSUMERS);
+
+    Ok(format_report(
+        conf,
+        start.date_naive(),
+        now.date_naive(),
+        usage(&current, &conf.meter),
+        usage(&previous, &conf.meter),
+        &consumers,
+    ))
+}
+
+/// Energy used by the entity, in kWh.
+fn usage(history: &HashMap<String, Vec<HistoryState>>, entity: &str) -> f64 {
+    history.get(entity).map_or(0.0, |states| {
+        consumption(states.iter().filter_map(|s| s.state.parse().ok()))
+    })
+}
+
+/// Sum the increments of a cumulative sensor.  A decrease is treated as a
+/// meter reset.
+fn consumption(values: impl Iterator<Item = f64>) -> f64 {
+    let mut total = 0.0;
+    let mut prev = None;
+    for value in values {
+        if let Some(prev) = prev {
+            total += if value >= prev { value - prev } else { value };
+        }
+        prev = Some(value);
+    }
+    total
+}
+
+fn format_report(
+    conf: &Energy,
+    from: NaiveDate,
+    to: NaiveDate,
+    total: f64,
+    previous: f64,
+    consumers: &[(&str, f64)],
+) -> String {
+    let mut text = format!("⚡ <b>Energy report</b>, {from} – {to}\n");
+    format_to!(
+        text,
+        "\nTotal: {total:.1} kWh ≈ {:.2} {}",
+        total * conf.tariff,
+        html::escape(&conf.currency),
+    );
+    if previous > 0.0 {
+        format_to!(
+            text,
+            "\nPrevious week: {previous:.1} kWh ({:+.0}%)",
+            (total - previous) / previous * 100.0,
+        );
+    }
+    if !consumers.is_empty() {
+        text.push_str("\n\nTop consumers:");
+        for (name, kwh) in consumers {
+            format_to!(text, "\n• {}: {kwh:.1} kWh", html::escape(name));
+            if total > 0.0 {
+                format_to!(text, " ({:.0}%)", kwh / total * 100.0);
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ThreadIdPair;
+
+    #[test]
+    fn test_consumption() {
+        assert!(consumption([].into_iter()).abs() < f64::EPSILON);
+        let values = [10.0, 12.5, 15.0, 1.0, 3.0];
+        assert!((consumption(values.into_iter()) - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_format_report() {
+        let conf = Energy {
+            thread: ThreadIdPair {
+                chat: ChatId(-1),
+                thread: teloxide::types::ThreadId(teloxide::types::MessageId(
+                    1,
+                )),
+            },
+            weekday: chrono::Weekday::Mon,
+            hour: 10,
+            meter: "sensor.meter".to_string(),
+            tariff: 6.5,
+            currency: "RUB".to_string(),
+            consumers: Vec::new(),
+        };
+        let date = |d| NaiveDate::from_ymd_opt(2026, 10, d).unwrap();
+        assert_eq!(
+            format_report(
+                &conf,
+                date(9),
+                date(16),
+                110.0,
+                100.0,
+                &[("Printers", 22.0)],
+            ),
+            "⚡ <b>Energy report</b>, 2026-10-09 – 2026-10-16\n\n\
+             Total: 110.0 kWh ≈ 715.00 RUB\n\
+             Previous week: 100.0 kWh (+10%)\n\n\
+             Top consumers:\n\
+             • Printers: 22.0 kWh (20%)",
+        );
+    }
+}
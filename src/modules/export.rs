@@ -0,0 +1,272 @@
+//! Periodic CSV snapshots of the database for analytics.
+//!
+//! Every [`telegram.export.interval_hours`], the [`telegram.export.tables`]
+//! are read in a single transaction and written as CSV files into a new
+//! subdirectory of [`telegram.export.directory`], along with a
+//! `manifest.json` describing the columns.  Columns listed in
+//! [`telegram.export.mask`] are replaced with a keyed hash, so the values can
+//! still be joined but not read.  Old snapshots beyond
+//! [`telegram.export.keep`] are removed.
+//!
+//! **Scope**: background task.
+//!
+//! [`telegram.export.interval_hours`]: crate::config::Export::interval_hours
+//! [`telegram.export.tables`]: crate::config::Export::tables
+//! [`telegram.export.directory`]: crate::config::Export::directory
+//! [`telegram.export.mask`]: crate::config::Export::mask
+//! [`telegram.export.keep`]: crate::config::Export::keep
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+use diesel::sql_types::Text;
+use diesel::{sql_query, QueryableByName, RunQueryDsl, SqliteConnection};
+use hmac::{Hmac, Mac as _};
+use itertools::Itertools as _;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::BotEnv;
+use crate::config::Export;
+
+#[derive(QueryableByName)]
+struct ColumnInfo {
+    #[diesel(sql_type = Text)]
+    name: String,
+    #[diesel(sql_type = Text)]
+    column_type: String,
+}
+
+#[derive(QueryableByName)]
+struct JsonRow {
+    #[diesel(sql_type = Text)]
+    row: String,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    exported_at: chrono::DateTime<chrono::Utc>,
+    tables: Vec<TableManifest>,
+}
+
+#[derive(Serialize)]
+struct TableManifest {
+    name: String,
+    file: String,
+    rows: usize,
+    columns: Vec<ColumnManifest>,
+}
+
+#[derive(Serialize)]
+struct ColumnManifest {
+    name: String,
+    #[serde(rename = "type")]
+    column_type: String,
+    masked: bool,
+}
+
+pub async fn task(env: Arc<BotEnv>, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.export else { return };
+    loop {
+        match export(&env, conf) {
+            Ok(path) => log::info!("export: written {}", path.display()),
+            Err(e) => log::error!("export: failed: {e:#}"),
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(
+                u64::from(conf.interval_hours) * 60 * 60,
+            )) => {}
+        }
+    }
+}
+
+/// Write a snapshot and return its directory.
+fn export(env: &BotEnv, conf: &Export) -> Result<PathBuf> {
+    let now = chrono::Utc::now();
+    let tables = env.transaction(|conn| {
+        conf.tables
+            .iter()
+            .filter(|t| {
+                let valid = is_identifier(t);
+                if !valid {
+                    log::warn!("export: invalid table name {t:?}");
+                }
+                valid
+            })
+            .map(|t| dump_table(conn, conf, t))
+            .collect::<diesel::QueryResult<Vec<_>>>()
+    })?;
+
+    // Write into a temporary directory first, so readers never see a
+    // partial snapshot.
+    let name = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let tmp = conf.directory.join(format!(".{name}"));
+    std::fs::create_dir_all(&tmp)
+        .with_context(|| format!("create {}", tmp.display()))?;
+    let mut manifest = Manifest { exported_at: now, tables: Vec::new() };
+    for (table, csv) in tables.into_iter().flatten() {
+        std::fs::write(tmp.join(&table.file), csv)?;
+        manifest.tables.push(table);
+    }
+    std::fs::write(
+        tmp.join("manifest.json"),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    let path = conf.directory.join(&name);
+    std::fs::rename(&tmp, &path)?;
+
+    prune(&conf.directory, conf.keep)?;
+    Ok(path)
+}
+
+/// Dump the table as CSV.  Returns `None` if the table does not exist.
+fn dump_table(
+    conn: &mut SqliteConnection,
+    conf: &Export,
+    table: &str,
+) -> diesel::QueryResult<Option<(TableManifest, String)>> {
+    let columns: Vec<ColumnInfo> =
+        sql_query("SELECT name, type AS column_type FROM pragma_table_info(?)")
+            .bind::<Text, _>(table)
+            .load(conn)?;
+    if columns.is_empty() {
+        log::warn!("export: table {table} does not exist");
+        return Ok(None);
+    }
+    let masked = columns
+        .iter()
+        .map(|c| conf.mask.contains(&format!("{table}.{}", c.name)))
+        .collect_vec();
+
+    // JSON can't hold BLOB values, export them as hex.
+    let exprs = columns
+        .iter()
+        .map(|c| {
+            format!(
+                "CASE WHEN typeof(\"{0}\") = 'blob' THEN hex(\"{0}\") \
+                 ELSE \"{0}\" END",
+                c.name
+            )
+        })
+        .join(", ");
+    let rows: Vec<JsonRow> = sql_query(format!(
+        "SELECT json_array({exprs}) AS row FROM \"{table}\""
+    ))
+    .load(conn)?;
+
+    let mut csv = columns.iter().map(|c| csv_field(&c.name)).join(",");
+    csv.push('\n');
+    for row in &rows {
+        let values: Vec<Value> =
+            serde_json::from_str(&row.row).map_err(|e| {
+                diesel::result::Error::DeserializationError(e.into())
+            })?;
+        let line = values
+            .iter()
+            .zip(&masked)
+            .map(|(value, &masked)| {
+                let value = match value {
+                    Value::Null => return String::new(),
+                    Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                };
+                if masked {
+                    mask(&conf.mask_key, &value)
+                } else {
+                    csv_field(&value)
+                }
+            })
+            .join(",");
+        csv.push_str(&line);
+        csv.push('\n');
+    }
+
+    let manifest = TableManifest {
+        name: table.to_string(),
+        file: format!("{table}.csv"),
+        rows: rows.len(),
+        columns: columns
+            .into_iter()
+            .zip(masked)
+            .map(|(c, masked)| ColumnManifest {
+                name: c.name,
+                column_type: c.column_type,
+                masked,
+            })
+            .collect(),
+    };
+    Ok(Some((manifest, csv)))
+}
+
+/// Remove the oldest snapshots, keeping `keep` latest.
+fn prune(directory: &Path, keep: usize) -> Result<()> {
+    let snapshots = std::fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|n| n.starts_with(|c: char| c.is_ascii_digit()))
+        .sorted()
+        .collect_vec();
+    for name in &snapshots[..snapshots.len().saturating_sub(keep)] {
+        std::fs::remove_dir_all(directory.join(name))?;
+    }
+    Ok(())
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quote the value if needed, as in RFC 4180.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Keyed hash of the value, truncated to 16 hex digits.
+fn mask(key: &str, value: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+        .expect("HMAC accepts any key length");
+    mac.update(value.as_bytes());
+    hex::encode(&mac.finalize().into_bytes()[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("key", "alice"), mask("key", "alice"));
+        assert_ne!(mask("key", "alice"), mask("key", "bob"));
+        assert_ne!(mask("key", "alice"), mask("other", "alice"));
+        assert_eq!(mask("key", "alice").len(), 16);
+    }
+
+    #[test]
+    fn test_is_identifier() {
+        assert!(is_identifier("tg_users"));
+        assert!(!is_identifier("tg_users; DROP TABLE residents"));
+        assert!(!is_identifier(""));
+    }
+}
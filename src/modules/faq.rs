@@ -0,0 +1,321 @@
+//! Automatic answers to frequently asked questions.
+//!
+//! Admins define triggers and canned answers with `/faq add`.  When a message
+//! in one of the [`telegram.faq.chats`] matches a trigger, the bot replies
+//! with the answer.  The same entry is not repeated in a chat more often than
+//! once per [`telegram.faq.cooldown_minutes`].
+//!
+//! **Scope**: `/faq` command, available to admins; messages in
+//! [`telegram.faq.chats`].
+//!
+//! [`telegram.faq.chats`]: crate::config::Faq::chats
+//! [`telegram.faq.cooldown_minutes`]: crate::config::Faq::cooldown_minutes
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use regex::Regex;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::DbUserId;
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "manage automatic answers: <code>/faq \
+                             list</code>, <code>/faq remove &lt;id&gt;</code>, \
+                             or <code>/faq add &lt;trigger&gt;</code> with the \
+                             answer on the following lines.  The trigger is \
+                             either <code>/regex/</code> or comma-separated \
+                             keywords.")]
+    #[custom(admin = true)]
+    Faq(String),
+}
+
+/// State contains the time each entry was last used in each chat.
+#[derive(Clone, Debug, Default)]
+pub struct State(HashMap<(i32, ChatId), Instant>);
+
+pub fn state() -> Arc<Mutex<State>> {
+    Arc::new(Mutex::new(State::default()))
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_faq)
+}
+
+pub fn message_handler() -> UpdateHandler {
+    Update::filter_message()
+        .filter_map(filter_messages)
+        .endpoint(handle_message)
+}
+
+/// A parsed trigger.
+#[derive(Debug, PartialEq, Eq)]
+enum Trigger {
+    Regex(String),
+    Keywords(Vec<String>),
+}
+
+impl Trigger {
+    /// Parse `/regex/` or `keyword, another keyword`.
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if let Some(pattern) = text
+            .strip_prefix('/')
+            .and_then(|t| t.strip_suffix('/'))
+            .filter(|t| !t.is_empty())
+        {
+            Regex::new(pattern).map_err(|e| format!("Invalid regex: {e}"))?;
+            return Ok(Self::Regex(pattern.to_string()));
+        }
+        let keywords: Vec<String> = text
+            .split(',')
+            .map(|k| k.trim().to_lowercase())
+            .filter(|k| !k.is_empty())
+            .collect();
+        if keywords.is_empty() {
+            return Err("Empty trigger.".to_string());
+        }
+        Ok(Self::Keywords(keywords))
+    }
+
+    fn from_entry(entry: &models::FaqEntry) -> Self {
+        if entry.is_regex {
+            Self::Regex(entry.pattern.clone())
+        } else {
+            Self::Keywords(
+                entry.pattern.split(',').map(ToString::to_string).collect(),
+            )
+        }
+    }
+
+    fn pattern(&self) -> String {
+        match self {
+            Self::Regex(pattern) => pattern.clone(),
+            Self::Keywords(keywords) => keywords.join(","),
+        }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Regex(pattern) => {
+                Regex::new(pattern).is_ok_and(|re| re.is_match(text))
+            }
+            Self::Keywords(keywords) => {
+                let text = text.to_lowercase();
+                keywords.iter().any(|k| text.contains(k.as_str()))
+            }
+        }
+    }
+}
+
+async fn cmd_faq(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Faq(args): Commands,
+) -> Result<()> {
+    let (subcommand, rest) = args
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .unwrap_or((&args, ""));
+    match subcommand.trim() {
+        "" | "list" => cmd_list(&bot, &env, &msg).await,
+        "add" => cmd_add(&bot, &env, &msg, rest).await,
+        "remove" => cmd_remove(&bot, &env, &msg, rest).await,
+        _ => {
+            bot.reply_message(&msg, "Usage: /faq list|add|remove").await?;
+            Ok(())
+        }
+    }
+}
+
+async fn cmd_list(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<()> {
+    let entries: Vec<models::FaqEntry> = schema::faq_entries::table
+        .order(schema::faq_entries::rowid)
+        .select(models::FaqEntry::as_select())
+        .load(&mut *env.conn())?;
+    if entries.is_empty() {
+        bot.reply_message(msg, "No FAQ entries.").await?;
+        return Ok(());
+    }
+
+    let mut text = String::from("<b>FAQ entries</b>\n");
+    for entry in &entries {
+        let pattern = if entry.is_regex {
+            format!("/{}/", entry.pattern)
+        } else {
+            entry.pattern.replace(',', ", ")
+        };
+        format_to!(
+            text,
+            "\n<b>#{}</b> <code>{}</code> (used {} times)\n{}\n",
+            entry.rowid,
+            html::escape(&pattern),
+            entry.uses,
+            html::escape(&entry.answer),
+        );
+    }
+    bot.reply_message(msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+async fn cmd_add(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    args: &str,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let (trigger, answer) = args.split_once('\n').unwrap_or((args, ""));
+    let answer = answer.trim();
+    if answer.is_empty() {
+        bot.reply_message(
+            msg,
+            "Usage: /faq add <trigger>, with the answer on the following \
+             lines.",
+        )
+        .await?;
+        return Ok(());
+    }
+    let trigger = match Trigger::parse(trigger) {
+        Ok(trigger) => trigger,
+        Err(e) => {
+            bot.reply_message(msg, e).await?;
+            return Ok(());
+        }
+    };
+
+    let rowid = env.transaction(|conn| {
+        diesel::insert_into(schema::faq_entries::table)
+            .values((
+                schema::faq_entries::pattern.eq(trigger.pattern()),
+                schema::faq_entries::is_regex
+                    .eq(matches!(trigger, Trigger::Regex(_))),
+                schema::faq_entries::answer.eq(answer),
+                schema::faq_entries::uses.eq(0),
+                schema::faq_entries::created_by.eq(DbUserId::from(from.id)),
+                schema::faq_entries::created_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        schema::faq_entries::table
+            .select(schema::faq_entries::rowid)
+            .order(schema::faq_entries::rowid.desc())
+            .first::<i32>(conn)
+    })?;
+    bot.reply_message(msg, format!("Added FAQ entry #{rowid}.")).await?;
+    Ok(())
+}
+
+async fn cmd_remove(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    args: &str,
+) -> Result<()> {
+    let Ok(rowid) = args.trim().trim_start_matches('#').parse::<i32>() else {
+        bot.reply_message(msg, "Usage: /faq remove <id>").await?;
+        return Ok(());
+    };
+    let deleted = diesel::delete(schema::faq_entries::table)
+        .filter(schema::faq_entries::rowid.eq(rowid))
+        .execute(&mut *env.conn())?;
+    let text = if deleted == 0 {
+        format!("No FAQ entry #{rowid}.")
+    } else {
+        format!("Removed FAQ entry #{rowid}.")
+    };
+    bot.reply_message(msg, text).await?;
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct Answer {
+    rowid: i32,
+    text: String,
+}
+
+fn filter_messages(
+    env: Arc<BotEnv>,
+    state: Arc<Mutex<State>>,
+    msg: Message,
+) -> Option<Answer> {
+    let conf = env.config.telegram.faq.as_ref()?;
+    if !conf.chats.contains(&msg.chat.id) {
+        return None;
+    }
+    let text = msg.text()?;
+    if text.starts_with('/') {
+        return None;
+    }
+
+    let entries = schema::faq_entries::table
+        .order(schema::faq_entries::rowid)
+        .select(models::FaqEntry::as_select())
+        .load(&mut *env.conn());
+    entries.log_error("faq: load entries");
+    let entries = entries.ok()?;
+    let entry =
+        entries.into_iter().find(|e| Trigger::from_entry(e).matches(text))?;
+
+    let cooldown = Duration::from_secs(u64::from(conf.cooldown_minutes) * 60);
+    let mut state = state.lock().unwrap();
+    let key = (entry.rowid, msg.chat.id);
+    if state.0.get(&key).is_some_and(|t| t.elapsed() < cooldown) {
+        return None;
+    }
+    state.0.insert(key, Instant::now());
+    Some(Answer { rowid: entry.rowid, text: entry.answer })
+}
+
+async fn handle_message(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    answer: Answer,
+) -> Result<()> {
+    bot.reply_message(&msg, answer.text).await?;
+    diesel::update(schema::faq_entries::table)
+        .filter(schema::faq_entries::rowid.eq(answer.rowid))
+        .set(schema::faq_entries::uses.eq(schema::faq_entries::uses + 1))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger() {
+        let trigger = Trigger::parse("wifi, Password ").unwrap();
+        assert_eq!(
+            trigger,
+            Trigger::Keywords(vec!["wifi".into(), "password".into()])
+        );
+        assert_eq!(trigger.pattern(), "wifi,password");
+        assert!(trigger.matches("What is the WiFi password?"));
+        assert!(!trigger.matches("Where is the printer?"));
+
+        let trigger = Trigger::parse("/^where is .+\\?$/").unwrap();
+        assert!(trigger.matches("where is the printer?"));
+        assert!(!trigger.matches("the printer is there"));
+
+        assert!(Trigger::parse("/(/").is_err());
+        assert!(Trigger::parse(" , ").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Toggle experimental behaviors per chat at runtime.
+//!
+//! Flags are defined in [`models`] with `feature_flag_def!` and listed in
+//! [`models::FEATURE_FLAGS`].  `/flags` shows the flags of the current chat,
+//! `/flags on|off|reset <name>` changes them.  Modules check flags with
+//! [`FeatureFlagDef::enabled`], which is served from [`DbCache`].
+//!
+//! **Scope**: `/flags` command, available to admins.
+//!
+//! [`FeatureFlagDef::enabled`]: crate::db::FeatureFlagDef::enabled
+//! [`DbCache`]: crate::cache::DbCache
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::FeatureFlagDef;
+use crate::models;
+use crate::utils::{format_to, html, BotExt as _};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "toggle features in this chat: \
+                             <code>/flags [on|off|reset name]</code>.")]
+    #[custom(admin = true)]
+    Flags(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_flags)
+}
+
+async fn cmd_flags(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Flags(args): Commands,
+) -> Result<()> {
+    let args = args.trim();
+    let (action, name) = args.split_once(' ').unwrap_or((args, ""));
+    let value = match action {
+        "" => {
+            bot.reply_message(&msg, list_flags(&env, msg.chat.id))
+                .parse_mode(ParseMode::Html)
+                .await?;
+            return Ok(());
+        }
+        "on" => Some(true),
+        "off" => Some(false),
+        "reset" => None,
+        _ => {
+            bot.reply_message(&msg, "Usage: /flags [on|off|reset name]")
+                .await?;
+            return Ok(());
+        }
+    };
+    let Some(flag) = find(name.trim()) else {
+        bot.reply_message(
+            &msg,
+            format!("Unknown flag {}.", html::code_inline(name.trim())),
+        )
+        .parse_mode(ParseMode::Html)
+        .await?;
+        return Ok(());
+    };
+
+    flag.set(&env, msg.chat.id, value)?;
+    bot.reply_message(
+        &msg,
+        format!(
+            "{} is {} in this chat.",
+            html::code_inline(flag.name()),
+            state(flag.enabled(&env, msg.chat.id), value.is_none()),
+        ),
+    )
+    .parse_mode(ParseMode::Html)
+    .await?;
+    Ok(())
+}
+
+fn list_flags(env: &BotEnv, chat: ChatId) -> String {
+    let mut text = String::from("🚩 <b>Feature flags in this chat</b>\n");
+    for flag in models::FEATURE_FLAGS {
+        let value = flag.get(env, chat);
+        format_to!(
+            text,
+            "\n{}: {} — {}",
+            html::code_inline(flag.name()),
+            state(value.unwrap_or(flag.default()), value.is_none()),
+            html::escape(flag.description()),
+        );
+    }
+    text
+}
+
+fn state(enabled: bool, is_default: bool) -> &'static str {
+    match (enabled, is_default) {
+        (true, false) => "on",
+        (false, false) => "off",
+        (true, true) => "on (default)",
+        (false, true) => "off (default)",
+    }
+}
+
+fn find(name: &str) -> Option<&'static FeatureFlagDef> {
+    models::FEATURE_FLAGS.iter().copied().find(|f| f.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools as _;
+
+    use super::*;
+    use crate::testing::TestBot;
+
+    #[test]
+    fn test_flag_names_unique() {
+        assert!(models::FEATURE_FLAGS.iter().map(|f| f.name()).all_unique());
+    }
+
+    #[tokio::test]
+    async fn test_flags() {
+        let t = TestBot::new();
+        let (chat, other) = (ChatId(-1), ChatId(-2));
+        let flag = &models::moderation;
+        assert!(flag.enabled(&t.env, chat));
+
+        flag.set(&t.env, chat, Some(false)).unwrap();
+        assert!(!flag.enabled(&t.env, chat));
+        assert!(flag.enabled(&t.env, other));
+        assert!(list_flags(&t.env, chat).contains("moderation</code>: off —"));
+
+        flag.set(&t.env, chat, None).unwrap();
+        assert_eq!(flag.get(&t.env, chat), None);
+        assert!(flag.enabled(&t.env, chat));
+    }
+}
@@ -0,0 +1,390 @@
+//! RSS/Atom feed watcher.
+//!
+//! Admins register feeds with `/feed add`, each with a target topic and
+//! optional keyword filters.  The feeds are polled every
+//! [`telegram.feeds.interval_minutes`], and new entries are posted to the
+//! topics.  Entries are deduplicated by their GUIDs.
+//!
+//! **Scope**: `/feed` command, available to admins.
+//!
+//! [`telegram.feeds.interval_minutes`]: crate::config::Feeds::interval_minutes
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{ParseMode, ThreadId};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::{DbChatId, DbThreadId, DbUserId};
+use crate::utils::{
+    format_to, html, parse_tg_thread_link, write_message_link, BotExt as _,
+    ThreadIdPair,
+};
+use crate::{models, schema};
+
+/// Maximum number of entries posted per feed per check.
+const MAX_POSTS: usize = 10;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "manage feeds: <code>/feed list</code>, \
+                             <code>/feed remove &lt;id&gt;</code>, or \
+                             <code>/feed add &lt;url&gt; &lt;topic link&gt; \
+                             [include=a,b] [exclude=c,d]</code>.")]
+    #[custom(admin = true)]
+    Feed(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_feed)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct AddArgs<'a> {
+    url: &'a str,
+    topic: ThreadIdPair,
+    include: String,
+    exclude: String,
+}
+
+fn parse_add(args: &str) -> Option<AddArgs<'_>> {
+    let mut words = args.split_whitespace();
+    let url = words.next().filter(|u| u.starts_with("http"))?;
+    let topic = parse_tg_thread_link(words.next()?)?;
+    let mut include = String::new();
+    let mut exclude = String::new();
+    for word in words {
+        let (key, value) = word.split_once('=')?;
+        let keywords = value
+            .split(',')
+            .map(|k| k.trim().to_lowercase())
+            .filter(|k| !k.is_empty())
+            .join(",");
+        match key {
+            "include" => include = keywords,
+            "exclude" => exclude = keywords,
+            _ => return None,
+        }
+    }
+    Some(AddArgs { url, topic, include, exclude })
+}
+
+/// Check the entry text against comma-separated keyword filters.
+fn matches_filters(include: &str, exclude: &str, text: &str) -> bool {
+    let text = text.to_lowercase();
+    let keywords = |s: &str| {
+        s.split(',').filter(|k| !k.is_empty()).map(str::to_string).collect_vec()
+    };
+    let include = keywords(include);
+    (include.is_empty() || include.iter().any(|k| text.contains(k.as_str())))
+        && !keywords(exclude).iter().any(|k| text.contains(k.as_str()))
+}
+
+async fn cmd_feed(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Feed(args): Commands,
+) -> Result<()> {
+    let args = args.trim();
+    let (subcommand, rest) = args.split_once(' ').unwrap_or((args, ""));
+    match subcommand {
+        "" | "list" => cmd_list(&bot, &env, &msg).await,
+        "add" => cmd_add(&bot, &env, &msg, rest).await,
+        "remove" => cmd_remove(&bot, &env, &msg, rest).await,
+        _ => {
+            bot.reply_message(&msg, "Usage: /feed list|add|remove").await?;
+            Ok(())
+        }
+    }
+}
+
+async fn cmd_add(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    args: &str,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(args) = parse_add(args) else {
+        bot.reply_message(
+            msg,
+            "Usage: /feed add <url> <topic link> [include=a,b] [exclude=c,d]",
+        )
+        .await?;
+        return Ok(());
+    };
+    let feed = match fetch(env, args.url).await {
+        Ok(feed) => feed,
+        Err(e) => {
+            bot.reply_message(msg, format!("Failed to fetch the feed: {e}"))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Existing entries are considered seen, to avoid flooding the topic.
+    let now = chrono::Utc::now().naive_utc();
+    let rowid = env.transaction(|conn| {
+        diesel::insert_into(schema::feeds::table)
+            .values((
+                schema::feeds::url.eq(args.url),
+                schema::feeds::chat_id.eq(DbChatId::from(args.topic.chat)),
+                schema::feeds::thread_id
+                    .eq(DbThreadId::from(args.topic.thread)),
+                schema::feeds::include.eq(&args.include),
+                schema::feeds::exclude.eq(&args.exclude),
+                schema::feeds::added_by.eq(DbUserId::from(from.id)),
+                schema::feeds::created_at.eq(now),
+            ))
+            .execute(conn)?;
+        let rowid = schema::feeds::table
+            .select(schema::feeds::rowid)
+            .order(schema::feeds::rowid.desc())
+            .first::<i32>(conn)?;
+        diesel::insert_or_ignore_into(schema::feed_entries::table)
+            .values(
+                feed.entries
+                    .iter()
+                    .map(|entry| {
+                        (
+                            schema::feed_entries::feed_id.eq(rowid),
+                            schema::feed_entries::guid.eq(&entry.id),
+                            schema::feed_entries::seen_at.eq(now),
+                        )
+                    })
+                    .collect_vec(),
+            )
+            .execute(conn)?;
+        Ok(rowid)
+    })?;
+
+    let title = feed.title.map(|t| t.content).unwrap_or_default();
+    bot.reply_message(
+        msg,
+        format!(
+            "Added feed #{rowid} {title:?}, skipped {} existing entries.",
+            feed.entries.len()
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn cmd_list(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<()> {
+    let feeds: Vec<models::Feed> = schema::feeds::table
+        .order(schema::feeds::rowid)
+        .select(models::Feed::as_select())
+        .load(&mut *env.conn())?;
+    if feeds.is_empty() {
+        bot.reply_message(msg, "No feeds.").await?;
+        return Ok(());
+    }
+
+    let mut text = String::from("<b>Feeds</b>\n");
+    for feed in &feeds {
+        format_to!(text, "\n#{} {} → ", feed.rowid, html::escape(&feed.url));
+        write_message_link(
+            &mut text,
+            feed.chat_id,
+            ThreadId::from(feed.thread_id).0,
+        );
+        text.push_str("topic</a>");
+        if !feed.include.is_empty() {
+            format_to!(text, " include={}", html::escape(&feed.include));
+        }
+        if !feed.exclude.is_empty() {
+            format_to!(text, " exclude={}", html::escape(&feed.exclude));
+        }
+    }
+    bot.reply_message(msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+async fn cmd_remove(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    args: &str,
+) -> Result<()> {
+    let Ok(rowid) = args.trim().trim_start_matches('#').parse::<i32>() else {
+        bot.reply_message(msg, "Usage: /feed remove <id>").await?;
+        return Ok(());
+    };
+    let deleted = env.transaction(|conn| {
+        diesel::delete(schema::feed_entries::table)
+            .filter(schema::feed_entries::feed_id.eq(rowid))
+            .execute(conn)?;
+        diesel::delete(schema::feeds::table)
+            .filter(schema::feeds::rowid.eq(rowid))
+            .execute(conn)
+    })?;
+    let text = if deleted == 0 {
+        format!("No feed #{rowid}.")
+    } else {
+        format!("Removed feed #{rowid}.")
+    };
+    bot.reply_message(msg, text).await?;
+    Ok(())
+}
+
+async fn fetch(env: &BotEnv, url: &str) -> Result<feed_rs::model::Feed> {
+    let body = env
+        .reqwest_client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    feed_rs::parser::parse(&body[..]).context("Failed to parse the feed")
+}
+
+/// Poll the feeds and post new entries.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.feeds else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(conf.interval_minutes * 60)) => {}
+        }
+
+        let feeds = schema::feeds::table
+            .order(schema::feeds::rowid)
+            .select(models::Feed::as_select())
+            .load(&mut *env.conn());
+        let feeds: Vec<models::Feed> = match feeds {
+            Ok(feeds) => feeds,
+            Err(e) => {
+                log::error!("feeds: failed to load feeds: {e}");
+                continue;
+            }
+        };
+        let mut ok = true;
+        for feed in &feeds {
+            if let Err(e) = check_feed(&env, &bot, feed).await {
+                log::warn!("feeds: failed to check {}: {e}", feed.url);
+                ok = false;
+            }
+        }
+        crate::metrics::update_service(&env.health, "feeds", ok);
+    }
+}
+
+async fn check_feed(
+    env: &BotEnv,
+    bot: &Bot,
+    feed: &models::Feed,
+) -> Result<()> {
+    let parsed = fetch(env, &feed.url).await?;
+    let seen: HashSet<String> = schema::feed_entries::table
+        .filter(schema::feed_entries::feed_id.eq(feed.rowid))
+        .filter(
+            schema::feed_entries::guid
+                .eq_any(parsed.entries.iter().map(|e| &e.id).collect_vec()),
+        )
+        .select(schema::feed_entries::guid)
+        .load::<String>(&mut *env.conn())?
+        .into_iter()
+        .collect();
+    let feed_title = parsed.title.map(|t| t.content).unwrap_or_default();
+
+    // Feeds usually list the newest entries first.
+    let new_entries =
+        parsed.entries.iter().filter(|e| !seen.contains(&e.id)).collect_vec();
+    for entry in new_entries.into_iter().rev().take(MAX_POSTS) {
+        diesel::insert_or_ignore_into(schema::feed_entries::table)
+            .values((
+                schema::feed_entries::feed_id.eq(feed.rowid),
+                schema::feed_entries::guid.eq(&entry.id),
+                schema::feed_entries::seen_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(&mut *env.conn())?;
+
+        let title = entry
+            .title
+            .as_ref()
+            .map(|t| t.content.as_str())
+            .unwrap_or_default();
+        let summary = entry
+            .summary
+            .as_ref()
+            .map(|t| t.content.as_str())
+            .unwrap_or_default();
+        if !matches_filters(
+            &feed.include,
+            &feed.exclude,
+            &format!("{title}\n{summary}"),
+        ) {
+            continue;
+        }
+
+        let mut text = format!("📰 <b>{}</b>\n", html::escape(&feed_title));
+        match entry.links.first() {
+            Some(link) => text.push_str(&html::link(&link.href, title)),
+            None => text.push_str(&html::escape(title)),
+        }
+        bot.send_message(ChatId::from(feed.chat_id), text)
+            .message_thread_id(ThreadId::from(feed.thread_id))
+            .parse_mode(ParseMode::Html)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageId;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_add() {
+        assert_eq!(
+            parse_add(
+                "https://example.com/feed.xml https://t.me/c/123/45 \
+                 include=Rust,,Nix exclude=ads"
+            ),
+            Some(AddArgs {
+                url: "https://example.com/feed.xml",
+                topic: ThreadIdPair {
+                    chat: ChatId(-1_000_000_000_123),
+                    thread: ThreadId(MessageId(45)),
+                },
+                include: "rust,nix".to_string(),
+                exclude: "ads".to_string(),
+            })
+        );
+        assert_eq!(parse_add("https://example.com/feed.xml"), None);
+        assert_eq!(
+            parse_add("https://example.com https://t.me/c/123/45 foo=bar"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_matches_filters() {
+        assert!(matches_filters("", "", "anything"));
+        assert!(matches_filters("rust,nix", "", "New Rust release"));
+        assert!(!matches_filters("rust,nix", "", "New Go release"));
+        assert!(!matches_filters("", "ads", "Sponsored: ADS"));
+        assert!(!matches_filters("rust", "ads", "Rust ads"));
+    }
+}
@@ -0,0 +1,252 @@
+//! Interest tags followed by residents.  Residents are notified in private
+//! messages when archived pinned messages or new needs match their tags.
+//!
+//! **Scope**: `/follow` and `/unfollow` commands, available to residents.
+//! Other modules call [`notify`] for new content.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::DbUserId;
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+/// Maximum number of tags a user can follow.
+const MAX_TAGS: usize = 50;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "follow an interest tag, or list followed tags: \
+                             <code>/follow [tag]</code>.  Use \
+                             <code>/follow mute [hours]</code> and \
+                             <code>/follow unmute</code> to pause \
+                             notifications.")]
+    #[custom(resident = true)]
+    Follow(String),
+    #[command(description = "stop following an interest tag.")]
+    #[custom(resident = true)]
+    Unfollow(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(handle_command)
+}
+
+async fn handle_command(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    command: Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let user_id = DbUserId::from(from.id);
+    let text = match command {
+        Commands::Follow(args) => {
+            match args.split_whitespace().collect_vec()[..] {
+                [] => list_tags(&env, user_id)?,
+                ["mute"] => mute(&env, user_id, None)?,
+                ["mute", hours] => match hours.parse::<u32>() {
+                    Ok(hours) => mute(&env, user_id, Some(hours))?,
+                    Err(_) => "Usage: /follow mute [hours]".to_string(),
+                },
+                ["unmute"] => {
+                    diesel::delete(schema::follow_mutes::table)
+                        .filter(schema::follow_mutes::user_id.eq(user_id))
+                        .execute(&mut *env.conn())?;
+                    "Notifications are unmuted.".to_string()
+                }
+                [tag] => follow(&env, user_id, tag)?,
+                _ => "Usage: /follow <tag>".to_string(),
+            }
+        }
+        Commands::Unfollow(tag) => {
+            let tag = normalize(&tag);
+            let deleted = diesel::delete(schema::follows::table)
+                .filter(schema::follows::user_id.eq(user_id))
+                .filter(schema::follows::tag.eq(&tag))
+                .execute(&mut *env.conn())?;
+            if deleted == 0 {
+                format!("You are not following #{tag}.")
+            } else {
+                format!("You no longer follow #{tag}.")
+            }
+        }
+    };
+    bot.reply_message(&msg, text).await?;
+    Ok(())
+}
+
+fn list_tags(env: &BotEnv, user_id: DbUserId) -> Result<String> {
+    let tags: Vec<String> = schema::follows::table
+        .filter(schema::follows::user_id.eq(user_id))
+        .select(schema::follows::tag)
+        .order(schema::follows::tag.asc())
+        .load(&mut *env.conn())?;
+    if tags.is_empty() {
+        return Ok("You don't follow any tags. Usage: /follow <tag>".into());
+    }
+    Ok(format!(
+        "You follow: {}",
+        tags.iter().map(|t| format!("#{t}")).join(" ")
+    ))
+}
+
+fn follow(env: &BotEnv, user_id: DbUserId, tag: &str) -> Result<String> {
+    let tag = normalize(tag);
+    if tag.is_empty() {
+        return Ok("Tags may contain only letters and digits.".into());
+    }
+    let result = env.transaction(|conn| {
+        let count: i64 = schema::follows::table
+            .filter(schema::follows::user_id.eq(user_id))
+            .count()
+            .get_result(conn)?;
+        if usize::try_from(count).unwrap_or(usize::MAX) >= MAX_TAGS {
+            return Ok(Err(()));
+        }
+        diesel::insert_or_ignore_into(schema::follows::table)
+            .values((
+                schema::follows::user_id.eq(user_id),
+                schema::follows::tag.eq(&tag),
+            ))
+            .execute(conn)?;
+        Ok(Ok(()))
+    })?;
+    Ok(match result {
+        Ok(()) => format!("You now follow #{tag}."),
+        Err(()) => format!("You can't follow more than {MAX_TAGS} tags."),
+    })
+}
+
+fn mute(env: &BotEnv, user_id: DbUserId, hours: Option<u32>) -> Result<String> {
+    let until = hours.map(|h| {
+        chrono::Utc::now().naive_utc() + chrono::Duration::hours(h.into())
+    });
+    diesel::replace_into(schema::follow_mutes::table)
+        .values((
+            schema::follow_mutes::user_id.eq(user_id),
+            schema::follow_mutes::until.eq(until),
+        ))
+        .execute(&mut *env.conn())?;
+    Ok(match hours {
+        Some(hours) => format!("Notifications are muted for {hours} hours."),
+        None => "Notifications are muted until /follow unmute.".to_string(),
+    })
+}
+
+/// Lowercase a tag and strip everything except letters and digits.
+fn normalize(tag: &str) -> String {
+    tag.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Check whether `text` mentions `tag`, either as a single word or as two
+/// adjacent words, e.g. "3D printing" matches `3dprinting`.
+fn matches(tag: &str, text: &str) -> bool {
+    let words = text.split_whitespace().map(normalize).collect_vec();
+    words.iter().any(|w| w == tag)
+        || words.iter().tuple_windows().any(|(a, b)| {
+            tag.len() == a.len() + b.len()
+                && tag.starts_with(a.as_str())
+                && tag.ends_with(b.as_str())
+        })
+}
+
+/// Notify followers whose tags match `text`.  `kind` describes the source,
+/// e.g. "New need", and `link` points to the original message.
+pub async fn notify(
+    bot: &Bot,
+    env: &BotEnv,
+    kind: &str,
+    text: &str,
+    link: Option<reqwest::Url>,
+    author: Option<UserId>,
+) {
+    let follows = match load_follows(env) {
+        Ok(follows) => follows,
+        Err(e) => {
+            log::error!("follows: failed to load follows: {e}");
+            return;
+        }
+    };
+
+    let mut notified = HashSet::new();
+    for (user_id, tag) in follows {
+        let user = UserId::from(user_id);
+        if Some(user) == author
+            || notified.contains(&user)
+            || !matches(&tag, text)
+        {
+            continue;
+        }
+        notified.insert(user);
+
+        let mut message =
+            format!("<b>{}</b> matching #{tag}:\n\n", html::escape(kind));
+        format_to!(message, "{}", html::escape(text));
+        if let Some(link) = &link {
+            format_to!(
+                message,
+                "\n\n{}",
+                html::link(link.as_str(), "Open message")
+            );
+        }
+        bot.send_message(user, message)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await
+            .log_error("follows: notify");
+    }
+}
+
+/// Load tags followed by current residents who have not muted or disabled
+/// notifications.
+fn load_follows(env: &BotEnv) -> QueryResult<Vec<(DbUserId, String)>> {
+    let mut conn = env.conn();
+    let now = chrono::Utc::now().naive_utc();
+    let mut muted: Vec<DbUserId> = schema::follow_mutes::table
+        .filter(
+            schema::follow_mutes::until
+                .is_null()
+                .or(schema::follow_mutes::until.gt(now)),
+        )
+        .select(schema::follow_mutes::user_id)
+        .load(&mut *conn)?;
+    muted.extend(models::follow_notifications.users_with(&mut *conn, &false)?);
+    schema::follows::table
+        .inner_join(
+            schema::residents::table.on(schema::residents::tg_id
+                .eq(schema::follows::user_id)
+                .and(schema::residents::end_date.is_null())),
+        )
+        .filter(schema::follows::user_id.ne_all(muted))
+        .select((schema::follows::user_id, schema::follows::tag))
+        .load(&mut *conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("3dprinting", "Who is into 3D printing?"));
+        assert!(matches("3dprinting", "#3dprinting meetup"));
+        assert!(matches("rust", "Rust, anyone?"));
+        assert!(!matches("rust", "trust me"));
+        assert!(!matches("3dprinting", "3D models for printing"));
+        assert_eq!(normalize("#3D-Printing"), "3dprinting");
+    }
+}
@@ -15,13 +15,12 @@ use teloxide::prelude::*;
 use teloxide::types::{
     InlineKeyboardButton, MessageEntity, MessageKind, ReplyMarkup, ThreadId,
 };
-use teloxide::utils::html;
 use teloxide::{ApiError, RequestError};
 
 use crate::common::{BotEnv, TopicEmojis};
 use crate::db::{DbChatId, DbThreadId};
 use crate::models;
-use crate::utils::{format_to, ChatIdExt as _, MessageExt as _};
+use crate::utils::{format_to, html, ChatIdExt as _, MessageExt as _};
 
 /// State contains a set of newly created topics.
 #[derive(Clone, Debug, Default)]
@@ -121,6 +120,18 @@ async fn forward_message(
         }
     }
 
+    if let Some(text) = msg.text().or_else(|| msg.caption()) {
+        crate::modules::follows::notify(
+            bot,
+            env,
+            "Pinned message",
+            text,
+            Url::parse(&link_url).ok(),
+            msg.from.as_ref().map(|u| u.id),
+        )
+        .await;
+    }
+
     Ok(())
 }
 
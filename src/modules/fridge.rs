@@ -0,0 +1,387 @@
+//! Fridge food expiry tracker.
+//!
+//! Residents register their food with `/fridge add <item> <expiry>`.  Every
+//! day, items that are expired or expiring soon are posted to the
+//! [`telegram.fridge.thread`], and owners can claim or discard them with
+//! buttons.  When [`telegram.fridge.vision`] is enabled, replying
+//! `/fridge add` to a photo of a label reads the item and date with `OpenAI`.
+//!
+//! **Scope**: `/fridge` command, available to residents.
+//!
+//! [`telegram.fridge.thread`]: crate::config::Fridge::thread
+//! [`telegram.fridge.vision`]: crate::config::Fridge::vision
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{Datelike as _, NaiveDate, Timelike as _};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use serde::Deserialize;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, PhotoSize,
+};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_user, read_photo, BotCommandsExt, BotEnv,
+    UpdateHandler,
+};
+use crate::config::Fridge;
+use crate::db::DbUserId;
+use crate::utils::{
+    format_to, html, remove_button_row, BotExt as _, ResultExt as _,
+};
+use crate::{models, schema};
+
+const STATUS_ACTIVE: &str = "active";
+const STATUS_CLAIMED: &str = "claimed";
+const STATUS_DISCARDED: &str = "discarded";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "track food in the fridge: <code>/fridge add \
+                             milk 2026-10-20</code>, or reply <code>/fridge \
+                             add</code> to a photo of the label.  Without \
+                             arguments, lists items in the fridge.")]
+    #[custom(resident = true)]
+    Fridge(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_fridge)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+/// Parse an expiry date: `YYYY-MM-DD`, `DD.MM`, `today`, `tomorrow`, or a
+/// number of days like `3d`.
+fn parse_expiry(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let text = text.trim().to_lowercase();
+    match text.as_str() {
+        "today" => return Some(today),
+        "tomorrow" => return today.succ_opt(),
+        _ => (),
+    }
+    if let Some(days) = text.trim_start_matches('+').strip_suffix('d') {
+        let days = days.parse::<u32>().ok()?;
+        return today.checked_add_days(chrono::Days::new(days.into()));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(&text, "%Y-%m-%d") {
+        return Some(date);
+    }
+    let (day, month) = text.split_once('.')?;
+    let date = NaiveDate::from_ymd_opt(
+        today.year(),
+        month.parse().ok()?,
+        day.parse().ok()?,
+    )?;
+    // `DD.MM` in the past most likely refers to the next year.
+    if date < today {
+        date.with_year(today.year() + 1)
+    } else {
+        Some(date)
+    }
+}
+
+async fn cmd_fridge(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Fridge(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) = &env.config.telegram.fridge else {
+        bot.reply_message(&msg, "Fridge tracking is not configured.").await?;
+        return Ok(());
+    };
+
+    let args = args.trim();
+    if args.is_empty() || args == "list" {
+        return list_items(&bot, &env, &msg).await;
+    }
+    let Some(args) = args.strip_prefix("add").map(str::trim) else {
+        bot.reply_message(&msg, "Usage: /fridge [add <item> <expiry>]").await?;
+        return Ok(());
+    };
+
+    let today = chrono::Utc::now().date_naive();
+    let parsed = if args.is_empty() {
+        let photo = msg
+            .reply_to_message()
+            .and_then(|m| m.photo())
+            .and_then(|p| p.last());
+        match photo {
+            Some(photo)
+                if conf.vision && !env.config.services.openai.disable =>
+            {
+                read_label(&bot, &env, photo).await?
+            }
+            _ => None,
+        }
+    } else {
+        args.rsplit_once(char::is_whitespace).and_then(|(item, expiry)| {
+            Some((item.trim().to_string(), parse_expiry(expiry, today)?))
+        })
+    };
+    let Some((item, expires_on)) = parsed else {
+        bot.reply_message(
+            &msg,
+            "Usage: /fridge add <item> <YYYY-MM-DD | DD.MM | 3d>",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    diesel::insert_into(schema::fridge_items::table)
+        .values((
+            schema::fridge_items::owner.eq(DbUserId::from(from.id)),
+            schema::fridge_items::name.eq(&item),
+            schema::fridge_items::expires_on.eq(expires_on),
+            schema::fridge_items::status.eq(STATUS_ACTIVE),
+            schema::fridge_items::added_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(&mut *env.conn())?;
+
+    bot.reply_message(
+        &msg,
+        format!("Added {} (expires {expires_on}).", html::escape(&item)),
+    )
+    .parse_mode(ParseMode::Html)
+    .await?;
+    Ok(())
+}
+
+async fn list_items(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<()> {
+    let items = load_items(env, None)?;
+    let mut text = String::from("🧊 <b>In the fridge</b>\n\n");
+    if items.is_empty() {
+        text.push_str("Nothing is tracked.");
+    }
+    for (item, user) in &items {
+        format_to!(
+            text,
+            "{} {}, ",
+            item.expires_on.format("%Y-%m-%d"),
+            html::escape(&item.name),
+        );
+        format_user(&mut text, item.owner, user.as_ref(), false);
+        text.push('\n');
+    }
+    bot.reply_message(msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+/// Load active items, optionally only those expiring on or before `until`.
+fn load_items(
+    env: &BotEnv,
+    until: Option<NaiveDate>,
+) -> Result<Vec<(models::FridgeItem, Option<models::TgUser>)>> {
+    let mut query = schema::fridge_items::table
+        .left_join(
+            schema::tg_users::table
+                .on(schema::tg_users::id.eq(schema::fridge_items::owner)),
+        )
+        .filter(schema::fridge_items::status.eq(STATUS_ACTIVE))
+        .order(schema::fridge_items::expires_on)
+        .select((
+            models::FridgeItem::as_select(),
+            schema::tg_users::all_columns.nullable(),
+        ))
+        .into_boxed();
+    if let Some(until) = until {
+        query = query.filter(schema::fridge_items::expires_on.le(until));
+    }
+    Ok(query.load(&mut *env.conn())?)
+}
+
+/// Read the item name and expiry date from a photo of a label.
+async fn read_label(
+    bot: &Bot,
+    env: &BotEnv,
+    photo: &PhotoSize,
+) -> Result<Option<(String, NaiveDate)>> {
+    #[derive(Deserialize)]
+    struct Label {
+        item: String,
+        expiry: NaiveDate,
+    }
+
+    let content =
+        read_photo(bot, env, photo, VISION_PROMPT.trim(), 100).await?;
+    Ok(serde_json::from_str::<Option<Label>>(&content)
+        .ok()
+        .flatten()
+        .map(|label| (label.item, label.expiry)))
+}
+
+const VISION_PROMPT: &str = r#"
+The image is a photo of a food label.
+Respond with a JSON object containing the product name and its expiry date, e.g. `{"item":"milk","expiry":"2026-10-20"}`.
+If there is no readable expiry date, respond with `null`.
+"#;
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.fridge else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(10 * 60)) => {}
+        }
+
+        let now = chrono::Utc::now();
+        if now.hour() < conf.sweep_hour {
+            continue;
+        }
+        let today = now.date_naive().to_string();
+        let last = models::fridge_last_sweep.get(&mut env.conn());
+        if last.as_ref().is_ok_and(|d| d.as_ref() == Some(&today)) {
+            continue;
+        }
+        if let Err(e) = sweep(&env, &bot, conf, now.date_naive()).await {
+            log::error!("fridge: failed to sweep: {e}");
+            continue;
+        }
+        models::fridge_last_sweep
+            .set(&mut env.conn(), &today)
+            .log_error("fridge: set last sweep");
+    }
+}
+
+async fn sweep(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Fridge,
+    today: NaiveDate,
+) -> Result<()> {
+    let until = today
+        .checked_add_days(chrono::Days::new(conf.warn_days.into()))
+        .unwrap_or(today);
+    let items = load_items(env, Some(until))?;
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    let mut text = String::from("🧊 <b>Fridge check</b>\n");
+    let mut buttons = Vec::new();
+    for (expired, group) in
+        &items.iter().group_by(|(item, _)| item.expires_on < today)
+    {
+        text.push_str(if expired {
+            "\n<b>Expired</b>\n"
+        } else {
+            "\n<b>Expiring soon</b>\n"
+        });
+        for (item, user) in group {
+            format_to!(
+                text,
+                "{} {}, ",
+                item.expires_on.format("%Y-%m-%d"),
+                html::escape(&item.name),
+            );
+            format_user(&mut text, item.owner, user.as_ref(), true);
+            text.push('\n');
+            buttons.push(vec![
+                InlineKeyboardButton::callback(
+                    format!("✅ Claim {}", item.name),
+                    format!("fr:claim:{}", item.rowid),
+                ),
+                InlineKeyboardButton::callback(
+                    "🗑 Discard",
+                    format!("fr:discard:{}", item.rowid),
+                ),
+            ]);
+        }
+    }
+
+    bot.send_message(conf.thread.chat, text)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<(&'static str, i32)> {
+    let data = callback.data.as_ref()?.strip_prefix("fr:")?;
+    let (action, rowid) = data.split_once(':')?;
+    let status = match action {
+        "claim" => STATUS_CLAIMED,
+        "discard" => STATUS_DISCARDED,
+        _ => return None,
+    };
+    Some((status, rowid.parse().ok()?))
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    (status, rowid): (&'static str, i32),
+) -> Result<()> {
+    let is_admin = env.config.telegram.admins.contains(&callback.from.id);
+    let result = env.transaction(|conn| {
+        let item = schema::fridge_items::table
+            .filter(schema::fridge_items::rowid.eq(rowid))
+            .select(models::FridgeItem::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(item) = item else {
+            return Ok(Err("This item no longer exists."));
+        };
+        if item.status != STATUS_ACTIVE {
+            return Ok(Err("This item is already handled."));
+        }
+        // Admins may discard abandoned food, but only owners claim it.
+        let allowed = UserId::from(item.owner) == callback.from.id
+            || (is_admin && status == STATUS_DISCARDED);
+        if !allowed {
+            return Ok(Err("Only the owner can do this."));
+        }
+        diesel::update(schema::fridge_items::table)
+            .filter(schema::fridge_items::rowid.eq(rowid))
+            .set(schema::fridge_items::status.eq(status))
+            .execute(conn)?;
+        Ok(Ok(if status == STATUS_CLAIMED { "Claimed." } else { "Discarded." }))
+    })?;
+
+    let text = match result {
+        Ok(text) | Err(text) => text,
+    };
+    bot.answer_callback_query(&callback.id).text(text).await?;
+    if result.is_ok() {
+        remove_button_row(&bot, &callback).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_expiry() {
+        let d = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let today = d(2026, 10, 16);
+        assert_eq!(parse_expiry("2026-10-20", today), Some(d(2026, 10, 20)));
+        assert_eq!(parse_expiry("20.10", today), Some(d(2026, 10, 20)));
+        assert_eq!(parse_expiry("05.01", today), Some(d(2027, 1, 5)));
+        assert_eq!(parse_expiry("Tomorrow", today), Some(d(2026, 10, 17)));
+        assert_eq!(parse_expiry("3d", today), Some(d(2026, 10, 19)));
+        assert_eq!(parse_expiry("+3d", today), Some(d(2026, 10, 19)));
+        assert_eq!(parse_expiry("soon", today), None);
+        assert_eq!(parse_expiry("31.02", today), None);
+    }
+}
@@ -0,0 +1,616 @@
+//! Relay between visitors writing to the bot and residents.
+//!
+//! The first private message of a visitor opens a topic for them in the
+//! [`telegram.frontdesk.forum`], and their messages are forwarded there.
+//! Messages of residents in that topic are copied back to the visitor on
+//! behalf of the bot, except ones starting with `//`, which are internal
+//! notes.  Topics of visitors are kept in the `frontdesk_threads` table.
+//!
+//! Messages of a visitor waiting for a reply make up an inquiry, recorded in
+//! the `frontdesk_inquiries` table along with the time of the first reply.
+//! If [`telegram.frontdesk.sla`] is set, residents on duty are pinged in the
+//! topic when an inquiry waits too long, and a weekly report of inquiries
+//! and response times is posted.
+//!
+//! Telegram Business connections aren't supported by teloxide yet, so
+//! visitors write to the bot account rather than to a personal account of
+//! the space.
+//!
+//! **Scope**: private messages of non-residents that aren't handled by other
+//! modules; topics of visitors in the frontdesk forum.  The module is
+//! disabled if the `telegram.frontdesk` section is absent.
+//!
+//! [`telegram.frontdesk.forum`]: crate::config::Frontdesk::forum
+//! [`telegram.frontdesk.sla`]: crate::config::Frontdesk::sla
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{Datelike as _, Timelike as _};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use teloxide::prelude::*;
+use teloxide::types::{MessageKind, ParseMode, ThreadId, User};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{BotEnv, UpdateHandler};
+use crate::config::FrontdeskSla;
+use crate::db::{DbChatId, DbThreadId, DbUserId};
+use crate::modules::roles;
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+/// Messages of residents in a visitor's topic starting with this prefix are
+/// not sent to the visitor.
+const NOTE_PREFIX: &str = "//";
+
+const TOPIC_ICON_COLOR: u32 = 0xFF_93_B2;
+
+/// Telegram limits topic names to 128 characters.
+const MAX_TOPIC_NAME_CHARS: usize = 128;
+
+pub fn message_handler() -> UpdateHandler {
+    Update::filter_message()
+        .branch(
+            dptree::filter_map(filter_visitor_messages)
+                .endpoint(relay_from_visitor),
+        )
+        .branch(
+            dptree::filter_map(filter_topic_messages)
+                .endpoint(relay_to_visitor),
+        )
+}
+
+#[derive(Clone)]
+struct Visitor(User);
+
+#[derive(Clone)]
+struct VisitorId(UserId);
+
+/// Service messages, e.g. a pinned message or a created topic, can't be
+/// forwarded or copied.
+const fn is_relayable(msg: &Message) -> bool {
+    matches!(msg.kind, MessageKind::Common(_))
+}
+
+fn filter_visitor_messages(env: Arc<BotEnv>, msg: Message) -> Option<Visitor> {
+    env.config.telegram.frontdesk.as_ref()?;
+    if !msg.chat.is_private() || !is_relayable(&msg) {
+        return None;
+    }
+    let from = msg.from.as_ref()?;
+    if from.is_bot || env.is_resident(from.id) {
+        return None;
+    }
+    Some(Visitor(from.clone()))
+}
+
+fn filter_topic_messages(env: Arc<BotEnv>, msg: Message) -> Option<VisitorId> {
+    let conf = env.config.telegram.frontdesk.as_ref()?;
+    if msg.chat.id != conf.forum || !is_relayable(&msg) {
+        return None;
+    }
+    if msg.from.as_ref()?.is_bot
+        || msg
+            .text()
+            .or_else(|| msg.caption())
+            .is_some_and(|text| text.starts_with(NOTE_PREFIX))
+    {
+        return None;
+    }
+    let visitor = schema::frontdesk_threads::table
+        .filter(
+            schema::frontdesk_threads::chat_id.eq(DbChatId::from(conf.forum)),
+        )
+        .filter(
+            schema::frontdesk_threads::thread_id
+                .eq(DbThreadId::from(msg.thread_id?)),
+        )
+        .select(schema::frontdesk_threads::visitor_id)
+        .get_result::<DbUserId>(&mut *env.conn())
+        .optional();
+    visitor.log_error("frontdesk: find visitor");
+    Some(VisitorId(visitor.ok()??.into()))
+}
+
+async fn relay_from_visitor(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Visitor(visitor): Visitor,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.frontdesk else { return Ok(()) };
+    let thread = find_thread(&env, conf.forum, visitor.id)?;
+    let is_new = thread.is_none();
+    let thread = match thread {
+        Some(thread) => thread,
+        None => open_thread(&bot, &env, conf.forum, &visitor).await?,
+    };
+    bot.forward_message(conf.forum, msg.chat.id, msg.id)
+        .message_thread_id(thread)
+        .await?;
+    open_inquiry(&env, visitor.id)?;
+    if is_new {
+        bot.reply_message(
+            &msg,
+            "Thanks!  Your message was passed to the residents, the reply \
+             will come here.",
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+fn find_thread(
+    env: &BotEnv,
+    forum: ChatId,
+    visitor: UserId,
+) -> Result<Option<ThreadId>> {
+    let thread = schema::frontdesk_threads::table
+        .filter(
+            schema::frontdesk_threads::visitor_id.eq(DbUserId::from(visitor)),
+        )
+        .select(models::FrontdeskThread::as_select())
+        .get_result(&mut *env.conn())
+        .optional()?;
+    // A thread in a forum that is no longer configured is replaced.
+    Ok(thread
+        .filter(|thread| ChatId::from(thread.chat_id) == forum)
+        .map(|thread| ThreadId::from(thread.thread_id)))
+}
+
+/// Create a topic for the visitor and introduce them there.
+async fn open_thread(
+    bot: &Bot,
+    env: &BotEnv,
+    forum: ChatId,
+    visitor: &User,
+) -> Result<ThreadId> {
+    let topic = bot
+        .create_forum_topic(forum, topic_name(visitor), TOPIC_ICON_COLOR, "")
+        .await?;
+    diesel::replace_into(schema::frontdesk_threads::table)
+        .values((
+            schema::frontdesk_threads::visitor_id
+                .eq(DbUserId::from(visitor.id)),
+            schema::frontdesk_threads::chat_id.eq(DbChatId::from(forum)),
+            schema::frontdesk_threads::thread_id
+                .eq(DbThreadId::from(topic.thread_id)),
+            schema::frontdesk_threads::created_at
+                .eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(&mut *env.conn())?;
+
+    let mut text = format!("Visitor: {}", html::user_mention(visitor));
+    if let Some(username) = &visitor.username {
+        format_to!(text, " (@{})", html::escape(username));
+    }
+    format_to!(
+        text,
+        "\nMessages here are sent to the visitor, except ones starting with \
+         <code>{NOTE_PREFIX}</code>.",
+    );
+    bot.send_message(forum, text)
+        .message_thread_id(topic.thread_id)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(topic.thread_id)
+}
+
+fn topic_name(visitor: &User) -> String {
+    let mut name = visitor.full_name();
+    if let Some(username) = &visitor.username {
+        format_to!(name, " (@{username})");
+    }
+    name.chars().take(MAX_TOPIC_NAME_CHARS).collect()
+}
+
+async fn relay_to_visitor(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    VisitorId(visitor): VisitorId,
+) -> Result<()> {
+    match bot.copy_message(visitor, msg.chat.id, msg.id).await {
+        Ok(_) => {
+            if let Some(from) = &msg.from {
+                answer_inquiry(&env, visitor, from.id)?;
+            }
+        }
+        // E.g. the visitor blocked the bot.
+        Err(e) => {
+            bot.reply_message(
+                &msg,
+                format!("Not delivered to the visitor: {e}"),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Start an inquiry, unless the visitor is already waiting for a reply.
+fn open_inquiry(env: &BotEnv, visitor: UserId) -> QueryResult<()> {
+    env.transaction(|conn| {
+        let waiting = schema::frontdesk_inquiries::table
+            .filter(
+                schema::frontdesk_inquiries::visitor_id
+                    .eq(DbUserId::from(visitor)),
+            )
+            .filter(schema::frontdesk_inquiries::answered_at.is_null())
+            .count()
+            .get_result::<i64>(conn)?;
+        if waiting == 0 {
+            diesel::insert_into(schema::frontdesk_inquiries::table)
+                .values((
+                    schema::frontdesk_inquiries::visitor_id
+                        .eq(DbUserId::from(visitor)),
+                    schema::frontdesk_inquiries::asked_at
+                        .eq(chrono::Utc::now().naive_utc()),
+                ))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Close the inquiry of the visitor with the first reply.
+fn answer_inquiry(
+    env: &BotEnv,
+    visitor: UserId,
+    resident: UserId,
+) -> QueryResult<()> {
+    diesel::update(schema::frontdesk_inquiries::table)
+        .filter(
+            schema::frontdesk_inquiries::visitor_id.eq(DbUserId::from(visitor)),
+        )
+        .filter(schema::frontdesk_inquiries::answered_at.is_null())
+        .set((
+            schema::frontdesk_inquiries::answered_at
+                .eq(chrono::Utc::now().naive_utc()),
+            schema::frontdesk_inquiries::answered_by
+                .eq(DbUserId::from(resident)),
+        ))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+/// Ping residents on duty about inquiries waiting too long, and post the
+/// weekly report.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.frontdesk else { return };
+    let Some(sla) = &conf.sla else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(60)) => {}
+        }
+
+        ping_overdue(&env, &bot, conf.forum, sla)
+            .await
+            .log_error("frontdesk: ping overdue inquiries");
+
+        let now = chrono::Utc::now();
+        if now.weekday() != sla.weekday || now.hour() < sla.hour {
+            continue;
+        }
+        let today = now.date_naive().to_string();
+        let last = models::frontdesk_last_report.get(&mut env.conn());
+        if last.as_ref().is_ok_and(|d| d.as_ref() == Some(&today)) {
+            continue;
+        }
+        if let Err(e) = post_report(&env, &bot, sla).await {
+            log::error!("frontdesk: failed to post report: {e}");
+            continue;
+        }
+        models::frontdesk_last_report
+            .set(&mut env.conn(), &today)
+            .log_error("frontdesk: set last report");
+    }
+}
+
+async fn ping_overdue(
+    env: &BotEnv,
+    bot: &Bot,
+    forum: ChatId,
+    sla: &FrontdeskSla,
+) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let overdue: Vec<models::FrontdeskInquiry> =
+        schema::frontdesk_inquiries::table
+            .filter(schema::frontdesk_inquiries::answered_at.is_null())
+            .filter(schema::frontdesk_inquiries::pinged_at.is_null())
+            .filter(
+                schema::frontdesk_inquiries::asked_at.le(
+                    now - chrono::Duration::minutes(sla.respond_minutes.into())
+                ),
+            )
+            .select(models::FrontdeskInquiry::as_select())
+            .load(&mut *env.conn())?;
+    if overdue.is_empty() {
+        return Ok(());
+    }
+
+    let mentions = duty_mentions(env, &sla.duty_role)?;
+    for inquiry in overdue {
+        // Mark first, so that a failure doesn't repeat the ping every minute.
+        diesel::update(schema::frontdesk_inquiries::table)
+            .filter(schema::frontdesk_inquiries::rowid.eq(inquiry.rowid))
+            .set(schema::frontdesk_inquiries::pinged_at.eq(now))
+            .execute(&mut *env.conn())?;
+        let Some(thread) = find_thread(env, forum, inquiry.visitor_id.into())?
+        else {
+            continue;
+        };
+        let mut text = format!(
+            "⏰ The visitor has been waiting for a reply for {}.",
+            format_minutes((now - inquiry.asked_at).num_minutes()),
+        );
+        if !mentions.is_empty() {
+            format_to!(text, "\n{mentions}");
+        }
+        bot.send_message(forum, text)
+            .message_thread_id(thread)
+            .parse_mode(ParseMode::Html)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Mentions of residents with the duty role.
+fn duty_mentions(env: &BotEnv, role: &str) -> Result<String> {
+    let users = roles::users_with_role(env, role)?;
+    let names: Vec<(DbUserId, String)> = schema::tg_users::table
+        .filter(
+            schema::tg_users::id
+                .eq_any(users.iter().map(|&u| DbUserId::from(u))),
+        )
+        .select((schema::tg_users::id, schema::tg_users::first_name))
+        .load(&mut *env.conn())?;
+    Ok(users
+        .iter()
+        .map(|&user| {
+            let name = names
+                .iter()
+                .find(|(id, _)| UserId::from(*id) == user)
+                .map_or("resident", |(_, name)| name.as_str());
+            html::mention(user, name)
+        })
+        .join(", "))
+}
+
+async fn post_report(
+    env: &BotEnv,
+    bot: &Bot,
+    sla: &FrontdeskSla,
+) -> Result<()> {
+    let since = chrono::Utc::now().naive_utc() - chrono::Duration::days(7);
+    let inquiries: Vec<models::FrontdeskInquiry> =
+        schema::frontdesk_inquiries::table
+            .filter(schema::frontdesk_inquiries::asked_at.ge(since))
+            .select(models::FrontdeskInquiry::as_select())
+            .load(&mut *env.conn())?;
+    bot.send_message(
+        sla.report.chat,
+        format_report(&inquiries, sla.respond_minutes),
+    )
+    .message_thread_id(sla.report.thread)
+    .parse_mode(ParseMode::Html)
+    .await?;
+    Ok(())
+}
+
+fn format_report(
+    inquiries: &[models::FrontdeskInquiry],
+    respond_minutes: u32,
+) -> String {
+    let response_minutes = inquiries
+        .iter()
+        .filter_map(|i| Some((i.answered_at? - i.asked_at).num_minutes()))
+        .sorted()
+        .collect_vec();
+    let within_sla = response_minutes
+        .iter()
+        .filter(|&&m| m <= i64::from(respond_minutes))
+        .count();
+
+    let mut text = format!(
+        "📊 <b>Frontdesk, last 7 days</b>\nInquiries: {}\n",
+        inquiries.len()
+    );
+    if inquiries.is_empty() {
+        return text;
+    }
+    format_to!(
+        text,
+        "Answered: {}, {within_sla} of them within {}\n",
+        response_minutes.len(),
+        format_minutes(respond_minutes.into()),
+    );
+    if let Some(&median) = response_minutes.get(response_minutes.len() / 2) {
+        format_to!(text, "Median response time: {}\n", format_minutes(median));
+    }
+    let unanswered = inquiries.len() - response_minutes.len();
+    if unanswered > 0 {
+        format_to!(text, "Waiting for a reply: {unanswered}\n");
+    }
+    text
+}
+
+/// Format a duration, e.g. `45m` or `2h 5m`.
+fn format_minutes(minutes: i64) -> String {
+    if minutes < 60 {
+        format!("{minutes}m")
+    } else if minutes % 60 == 0 {
+        format!("{}h", minutes / 60)
+    } else {
+        format!("{}h {}m", minutes / 60, minutes % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    /// Ignore messages that are not relayed.
+    fn handler() -> UpdateHandler {
+        message_handler().endpoint(|| async { Ok(()) })
+    }
+
+    fn inquiries(t: &TestBot) -> Vec<models::FrontdeskInquiry> {
+        schema::frontdesk_inquiries::table
+            .order(schema::frontdesk_inquiries::rowid)
+            .select(models::FrontdeskInquiry::as_select())
+            .load(&mut *t.env.conn())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_frontdesk() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let forum = t.env.config.telegram.frontdesk.as_ref().unwrap().forum;
+        let alice = testing::user_json(1, "Alice");
+        let visitor = testing::user_json(50, "Bob");
+
+        t.dispatch(
+            &handler(),
+            testing::message(50, None, &visitor, "Can I visit on Friday?"),
+        )
+        .await;
+        let created = t.telegram.calls("createForumTopic");
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0]["name"], "Bob");
+        assert_eq!(created[0]["chat_id"], forum.0);
+        let thread = t.telegram.results("createForumTopic")[0]
+            ["message_thread_id"]
+            .as_i64()
+            .unwrap();
+        let forwarded = t.telegram.calls("forwardMessage");
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded[0]["message_thread_id"], thread);
+        // The introduction in the topic and the reply to the visitor.
+        assert_eq!(t.telegram.calls("sendMessage").len(), 2);
+
+        // The topic is reused.
+        t.telegram.clear();
+        t.dispatch(
+            &handler(),
+            testing::message(50, None, &visitor, "At 18:00"),
+        )
+        .await;
+        assert!(t.telegram.calls("createForumTopic").is_empty());
+        assert_eq!(t.telegram.calls("forwardMessage").len(), 1);
+        assert!(t.telegram.calls("sendMessage").is_empty());
+        // Both messages are one inquiry.
+        assert_eq!(inquiries(&t).len(), 1);
+
+        // Residents don't get a topic.
+        t.telegram.clear();
+        t.dispatch(&handler(), testing::message(1, None, &alice, "hi")).await;
+        assert!(t.telegram.calls("forwardMessage").is_empty());
+
+        // Replies go back to the visitor, notes stay in the topic.
+        t.dispatch(
+            &handler(),
+            testing::message(forum.0, Some(thread), &alice, "Sure, welcome!"),
+        )
+        .await;
+        let copied = t.telegram.calls("copyMessage");
+        assert_eq!(copied.len(), 1);
+        assert_eq!(copied[0]["chat_id"], 50);
+        let inquiry = &inquiries(&t)[0];
+        assert!(inquiry.answered_at.is_some());
+        assert_eq!(inquiry.answered_by, Some(DbUserId::from(UserId(1))));
+        t.dispatch(
+            &handler(),
+            testing::message(forum.0, Some(thread), &alice, "// friend of Bo"),
+        )
+        .await;
+        assert_eq!(t.telegram.calls("copyMessage").len(), 1);
+
+        t.telegram
+            .fail("copyMessage", "Forbidden: bot was blocked by the user");
+        t.dispatch(
+            &handler(),
+            testing::message(forum.0, Some(thread), &alice, "Hello?"),
+        )
+        .await;
+        let sent = t.telegram.calls("sendMessage");
+        assert!(sent[0]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("Not delivered to the visitor"));
+    }
+
+    #[tokio::test]
+    async fn test_ping_overdue() {
+        let t = TestBot::new();
+        let conf = t.env.config.telegram.frontdesk.as_ref().unwrap();
+        let sla = conf.sla.as_ref().unwrap();
+        let visitor = testing::user_json(50, "Bob");
+        t.dispatch(&handler(), testing::message(50, None, &visitor, "Hi"))
+            .await;
+        t.telegram.clear();
+
+        ping_overdue(&t.env, &t.bot, conf.forum, sla).await.unwrap();
+        assert!(t.telegram.calls("sendMessage").is_empty());
+
+        diesel::update(schema::frontdesk_inquiries::table)
+            .set(
+                schema::frontdesk_inquiries::asked_at
+                    .eq(chrono::Utc::now().naive_utc()
+                        - chrono::Duration::minutes(90)),
+            )
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        ping_overdue(&t.env, &t.bot, conf.forum, sla).await.unwrap();
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0]["text"],
+            "⏰ The visitor has been waiting for a reply for 1h 30m."
+        );
+        // Pinged once.
+        ping_overdue(&t.env, &t.bot, conf.forum, sla).await.unwrap();
+        assert_eq!(t.telegram.calls("sendMessage").len(), 1);
+    }
+
+    #[test]
+    fn test_format_report() {
+        let at = |minutes| {
+            chrono::NaiveDateTime::default()
+                + chrono::Duration::minutes(minutes)
+        };
+        let inquiry = |answered_after: Option<i64>| models::FrontdeskInquiry {
+            rowid: 0,
+            visitor_id: DbUserId::from(UserId(50)),
+            asked_at: at(0),
+            answered_at: answered_after.map(at),
+            answered_by: None,
+            pinged_at: None,
+        };
+        assert_eq!(
+            format_report(&[], 60),
+            "📊 <b>Frontdesk, last 7 days</b>\nInquiries: 0\n"
+        );
+        assert_eq!(
+            format_report(
+                &[inquiry(Some(10)), inquiry(Some(125)), inquiry(None)],
+                60
+            ),
+            "📊 <b>Frontdesk, last 7 days</b>\nInquiries: 3\n\
+             Answered: 2, 1 of them within 1h\n\
+             Median response time: 2h 5m\n\
+             Waiting for a reply: 1\n"
+        );
+        assert_eq!(format_minutes(45), "45m");
+        assert_eq!(format_minutes(120), "2h");
+    }
+}
@@ -0,0 +1,271 @@
+//! Guest WiFi vouchers.
+//!
+//! `/wifi guest [hours]` creates a hotspot user on the `MikroTik` router with
+//! a random login and password, limited to the given number of hours, and
+//! replies with the credentials.  Expired users are removed from the router
+//! by a background task.  A resident can issue at most
+//! [`telegram.guest_wifi.weekly_quota`] vouchers in seven days.
+//!
+//! **Scope**: `/wifi` command in private chats, available to residents;
+//! background task.
+//!
+//! [`telegram.guest_wifi.weekly_quota`]: crate::config::GuestWifi::weekly_quota
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use rand_core::{OsRng, RngCore as _};
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::config::GuestWifi;
+use crate::db::DbUserId;
+use crate::modules::timezones;
+use crate::utils::mikrotik::{self, HotspotUser};
+use crate::utils::{html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+/// How often to check for expired vouchers.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Characters used in generated credentials, without look-alikes.
+const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "issue a guest WiFi voucher: \
+                             <code>/wifi guest [hours]</code>.")]
+    #[custom(resident = true, in_group = false)]
+    Wifi(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_wifi)
+}
+
+async fn cmd_wifi(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Wifi(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) = &env.config.telegram.guest_wifi else {
+        bot.reply_message(&msg, "Guest WiFi is not configured.").await?;
+        return Ok(());
+    };
+    let hours = match parse_args(&args, conf) {
+        Ok(hours) => hours,
+        Err(e) => {
+            bot.reply_message(&msg, e).await?;
+            return Ok(());
+        }
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    let issued: i64 = schema::guest_vouchers::table
+        .filter(schema::guest_vouchers::issued_by.eq(DbUserId::from(from.id)))
+        .filter(
+            schema::guest_vouchers::created_at
+                .gt(now - chrono::Duration::days(7)),
+        )
+        .count()
+        .get_result(&mut *env.conn())?;
+    if issued >= i64::from(conf.weekly_quota) {
+        bot.reply_message(
+            &msg,
+            format!(
+                "You have already issued {issued} vouchers in the last seven \
+                 days, the limit is {}.",
+                conf.weekly_quota
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let username = format!("guest-{}", random_string(4));
+    let password = random_string(8);
+    let router_id = mikrotik::add_hotspot_user(
+        &env.health,
+        &env.reqwest_client,
+        &env.config.services.mikrotik,
+        &HotspotUser {
+            server: &conf.server,
+            profile: &conf.profile,
+            name: &username,
+            password: &password,
+            limit_uptime: Duration::from_secs(u64::from(hours) * 3600),
+            comment: &format!("botka: tg {}", from.id.0),
+        },
+    )
+    .await;
+    let router_id = match router_id {
+        Ok(router_id) => router_id,
+        Err(e) => {
+            log::error!("guest_wifi: failed to add hotspot user: {e}");
+            bot.reply_message(&msg, "Failed to create the voucher.").await?;
+            return Ok(());
+        }
+    };
+
+    let expires_at = now + chrono::Duration::hours(hours.into());
+    env.transaction(|conn| {
+        diesel::insert_into(schema::guest_vouchers::table)
+            .values((
+                schema::guest_vouchers::issued_by.eq(DbUserId::from(from.id)),
+                schema::guest_vouchers::username.eq(&username),
+                schema::guest_vouchers::router_id.eq(&router_id),
+                schema::guest_vouchers::created_at.eq(now),
+                schema::guest_vouchers::expires_at.eq(expires_at),
+            ))
+            .execute(conn)?;
+        crate::modules::audit::record(
+            conn,
+            Some(from.id),
+            "guest_voucher_issue",
+            &serde_json::json!({ "username": username, "hours": hours }),
+        )
+        .log_error("guest_wifi: audit");
+        Ok(())
+    })?;
+
+    let tz = timezones::chat_tz(&env, &mut env.conn(), msg.chat.id)?;
+    bot.reply_message(
+        &msg,
+        format!(
+            "🛜 <b>Guest WiFi voucher</b>\n\nNetwork: {}\nLogin: {}\n\
+             Password: {}\nValid until: {} ({tz})",
+            html::escape(&conf.ssid),
+            html::code_inline(&username),
+            html::code_inline(&password),
+            timezones::to_local(tz, expires_at).format("%Y-%m-%d %H:%M"),
+        ),
+    )
+    .parse_mode(ParseMode::Html)
+    .await?;
+    Ok(())
+}
+
+/// Parse `guest [hours]` into the number of hours.
+fn parse_args(args: &str, conf: &GuestWifi) -> Result<u32, String> {
+    let usage = || "Usage: /wifi guest [hours]".to_string();
+    let mut args = args.split_whitespace();
+    if args.next() != Some("guest") {
+        return Err(usage());
+    }
+    let hours = match (args.next(), args.next()) {
+        (None, _) => conf.default_hours,
+        (Some(hours), None) => hours.parse().map_err(|_| usage())?,
+        (Some(_), Some(_)) => return Err(usage()),
+    };
+    if hours == 0 || hours > conf.max_hours {
+        return Err(format!(
+            "The number of hours must be between 1 and {}.",
+            conf.max_hours
+        ));
+    }
+    Ok(hours)
+}
+
+fn random_string(len: usize) -> String {
+    let mut rng = OsRng;
+    (0..len)
+        .map(|_| {
+            let i = usize::try_from(rng.next_u32()).unwrap_or_default();
+            char::from(ALPHABET[i % ALPHABET.len()])
+        })
+        .collect()
+}
+
+/// Remove expired hotspot users from the router.
+pub async fn task(env: Arc<BotEnv>, shutdown: CancellationToken) {
+    if env.config.telegram.guest_wifi.is_none() {
+        return;
+    }
+    loop {
+        let vouchers = schema::guest_vouchers::table
+            .filter(schema::guest_vouchers::removed_at.is_null())
+            .filter(
+                schema::guest_vouchers::expires_at
+                    .le(chrono::Utc::now().naive_utc()),
+            )
+            .select(models::GuestVoucher::as_select())
+            .load(&mut *env.conn());
+        match vouchers {
+            Ok(vouchers) => {
+                for voucher in &vouchers {
+                    remove(&env, voucher)
+                        .await
+                        .log_error("guest_wifi: remove expired");
+                }
+            }
+            Err(e) => log::error!("guest_wifi: failed to load vouchers: {e}"),
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(CLEANUP_INTERVAL) => {}
+        }
+    }
+}
+
+async fn remove(env: &BotEnv, voucher: &models::GuestVoucher) -> Result<()> {
+    mikrotik::remove_hotspot_user(
+        &env.health,
+        &env.reqwest_client,
+        &env.config.services.mikrotik,
+        &voucher.router_id,
+    )
+    .await?;
+    diesel::update(schema::guest_vouchers::table)
+        .filter(schema::guest_vouchers::rowid.eq(voucher.rowid))
+        .set(
+            schema::guest_vouchers::removed_at
+                .eq(chrono::Utc::now().naive_utc()),
+        )
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let conf = GuestWifi {
+            ssid: "guest".to_string(),
+            server: "hotspot1".to_string(),
+            profile: "guest".to_string(),
+            default_hours: 4,
+            max_hours: 24,
+            weekly_quota: 5,
+        };
+        assert_eq!(parse_args("guest", &conf), Ok(4));
+        assert_eq!(parse_args(" guest  12 ", &conf), Ok(12));
+        assert!(parse_args("guest 0", &conf).is_err());
+        assert!(parse_args("guest 25", &conf).is_err());
+        assert!(parse_args("guest two", &conf).is_err());
+        assert!(parse_args("guest 1 2", &conf).is_err());
+        assert!(parse_args("", &conf).is_err());
+    }
+
+    #[test]
+    fn test_random_string() {
+        let s = random_string(8);
+        assert_eq!(s.len(), 8);
+        assert!(s.bytes().all(|c| ALPHABET.contains(&c)));
+    }
+}
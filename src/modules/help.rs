@@ -0,0 +1,282 @@
+//! Help generated from the command metadata of all modules.
+//!
+//! `/help` lists the commands the user can run, i.e. those not restricted to
+//! admins or residents unless the user is one, split into pages.  Each
+//! command has a button showing its detail page; `/help <command>` shows the
+//! detail page directly.
+//!
+//! **Scope**: `/help` command.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+
+use crate::common::{
+    filter_command, BotCommandsExt, BotEnv, CommandInfo, UpdateHandler,
+};
+use crate::modules;
+use crate::utils::{format_to, BotExt as _, ResultExt as _};
+
+/// Number of commands listed on a page.
+const PAGE_SIZE: usize = 12;
+
+/// Number of command buttons in a keyboard row.
+const ROW_SIZE: usize = 3;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "list available commands, or describe one with \
+                             <code>/help command</code>.")]
+    Help(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Action {
+    /// Show the page of the command list.
+    Page(usize),
+    /// Show the command, with a button back to the page.
+    Command(usize, String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_help)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+async fn cmd_help(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Help(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let commands = available_commands(&env, from.id);
+    let name = args.trim().trim_start_matches('/');
+    let (text, keyboard) = if name.is_empty() {
+        let (text, keyboard) = page(&commands, 0);
+        (text, Some(keyboard))
+    } else if let Some(command) = commands.iter().find(|c| c.name == name) {
+        (command_text(command), None)
+    } else {
+        bot.reply_message(&msg, format!("Unknown command: /{name}")).await?;
+        return Ok(());
+    };
+    let mut request = bot.reply_message(&msg, text).parse_mode(ParseMode::Html);
+    request.reply_markup = keyboard.map(Into::into);
+    request.await?;
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<Action> {
+    parse_callback(callback.data.as_ref()?)
+}
+
+fn parse_callback(data: &str) -> Option<Action> {
+    let data = data.strip_prefix("hp:")?;
+    if let Some(page) = data.strip_prefix("p:") {
+        return Some(Action::Page(page.parse().ok()?));
+    }
+    let (page, name) = data.strip_prefix("c:")?.split_once(':')?;
+    Some(Action::Command(page.parse().ok()?, name.to_string()))
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    action: Action,
+) -> Result<()> {
+    bot.answer_callback_query(&callback.id).await?;
+    let Some(message) = &callback.message else { return Ok(()) };
+
+    // Rendered for the user who pressed the button, who may not be the one
+    // who asked.
+    let commands = available_commands(&env, callback.from.id);
+    let (text, keyboard) = match action {
+        Action::Page(page_no) => page(&commands, page_no),
+        Action::Command(page_no, name) => {
+            match commands.iter().find(|c| c.name == name) {
+                Some(command) => (
+                    command_text(command),
+                    InlineKeyboardMarkup::new([[
+                        InlineKeyboardButton::callback(
+                            "« Back",
+                            format!("hp:p:{page_no}"),
+                        ),
+                    ]]),
+                ),
+                None => page(&commands, page_no),
+            }
+        }
+    };
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard)
+        .await
+        .log_error("help: update message");
+    Ok(())
+}
+
+/// Commands the user has the role to run.
+fn available_commands(env: &BotEnv, user: UserId) -> Vec<CommandInfo> {
+    modules::commands()
+        .into_iter()
+        .filter(|c| c.rules.allows(env, user))
+        .collect()
+}
+
+/// Render the page of the command list, with a button for each command.
+fn page(
+    commands: &[CommandInfo],
+    page: usize,
+) -> (String, InlineKeyboardMarkup) {
+    let pages = commands.len().div_ceil(PAGE_SIZE).max(1);
+    let page = page.min(pages - 1);
+    let on_page =
+        commands.iter().skip(page * PAGE_SIZE).take(PAGE_SIZE).collect_vec();
+
+    let mut text = format!("Available commands ({}/{pages}):\n\n", page + 1);
+    for command in &on_page {
+        format_to!(text, "/{}{} — ", command.name, place(command));
+        text.push_str(&command.description);
+        text.push('\n');
+    }
+    text.push_str("\nTap a command for details.");
+
+    let mut rows = on_page
+        .chunks(ROW_SIZE)
+        .map(|row| {
+            row.iter()
+                .map(|command| {
+                    InlineKeyboardButton::callback(
+                        format!("/{}", command.name),
+                        format!("hp:c:{page}:{}", command.name),
+                    )
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+    let nav = [
+        (page > 0).then(|| {
+            InlineKeyboardButton::callback("◀️", format!("hp:p:{}", page - 1))
+        }),
+        (page + 1 < pages).then(|| {
+            InlineKeyboardButton::callback("▶️", format!("hp:p:{}", page + 1))
+        }),
+    ]
+    .into_iter()
+    .flatten()
+    .collect_vec();
+    if !nav.is_empty() {
+        rows.push(nav);
+    }
+    (text, InlineKeyboardMarkup::new(rows))
+}
+
+/// Render the detail page of the command.
+fn command_text(command: &CommandInfo) -> String {
+    let mut text = format!("<code>/{}", command.name);
+    if command.takes_args {
+        text.push_str(" …");
+    }
+    text.push_str("</code>\n\n");
+    text.push_str(&command.description);
+    let who = match (command.rules.admin, command.rules.resident) {
+        (true, _) => "admins",
+        (false, true) => "residents",
+        (false, false) => "everyone",
+    };
+    let chats = match (command.rules.in_private, command.rules.in_group) {
+        (true, true) => "private chats and groups",
+        (true, false) => "private chats",
+        (false, true) => "groups",
+        (false, false) => "nowhere",
+    };
+    format_to!(text, "\n\nAvailable to {who}, in {chats}.");
+    text
+}
+
+/// A note on where the command can be used, if not everywhere.
+fn place(command: &CommandInfo) -> &'static str {
+    match (command.rules.in_private, command.rules.in_group) {
+        (true, true) => "",
+        (true, false) => " (in private)",
+        (false, true) => " (not in private)",
+        (false, false) => " (disabled?)",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    #[test]
+    fn test_parse_callback() {
+        assert_eq!(parse_callback("hp:p:2"), Some(Action::Page(2)));
+        assert_eq!(
+            parse_callback("hp:c:1:residents"),
+            Some(Action::Command(1, "residents".to_string()))
+        );
+        assert_eq!(parse_callback("hp:x:0"), None);
+        assert_eq!(parse_callback("st:p:0"), None);
+    }
+
+    #[test]
+    fn test_callback_data_fits() {
+        // Telegram limits callback data to 64 bytes.
+        for command in modules::commands() {
+            assert!(format!("hp:c:99:{}", command.name).len() <= 64);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_help_is_filtered() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let help = |from: serde_json::Value, text: &'static str| {
+            let t = &t;
+            async move {
+                t.telegram.clear();
+                t.dispatch(
+                    &command_handler(),
+                    testing::message(
+                        from["id"].as_i64().unwrap(),
+                        None,
+                        &from,
+                        text,
+                    ),
+                )
+                .await;
+                let sent = t.telegram.calls("sendMessage");
+                sent[0]["text"].as_str().unwrap().to_string()
+            }
+        };
+
+        let guest = testing::user_json(2, "Guest");
+        assert!(help(guest.clone(), "/help residents_timeline")
+            .await
+            .starts_with("Unknown command"));
+        let alice = testing::user_json(1, "Alice");
+        let text = help(alice, "/help residents_timeline").await;
+        assert!(text.contains("Available to residents"), "{text}");
+
+        let all = modules::commands();
+        let text = help(guest, "/help").await;
+        let pages = all
+            .iter()
+            .filter(|c| !c.rules.admin && !c.rules.resident)
+            .count()
+            .div_ceil(PAGE_SIZE);
+        assert!(text.contains(&format!("(1/{pages})")), "{text}");
+    }
+}
@@ -0,0 +1,408 @@
+//! Incident reports: injuries, near-misses, and equipment damage.
+//!
+//! Residents report incidents with `/incident`; the
+//! [`telegram.incidents.safety_officers`] are notified immediately, follow up
+//! with status updates, and receive a quarterly summary.
+//!
+//! **Scope**: `/incident` command, available to residents.  Status updates
+//! are available to safety officers and admins.
+//!
+//! [`telegram.incidents.safety_officers`]: crate::config::Incidents::safety_officers
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{Datelike as _, NaiveDate, NaiveTime, Timelike as _};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::config::Incidents;
+use crate::db::DbUserId;
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+const KINDS: &[&str] = &["injury", "near_miss", "damage", "other"];
+const STATUSES: &[&str] = &["open", "investigating", "resolved"];
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "report an incident: <code>/incident \
+                             [injury|near_miss|damage] description</code>.  \
+                             Safety officers update it with <code>/incident \
+                             id investigating|resolved [note]</code>.")]
+    #[custom(resident = true)]
+    Incident(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_incident)
+}
+
+/// Parsed `/incident` arguments.
+#[derive(Debug, PartialEq, Eq)]
+enum Args<'a> {
+    Report { kind: &'a str, description: &'a str },
+    Update { id: i32, status: &'a str, note: &'a str },
+    List,
+}
+
+fn parse_args(args: &str) -> Option<Args<'_>> {
+    let args = args.trim();
+    if args.is_empty() || args == "list" {
+        return Some(Args::List);
+    }
+    let (first, rest) = args
+        .split_once(char::is_whitespace)
+        .map_or((args, ""), |(a, b)| (a, b.trim()));
+    if let Ok(id) = first.parse() {
+        let (status, note) = rest
+            .split_once(char::is_whitespace)
+            .map_or((rest, ""), |(a, b)| (a, b.trim()));
+        return STATUSES.contains(&status).then_some(Args::Update {
+            id,
+            status,
+            note,
+        });
+    }
+    let (kind, description) = match KINDS.iter().find(|k| **k == first) {
+        Some(kind) => (*kind, rest),
+        None => ("other", args),
+    };
+    (!description.is_empty()).then_some(Args::Report { kind, description })
+}
+
+fn is_officer(env: &BotEnv, conf: &Incidents, user: UserId) -> bool {
+    conf.safety_officers.contains(&user)
+        || env.config.telegram.admins.contains(&user)
+}
+
+async fn cmd_incident(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Incident(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) = &env.config.telegram.incidents else {
+        bot.reply_message(&msg, "Incident reports are not configured.").await?;
+        return Ok(());
+    };
+
+    match parse_args(&args) {
+        Some(Args::Report { kind, description }) => {
+            report(&bot, &env, conf, &msg, from, kind, description).await
+        }
+        Some(Args::Update { id, status, note }) => {
+            if !is_officer(&env, conf, from.id) {
+                bot.reply_message(
+                    &msg,
+                    "Only safety officers can update incidents.",
+                )
+                .await?;
+                return Ok(());
+            }
+            update(&bot, &env, &msg, from, id, status, note).await
+        }
+        Some(Args::List) if is_officer(&env, conf, from.id) => {
+            list_open(&bot, &env, &msg).await
+        }
+        Some(Args::List) | None => {
+            bot.reply_message(
+                &msg,
+                "Usage: /incident [injury|near_miss|damage] <description>",
+            )
+            .await?;
+            Ok(())
+        }
+    }
+}
+
+async fn report(
+    bot: &Bot,
+    env: &BotEnv,
+    conf: &Incidents,
+    msg: &Message,
+    from: &teloxide::types::User,
+    kind: &str,
+    description: &str,
+) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let id = env.transaction(|conn| {
+        diesel::insert_into(schema::incidents::table)
+            .values((
+                schema::incidents::reporter.eq(DbUserId::from(from.id)),
+                schema::incidents::kind.eq(kind),
+                schema::incidents::description.eq(description),
+                schema::incidents::status.eq(STATUSES[0]),
+                schema::incidents::created_at.eq(now),
+                schema::incidents::updated_at.eq(now),
+            ))
+            .execute(conn)?;
+        schema::incidents::table
+            .select(schema::incidents::rowid)
+            .order(schema::incidents::rowid.desc())
+            .first::<i32>(conn)
+    })?;
+
+    let mut text = format!("🚨 <b>Incident #{id}</b> ({kind}) reported by ");
+    let user = models::TgUser {
+        id: from.id.into(),
+        username: from.username.clone(),
+        first_name: from.first_name.clone(),
+        last_name: from.last_name.clone(),
+    };
+    format_user(&mut text, from.id, &user, true);
+    format_to!(text, ":\n\n{}", html::escape(description));
+    for officer in &conf.safety_officers {
+        bot.send_message(*officer, &text)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await
+            .log_error("incidents: notify officer");
+    }
+
+    bot.reply_message(
+        msg,
+        format!("Incident #{id} is recorded, safety officers are notified."),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn update(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    from: &teloxide::types::User,
+    id: i32,
+    status: &str,
+    note: &str,
+) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let incident = env.transaction(|conn| {
+        let incident = schema::incidents::table
+            .filter(schema::incidents::rowid.eq(id))
+            .select(models::Incident::as_select())
+            .first(conn)
+            .optional()?;
+        if incident.is_none() {
+            return Ok(None);
+        }
+        diesel::update(schema::incidents::table)
+            .filter(schema::incidents::rowid.eq(id))
+            .set((
+                schema::incidents::status.eq(status),
+                schema::incidents::updated_at.eq(now),
+            ))
+            .execute(conn)?;
+        diesel::insert_into(schema::incident_updates::table)
+            .values((
+                schema::incident_updates::incident_id.eq(id),
+                schema::incident_updates::author.eq(DbUserId::from(from.id)),
+                schema::incident_updates::status.eq(status),
+                schema::incident_updates::note.eq(note),
+                schema::incident_updates::created_at.eq(now),
+            ))
+            .execute(conn)?;
+        Ok(incident)
+    })?;
+    let Some(incident) = incident else {
+        bot.reply_message(msg, "Unknown incident.").await?;
+        return Ok(());
+    };
+
+    let mut text = format!("Your incident report #{id} is now <b>{status}</b>");
+    if note.is_empty() {
+        text.push('.');
+    } else {
+        format_to!(text, ":\n\n{}", html::escape(note));
+    }
+    let reporter = UserId::from(incident.reporter);
+    if reporter != from.id
+        && models::incident_updates.get(&mut env.conn(), reporter)?
+    {
+        bot.send_message(reporter, text)
+            .parse_mode(ParseMode::Html)
+            .await
+            .log_error("incidents: notify reporter");
+    }
+    bot.reply_message(msg, format!("Incident #{id} is now {status}.")).await?;
+    Ok(())
+}
+
+async fn list_open(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<()> {
+    let incidents: Vec<models::Incident> = schema::incidents::table
+        .filter(schema::incidents::status.ne(STATUSES[2]))
+        .order(schema::incidents::rowid)
+        .select(models::Incident::as_select())
+        .load(&mut *env.conn())?;
+    let mut text = String::from("🚨 <b>Open incidents</b>\n\n");
+    if incidents.is_empty() {
+        text.push_str("None.");
+    }
+    for incident in &incidents {
+        format_incident(&mut text, incident);
+    }
+    bot.reply_message(msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+fn format_incident(out: &mut String, incident: &models::Incident) {
+    format_to!(
+        out,
+        "#{} {} ({}, {}): {}\n",
+        incident.rowid,
+        incident.created_at.format("%Y-%m-%d"),
+        incident.kind,
+        incident.status,
+        html::escape(&incident.description),
+    );
+}
+
+/// Return the key of the quarter containing `date`, e.g. `2026-Q4`, and the
+/// date range `[start, end)` of the preceding quarter.
+fn quarters(date: NaiveDate) -> (String, NaiveDate, NaiveDate) {
+    let quarter = date.month0() / 3;
+    let end = NaiveDate::from_ymd_opt(date.year(), quarter * 3 + 1, 1)
+        .unwrap_or(date);
+    let start = if quarter == 0 {
+        NaiveDate::from_ymd_opt(date.year() - 1, 10, 1)
+    } else {
+        NaiveDate::from_ymd_opt(date.year(), quarter * 3 - 2, 1)
+    }
+    .unwrap_or(end);
+    (format!("{}-Q{}", date.year(), quarter + 1), start, end)
+}
+
+/// Send quarterly summaries to safety officers.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.incidents else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(60 * 60)) => {}
+        }
+
+        let now = chrono::Utc::now();
+        if now.hour() < 9 {
+            continue;
+        }
+        let (key, start, end) = quarters(now.date_naive());
+        let last = models::incidents_last_summary.get(&mut env.conn());
+        if last.as_ref().is_ok_and(|k| k.as_ref() == Some(&key)) {
+            continue;
+        }
+        if let Err(e) = send_summary(&env, &bot, conf, start, end).await {
+            log::error!("incidents: failed to send summary: {e}");
+            continue;
+        }
+        models::incidents_last_summary
+            .set(&mut env.conn(), &key)
+            .log_error("incidents: set last summary");
+    }
+}
+
+async fn send_summary(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Incidents,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<()> {
+    let from = start.and_time(NaiveTime::default());
+    let to = end.and_time(NaiveTime::default());
+    let incidents: Vec<models::Incident> = schema::incidents::table
+        .filter(schema::incidents::created_at.ge(from))
+        .filter(schema::incidents::created_at.lt(to))
+        .order(schema::incidents::rowid)
+        .select(models::Incident::as_select())
+        .load(&mut *env.conn())?;
+
+    let last_day = end.pred_opt().unwrap_or(end);
+    let mut text =
+        format!("📊 <b>Incident summary</b> for {start} – {last_day}\n\n");
+    if incidents.is_empty() {
+        text.push_str("No incidents reported.");
+    } else {
+        let counts = incidents.iter().counts_by(|i| i.kind.as_str());
+        for kind in KINDS {
+            format_to!(text, "{kind}: {}\n", counts.get(kind).unwrap_or(&0));
+        }
+        text.push('\n');
+        for incident in &incidents {
+            format_incident(&mut text, incident);
+        }
+    }
+    for officer in &conf.safety_officers {
+        bot.send_message(*officer, &text)
+            .parse_mode(ParseMode::Html)
+            .await
+            .log_error("incidents: send summary");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args("injury cut finger on the bandsaw"),
+            Some(Args::Report {
+                kind: "injury",
+                description: "cut finger on the bandsaw"
+            })
+        );
+        assert_eq!(
+            parse_args("loud music at 3am"),
+            Some(Args::Report {
+                kind: "other",
+                description: "loud music at 3am"
+            })
+        );
+        assert_eq!(
+            parse_args("12 resolved blade guard replaced"),
+            Some(Args::Update {
+                id: 12,
+                status: "resolved",
+                note: "blade guard replaced"
+            })
+        );
+        assert_eq!(
+            parse_args("12 resolved"),
+            Some(Args::Update { id: 12, status: "resolved", note: "" })
+        );
+        assert_eq!(parse_args("12 done"), None);
+        assert_eq!(parse_args("damage"), None);
+        assert_eq!(parse_args(""), Some(Args::List));
+    }
+
+    #[test]
+    fn test_quarters() {
+        let d = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(
+            quarters(d(2026, 10, 16)),
+            ("2026-Q4".to_string(), d(2026, 7, 1), d(2026, 10, 1))
+        );
+        assert_eq!(
+            quarters(d(2026, 2, 1)),
+            ("2026-Q1".to_string(), d(2025, 10, 1), d(2026, 1, 1))
+        );
+    }
+}
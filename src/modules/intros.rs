@@ -0,0 +1,305 @@
+//! Member introductions and a directory of residents' skills.
+//!
+//! New residents are prompted in private messages to fill in a short intro,
+//! which is then posted to the [`telegram.chats.introductions`] thread.
+//! `/members` searches stored intros of current residents.
+//!
+//! **Scope**: `/intro` command in private chats, `/members` command for
+//! residents.
+//!
+//! [`telegram.chats.introductions`]: crate::config::TelegramChats::introductions
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{ParseMode, User};
+
+use crate::common::{
+    filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+const INTRO_TEMPLATE: &str = "<code>/intro
+skills: electronics, rust
+interests: 3d printing, board games
+pronouns: they/them</code>";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show or update your introduction.")]
+    #[custom(in_group = false)]
+    Intro(String),
+    #[command(description = "find residents: <code>/members \
+                             skill:query</code> or <code>/members \
+                             interest:query</code>.")]
+    #[custom(resident = true)]
+    Members(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(handle_command)
+}
+
+async fn handle_command(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    command: Commands,
+) -> Result<()> {
+    match command {
+        Commands::Intro(args) => cmd_intro(bot, env, msg, args).await,
+        Commands::Members(args) => cmd_members(bot, env, msg, args).await,
+    }
+}
+
+/// Fields of an introduction.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Intro {
+    skills: String,
+    interests: String,
+    pronouns: String,
+}
+
+/// Parse `key: value` lines.  Unknown keys are rejected.
+fn parse_intro(text: &str) -> Option<Intro> {
+    let mut intro = Intro::default();
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let (key, value) = line.split_once(':')?;
+        let field = match key.trim().to_lowercase().as_str() {
+            "skills" => &mut intro.skills,
+            "interests" => &mut intro.interests,
+            "pronouns" => &mut intro.pronouns,
+            _ => return None,
+        };
+        *field = value.trim().to_string();
+    }
+    (intro != Intro::default()).then_some(intro)
+}
+
+/// Ask newcomers to introduce themselves.  Called by the `welcome` module.
+pub async fn prompt(bot: &Bot, user: &User) {
+    let text = format!(
+        "Welcome, {}! Please introduce yourself to other residents by \
+         sending a message like this:\n\n{INTRO_TEMPLATE}",
+        html::escape(&user.first_name),
+    );
+    bot.send_message(user.id, text)
+        .parse_mode(ParseMode::Html)
+        .await
+        .log_error("intros: prompt");
+}
+
+async fn cmd_intro(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    args: String,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+
+    if args.trim().is_empty() {
+        let intro = schema::member_intros::table
+            .filter(schema::member_intros::user_id.eq(DbUserId::from(from.id)))
+            .first::<models::MemberIntro>(&mut *env.conn())
+            .optional()?;
+        let mut text = String::new();
+        if let Some(intro) = &intro {
+            text.push_str("Your current introduction:\n\n");
+            format_intro(&mut text, intro);
+            text.push_str("\nTo update it, send:\n\n");
+        } else {
+            text.push_str("To introduce yourself, send:\n\n");
+        }
+        text.push_str(INTRO_TEMPLATE);
+        bot.reply_message(&msg, text).parse_mode(ParseMode::Html).await?;
+        return Ok(());
+    }
+
+    let Some(intro) = parse_intro(&args) else {
+        bot.reply_message(
+            &msg,
+            format!(
+                "Can't parse your introduction. Example:\n\n{INTRO_TEMPLATE}"
+            ),
+        )
+        .parse_mode(ParseMode::Html)
+        .await?;
+        return Ok(());
+    };
+
+    let stored = env.transaction(|conn| {
+        use schema::member_intros::dsl as i;
+        let values = (
+            i::skills.eq(&intro.skills),
+            i::interests.eq(&intro.interests),
+            i::pronouns.eq(&intro.pronouns),
+            i::updated_at.eq(chrono::Utc::now().naive_utc()),
+        );
+        diesel::insert_into(i::member_intros)
+            .values((i::user_id.eq(DbUserId::from(from.id)), values))
+            .on_conflict(i::user_id)
+            .do_update()
+            .set(values)
+            .execute(conn)?;
+        i::member_intros
+            .filter(i::user_id.eq(DbUserId::from(from.id)))
+            .first::<models::MemberIntro>(conn)
+    })?;
+
+    publish(&bot, &env, from, &stored).await?;
+
+    bot.reply_message(&msg, "Your introduction is saved, thank you!").await?;
+    Ok(())
+}
+
+/// Post an intro to the introductions thread, or edit the existing post.
+async fn publish(
+    bot: &Bot,
+    env: &BotEnv,
+    user: &User,
+    intro: &models::MemberIntro,
+) -> Result<()> {
+    let Some(thread) = env.config.telegram.chats.introductions else {
+        return Ok(());
+    };
+    let tg_user = models::TgUser {
+        id: user.id.into(),
+        username: user.username.clone(),
+        first_name: user.first_name.clone(),
+        last_name: user.last_name.clone(),
+    };
+    let mut text = String::from("👋 ");
+    format_user(&mut text, user.id, &tg_user, true);
+    text.push('\n');
+    format_intro(&mut text, intro);
+
+    if let (Some(chat_id), Some(message_id)) = (intro.chat_id, intro.message_id)
+    {
+        let edited = bot
+            .edit_message_text(chat_id, message_id.into(), &text)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await;
+        if edited.is_ok() {
+            return Ok(());
+        }
+    }
+
+    let sent = bot
+        .send_message(thread.chat, text)
+        .message_thread_id(thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    diesel::update(schema::member_intros::table)
+        .filter(schema::member_intros::user_id.eq(DbUserId::from(user.id)))
+        .set((
+            schema::member_intros::chat_id.eq(DbChatId::from(sent.chat.id)),
+            schema::member_intros::message_id.eq(DbMessageId::from(sent.id)),
+        ))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+fn format_intro(out: &mut String, intro: &models::MemberIntro) {
+    for (name, value) in [
+        ("Skills", &intro.skills),
+        ("Interests", &intro.interests),
+        ("Pronouns", &intro.pronouns),
+    ] {
+        if !value.is_empty() {
+            format_to!(out, "<b>{name}:</b> {}\n", html::escape(value));
+        }
+    }
+}
+
+async fn cmd_members(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    args: String,
+) -> Result<()> {
+    let args = args.trim();
+    let (search_skills, query) = if let Some(q) = args.strip_prefix("skill:") {
+        (true, q.trim())
+    } else if let Some(q) = args.strip_prefix("interest:") {
+        (false, q.trim())
+    } else {
+        (false, "")
+    };
+    if query.is_empty() {
+        bot.reply_message(
+            &msg,
+            "Usage: /members skill:<query> or /members interest:<query>",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let pattern = format!("%{}%", query.replace(['%', '_'], ""));
+    let mut intros = schema::member_intros::table
+        .inner_join(
+            schema::residents::table.on(schema::residents::tg_id
+                .eq(schema::member_intros::user_id)
+                .and(schema::residents::end_date.is_null())),
+        )
+        .left_join(
+            schema::tg_users::table
+                .on(schema::tg_users::id.eq(schema::member_intros::user_id)),
+        )
+        .select((
+            models::MemberIntro::as_select(),
+            schema::tg_users::all_columns.nullable(),
+        ))
+        .into_boxed();
+    intros = if search_skills {
+        intros.filter(schema::member_intros::skills.like(pattern))
+    } else {
+        intros.filter(schema::member_intros::interests.like(pattern))
+    };
+    let found: Vec<(models::MemberIntro, Option<models::TgUser>)> =
+        intros.load(&mut *env.conn())?;
+
+    let mut text = String::new();
+    if found.is_empty() {
+        text.push_str("No residents found.");
+    }
+    for (intro, user) in &found {
+        format_user(&mut text, intro.user_id, user.as_ref(), true);
+        text.push('\n');
+        format_intro(&mut text, intro);
+        text.push('\n');
+    }
+    bot.reply_message(&msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_intro() {
+        assert_eq!(
+            parse_intro("skills: rust, soldering\n\nPronouns: she/her\n"),
+            Some(Intro {
+                skills: "rust, soldering".to_string(),
+                interests: String::new(),
+                pronouns: "she/her".to_string(),
+            })
+        );
+        assert_eq!(parse_intro("hello world"), None);
+        assert_eq!(parse_intro("age: 42"), None);
+        assert_eq!(parse_intro(""), None);
+    }
+}
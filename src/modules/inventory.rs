@@ -0,0 +1,375 @@
+//! Inventory catalog with photo-based intake.
+//!
+//! Residents send a photo with the `/intake` caption to the
+//! [`telegram.inventory.thread`].  The bot recognizes the items and their
+//! quantities with `OpenAI` vision and proposes them with inline buttons.
+//! Unwanted entries can be dropped, and the confirmed ones are added to the
+//! catalog, which is listed with `/inventory`.
+//!
+//! **Scope**: `/intake` and `/inventory` commands, available to residents.
+//!
+//! [`telegram.inventory.thread`]: crate::config::Inventory::thread
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+
+use crate::common::{
+    filter_command, read_photo, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::db::DbUserId;
+use crate::models::IntakeItem;
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _, Sqlizer};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "use as a photo caption in the inventory thread \
+                             to add the pictured items to the inventory.")]
+    #[custom(resident = true)]
+    Intake,
+
+    #[command(description = "list inventory items, optionally filtered by \
+                             name.")]
+    #[custom(resident = true)]
+    Inventory(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(handle_command)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+async fn handle_command(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    command: Commands,
+) -> Result<()> {
+    match command {
+        Commands::Intake => cmd_intake(&bot, &env, &msg).await,
+        Commands::Inventory(query) => {
+            cmd_inventory(&bot, &env, &msg, query.trim()).await
+        }
+    }
+}
+
+async fn cmd_intake(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<()> {
+    let Some(conf) = &env.config.telegram.inventory else { return Ok(()) };
+    let Some(from) = &msg.from else { return Ok(()) };
+    if !conf.thread.has_message(msg) {
+        bot.reply_message(msg, "Use this command in the inventory thread.")
+            .await?;
+        return Ok(());
+    }
+    if env.config.services.openai.disable {
+        bot.reply_message(msg, "Photo recognition is disabled.").await?;
+        return Ok(());
+    }
+    let Some(photo) = msg.photo().and_then(<[_]>::last) else {
+        bot.reply_message(msg, "Send a photo with the /intake caption.")
+            .await?;
+        return Ok(());
+    };
+
+    let content =
+        read_photo(bot, env, photo, VISION_PROMPT.trim(), 300).await?;
+    let items = parse_items(&content);
+    if items.is_empty() {
+        bot.reply_message(msg, "No items recognized on the photo.").await?;
+        return Ok(());
+    }
+
+    let rowid = env.transaction(|conn| {
+        diesel::insert_into(schema::inventory_intakes::table)
+            .values((
+                schema::inventory_intakes::user_id.eq(DbUserId::from(from.id)),
+                schema::inventory_intakes::items
+                    .eq(Sqlizer::new(items.clone()).unwrap()),
+                schema::inventory_intakes::created_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        schema::inventory_intakes::table
+            .select(schema::inventory_intakes::rowid)
+            .order(schema::inventory_intakes::rowid.desc())
+            .first::<i32>(conn)
+    })?;
+
+    bot.reply_message(msg, format_items(INTAKE_HEADER, &items))
+        .parse_mode(ParseMode::Html)
+        .reply_markup(intake_keyboard(rowid, &items))
+        .await?;
+    Ok(())
+}
+
+const VISION_PROMPT: &str = r#"
+The image is a photo of items brought to a hackerspace inventory.
+Respond with a JSON array of the items and their quantities, e.g. `[{"name":"M3 screw","quantity":20},{"name":"soldering iron","quantity":1}]`.
+If there are no recognizable items, respond with `[]`.
+"#;
+
+const INTAKE_HEADER: &str =
+    "📷 <b>Recognized items</b>\nDrop the wrong ones and confirm.";
+
+/// Parse the vision model response, skipping malformed entries.
+fn parse_items(content: &str) -> Vec<IntakeItem> {
+    serde_json::from_str::<Vec<serde_json::Value>>(content)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|item| {
+            let name = item["name"].as_str()?.trim();
+            let quantity = i32::try_from(item["quantity"].as_u64()?).ok()?;
+            (!name.is_empty() && quantity > 0)
+                .then(|| IntakeItem { name: name.to_string(), quantity })
+        })
+        .collect()
+}
+
+fn format_items(header: &str, items: &[IntakeItem]) -> String {
+    let mut text = format!("{header}\n\n");
+    for item in items {
+        format_to!(
+            text,
+            "• {} × {}\n",
+            html::escape(&item.name),
+            item.quantity
+        );
+    }
+    text
+}
+
+fn intake_keyboard(rowid: i32, items: &[IntakeItem]) -> InlineKeyboardMarkup {
+    let mut rows = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            vec![InlineKeyboardButton::callback(
+                format!("❌ {}", item.name),
+                format!("iv:drop:{rowid}:{i}"),
+            )]
+        })
+        .collect::<Vec<_>>();
+    rows.push(vec![
+        InlineKeyboardButton::callback("✅ Add", format!("iv:add:{rowid}")),
+        InlineKeyboardButton::callback(
+            "🚫 Cancel",
+            format!("iv:cancel:{rowid}"),
+        ),
+    ]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+async fn cmd_inventory(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    query: &str,
+) -> Result<()> {
+    let items: Vec<models::InventoryItem> = schema::inventory_items::table
+        .filter(schema::inventory_items::name.like(format!("%{query}%")))
+        .order(schema::inventory_items::name)
+        .select(models::InventoryItem::as_select())
+        .load(&mut *env.conn())?;
+    if items.is_empty() {
+        bot.reply_message(msg, "No inventory items found.").await?;
+        return Ok(());
+    }
+
+    let mut text = String::from("📦 <b>Inventory</b>\n\n");
+    for item in &items {
+        format_to!(
+            text,
+            "• {} × {}\n",
+            html::escape(&item.name),
+            item.quantity
+        );
+    }
+    bot.reply_message(msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Drop(i32, usize),
+    Add(i32),
+    Cancel(i32),
+}
+
+impl Action {
+    const fn rowid(&self) -> i32 {
+        match self {
+            Self::Drop(rowid, _) | Self::Add(rowid) | Self::Cancel(rowid) => {
+                *rowid
+            }
+        }
+    }
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<Action> {
+    parse_callback(callback.data.as_ref()?)
+}
+
+fn parse_callback(data: &str) -> Option<Action> {
+    let data = data.strip_prefix("iv:")?;
+    if let Some(args) = data.strip_prefix("drop:") {
+        let (rowid, index) = args.split_once(':')?;
+        return Some(Action::Drop(rowid.parse().ok()?, index.parse().ok()?));
+    }
+    if let Some(rowid) = data.strip_prefix("add:") {
+        return Some(Action::Add(rowid.parse().ok()?));
+    }
+    Some(Action::Cancel(data.strip_prefix("cancel:")?.parse().ok()?))
+}
+
+enum Outcome {
+    Updated(Vec<IntakeItem>),
+    Added(Vec<IntakeItem>),
+    Cancelled,
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    action: Action,
+) -> Result<()> {
+    let is_admin = env.config.telegram.admins.contains(&callback.from.id);
+    let rowid = action.rowid();
+    let result = env.transaction(|conn| {
+        let intake = schema::inventory_intakes::table
+            .filter(schema::inventory_intakes::rowid.eq(rowid))
+            .select(models::InventoryIntake::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(intake) = intake else {
+            return Ok(Err("This intake is already processed."));
+        };
+        if UserId::from(intake.user_id) != callback.from.id && !is_admin {
+            return Ok(Err("Only the sender of the photo can do this."));
+        }
+        let mut items = intake.items.as_ref().clone();
+
+        if let Action::Drop(_, index) = action {
+            if index >= items.len() {
+                return Ok(Err("This item is already dropped."));
+            }
+            items.remove(index);
+            if !items.is_empty() {
+                diesel::update(schema::inventory_intakes::table)
+                    .filter(schema::inventory_intakes::rowid.eq(rowid))
+                    .set(
+                        schema::inventory_intakes::items
+                            .eq(Sqlizer::new(items.clone()).unwrap()),
+                    )
+                    .execute(conn)?;
+                return Ok(Ok(Outcome::Updated(items)));
+            }
+        }
+
+        diesel::delete(schema::inventory_intakes::table)
+            .filter(schema::inventory_intakes::rowid.eq(rowid))
+            .execute(conn)?;
+        if action != Action::Add(rowid) || items.is_empty() {
+            return Ok(Ok(Outcome::Cancelled));
+        }
+        let now = chrono::Utc::now().naive_utc();
+        for item in &items {
+            diesel::insert_into(schema::inventory_items::table)
+                .values((
+                    schema::inventory_items::name.eq(&item.name),
+                    schema::inventory_items::quantity.eq(item.quantity),
+                    schema::inventory_items::added_by
+                        .eq(DbUserId::from(callback.from.id)),
+                    schema::inventory_items::updated_at.eq(now),
+                ))
+                .on_conflict(schema::inventory_items::name)
+                .do_update()
+                .set((
+                    schema::inventory_items::quantity
+                        .eq(schema::inventory_items::quantity + item.quantity),
+                    schema::inventory_items::updated_at.eq(now),
+                ))
+                .execute(conn)?;
+        }
+        Ok(Ok(Outcome::Added(items)))
+    })?;
+
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&callback.id).await?;
+    let Some(message) = &callback.message else { return Ok(()) };
+    match outcome {
+        Outcome::Updated(items) => {
+            bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                format_items(INTAKE_HEADER, &items),
+            )
+            .parse_mode(ParseMode::Html)
+            .reply_markup(intake_keyboard(rowid, &items))
+            .await
+            .log_error("inventory: update intake");
+        }
+        Outcome::Added(items) => {
+            let text = format_items("✅ <b>Added to the inventory</b>", &items);
+            bot.edit_message_text(message.chat.id, message.id, text)
+                .parse_mode(ParseMode::Html)
+                .await
+                .log_error("inventory: confirm intake");
+        }
+        Outcome::Cancelled => {
+            bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                "🚫 Intake cancelled.",
+            )
+            .await
+            .log_error("inventory: cancel intake");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_items() {
+        assert_eq!(
+            parse_items(
+                r#"[{"name":" M3 screw ","quantity":20},
+                    {"name":"","quantity":1},
+                    {"name":"cable","quantity":0},
+                    {"name":"tape"}]"#
+            ),
+            vec![IntakeItem { name: "M3 screw".to_string(), quantity: 20 }]
+        );
+        assert!(parse_items("[]").is_empty());
+        assert!(parse_items("I can't see anything").is_empty());
+    }
+
+    #[test]
+    fn test_parse_callback() {
+        assert_eq!(parse_callback("iv:drop:3:1"), Some(Action::Drop(3, 1)));
+        assert_eq!(parse_callback("iv:add:3"), Some(Action::Add(3)));
+        assert_eq!(parse_callback("iv:cancel:3"), Some(Action::Cancel(3)));
+        assert_eq!(parse_callback("iv:drop:3"), None);
+        assert_eq!(parse_callback("pk:pickup:3"), None);
+    }
+}
@@ -0,0 +1,434 @@
+//! Tracked invite links.
+//!
+//! `/invite create <chat> [expires] [member_limit]` creates an invite link
+//! to the chat, e.g. `/invite create here 7d 5`.  Users joining with a
+//! tracked link are recorded from `chat_member` updates, so `/invite list`
+//! shows who joined with which link, and a leaked link can be revoked with
+//! `/invite revoke #<id>`.
+//!
+//! **Scope**: `/invite` command, available to admins; `chat_member` updates
+//! of all chats.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{ParseMode, UpdateKind};
+
+use crate::common::{
+    filter_command, format_user, format_users, BotCommandsExt, BotEnv,
+    UpdateHandler,
+};
+use crate::db::{DbChatId, DbUserId};
+use crate::modules::timezones;
+use crate::utils::{
+    format_to, html, parse_duration, BotExt as _, ResultExt as _,
+};
+use crate::{models, schema};
+
+/// Telegram limits the name of an invite link.
+const MAX_NAME_CHARS: usize = 32;
+
+/// Number of links shown by `/invite list`.
+const LIST_LIMIT: i64 = 20;
+
+const USAGE: &str = "Usage:\n\
+                     /invite create <chat id or \"here\"> [expires, e.g. 7d] \
+                     [member limit]\n\
+                     /invite list\n\
+                     /invite revoke #<id>";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "manage tracked invite links: <code>/invite \
+                             create here 7d 5</code>, <code>/invite \
+                             list</code>, <code>/invite revoke #id</code>.")]
+    #[custom(admin = true)]
+    Invite(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct CreateArgs {
+    /// `None` for the current chat.
+    chat: Option<ChatId>,
+    expires_in: Option<std::time::Duration>,
+    member_limit: Option<u32>,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_invite)
+}
+
+async fn cmd_invite(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Invite(args): Commands,
+) -> Result<()> {
+    let mut words = args.split_whitespace();
+    match words.next() {
+        Some("create") => {
+            let Some(create) = parse_create_args(words) else {
+                bot.reply_message(&msg, USAGE).await?;
+                return Ok(());
+            };
+            cmd_create(&bot, &env, &msg, &create).await?;
+        }
+        Some("list") => cmd_list(&bot, &env, &msg).await?,
+        Some("revoke") => {
+            let id = words.next().and_then(|w| w.strip_prefix('#'));
+            let Some(id) = id.and_then(|id| id.parse().ok()) else {
+                bot.reply_message(&msg, USAGE).await?;
+                return Ok(());
+            };
+            cmd_revoke(&bot, &env, &msg, id).await?;
+        }
+        _ => {
+            bot.reply_message(&msg, USAGE).await?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_create_args<'a>(
+    mut words: impl Iterator<Item = &'a str>,
+) -> Option<CreateArgs> {
+    let chat = match words.next()? {
+        "here" => None,
+        chat => Some(ChatId(chat.parse().ok()?)),
+    };
+    let mut args = CreateArgs { chat, expires_in: None, member_limit: None };
+    for word in words {
+        if let Ok(limit) = word.parse() {
+            args.member_limit = Some(limit);
+        } else {
+            args.expires_in = Some(parse_duration(word)?);
+        }
+    }
+    Some(args)
+}
+
+async fn cmd_create(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    args: &CreateArgs,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let chat = args.chat.unwrap_or(msg.chat.id);
+    if chat.is_user() {
+        bot.reply_message(msg, "Invite links are only for groups.").await?;
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now();
+    let expires_at = args
+        .expires_in
+        .and_then(|d| chrono::Duration::from_std(d).ok())
+        .and_then(|d| now.checked_add_signed(d));
+    let mut request = bot.create_chat_invite_link(chat).name(
+        from.full_name().chars().take(MAX_NAME_CHARS).collect::<String>(),
+    );
+    request.expire_date = expires_at;
+    request.member_limit = args.member_limit;
+    let link = match request.await {
+        Ok(link) => link,
+        Err(e) => {
+            bot.reply_message(msg, format!("Failed to create a link: {e}"))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let id = env.transaction(|conn| {
+        use schema::invite_links::dsl as l;
+        diesel::insert_into(l::invite_links)
+            .values((
+                l::chat_id.eq(DbChatId::from(chat)),
+                l::invite_link.eq(&link.invite_link),
+                l::created_by.eq(DbUserId::from(from.id)),
+                l::created_at.eq(now.naive_utc()),
+                l::expires_at.eq(expires_at.map(|t| t.naive_utc())),
+                l::member_limit
+                    .eq(args.member_limit.and_then(|m| i32::try_from(m).ok())),
+            ))
+            .execute(conn)?;
+        l::invite_links
+            .select(l::rowid)
+            .order(l::rowid.desc())
+            .first::<i32>(conn)
+    })?;
+
+    bot.reply_message(
+        msg,
+        format!(
+            "🔗 Invite link #{id}: {}\nRevoke it with /invite revoke #{id}",
+            link.invite_link,
+        ),
+    )
+    .disable_web_page_preview(true)
+    .await?;
+    Ok(())
+}
+
+async fn cmd_list(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<()> {
+    let text = env.transaction(|conn| list_text(env, conn, msg.chat.id))?;
+    bot.reply_message(msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+fn list_text(
+    env: &BotEnv,
+    conn: &mut SqliteConnection,
+    shown_in: ChatId,
+) -> QueryResult<String> {
+    let links: Vec<models::InviteLink> = schema::invite_links::table
+        .order(schema::invite_links::rowid.desc())
+        .limit(LIST_LIMIT)
+        .select(models::InviteLink::as_select())
+        .load(conn)?;
+    if links.is_empty() {
+        return Ok("No invite links yet.".to_string());
+    }
+
+    let joins: Vec<(i32, DbUserId, Option<models::TgUser>)> =
+        schema::invite_link_joins::table
+            .left_join(schema::tg_users::table.on(
+                schema::tg_users::id.eq(schema::invite_link_joins::user_id),
+            ))
+            .filter(
+                schema::invite_link_joins::invite_link_id
+                    .eq_any(links.iter().map(|l| l.rowid)),
+            )
+            .order(schema::invite_link_joins::rowid)
+            .select((
+                schema::invite_link_joins::invite_link_id,
+                schema::invite_link_joins::user_id,
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(conn)?;
+    let joins = joins.into_iter().into_group_map_by(|(link, _, _)| *link);
+    let titles: Vec<(DbChatId, Option<String>)> = schema::tg_chats::table
+        .filter(
+            schema::tg_chats::id
+                .eq_any(links.iter().map(|l| l.chat_id).unique()),
+        )
+        .select((schema::tg_chats::id, schema::tg_chats::title))
+        .load(conn)?;
+    let tz = timezones::chat_tz(env, conn, shown_in)?;
+
+    let mut text = String::from("🔗 <b>Invite links</b>\n");
+    for link in &links {
+        let title = titles
+            .iter()
+            .find(|(id, _)| *id == link.chat_id)
+            .and_then(|(_, title)| title.as_deref());
+        format_to!(
+            text,
+            "\n#{} <code>{}</code> to {}, by ",
+            link.rowid,
+            html::escape(&link.invite_link),
+            title.map_or_else(
+                || format!("<code>{}</code>", ChatId::from(link.chat_id)),
+                html::escape,
+            ),
+        );
+        let creator = schema::tg_users::table
+            .filter(schema::tg_users::id.eq(link.created_by))
+            .first::<models::TgUser>(conn)
+            .optional()?;
+        format_user(&mut text, link.created_by, creator.as_ref(), true);
+        if link.revoked_at.is_some() {
+            text.push_str(", revoked");
+        } else if let Some(expires_at) = link.expires_at {
+            format_to!(
+                text,
+                ", expires {}",
+                timezones::to_local(tz, expires_at).format("%Y-%m-%d %H:%M"),
+            );
+        }
+        if let Some(limit) = link.member_limit {
+            format_to!(text, ", limit {limit}");
+        }
+        match joins.get(&link.rowid) {
+            Some(users) => {
+                format_to!(text, "\nJoined ({}): ", users.len());
+                format_users(
+                    &mut text,
+                    users.iter().map(|(_, id, user)| (*id, user.as_ref())),
+                );
+            }
+            None => text.push_str("\nNo one joined yet."),
+        }
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+async fn cmd_revoke(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    id: i32,
+) -> Result<()> {
+    let link = schema::invite_links::table
+        .filter(schema::invite_links::rowid.eq(id))
+        .select(models::InviteLink::as_select())
+        .first(&mut *env.conn())
+        .optional()?;
+    let Some(link) = link else {
+        bot.reply_message(msg, "Unknown invite link.").await?;
+        return Ok(());
+    };
+    if link.revoked_at.is_some() {
+        bot.reply_message(msg, "This link is already revoked.").await?;
+        return Ok(());
+    }
+
+    if let Err(e) =
+        bot.revoke_chat_invite_link(link.chat_id, &link.invite_link).await
+    {
+        bot.reply_message(msg, format!("Failed to revoke the link: {e}"))
+            .await?;
+        return Ok(());
+    }
+    diesel::update(schema::invite_links::table)
+        .filter(schema::invite_links::rowid.eq(id))
+        .set(
+            schema::invite_links::revoked_at.eq(chrono::Utc::now().naive_utc()),
+        )
+        .execute(&mut *env.conn())?;
+    bot.reply_message(msg, format!("Invite link #{id} is revoked.")).await?;
+    Ok(())
+}
+
+/// Record users who joined with a tracked link.
+pub fn inspect_update(env: Arc<BotEnv>, upd: Update) {
+    let UpdateKind::ChatMember(cm) = &upd.kind else { return };
+    let Some(link) = &cm.invite_link else { return };
+    if cm.old_chat_member.kind.is_present()
+        || !cm.new_chat_member.kind.is_present()
+    {
+        return;
+    }
+    let user = cm.new_chat_member.user.id;
+    env.transaction(|conn| {
+        let id = schema::invite_links::table
+            .filter(
+                schema::invite_links::chat_id.eq(DbChatId::from(cm.chat.id)),
+            )
+            .filter(schema::invite_links::invite_link.eq(&link.invite_link))
+            .select(schema::invite_links::rowid)
+            .first::<i32>(conn)
+            .optional()?;
+        let Some(id) = id else { return Ok(()) };
+        diesel::insert_into(schema::invite_link_joins::table)
+            .values((
+                schema::invite_link_joins::invite_link_id.eq(id),
+                schema::invite_link_joins::user_id.eq(DbUserId::from(user)),
+                schema::invite_link_joins::joined_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)
+            .map(drop)
+    })
+    .log_error("invite_links: record join");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    #[test]
+    fn test_parse_create_args() {
+        let parse = |s: &str| parse_create_args(s.split_whitespace());
+        assert_eq!(
+            parse("here 7d 5"),
+            Some(CreateArgs {
+                chat: None,
+                expires_in: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+                member_limit: Some(5),
+            })
+        );
+        assert_eq!(
+            parse("-100123"),
+            Some(CreateArgs {
+                chat: Some(ChatId(-100_123)),
+                expires_in: None,
+                member_limit: None,
+            })
+        );
+        assert_eq!(parse("here soon"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_invite_links() {
+        let t = TestBot::new();
+        let admin = t.env.config.telegram.admins[0];
+        let admin = testing::user_json(admin.0, "Admin");
+        let link = "https://t.me/+abc";
+        t.telegram.respond(
+            "createChatInviteLink",
+            json!({
+                "invite_link": link,
+                "creator": testing::user_json(1, "Bot"),
+                "creates_join_request": false,
+                "is_primary": false,
+                "is_revoked": false,
+            }),
+        );
+        t.dispatch(
+            &command_handler(),
+            testing::message(CHAT, None, &admin, "/invite create here 1d 3"),
+        )
+        .await;
+        let created = t.telegram.calls("createChatInviteLink");
+        assert_eq!(created[0]["chat_id"], CHAT);
+        assert_eq!(created[0]["member_limit"], 3);
+        assert!(created[0]["expire_date"].is_number());
+
+        crate::modules::tg_scraper::store_users(
+            &mut t.env.conn(),
+            &[models::NewTgUser {
+                id: UserId(2).into(),
+                username: None,
+                first_name: "Bob",
+                last_name: None,
+            }],
+        )
+        .unwrap();
+        let bob = testing::user_json(2, "Bob");
+        let update = testing::chat_member(CHAT, &bob, Some(link));
+        inspect_update(
+            Arc::clone(&t.env),
+            serde_json::from_value(update).unwrap(),
+        );
+        let text = list_text(&t.env, &mut t.env.conn(), ChatId(CHAT)).unwrap();
+        assert!(text.contains("Joined (1): Bob"), "{text}");
+
+        t.dispatch(
+            &command_handler(),
+            testing::message(CHAT, None, &admin, "/invite revoke #1"),
+        )
+        .await;
+        assert_eq!(t.telegram.calls("revokeChatInviteLink").len(), 1);
+        let text = list_text(&t.env, &mut t.env.conn(), ChatId(CHAT)).unwrap();
+        assert!(text.contains("revoked"), "{text}");
+    }
+}
@@ -0,0 +1,261 @@
+//! Requests to join residential chats.
+//!
+//! Requests from current residents are approved right away.  Requests from
+//! other users are posted to [`telegram.chats.join_requests`] with buttons
+//! to approve or decline them, or left pending if it's not set.  Every
+//! decision is recorded in the audit log.
+//!
+//! **Scope**: chats listed in [`telegram.chats.residential`] that require
+//! approval to join.
+//!
+//! [`telegram.chats.join_requests`]: crate::config::TelegramChats::join_requests
+//! [`telegram.chats.residential`]: crate::config::TelegramChats::residential
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use teloxide::prelude::*;
+use teloxide::types::{
+    ChatJoinRequest, InlineKeyboardButton, InlineKeyboardMarkup, ParseMode,
+};
+
+use crate::common::{BotEnv, UpdateHandler};
+use crate::utils::{format_to, html, remove_button_row, ResultExt as _};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Decision {
+    Approve,
+    Decline,
+}
+
+impl Decision {
+    const fn audit_action(self) -> &'static str {
+        match self {
+            Self::Approve => "join_request_approve",
+            Self::Decline => "join_request_decline",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CallbackData {
+    decision: Decision,
+    chat: ChatId,
+    user: UserId,
+}
+
+pub fn join_request_handler() -> UpdateHandler {
+    Update::filter_chat_join_request()
+        .filter(|req: ChatJoinRequest, env: Arc<BotEnv>| {
+            !env.config.telegram.passive_mode
+                && env.config.telegram.chats.residential.contains(&req.chat.id)
+        })
+        .endpoint(handle_join_request)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+async fn handle_join_request(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    req: ChatJoinRequest,
+) -> Result<()> {
+    let user = req.from.id;
+    if env.is_resident(user) {
+        bot.approve_chat_join_request(req.chat.id, user).await?;
+        crate::modules::audit::record(
+            &mut env.conn(),
+            None,
+            Decision::Approve.audit_action(),
+            &serde_json::json!({
+                "chat_id": req.chat.id.0,
+                "user_id": user.0,
+                "source": "resident",
+            }),
+        )
+        .log_error("join_requests: audit");
+        return Ok(());
+    }
+
+    let Some(thread) = env.config.telegram.chats.join_requests else {
+        log::info!(
+            "join_requests: leaving the request of {} to join {} pending",
+            user.0,
+            req.chat.id.0,
+        );
+        return Ok(());
+    };
+
+    let mut text = format!("🚪 {}", html::user_mention(&req.from));
+    format_to!(text, " (<code>{}</code>) asks to join ", user.0);
+    match req.chat.title() {
+        Some(title) => text.push_str(&html::escape(title)),
+        None => format_to!(text, "<code>{}</code>", req.chat.id.0),
+    }
+    text.push_str(", but isn't a resident.");
+    if let Some(bio) = &req.bio {
+        format_to!(text, "\n\nBio: {}", html::escape(bio));
+    }
+
+    let data = |decision: Decision| {
+        let decision = match decision {
+            Decision::Approve => "a",
+            Decision::Decline => "d",
+        };
+        format!("jr:{decision}:{}:{}", req.chat.id.0, user.0)
+    };
+    bot.send_message(thread.chat, text)
+        .message_thread_id(thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(InlineKeyboardMarkup::new([[
+            InlineKeyboardButton::callback(
+                "✅ Approve",
+                data(Decision::Approve),
+            ),
+            InlineKeyboardButton::callback(
+                "❌ Decline",
+                data(Decision::Decline),
+            ),
+        ]]))
+        .await?;
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<CallbackData> {
+    parse_callback(callback.data.as_ref()?)
+}
+
+fn parse_callback(data: &str) -> Option<CallbackData> {
+    let mut parts = data.strip_prefix("jr:")?.split(':');
+    let decision = match parts.next()? {
+        "a" => Decision::Approve,
+        "d" => Decision::Decline,
+        _ => return None,
+    };
+    let chat = ChatId(parts.next()?.parse().ok()?);
+    let user = UserId(parts.next()?.parse().ok()?);
+    Some(CallbackData { decision, chat, user })
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    data: CallbackData,
+) -> Result<()> {
+    if !env.config.telegram.admins.contains(&callback.from.id) {
+        bot.answer_callback_query(&callback.id)
+            .text("You must be an admin to do this.")
+            .await?;
+        return Ok(());
+    }
+
+    let CallbackData { decision, chat, user } = data;
+    let result = match decision {
+        Decision::Approve => {
+            bot.approve_chat_join_request(chat, user).await.map(drop)
+        }
+        Decision::Decline => {
+            bot.decline_chat_join_request(chat, user).await.map(drop)
+        }
+    };
+    if let Err(e) = result {
+        // E.g. the request was withdrawn or handled by another admin.
+        log::warn!("join_requests: {decision:?} {} in {}: {e}", user.0, chat.0);
+        bot.answer_callback_query(&callback.id)
+            .text(format!("Failed: {e}"))
+            .await?;
+        remove_button_row(&bot, &callback).await;
+        return Ok(());
+    }
+
+    crate::modules::audit::record(
+        &mut env.conn(),
+        Some(callback.from.id),
+        decision.audit_action(),
+        &serde_json::json!({
+            "chat_id": chat.0,
+            "user_id": user.0,
+            "source": "admin",
+        }),
+    )
+    .log_error("join_requests: audit");
+    let text = match decision {
+        Decision::Approve => "Approved.",
+        Decision::Decline => "Declined.",
+    };
+    bot.answer_callback_query(&callback.id).text(text).await?;
+    remove_button_row(&bot, &callback).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+
+    use super::*;
+    use crate::schema;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    #[test]
+    fn test_parse_callback() {
+        assert_eq!(
+            parse_callback("jr:d:-100123:42"),
+            Some(CallbackData {
+                decision: Decision::Decline,
+                chat: ChatId(-100_123),
+                user: UserId(42),
+            })
+        );
+        assert_eq!(parse_callback("jr:x:-100123:42"), None);
+        assert_eq!(parse_callback("mr:inv:-100123:42"), None);
+    }
+
+    fn audit_actions(t: &TestBot) -> Vec<String> {
+        schema::audit_log::table
+            .select(schema::audit_log::action)
+            .load(&mut *t.env.conn())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_join_requests() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let handler = join_request_handler();
+
+        let alice = testing::user_json(1, "Alice");
+        t.dispatch(&handler, testing::chat_join_request(CHAT, &alice)).await;
+        let approved = t.telegram.calls("approveChatJoinRequest");
+        assert_eq!(approved.len(), 1);
+        assert_eq!(approved[0]["user_id"], 1);
+        assert!(t.telegram.calls("sendMessage").is_empty());
+
+        let bob = testing::user_json(2, "Bob");
+        t.dispatch(&handler, testing::chat_join_request(CHAT, &bob)).await;
+        assert_eq!(t.telegram.calls("approveChatJoinRequest").len(), 1);
+        let posted = t.telegram.results("sendMessage");
+        assert_eq!(posted.len(), 1);
+
+        let admin = t.env.config.telegram.admins[0];
+        let admin = testing::user_json(admin.0, "Admin");
+        let data = format!("jr:d:{CHAT}:2");
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(&admin, &posted[0], &data),
+        )
+        .await;
+        let declined = t.telegram.calls("declineChatJoinRequest");
+        assert_eq!(declined.len(), 1);
+        assert_eq!(declined[0]["user_id"], 2);
+        assert_eq!(
+            audit_actions(&t),
+            ["join_request_approve", "join_request_decline"],
+        );
+    }
+}
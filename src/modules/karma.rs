@@ -0,0 +1,309 @@
+//! Thank other members by replying `/thanks` or `++` to their message.
+//!
+//! Thanks are counted per chat, and `/karma` shows the leaderboards of the
+//! month and of all time.  To keep the numbers meaningful, only residents
+//! can thank, not themselves or bots, each message counts once per member,
+//! and the number of thanks per day is limited.
+//!
+//! **Scope**: `/thanks` and `/karma` commands, and `++` replies, in groups.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{Datelike as _, NaiveDateTime};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::dispatching::UpdateFilterExt as _;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{ParseMode, ThreadId};
+
+use crate::common::{
+    filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::modules::timezones;
+use crate::utils::{format_to, html, BotExt as _};
+use crate::{models, schema};
+
+/// Thanks a member can give in a chat in 24 hours.
+const DAILY_LIMIT: i64 = 10;
+/// Thanks a member can give to the same member in a chat in 24 hours.
+const DAILY_LIMIT_PER_USER: i64 = 3;
+/// Entries in each leaderboard.
+const LEADERBOARD_SIZE: usize = 10;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "reply to a message to thank its author.")]
+    #[custom(resident = true, in_private = false)]
+    Thanks,
+    #[command(description = "show who was thanked the most in this chat.")]
+    #[custom(in_private = false)]
+    Karma,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(
+        |bot: Bot, env: Arc<BotEnv>, msg: Message, cmd: Commands| async move {
+            match cmd {
+                Commands::Thanks => thank(bot, env, msg).await,
+                Commands::Karma => cmd_karma(bot, env, msg).await,
+            }
+        },
+    )
+}
+
+pub fn message_handler() -> UpdateHandler {
+    Update::filter_message().filter(is_plus_plus).endpoint(thank)
+}
+
+/// A reply starting with `++` by a resident.
+fn is_plus_plus(env: Arc<BotEnv>, msg: Message) -> bool {
+    !msg.chat.is_private()
+        && replied_message(&msg).is_some()
+        && msg.text().is_some_and(|t| t.trim_start().starts_with("++"))
+        && msg.from.as_ref().is_some_and(|u| env.is_resident(u.id))
+}
+
+/// The message replied to, ignoring the service message of the forum topic,
+/// which all messages in the topic reply to.
+fn replied_message(msg: &Message) -> Option<&Message> {
+    msg.reply_to_message().filter(|r| msg.thread_id != Some(ThreadId(r.id)))
+}
+
+async fn thank(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(reply) = replied_message(&msg) else {
+        bot.reply_message(&msg, "Reply to a message to thank its author.")
+            .await?;
+        return Ok(());
+    };
+    let Some(to) = reply.from.as_ref().filter(|u| !u.is_bot) else {
+        bot.reply_message(&msg, "Only people can be thanked.").await?;
+        return Ok(());
+    };
+    if to.id == from.id {
+        bot.reply_message(&msg, "You can't thank yourself.").await?;
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let result = env.transaction(|conn| {
+        record(conn, msg.chat.id, reply.id, from.id, to.id, now)
+    })?;
+    let text = match result {
+        Ok(total) => format!(
+            "👍 {} +1, thanked {total} time{} here.",
+            html::user_mention(to),
+            if total == 1 { "" } else { "s" },
+        ),
+        Err(error) => error.to_string(),
+    };
+    bot.reply_message(&msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_notification(true)
+        .await?;
+    Ok(())
+}
+
+/// Store the thanks, returning the total number of thanks of the receiver in
+/// the chat, or the reason it is not counted.
+fn record(
+    conn: &mut SqliteConnection,
+    chat: ChatId,
+    message: teloxide::types::MessageId,
+    from: UserId,
+    to: UserId,
+    now: NaiveDateTime,
+) -> QueryResult<Result<i64, &'static str>> {
+    use schema::karma_thanks::dsl as k;
+
+    let given = k::karma_thanks
+        .filter(k::chat_id.eq(DbChatId::from(chat)))
+        .filter(k::from_user.eq(DbUserId::from(from)));
+    let already = given
+        .clone()
+        .filter(k::message_id.eq(DbMessageId::from(message)))
+        .count()
+        .get_result::<i64>(conn)?;
+    if already > 0 {
+        return Ok(Err("You already thanked for this message."));
+    }
+    let recent =
+        given.filter(k::created_at.gt(now - chrono::Duration::days(1)));
+    if recent.clone().count().get_result::<i64>(conn)? >= DAILY_LIMIT {
+        return Ok(Err("You've given enough thanks for today."));
+    }
+    let recent_to_user = recent
+        .filter(k::to_user.eq(DbUserId::from(to)))
+        .count()
+        .get_result::<i64>(conn)?;
+    if recent_to_user >= DAILY_LIMIT_PER_USER {
+        return Ok(Err("You've thanked them enough for today."));
+    }
+
+    diesel::insert_into(k::karma_thanks)
+        .values((
+            k::chat_id.eq(DbChatId::from(chat)),
+            k::message_id.eq(DbMessageId::from(message)),
+            k::from_user.eq(DbUserId::from(from)),
+            k::to_user.eq(DbUserId::from(to)),
+            k::created_at.eq(now),
+        ))
+        .execute(conn)?;
+    k::karma_thanks
+        .filter(k::chat_id.eq(DbChatId::from(chat)))
+        .filter(k::to_user.eq(DbUserId::from(to)))
+        .count()
+        .get_result(conn)
+        .map(Ok)
+}
+
+async fn cmd_karma(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let tz = timezones::space_tz(&env);
+    let month_start = timezones::now(tz)
+        .date()
+        .with_day(1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|d| timezones::to_utc(tz, d));
+    let text = leaderboards(&mut env.conn(), msg.chat.id, month_start)?;
+    bot.reply_message(&msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+fn leaderboards(
+    conn: &mut SqliteConnection,
+    chat: ChatId,
+    month_start: Option<NaiveDateTime>,
+) -> QueryResult<String> {
+    let thanks: Vec<(DbUserId, NaiveDateTime, Option<models::TgUser>)> =
+        schema::karma_thanks::table
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::tg_users::id.eq(schema::karma_thanks::to_user)),
+            )
+            .filter(schema::karma_thanks::chat_id.eq(DbChatId::from(chat)))
+            .select((
+                schema::karma_thanks::to_user,
+                schema::karma_thanks::created_at,
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(conn)?;
+    if thanks.is_empty() {
+        return Ok("Nobody was thanked here yet.  Reply /thanks or ++ to \
+                   a helpful message."
+            .to_string());
+    }
+    let users: HashMap<DbUserId, &Option<models::TgUser>> =
+        thanks.iter().map(|(id, _, user)| (*id, user)).collect();
+
+    let mut text = String::new();
+    let boards =
+        [("This month", month_start), ("All time", None::<NaiveDateTime>)];
+    for (title, since) in boards {
+        let counts = thanks
+            .iter()
+            .filter(|(_, at, _)| since.map_or(true, |since| *at >= since))
+            .map(|(id, _, _)| *id)
+            .counts();
+        format_to!(text, "🏆 <b>{title}</b>\n");
+        if counts.is_empty() {
+            text.push_str("No thanks yet.\n");
+        }
+        let top = counts
+            .into_iter()
+            .sorted_by_key(|&(id, count)| (std::cmp::Reverse(count), id))
+            .take(LEADERBOARD_SIZE);
+        for (place, (id, count)) in top.enumerate() {
+            format_to!(text, "{}. ", place + 1);
+            format_user(&mut text, id, users[&id].as_ref(), false);
+            format_to!(text, " — {count}\n");
+        }
+        text.push('\n');
+    }
+    Ok(text.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use teloxide::types::MessageId;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    fn reply(from: &Value, to: &Value, text: &str) -> Value {
+        let mut update = testing::message(CHAT, None, from, text);
+        update["message"]["reply_to_message"] =
+            testing::message(CHAT, None, to, "Fixed the printer")["message"]
+                .clone();
+        update
+    }
+
+    #[tokio::test]
+    async fn test_thanks() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+        let handler = Update::filter_message()
+            .branch(command_handler())
+            .branch(message_handler());
+
+        t.dispatch(&handler, reply(&alice, &bob, "++ thanks!")).await;
+        t.dispatch(&handler, reply(&alice, &bob, "/thanks")).await;
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 2);
+        assert!(sent[1]["text"].as_str().unwrap().contains("2 times"));
+        t.telegram.clear();
+
+        // Not counted: self-thanks, and `++` from non-residents.
+        t.dispatch(&handler, reply(&alice, &alice, "++")).await;
+        assert_eq!(
+            t.telegram.calls("sendMessage")[0]["text"],
+            "You can't thank yourself.",
+        );
+        t.dispatch(&handler, reply(&bob, &alice, "++")).await;
+        assert_eq!(t.telegram.calls("sendMessage").len(), 1);
+
+        let text = leaderboards(&mut t.env.conn(), ChatId(CHAT), None).unwrap();
+        assert!(text.contains("1. id=2 (unknown) — 2"), "{text}");
+    }
+
+    #[test]
+    fn test_limits() {
+        let t = TestBot::new();
+        let mut conn = t.env.conn();
+        let now = chrono::Utc::now().naive_utc();
+        let mut thank = |message: i32, to: u64| {
+            record(
+                &mut conn,
+                ChatId(CHAT),
+                MessageId(message),
+                UserId(1),
+                UserId(to),
+                now,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(thank(1, 2), Ok(1));
+        assert!(thank(1, 2).is_err());
+        assert_eq!(thank(2, 2), Ok(2));
+        assert_eq!(thank(3, 2), Ok(3));
+        assert!(thank(4, 2).is_err());
+        for message in 5..12 {
+            assert!(thank(message, u64::try_from(message).unwrap()).is_ok());
+        }
+        assert!(thank(12, 12).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! Archive links posted to the configured threads.
+//!
+//! Links found in messages in the [`telegram.link_archive.threads`] are
+//! submitted to the [`services.archivebox`] instance if configured, or to the
+//! Internet Archive otherwise.  The bot replies with permalinks to the
+//! archived copies.  Archived links are searchable with `/search`; long
+//! results are split into pages.
+//!
+//! **Scope**: messages in [`telegram.link_archive.threads`]; `/search`
+//! command, available to residents.
+//!
+//! [`telegram.link_archive.threads`]: crate::config::LinkArchive::threads
+//! [`services.archivebox`]: crate::config::Services::archivebox
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use tap::Tap as _;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, MessageEntityKind, ParseMode,
+};
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::{DbChatId, DbMessageId};
+use crate::models::{OutboxAction, UndoAction};
+use crate::modules::undo;
+use crate::utils::{
+    format_to, html, write_message_link, BotExt as _, Paginator,
+};
+use crate::{models, schema};
+
+const WAYBACK_SAVE_URL: &str = "https://web.archive.org/save/";
+const WAYBACK_URL: &str = "https://web.archive.org/web/";
+
+/// Maximum number of characters of a link shown in messages.
+const LINK_CHARS: usize = 60;
+
+/// Maximum number of search results.
+const MAX_RESULTS: i64 = 500;
+
+/// Pages of `/search` results.
+const PAGINATOR: Paginator = Paginator::new("la:page:", 20);
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "search archived links.")]
+    #[custom(resident = true)]
+    Search(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_search)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(|callback: CallbackQuery| {
+        PAGINATOR.parse(callback.data.as_ref()?)
+    })
+    .endpoint(handle_page_callback)
+}
+
+/// Archive links in messages posted to the configured threads.
+pub async fn inspect_message(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.link_archive else { return Ok(()) };
+    if !conf.threads.iter().any(|t| t.has_message(&msg))
+        || !models::link_archive.enabled(&env, msg.chat.id)
+    {
+        return Ok(());
+    }
+    let urls = extract_urls(&msg);
+    if urls.is_empty() {
+        return Ok(());
+    }
+
+    // Archiving may take a while, don't block other handlers.
+    tokio::spawn(async move {
+        if let Err(e) = archive_message(&bot, &env, &msg, urls).await {
+            log::error!("link_archive: failed to archive links: {e}");
+        }
+    });
+    Ok(())
+}
+
+fn extract_urls(msg: &Message) -> Vec<String> {
+    msg.parse_entities()
+        .or_else(|| msg.parse_caption_entities())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|e| match e.kind() {
+            MessageEntityKind::Url => Some(e.text().to_string()),
+            MessageEntityKind::TextLink { url } => Some(url.to_string()),
+            _ => None,
+        })
+        .map(|url| {
+            if url.starts_with("http://") || url.starts_with("https://") {
+                url
+            } else {
+                format!("http://{url}")
+            }
+        })
+        .unique()
+        .collect()
+}
+
+async fn archive_message(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    urls: Vec<String>,
+) -> Result<()> {
+    let mut text = String::new();
+    for url in urls {
+        let existing = schema::archived_links::table
+            .filter(schema::archived_links::url.eq(&url))
+            .select(schema::archived_links::archive_url)
+            .first::<String>(&mut *env.conn())
+            .optional()?;
+        let archive_url = match existing {
+            Some(archive_url) => archive_url,
+            None => match archive(env, &url).await {
+                Ok(archive_url) => {
+                    diesel::insert_or_ignore_into(
+                        schema::archived_links::table,
+                    )
+                    .values((
+                        schema::archived_links::url.eq(&url),
+                        schema::archived_links::archive_url.eq(&archive_url),
+                        schema::archived_links::chat_id
+                            .eq(DbChatId::from(msg.chat.id)),
+                        schema::archived_links::message_id
+                            .eq(DbMessageId::from(msg.id)),
+                        schema::archived_links::archived_at
+                            .eq(chrono::Utc::now().naive_utc()),
+                    ))
+                    .execute(&mut *env.conn())?;
+                    archive_url
+                }
+                Err(e) => {
+                    log::warn!("link_archive: failed to archive {url}: {e}");
+                    continue;
+                }
+            },
+        };
+        format_to!(text, "\n{}", html::link(&archive_url, &short_url(&url)));
+    }
+    if text.is_empty() {
+        return Ok(());
+    }
+
+    let reply = bot
+        .reply_message(msg, format!("🗄 Archived:{text}"))
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .disable_notification(true)
+        .await?;
+    if let Some(from) = &msg.from {
+        undo::push(
+            &mut env.conn(),
+            msg.chat.id,
+            from.id,
+            "archive reply",
+            vec![
+                UndoAction::Telegram {
+                    action: OutboxAction::DeleteMessage {
+                        chat_id: reply.chat.id,
+                        message_id: reply.id,
+                    },
+                },
+                UndoAction::ForgetArchivedLinks {
+                    chat_id: msg.chat.id,
+                    message_id: msg.id,
+                },
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Submit the URL for archiving.  Returns a permalink to the archived copy.
+async fn archive(env: &BotEnv, url: &str) -> Result<String> {
+    let result = if let Some(conf) = &env.config.services.archivebox {
+        let base = conf.url.trim_end_matches('/');
+        env.reqwest_client
+            .post(format!("{base}/api/v1/cli/add"))
+            .header("X-ArchiveBox-API-Key", &conf.token)
+            .json(&serde_json::json!({ "urls": [url], "depth": 0 }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map(|_| format!("{base}/archive/{url}"))
+    } else {
+        env.reqwest_client
+            .get(format!("{WAYBACK_SAVE_URL}{url}"))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map(|response| {
+                // The Wayback Machine redirects to the snapshot.
+                if response.url().path().starts_with("/web/") {
+                    response.url().to_string()
+                } else {
+                    format!("{WAYBACK_URL}{url}")
+                }
+            })
+    };
+    Ok(result.tap(|r| {
+        crate::metrics::update_service(&env.health, "archive", r.is_ok())
+    })?)
+}
+
+/// Shorten the URL for display.
+fn short_url(url: &str) -> String {
+    let url = url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_start_matches("www.");
+    if url.chars().count() <= LINK_CHARS {
+        return url.to_string();
+    }
+    let mut short: String = url.chars().take(LINK_CHARS - 1).collect();
+    short.push('…');
+    short
+}
+
+async fn cmd_search(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Search(query): Commands,
+) -> Result<()> {
+    let query = query.trim();
+    if query.is_empty() {
+        bot.reply_message(&msg, "Usage: /search <text>").await?;
+        return Ok(());
+    }
+    let Some((text, buttons)) = search_results(&env, query, 0)? else {
+        bot.reply_message(&msg, "Nothing found.").await?;
+        return Ok(());
+    };
+    bot.reply_message(&msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    Ok(())
+}
+
+/// Show another page of the results.  The query is taken from the `/search`
+/// command the results are replying to.
+async fn handle_page_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    page: usize,
+) -> Result<()> {
+    if !env.is_resident(callback.from.id) {
+        bot.answer_callback_query(&callback.id)
+            .text("Only residents can search links.")
+            .await?;
+        return Ok(());
+    }
+    let Some(message) = &callback.message else { return Ok(()) };
+    let query = message
+        .reply_to_message()
+        .and_then(|m| m.text())
+        .and_then(|t| t.split_once(char::is_whitespace))
+        .map(|(_, query)| query.trim());
+    let results = match query {
+        Some(query) => search_results(&env, query, page)?,
+        None => None,
+    };
+    let Some((text, buttons)) = results else {
+        bot.answer_callback_query(&callback.id)
+            .text("The search results are outdated.")
+            .await?;
+        return Ok(());
+    };
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    bot.answer_callback_query(&callback.id).await?;
+    Ok(())
+}
+
+/// Render the page of links matching the query, `None` if nothing matches.
+fn search_results(
+    env: &BotEnv,
+    query: &str,
+    page: usize,
+) -> Result<Option<(String, Vec<Vec<InlineKeyboardButton>>)>> {
+    let links: Vec<models::ArchivedLink> = schema::archived_links::table
+        .filter(schema::archived_links::url.like(format!("%{query}%")))
+        .filter(schema::archived_links::deleted_at.is_null())
+        .order(schema::archived_links::rowid.desc())
+        .limit(MAX_RESULTS)
+        .select(models::ArchivedLink::as_select())
+        .load(&mut *env.conn())?;
+    if links.is_empty() {
+        return Ok(None);
+    }
+
+    let page = PAGINATOR.page(&links, page);
+    let mut text = String::from("🗄 <b>Archived links</b>\n");
+    for link in page.items {
+        format_to!(
+            text,
+            "\n{} {} (",
+            link.archived_at.format("%Y-%m-%d"),
+            html::link(&link.url, &short_url(&link.url)),
+        );
+        write_message_link(&mut text, link.chat_id, link.message_id);
+        format_to!(
+            text,
+            "message</a>, {})",
+            html::link(&link.archive_url, "archive")
+        );
+    }
+    Ok(Some((text, page.buttons().into_iter().collect())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_url() {
+        assert_eq!(short_url("https://www.example.com/a"), "example.com/a");
+        let long = format!("http://example.com/{}", "x".repeat(100));
+        let short = short_url(&long);
+        assert_eq!(short.chars().count(), LINK_CHARS);
+        assert!(short.starts_with("example.com/x") && short.ends_with('…'));
+    }
+}
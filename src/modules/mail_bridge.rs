@@ -0,0 +1,321 @@
+//! Mirror announcements and poll results to a mailing list, and relay inbound
+//! mail to a Telegram thread.
+//!
+//! **Scope**: threads listed in [`services.mail.announcements`] config option
+//! (outbound), and [`services.mail.inbound`] thread (inbound).  The module is
+//! disabled if the `services.mail` section is absent.
+//!
+//! Both directions are deduplicated by `Message-ID` using the `mail_messages`
+//! table.  Inbound mail is marked as seen only once it is relayed, so mail
+//! that failed to be relayed is retried.
+//!
+//! [`services.mail.announcements`]: crate::config::Mail::announcements
+//! [`services.mail.inbound`]: crate::config::Mail::inbound
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+use diesel::prelude::*;
+use imap::types::Uid;
+use itertools::Itertools as _;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::BotEnv;
+use crate::config::{Mail, MailServer};
+use crate::utils::{format_to, html, ResultExt as _};
+use crate::{models, schema};
+
+/// Maximum length of the inbound mail body relayed to Telegram, in chars.
+const MAX_BODY_CHARS: usize = 3000;
+
+pub async fn inspect_message(env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let Some(conf) = &env.config.services.mail else { return Ok(()) };
+    if !conf.announcements.iter().any(|t| t.has_message(&msg)) {
+        return Ok(());
+    }
+    let Some(text) = msg.text().or_else(|| msg.caption()) else {
+        return Ok(());
+    };
+
+    let subject = text.lines().next().unwrap_or_default();
+    let mut body = text.to_string();
+    if let Some(from) = &msg.from {
+        format_to!(body, "\n\n-- \n{}", from.full_name());
+    }
+    send_mail(
+        &env,
+        conf,
+        &format!("tg.{}.{}", msg.chat.id, msg.id),
+        subject,
+        body,
+        (msg.chat.id, msg.id),
+    )
+    .await
+}
+
+/// Send results of a closed poll to the mailing list.
+pub async fn send_poll_results(
+    env: &BotEnv,
+    poll: &Poll,
+    info_message: (ChatId, MessageId),
+) -> Result<()> {
+    let Some(conf) = &env.config.services.mail else { return Ok(()) };
+
+    let mut body = String::new();
+    body.push_str(&poll.question);
+    body.push_str("\n\n");
+    for opt in &poll.options {
+        let percent = if poll.total_voter_count == 0 {
+            0
+        } else {
+            opt.voter_count * 100 / poll.total_voter_count
+        };
+        format_to!(body, "{percent:>3}% ({}) {}\n", opt.voter_count, opt.text);
+    }
+    format_to!(body, "\nTotal voters: {}\n", poll.total_voter_count);
+
+    send_mail(
+        env,
+        conf,
+        &format!("poll.{}", poll.id),
+        &format!("Poll results: {}", poll.question),
+        body,
+        info_message,
+    )
+    .await
+}
+
+async fn send_mail(
+    env: &BotEnv,
+    conf: &Mail,
+    local_id: &str,
+    subject: &str,
+    body: String,
+    tg_message: (ChatId, MessageId),
+) -> Result<()> {
+    let from: Mailbox = conf.from.parse().context("Invalid `from` address")?;
+    let message_id = format!("{local_id}@{}", from.email.domain());
+
+    let already_sent = schema::mail_messages::table
+        .filter(schema::mail_messages::message_id.eq(&message_id))
+        .count()
+        .get_result::<i64>(&mut *env.conn())?
+        > 0;
+    if already_sent {
+        return Ok(());
+    }
+
+    let email = lettre::Message::builder()
+        .message_id(Some(format!("<{message_id}>")))
+        .from(from)
+        .to(conf.mailing_list.parse().context("Invalid `mailing_list`")?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)?;
+
+    let mailer: AsyncSmtpTransport<Tokio1Executor> =
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&conf.smtp.host)?
+            .port(conf.smtp.port)
+            .credentials(Credentials::new(
+                conf.smtp.username.clone(),
+                conf.smtp.password.clone(),
+            ))
+            .build();
+    let result = mailer.send(email).await;
+    crate::metrics::update_service(&env.health, "smtp", result.is_ok());
+    result?;
+
+    diesel::insert_into(schema::mail_messages::table)
+        .values(models::MailMessage {
+            message_id,
+            inbound: false,
+            chat_id: tg_message.0.into(),
+            tg_message_id: tg_message.1.into(),
+            created_at: chrono::Utc::now().naive_utc(),
+        })
+        .execute(&mut *env.conn())?;
+
+    Ok(())
+}
+
+/// Periodically fetch inbound mail and relay it to the configured thread.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.services.mail else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(5 * 60)) => {}
+        }
+
+        let server = conf.imap.clone();
+        let fetched =
+            tokio::task::spawn_blocking(move || fetch_unseen(&server)).await;
+        let unseen = match fetched {
+            Ok(Ok(unseen)) => {
+                crate::metrics::update_service(&env.health, "imap", true);
+                unseen
+            }
+            Ok(Err(e)) => {
+                crate::metrics::update_service(&env.health, "imap", false);
+                log::error!("mail_bridge: failed to fetch mail: {e}");
+                continue;
+            }
+            Err(e) => {
+                log::error!("mail_bridge: fetch task panicked: {e}");
+                continue;
+            }
+        };
+
+        // Mail that failed to be relayed stays unseen and is retried.
+        let mut relayed = Vec::new();
+        for (uid, raw) in unseen {
+            let result = relay_inbound(&env, &bot, conf, &raw).await;
+            if result.is_ok() {
+                relayed.push(uid);
+            }
+            result.log_error("mail_bridge: relay inbound mail");
+        }
+        if relayed.is_empty() {
+            continue;
+        }
+        let server = conf.imap.clone();
+        let marked =
+            tokio::task::spawn_blocking(move || mark_seen(&server, &relayed))
+                .await;
+        match marked {
+            Ok(result) => {
+                crate::metrics::update_service(
+                    &env.health,
+                    "imap",
+                    result.is_ok(),
+                );
+                result.log_error("mail_bridge: mark mail as seen");
+            }
+            Err(e) => log::error!("mail_bridge: mark task panicked: {e}"),
+        }
+    }
+}
+
+type ImapSession = imap::Session<native_tls::TlsStream<std::net::TcpStream>>;
+
+fn imap_session(server: &MailServer) -> Result<ImapSession> {
+    let tls = native_tls::TlsConnector::builder().build()?;
+    let client =
+        imap::connect((server.host.as_str(), server.port), &server.host, &tls)?;
+    let mut session =
+        client.login(&server.username, &server.password).map_err(|(e, _)| e)?;
+    session.select("INBOX")?;
+    Ok(session)
+}
+
+/// Fetch unseen messages from the inbox with their UIDs.  They are left
+/// unseen until they are relayed, see [`mark_seen`].
+fn fetch_unseen(server: &MailServer) -> Result<Vec<(Uid, Vec<u8>)>> {
+    let mut session = imap_session(server)?;
+    let uids = session.uid_search("UNSEEN")?;
+    let mut result = Vec::new();
+    if !uids.is_empty() {
+        let uid_set = uids.iter().sorted().join(",");
+        for fetch in session.uid_fetch(uid_set, "(UID BODY.PEEK[])")?.iter() {
+            if let (Some(uid), Some(body)) = (fetch.uid, fetch.body()) {
+                result.push((uid, body.to_vec()));
+            }
+        }
+    }
+    session.logout()?;
+    Ok(result)
+}
+
+fn mark_seen(server: &MailServer, uids: &[Uid]) -> Result<()> {
+    let mut session = imap_session(server)?;
+    session.uid_store(uids.iter().join(","), "+FLAGS (\\Seen)")?;
+    session.logout()?;
+    Ok(())
+}
+
+async fn relay_inbound(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Mail,
+    raw: &[u8],
+) -> Result<()> {
+    let Some(mail) = mail_parser::MessageParser::default().parse(raw) else {
+        // Retrying won't help, so the mail is marked as seen.
+        log::warn!("mail_bridge: ignoring mail that failed to parse");
+        return Ok(());
+    };
+
+    if !mail.to().map_or(false, |to| to.contains(&conf.inbound_address)) {
+        return Ok(());
+    }
+
+    let Some(message_id) = mail.message_id() else {
+        log::warn!("mail_bridge: ignoring mail without Message-ID");
+        return Ok(());
+    };
+
+    let seen = schema::mail_messages::table
+        .filter(schema::mail_messages::message_id.eq(message_id))
+        .count()
+        .get_result::<i64>(&mut *env.conn())?
+        > 0;
+    if seen {
+        return Ok(());
+    }
+
+    let mut text = String::new();
+    text.push_str("📧 <b>");
+    text.push_str(&html::escape(mail.subject().unwrap_or("(no subject)")));
+    text.push_str("</b>\n");
+    if let Some(from) = mail.from().and_then(|f| f.first()) {
+        text.push_str("From: ");
+        if let Some(name) = from.name() {
+            text.push_str(&html::escape(name));
+            text.push(' ');
+        }
+        if let Some(address) = from.address() {
+            text.push_str(&html::escape(&format!("<{address}>")));
+        }
+        text.push('\n');
+    }
+    if let Some(body) = mail.body_text(0) {
+        let body = body.trim();
+        text.push('\n');
+        text.push_str(&html::escape(
+            &body.chars().take(MAX_BODY_CHARS).collect::<String>(),
+        ));
+        if body.chars().nth(MAX_BODY_CHARS).is_some() {
+            text.push_str("…\n\n[Message truncated]");
+        }
+    }
+
+    let sent = bot
+        .send_message(conf.inbound.chat, text)
+        .message_thread_id(conf.inbound.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+
+    diesel::insert_into(schema::mail_messages::table)
+        .values(models::MailMessage {
+            message_id: message_id.to_string(),
+            inbound: true,
+            chat_id: sent.chat.id.into(),
+            tg_message_id: sent.id.into(),
+            created_at: chrono::Utc::now().naive_utc(),
+        })
+        .execute(&mut *env.conn())?;
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+//! Mirror messages from selected threads into Matrix rooms, for members who
+//! don't use Telegram.  Mirroring is one-way, messages sent in Matrix are not
+//! relayed back.
+//!
+//! **Scope**: threads listed in [`services.matrix.rooms`] config option.  The
+//! module is disabled if the `services.matrix` section is absent.
+//!
+//! [`services.matrix.rooms`]: crate::config::Matrix::rooms
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use teloxide::prelude::*;
+
+use crate::common::BotEnv;
+use crate::config::Matrix;
+use crate::utils::{html, MessageExt as _};
+
+pub async fn inspect_message(env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    mirror(&env, &msg, None).await
+}
+
+/// Mirror a message to the Matrix rooms mapped to its thread.  Messages sent
+/// by the bot itself don't come as updates, so modules should call this
+/// function explicitly for them, passing the original HTML text.
+pub async fn mirror(
+    env: &BotEnv,
+    msg: &Message,
+    html_text: Option<&str>,
+) -> Result<()> {
+    let Some(conf) = &env.config.services.matrix else { return Ok(()) };
+    let Some(thread) = msg.thread_id_ext() else { return Ok(()) };
+    let rooms = conf
+        .rooms
+        .iter()
+        .filter(|r| r.from.chat == msg.chat.id && r.from.thread == thread)
+        .collect::<Vec<_>>();
+    if rooms.is_empty() {
+        return Ok(());
+    }
+    let Some(content) = event_content(msg, html_text) else { return Ok(()) };
+
+    // The transaction id makes retries idempotent on the homeserver side.
+    let txn_id = format!("botka-{}-{}", msg.chat.id, msg.id);
+    for room in rooms {
+        let result =
+            send_message(env, conf, &room.room_id, &txn_id, &content).await;
+        crate::metrics::update_service(&env.health, "matrix", result.is_ok());
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Content of the `m.room.message` event mirroring the message, if it has
+/// text.
+fn event_content(
+    msg: &Message,
+    html_text: Option<&str>,
+) -> Option<serde_json::Value> {
+    let text = msg.text().or_else(|| msg.caption())?;
+    let formatted = match (html_text, &msg.from) {
+        (Some(html_text), _) => html_text.to_string(),
+        (None, Some(from)) if !from.is_bot => format!(
+            "<b>{}</b>:<br>{}",
+            html::escape(&from.full_name()),
+            html::escape(text).replace('\n', "<br>"),
+        ),
+        (None, _) => html::escape(text).replace('\n', "<br>"),
+    };
+    let body = match &msg.from {
+        Some(from) if !from.is_bot && html_text.is_none() => {
+            format!("{}: {text}", from.full_name())
+        }
+        _ => text.to_string(),
+    };
+    Some(serde_json::json!({
+        "msgtype": "m.text",
+        "body": body,
+        "format": "org.matrix.custom.html",
+        "formatted_body": formatted,
+    }))
+}
+
+/// URL of the endpoint to send an event to the room.
+fn send_url(homeserver: &str, room_id: &str, txn_id: &str) -> Result<Url> {
+    let mut url = Url::parse(homeserver)?;
+    url.path_segments_mut()
+        .map_err(|()| anyhow!("Invalid Matrix homeserver URL"))?
+        .pop_if_empty()
+        .extend([
+            "_matrix",
+            "client",
+            "v3",
+            "rooms",
+            room_id,
+            "send",
+            "m.room.message",
+            txn_id,
+        ]);
+    Ok(url)
+}
+
+async fn send_message(
+    env: &BotEnv,
+    conf: &Matrix,
+    room_id: &str,
+    txn_id: &str,
+    content: &serde_json::Value,
+) -> Result<()> {
+    env.reqwest_client
+        .put(send_url(&conf.homeserver, room_id, txn_id)?)
+        .timeout(Duration::from_secs(10))
+        .bearer_auth(&conf.access_token)
+        .json(content)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock_telegram::message_json;
+    use crate::testing;
+
+    fn message(from: &serde_json::Value, text: &str) -> Message {
+        let mut msg = message_json(-1, 1, Some(123), from);
+        msg["text"] = text.into();
+        serde_json::from_value(msg).unwrap()
+    }
+
+    #[test]
+    fn test_event_content() {
+        let alice = testing::user_json(1, "Alice");
+        assert_eq!(
+            event_content(&message(&alice, "<hi>\nthere"), None),
+            Some(json!({
+                "msgtype": "m.text",
+                "body": "Alice: <hi>\nthere",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "<b>Alice</b>:<br>&lt;hi&gt;<br>there",
+            })),
+        );
+
+        let mut bot = testing::user_json(2, "Botka");
+        bot["is_bot"] = true.into();
+        let content =
+            event_content(&message(&bot, "Hi there"), Some("<b>Hi</b> there"))
+                .unwrap();
+        assert_eq!(content["body"], "Hi there");
+        assert_eq!(content["formatted_body"], "<b>Hi</b> there");
+        let content = event_content(&message(&bot, "a < b"), None).unwrap();
+        assert_eq!(content["body"], "a < b");
+        assert_eq!(content["formatted_body"], "a &lt; b");
+
+        let mut renamed = message_json(-1, 2, Some(123), &alice);
+        renamed.as_object_mut().unwrap().remove("text");
+        renamed["new_chat_title"] = "Chat".into();
+        let renamed: Message = serde_json::from_value(renamed).unwrap();
+        assert_eq!(event_content(&renamed, None), None);
+    }
+
+    #[test]
+    fn test_send_url() {
+        assert_eq!(
+            send_url("https://matrix.example.com/", "!room:example.com", "t1")
+                .unwrap()
+                .as_str(),
+            "https://matrix.example.com/_matrix/client/v3/rooms/\
+             !room:example.com/send/m.room.message/t1",
+        );
+        assert!(send_url("mailto:matrix@example.com", "!r", "t").is_err());
+    }
+}
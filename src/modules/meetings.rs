@@ -0,0 +1,461 @@
+//! Meeting minutes.
+//!
+//! `/meeting start` in a topic starts recording its messages until
+//! `/meeting end`.  Meanwhile, `/decision` records a decision, taken from
+//! the command text or from the message replied to, and `/action` of the
+//! `action_items` module creates action items linked to the meeting.  When
+//! the meeting ends, the minutes with participants, decisions and action
+//! items are posted to the topic, and appended along with the transcript to
+//! [`services.wikijs.meetings_page`] if it's set.
+//!
+//! **Scope**: `/meeting` and `/decision` commands, available to residents in
+//! groups; messages in topics with a meeting.
+//!
+//! [`services.wikijs.meetings_page`]: crate::config::WikiJs::meetings_page
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{MessageId, ParseMode};
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::{DbChatId, DbMessageId, DbThreadId, DbUserId};
+use crate::modules::timezones;
+use crate::utils::{
+    format_to, get_wikijs_page_with_id, html, update_wikijs_page, BotExt as _,
+    ResultExt as _,
+};
+use crate::{models, schema};
+
+/// Kinds of [`models::MeetingNote`].
+const MESSAGE: &str = "message";
+const DECISION: &str = "decision";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "record meeting minutes in this topic: \
+                             <code>/meeting start|end</code>.")]
+    #[custom(resident = true, in_private = false)]
+    Meeting(String),
+    #[command(description = "record a decision of the meeting, or reply to \
+                             a message with it.")]
+    #[custom(resident = true, in_private = false)]
+    Decision(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(handle_command)
+}
+
+async fn handle_command(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    command: Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let chat = DbChatId::from(msg.chat.id);
+    let thread = DbThreadId::from(msg.thread_id);
+    let meeting = active(&mut env.conn(), chat, thread)?;
+    let now = chrono::Utc::now().naive_utc();
+
+    let (meeting, args) = match (command, meeting) {
+        (Commands::Meeting(args), None) if args.trim() == "start" => {
+            diesel::insert_into(schema::meetings::table)
+                .values((
+                    schema::meetings::chat_id.eq(chat),
+                    schema::meetings::thread_id.eq(thread),
+                    schema::meetings::started_by.eq(DbUserId::from(from.id)),
+                    schema::meetings::started_at.eq(now),
+                ))
+                .execute(&mut *env.conn())?;
+            bot.reply_message(
+                &msg,
+                "📝 Meeting started, messages in this topic are recorded \
+                 until /meeting end.  Record decisions with /decision and \
+                 action items with /action @user, e.g. in reply to a message.",
+            )
+            .await?;
+            return Ok(());
+        }
+        (Commands::Meeting(args), Some(meeting)) if args.trim() == "end" => {
+            return end(&bot, &env, &msg, &meeting).await;
+        }
+        (Commands::Meeting(args), meeting) => {
+            let text = match (args.trim(), meeting) {
+                ("start", Some(_)) => "A meeting is already going on here.",
+                ("end", None) => "There is no meeting here.",
+                _ => "Usage: /meeting start|end",
+            };
+            bot.reply_message(&msg, text).await?;
+            return Ok(());
+        }
+        (Commands::Decision(_), None) => {
+            bot.reply_message(&msg, "Start a meeting with /meeting start.")
+                .await?;
+            return Ok(());
+        }
+        (Commands::Decision(args), Some(meeting)) => (meeting, args),
+    };
+
+    let Some(text) = note_text(&msg, &args) else {
+        bot.reply_message(
+            &msg,
+            "Add the text to the command, or reply to a message with it.",
+        )
+        .await?;
+        return Ok(());
+    };
+    diesel::insert_into(schema::meeting_notes::table)
+        .values((
+            schema::meeting_notes::meeting_id.eq(meeting.rowid),
+            schema::meeting_notes::kind.eq(DECISION),
+            schema::meeting_notes::user_id.eq(DbUserId::from(from.id)),
+            schema::meeting_notes::text.eq(&text),
+            schema::meeting_notes::created_at.eq(now),
+            schema::meeting_notes::message_id.eq(DbMessageId::from(msg.id)),
+        ))
+        .execute(&mut *env.conn())?;
+    bot.reply_message(&msg, "✅ Decision recorded.").await?;
+    Ok(())
+}
+
+/// The meeting going on in the topic.
+pub fn active(
+    conn: &mut SqliteConnection,
+    chat: DbChatId,
+    thread: DbThreadId,
+) -> QueryResult<Option<models::Meeting>> {
+    schema::meetings::table
+        .filter(schema::meetings::chat_id.eq(chat))
+        .filter(schema::meetings::thread_id.eq(thread))
+        .filter(schema::meetings::ended_at.is_null())
+        .select(models::Meeting::as_select())
+        .first(conn)
+        .optional()
+}
+
+/// The decision recorded with the message during the meeting.
+pub fn decision_of_message(
+    conn: &mut SqliteConnection,
+    meeting_id: i32,
+    message_id: MessageId,
+) -> QueryResult<Option<i32>> {
+    schema::meeting_notes::table
+        .filter(schema::meeting_notes::meeting_id.eq(meeting_id))
+        .filter(schema::meeting_notes::kind.eq(DECISION))
+        .filter(
+            schema::meeting_notes::message_id.eq(DbMessageId::from(message_id)),
+        )
+        .select(schema::meeting_notes::rowid)
+        .first(conn)
+        .optional()
+}
+
+/// Text of a decision or an action item: the command text, or the text of
+/// the message replied to.
+pub fn note_text(msg: &Message, text: &str) -> Option<String> {
+    let text = text.trim();
+    if !text.is_empty() {
+        return Some(text.to_string());
+    }
+    let reply = msg.reply_to_message()?;
+    reply.text().or_else(|| reply.caption()).map(ToString::to_string)
+}
+
+/// Record messages posted in topics with a meeting.
+pub fn inspect_message(env: Arc<BotEnv>, msg: Message) {
+    let Some(from) = &msg.from else { return };
+    let Some(text) = msg.text().or_else(|| msg.caption()) else { return };
+    if msg.chat.is_private() || text.starts_with('/') {
+        return;
+    }
+    env.transaction(|conn| {
+        let meeting =
+            active(conn, msg.chat.id.into(), DbThreadId::from(msg.thread_id))?;
+        let Some(meeting) = meeting else { return Ok(()) };
+        diesel::insert_into(schema::meeting_notes::table)
+            .values((
+                schema::meeting_notes::meeting_id.eq(meeting.rowid),
+                schema::meeting_notes::kind.eq(MESSAGE),
+                schema::meeting_notes::user_id.eq(DbUserId::from(from.id)),
+                schema::meeting_notes::text.eq(text),
+                schema::meeting_notes::created_at
+                    .eq(chrono::Utc::now().naive_utc()),
+                schema::meeting_notes::message_id.eq(DbMessageId::from(msg.id)),
+            ))
+            .execute(conn)
+            .map(drop)
+    })
+    .log_error("meetings: record message");
+}
+
+async fn end(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    meeting: &models::Meeting,
+) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let minutes = env.transaction(|conn| {
+        diesel::update(schema::meetings::table)
+            .filter(schema::meetings::rowid.eq(meeting.rowid))
+            .set(schema::meetings::ended_at.eq(now))
+            .execute(conn)?;
+        Minutes::load(env, conn, meeting.rowid)
+    })?;
+
+    bot.reply_message(msg, minutes.to_html())
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    append_to_wiki(env, &minutes.to_markdown())
+        .await
+        .log_error("meetings: append minutes to the wiki");
+    Ok(())
+}
+
+async fn append_to_wiki(env: &BotEnv, minutes: &str) -> Result<()> {
+    let conf = &env.config.services.wikijs;
+    let Some(path) = &conf.meetings_page else { return Ok(()) };
+    let page = get_wikijs_page_with_id(&conf.url, &conf.token, path).await?;
+    let content = format!("{}\n\n{minutes}", page.content.trim_end());
+    update_wikijs_page(&conf.url, &conf.token, &page, &content).await
+}
+
+/// Everything recorded during a meeting.
+struct Minutes {
+    meeting: models::Meeting,
+    notes: Vec<models::MeetingNote>,
+    actions: Vec<models::ActionItem>,
+    users: Vec<models::TgUser>,
+    tz: Tz,
+}
+
+impl Minutes {
+    fn load(
+        env: &BotEnv,
+        conn: &mut SqliteConnection,
+        meeting_id: i32,
+    ) -> QueryResult<Self> {
+        let meeting = schema::meetings::table
+            .filter(schema::meetings::rowid.eq(meeting_id))
+            .select(models::Meeting::as_select())
+            .first(conn)?;
+        let notes: Vec<models::MeetingNote> = schema::meeting_notes::table
+            .filter(schema::meeting_notes::meeting_id.eq(meeting_id))
+            .order(schema::meeting_notes::rowid)
+            .select(models::MeetingNote::as_select())
+            .load(conn)?;
+        let actions: Vec<models::ActionItem> = schema::action_items::table
+            .filter(schema::action_items::meeting_id.eq(meeting_id))
+            .order(schema::action_items::rowid)
+            .select(models::ActionItem::as_select())
+            .load(conn)?;
+        let ids = notes
+            .iter()
+            .map(|n| n.user_id)
+            .chain(actions.iter().map(|a| a.assignee_id))
+            .chain([meeting.started_by])
+            .unique()
+            .collect_vec();
+        let users = schema::tg_users::table
+            .filter(schema::tg_users::id.eq_any(ids))
+            .load(conn)?;
+        let tz = timezones::chat_tz(env, conn, meeting.chat_id.into())?;
+        Ok(Self { meeting, notes, actions, users, tz })
+    }
+
+    fn name(&self, id: DbUserId) -> String {
+        self.users.iter().find(|u| u.id == id).map_or_else(
+            || id.0.to_string(),
+            |u| match &u.last_name {
+                Some(last_name) => format!("{} {last_name}", u.first_name),
+                None => u.first_name.clone(),
+            },
+        )
+    }
+
+    /// Users who started the meeting or wrote in it.
+    fn participants(&self) -> Vec<String> {
+        [self.meeting.started_by]
+            .into_iter()
+            .chain(self.notes.iter().map(|n| n.user_id))
+            .unique()
+            .map(|id| self.name(id))
+            .collect()
+    }
+
+    fn notes(
+        &self,
+        kind: &'static str,
+    ) -> impl Iterator<Item = &models::MeetingNote> {
+        self.notes.iter().filter(move |n| n.kind == kind)
+    }
+
+    /// Start and end time of the meeting.
+    fn time(&self) -> String {
+        let local = |t| timezones::to_local(self.tz, t);
+        let started_at = local(self.meeting.started_at);
+        let mut text = started_at.format("%Y-%m-%d %H:%M").to_string();
+        if let Some(ended_at) = self.meeting.ended_at {
+            format_to!(text, "–{}", local(ended_at).format("%H:%M"));
+        }
+        text
+    }
+
+    /// Decisions and action items, as titled lists.
+    fn sections(&self) -> [(&'static str, Vec<String>); 2] {
+        let decisions = self.notes(DECISION).map(|n| n.text.clone()).collect();
+        let actions = self
+            .actions
+            .iter()
+            .map(|a| {
+                let mut text =
+                    format!("{}: {}", self.name(a.assignee_id), a.text);
+                if let Some(due_at) = a.due_at {
+                    format_to!(
+                        text,
+                        ", due {}",
+                        timezones::to_local(self.tz, due_at)
+                            .format("%Y-%m-%d %H:%M"),
+                    );
+                }
+                text
+            })
+            .collect();
+        [("Decisions", decisions), ("Action items", actions)]
+    }
+
+    fn to_html(&self) -> String {
+        let mut text = format!(
+            "📝 <b>Meeting minutes</b>, {}\nParticipants: {}\nMessages: {}\n",
+            self.time(),
+            html::escape(&self.participants().join(", ")),
+            self.notes(MESSAGE).count(),
+        );
+        for (title, items) in self.sections() {
+            format_to!(text, "\n<b>{title}</b>\n");
+            if items.is_empty() {
+                text.push_str("None.\n");
+            }
+            for (i, item) in items.iter().enumerate() {
+                format_to!(text, "{}. {}\n", i + 1, html::escape(item));
+            }
+        }
+        text
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut text = format!(
+            "## Meeting {}\n\nParticipants: {}\n",
+            self.time(),
+            self.participants().join(", "),
+        );
+        for (title, items) in self.sections() {
+            format_to!(text, "\n### {title}\n\n");
+            if items.is_empty() {
+                text.push_str("None.\n");
+            }
+            for item in items {
+                format_to!(text, "- {item}\n");
+            }
+        }
+        text.push_str("\n### Transcript\n\n");
+        for note in self.notes(MESSAGE) {
+            format_to!(
+                text,
+                "- {} {}: {}\n",
+                timezones::to_local(self.tz, note.created_at).format("%H:%M"),
+                self.name(note.user_id),
+                note.text.replace('\n', " "),
+            );
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::ThreadId;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    #[tokio::test]
+    async fn test_meeting() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+        let handler = command_handler();
+
+        t.dispatch(
+            &handler,
+            testing::message(CHAT, Some(5), &alice, "/decision x"),
+        )
+        .await;
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent[0]["text"], "Start a meeting with /meeting start.");
+
+        t.dispatch(
+            &handler,
+            testing::message(CHAT, Some(5), &alice, "/meeting start"),
+        )
+        .await;
+        let message = |from, text| {
+            let upd: Update = serde_json::from_value(testing::message(
+                CHAT,
+                Some(5),
+                from,
+                text,
+            ))
+            .unwrap();
+            let teloxide::types::UpdateKind::Message(msg) = upd.kind else {
+                unreachable!()
+            };
+            inspect_message(Arc::clone(&t.env), msg);
+        };
+        message(&bob, "Let's buy a new printer");
+        message(&alice, "Agreed");
+        t.dispatch(
+            &handler,
+            testing::message(CHAT, Some(5), &alice, "/decision Buy a printer"),
+        )
+        .await;
+        t.dispatch(
+            &crate::modules::action_items::command_handler(),
+            testing::message(CHAT, Some(5), &alice, "/action @bob order it"),
+        )
+        .await;
+
+        let thread = ThreadId(MessageId(5)).into();
+        let meeting =
+            active(&mut t.env.conn(), ChatId(CHAT).into(), thread).unwrap();
+        let minutes =
+            Minutes::load(&t.env, &mut t.env.conn(), meeting.unwrap().rowid)
+                .unwrap();
+        let text = minutes.to_html();
+        assert!(
+            text.contains("Participants: Alice, Bob\nMessages: 2"),
+            "{text}"
+        );
+        assert!(text.contains("<b>Decisions</b>\n1. Buy a printer"), "{text}");
+        assert!(
+            text.contains("<b>Action items</b>\n1. Bob: order it"),
+            "{text}"
+        );
+        let text = minutes.to_markdown();
+        assert!(text.contains(": Bob: Let's buy a new printer\n"), "{text}");
+    }
+}
@@ -0,0 +1,538 @@
+//! Nightly cross-check of residential chat membership.
+//!
+//! Membership recorded in `tg_users_in_chats` may drift from reality when the
+//! bot misses updates.  Once a day, the actual membership of residents and
+//! ex-residents is checked using `getChatMember`, and a report with one-tap
+//! fix buttons is posted for admins.
+//!
+//! **Scope**: chats listed in [`telegram.chats.residential`]; the report is
+//! posted to [`telegram.chats.membership_report`].
+//!
+//! [`telegram.chats.residential`]: crate::config::TelegramChats::residential
+//! [`telegram.chats.membership_report`]: crate::config::TelegramChats::membership_report
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Timelike as _;
+use diesel::prelude::*;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{format_user, BotEnv, UpdateHandler};
+use crate::db::{DbChatId, DbUserId};
+use crate::modules::role_changes;
+use crate::utils::{
+    format_to, html, remove_button_row, ResultExt as _, ThreadIdPair,
+};
+use crate::{models, schema};
+
+/// Hour of day (UTC) after which the nightly check runs.
+const RUN_AFTER_HOUR: u32 = 3;
+
+/// Delay between `getChatMember` requests to stay within rate limits.
+const REQUEST_DELAY: Duration = Duration::from_millis(100);
+
+/// Issues per report message, to stay within the Telegram limits of 4096
+/// characters and 100 buttons per message.
+const ISSUES_PER_MESSAGE: usize = 10;
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+/// A single membership inconsistency.
+enum Issue {
+    /// An active resident is not a member of a residential chat.
+    MissingResident(ChatId, UserId),
+    /// An ex-resident is still a member of a residential chat.
+    ExResidentInside(ChatId, UserId),
+}
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(report_to) = env.config.telegram.chats.membership_report else {
+        return;
+    };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(60 * 60)) => {}
+        }
+
+        let now = chrono::Utc::now();
+        if now.hour() < RUN_AFTER_HOUR {
+            continue;
+        }
+        let today = now.date_naive().to_string();
+        let last =
+            models::membership_reconciliation_last_run.get(&mut env.conn());
+        if last.as_ref().is_ok_and(|d| d.as_ref() == Some(&today)) {
+            continue;
+        }
+
+        let result = reconcile(&env, &bot, report_to).await;
+        crate::metrics::update_service(
+            &env.health,
+            "membership_reconciliation",
+            result.is_ok(),
+        );
+        if let Err(e) = result {
+            log::error!("membership_reconciliation: {e:#}");
+            continue;
+        }
+        models::membership_reconciliation_last_run
+            .set(&mut env.conn(), &today)
+            .log_error("membership_reconciliation: set last run");
+    }
+}
+
+async fn reconcile(
+    env: &BotEnv,
+    bot: &Bot,
+    report_to: ThreadIdPair,
+) -> Result<()> {
+    let residential = &env.config.telegram.chats.residential;
+    let (residents, ex_residents) = {
+        let mut conn = env.conn();
+        let residents: BTreeSet<UserId> = schema::residents::table
+            .filter(schema::residents::end_date.is_null())
+            .select(schema::residents::tg_id)
+            .load::<DbUserId>(&mut *conn)?
+            .into_iter()
+            .map(UserId::from)
+            .collect();
+        // Ex-residents that are believed to be in residential chats.
+        let ex_residents: Vec<(DbChatId, DbUserId)> =
+            schema::tg_users_in_chats::table
+                .filter(schema::tg_users_in_chats::seen.eq(true))
+                .filter(
+                    schema::tg_users_in_chats::chat_id
+                        .eq_any(residential.iter().map(|&c| DbChatId::from(c))),
+                )
+                .filter(schema::tg_users_in_chats::user_id.eq_any(
+                    schema::residents::table.select(schema::residents::tg_id),
+                ))
+                .select((
+                    schema::tg_users_in_chats::chat_id,
+                    schema::tg_users_in_chats::user_id,
+                ))
+                .load(&mut *conn)?;
+        (residents, ex_residents)
+    };
+
+    let mut issues = Vec::new();
+    for &chat in residential {
+        for &user in &residents {
+            if is_member(bot, chat, user).await == Some(false) {
+                issues.push(Issue::MissingResident(chat, user));
+            }
+        }
+    }
+    for (chat, user) in ex_residents {
+        let (chat, user) = (ChatId::from(chat), UserId::from(user));
+        if residents.contains(&user) {
+            continue;
+        }
+        if is_member(bot, chat, user).await == Some(true) {
+            issues.push(Issue::ExResidentInside(chat, user));
+        }
+    }
+
+    if issues.is_empty() {
+        return Ok(());
+    }
+
+    let (users, chats) = load_names(env, &issues)?;
+    let chat_name = |chat: &ChatId| {
+        chats.get(chat).map_or_else(|| chat.0.to_string(), |t| html::escape(t))
+    };
+    let user_name = |user: &UserId| {
+        users
+            .get(user)
+            .map_or_else(|| user.0.to_string(), |u| u.first_name.clone())
+    };
+
+    let parts = issues.chunks(ISSUES_PER_MESSAGE).collect::<Vec<_>>();
+    for (index, part) in parts.iter().enumerate() {
+        let mut text = String::from("<b>Chat membership reconciliation</b>");
+        if parts.len() > 1 {
+            format_to!(text, " ({}/{})", index + 1, parts.len());
+        }
+        text.push('\n');
+        let mut buttons = Vec::new();
+        for issue in *part {
+            match issue {
+                Issue::MissingResident(chat, user) => {
+                    text.push_str("• Resident ");
+                    format_user(&mut text, *user, users.get(user), true);
+                    format_to!(text, " is not in {}\n", chat_name(chat));
+                    buttons.push(vec![
+                        InlineKeyboardButton::callback(
+                            format!("📨 Invite {}", user_name(user)),
+                            format!("mr:inv:{}:{}", chat.0, user.0),
+                        ),
+                        InlineKeyboardButton::callback(
+                            format!("🚪 End residency of {}", user_name(user)),
+                            format!("mr:end:{}:{}", chat.0, user.0),
+                        ),
+                    ]);
+                }
+                Issue::ExResidentInside(chat, user) => {
+                    text.push_str("• Ex-resident ");
+                    format_user(&mut text, *user, users.get(user), true);
+                    format_to!(text, " is still in {}\n", chat_name(chat));
+                    buttons.push(vec![InlineKeyboardButton::callback(
+                        format!("👢 Remove {} from the chat", user_name(user)),
+                        format!("mr:kick:{}:{}", chat.0, user.0),
+                    )]);
+                }
+            }
+        }
+
+        bot.send_message(report_to.chat, text)
+            .message_thread_id(report_to.thread)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .reply_markup(InlineKeyboardMarkup::new(buttons))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Whether the user is a member of the chat, or `None` if it can't be
+/// checked.  A failed check doesn't stop the reconciliation.
+async fn is_member(bot: &Bot, chat: ChatId, user: UserId) -> Option<bool> {
+    let result = bot.get_chat_member(chat, user).await;
+    sleep(REQUEST_DELAY).await;
+    result
+        .map_err(|e| {
+            log::error!(
+                "membership_reconciliation: failed to check {} in {}: {e}",
+                user.0,
+                chat.0,
+            );
+        })
+        .ok()
+        .map(|member| member.is_present())
+}
+
+fn load_names(
+    env: &BotEnv,
+    issues: &[Issue],
+) -> Result<(HashMap<UserId, models::TgUser>, HashMap<ChatId, String>)> {
+    let (chat_ids, user_ids): (Vec<_>, Vec<_>) = issues
+        .iter()
+        .map(|issue| match *issue {
+            Issue::MissingResident(chat, user)
+            | Issue::ExResidentInside(chat, user) => {
+                (DbChatId::from(chat), DbUserId::from(user))
+            }
+        })
+        .unzip();
+    let mut conn = env.conn();
+    let users = schema::tg_users::table
+        .filter(schema::tg_users::id.eq_any(user_ids))
+        .load::<models::TgUser>(&mut *conn)?
+        .into_iter()
+        .map(|u| (u.id.into(), u))
+        .collect();
+    let chats = schema::tg_chats::table
+        .filter(schema::tg_chats::id.eq_any(chat_ids))
+        .load::<models::TgChat>(&mut *conn)?
+        .into_iter()
+        .filter_map(|c| Some((c.id.into(), c.title?)))
+        .collect();
+    Ok((users, chats))
+}
+
+#[derive(Clone, Copy)]
+enum CallbackAction {
+    Invite,
+    EndResidency,
+    Kick,
+}
+
+#[derive(Clone, Copy)]
+struct CallbackData {
+    action: CallbackAction,
+    chat: ChatId,
+    user: UserId,
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<CallbackData> {
+    let data = callback.data.as_ref()?.strip_prefix("mr:")?;
+    let mut parts = data.split(':');
+    let action = match parts.next()? {
+        "inv" => CallbackAction::Invite,
+        "end" => CallbackAction::EndResidency,
+        "kick" => CallbackAction::Kick,
+        _ => return None,
+    };
+    let chat = ChatId(parts.next()?.parse().ok()?);
+    let user = UserId(parts.next()?.parse().ok()?);
+    Some(CallbackData { action, chat, user })
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    data: CallbackData,
+) -> Result<()> {
+    if !env.config.telegram.admins.contains(&callback.from.id) {
+        bot.answer_callback_query(&callback.id)
+            .text("You must be an admin to do this.")
+            .await?;
+        return Ok(());
+    }
+
+    let CallbackData { action, chat, user } = data;
+    let result = match action {
+        CallbackAction::Invite => {
+            let link = bot
+                .create_chat_invite_link(chat)
+                .member_limit(1)
+                .await?
+                .invite_link;
+            bot.send_message(
+                user,
+                format!(
+                    "You are a resident, but you are not a member of one of \
+                     the residential chats.  Please join it using this \
+                     link: {link}"
+                ),
+            )
+            .await
+            .map(|_| "Invite link sent.")
+            .map_err(|_| "Failed to message the user.")
+        }
+        CallbackAction::EndResidency => {
+            let ended = env.transaction(|conn| {
+                let ended = role_changes::track(conn, user, |conn| {
+                    diesel::update(schema::residents::table)
+                        .filter(
+                            schema::residents::tg_id.eq(DbUserId::from(user)),
+                        )
+                        .filter(schema::residents::end_date.is_null())
+                        .set(schema::residents::end_date.eq(diesel::dsl::now))
+                        .execute(conn)
+                })?;
+                if ended > 0 {
+                    crate::modules::audit::record(
+                        conn,
+                        Some(callback.from.id),
+                        "resident_remove",
+                        &serde_json::json!({
+                            "user_id": user.0,
+                            "source": "membership_reconciliation",
+                        }),
+                    )
+                    .log_error("membership_reconciliation: audit");
+                }
+                Ok(ended)
+            })?;
+            if ended > 0 {
+                role_changes::committed(&env);
+                Ok("Residency ended.")
+            } else {
+                Err("This user is not a resident anymore.")
+            }
+        }
+        CallbackAction::Kick => {
+            // Ban and immediately unban to remove the user without
+            // preventing them from joining again later.
+            let result = bot.ban_chat_member(chat, user).await;
+            if result.is_ok() {
+                bot.unban_chat_member(chat, user)
+                    .await
+                    .log_error("membership_reconciliation: unban");
+                crate::modules::audit::record(
+                    &mut env.conn(),
+                    Some(callback.from.id),
+                    "chat_kick",
+                    &serde_json::json!({
+                        "user_id": user.0,
+                        "chat_id": chat.0,
+                    }),
+                )
+                .log_error("membership_reconciliation: audit");
+            }
+            result
+                .map(|_| "Removed from the chat.")
+                .map_err(|_| "Failed to remove the user.")
+        }
+    };
+
+    let text = match result {
+        Ok(text) | Err(text) => text,
+    };
+    bot.answer_callback_query(&callback.id).text(text).await?;
+
+    if result.is_ok() {
+        remove_button_row(&bot, &callback).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use teloxide::types::{MessageId, ThreadId};
+
+    use super::*;
+    use crate::mock_telegram::{me_json, message_json};
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+    const ADMIN: u64 = 1_234_567_890;
+
+    fn test_bot() -> TestBot {
+        let t = TestBot::with_config(|config| {
+            config.telegram.chats.residential = vec![ChatId(CHAT)];
+        });
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        t.add_resident(3, "carol", "Carol");
+        diesel::update(schema::residents::table)
+            .filter(schema::residents::tg_id.eq(DbUserId::from(UserId(3))))
+            .set(schema::residents::end_date.eq(diesel::dsl::now))
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        diesel::insert_into(schema::tg_users_in_chats::table)
+            .values((
+                schema::tg_users_in_chats::chat_id
+                    .eq(DbChatId::from(ChatId(CHAT))),
+                schema::tg_users_in_chats::user_id
+                    .eq(DbUserId::from(UserId(3))),
+                schema::tg_users_in_chats::seen.eq(true),
+            ))
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        t.env.cache.residents.invalidate();
+        t
+    }
+
+    async fn press(t: &TestBot, from: u64, report: &Value, data: &str) {
+        t.telegram.clear();
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(&testing::user_json(from, "User"), report, data),
+        )
+        .await;
+    }
+
+    fn answer(t: &TestBot) -> Value {
+        t.telegram.calls("answerCallbackQuery")[0]["text"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() {
+        let t = test_bot();
+        // Alice has left the chat; Bob and ex-resident Carol are members.
+        t.telegram.respond(
+            "getChatMember",
+            json!({ "status": "left", "user": testing::user_json(1, "Alice") }),
+        );
+        let report_to = ThreadIdPair {
+            chat: ChatId(CHAT),
+            thread: ThreadId(MessageId(123)),
+        };
+        reconcile(&t.env, &t.bot, report_to).await.unwrap();
+
+        let report = &t.telegram.calls("sendMessage")[0];
+        let text = report["text"].as_str().unwrap();
+        assert!(text.contains("Resident"), "{text}");
+        assert!(text.contains("Ex-resident"), "{text}");
+        assert!(!text.contains("Bob"), "{text}");
+        let buttons = &report["reply_markup"]["inline_keyboard"];
+        assert_eq!(buttons[0][0]["callback_data"], format!("mr:inv:{CHAT}:1"));
+        assert_eq!(buttons[0][1]["callback_data"], format!("mr:end:{CHAT}:1"));
+        assert_eq!(buttons[1][0]["callback_data"], format!("mr:kick:{CHAT}:3"));
+
+        // No report if the membership is consistent.
+        t.telegram.clear();
+        diesel::delete(schema::tg_users_in_chats::table)
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        reconcile(&t.env, &t.bot, report_to).await.unwrap();
+        assert!(t.telegram.calls("sendMessage").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_failures_and_long_reports() {
+        let t = test_bot();
+        for id in 10..30 {
+            t.add_resident(id, &format!("user{id}"), "Former");
+            diesel::insert_into(schema::tg_users_in_chats::table)
+                .values((
+                    schema::tg_users_in_chats::chat_id
+                        .eq(DbChatId::from(ChatId(CHAT))),
+                    schema::tg_users_in_chats::user_id
+                        .eq(DbUserId::from(UserId(id))),
+                    schema::tg_users_in_chats::seen.eq(true),
+                ))
+                .execute(&mut *t.env.conn())
+                .unwrap();
+        }
+        diesel::update(schema::residents::table)
+            .filter(schema::residents::tg_id.ge(DbUserId::from(UserId(10))))
+            .set(schema::residents::end_date.eq(diesel::dsl::now))
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        t.env.cache.residents.invalidate();
+        // The check of Alice fails, the rest are still checked.
+        t.telegram.fail("getChatMember", "Bad Request: chat not found");
+        let report_to = ThreadIdPair {
+            chat: ChatId(CHAT),
+            thread: ThreadId(MessageId(123)),
+        };
+        reconcile(&t.env, &t.bot, report_to).await.unwrap();
+
+        // 21 ex-residents are split into three messages.
+        let reports = t.telegram.calls("sendMessage");
+        assert_eq!(reports.len(), 3);
+        let text = reports[0]["text"].as_str().unwrap();
+        assert!(text.contains("(1/3)"), "{text}");
+        assert!(!text.contains("• Resident"), "{text}");
+        for report in &reports {
+            let buttons =
+                report["reply_markup"]["inline_keyboard"].as_array().unwrap();
+            assert!(buttons.len() <= ISSUES_PER_MESSAGE);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fix_buttons() {
+        let t = test_bot();
+        let report_to = ThreadIdPair {
+            chat: ChatId(CHAT),
+            thread: ThreadId(MessageId(123)),
+        };
+        reconcile(&t.env, &t.bot, report_to).await.unwrap();
+        let report = t.telegram.results("sendMessage").pop().unwrap();
+
+        press(&t, 1, &report, &format!("mr:end:{CHAT}:1")).await;
+        assert_eq!(answer(&t), "You must be an admin to do this.");
+        assert!(t.env.is_resident(UserId(1)));
+
+        press(&t, ADMIN, &report, &format!("mr:end:{CHAT}:1")).await;
+        assert_eq!(answer(&t), "Residency ended.");
+        assert!(!t.env.is_resident(UserId(1)));
+        press(&t, ADMIN, &report, &format!("mr:end:{CHAT}:1")).await;
+        assert_eq!(answer(&t), "This user is not a resident anymore.");
+
+        press(&t, ADMIN, &report, &format!("mr:kick:{CHAT}:3")).await;
+        assert_eq!(answer(&t), "Removed from the chat.");
+        assert_eq!(t.telegram.calls("banChatMember")[0]["user_id"], 3);
+        assert_eq!(t.telegram.calls("unbanChatMember")[0]["user_id"], 3);
+    }
+}
@@ -0,0 +1,251 @@
+//! Mention groups: `/ping keyholders <text>` mentions everyone holding the
+//! `keyholder` role, and `/ping residents <text>` mentions all residents.
+//! In chats with the `mention_groups` flag, writing `@keyholders` in a
+//! message does the same.  Mentions are split across several messages, and
+//! each group can be pinged once per [`COOLDOWN`] in a chat.
+//!
+//! **Scope**: `/ping` command, available to residents in groups; messages of
+//! residents in chats with the `mention_groups` flag.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::DbUserId;
+use crate::modules::roles;
+use crate::utils::{format_to, html, BotExt as _};
+use crate::{models, schema};
+
+/// How often a group can be pinged in a chat.
+const COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// Telegram notifies only a few users mentioned in a single message.
+const MENTIONS_PER_MESSAGE: usize = 5;
+
+/// The group of all residents, other groups are user roles.
+const RESIDENTS: &str = "residents";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "mention a group of users: <code>/ping \
+                             keyholders text</code>, groups are \
+                             <code>residents</code> and user roles.")]
+    #[custom(resident = true, in_private = false)]
+    Ping(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_ping)
+}
+
+async fn cmd_ping(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Ping(args): Commands,
+) -> Result<()> {
+    let args = args.trim();
+    let (group, text) =
+        args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let group = group.trim_start_matches('@').to_lowercase();
+    if group.is_empty() {
+        bot.reply_message(&msg, "Usage: /ping <group> [text]").await?;
+        return Ok(());
+    }
+    if !ping(&bot, &env, &msg, &group, text.trim()).await? {
+        bot.reply_message(&msg, format!("Unknown group: {group}.")).await?;
+    }
+    Ok(())
+}
+
+/// Expand mentions of groups, e.g. `@keyholders`, in chats with the
+/// `mention_groups` flag.  Mentions of unknown groups, e.g. of usernames,
+/// are ignored.
+pub async fn inspect_message(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(text) = msg.text().or_else(|| msg.caption()) else {
+        return Ok(());
+    };
+    // `/ping` is handled by the command handler.
+    if msg.chat.is_private() || text.starts_with('/') {
+        return Ok(());
+    }
+    let groups = mentioned_groups(text);
+    if groups.is_empty()
+        || !models::mention_groups.enabled(&env, msg.chat.id)
+        || !env.is_resident(from.id)
+    {
+        return Ok(());
+    }
+    for group in groups {
+        ping(&bot, &env, &msg, &group, "").await?;
+    }
+    Ok(())
+}
+
+/// Lowercase names of mentioned groups, without the `@`.
+fn mentioned_groups(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|word| {
+            word.trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_')
+                .to_lowercase()
+        })
+        .filter(|group| !group.is_empty())
+        .unique()
+        .collect()
+}
+
+/// Members of the group, or `None` if there is no such group.  A role is
+/// also accepted in plural, e.g. `keyholders` for `keyholder`.
+fn group_members(
+    env: &BotEnv,
+    group: &str,
+) -> QueryResult<Option<Vec<UserId>>> {
+    if group == RESIDENTS {
+        let residents = env.cache.residents(&env.conn)?;
+        return Ok(Some(
+            residents.iter().copied().sorted_by_key(|u| u.0).collect(),
+        ));
+    }
+    for role in [Some(group), group.strip_suffix('s')].into_iter().flatten() {
+        let users = roles::users_with_role(env, role)?;
+        if !users.is_empty() {
+            return Ok(Some(users));
+        }
+    }
+    Ok(None)
+}
+
+/// Mention members of the group in replies to the message.  Returns `false`
+/// if there is no such group.
+async fn ping(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    group: &str,
+    text: &str,
+) -> Result<bool> {
+    let Some(from) = &msg.from else { return Ok(true) };
+    let Some(members) = group_members(env, group)? else { return Ok(false) };
+    let members = members.into_iter().filter(|&u| u != from.id).collect_vec();
+    if members.is_empty() {
+        bot.reply_message(msg, format!("No one else is in @{group}.")).await?;
+        return Ok(true);
+    }
+
+    let key = format!("ping:{}:{group}", msg.chat.id);
+    if let Err(left) = env.cooldowns.start(&key, COOLDOWN) {
+        // Round up, so that it's never "try again in 0m".
+        let minutes = left.as_secs() / 60 + 1;
+        bot.reply_message(
+            msg,
+            format!(
+                "⏳ @{group} was pinged just now, try again in {minutes}m."
+            ),
+        )
+        .await?;
+        return Ok(true);
+    }
+
+    let names: Vec<(DbUserId, String)> = schema::tg_users::table
+        .filter(
+            schema::tg_users::id
+                .eq_any(members.iter().map(|&u| DbUserId::from(u))),
+        )
+        .select((schema::tg_users::id, schema::tg_users::first_name))
+        .load(&mut *env.conn())?;
+    let mentions = members
+        .iter()
+        .map(|&user| {
+            let name = names
+                .iter()
+                .find(|(id, _)| UserId::from(*id) == user)
+                .map_or("user", |(_, name)| name.as_str());
+            html::mention(user, name)
+        })
+        .collect_vec();
+
+    for (i, chunk) in mentions.chunks(MENTIONS_PER_MESSAGE).enumerate() {
+        let mut message = String::new();
+        if i == 0 {
+            format_to!(
+                message,
+                "📣 {} → <b>@{}</b>",
+                html::user_mention(from),
+                html::escape(group),
+            );
+            if !text.is_empty() {
+                format_to!(message, ": {}", html::escape(text));
+            }
+            message.push('\n');
+        }
+        message.push_str(&chunk.join(", "));
+        bot.reply_message(msg, message)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    #[test]
+    fn test_mentioned_groups() {
+        assert_eq!(
+            mentioned_groups("@Keyholders, door? cc @residents @keyholders"),
+            ["keyholders", "residents"],
+        );
+        assert!(mentioned_groups("mail@example.com @").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let t = TestBot::new();
+        for id in 1..=7 {
+            t.add_resident(id, &format!("user{id}"), &format!("User {id}"));
+        }
+        let alice = testing::user_json(1, "Alice");
+        let ping = |text: &str| testing::message(CHAT, None, &alice, text);
+
+        t.dispatch(&command_handler(), ping("/ping nobodies hi")).await;
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent[0]["text"], "Unknown group: nobodies.");
+
+        t.telegram.clear();
+        t.dispatch(&command_handler(), ping("/ping residents lunch")).await;
+        let sent = t.telegram.calls("sendMessage");
+        // 6 residents besides the sender, 5 per message.
+        assert_eq!(sent.len(), 2);
+        let first = sent[0]["text"].as_str().unwrap();
+        assert!(first.contains("lunch"), "{first}");
+        // The sender and 5 members.
+        assert_eq!(first.matches("tg://user").count(), 6, "{first}");
+        assert!(sent[1]["text"].as_str().unwrap().contains("tg://user?id=7"));
+
+        t.telegram.clear();
+        t.dispatch(&command_handler(), ping("/ping residents again")).await;
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0]["text"].as_str().unwrap().starts_with('⏳'));
+    }
+}
@@ -0,0 +1,339 @@
+//! Auto-delete of old messages in topics, e.g. keep the door log topic for a
+//! day.
+//!
+//! An admin sets the policy of a topic with `/ttl 1d` to delete the bot's
+//! messages after a day, or with `/ttl 1d all` to delete messages of users
+//! too.  Messages are recorded as they are sent, and deleted when they
+//! expire; messages sent before the policy was set are kept.
+//!
+//! The Bot API refuses to delete messages older than 48 hours, even for
+//! admins, so longer TTLs are rejected.  Deleting messages of users also
+//! requires the bot to be an admin with the right to delete messages.
+//!
+//! **Scope**: `/ttl` command, available to admins in groups; messages sent
+//! by the bot, and messages in topics with a policy that includes users.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::MessageId;
+use tokio::select;
+use tokio::sync::mpsc;
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::{DbChatId, DbMessageId, DbThreadId, DbUserId};
+use crate::utils::{parse_duration, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+/// How many expired messages are deleted at once, to stay within the Bot API
+/// rate limits.
+const DELETE_BATCH: i64 = 50;
+
+/// The longest TTL that can be set: bots can't delete messages older than
+/// 48 hours.  A minute is left for the deletion task to catch up.
+const MAX_TTL: Duration = Duration::from_secs(48 * 60 * 60 - 60);
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "auto-delete messages in this topic: \
+                             <code>/ttl 7d [all]</code> or \
+                             <code>/ttl off</code>.")]
+    #[custom(admin = true, in_private = false)]
+    Ttl(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_ttl)
+}
+
+async fn cmd_ttl(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Ttl(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let chat = DbChatId::from(msg.chat.id);
+    let thread = DbThreadId::from(msg.thread_id);
+    let args = args.split_whitespace().collect::<Vec<_>>();
+
+    let text = match args[..] {
+        [] => match env.cache.ttl_policies(&env.conn)?.get(&(chat, thread)) {
+            None => "Messages in this topic are kept forever.".to_string(),
+            Some(policy) => describe(policy),
+        },
+        ["off"] => {
+            diesel::delete(schema::message_ttl_policies::table)
+                .filter(schema::message_ttl_policies::chat_id.eq(chat))
+                .filter(schema::message_ttl_policies::thread_id.eq(thread))
+                .execute(&mut *env.conn())?;
+            env.cache.ttl_policies.invalidate();
+            "Messages in this topic are kept forever now.  Messages recorded \
+             before will still be deleted."
+                .to_string()
+        }
+        [ttl] | [ttl, "all"] => {
+            let Some(ttl) = parse_duration(ttl).filter(|d| d.as_secs() > 0)
+            else {
+                bot.reply_message(
+                    &msg,
+                    "Usage: /ttl <duration, e.g. 1d> [all]",
+                )
+                .await?;
+                return Ok(());
+            };
+            if ttl > MAX_TTL {
+                bot.reply_message(
+                    &msg,
+                    "Telegram doesn't let bots delete messages older than 48 \
+                     hours, so the TTL must be shorter than that.",
+                )
+                .await?;
+                return Ok(());
+            }
+            let policy = models::MessageTtlPolicy {
+                chat_id: chat,
+                thread_id: thread,
+                ttl_seconds: i64::try_from(ttl.as_secs())?,
+                include_others: args.len() == 2,
+                set_by: DbUserId::from(from.id),
+                set_at: chrono::Utc::now().naive_utc(),
+            };
+            diesel::replace_into(schema::message_ttl_policies::table)
+                .values((
+                    schema::message_ttl_policies::chat_id.eq(policy.chat_id),
+                    schema::message_ttl_policies::thread_id
+                        .eq(policy.thread_id),
+                    schema::message_ttl_policies::ttl_seconds
+                        .eq(policy.ttl_seconds),
+                    schema::message_ttl_policies::include_others
+                        .eq(policy.include_others),
+                    schema::message_ttl_policies::set_by.eq(policy.set_by),
+                    schema::message_ttl_policies::set_at.eq(policy.set_at),
+                ))
+                .execute(&mut *env.conn())?;
+            env.cache.ttl_policies.invalidate();
+            crate::modules::audit::record(
+                &mut env.conn(),
+                Some(from.id),
+                "message_ttl_set",
+                &serde_json::json!({
+                    "chat_id": msg.chat.id.0,
+                    "thread_id": msg.thread_id.map(|t| t.0 .0),
+                    "ttl_seconds": policy.ttl_seconds,
+                    "include_others": policy.include_others,
+                }),
+            )
+            .log_error("message_ttl: audit");
+            describe(&policy)
+        }
+        _ => "Usage: /ttl <duration, e.g. 1d> [all], or /ttl off".to_string(),
+    };
+    bot.reply_message(&msg, text).await?;
+    Ok(())
+}
+
+fn describe(policy: &models::MessageTtlPolicy) -> String {
+    let whose = if policy.include_others { "All" } else { "Bot" };
+    format!(
+        "{whose} messages in this topic are deleted after {}.",
+        format_ttl(policy.ttl_seconds),
+    )
+}
+
+/// Format the TTL in the largest whole unit, e.g. `7d` or `36h`.
+fn format_ttl(seconds: i64) -> String {
+    [(86_400, "d"), (3600, "h"), (60, "m")]
+        .into_iter()
+        .find(|(unit, _)| seconds % unit == 0)
+        .map_or_else(
+            || format!("{seconds}s"),
+            |(unit, suffix)| format!("{}{suffix}", seconds / unit),
+        )
+}
+
+/// Record messages of users in topics with a policy that includes them.
+pub fn inspect_message(env: Arc<BotEnv>, msg: Message) {
+    record(&env, &msg, false).log_error("message_ttl: record message");
+}
+
+/// Record the message if its topic has a policy, so that it's deleted when
+/// it expires.
+fn record(env: &BotEnv, msg: &Message, own: bool) -> Result<()> {
+    let policies = env.cache.ttl_policies(&env.conn)?;
+    let key = (DbChatId::from(msg.chat.id), DbThreadId::from(msg.thread_id));
+    let Some(policy) = policies.get(&key) else { return Ok(()) };
+    if !own && !policy.include_others {
+        return Ok(());
+    }
+    let delete_at =
+        msg.date.naive_utc() + chrono::Duration::seconds(policy.ttl_seconds);
+    diesel::insert_or_ignore_into(schema::expiring_messages::table)
+        .values((
+            schema::expiring_messages::chat_id.eq(key.0),
+            schema::expiring_messages::message_id.eq(DbMessageId::from(msg.id)),
+            schema::expiring_messages::delete_at.eq(delete_at),
+        ))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+/// Record messages sent by the bot, and delete expired messages.
+pub async fn task(
+    env: Arc<BotEnv>,
+    bot: Bot,
+    mut sent_messages: mpsc::UnboundedReceiver<serde_json::Value>,
+    shutdown: CancellationToken,
+) {
+    let mut tick = interval(Duration::from_secs(60));
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            message = sent_messages.recv() => {
+                let Some(message) = message else { break };
+                serde_json::from_value::<Message>(message)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|msg| record(&env, &msg, true))
+                    .log_error("message_ttl: record sent message");
+            }
+            _ = tick.tick() => {
+                delete_expired(&env, &bot)
+                    .await
+                    .log_error("message_ttl: delete expired");
+            }
+        }
+    }
+}
+
+async fn delete_expired(env: &BotEnv, bot: &Bot) -> Result<()> {
+    let expired: Vec<(DbChatId, DbMessageId)> =
+        schema::expiring_messages::table
+            .filter(
+                schema::expiring_messages::delete_at
+                    .le(chrono::Utc::now().naive_utc()),
+            )
+            .order(schema::expiring_messages::delete_at)
+            .limit(DELETE_BATCH)
+            .select((
+                schema::expiring_messages::chat_id,
+                schema::expiring_messages::message_id,
+            ))
+            .load(&mut *env.conn())?;
+    for (chat, message) in expired {
+        // Deleting fails if the message is already gone or too old, either
+        // way there is nothing left to do.
+        if let Err(e) = bot.delete_message(chat, MessageId::from(message)).await
+        {
+            log::warn!("message_ttl: failed to delete a message: {e}");
+        }
+        diesel::delete(schema::expiring_messages::table)
+            .filter(schema::expiring_messages::chat_id.eq(chat))
+            .filter(schema::expiring_messages::message_id.eq(message))
+            .execute(&mut *env.conn())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    #[test]
+    fn test_format_ttl() {
+        assert_eq!(format_ttl(7 * 86_400), "7d");
+        assert_eq!(format_ttl(36 * 3600), "36h");
+        assert_eq!(format_ttl(90), "90s");
+    }
+
+    #[tokio::test]
+    async fn test_message_ttl() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let admin = t.env.config.telegram.admins[0];
+        let admin = testing::user_json(admin.0, "Admin");
+        let alice = testing::user_json(1, "Alice");
+        let message = |from, text| {
+            let update: Update = serde_json::from_value(testing::message(
+                CHAT,
+                Some(5),
+                from,
+                text,
+            ))
+            .unwrap();
+            let teloxide::types::UpdateKind::Message(msg) = update.kind else {
+                unreachable!()
+            };
+            msg
+        };
+        let expiring = || -> i64 {
+            schema::expiring_messages::table
+                .count()
+                .get_result(&mut *t.env.conn())
+                .unwrap()
+        };
+
+        t.dispatch(
+            &command_handler(),
+            testing::message(CHAT, Some(5), &admin, "/ttl 1s"),
+        )
+        .await;
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(
+            sent[0]["text"],
+            "Bot messages in this topic are deleted after 1s."
+        );
+
+        // Too old to be deleted by then.
+        t.telegram.clear();
+        t.dispatch(
+            &command_handler(),
+            testing::message(CHAT, Some(5), &admin, "/ttl 2d"),
+        )
+        .await;
+        let sent = t.telegram.calls("sendMessage");
+        assert!(sent[0]["text"].as_str().unwrap().contains("48 hours"));
+        let ttl: i64 = schema::message_ttl_policies::table
+            .select(schema::message_ttl_policies::ttl_seconds)
+            .first(&mut *t.env.conn())
+            .unwrap();
+        assert_eq!(ttl, 1, "the previous policy is kept");
+
+        // Messages of users are kept unless the policy includes them.
+        inspect_message(Arc::clone(&t.env), message(&alice, "hi"));
+        assert_eq!(expiring(), 0);
+        record(&t.env, &message(&admin, "door opened"), true).unwrap();
+        assert_eq!(expiring(), 1);
+
+        t.dispatch(
+            &command_handler(),
+            testing::message(CHAT, Some(5), &admin, "/ttl 1s all"),
+        )
+        .await;
+        inspect_message(Arc::clone(&t.env), message(&alice, "hi"));
+        assert_eq!(expiring(), 2);
+
+        diesel::update(schema::expiring_messages::table)
+            .set(
+                schema::expiring_messages::delete_at
+                    .eq(chrono::NaiveDateTime::default()),
+            )
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        delete_expired(&t.env, &t.bot).await.unwrap();
+        assert_eq!(t.telegram.calls("deleteMessage").len(), 2);
+        assert_eq!(expiring(), 0);
+    }
+}
@@ -0,0 +1,454 @@
+//! Moderation assist for public chats.
+//!
+//! Messages in the [`telegram.moderation.chats`] are checked with the
+//! `OpenAI` moderation endpoint and the local [`telegram.moderation.rules`].
+//! Flagged messages are relayed to the [`telegram.moderation.thread`] with
+//! buttons to delete the message, ban the sender, or ignore the report.
+//! Users can appeal the decision with `/appeal` in private messages; the
+//! decisions and appeals are listed with `/modlog`.
+//!
+//! **Scope**: messages in [`telegram.moderation.chats`]; `/appeal` command,
+//! available to everyone in private messages; `/modlog` command, available to
+//! admins.
+//!
+//! [`telegram.moderation.chats`]: crate::config::Moderation::chats
+//! [`telegram.moderation.rules`]: crate::config::Moderation::rules
+//! [`telegram.moderation.thread`]: crate::config::Moderation::thread
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use regex::Regex;
+use tap::Tap as _;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode,
+};
+
+use crate::common::{
+    filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::config::Moderation;
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::utils::{
+    format_to, html, write_message_link, BotExt as _, ResultExt as _,
+};
+use crate::{models, schema};
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_DELETED: &str = "deleted";
+const STATUS_BANNED: &str = "banned";
+const STATUS_IGNORED: &str = "ignored";
+
+const MODERATION_URL: &str = "https://api.openai.com/v1/moderations";
+
+/// Maximum number of characters of the flagged message shown to moderators.
+const QUOTE_CHARS: usize = 1000;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "appeal a moderation decision: \
+                             <code>/appeal &lt;explanation&gt;</code>.")]
+    #[custom(in_group = false)]
+    Appeal(String),
+
+    #[command(description = "show recent moderation decisions and appeals.")]
+    #[custom(admin = true)]
+    Modlog,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(handle_command)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+/// Check messages in the moderated chats and report the flagged ones.
+pub async fn inspect_message(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.moderation else { return Ok(()) };
+    if !conf.chats.contains(&msg.chat.id)
+        || !models::moderation.enabled(&env, msg.chat.id)
+    {
+        return Ok(());
+    }
+    let Some(from) = &msg.from else { return Ok(()) };
+    if from.is_bot || env.config.telegram.admins.contains(&from.id) {
+        return Ok(());
+    }
+    let Some(text) = msg.text().or_else(|| msg.caption()) else {
+        return Ok(());
+    };
+
+    let reason = match match_rules(&conf.rules, text) {
+        Some(reason) => reason,
+        None if env.config.services.openai.disable => return Ok(()),
+        None => match check_openai(&env, text).await? {
+            Some(reason) => reason,
+            None => return Ok(()),
+        },
+    };
+
+    let rowid = env.transaction(|conn| {
+        diesel::insert_into(schema::moderation_cases::table)
+            .values((
+                schema::moderation_cases::chat_id
+                    .eq(DbChatId::from(msg.chat.id)),
+                schema::moderation_cases::message_id
+                    .eq(DbMessageId::from(msg.id)),
+                schema::moderation_cases::user_id.eq(DbUserId::from(from.id)),
+                schema::moderation_cases::text.eq(text),
+                schema::moderation_cases::reason.eq(&reason),
+                schema::moderation_cases::status.eq(STATUS_PENDING),
+                schema::moderation_cases::created_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        schema::moderation_cases::table
+            .select(schema::moderation_cases::rowid)
+            .order(schema::moderation_cases::rowid.desc())
+            .first::<i32>(conn)
+    })?;
+
+    let mut report = format!("🚩 <b>Flagged message #{rowid}</b> from ");
+    format_user(&mut report, from.id, &tg_user(from), true);
+    report.push_str(" in ");
+    write_message_link(&mut report, msg.chat.id, msg.id);
+    format_to!(
+        report,
+        "{}</a>\nReason: {}\n\n{}",
+        html::escape(msg.chat.title().unwrap_or("chat")),
+        html::escape(&reason),
+        html::quote_message(&msg, QUOTE_CHARS).unwrap_or_default(),
+    );
+    bot.send_message(conf.thread.chat, report)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(InlineKeyboardMarkup::new([[
+            InlineKeyboardButton::callback(
+                "🗑 Delete",
+                format!("md:{STATUS_DELETED}:{rowid}"),
+            ),
+            InlineKeyboardButton::callback(
+                "⛔ Ban",
+                format!("md:{STATUS_BANNED}:{rowid}"),
+            ),
+            InlineKeyboardButton::callback(
+                "👌 Ignore",
+                format!("md:{STATUS_IGNORED}:{rowid}"),
+            ),
+        ]]))
+        .await?;
+    Ok(())
+}
+
+fn tg_user(user: &teloxide::types::User) -> models::TgUser {
+    models::TgUser {
+        id: user.id.into(),
+        username: user.username.clone(),
+        first_name: user.first_name.clone(),
+        last_name: user.last_name.clone(),
+    }
+}
+
+/// Match the lowercased text against the local rules.  Returns the reason if
+/// any rule matches.
+fn match_rules(rules: &[String], text: &str) -> Option<String> {
+    let text = text.to_lowercase();
+    rules.iter().find_map(|rule| {
+        let regex = Regex::new(rule);
+        regex.log_error("moderation: invalid rule");
+        regex.ok()?.is_match(&text).then(|| format!("rule {rule}"))
+    })
+}
+
+/// Check the text with the `OpenAI` moderation endpoint.  Returns the reason
+/// if the text is flagged.
+async fn check_openai(env: &BotEnv, text: &str) -> Result<Option<String>> {
+    let response: serde_json::Value = env
+        .reqwest_client
+        .post(MODERATION_URL)
+        .bearer_auth(&env.config.services.openai.api_key)
+        .json(&serde_json::json!({ "input": text }))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .tap(|r| {
+            crate::metrics::update_service(&env.health, "openai", r.is_ok())
+        })?
+        .json()
+        .await?;
+    Ok(flagged_categories(&response["results"][0]))
+}
+
+fn flagged_categories(result: &serde_json::Value) -> Option<String> {
+    if !result["flagged"].as_bool()? {
+        return None;
+    }
+    let categories = result["categories"]
+        .as_object()
+        .map(|c| {
+            c.iter()
+                .filter(|(_, v)| v.as_bool() == Some(true))
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+    Some(format!("OpenAI: {categories}"))
+}
+
+async fn handle_command(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    command: Commands,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.moderation else { return Ok(()) };
+    match command {
+        Commands::Appeal(text) => {
+            cmd_appeal(&bot, &env, conf, &msg, text.trim()).await
+        }
+        Commands::Modlog => cmd_modlog(&bot, &env, &msg).await,
+    }
+}
+
+async fn cmd_appeal(
+    bot: &Bot,
+    env: &BotEnv,
+    conf: &Moderation,
+    msg: &Message,
+    text: &str,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    if text.is_empty() {
+        bot.reply_message(msg, "Usage: /appeal <explanation>").await?;
+        return Ok(());
+    }
+
+    let case = env.transaction(|conn| {
+        let case = schema::moderation_cases::table
+            .filter(
+                schema::moderation_cases::user_id.eq(DbUserId::from(from.id)),
+            )
+            .filter(
+                schema::moderation_cases::status
+                    .eq_any([STATUS_DELETED, STATUS_BANNED]),
+            )
+            .filter(schema::moderation_cases::appeal.is_null())
+            .order(schema::moderation_cases::rowid.desc())
+            .select(models::ModerationCase::as_select())
+            .first(conn)
+            .optional()?;
+        if let Some(case) = &case {
+            diesel::update(schema::moderation_cases::table)
+                .filter(schema::moderation_cases::rowid.eq(case.rowid))
+                .set(schema::moderation_cases::appeal.eq(text))
+                .execute(conn)?;
+        }
+        Ok(case)
+    })?;
+    let Some(case) = case else {
+        bot.reply_message(msg, "You have no moderation decisions to appeal.")
+            .await?;
+        return Ok(());
+    };
+
+    let mut report = format!("📨 <b>Appeal on #{}</b> from ", case.rowid);
+    format_user(&mut report, from.id, &tg_user(from), true);
+    format_to!(
+        report,
+        "\nDecision: {}\nReason: {}\n\n{}",
+        case.status,
+        html::escape(&case.reason),
+        html::escape(text),
+    );
+    bot.send_message(conf.thread.chat, report)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    bot.reply_message(msg, "Your appeal is sent to the moderators.").await?;
+    Ok(())
+}
+
+async fn cmd_modlog(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<()> {
+    let cases: Vec<(models::ModerationCase, Option<models::TgUser>)> =
+        schema::moderation_cases::table
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::tg_users::id
+                        .eq(schema::moderation_cases::user_id)),
+            )
+            .filter(schema::moderation_cases::status.ne(STATUS_PENDING))
+            .order(schema::moderation_cases::rowid.desc())
+            .limit(20)
+            .select((
+                models::ModerationCase::as_select(),
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+    if cases.is_empty() {
+        bot.reply_message(msg, "No moderation decisions yet.").await?;
+        return Ok(());
+    }
+
+    let mut text = String::from("<b>Recent moderation decisions</b>\n");
+    for (case, user) in &cases {
+        format_to!(
+            text,
+            "\n#{} {} ",
+            case.rowid,
+            case.created_at.format("%Y-%m-%d")
+        );
+        format_user(&mut text, case.user_id, user.as_ref(), true);
+        format_to!(text, ": {} ({})", case.status, html::escape(&case.reason));
+        if let Some(appeal) = &case.appeal {
+            format_to!(text, "\n  Appeal: {}", html::escape(appeal));
+        }
+    }
+    bot.reply_message(msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<(&'static str, i32)> {
+    let (status, rowid) =
+        callback.data.as_ref()?.strip_prefix("md:")?.split_once(':')?;
+    let status = [STATUS_DELETED, STATUS_BANNED, STATUS_IGNORED]
+        .into_iter()
+        .find(|s| *s == status)?;
+    Some((status, rowid.parse().ok()?))
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    (status, rowid): (&'static str, i32),
+) -> Result<()> {
+    if !env.config.telegram.admins.contains(&callback.from.id) {
+        bot.answer_callback_query(&callback.id)
+            .text("Only moderators can do this.")
+            .await?;
+        return Ok(());
+    }
+    let result = env.transaction(|conn| {
+        let case = schema::moderation_cases::table
+            .filter(schema::moderation_cases::rowid.eq(rowid))
+            .select(models::ModerationCase::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(case) = case else {
+            return Ok(Err("This case no longer exists."));
+        };
+        if case.status != STATUS_PENDING {
+            return Ok(Err("This case is already resolved."));
+        }
+        diesel::update(schema::moderation_cases::table)
+            .filter(schema::moderation_cases::rowid.eq(rowid))
+            .set((
+                schema::moderation_cases::status.eq(status),
+                schema::moderation_cases::moderator
+                    .eq(DbUserId::from(callback.from.id)),
+                schema::moderation_cases::resolved_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        if status != STATUS_IGNORED {
+            crate::modules::audit::record(
+                conn,
+                Some(callback.from.id),
+                if status == STATUS_BANNED {
+                    "moderation_ban"
+                } else {
+                    "moderation_delete"
+                },
+                &serde_json::json!({
+                    "case": rowid,
+                    "chat_id": ChatId::from(case.chat_id).0,
+                    "user_id": UserId::from(case.user_id).0,
+                    "message_id": MessageId::from(case.message_id).0,
+                }),
+            )
+            .log_error("moderation: audit");
+        }
+        Ok(Ok(case))
+    })?;
+    let case = match result {
+        Ok(case) => case,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+
+    let chat_id = ChatId::from(case.chat_id);
+    if status == STATUS_BANNED {
+        bot.ban_chat_member(chat_id, UserId::from(case.user_id))
+            .await
+            .log_error("moderation: ban user");
+    }
+    if status != STATUS_IGNORED {
+        bot.delete_message(chat_id, case.message_id.into())
+            .await
+            .log_error("moderation: delete message");
+    }
+    bot.answer_callback_query(&callback.id).text("Done.").await?;
+
+    let Some(message) = &callback.message else { return Ok(()) };
+    bot.edit_message_reply_markup(message.chat.id, message.id)
+        .await
+        .log_error("moderation: remove buttons");
+    let mut text = format!("Case #{rowid}: {status} by ");
+    format_user(&mut text, callback.from.id, &tg_user(&callback.from), true);
+    text.push('.');
+    bot.send_message(message.chat.id, text)
+        .reply_to_message_id(message.id)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await
+        .log_error("moderation: report decision");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_rules() {
+        let rules = vec!["free +(crypto|bitcoin)".to_string(), "(".to_string()];
+        assert_eq!(
+            match_rules(&rules, "Get FREE  Bitcoin now"),
+            Some("rule free +(crypto|bitcoin)".to_string())
+        );
+        assert_eq!(match_rules(&rules, "free pizza at the space"), None);
+    }
+
+    #[test]
+    fn test_flagged_categories() {
+        let result = serde_json::json!({
+            "flagged": true,
+            "categories": { "hate": false, "harassment": true },
+        });
+        assert_eq!(
+            flagged_categories(&result),
+            Some("OpenAI: harassment".to_string())
+        );
+        let result = serde_json::json!({ "flagged": false });
+        assert_eq!(flagged_categories(&result), None);
+    }
+}
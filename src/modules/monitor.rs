@@ -0,0 +1,226 @@
+//! Uptime monitoring of space services.
+//!
+//! The [`telegram.monitor.services`] are checked every
+//! [`telegram.monitor.interval_seconds`] with an HTTP request or a TCP
+//! connection.  State changes are posted to the [`telegram.monitor.thread`],
+//! including the downtime duration when a service is back up.  The current
+//! state is shown by `/status services` and the `/status` HTTP endpoint.
+//!
+//! **Scope**: background task; `/status services` command.
+//!
+//! [`telegram.monitor.services`]: crate::config::Monitor::services
+//! [`telegram.monitor.interval_seconds`]: crate::config::Monitor::interval_seconds
+//! [`telegram.monitor.thread`]: crate::config::Monitor::thread
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
+
+use crate::common::BotEnv;
+use crate::config::MonitoredService;
+use crate::utils::{format_to, html, ResultExt as _};
+use crate::{models, schema};
+
+/// Timeout of a single check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before re-checking a failed service, to avoid alerts on blips.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.monitor else { return };
+
+    // Forget services removed from the config.
+    diesel::delete(schema::service_status::table)
+        .filter(
+            schema::service_status::name
+                .ne_all(conf.services.iter().map(|s| &s.name)),
+        )
+        .execute(&mut *env.conn())
+        .log_error("monitor: clean up");
+
+    loop {
+        let results = futures::future::join_all(
+            conf.services.iter().map(|s| check_with_retry(&env, s)),
+        )
+        .await;
+        for (service, result) in conf.services.iter().zip(results) {
+            if let Err(e) = update(&env, &bot, service, result).await {
+                log::error!("monitor: failed to update {}: {e}", service.name);
+            }
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(conf.interval_seconds)) => {}
+        }
+    }
+}
+
+async fn check_with_retry(
+    env: &BotEnv,
+    service: &MonitoredService,
+) -> Result<(), String> {
+    if check(env, &service.target).await.is_ok() {
+        return Ok(());
+    }
+    sleep(RETRY_DELAY).await;
+    check(env, &service.target).await
+}
+
+/// Check the target, either an HTTP(S) URL or `host:port`.
+async fn check(env: &BotEnv, target: &str) -> Result<(), String> {
+    if target.starts_with("http://") || target.starts_with("https://") {
+        env.reqwest_client
+            .get(target)
+            .timeout(CHECK_TIMEOUT)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map(|_| ())
+            .map_err(|e| e.without_url().to_string())
+    } else {
+        match timeout(CHECK_TIMEOUT, tokio::net::TcpStream::connect(target))
+            .await
+        {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("connection timed out".to_string()),
+        }
+    }
+}
+
+/// Store the check result and post an alert if the state has changed.
+async fn update(
+    env: &BotEnv,
+    bot: &Bot,
+    service: &MonitoredService,
+    result: Result<(), String>,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.monitor else { return Ok(()) };
+    let now = chrono::Utc::now().naive_utc();
+    let up = result.is_ok();
+    let error = result.err();
+    let prev: Option<models::ServiceStatus> = schema::service_status::table
+        .filter(schema::service_status::name.eq(&service.name))
+        .select(models::ServiceStatus::as_select())
+        .first(&mut *env.conn())
+        .optional()?;
+    let changed = prev.as_ref().map_or(!up, |p| p.up != up);
+    let since = match &prev {
+        Some(prev) if !changed => prev.since,
+        _ => now,
+    };
+    diesel::insert_into(schema::service_status::table)
+        .values((
+            schema::service_status::name.eq(&service.name),
+            schema::service_status::up.eq(up),
+            schema::service_status::since.eq(since),
+            schema::service_status::checked_at.eq(now),
+            schema::service_status::error.eq(&error),
+        ))
+        .on_conflict(schema::service_status::name)
+        .do_update()
+        .set((
+            schema::service_status::up.eq(up),
+            schema::service_status::since.eq(since),
+            schema::service_status::checked_at.eq(now),
+            schema::service_status::error.eq(&error),
+        ))
+        .execute(&mut *env.conn())?;
+    if !changed {
+        return Ok(());
+    }
+
+    let name = html::escape(&service.name);
+    let text = match (&prev, &error) {
+        (_, Some(error)) => {
+            format!("🔴 <b>{name}</b> is down: {}", html::escape(error))
+        }
+        (Some(prev), None) => format!(
+            "🟢 <b>{name}</b> is back up after {}",
+            format_duration(now - prev.since),
+        ),
+        (None, None) => return Ok(()),
+    };
+    bot.send_message(conf.thread.chat, text)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+/// Format the current state of the services as a HTML message.
+pub fn status_text(env: &BotEnv) -> Result<String> {
+    let Some(conf) = &env.config.telegram.monitor else {
+        return Ok("Service monitoring is not configured.".to_string());
+    };
+    let statuses: Vec<models::ServiceStatus> = schema::service_status::table
+        .select(models::ServiceStatus::as_select())
+        .load(&mut *env.conn())?;
+    let now = chrono::Utc::now().naive_utc();
+    let mut text = String::from("🖥 <b>Services</b>\n");
+    for service in &conf.services {
+        let name = html::escape(&service.name);
+        match statuses.iter().find(|s| s.name == service.name) {
+            None => format_to!(text, "\n⚪ {name}: not checked yet"),
+            Some(s) if s.up => format_to!(
+                text,
+                "\n🟢 {name}: up for {}",
+                format_duration(now - s.since),
+            ),
+            Some(s) => format_to!(
+                text,
+                "\n🔴 {name}: down for {} ({})",
+                format_duration(now - s.since),
+                html::escape(s.error.as_deref().unwrap_or("unknown error")),
+            ),
+        }
+    }
+    Ok(text)
+}
+
+/// Format the duration as e.g. `2d 5h`, `3h 10m` or `7m`.
+fn format_duration(duration: chrono::Duration) -> String {
+    let minutes = duration.num_minutes();
+    if minutes < 1 {
+        return "less than a minute".to_string();
+    }
+    [(minutes / 1440, "d"), (minutes / 60 % 24, "h"), (minutes % 60, "m")]
+        .into_iter()
+        .skip_while(|(n, _)| *n == 0)
+        .take(2)
+        .filter(|(n, _)| *n != 0)
+        .map(|(n, unit)| format!("{n}{unit}"))
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        let d = chrono::Duration::minutes;
+        assert_eq!(
+            format_duration(chrono::Duration::seconds(30)),
+            "less than a minute"
+        );
+        assert_eq!(format_duration(d(7)), "7m");
+        assert_eq!(format_duration(d(190)), "3h 10m");
+        assert_eq!(format_duration(d(120)), "2h");
+        assert_eq!(format_duration(d(2 * 1440 + 5 * 60 + 3)), "2d 5h");
+        assert_eq!(format_duration(d(1440 + 3)), "1d");
+    }
+}
@@ -0,0 +1,200 @@
+//! MQTT integration: publish space state to the broker, and relay messages
+//! from subscribed topics to Telegram.
+//!
+//! **Scope**: the broker configured in [`services.mqtt`] config option.  The
+//! module is disabled if the section is absent.
+//!
+//! [`services.mqtt`]: crate::config::Services::mqtt
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use teloxide::prelude::*;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::BotEnv;
+use crate::config::Mqtt;
+use crate::schema;
+use crate::utils::ResultExt as _;
+
+/// Interval between state publications.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Space state published to the broker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SpaceState {
+    person_count: Option<usize>,
+    needs_count: i64,
+}
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.services.mqtt else { return };
+
+    let mut options = MqttOptions::new(&conf.client_id, &conf.host, conf.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_credentials(&conf.username, &conf.password);
+    let (client, eventloop) = AsyncClient::new(options, 16);
+
+    select! {
+        () = shutdown.cancelled() => {}
+        () = run_eventloop(&env, &bot, conf, &client, eventloop) => {}
+        () = run_publisher(&env, conf, &client) => {}
+    }
+
+    client.disconnect().await.log_error("mqtt: disconnect");
+}
+
+async fn run_eventloop(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Mqtt,
+    client: &AsyncClient,
+    mut eventloop: EventLoop,
+) {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                crate::metrics::update_service(&env.health, "mqtt", true);
+                // Sessions are not persistent, so subscribe on each reconnect.
+                for sub in &conf.subscriptions {
+                    client
+                        .subscribe(&sub.topic, QoS::AtLeastOnce)
+                        .await
+                        .log_error("mqtt: subscribe");
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                handle_publish(bot, conf, &publish.topic, &publish.payload)
+                    .await
+                    .log_error("mqtt: handle publish");
+            }
+            Ok(_) => (),
+            Err(e) => {
+                crate::metrics::update_service(&env.health, "mqtt", false);
+                log::error!("mqtt: connection error: {e}");
+                sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+async fn handle_publish(
+    bot: &Bot,
+    conf: &Mqtt,
+    topic: &str,
+    payload: &[u8],
+) -> Result<()> {
+    let payload = String::from_utf8_lossy(payload);
+    for sub in
+        conf.subscriptions.iter().filter(|s| topic_matches(&s.topic, topic))
+    {
+        let text = sub
+            .message
+            .replace("%topic%", topic)
+            .replace("%payload%", payload.trim());
+        bot.send_message(sub.to.chat, text)
+            .message_thread_id(sub.to.thread)
+            .disable_web_page_preview(true)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn run_publisher(env: &BotEnv, conf: &Mqtt, client: &AsyncClient) {
+    let mut last_state = None;
+    loop {
+        let person_count = crate::modules::basic::users_in_space(env)
+            .await
+            .map(|users| users.len())
+            .ok();
+        let needs_count = schema::needed_items::table
+            .filter(schema::needed_items::buyer_user_id.is_null())
+            .count()
+            .get_result(&mut *env.conn())
+            .unwrap_or_default();
+        let state = SpaceState { person_count, needs_count };
+
+        if last_state != Some(state) {
+            match publish_state(conf, client, state).await {
+                Ok(()) => last_state = Some(state),
+                Err(e) => log::error!("mqtt: failed to publish state: {e}"),
+            }
+        }
+
+        sleep(PUBLISH_INTERVAL).await;
+    }
+}
+
+async fn publish_state(
+    conf: &Mqtt,
+    client: &AsyncClient,
+    state: SpaceState,
+) -> Result<()> {
+    if let Some(person_count) = state.person_count {
+        client
+            .publish(
+                &conf.publish.space_open,
+                QoS::AtLeastOnce,
+                true,
+                if person_count > 0 { "open" } else { "closed" },
+            )
+            .await?;
+        client
+            .publish(
+                &conf.publish.person_count,
+                QoS::AtLeastOnce,
+                true,
+                person_count.to_string(),
+            )
+            .await?;
+    }
+    client
+        .publish(
+            &conf.publish.needs_count,
+            QoS::AtLeastOnce,
+            true,
+            state.needs_count.to_string(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Check whether an MQTT topic matches a subscription filter with `+` and `#`
+/// wildcards.
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut filter = filter.split('/');
+    let mut topic = topic.split('/');
+    loop {
+        match (filter.next(), topic.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => (),
+            (Some(f), Some(t)) if f == t => (),
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches("sensors/door", "sensors/door"));
+        assert!(!topic_matches("sensors/door", "sensors/window"));
+        assert!(!topic_matches("sensors/door", "sensors/door/state"));
+        assert!(!topic_matches("sensors/door/state", "sensors/door"));
+        assert!(topic_matches("sensors/+", "sensors/door"));
+        assert!(!topic_matches("sensors/+", "sensors/door/state"));
+        assert!(topic_matches("sensors/+/state", "sensors/door/state"));
+        assert!(topic_matches("sensors/#", "sensors/door/state"));
+        assert!(topic_matches("sensors/#", "sensors"));
+        assert!(topic_matches("#", "sensors/door"));
+    }
+}
@@ -22,19 +22,25 @@ use teloxide::prelude::*;
 use teloxide::types::{
     InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId,
 };
-use teloxide::utils::html;
 
 use crate::common::{
     filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
 };
 use crate::config::Config;
 use crate::db::DbUserId;
+use crate::events::Event;
+use crate::models::{OutboxAction, UndoAction};
+use crate::modules::{activity_feed, undo};
+use crate::outbox;
 use crate::utils::{
-    replace_urls_with_titles, write_message_link, BotExt, ResultExt,
-    ThreadIdPair,
+    html, replace_urls_with_titles, write_message_link, BotExt, Paginator,
+    ResultExt, ThreadIdPair,
 };
 use crate::{models, schema};
 
+/// Pages of the `/needs` list.
+const PAGINATOR: Paginator = Paginator::new("n:page:", 30);
+
 #[derive(Clone, BotCommands, BotCommandsExt!)]
 #[command(rename_rule = "snake_case")]
 pub enum Commands {
@@ -91,6 +97,7 @@ async fn handle_command(
 }
 
 async fn command_needs(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let Some(from) = msg.from.as_ref().map(|u| u.id) else { return Ok(()) };
     // Delete old pinned message (if it is the needs thread)
     if let Some(thread_pair) = check_thread_id(&env.config, &msg) {
         let last_pin = models::needs_last_pin.get(&mut env.conn())?;
@@ -104,7 +111,7 @@ async fn command_needs(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
     }
 
     // Send new message
-    let (text, buttons) = command_needs_message_and_buttons(&env)?;
+    let (text, buttons) = command_needs_message_and_buttons(&env, 0)?;
     let msg = bot
         .reply_message(&msg, text)
         .parse_mode(teloxide::types::ParseMode::Html)
@@ -115,10 +122,29 @@ async fn command_needs(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
     // Pin new message (if it is the needs thread)
     if let Some(thread_id_pair) = check_thread_id(&env.config, &msg) {
         bot.pin_chat_message(thread_id_pair.chat, msg.id).await?;
-        models::needs_last_pin.set(
-            &mut env.conn(),
-            &models::NeedsLastPin { thread_id_pair, message_id: msg.id },
-        )?;
+        env.transaction(|conn| {
+            models::needs_last_pin.set(
+                conn,
+                &models::NeedsLastPin { thread_id_pair, message_id: msg.id },
+            )?;
+            let action = |action| UndoAction::Telegram { action };
+            undo::push(
+                conn,
+                msg.chat.id,
+                from,
+                "pin of the shopping list",
+                vec![
+                    action(OutboxAction::UnpinMessage {
+                        chat_id: msg.chat.id,
+                        message_id: msg.id,
+                    }),
+                    action(OutboxAction::DeleteMessage {
+                        chat_id: msg.chat.id,
+                        message_id: msg.id,
+                    }),
+                ],
+            )
+        })?;
     }
 
     Ok(())
@@ -152,24 +178,52 @@ async fn add_items(
         )
     };
 
-    diesel::insert_into(schema::needed_items::table)
-        .values(
-            list_items
-                .iter()
-                .map(|item| models::NewNeededItem {
-                    request_chat_id: msg.chat.id.into(),
-                    request_message_id: msg.id.into(),
-                    request_user_id: user.id.into(),
-                    pinned_chat_id: pinned_message.chat.id.into(),
-                    pinned_message_id: pinned_message.id.into(),
-                    buyer_user_id: None,
-                    item,
-                })
-                .collect_vec(),
+    env.transaction(|conn| {
+        diesel::insert_into(schema::needed_items::table)
+            .values(
+                list_items
+                    .iter()
+                    .map(|item| models::NewNeededItem {
+                        request_chat_id: msg.chat.id.into(),
+                        request_message_id: msg.id.into(),
+                        request_user_id: user.id.into(),
+                        pinned_chat_id: pinned_message.chat.id.into(),
+                        pinned_message_id: pinned_message.id.into(),
+                        buyer_user_id: None,
+                        item,
+                        created_at: Some(chrono::Utc::now().naive_utc()),
+                    })
+                    .collect_vec(),
+            )
+            .execute(conn)?;
+        activity_feed::record(
+            conn,
+            "needs",
+            Some(user.id),
+            "asked for",
+            &list_items.join(", "),
+            msg.url().as_ref().map(|u| u.as_str()),
+        )?;
+        outbox::enqueue(
+            conn,
+            OutboxAction::PinMessage {
+                chat_id: pinned_message.chat.id,
+                message_id: pinned_message.id,
+            },
+            Vec::new(),
         )
-        .execute(&mut *env.conn())?;
-
-    bot.pin_chat_message(pinned_message.chat.id, pinned_message.id).await?;
+    })?;
+    outbox::wake(&env);
+
+    crate::modules::follows::notify(
+        bot,
+        env,
+        "New need",
+        &list_items.join("\n"),
+        msg.url(),
+        Some(user.id),
+    )
+    .await;
 
     update_pinned_needs_message(bot, env, None).await?;
 
@@ -183,14 +237,15 @@ fn check_thread_id(config: &Config, msg: &Message) -> Option<ThreadIdPair> {
         .filter(|p| p == &config.telegram.chats.needs)
 }
 
-/// Update `/needs` message.
+/// Update `/needs` message, showing the given page.
 async fn edit_list_message(
     bot: &Bot,
     env: &BotEnv,
     chat: ChatId,
     message: MessageId,
+    page: usize,
 ) -> Result<()> {
-    let (text, buttons) = command_needs_message_and_buttons(env)?;
+    let (text, buttons) = command_needs_message_and_buttons(env, page)?;
     bot.edit_message_text(chat, message, text)
         .parse_mode(teloxide::types::ParseMode::Html)
         .disable_web_page_preview(true)
@@ -199,18 +254,20 @@ async fn edit_list_message(
     Ok(())
 }
 
-/// Update last pinned `/needs` message.
+/// Update last pinned `/needs` message and notify [`Event::NeedsChanged`]
+/// subscribers.
 async fn update_pinned_needs_message(
     bot: &Bot,
     env: &BotEnv,
     msg: Option<&Message>,
 ) -> Result<()> {
+    env.events.publish(Event::NeedsChanged);
     let pin = models::needs_last_pin.get(&mut env.conn())?;
     let Some(pin) = pin else { return Ok(()) };
     if msg.map_or(false, |msg| pin.thread_id_pair.has_message(msg)) {
         return Ok(());
     }
-    edit_list_message(bot, env, pin.thread_id_pair.chat, pin.message_id)
+    edit_list_message(bot, env, pin.thread_id_pair.chat, pin.message_id, 0)
         .await
         .log_error("Cannot edit last pin");
     Ok(())
@@ -218,6 +275,7 @@ async fn update_pinned_needs_message(
 
 fn command_needs_message_and_buttons(
     env: &BotEnv,
+    page: usize,
 ) -> Result<(String, Vec<Vec<InlineKeyboardButton>>)> {
     let items: Vec<(models::NeededItem, Option<models::TgUser>)> =
         schema::needed_items::table
@@ -240,11 +298,13 @@ fn command_needs_message_and_buttons(
     let mut text = String::new();
     let mut buttons = Vec::new();
 
-    for (idx1, idx2, (item, user)) in
-        subnumerate(items.into_iter(), |(i, _)| {
-            (i.request_chat_id, i.request_message_id)
-        })
-    {
+    let items = subnumerate(items.into_iter(), |(i, _)| {
+        (i.request_chat_id, i.request_message_id)
+    })
+    .collect_vec();
+    let page = PAGINATOR.page(&items, page);
+    for (idx1, idx2, (item, user)) in page.items {
+        let (idx1, idx2) = (*idx1, *idx2);
         let is_public = item.request_chat_id
             == env.config.telegram.chats.needs.chat.into()
             || env
@@ -275,7 +335,7 @@ fn command_needs_message_and_buttons(
             },
         );
         write!(text, "by ").unwrap();
-        format_user(&mut text, item.request_user_id, &user, false);
+        format_user(&mut text, item.request_user_id, user, false);
         text.push_str("</a>)\n");
 
         write!(button_text, ". {}", item.item).unwrap();
@@ -295,6 +355,7 @@ fn command_needs_message_and_buttons(
     }
 
     text.push_str("\nPress a button to mark an item as bought.");
+    buttons.extend(page.buttons());
 
     Ok((text, buttons))
 }
@@ -303,9 +364,13 @@ fn command_needs_message_and_buttons(
 enum CallbackData {
     Bought(i32),
     Undo(i32),
+    Page(usize),
 }
 
 fn filter_callbacks(callback: CallbackQuery) -> Option<CallbackData> {
+    if let Some(page) = PAGINATOR.parse(callback.data.as_ref()?) {
+        return Some(CallbackData::Page(page));
+    }
     let data = callback.data.as_ref()?.strip_prefix("n:")?;
     let (prefix, data) = data.split_once(':')?;
     let data = data.parse().ok()?;
@@ -329,6 +394,21 @@ async fn handle_callback(
         CallbackData::Undo(rowid) => {
             handle_callback_undo(bot, env, callback, rowid).await
         }
+        CallbackData::Page(page) => {
+            if let Some(message) = &callback.message {
+                edit_list_message(
+                    &bot,
+                    &env,
+                    message.chat.id,
+                    message.id,
+                    page,
+                )
+                .await
+                .log_error("Cannot switch page");
+            }
+            bot.answer_callback_query(&callback.id).await?;
+            Ok(())
+        }
     }
 }
 
@@ -356,8 +436,19 @@ async fn handle_callback_bought(
 
         diesel::update(schema::needed_items::table)
             .filter(rowid.eq(rowid_))
-            .set(buyer_user_id.eq(DbUserId::from(callback.from.id)))
+            .set((
+                buyer_user_id.eq(DbUserId::from(callback.from.id)),
+                bought_at.eq(chrono::Utc::now().naive_utc()),
+            ))
             .execute(conn)?;
+        activity_feed::record(
+            conn,
+            "needs",
+            Some(callback.from.id),
+            "bought",
+            &item_.item,
+            None,
+        )?;
 
         let remaining: i64 = schema::needed_items::table
             .filter(request_chat_id.eq(item_.request_chat_id))
@@ -365,24 +456,30 @@ async fn handle_callback_bought(
             .filter(buyer_user_id.is_null())
             .count()
             .get_result(conn)?;
+        if remaining == 0 {
+            outbox::enqueue(
+                conn,
+                OutboxAction::UnpinMessage {
+                    chat_id: item_.pinned_chat_id.into(),
+                    message_id: item_.pinned_message_id.into(),
+                },
+                Vec::new(),
+            )?;
+        }
 
-        Ok(Ok((item_, remaining > 0)))
+        Ok(Ok(item_))
     })?;
 
-    let (item, has_more) = match result {
-        Ok((item, has_more)) => (item, has_more),
+    let item = match result {
+        Ok(item) => item,
         Err(error) => {
             bot.answer_callback_query(&callback.id).text(error).await?;
             return Ok(());
         }
     };
+    outbox::wake(&env);
 
     bot.answer_callback_query(&callback.id).text("Done!").await?;
-    if !has_more {
-        bot.unpin_chat_message(item.pinned_chat_id)
-            .message_id(item.pinned_message_id.into())
-            .await?;
-    }
 
     bot.send_message(
         env.config.telegram.chats.needs.chat,
@@ -399,7 +496,8 @@ async fn handle_callback_bought(
     .log_error("Cannot send message to needs thread");
 
     if let Some(ref message) = callback.message {
-        edit_list_message(&bot, &env, message.chat.id, message.id)
+        let page = PAGINATOR.current(message);
+        edit_list_message(&bot, &env, message.chat.id, message.id, page)
             .await
             .log_error("Cannot edit callback message");
     }
@@ -444,33 +542,35 @@ async fn handle_callback_undo(
 
         diesel::update(schema::needed_items::table)
             .filter(rowid.eq(rowid_))
-            .set(buyer_user_id.eq(None::<DbUserId>))
+            .set((
+                buyer_user_id.eq(None::<DbUserId>),
+                bought_at.eq(None::<chrono::NaiveDateTime>),
+            ))
             .execute(conn)?;
+        if remaining_before_undoing == 0 {
+            outbox::enqueue(
+                conn,
+                OutboxAction::PinMessage {
+                    chat_id: item_.pinned_chat_id.into(),
+                    message_id: item_.pinned_message_id.into(),
+                },
+                Vec::new(),
+            )?;
+        }
 
-        Ok(Ok((item_, remaining_before_undoing == 0)))
+        Ok(Ok(()))
     })?;
 
-    let (item, was_all_bought) = match result {
-        Ok((item, was_all_bought)) => (item, was_all_bought),
-        Err(error) => {
-            bot.answer_callback_query(&callback.id).text(error).await?;
-            return Ok(());
-        }
-    };
+    if let Err(error) = result {
+        bot.answer_callback_query(&callback.id).text(error).await?;
+        return Ok(());
+    }
+    outbox::wake(&env);
 
     update_pinned_needs_message(&bot, &env, None)
         .await
         .log_error("update pinned needs message");
 
-    if was_all_bought {
-        bot.pin_chat_message(
-            item.pinned_chat_id,
-            item.pinned_message_id.into(),
-        )
-        .await
-        .log_error("pin chat message");
-    }
-
     if let Some(cb_message) = callback.message {
         bot.delete_message(cb_message.chat.id, cb_message.id).await?;
     }
@@ -528,7 +628,179 @@ fn letter_index(out: &mut String, index: usize) {
 
 #[cfg(test)]
 mod tests {
+    use serde_json::Value;
+    use teloxide::dispatching::UpdateFilterExt;
+
     use super::*;
+    use crate::testing::{self, TestBot};
+
+    const NEEDS_CHAT: i64 = -1_001_234_567_890;
+    const NEEDS_THREAD: i64 = 123;
+
+    fn handler() -> UpdateHandler {
+        dptree::entry()
+            .branch(Update::filter_message().branch(message_handler()))
+            .branch(Update::filter_callback_query().branch(callback_handler()))
+    }
+
+    fn load_items(t: &TestBot) -> Vec<(String, Option<DbUserId>)> {
+        schema::needed_items::table
+            .order(schema::needed_items::rowid)
+            .select((
+                schema::needed_items::item,
+                schema::needed_items::buyer_user_id,
+            ))
+            .load(&mut *t.env.conn())
+            .unwrap()
+    }
+
+    /// Send `/needs` and return the list message sent by the bot.
+    async fn request_list(t: &TestBot, alice: &Value) -> Value {
+        t.dispatch(
+            &handler(),
+            testing::message(NEEDS_CHAT, Some(NEEDS_THREAD), alice, "/needs"),
+        )
+        .await;
+        let sent = t.telegram.results("sendMessage");
+        assert_eq!(sent.len(), 1);
+        sent[0].clone()
+    }
+
+    #[tokio::test]
+    async fn test_needs_flow() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let alice = testing::user_json(1, "Alice");
+
+        // Add items by posting a list in the needs thread.
+        t.dispatch(
+            &handler(),
+            testing::message(
+                NEEDS_CHAT,
+                Some(NEEDS_THREAD),
+                &alice,
+                "Please buy:\n- milk\n- bread",
+            ),
+        )
+        .await;
+        assert_eq!(
+            load_items(&t),
+            [("milk".to_string(), None), ("bread".to_string(), None)],
+        );
+        assert!(t.telegram.calls("pinChatMessage").is_empty());
+        outbox::process(&t.env, &t.bot).await;
+        assert_eq!(t.telegram.calls("pinChatMessage").len(), 1);
+        t.telegram.clear();
+
+        // Show the list.
+        let list = request_list(&t, &alice).await;
+        let text = list["text"].as_str().unwrap();
+        assert!(text.contains("milk") && text.contains("bread"), "{text}");
+        assert_eq!(
+            list["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+            "n:bought:1",
+        );
+        t.telegram.clear();
+
+        // Mark an item as bought.
+        t.dispatch(&handler(), testing::callback(&alice, &list, "n:bought:1"))
+            .await;
+        assert_eq!(
+            load_items(&t),
+            [
+                ("milk".to_string(), Some(DbUserId::from(UserId(1)))),
+                ("bread".to_string(), None),
+            ],
+        );
+        let answers = t.telegram.calls("answerCallbackQuery");
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0]["text"], "Done!");
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0]["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+            "n:undo:1"
+        );
+        t.telegram.clear();
+
+        // Undo.
+        t.dispatch(&handler(), testing::callback(&alice, &list, "n:undo:1"))
+            .await;
+        assert_eq!(
+            load_items(&t),
+            [("milk".to_string(), None), ("bread".to_string(), None)],
+        );
+        assert_eq!(t.telegram.calls("deleteMessage").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_needs_bought_twice() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let alice = testing::user_json(1, "Alice");
+        t.dispatch(
+            &handler(),
+            testing::message(NEEDS_CHAT, None, &alice, "/need milk"),
+        )
+        .await;
+        t.telegram.clear();
+
+        let list = request_list(&t, &alice).await;
+        for _ in 0..2 {
+            t.dispatch(
+                &handler(),
+                testing::callback(&alice, &list, "n:bought:1"),
+            )
+            .await;
+        }
+        let answers = t.telegram.calls("answerCallbackQuery");
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[1]["text"], "Item already bought");
+    }
+
+    #[tokio::test]
+    async fn test_needs_pages() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let alice = testing::user_json(1, "Alice");
+        let names = (1..=35).map(|i| format!("item{i}")).collect_vec();
+        diesel::insert_into(schema::needed_items::table)
+            .values(
+                names
+                    .iter()
+                    .zip(1..)
+                    .map(|(item, message_id)| models::NewNeededItem {
+                        request_chat_id: ChatId(NEEDS_CHAT).into(),
+                        request_message_id: MessageId(message_id).into(),
+                        request_user_id: UserId(1).into(),
+                        pinned_chat_id: ChatId(NEEDS_CHAT).into(),
+                        pinned_message_id: MessageId(message_id).into(),
+                        buyer_user_id: None,
+                        item,
+                        created_at: None,
+                    })
+                    .collect_vec(),
+            )
+            .execute(&mut *t.env.conn())
+            .unwrap();
+
+        let list = request_list(&t, &alice).await;
+        let text = list["text"].as_str().unwrap();
+        assert!(text.contains("30. item30") && !text.contains("item31"));
+        let keyboard =
+            list["reply_markup"]["inline_keyboard"].as_array().unwrap();
+        let nav = keyboard.last().unwrap();
+        assert_eq!(nav[1]["text"], "1/2");
+        assert_eq!(nav[2]["callback_data"], "n:page:1");
+
+        t.dispatch(&handler(), testing::callback(&alice, &list, "n:page:1"))
+            .await;
+        let edits = t.telegram.calls("editMessageText");
+        assert_eq!(edits.len(), 1);
+        let text = edits[0]["text"].as_str().unwrap();
+        assert!(text.contains("31. item31") && !text.contains("item30"));
+        assert_eq!(t.telegram.calls("answerCallbackQuery").len(), 1);
+    }
 
     #[test]
     fn test_subnumerate() {
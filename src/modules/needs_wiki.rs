@@ -0,0 +1,251 @@
+//! Mirror the shopping list onto a Wiki.js page.
+//!
+//! The open items of the `needs` module are written to
+//! [`services.wikijs.needs_page`] on startup and after each change, so
+//! members without Telegram can see the current list.  The page is owned by
+//! the bot: if it was edited on the wiki since the last sync, it is not
+//! overwritten, and the edit is reported to the needs thread once.  Admins
+//! can discard the edit and resume syncing with `/needs_wiki_sync`.
+//!
+//! **Scope**: background task, and `/needs_wiki_sync` command, available to
+//! admins.
+//!
+//! [`services.wikijs.needs_page`]: crate::config::WikiJs::needs_page
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use sha2::{Digest as _, Sha256};
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::events::Event;
+use crate::models::NeedsWikiSync;
+use crate::utils::{
+    format_to, get_wikijs_page_with_id, update_wikijs_page, BotExt as _,
+};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "overwrite the shopping list page on the wiki.")]
+    #[custom(admin = true)]
+    NeedsWikiSync,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_needs_wiki_sync)
+}
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    if env.config.services.wikijs.needs_page.is_none() {
+        return;
+    }
+    let mut events = env.events.subscribe();
+    loop {
+        if let Err(e) = sync(&env, &bot, false).await {
+            log::error!("needs_wiki: failed to sync: {e:#}");
+        }
+
+        loop {
+            select! {
+                () = shutdown.cancelled() => return,
+                event = events.recv() => match event {
+                    Ok(Event::NeedsChanged) | Err(RecvError::Lagged(_)) => {
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Closed) => return,
+                },
+            }
+        }
+    }
+}
+
+async fn cmd_needs_wiki_sync(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Result<()> {
+    let text = if env.config.services.wikijs.needs_page.is_none() {
+        "The shopping list page is not configured."
+    } else {
+        sync(&env, &bot, true).await?;
+        "The shopping list page is updated."
+    };
+    bot.reply_message(&msg, text).await?;
+    Ok(())
+}
+
+/// What to do with the page.
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    /// The page is up to date.
+    None,
+    Update,
+    /// The page was edited on the wiki, and the edit is not reported yet.
+    Report,
+    /// The page was edited on the wiki, and the edit was already reported.
+    Skip,
+}
+
+fn action(state: Option<&NeedsWikiSync>, page: &str, content: &str) -> Action {
+    let page = digest(page);
+    if page == digest(content) {
+        return Action::None;
+    }
+    // A page never written by the bot has no state.  It is overwritten only
+    // if it is empty, so that an existing page is not lost by mistake.
+    let edited = match state {
+        Some(state) => state.written != page,
+        None => page != digest(""),
+    };
+    if !edited {
+        Action::Update
+    } else if state.and_then(|s| s.reported.as_ref()) == Some(&page) {
+        Action::Skip
+    } else {
+        Action::Report
+    }
+}
+
+async fn sync(env: &BotEnv, bot: &Bot, force: bool) -> Result<()> {
+    let conf = &env.config.services.wikijs;
+    let Some(path) = &conf.needs_page else { return Ok(()) };
+
+    let content = render(&mut env.conn())?;
+    let page = get_wikijs_page_with_id(&conf.url, &conf.token, path).await?;
+    let state = models::needs_wiki_sync.get(&mut env.conn())?;
+    let action = match action(state.as_ref(), &page.content, &content) {
+        Action::Report | Action::Skip if force => Action::Update,
+        action => action,
+    };
+
+    match action {
+        Action::None | Action::Skip => {}
+        Action::Update => {
+            update_wikijs_page(&conf.url, &conf.token, &page, &content).await?;
+            models::needs_wiki_sync.set(
+                &mut env.conn(),
+                &NeedsWikiSync { written: digest(&content), reported: None },
+            )?;
+        }
+        Action::Report => {
+            let needs = env.config.telegram.chats.needs;
+            bot.send_message(
+                needs.chat,
+                format!(
+                    "⚠️ The shopping list page {}{path} was edited on the \
+                     wiki, so it is no longer updated.  Move the changes to \
+                     the list here, then run /needs_wiki_sync to overwrite \
+                     the page.",
+                    conf.url.trim_end_matches('/'),
+                ),
+            )
+            .message_thread_id(needs.thread)
+            .disable_web_page_preview(true)
+            .await?;
+            models::needs_wiki_sync.set(
+                &mut env.conn(),
+                &NeedsWikiSync {
+                    reported: Some(digest(&page.content)),
+                    ..state.unwrap_or_default()
+                },
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Markdown source of the page.
+fn render(conn: &mut SqliteConnection) -> QueryResult<String> {
+    let items: Vec<(String, Option<String>)> =
+        schema::needed_items::table
+            .left_join(schema::tg_users::table.on(
+                schema::tg_users::id.eq(schema::needed_items::request_user_id),
+            ))
+            .filter(schema::needed_items::buyer_user_id.is_null())
+            .order(schema::needed_items::rowid)
+            .select((
+                schema::needed_items::item,
+                schema::tg_users::first_name.nullable(),
+            ))
+            .load(conn)?;
+
+    let mut text = String::from(
+        "# Shopping list\n\n\
+         This page is updated automatically from the shopping list in \
+         Telegram.  Edits made here are not kept.\n\n",
+    );
+    if items.is_empty() {
+        text.push_str("Nothing is needed right now.\n");
+    }
+    for (item, requested_by) in items {
+        format_to!(text, "- {}", item.replace('\n', " "));
+        if let Some(name) = requested_by {
+            format_to!(text, " (requested by {name})");
+        }
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Digest of the content, ignoring differences in line endings and trailing
+/// whitespace introduced by the wiki editor.
+fn digest(content: &str) -> String {
+    let content = content.replace("\r\n", "\n");
+    hex::encode(Sha256::digest(content.trim_end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestBot;
+
+    #[test]
+    fn test_action() {
+        let written = NeedsWikiSync { written: digest("old"), reported: None };
+        assert_eq!(action(Some(&written), "old", "old\r\n"), Action::None);
+        assert_eq!(action(Some(&written), "old", "new"), Action::Update);
+        assert_eq!(action(None, "", "new"), Action::Update);
+
+        // Edits on the wiki are reported once.
+        assert_eq!(action(Some(&written), "edited", "new"), Action::Report);
+        assert_eq!(action(None, "existing", "new"), Action::Report);
+        let reported = NeedsWikiSync {
+            reported: Some(digest("edited")),
+            ..written.clone()
+        };
+        assert_eq!(action(Some(&reported), "edited", "new"), Action::Skip);
+        assert_eq!(action(Some(&reported), "again", "new"), Action::Report);
+    }
+
+    #[test]
+    fn test_render() {
+        let t = TestBot::new();
+        let mut conn = t.env.conn();
+        assert!(render(&mut conn).unwrap().ends_with("right now.\n"));
+
+        diesel::insert_into(schema::needed_items::table)
+            .values(models::NewNeededItem {
+                request_chat_id: ChatId(-1).into(),
+                request_message_id: teloxide::types::MessageId(1).into(),
+                request_user_id: UserId(1).into(),
+                pinned_chat_id: ChatId(-1).into(),
+                pinned_message_id: teloxide::types::MessageId(1).into(),
+                buyer_user_id: None,
+                item: "milk",
+                created_at: None,
+            })
+            .execute(&mut *conn)
+            .unwrap();
+        assert!(render(&mut conn).unwrap().ends_with("\n- milk\n"));
+    }
+}
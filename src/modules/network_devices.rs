@@ -0,0 +1,459 @@
+//! Network device inventory and rogue device alerts.
+//!
+//! Active DHCP leases are periodically fetched from the `MikroTik` router and
+//! stored in the `network_devices` table.  Devices on the
+//! [`telegram.network_devices.dhcp_servers`] that are neither assigned to a
+//! user nor known infrastructure are reported to the
+//! [`telegram.network_devices.thread`].  A resident can claim the device with
+//! a button, an admin can mark it as infrastructure, or assign it to a user by
+//! replying to the alert with their username.
+//!
+//! **Scope**: background task; alerts and replies in
+//! [`telegram.network_devices.thread`].
+//!
+//! [`telegram.network_devices.dhcp_servers`]: crate::config::NetworkDevices::dhcp_servers
+//! [`telegram.network_devices.thread`]: crate::config::NetworkDevices::thread
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{resolve_user, BotEnv, UpdateHandler};
+use crate::config::NetworkDevices;
+use crate::db::{DbMessageId, DbUserId};
+use crate::utils::mikrotik::{self, ACTIVE_LEASE_INTERVAL};
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+/// How often to fetch DHCP leases.
+const SCAN_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Copy, Debug)]
+enum Action {
+    /// Assign the device to the user who pressed the button.
+    Mine,
+    /// Mark the device as infrastructure.
+    Infrastructure,
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.network_devices else { return };
+    loop {
+        if let Err(e) = scan(&env, &bot, conf).await {
+            log::error!("network_devices: scan failed: {e}");
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(SCAN_INTERVAL) => {}
+        }
+    }
+}
+
+/// Store active leases and alert about unknown devices.
+async fn scan(env: &BotEnv, bot: &Bot, conf: &NetworkDevices) -> Result<()> {
+    let leases = mikrotik::get_dhcp_leases(
+        &env.health,
+        &env.reqwest_client,
+        &env.config.services.mikrotik,
+    )
+    .await?;
+    let leases = leases
+        .into_iter()
+        .filter(|l| l.last_seen < ACTIVE_LEASE_INTERVAL)
+        .filter(|l| {
+            conf.dhcp_servers.is_empty()
+                || l.server
+                    .as_ref()
+                    .is_some_and(|s| conf.dhcp_servers.contains(s))
+        })
+        .collect_vec();
+    let infrastructure: HashSet<String> =
+        conf.infrastructure.iter().map(ToString::to_string).collect();
+
+    let now = chrono::Utc::now().naive_utc();
+    let unknown = env.transaction(|conn| {
+        for lease in &leases {
+            diesel::insert_into(schema::network_devices::table)
+                .values((
+                    schema::network_devices::mac.eq(&lease.mac_address),
+                    schema::network_devices::host_name.eq(&lease.host_name),
+                    schema::network_devices::address.eq(&lease.address),
+                    schema::network_devices::dhcp_server.eq(&lease.server),
+                    schema::network_devices::first_seen.eq(now),
+                    schema::network_devices::last_seen.eq(now),
+                    schema::network_devices::infrastructure.eq(false),
+                ))
+                .on_conflict(schema::network_devices::mac)
+                .do_update()
+                .set((
+                    schema::network_devices::host_name.eq(&lease.host_name),
+                    schema::network_devices::address.eq(&lease.address),
+                    schema::network_devices::dhcp_server.eq(&lease.server),
+                    schema::network_devices::last_seen.eq(now),
+                ))
+                .execute(conn)?;
+        }
+        let macs = leases.iter().map(|l| &l.mac_address).collect_vec();
+        let assigned: HashSet<String> = schema::user_macs::table
+            .filter(schema::user_macs::mac.eq_any(&macs))
+            .select(schema::user_macs::mac)
+            .load(conn)?
+            .into_iter()
+            .collect();
+        let devices: Vec<models::NetworkDevice> =
+            schema::network_devices::table
+                .filter(schema::network_devices::mac.eq_any(&macs))
+                .filter(schema::network_devices::infrastructure.eq(false))
+                .filter(schema::network_devices::alert_message_id.is_null())
+                .select(models::NetworkDevice::as_select())
+                .load(conn)?;
+        Ok(devices
+            .into_iter()
+            .filter(|d| {
+                !assigned.contains(&d.mac) && !infrastructure.contains(&d.mac)
+            })
+            .collect_vec())
+    })?;
+
+    for device in unknown {
+        let message = bot
+            .send_message(conf.thread.chat, format_alert(&device))
+            .message_thread_id(conf.thread.thread)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(InlineKeyboardMarkup::new([[
+                InlineKeyboardButton::callback(
+                    "👤 It's mine",
+                    format!("nd:mine:{}", device.mac),
+                ),
+                InlineKeyboardButton::callback(
+                    "🖥 Infrastructure",
+                    format!("nd:infra:{}", device.mac),
+                ),
+            ]]))
+            .await?;
+        diesel::update(schema::network_devices::table)
+            .filter(schema::network_devices::mac.eq(&device.mac))
+            .set(
+                schema::network_devices::alert_message_id
+                    .eq(DbMessageId::from(message.id)),
+            )
+            .execute(&mut *env.conn())?;
+    }
+    Ok(())
+}
+
+fn format_alert(device: &models::NetworkDevice) -> String {
+    let mut text = String::from("🚨 <b>Unknown device</b>");
+    if let Some(server) = &device.dhcp_server {
+        format_to!(text, " on {}", html::escape(server));
+    }
+    format_to!(text, "\nMAC: {}", html::code_inline(&device.mac));
+    if let Some(host_name) = &device.host_name {
+        format_to!(text, "\nHost name: {}", html::escape(host_name));
+    }
+    if let Some(address) = &device.address {
+        format_to!(text, "\nIP: {}", html::code_inline(address));
+    }
+    text.push_str(
+        "\n\nAdmins can assign it to a user by replying with their username.",
+    );
+    text
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<(Action, String)> {
+    let (action, mac) =
+        callback.data.as_ref()?.strip_prefix("nd:")?.split_once(':')?;
+    let action = match action {
+        "mine" => Action::Mine,
+        "infra" => Action::Infrastructure,
+        _ => return None,
+    };
+    Some((action, mac.to_string()))
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    (action, mac): (Action, String),
+) -> Result<()> {
+    let user = &callback.from;
+    let result = match action {
+        Action::Mine if !env.is_resident(user.id) => {
+            Err("Only residents can claim devices.")
+        }
+        Action::Mine => assign(&env, &mac, user.id, user.id).map(|()| {
+            format!("{} claimed by {}.", mac, html::escape(&user.full_name()))
+        }),
+        Action::Infrastructure
+            if !env.config.telegram.admins.contains(&user.id) =>
+        {
+            Err("Only admins can do this.")
+        }
+        Action::Infrastructure => {
+            mark_infrastructure(&env, &mac, user.id).map(|()| {
+                format!(
+                    "{} marked as infrastructure by {}.",
+                    mac,
+                    html::escape(&user.full_name()),
+                )
+            })
+        }
+    };
+    let text = match result {
+        Ok(text) => text,
+        Err(e) => {
+            bot.answer_callback_query(&callback.id).text(e).await?;
+            return Ok(());
+        }
+    };
+
+    bot.answer_callback_query(&callback.id).await?;
+    if let Some(message) = &callback.message {
+        bot.edit_message_reply_markup(message.chat.id, message.id)
+            .await
+            .log_error("network_devices: remove buttons");
+        bot.reply_message(message, text).parse_mode(ParseMode::Html).await?;
+    }
+    Ok(())
+}
+
+/// Assign a device to the user in reply to the alert.
+pub async fn inspect_message(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.network_devices else {
+        return Ok(());
+    };
+    let (Some(from), Some(reply_to), Some(text)) =
+        (&msg.from, msg.reply_to_message(), msg.text())
+    else {
+        return Ok(());
+    };
+    if !conf.thread.has_message(&msg)
+        || !env.config.telegram.admins.contains(&from.id)
+    {
+        return Ok(());
+    }
+    let mac = schema::network_devices::table
+        .filter(
+            schema::network_devices::alert_message_id
+                .eq(DbMessageId::from(reply_to.id)),
+        )
+        .select(schema::network_devices::mac)
+        .first::<String>(&mut *env.conn())
+        .optional()?;
+    let Some(mac) = mac else { return Ok(()) };
+
+    let user = resolve_user(&bot, &env, &msg, text.trim()).await?;
+    let reply = match user {
+        Some(user) => assign(&env, &mac, user, from.id)
+            .map_or_else(str::to_string, |()| {
+                format!("{mac} assigned to user {}.", user.0)
+            }),
+        None => "Unknown user.".to_string(),
+    };
+    bot.reply_message(&msg, reply).await?;
+    Ok(())
+}
+
+fn assign(
+    env: &BotEnv,
+    mac: &str,
+    user: UserId,
+    actor: UserId,
+) -> Result<(), &'static str> {
+    env.transaction(|conn| {
+        diesel::insert_or_ignore_into(schema::user_macs::table)
+            .values((
+                schema::user_macs::tg_id.eq(DbUserId::from(user)),
+                schema::user_macs::mac.eq(mac),
+            ))
+            .execute(conn)?;
+        crate::modules::audit::record(
+            conn,
+            Some(actor),
+            "network_device_assign",
+            &serde_json::json!({ "mac": mac, "user_id": user.0 }),
+        )
+        .log_error("network_devices: audit");
+        Ok(())
+    })
+    .map_err(|e: diesel::result::Error| {
+        log::error!("network_devices: failed to assign {mac}: {e}");
+        "Failed to assign the device."
+    })
+}
+
+fn mark_infrastructure(
+    env: &BotEnv,
+    mac: &str,
+    actor: UserId,
+) -> Result<(), &'static str> {
+    env.transaction(|conn| {
+        diesel::update(schema::network_devices::table)
+            .filter(schema::network_devices::mac.eq(mac))
+            .set(schema::network_devices::infrastructure.eq(true))
+            .execute(conn)?;
+        crate::modules::audit::record(
+            conn,
+            Some(actor),
+            "network_device_infrastructure",
+            &serde_json::json!({ "mac": mac }),
+        )
+        .log_error("network_devices: audit");
+        Ok(())
+    })
+    .map_err(|e: diesel::result::Error| {
+        log::error!("network_devices: failed to mark {mac}: {e}");
+        "Failed to mark the device."
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use teloxide::dispatching::UpdateFilterExt as _;
+    use teloxide::types::MessageId;
+
+    use super::*;
+    use crate::mock_telegram::{me_json, message_json};
+    use crate::testing::{self, TestBot};
+    use crate::utils::HandlerExt as _;
+
+    const CHAT: i64 = -1_001_234_567_890;
+    const THREAD: i64 = 123;
+    const ADMIN: u64 = 1_234_567_890;
+    const MAC: &str = "AA:BB:CC:DD:EE:FF";
+    const ALERT_ID: i32 = 42;
+
+    fn add_device(t: &TestBot) {
+        let now = chrono::Utc::now().naive_utc();
+        diesel::insert_into(schema::network_devices::table)
+            .values((
+                schema::network_devices::mac.eq(MAC),
+                schema::network_devices::first_seen.eq(now),
+                schema::network_devices::last_seen.eq(now),
+                schema::network_devices::infrastructure.eq(false),
+                schema::network_devices::alert_message_id
+                    .eq(DbMessageId::from(MessageId(ALERT_ID))),
+            ))
+            .execute(&mut *t.env.conn())
+            .unwrap();
+    }
+
+    fn alert() -> Value {
+        let mut alert = message_json(CHAT, ALERT_ID, Some(THREAD), &me_json());
+        alert["text"] = "🚨 Unknown device".into();
+        alert
+    }
+
+    fn owners(t: &TestBot) -> Vec<DbUserId> {
+        schema::user_macs::table
+            .filter(schema::user_macs::mac.eq(MAC))
+            .select(schema::user_macs::tg_id)
+            .load(&mut *t.env.conn())
+            .unwrap()
+    }
+
+    async fn press(t: &TestBot, from: &Value, data: &str) -> Value {
+        t.telegram.clear();
+        t.dispatch(
+            &callback_handler(),
+            testing::callback(from, &alert(), data),
+        )
+        .await;
+        t.telegram.calls("answerCallbackQuery")[0].clone()
+    }
+
+    #[tokio::test]
+    async fn test_claim() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        add_device(&t);
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+
+        let answer = press(&t, &bob, &format!("nd:mine:{MAC}")).await;
+        assert_eq!(answer["text"], "Only residents can claim devices.");
+        assert!(owners(&t).is_empty());
+
+        let answer = press(&t, &alice, &format!("nd:mine:{MAC}")).await;
+        assert!(answer.get("text").is_none());
+        assert_eq!(owners(&t), [DbUserId::from(UserId(1))]);
+        assert_eq!(
+            t.telegram.calls("sendMessage")[0]["text"],
+            format!("{MAC} claimed by Alice."),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_infrastructure() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        add_device(&t);
+        let alice = testing::user_json(1, "Alice");
+        let admin = testing::user_json(ADMIN, "Admin");
+        let infrastructure = |t: &TestBot| -> bool {
+            schema::network_devices::table
+                .filter(schema::network_devices::mac.eq(MAC))
+                .select(schema::network_devices::infrastructure)
+                .first(&mut *t.env.conn())
+                .unwrap()
+        };
+
+        let answer = press(&t, &alice, &format!("nd:infra:{MAC}")).await;
+        assert_eq!(answer["text"], "Only admins can do this.");
+        assert!(!infrastructure(&t));
+
+        press(&t, &admin, &format!("nd:infra:{MAC}")).await;
+        assert!(infrastructure(&t));
+    }
+
+    #[tokio::test]
+    async fn test_assign_by_reply() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        add_device(&t);
+        let alice = testing::user_json(1, "Alice");
+        let admin = testing::user_json(ADMIN, "Admin");
+        let handler: UpdateHandler = Update::filter_message()
+            .inspect_err(inspect_message)
+            .endpoint(|| async { Ok(()) });
+        let reply = |from: &Value, text: &str| {
+            let mut update = testing::message(CHAT, Some(THREAD), from, text);
+            update["message"]["reply_to_message"] = alert();
+            update
+        };
+
+        // Replies of non-admins are ignored.
+        t.dispatch(&handler, reply(&alice, "@alice")).await;
+        assert!(owners(&t).is_empty());
+        assert!(t.telegram.calls("sendMessage").is_empty());
+
+        t.dispatch(&handler, reply(&admin, "@alice")).await;
+        assert_eq!(owners(&t), [DbUserId::from(UserId(1))]);
+        assert_eq!(
+            t.telegram.calls("sendMessage")[0]["text"],
+            format!("{MAC} assigned to user 1."),
+        );
+    }
+}
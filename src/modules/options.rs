@@ -0,0 +1,97 @@
+//! Inspect and change options stored in the database.
+//!
+//! `/option get|set|unset <name> [value]` works with any option listed in
+//! [`models::CONFIG_OPTIONS`].  Values are JSON and are validated against the
+//! option type before being stored.  Modules that [`watch`] an option are
+//! notified of the change.
+//!
+//! **Scope**: `/option` command, available to admins.
+//!
+//! [`watch`]: crate::db::ConfigOptionDef::watch
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::AnyConfigOption;
+use crate::models;
+use crate::utils::{html, BotExt as _};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "manage database options: \
+                             <code>/option get|set|unset name [json]</code>.")]
+    #[custom(admin = true)]
+    Option(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_option)
+}
+
+async fn cmd_option(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Option(args): Commands,
+) -> Result<()> {
+    let args = args.trim();
+    let (action, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let (name, value) = rest.trim().split_once(' ').unwrap_or((rest, ""));
+    let option = find(name.trim());
+
+    let text = match (action, option) {
+        ("get", Some(option)) => match option.get_json(&mut env.conn())? {
+            Some(value) => html::code_block(&value),
+            None => "Not set.".to_string(),
+        },
+        ("set", Some(option)) => {
+            match option.set_json(&mut env.conn(), value.trim()) {
+                Ok(()) => format!("{} is set.", option.name()),
+                Err(e) if e.is::<serde_json::Error>() => {
+                    format!("Invalid value: {}", html::escape(&e.to_string()))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        ("unset", Some(option)) => {
+            option.unset(&mut env.conn())?;
+            format!("{} is unset.", option.name())
+        }
+        ("get" | "set" | "unset", None) if !name.is_empty() => {
+            format!("Unknown option {}.", html::code_inline(name))
+        }
+        _ => format!(
+            "Usage: /option get|set|unset name [json]\n\nOptions: {}",
+            models::CONFIG_OPTIONS
+                .iter()
+                .map(|o| html::code_inline(o.name()))
+                .join(", "),
+        ),
+    };
+    bot.reply_message(&msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+fn find(name: &str) -> Option<&'static dyn AnyConfigOption> {
+    models::CONFIG_OPTIONS.iter().copied().find(|o| o.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_option_names_unique() {
+        assert!(models::CONFIG_OPTIONS.iter().map(|o| o.name()).all_unique());
+        assert!(find("energy_last_report").is_some());
+        assert!(find("unknown").is_none());
+    }
+}
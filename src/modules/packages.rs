@@ -0,0 +1,291 @@
+//! Package and mail arrival notifications.
+//!
+//! Whoever accepts a delivery at the space logs it with
+//! `/package for @user [description]`.  The recipient is notified in private
+//! messages and confirms the pickup with a button.  Unclaimed packages are
+//! listed weekly in the [`telegram.packages.thread`].
+//!
+//! **Scope**: `/package` command, available to residents.
+//!
+//! [`telegram.packages.thread`]: crate::config::Packages::thread
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{Datelike as _, Timelike as _};
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_user, resolve_user, BotCommandsExt, BotEnv,
+    UpdateHandler,
+};
+use crate::config::Packages;
+use crate::db::DbUserId;
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "log an accepted delivery: <code>/package for \
+                             @user [description]</code>.  Without arguments, \
+                             lists unclaimed packages.")]
+    #[custom(resident = true)]
+    Package(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_package)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+/// Split `for @user description` into the recipient and the description.
+fn parse_args(args: &str) -> Option<(&str, &str)> {
+    let args = args.trim().strip_prefix("for")?;
+    if !args.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let args = args.trim_start();
+    let (user, description) =
+        args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    Some((user, description.trim()))
+}
+
+async fn cmd_package(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Package(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    if args.trim().is_empty() {
+        return list_unclaimed(&bot, &env, &msg).await;
+    }
+    let Some((user, description)) = parse_args(&args) else {
+        bot.reply_message(&msg, "Usage: /package for @user [description]")
+            .await?;
+        return Ok(());
+    };
+
+    let Some(recipient) = resolve_user(&bot, &env, &msg, user).await? else {
+        bot.reply_message(&msg, "Unknown user.").await?;
+        return Ok(());
+    };
+    let rowid = env.transaction(|conn| {
+        diesel::insert_into(schema::packages::table)
+            .values((
+                schema::packages::recipient.eq(DbUserId::from(recipient)),
+                schema::packages::description.eq(description),
+                schema::packages::accepted_by.eq(DbUserId::from(from.id)),
+                schema::packages::accepted_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        schema::packages::table
+            .filter(schema::packages::recipient.eq(DbUserId::from(recipient)))
+            .select(schema::packages::rowid)
+            .order(schema::packages::rowid.desc())
+            .first::<i32>(conn)
+    })?;
+
+    let mut text = String::from("📦 A package has arrived for you");
+    if !description.is_empty() {
+        format_to!(text, ": {}", html::escape(description));
+    }
+    text.push_str(".\nAccepted by ");
+    format_user(&mut text, from.id, &tg_user(from), true);
+    text.push_str(". Press the button once you pick it up.");
+    let notified = bot
+        .send_message(recipient, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(InlineKeyboardMarkup::new([[
+            InlineKeyboardButton::callback(
+                "✅ Picked up",
+                format!("pk:pickup:{rowid}"),
+            ),
+        ]]))
+        .await
+        .is_ok();
+
+    bot.reply_message(
+        &msg,
+        if notified {
+            "Logged, the recipient is notified."
+        } else {
+            "Logged, but the recipient can't be notified in private \
+             messages. Please tell them in person."
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+fn tg_user(user: &teloxide::types::User) -> models::TgUser {
+    models::TgUser {
+        id: user.id.into(),
+        username: user.username.clone(),
+        first_name: user.first_name.clone(),
+        last_name: user.last_name.clone(),
+    }
+}
+
+/// Format unclaimed packages, one per line.  Returns `None` if there are
+/// none.
+fn format_unclaimed(env: &BotEnv) -> Result<Option<String>> {
+    let packages: Vec<(models::Package, Option<models::TgUser>)> =
+        schema::packages::table
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::tg_users::id.eq(schema::packages::recipient)),
+            )
+            .filter(schema::packages::picked_up_at.is_null())
+            .order(schema::packages::accepted_at)
+            .select((
+                models::Package::as_select(),
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+    if packages.is_empty() {
+        return Ok(None);
+    }
+
+    let mut text = String::from("📦 <b>Unclaimed packages</b>\n\n");
+    for (package, user) in &packages {
+        format_to!(text, "{} ", package.accepted_at.format("%Y-%m-%d"));
+        format_user(&mut text, package.recipient, user.as_ref(), true);
+        if !package.description.is_empty() {
+            format_to!(text, ": {}", html::escape(&package.description));
+        }
+        text.push('\n');
+    }
+    Ok(Some(text))
+}
+
+async fn list_unclaimed(bot: &Bot, env: &BotEnv, msg: &Message) -> Result<()> {
+    let text = format_unclaimed(env)?
+        .unwrap_or_else(|| "No unclaimed packages.".to_string());
+    bot.reply_message(msg, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+/// Post the weekly reminder about unclaimed packages.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.packages else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(10 * 60)) => {}
+        }
+
+        let now = chrono::Utc::now();
+        if now.weekday() != conf.weekday || now.hour() < conf.hour {
+            continue;
+        }
+        let today = now.date_naive().to_string();
+        let last = models::packages_last_reminder.get(&mut env.conn());
+        if last.as_ref().is_ok_and(|d| d.as_ref() == Some(&today)) {
+            continue;
+        }
+        if let Err(e) = post_reminder(&env, &bot, conf).await {
+            log::error!("packages: failed to post reminder: {e}");
+            continue;
+        }
+        models::packages_last_reminder
+            .set(&mut env.conn(), &today)
+            .log_error("packages: set last reminder");
+    }
+}
+
+async fn post_reminder(env: &BotEnv, bot: &Bot, conf: &Packages) -> Result<()> {
+    let Some(text) = format_unclaimed(env)? else { return Ok(()) };
+    bot.send_message(conf.thread.chat, text)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<i32> {
+    callback.data.as_ref()?.strip_prefix("pk:pickup:")?.parse().ok()
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    rowid: i32,
+) -> Result<()> {
+    let is_admin = env.config.telegram.admins.contains(&callback.from.id);
+    let result = env.transaction(|conn| {
+        let package = schema::packages::table
+            .filter(schema::packages::rowid.eq(rowid))
+            .select(models::Package::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(package) = package else {
+            return Ok(Err("This package no longer exists."));
+        };
+        if UserId::from(package.recipient) != callback.from.id && !is_admin {
+            return Ok(Err("Only the recipient can confirm the pickup."));
+        }
+        if package.picked_up_at.is_some() {
+            return Ok(Err("This package is already picked up."));
+        }
+        diesel::update(schema::packages::table)
+            .filter(schema::packages::rowid.eq(rowid))
+            .set(
+                schema::packages::picked_up_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            )
+            .execute(conn)?;
+        Ok(Ok(()))
+    })?;
+
+    let text = match result {
+        Ok(()) => "Pickup confirmed.",
+        Err(error) => error,
+    };
+    bot.answer_callback_query(&callback.id).text(text).await?;
+    if result.is_ok() {
+        if let Some(message) = &callback.message {
+            bot.edit_message_reply_markup(message.chat.id, message.id)
+                .await
+                .log_error("packages: remove button");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args("for @alice big box from IKEA"),
+            Some(("@alice", "big box from IKEA"))
+        );
+        assert_eq!(parse_args(" for  12345 "), Some(("12345", "")));
+        assert_eq!(parse_args("format"), None);
+        assert_eq!(parse_args("@alice"), None);
+    }
+}
@@ -0,0 +1,156 @@
+//! Cross-post announcements of tracked polls to other topics.
+//!
+//! Polls tagged `[announce]` after the `!`, or created by `POST /polls` with
+//! `announce`, are announced with a "Vote here →" link in the topics of
+//! [`telegram.chats.poll_announcements`], except the topic of the poll
+//! itself.  When the poll closes, the announcements are edited to show the
+//! final votes for each option.  Links to messages exist only in
+//! supergroups, so polls in other chats aren't announced.
+//!
+//! **Scope**: polls tracked by the `polls` module.
+//!
+//! [`telegram.chats.poll_announcements`]: crate::config::TelegramChats::poll_announcements
+
+use std::fmt::Write as _;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use teloxide::{ApiError, RequestError};
+
+use crate::common::BotEnv;
+use crate::modules::poll_webhooks;
+use crate::utils::html;
+use crate::{models, schema};
+
+/// Announce the poll in the configured topics.  `question` is the question
+/// without tags.
+pub async fn announce(
+    bot: &Bot,
+    env: &BotEnv,
+    poll_message: &Message,
+    question: &str,
+) -> Result<()> {
+    let (Some(poll), Some(url)) = (poll_message.poll(), poll_message.url())
+    else {
+        return Ok(());
+    };
+    let text = format!(
+        "📊 <b>{}</b>\n{}",
+        html::escape(question),
+        html::link(url.as_str(), "Vote here →"),
+    );
+    for thread in &env.config.telegram.chats.poll_announcements {
+        if thread.has_message(poll_message) {
+            continue;
+        }
+        let sent = bot
+            .send_message(thread.chat, &text)
+            .message_thread_id(thread.thread)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await;
+        let sent = match sent {
+            Ok(sent) => sent,
+            Err(e) => {
+                log::warn!("poll_announcements: failed to announce: {e}");
+                continue;
+            }
+        };
+        diesel::insert_into(schema::poll_announcements::table)
+            .values(models::PollAnnouncement {
+                chat_id: sent.chat.id.into(),
+                message_id: sent.id.into(),
+                tg_poll_id: poll.id.clone(),
+                question: question.to_string(),
+                poll_url: url.to_string(),
+            })
+            .execute(&mut *env.conn())?;
+    }
+    Ok(())
+}
+
+/// Edit the announcements of the closed poll to show the outcome.
+pub async fn finish(
+    bot: &Bot,
+    env: &BotEnv,
+    poll: &models::TrackedPoll,
+) -> Result<()> {
+    let announcements: Vec<models::PollAnnouncement> =
+        schema::poll_announcements::table
+            .filter(schema::poll_announcements::tg_poll_id.eq(&poll.tg_poll_id))
+            .select(models::PollAnnouncement::as_select())
+            .load(&mut *env.conn())?;
+    if announcements.is_empty() {
+        return Ok(());
+    }
+    let options = poll.options.as_deref().map_or(&[][..], Vec::as_slice);
+    let results =
+        poll_webhooks::tally(&mut env.conn(), &poll.tg_poll_id, options.len())?;
+
+    for announcement in announcements {
+        let text = outcome_text(&announcement, options, &results);
+        let result = bot
+            .edit_message_text(
+                announcement.chat_id,
+                announcement.message_id.into(),
+                text,
+            )
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await;
+        match result {
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+            Err(e) => log::warn!("poll_announcements: failed to edit: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Text of an announcement of a closed poll.  Votes are known only for
+/// polls sent by the bot, otherwise `results` are all zeros.
+fn outcome_text(
+    announcement: &models::PollAnnouncement,
+    options: &[String],
+    results: &[usize],
+) -> String {
+    let mut text =
+        format!("📊 <b>{}</b>\nClosed", html::escape(&announcement.question));
+    if results.iter().any(|&n| n > 0) {
+        text.push(':');
+        for (option, votes) in options.iter().zip(results) {
+            write!(text, "\n{} — {votes}", html::escape(option)).unwrap();
+        }
+    } else {
+        text.push('.');
+    }
+    write!(text, "\n{}", html::link(&announcement.poll_url, "Poll →")).unwrap();
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageId;
+
+    use super::*;
+
+    #[test]
+    fn test_outcome_text() {
+        let announcement = models::PollAnnouncement {
+            chat_id: ChatId(-1).into(),
+            message_id: MessageId(1).into(),
+            tg_poll_id: "poll".to_string(),
+            question: "Pizza & beer?".to_string(),
+            poll_url: "https://t.me/c/1/2".to_string(),
+        };
+        let options = ["Yes".to_string(), "No".to_string()];
+        assert_eq!(
+            outcome_text(&announcement, &options, &[3, 1]),
+            "📊 <b>Pizza &amp; beer?</b>\nClosed:\nYes — 3\nNo — 1\n\
+             <a href=\"https://t.me/c/1/2\">Poll →</a>",
+        );
+        assert!(outcome_text(&announcement, &options, &[0, 0])
+            .contains("\nClosed.\n"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Notify external services about the lifecycle of tracked polls.
+//!
+//! Each hook in [`telegram.poll_webhooks`] receives a JSON payload by `POST`
+//! for the events it subscribed to: `created`, `quorum` (once, when enough
+//! residents voted), and `closed` (with the final number of votes for each
+//! option).  The body is signed with the secret of the hook, the signature is
+//! sent as `X-Botka-Signature: sha256=<hex>`, like GitHub does.  Deliveries
+//! are retried a few times, then dropped.
+//!
+//! **Scope**: polls tracked by the `polls` module.
+//!
+//! [`telegram.poll_webhooks`]: crate::config::Telegram::poll_webhooks
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use hmac::{Hmac, Mac as _};
+use serde::Serialize;
+use sha2::Sha256;
+use teloxide::types::{ChatId, MessageId, UserId};
+
+use crate::common::BotEnv;
+use crate::config::{PollEvent, PollWebhook};
+use crate::db::DbUserId;
+use crate::utils::Sqlizer;
+use crate::{models, schema};
+
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug)]
+struct Payload<'a> {
+    event: PollEvent,
+    poll_id: &'a str,
+    chat_id: i64,
+    poll_message_id: Option<i32>,
+    info_message_id: i32,
+    creator_id: u64,
+    options: &'a [String],
+    /// Users who voted, residents or not.
+    voters: usize,
+    /// Residents who haven't voted yet.
+    pending: usize,
+    /// Votes for each option, only in `closed` events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    results: Option<Vec<usize>>,
+    sent_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Whether `percent` of residents voted, given the number of residents who
+/// haven't.
+pub fn quorum_reached(percent: u32, residents: usize, pending: usize) -> bool {
+    residents != 0
+        && (residents - pending) * 100 >= percent as usize * residents
+}
+
+/// Send `event` about the poll to the subscribed hooks in the background.
+/// Call outside of database transactions.
+pub fn notify(
+    env: &BotEnv,
+    event: PollEvent,
+    poll: &models::TrackedPoll,
+    pending: usize,
+) {
+    let Some(conf) = &env.config.telegram.poll_webhooks else { return };
+    let hooks = conf
+        .hooks
+        .iter()
+        .filter(|h| h.events.contains(&event))
+        .cloned()
+        .collect::<Vec<_>>();
+    if hooks.is_empty() {
+        return;
+    }
+
+    let body = match payload(env, event, poll, pending) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("poll_webhooks: failed to build payload: {e:#}");
+            return;
+        }
+    };
+    for hook in hooks {
+        tokio::spawn(deliver(env.reqwest_client.clone(), hook, body.clone()));
+    }
+}
+
+fn payload(
+    env: &BotEnv,
+    event: PollEvent,
+    poll: &models::TrackedPoll,
+    pending: usize,
+) -> Result<String> {
+    let options = poll.options.as_deref().map_or(&[][..], Vec::as_slice);
+    let results = match event {
+        PollEvent::Closed => {
+            Some(tally(&mut env.conn(), &poll.tg_poll_id, options.len())?)
+        }
+        PollEvent::Created | PollEvent::Quorum => None,
+    };
+    Ok(serde_json::to_string(&Payload {
+        event,
+        poll_id: &poll.tg_poll_id,
+        chat_id: ChatId::from(poll.info_chat_id).0,
+        poll_message_id: poll.poll_message_id.map(|m| MessageId::from(m).0),
+        info_message_id: MessageId::from(poll.info_message_id).0,
+        creator_id: UserId::from(poll.creator_id).0,
+        options,
+        voters: poll.voted_users.len(),
+        pending,
+        results,
+        sent_at: chrono::Utc::now(),
+    })?)
+}
+
+/// Count the last answer of each user for each option.
+pub fn tally(
+    conn: &mut SqliteConnection,
+    poll_id: &str,
+    options: usize,
+) -> QueryResult<Vec<usize>> {
+    let votes: Vec<(DbUserId, Sqlizer<Vec<usize>>)> =
+        schema::tracked_poll_votes::table
+            .filter(schema::tracked_poll_votes::tg_poll_id.eq(poll_id))
+            .order(schema::tracked_poll_votes::rowid)
+            .select((
+                schema::tracked_poll_votes::user_id,
+                schema::tracked_poll_votes::option_ids,
+            ))
+            .load(conn)?;
+    let last: HashMap<DbUserId, Sqlizer<Vec<usize>>> =
+        votes.into_iter().collect();
+    let mut results = vec![0; options];
+    for &i in last.values().flat_map(|v| v.iter()) {
+        if let Some(count) = results.get_mut(i) {
+            *count += 1;
+        }
+    }
+    Ok(results)
+}
+
+async fn deliver(client: reqwest::Client, hook: PollWebhook, body: String) {
+    let signature = signature(&hook.secret, &body);
+    for attempt in 1..=ATTEMPTS {
+        let result = client
+            .post(&hook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Botka-Signature", &signature)
+            .timeout(TIMEOUT)
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        match result {
+            Ok(_) => return,
+            Err(e) => log::warn!(
+                "poll_webhooks: attempt {attempt} to {} failed: {e}",
+                hook.url,
+            ),
+        }
+        if attempt < ATTEMPTS {
+            tokio::time::sleep(RETRY_DELAY * attempt).await;
+        }
+    }
+    log::error!("poll_webhooks: giving up on {}", hook.url);
+}
+
+fn signature(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts any key length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quorum_reached() {
+        assert!(!quorum_reached(50, 0, 0));
+        assert!(!quorum_reached(50, 5, 3));
+        assert!(quorum_reached(50, 4, 2));
+        assert!(quorum_reached(0, 4, 4));
+        assert!(quorum_reached(100, 4, 0));
+    }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature("SECRET", r#"{"event":"closed"}"#),
+            "sha256=5653f1c064435fc6a4c5e7a3c6ef0f5aa8f8f7e62eceab999641ad6443956ec4",
+        );
+    }
+}
@@ -1,27 +1,90 @@
 //! Intercept polls to track who voted and who didn't.
 //!
+//! Info messages are updated at most once per [`INFO_EDIT_DELAY`], to stay
+//! within flood limits in big votes.
+//!
+//! Votes cast while the bot is offline for more than a day are lost.  On
+//! startup, [`recover`] reads the state of open polls from copies forwarded
+//! to `telegram.chats.forward_channel`, closing polls stopped in the meantime
+//! and noting missed votes.
+//!
+//! Every answer is kept in `tracked_poll_votes`.  Replying `/pollexport` to
+//! the info message of a closed poll sends the final votes as a CSV file to
+//! the requester in private, for the archive.
+//!
+//! External tools can start tracked polls with `POST /polls` of the web API,
+//! see [`create_poll`], and follow them with the `poll_webhooks` module.
+//!
+//! In [`telegram.chats.private_polls`], info messages show only counts of
+//! voters and note that votes are recorded; residents see who hasn't voted
+//! by forwarding the poll to the bot in private.
+//!
+//! Tags after the `!` set options of the poll, and are removed from the copy
+//! sent by the bot: `[private]` or `[public]` overrides the mode of the chat,
+//! and `[announce]` cross-posts the poll, see [`poll_announcements`].
+//!
 //! **Scope**: all new non-anonymous polls created by residents, which start
-//! with the `!` character.
+//! with the `!` character; `/pollexport` command, available to residents;
+//! startup task.
+//!
+//! [`telegram.chats.private_polls`]: crate::config::TelegramChats::private_polls
 
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt::Write;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Result;
 use diesel::prelude::*;
+use macro_rules_attribute::derive;
 use teloxide::dispatching::UpdateFilterExt;
+use teloxide::macros::BotCommands;
 use teloxide::prelude::*;
 use teloxide::types::{
-    Forward, ForwardedFrom, InlineKeyboardButton, InlineKeyboardMarkup, Me,
-    MessageId, PollType, ReplyMarkup, User,
+    Forward, ForwardedFrom, InlineKeyboardButton, InlineKeyboardMarkup,
+    InputFile, Me, MessageId, PollType, ReplyMarkup, ThreadId, User,
 };
+use teloxide::{ApiError, RequestError};
 
 use crate::common::{
-    format_user, format_users, is_resident, BotEnv, UpdateHandler,
+    filter_command, format_user, format_users, BotCommandsExt, BotEnv,
+    UpdateHandler,
 };
-use crate::db::DbUserId;
+use crate::config::PollEvent;
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::models::{OutboxAction, UndoAction};
+use crate::modules::role_changes::{Role, RoleChange};
+use crate::modules::{activity_feed, poll_announcements, poll_webhooks, undo};
+use crate::outbox;
 use crate::utils::{format_to, BotExt, ResultExt, Sqlizer};
 use crate::{models, schema};
 
+/// How long to collect votes before updating the info message.  Editing on
+/// every vote hits flood limits in big votes.
+const INFO_EDIT_DELAY: Duration =
+    Duration::from_millis(if cfg!(test) { 50 } else { 3000 });
+
+lazy_static::lazy_static! {
+    /// Polls with a scheduled info message edit, by instance and poll id,
+    /// and whether they changed since the edit started.
+    static ref PENDING_INFO_EDITS: Mutex<HashMap<(String, String), bool>> =
+        Mutex::default();
+}
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "reply to the info message of a closed poll to \
+                             get its votes as a CSV file.")]
+    #[custom(resident = true)]
+    Pollexport,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_pollexport)
+}
+
 pub fn message_handler() -> UpdateHandler {
     dptree::filter_map(filter_polls).endpoint(handle_message)
 }
@@ -30,6 +93,10 @@ pub fn poll_answer_handler() -> UpdateHandler {
     Update::filter_poll_answer().endpoint(handle_poll_answer)
 }
 
+pub fn poll_handler() -> UpdateHandler {
+    Update::filter_poll().endpoint(handle_poll)
+}
+
 pub fn callback_handler() -> UpdateHandler {
     dptree::filter_map(filter_callbacks).endpoint(handle_callback)
 }
@@ -59,7 +126,7 @@ fn filter_polls(me: Me, env: Arc<BotEnv>, msg: Message) -> Option<PollKind> {
             from: ForwardedFrom::User(User { id, .. }), ..
         }) if id == &me.user.id
             && msg.chat.is_private()
-            && is_resident(&mut env.conn(), from) =>
+            && env.is_resident(from.id) =>
         {
             Some(PollKind::Forward(poll.id.clone()))
         }
@@ -84,19 +151,27 @@ fn check_new_poll_requirements(
     // Bots can't obtain information from quiz polls, so we can't track them
     // properly.
     check(poll.poll_type == PollType::Regular, "regular poll, not quiz");
-    check(is_resident(&mut env.conn(), from), "created by resident");
+    check(env.is_resident(from.id), "created by resident");
     diag_ok.then_some(()).ok_or(diag_text)
 }
 
 async fn handle_message(
     bot: Bot,
+    me: Me,
     msg: Message,
     env: Arc<BotEnv>,
     kind: PollKind,
 ) -> Result<()> {
     match kind {
         PollKind::New { poll, creator } => {
-            intercept_new_poll(bot, msg, &poll, creator, env).await
+            if can_replace_poll(&bot, &me, &msg).await? {
+                intercept_new_poll(bot, msg, &poll, creator, env).await
+            } else {
+                // Telegram reports votes only in polls sent by the bot, so
+                // the poll is left as is.
+                bot.reply_message(&msg, UNTRACKED_POLL_TEXT).await?;
+                Ok(())
+            }
         }
         PollKind::FailedDiag(chat_id, msg_id, diag_text) => {
             bot.send_message(
@@ -116,6 +191,16 @@ async fn handle_message(
     }
 }
 
+/// Whether the bot can send a poll in place of the user's one and delete
+/// the original.
+async fn can_replace_poll(bot: &Bot, me: &Me, msg: &Message) -> Result<bool> {
+    if msg.chat.is_private() {
+        return Ok(true);
+    }
+    let member = bot.get_chat_member(msg.chat.id, me.user.id).await?;
+    Ok(member.kind.can_delete_messages() && member.kind.can_send_polls())
+}
+
 async fn intercept_new_poll(
     bot: Bot,
     msg: Message,
@@ -123,10 +208,11 @@ async fn intercept_new_poll(
     creator: User,
     env: Arc<BotEnv>,
 ) -> Result<()> {
+    let (tags, question) = split_tags(&poll.question);
     let mut new_poll = bot
         .send_poll(
             msg.chat.id,
-            &poll.question,
+            question,
             poll.options.iter().map(|o| o.text.clone()),
         )
         .is_anonymous(poll.is_anonymous)
@@ -136,22 +222,128 @@ async fn intercept_new_poll(
     new_poll.reply_to_message_id = msg.reply_to_message().map(|m| m.id);
     let new_poll = new_poll.await?;
 
-    let Some(new_poll_id) = new_poll.poll().map(|p| &p.id) else {
+    let Some(new_poll_id) = new_poll.poll().map(|p| p.id.clone()) else {
         bot.delete_message(msg.chat.id, msg.id)
             .await
             .log_error("delete message");
         anyhow::bail!("Expected poll, got {new_poll:?}");
     };
 
-    if let Err(e) = bot.delete_message(msg.chat.id, msg.id).await {
-        // TODO: check rights before sending message
-        bot.delete_message(msg.chat.id, new_poll.id)
-            .await
-            .log_error("delete message");
-        anyhow::bail!("Failed to delete poll message: {e}");
-    }
+    // The original poll is deleted only if the new one is tracked.  If the
+    // deletion fails, e.g. due to missing rights, the new poll is removed to
+    // avoid duplicates.
+    let track = |conn: &mut SqliteConnection, poll_info: &Message| {
+        let delete = |message_id| OutboxAction::DeleteMessage {
+            chat_id: msg.chat.id,
+            message_id,
+        };
+        outbox::enqueue(
+            conn,
+            delete(msg.id),
+            vec![delete(new_poll.id), delete(poll_info.id)],
+        )?;
+        let undo_delete =
+            |message_id| UndoAction::Telegram { action: delete(message_id) };
+        // The original poll can't be restored, so a plain copy is sent.
+        let send_original = OutboxAction::SendPoll {
+            chat_id: msg.chat.id,
+            thread_id: msg.thread_id,
+            question: poll.question.clone(),
+            options: poll.options.iter().map(|o| o.text.clone()).collect(),
+            is_anonymous: poll.is_anonymous,
+            allows_multiple_answers: poll.allows_multiple_answers,
+        };
+        undo::push(
+            conn,
+            msg.chat.id,
+            creator.id,
+            "poll replacement",
+            vec![
+                undo_delete(new_poll.id),
+                undo_delete(poll_info.id),
+                UndoAction::ClosePoll { poll_id: new_poll_id },
+                UndoAction::Telegram { action: send_original },
+            ],
+        )
+    };
+    track_poll_with(&bot, &env, &new_poll, &creator, tags, track).await?;
+    outbox::wake(&env);
+    Ok(())
+}
 
-    let non_voters = db_find_non_voters(&mut env.conn(), &[]);
+/// Start tracking a poll sent by the bot: reply to it with the info message
+/// listing residents who haven't voted yet.
+pub async fn track_poll(
+    bot: &Bot,
+    env: &BotEnv,
+    poll_message: &Message,
+    creator: &User,
+) -> Result<()> {
+    let tags = poll_message
+        .poll()
+        .map_or_else(PollTags::default, |p| split_tags(&p.question).0);
+    track_poll_with(bot, env, poll_message, creator, tags, |_, _| Ok(()))
+        .await?;
+    Ok(())
+}
+
+/// A poll sent by [`create_poll`].
+#[derive(Debug)]
+pub struct CreatedPoll {
+    pub poll_id: String,
+    pub poll_message: MessageId,
+    pub info_message: MessageId,
+}
+
+/// Send a new non-anonymous poll on behalf of the bot and track it, for
+/// polls requested outside of Telegram.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_poll(
+    bot: &Bot,
+    env: &BotEnv,
+    chat: ChatId,
+    thread: Option<ThreadId>,
+    question: &str,
+    options: Vec<String>,
+    allows_multiple_answers: bool,
+    tags: PollTags,
+) -> Result<CreatedPoll> {
+    let me = bot.get_me().await?;
+    let mut request = bot
+        .send_poll(chat, question, options)
+        .is_anonymous(false)
+        .allows_multiple_answers(allows_multiple_answers);
+    request.message_thread_id = thread;
+    let poll_message = request.await?;
+    let Some(poll) = poll_message.poll() else {
+        anyhow::bail!("Expected poll, got {poll_message:?}");
+    };
+    let info_message =
+        track_poll_with(bot, env, &poll_message, &me.user, tags, |_, _| Ok(()))
+            .await?;
+    Ok(CreatedPoll {
+        poll_id: poll.id.clone(),
+        poll_message: poll_message.id,
+        info_message,
+    })
+}
+
+/// Like [`track_poll`], also calling `with` in the transaction that stores
+/// the poll, with the info message.  Returns the info message.
+async fn track_poll_with(
+    bot: &Bot,
+    env: &BotEnv,
+    poll_message: &Message,
+    creator: &User,
+    tags: PollTags,
+    with: impl FnOnce(&mut SqliteConnection, &Message) -> QueryResult<()>,
+) -> Result<MessageId> {
+    let Some(poll) = poll_message.poll() else {
+        anyhow::bail!("Expected poll, got {poll_message:?}");
+    };
+    let private = is_private(env, poll_message.chat.id, tags.private);
+
+    let non_voters = db_find_non_voters(&mut env.conn(), &[])?;
 
     let creator_info = models::TgUser {
         id: creator.id.into(),
@@ -162,26 +354,57 @@ async fn intercept_new_poll(
 
     let poll_info = bot
         .reply_message(
-            &msg,
-            poll_text((creator.id.into(), Some(creator_info)), &non_voters?, 0),
+            poll_message,
+            poll_text(
+                (creator.id.into(), Some(creator_info)),
+                &non_voters,
+                0,
+                private,
+            ),
         )
-        .reply_to_message_id(new_poll.id)
         .parse_mode(teloxide::types::ParseMode::Html)
-        .reply_markup(ReplyMarkup::InlineKeyboard(make_keyboard(&poll.id)))
         .disable_web_page_preview(true)
+        .reply_markup(ReplyMarkup::InlineKeyboard(make_keyboard(&poll.id)))
         .await?;
 
-    diesel::insert_into(schema::tracked_polls::table)
-        .values(&models::TrackedPoll {
-            tg_poll_id: new_poll_id.clone(),
-            creator_id: creator.id.into(),
-            info_chat_id: poll_info.chat.id.into(),
-            info_message_id: poll_info.id.into(),
-            voted_users: Sqlizer::new(Vec::new()).unwrap(),
-        })
-        .execute(&mut *env.conn())?;
+    let db_poll = models::TrackedPoll {
+        tg_poll_id: poll.id.clone(),
+        creator_id: creator.id.into(),
+        info_chat_id: poll_info.chat.id.into(),
+        info_message_id: poll_info.id.into(),
+        voted_users: Sqlizer::new(Vec::new()).unwrap(),
+        poll_message_id: Some(poll_message.id.into()),
+        closed_at: None,
+        options: Some(
+            Sqlizer::new(poll.options.iter().map(|o| o.text.clone()).collect())
+                .unwrap(),
+        ),
+        quorum_reached_at: None,
+        private,
+    };
+    env.transaction(|conn| {
+        diesel::insert_into(schema::tracked_polls::table)
+            .values(&db_poll)
+            .execute(conn)?;
+        activity_feed::record(
+            conn,
+            "polls",
+            Some(creator.id),
+            "created a poll",
+            &poll.question,
+            poll_message.url().as_ref().map(|u| u.as_str()),
+        )?;
+        with(conn, &poll_info)
+    })?;
+    poll_webhooks::notify(env, PollEvent::Created, &db_poll, non_voters.len());
+    if tags.announce {
+        let question = split_tags(&poll.question).1;
+        poll_announcements::announce(bot, env, poll_message, &question)
+            .await
+            .log_error("polls: announce");
+    }
 
-    Ok(())
+    Ok(poll_info.id)
 }
 
 async fn hande_poll_forward(
@@ -226,12 +449,30 @@ async fn handle_poll_answer(
     poll_answer: PollAnswer,
     env: Arc<BotEnv>,
 ) -> Result<()> {
-    let update = env.transaction(|conn| {
-        let Some((db_poll, creator)) =
-            db_find_poll(conn, &poll_answer.poll_id)?
+    let updated = env.transaction(|conn| {
+        let Some((db_poll, _)) = db_find_poll(conn, &poll_answer.poll_id)?
         else {
             return Ok(None);
         };
+        if db_poll.closed_at.is_some() {
+            return Ok(None);
+        }
+
+        diesel::insert_into(schema::tracked_poll_votes::table)
+            .values(models::NewTrackedPollVote {
+                tg_poll_id: &poll_answer.poll_id,
+                user_id: poll_answer.user.id.into(),
+                option_ids: Sqlizer::new(
+                    poll_answer
+                        .option_ids
+                        .iter()
+                        .filter_map(|&id| usize::try_from(id).ok())
+                        .collect(),
+                )
+                .unwrap(),
+                voted_at: chrono::Utc::now().naive_utc(),
+            })
+            .execute(conn)?;
 
         let mut voted_users = (*db_poll.voted_users).clone();
         if poll_answer.option_ids.is_empty() {
@@ -242,46 +483,303 @@ async fn handle_poll_answer(
         voted_users.sort();
         voted_users.dedup();
 
+        // Number of pending voters, if the poll has just reached the quorum.
+        let quorum = match &env.config.telegram.poll_webhooks {
+            Some(conf) if db_poll.quorum_reached_at.is_none() => {
+                let pending = db_find_non_voters(conn, &voted_users)?.len();
+                let residents = db_count_residents(conn)?;
+                poll_webhooks::quorum_reached(
+                    conf.quorum_percent,
+                    residents,
+                    pending,
+                )
+                .then_some(pending)
+            }
+            _ => None,
+        };
+        let db_poll = models::TrackedPoll {
+            voted_users: Sqlizer::new(voted_users).unwrap(),
+            quorum_reached_at: db_poll
+                .quorum_reached_at
+                .or_else(|| quorum.map(|_| chrono::Utc::now().naive_utc())),
+            ..db_poll
+        };
+
         diesel::update(schema::tracked_polls::table)
             .filter(schema::tracked_polls::tg_poll_id.eq(&poll_answer.poll_id))
-            .set(
-                schema::tracked_polls::voted_users
-                    .eq(Sqlizer::new(voted_users.clone()).unwrap()),
-            )
+            .set((
+                schema::tracked_polls::voted_users.eq(&db_poll.voted_users),
+                schema::tracked_polls::quorum_reached_at
+                    .eq(db_poll.quorum_reached_at),
+            ))
             .execute(conn)?;
+        Ok(Some(quorum.map(|pending| (db_poll, pending))))
+    })?;
 
-        let non_voters = db_find_non_voters(conn, &voted_users)?;
+    let Some(quorum) = updated else { return Ok(()) };
+    if let Some((db_poll, pending)) = quorum {
+        poll_webhooks::notify(&env, PollEvent::Quorum, &db_poll, pending);
+    }
+    schedule_info_edit(bot, env, poll_answer.poll_id);
+
+    Ok(())
+}
+
+/// Refresh the lists of pending voters when someone becomes or stops being a
+/// resident.
+pub fn on_role_change(
+    env: &Arc<BotEnv>,
+    bot: &Bot,
+    change: &RoleChange,
+) -> Result<()> {
+    if change.old != Role::Resident && change.new != Role::Resident {
+        return Ok(());
+    }
+    let poll_ids: Vec<String> = schema::tracked_polls::table
+        .filter(schema::tracked_polls::closed_at.is_null())
+        .select(schema::tracked_polls::tg_poll_id)
+        .load(&mut *env.conn())?;
+    for poll_id in poll_ids {
+        schedule_info_edit(bot.clone(), Arc::clone(env), poll_id);
+    }
+    Ok(())
+}
+
+/// Update the info message of the poll after [`INFO_EDIT_DELAY`], coalescing
+/// with other updates of the same poll scheduled in the meantime.
+fn schedule_info_edit(bot: Bot, env: Arc<BotEnv>, poll_id: String) {
+    let key = (env.instance.clone(), poll_id.clone());
+    {
+        let mut pending = PENDING_INFO_EDITS.lock().unwrap();
+        if let Some(dirty) = pending.get_mut(&key) {
+            // The running editor will pick up the change.
+            *dirty = true;
+            return;
+        }
+        pending.insert(key.clone(), false);
+    }
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(INFO_EDIT_DELAY).await;
+            PENDING_INFO_EDITS.lock().unwrap().insert(key.clone(), false);
+
+            // The text is built from the database, so the last edit always
+            // reflects the final state.
+            edit_info(&bot, &env, &poll_id)
+                .await
+                .log_error("polls: edit info message");
+
+            let mut pending = PENDING_INFO_EDITS.lock().unwrap();
+            if pending.get(&key) != Some(&true) {
+                pending.remove(&key);
+                break;
+            }
+        }
+    });
+}
 
-        Ok(Some((
+async fn edit_info(bot: &Bot, env: &BotEnv, poll_id: &str) -> Result<()> {
+    let info = env.transaction(|conn| {
+        let Some((db_poll, creator)) = db_find_poll(conn, poll_id)? else {
+            return Ok(None);
+        };
+        if db_poll.closed_at.is_some() {
+            return Ok(None);
+        }
+        let non_voters = db_find_non_voters(conn, &db_poll.voted_users)?;
+        Ok(Some((db_poll, creator, non_voters)))
+    })?;
+    let Some((db_poll, creator, non_voters)) = info else {
+        return Ok(());
+    };
+
+    let result = bot
+        .edit_message_text(
             db_poll.info_chat_id,
-            db_poll.info_message_id,
-            (db_poll.creator_id, creator),
-            non_voters,
-            voted_users.len(),
-        )))
+            db_poll.info_message_id.into(),
+            poll_text(
+                (db_poll.creator_id, creator),
+                &non_voters,
+                db_poll.voted_users.len(),
+                db_poll.private,
+            ),
+        )
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .reply_markup(make_keyboard(poll_id))
+        .disable_web_page_preview(true)
+        .await;
+    match result {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Telegram sends the state of polls sent by the bot when it changes.
+async fn handle_poll(bot: Bot, env: Arc<BotEnv>, poll: Poll) -> Result<()> {
+    if poll.is_closed {
+        close_poll(&bot, &env, &poll.id).await?;
+    }
+    Ok(())
+}
+
+/// Mark the poll as closed and replace the info message with the results.
+async fn close_poll(bot: &Bot, env: &BotEnv, poll_id: &str) -> Result<()> {
+    let closed = env.transaction(|conn| {
+        let Some((db_poll, creator)) = db_find_poll(conn, poll_id)? else {
+            return Ok(None);
+        };
+        if db_poll.closed_at.is_some() {
+            return Ok(None);
+        }
+        let closed_at = chrono::Utc::now().naive_utc();
+        diesel::update(schema::tracked_polls::table)
+            .filter(schema::tracked_polls::tg_poll_id.eq(poll_id))
+            .set(schema::tracked_polls::closed_at.eq(closed_at))
+            .execute(conn)?;
+        let url =
+            ChatId::from(db_poll.info_chat_id).channel_t_me_id().map(|c| {
+                format!("https://t.me/c/{c}/{}", db_poll.info_message_id.0)
+            });
+        activity_feed::record(
+            conn,
+            "polls",
+            None,
+            "closed a poll",
+            &db_poll
+                .options
+                .as_ref()
+                .map_or_else(String::new, |o| o.join(" / ")),
+            url.as_deref(),
+        )?;
+        let pending = db_find_non_voters(conn, &db_poll.voted_users)?.len();
+        let db_poll =
+            models::TrackedPoll { closed_at: Some(closed_at), ..db_poll };
+        Ok(Some((db_poll, creator, pending)))
     })?;
+    let Some((db_poll, creator, pending)) = closed else { return Ok(()) };
+    poll_webhooks::notify(env, PollEvent::Closed, &db_poll, pending);
+    poll_announcements::finish(bot, env, &db_poll)
+        .await
+        .log_error("polls: finish announcements");
+
+    let mut text = String::from("Poll by ");
+    format_user(&mut text, db_poll.creator_id, &creator, true);
+    match db_poll.voted_users.len() {
+        // Votes in polls not sent by the bot are unknown.
+        0 => text.push_str(". Closed."),
+        voters => format_to!(
+            text,
+            ". Closed, voted {voters} user{}.",
+            if voters == 1 { "" } else { "s" },
+        ),
+    }
+    bot.edit_message_text(
+        db_poll.info_chat_id,
+        db_poll.info_message_id.into(),
+        text,
+    )
+    .parse_mode(teloxide::types::ParseMode::Html)
+    .disable_web_page_preview(true)
+    .await?;
+    Ok(())
+}
 
-    let Some((
-        info_chat_id,
-        info_message_id,
-        creator,
-        non_voters,
-        total_voters,
-    )) = update
-    else {
+/// Bring open polls up to date after the bot was offline.
+pub async fn recover(env: Arc<BotEnv>, bot: Bot) {
+    let polls: QueryResult<Vec<models::TrackedPoll>> =
+        schema::tracked_polls::table
+            .filter(schema::tracked_polls::closed_at.is_null())
+            .load(&mut *env.conn());
+    let polls = match polls {
+        Ok(polls) => polls,
+        Err(e) => {
+            log::error!("polls: failed to load open polls: {e}");
+            return;
+        }
+    };
+    for db_poll in polls {
+        if let Err(e) = recover_poll(&bot, &env, &db_poll).await {
+            log::error!(
+                "polls: failed to recover poll {}: {e}",
+                db_poll.tg_poll_id
+            );
+        }
+    }
+}
+
+async fn recover_poll(
+    bot: &Bot,
+    env: &BotEnv,
+    db_poll: &models::TrackedPoll,
+) -> Result<()> {
+    let Some(poll_message_id) = db_poll.poll_message_id else {
+        // Tracked before poll messages were stored.
         return Ok(());
     };
 
+    // Bots can't fetch polls, but a forwarded copy contains the poll state.
+    let copy = bot
+        .forward_message(
+            env.config.telegram.chats.forward_channel,
+            db_poll.info_chat_id,
+            poll_message_id.into(),
+        )
+        .disable_notification(true)
+        .await;
+    let copy = match copy {
+        Ok(copy) => copy,
+        Err(RequestError::Api(ApiError::MessageToForwardNotFound)) => {
+            return close_poll(bot, env, &db_poll.tg_poll_id).await;
+        }
+        Err(e) => return Err(e.into()),
+    };
+    bot.delete_message(copy.chat.id, copy.id)
+        .await
+        .log_error("polls: delete forwarded poll");
+    let Some(poll) = copy.poll() else {
+        anyhow::bail!("Expected poll, got {copy:?}");
+    };
+
+    if poll.is_closed {
+        return close_poll(bot, env, &db_poll.tg_poll_id).await;
+    }
+
+    // Votes are known only for polls sent by the bot.
+    let own_poll = copy.forward_from_user().is_some_and(|u| u.is_bot);
+    let missed = usize::try_from(poll.total_voter_count)
+        .unwrap_or_default()
+        .saturating_sub(db_poll.voted_users.len());
+    if !own_poll || missed == 0 {
+        return Ok(());
+    }
+
+    let (creator, non_voters) = env.transaction(|conn| {
+        let creator = db_find_poll(conn, &db_poll.tg_poll_id)?
+            .and_then(|(_, creator)| creator);
+        let non_voters = db_find_non_voters(conn, &db_poll.voted_users)?;
+        Ok((creator, non_voters))
+    })?;
+    let mut text = poll_text(
+        (db_poll.creator_id, creator),
+        &non_voters,
+        db_poll.voted_users.len(),
+        db_poll.private,
+    );
+    format_to!(
+        text,
+        "\n{missed} vote{} cast while the bot was offline not shown.",
+        if missed == 1 { "" } else { "s" },
+    );
     bot.edit_message_text(
-        info_chat_id,
-        info_message_id.into(),
-        poll_text(creator, &non_voters, total_voters),
+        db_poll.info_chat_id,
+        db_poll.info_message_id.into(),
+        text,
     )
     .parse_mode(teloxide::types::ParseMode::Html)
-    .reply_markup(make_keyboard(&poll_answer.poll_id))
+    .reply_markup(make_keyboard(&db_poll.tg_poll_id))
     .disable_web_page_preview(true)
     .await?;
-
     Ok(())
 }
 
@@ -329,10 +827,11 @@ async fn handle_callback(
         return Ok(());
     }
 
-    // TODO: store poll message id in the database
-    let poll_message =
-        callback.message.as_ref().and_then(|m| m.reply_to_message());
-    let Some(poll_message) = poll_message else {
+    let poll_message_id = db_poll
+        .poll_message_id
+        .map(MessageId::from)
+        .or_else(|| Some(callback.message.as_ref()?.reply_to_message()?.id));
+    let Some(poll_message_id) = poll_message_id else {
         bot.answer_callback_query(&callback.id)
             .text("Poll message not found.")
             .await?;
@@ -354,8 +853,16 @@ async fn handle_callback(
         }
         Action::Confirm => {
             bot.answer_callback_query(&callback.id).await?;
-            bot.stop_poll(db_poll.info_chat_id, poll_message.id).await?;
-            None
+            let poll =
+                bot.stop_poll(db_poll.info_chat_id, poll_message_id).await?;
+            crate::modules::mail_bridge::send_poll_results(
+                &env,
+                &poll,
+                (db_poll.info_chat_id.into(), db_poll.info_message_id.into()),
+            )
+            .await
+            .log_error("mail_bridge::send_poll_results");
+            return close_poll(&bot, &env, &stop.poll_id).await;
         }
         Action::Cancel => {
             bot.answer_callback_query(&callback.id).await?;
@@ -373,10 +880,136 @@ async fn handle_callback(
     Ok(())
 }
 
+async fn cmd_pollexport(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(info) = msg.reply_to_message() else {
+        bot.reply_message(&msg, "Reply to the info message of a closed poll.")
+            .await?;
+        return Ok(());
+    };
+    let db_poll: Option<models::TrackedPoll> = schema::tracked_polls::table
+        .filter(
+            schema::tracked_polls::info_chat_id.eq(DbChatId::from(msg.chat.id)),
+        )
+        .filter(
+            schema::tracked_polls::info_message_id
+                .eq(DbMessageId::from(info.id)),
+        )
+        .select(models::TrackedPoll::as_select())
+        .first(&mut *env.conn())
+        .optional()?;
+    let Some(db_poll) = db_poll else {
+        bot.reply_message(&msg, "This is not a poll info message.").await?;
+        return Ok(());
+    };
+    let Some(closed_at) = db_poll.closed_at else {
+        bot.reply_message(&msg, "The poll is still open.").await?;
+        return Ok(());
+    };
+
+    let Some(csv) = export_votes(&mut env.conn(), &db_poll)? else {
+        bot.reply_message(&msg, "No votes were recorded in this poll.").await?;
+        return Ok(());
+    };
+    let file_name = format!("poll-{}.csv", closed_at.format("%Y-%m-%d"));
+    let sent = bot
+        .send_document(from.id, InputFile::memory(csv).file_name(file_name))
+        .await;
+    let text = match sent {
+        Ok(_) => "Sent the votes in a private message.",
+        Err(RequestError::Api(_)) => {
+            "Can't send you a private message, start a chat with me first."
+        }
+        Err(e) => return Err(e.into()),
+    };
+    bot.reply_message(&msg, text).await?;
+    Ok(())
+}
+
+/// Final votes in the poll as CSV, one row per voter in the order of their
+/// last answer, or `None` if there are no votes.
+fn export_votes(
+    conn: &mut SqliteConnection,
+    db_poll: &models::TrackedPoll,
+) -> Result<Option<Vec<u8>>> {
+    let votes: Vec<(models::TrackedPollVote, Option<models::TgUser>)> =
+        schema::tracked_poll_votes::table
+            .filter(
+                schema::tracked_poll_votes::tg_poll_id.eq(&db_poll.tg_poll_id),
+            )
+            .left_join(schema::tg_users::table.on(
+                schema::tracked_poll_votes::user_id.eq(schema::tg_users::id),
+            ))
+            .order(schema::tracked_poll_votes::rowid)
+            .select((
+                models::TrackedPollVote::as_select(),
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(conn)?;
+    let last: HashMap<DbUserId, i32> =
+        votes.iter().map(|(v, _)| (v.user_id, v.rowid)).collect();
+    let votes = votes
+        .iter()
+        .filter(|(v, _)| {
+            last[&v.user_id] == v.rowid && !v.option_ids.is_empty()
+        })
+        .collect::<Vec<_>>();
+    if votes.is_empty() {
+        return Ok(None);
+    }
+
+    let options = db_poll.options.as_deref().map_or(&[][..], Vec::as_slice);
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "user_id",
+        "username",
+        "name",
+        "options",
+        "voted_at_utc",
+    ])?;
+    for (vote, user) in votes {
+        let name = user.as_ref().map(|u| match &u.last_name {
+            Some(last_name) => format!("{} {last_name}", u.first_name),
+            None => u.first_name.clone(),
+        });
+        let chosen = vote
+            .option_ids
+            .iter()
+            .map(|&i| {
+                options.get(i).cloned().unwrap_or_else(|| format!("#{}", i + 1))
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        writer.write_record([
+            UserId::from(vote.user_id).to_string(),
+            user.as_ref().and_then(|u| u.username.clone()).unwrap_or_default(),
+            name.unwrap_or_default(),
+            chosen,
+            vote.voted_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        ])?;
+    }
+    Ok(Some(writer.into_inner().map_err(|e| e.into_error())?))
+}
+
+/// Reply to polls the bot can't replace with a copy it can track.
+const UNTRACKED_POLL_TEXT: &str = "Votes in this poll can't be tracked: the \
+    bot only sees votes in polls it sends itself.  Allow it to delete \
+    messages and send polls here to have new polls replaced with tracked \
+    copies.";
+
+/// Appended to the info message of private polls.
+const PRIVATE_POLL_NOTE: &str = "Votes are recorded by the bot.  Forward \
+    the poll to the bot in private to see who hasn't voted.\n";
+
 fn poll_text(
     creator: (DbUserId, Option<models::TgUser>),
     non_voters: &[(DbUserId, Option<models::TgUser>)],
     total_voters: usize,
+    private: bool,
 ) -> String {
     let mut text = String::new();
 
@@ -386,6 +1019,16 @@ fn poll_text(
 
     if non_voters.is_empty() {
         text.push_str("Everyone voted!");
+    } else if private {
+        write!(
+            text,
+            "Voted {} user{}, pending vote {} user{}.\n{PRIVATE_POLL_NOTE}",
+            total_voters,
+            if total_voters == 1 { "" } else { "s" },
+            non_voters.len(),
+            if non_voters.len() == 1 { "" } else { "s" },
+        )
+        .unwrap();
     } else {
         write!(
             text,
@@ -403,6 +1046,51 @@ fn poll_text(
     text
 }
 
+/// Options of a poll, set by tags after the leading `!` of the question.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PollTags {
+    /// `[private]` or `[public]`, overrides `telegram.chats.private_polls`.
+    pub private: Option<bool>,
+    /// `[announce]`, see [`poll_announcements`].
+    pub announce: bool,
+}
+
+/// Split the tags after the leading `!` off the question, e.g.
+/// `![private] [announce] Pizza?` is `!Pizza?` with both tags.
+fn split_tags(question: &str) -> (PollTags, Cow<'_, str>) {
+    let mut tags = PollTags::default();
+    let Some(mut rest) = question.strip_prefix('!') else {
+        return (tags, question.into());
+    };
+    loop {
+        rest = rest.trim_start();
+        if let Some(r) = rest.strip_prefix("[private]") {
+            tags.private = Some(true);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("[public]") {
+            tags.private = Some(false);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("[announce]") {
+            tags.announce = true;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+    if tags == PollTags::default() {
+        return (tags, question.into());
+    }
+    (tags, format!("!{rest}").into())
+}
+
+/// Whether a poll in the chat is private, with the `tag` of the poll
+/// overriding the mode of the chat.
+fn is_private(env: &BotEnv, chat: ChatId, tag: Option<bool>) -> bool {
+    tag.unwrap_or_else(|| {
+        env.config.telegram.chats.private_polls.contains(&chat)
+    })
+}
+
 fn make_keyboard(poll_id: &str) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
         "Stop poll",
@@ -440,6 +1128,14 @@ fn db_find_poll(
         .optional()
 }
 
+fn db_count_residents(conn: &mut SqliteConnection) -> QueryResult<usize> {
+    let count: i64 = schema::residents::table
+        .filter(schema::residents::end_date.is_null())
+        .count()
+        .get_result(conn)?;
+    Ok(usize::try_from(count).unwrap_or_default())
+}
+
 fn db_find_non_voters(
     conn: &mut SqliteConnection,
     voted_users: &[DbUserId],
@@ -458,3 +1154,406 @@ fn db_find_non_voters(
         ))
         .load(conn)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_111_111_111;
+
+    fn handler() -> UpdateHandler {
+        dptree::entry()
+            .branch(
+                Update::filter_message()
+                    .branch(command_handler())
+                    .branch(message_handler()),
+            )
+            .branch(poll_answer_handler())
+            .branch(poll_handler())
+    }
+
+    fn load_poll(t: &TestBot) -> models::TrackedPoll {
+        schema::tracked_polls::table.first(&mut *t.env.conn()).unwrap()
+    }
+
+    async fn wait_info_edit(t: &TestBot, poll_id: &str) {
+        let key = (t.env.instance.clone(), poll_id.to_string());
+        while PENDING_INFO_EDITS.lock().unwrap().contains_key(&key) {
+            tokio::time::sleep(INFO_EDIT_DELAY / 10).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_poll_flow() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+
+        // The poll is re-sent by the bot and tracked.
+        t.dispatch(
+            &handler(),
+            testing::poll_message(CHAT, &alice, "!Pizza?", &["Yes", "No"]),
+        )
+        .await;
+        let polls = t.telegram.calls("sendPoll");
+        assert_eq!(polls.len(), 1);
+        assert_eq!(polls[0]["question"], "!Pizza?");
+        assert!(t.telegram.calls("deleteMessage").is_empty());
+        outbox::process(&t.env, &t.bot).await;
+        assert_eq!(t.telegram.calls("deleteMessage").len(), 1);
+        let info = t.telegram.calls("sendMessage");
+        assert_eq!(info.len(), 1);
+        let text = info[0]["text"].as_str().unwrap();
+        assert!(text.contains("pending vote 2 users"), "{text}");
+
+        let poll_id = t.telegram.results("sendPoll")[0]["poll"]["id"].clone();
+        let poll_id = poll_id.as_str().unwrap();
+        let poll = load_poll(&t);
+        assert_eq!(poll.tg_poll_id, poll_id);
+        assert_eq!(poll.creator_id, DbUserId::from(UserId(1)));
+        assert!(poll.voted_users.is_empty());
+        assert!(poll.quorum_reached_at.is_none());
+        t.telegram.clear();
+
+        // A vote updates the info message.  Half of residents is the quorum
+        // in the example config.
+        t.dispatch(&handler(), testing::poll_answer(poll_id, &bob, &[0])).await;
+        let poll = load_poll(&t);
+        assert_eq!(*poll.voted_users, [DbUserId::from(UserId(2))]);
+        assert!(poll.quorum_reached_at.is_some());
+        wait_info_edit(&t, poll_id).await;
+        let edits = t.telegram.calls("editMessageText");
+        assert_eq!(edits.len(), 1);
+        let text = edits[0]["text"].as_str().unwrap();
+        assert!(text.contains("Voted 1 user, pending vote 1 user"), "{text}");
+        t.telegram.clear();
+
+        // A retracted vote too.
+        t.dispatch(&handler(), testing::poll_answer(poll_id, &bob, &[])).await;
+        assert!(load_poll(&t).voted_users.is_empty());
+        wait_info_edit(&t, poll_id).await;
+        assert_eq!(t.telegram.calls("editMessageText").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_undo_poll_replacement() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let alice = testing::user_json(1, "Alice");
+        t.dispatch(
+            &handler(),
+            testing::poll_message(CHAT, &alice, "!Pizza?", &["Yes", "No"]),
+        )
+        .await;
+        outbox::process(&t.env, &t.bot).await;
+        let bot_poll = t.telegram.results("sendPoll")[0]["message_id"].clone();
+        let info = t.telegram.results("sendMessage")[0]["message_id"].clone();
+        t.telegram.clear();
+
+        t.dispatch(
+            &Update::filter_message().branch(undo::command_handler()),
+            testing::message(CHAT, None, &alice, "/undo"),
+        )
+        .await;
+        outbox::process(&t.env, &t.bot).await;
+
+        // The bot's copy is removed and the original poll is sent back, not
+        // tracked.
+        let deleted = t
+            .telegram
+            .calls("deleteMessage")
+            .into_iter()
+            .map(|c| c["message_id"].clone())
+            .collect::<Vec<_>>();
+        assert!(deleted.contains(&bot_poll) && deleted.contains(&info));
+        let polls = t.telegram.calls("sendPoll");
+        assert_eq!(polls.len(), 1);
+        assert_eq!(polls[0]["question"], "!Pizza?");
+        assert_eq!(polls[0]["options"], serde_json::json!(["Yes", "No"]));
+        assert!(load_poll(&t).closed_at.is_some());
+        let tracked = schema::tracked_polls::table
+            .count()
+            .get_result::<i64>(&mut *t.env.conn())
+            .unwrap();
+        assert_eq!(tracked, 1);
+    }
+
+    #[tokio::test]
+    async fn test_poll_by_non_resident() {
+        let t = TestBot::new();
+        let mallory = testing::user_json(3, "Mallory");
+        t.dispatch(
+            &handler(),
+            testing::poll_message(CHAT, &mallory, "!Pizza?", &["Yes", "No"]),
+        )
+        .await;
+        assert!(t.telegram.calls("sendPoll").is_empty());
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        let text = sent[0]["text"].as_str().unwrap();
+        assert!(text.contains("❌ created by resident"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn test_private_poll() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        let alice = testing::user_json(1, "Alice");
+        t.dispatch(
+            &handler(),
+            testing::poll_message(
+                CHAT,
+                &alice,
+                "![private] Pizza?",
+                &["Yes", "No"],
+            ),
+        )
+        .await;
+
+        // The tag is removed, and pending voters are not listed.
+        let polls = t.telegram.calls("sendPoll");
+        assert_eq!(polls[0]["question"], "!Pizza?");
+        let info = t.telegram.calls("sendMessage");
+        let text = info[0]["text"].as_str().unwrap();
+        assert!(text.contains("pending vote 2 users."), "{text}");
+        assert!(text.contains(PRIVATE_POLL_NOTE), "{text}");
+        assert!(!text.contains("Bob"), "{text}");
+        assert!(load_poll(&t).private);
+    }
+
+    #[test]
+    fn test_split_tags() {
+        let none = PollTags::default();
+        assert_eq!(split_tags("! Pizza?"), (none, "! Pizza?".into()));
+        assert_eq!(
+            split_tags("![public]Pizza?"),
+            (PollTags { private: Some(false), ..none }, "!Pizza?".into())
+        );
+        assert_eq!(
+            split_tags("![announce] [private] Pizza?"),
+            (
+                PollTags { private: Some(true), announce: true },
+                "!Pizza?".into()
+            )
+        );
+        assert_eq!(
+            split_tags("[private] Pizza?"),
+            (none, "[private] Pizza?".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poll_without_delete_rights() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let alice = testing::user_json(1, "Alice");
+        t.dispatch(
+            &handler(),
+            testing::poll_message(CHAT, &alice, "!Pizza?", &["Yes", "No"]),
+        )
+        .await;
+        let poll = t.telegram.results("sendPoll")[0].clone();
+        let info = t.telegram.results("sendMessage")[0].clone();
+
+        // The copy sent by the bot is removed as well.
+        t.telegram
+            .fail("deleteMessage", "Bad Request: message can't be deleted");
+        outbox::process(&t.env, &t.bot).await;
+        let deleted = t.telegram.calls("deleteMessage");
+        assert_eq!(deleted.len(), 3);
+        assert_eq!(deleted[1]["message_id"], poll["message_id"]);
+        assert_eq!(deleted[2]["message_id"], info["message_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_poll_without_rights() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let alice = testing::user_json(1, "Alice");
+        t.telegram.respond(
+            "getChatMember",
+            serde_json::json!({
+                "status": "member",
+                "user": { "id": 1, "is_bot": true, "first_name": "Botka" },
+            }),
+        );
+        t.dispatch(
+            &handler(),
+            testing::poll_message(CHAT, &alice, "!Pizza?", &["Yes", "No"]),
+        )
+        .await;
+        outbox::process(&t.env, &t.bot).await;
+
+        // The original poll is kept, but not tracked.
+        assert!(t.telegram.calls("sendPoll").is_empty());
+        assert!(t.telegram.calls("deleteMessage").is_empty());
+        let sent = t.telegram.calls("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["text"], UNTRACKED_POLL_TEXT);
+        assert_eq!(
+            schema::tracked_polls::table
+                .count()
+                .get_result::<i64>(&mut *t.env.conn())
+                .unwrap(),
+            0,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poll_closed() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+        t.dispatch(
+            &handler(),
+            testing::poll_message(CHAT, &alice, "!Pizza?", &["Yes", "No"]),
+        )
+        .await;
+        let poll_id = load_poll(&t).tg_poll_id;
+        t.dispatch(&handler(), testing::poll_answer(&poll_id, &bob, &[0]))
+            .await;
+        wait_info_edit(&t, &poll_id).await;
+        t.telegram.clear();
+
+        // Closing the poll replaces the info message with the results.
+        t.dispatch(&handler(), testing::poll(&poll_id, true)).await;
+        assert!(load_poll(&t).closed_at.is_some());
+        let edits = t.telegram.calls("editMessageText");
+        assert_eq!(edits.len(), 1);
+        let text = edits[0]["text"].as_str().unwrap();
+        assert!(text.contains("Closed, voted 1 user."), "{text}");
+        assert!(edits[0]["reply_markup"].is_null());
+        t.telegram.clear();
+
+        // Late updates are ignored.
+        t.dispatch(&handler(), testing::poll(&poll_id, true)).await;
+        t.dispatch(&handler(), testing::poll_answer(&poll_id, &alice, &[1]))
+            .await;
+        assert!(t.telegram.calls("editMessageText").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_recover_closed_poll() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let alice = testing::user_json(1, "Alice");
+        t.dispatch(
+            &handler(),
+            testing::poll_message(CHAT, &alice, "!Pizza?", &["Yes", "No"]),
+        )
+        .await;
+        let poll_message_id = load_poll(&t).poll_message_id.unwrap();
+        t.telegram.clear();
+
+        // The poll was stopped while the bot was offline.
+        let forward_channel = t.env.config.telegram.chats.forward_channel;
+        let mut copy = crate::mock_telegram::message_json(
+            forward_channel.0,
+            5000,
+            None,
+            &alice,
+        );
+        copy.as_object_mut().unwrap().remove("text");
+        copy["poll"] = crate::mock_telegram::poll_json("x", "!Pizza?", &[]);
+        copy["poll"]["is_closed"] = true.into();
+        t.telegram.respond("forwardMessage", copy);
+
+        recover(Arc::clone(&t.env), t.bot.clone()).await;
+        let forwards = t.telegram.calls("forwardMessage");
+        assert_eq!(forwards.len(), 1);
+        assert_eq!(
+            forwards[0]["message_id"],
+            MessageId::from(poll_message_id).0
+        );
+        assert_eq!(t.telegram.calls("deleteMessage").len(), 1);
+        assert!(load_poll(&t).closed_at.is_some());
+        assert_eq!(t.telegram.calls("editMessageText").len(), 1);
+
+        // Closed polls are not checked again.
+        t.telegram.clear();
+        recover(Arc::clone(&t.env), t.bot.clone()).await;
+        assert!(t.telegram.calls("forwardMessage").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_info_edits_coalesced() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        t.add_resident(3, "carol", "Carol");
+        let alice = testing::user_json(1, "Alice");
+        t.dispatch(
+            &handler(),
+            testing::poll_message(CHAT, &alice, "!Pizza?", &["Yes", "No"]),
+        )
+        .await;
+        let poll_id = load_poll(&t).tg_poll_id;
+        t.telegram.clear();
+
+        for (id, name) in [(1, "Alice"), (2, "Bob")] {
+            let user = testing::user_json(id, name);
+            t.dispatch(&handler(), testing::poll_answer(&poll_id, &user, &[0]))
+                .await;
+        }
+        assert!(t.telegram.calls("editMessageText").is_empty());
+        wait_info_edit(&t, &poll_id).await;
+        let edits = t.telegram.calls("editMessageText");
+        assert_eq!(edits.len(), 1);
+        let text = edits[0]["text"].as_str().unwrap();
+        assert!(text.contains("Voted 2 users, pending vote 1 user"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn test_pollexport() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+        t.dispatch(
+            &handler(),
+            testing::poll_message(CHAT, &alice, "!Pizza?", &["Yes", "No"]),
+        )
+        .await;
+        let poll_id = load_poll(&t).tg_poll_id;
+        let info = t.telegram.results("sendMessage")[0].clone();
+        for (user, options) in [(&bob, &[1][..]), (&alice, &[0]), (&bob, &[])] {
+            t.dispatch(
+                &handler(),
+                testing::poll_answer(&poll_id, user, options),
+            )
+            .await;
+        }
+        wait_info_edit(&t, &poll_id).await;
+        t.telegram.clear();
+
+        let mut export = testing::message(CHAT, None, &bob, "/pollexport");
+        export["message"]["reply_to_message"] = info;
+        t.dispatch(&handler(), export.clone()).await;
+        let replies = t.telegram.calls("sendMessage");
+        assert_eq!(replies[0]["text"], "The poll is still open.");
+
+        t.dispatch(&handler(), testing::poll(&poll_id, true)).await;
+        let csv = export_votes(&mut t.env.conn(), &load_poll(&t)).unwrap();
+        let csv = String::from_utf8(csv.unwrap()).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{csv}");
+        assert!(lines[1].starts_with("1,alice,Alice,Yes,"), "{csv}");
+
+        t.telegram.clear();
+        t.telegram.respond(
+            "sendDocument",
+            crate::mock_telegram::message_json(1, 5000, None, &alice),
+        );
+        t.dispatch(&handler(), export).await;
+        assert_eq!(t.telegram.calls("sendDocument").len(), 1);
+        let replies = t.telegram.calls("sendMessage");
+        assert_eq!(replies[0]["text"], "Sent the votes in a private message.");
+    }
+}
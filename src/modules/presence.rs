@@ -0,0 +1,45 @@
+//! Periodically record the number of people in the space into the
+//! `presence_log` table, for historical statistics.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::RunQueryDsl;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::BotEnv;
+use crate::{models, schema};
+
+/// Interval between presence samples.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+pub async fn task(env: Arc<BotEnv>, shutdown: CancellationToken) {
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(SAMPLE_INTERVAL) => {}
+        }
+
+        let users = match crate::modules::basic::users_in_space(&env).await {
+            Ok(users) => users,
+            Err(e) => {
+                log::error!("presence: failed to get users in space: {e}");
+                continue;
+            }
+        };
+
+        let result = diesel::insert_into(schema::presence_log::table)
+            .values(models::PresenceLogEntry {
+                timestamp: chrono::Utc::now().naive_utc(),
+                person_count: i32::try_from(users.len()).unwrap_or(i32::MAX),
+            })
+            .execute(&mut *env.conn());
+        if let Err(e) = result {
+            log::error!("presence: failed to record presence: {e}");
+        }
+    }
+}
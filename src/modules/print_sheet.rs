@@ -0,0 +1,260 @@
+//! Printable weekly sheet for the noticeboard of the space.
+//!
+//! `/printsheet` replies with a PDF listing the shopping list, items that
+//! are still borrowed, and bookings and countdowns of the coming week.  The
+//! sheet is rendered from `print_sheet.html` by [weasyprint], which has to
+//! be installed for the command to work.
+//!
+//! **Scope**: `/printsheet` command, available to residents.
+//!
+//! [weasyprint]: https://weasyprint.org/
+
+use std::collections::HashMap;
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use chrono::{Duration, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use minijinja::Environment;
+use serde::Serialize;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::InputFile;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::DbUserId;
+use crate::modules::timezones;
+use crate::utils::BotExt as _;
+use crate::{models, schema};
+
+/// Number of days of upcoming events on the sheet.
+const DAYS: i64 = 7;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "printable PDF of needs, borrows and events of \
+                             the week.")]
+    #[custom(resident = true)]
+    Printsheet,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_printsheet)
+}
+
+async fn cmd_printsheet(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Result<()> {
+    let tz = timezones::space_tz(&env);
+    let sheet = load(&mut env.conn(), tz, Utc::now().naive_utc())?;
+    match render_pdf(&sheet).await {
+        Ok(pdf) => {
+            let file_name = format!("weekly-{}.pdf", sheet.date);
+            bot.reply_document(
+                &msg,
+                InputFile::memory(pdf).file_name(file_name),
+            )
+            .await?;
+        }
+        Err(e) => {
+            log::warn!("Failed to render the print sheet: {e:#}");
+            bot.reply_message(&msg, "Failed to render the sheet.").await?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Sheet {
+    date: String,
+    until: String,
+    needs: Vec<Need>,
+    borrows: Vec<Borrow>,
+    events: Vec<Event>,
+}
+
+#[derive(Serialize)]
+struct Need {
+    item: String,
+    by: String,
+}
+
+#[derive(Serialize)]
+struct Borrow {
+    items: Vec<String>,
+    by: String,
+    since: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Event {
+    at: String,
+    title: String,
+}
+
+/// Collect the sheet as of `now` (UTC), with times in the time zone `tz`.
+fn load(
+    conn: &mut SqliteConnection,
+    tz: Tz,
+    now: NaiveDateTime,
+) -> QueryResult<Sheet> {
+    let users: HashMap<DbUserId, models::TgUser> = schema::tg_users::table
+        .load::<models::TgUser>(conn)?
+        .into_iter()
+        .map(|u| (u.id, u))
+        .collect();
+    let name = |id: DbUserId| {
+        users.get(&id).map_or_else(
+            || format!("id={}", UserId::from(id)),
+            |u| {
+                [Some(u.first_name.as_str()), u.last_name.as_deref()]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            },
+        )
+    };
+    let local = |t: NaiveDateTime| timezones::to_local(tz, t);
+    let until = now + Duration::days(DAYS);
+
+    let needs = schema::needed_items::table
+        .filter(schema::needed_items::bought_at.is_null())
+        .order(schema::needed_items::rowid.asc())
+        .select(models::NeededItem::as_select())
+        .load(conn)?
+        .into_iter()
+        .map(|n| Need { item: n.item, by: name(n.request_user_id) })
+        .collect();
+
+    let borrows = schema::borrowed_items::table
+        .select(models::BorrowedItems::as_select())
+        .load(conn)?
+        .into_iter()
+        .filter_map(|b| {
+            let items = b
+                .items
+                .iter()
+                .filter(|i| i.returned.is_none())
+                .map(|i| i.name.clone())
+                .collect::<Vec<_>>();
+            (!items.is_empty()).then(|| Borrow {
+                items,
+                by: name(b.user_id),
+                since: b
+                    .created_at
+                    .map(|t| local(t).format("%Y-%m-%d").to_string()),
+            })
+        })
+        .collect();
+
+    let bookings = schema::bookings::table
+        .filter(schema::bookings::end_at.gt(now))
+        .filter(schema::bookings::start_at.lt(until))
+        .select(models::Booking::as_select())
+        .load(conn)?
+        .into_iter()
+        .map(|b| {
+            let title = format!(
+                "{} until {}: {}",
+                b.resource,
+                local(b.end_at).format("%H:%M"),
+                name(b.user_id),
+            );
+            (b.start_at, title)
+        });
+    let countdowns = schema::countdowns::table
+        .filter(schema::countdowns::finished.eq(false))
+        .filter(schema::countdowns::target_at.gt(now))
+        .filter(schema::countdowns::target_at.lt(until))
+        .select(models::Countdown::as_select())
+        .load(conn)?
+        .into_iter()
+        .map(|c| (c.target_at, c.title));
+    let mut events = bookings.chain(countdowns).collect::<Vec<_>>();
+    events.sort_by_key(|(at, _)| *at);
+    let events = events
+        .into_iter()
+        .map(|(at, title)| Event {
+            at: local(at).format("%a %d.%m %H:%M").to_string(),
+            title,
+        })
+        .collect();
+
+    Ok(Sheet {
+        date: local(now).format("%Y-%m-%d").to_string(),
+        until: local(until).format("%Y-%m-%d").to_string(),
+        needs,
+        borrows,
+        events,
+    })
+}
+
+fn render(sheet: &Sheet) -> Result<String, minijinja::Error> {
+    let mut env = Environment::new();
+    env.add_template("print_sheet.html", include_str!("print_sheet.html"))?;
+    env.get_template("print_sheet.html")?.render(sheet)
+}
+
+async fn render_pdf(sheet: &Sheet) -> Result<Vec<u8>> {
+    let html = render(sheet)?;
+    tokio::task::spawn_blocking(move || html_to_pdf(html)).await?
+}
+
+/// Convert HTML to PDF using external tools.
+fn html_to_pdf(html: String) -> Result<Vec<u8>> {
+    let mut pdf = Command::new("weasyprint")
+        .arg("-")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = pdf.stdin.take().context("stdin is not piped")?;
+    // Write from another thread: weasyprint may fill the output pipes before
+    // it has read all of the input.
+    let writer = std::thread::spawn(move || stdin.write_all(html.as_bytes()));
+    let pdf = pdf.wait_with_output()?;
+    if !pdf.status.success() || !pdf.stdout.starts_with(b"%PDF") {
+        bail!(
+            "Failed to generate PDF: {}",
+            String::from_utf8_lossy(&pdf.stderr)
+        );
+    }
+    writer.join().map_err(|_| anyhow!("stdin writer panicked"))??;
+    Ok(pdf.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let sheet = Sheet {
+            date: "2024-01-29".to_string(),
+            until: "2024-02-05".to_string(),
+            needs: vec![Need {
+                item: "<milk>".to_string(),
+                by: "Alice".to_string(),
+            }],
+            borrows: vec![Borrow {
+                items: vec!["drill".to_string(), "saw".to_string()],
+                by: "Bob".to_string(),
+                since: None,
+            }],
+            events: Vec::new(),
+        };
+        let html = render(&sheet).unwrap();
+        assert!(html.contains("&lt;milk&gt;"));
+        assert!(html.contains("drill, saw"));
+        assert!(html.contains("No events"));
+    }
+}
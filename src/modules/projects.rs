@@ -0,0 +1,556 @@
+//! Shared project tracker: residents create projects, join them, update
+//! their status and log progress.  An overview of all projects is pinned in
+//! the [`telegram.projects.thread`], and members of stale projects are nudged
+//! after [`telegram.projects.stale_days`] of inactivity.  Projects created
+//! outside of a topic get their own topic in [`telegram.projects.topics`],
+//! which is closed when the project is done and reopened if it's resumed.
+//!
+//! **Scope**: `/project` and `/projects` commands, available to residents.
+//!
+//! [`telegram.projects.thread`]: crate::config::Projects::thread
+//! [`telegram.projects.stale_days`]: crate::config::Projects::stale_days
+//! [`telegram.projects.topics`]: crate::config::Projects::topics
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{ParseMode, ThreadId};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_users, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::db::{DbChatId, DbThreadId, DbUserId};
+use crate::utils::{
+    format_to, html, BotExt as _, ChatIdExt as _, ResultExt as _,
+};
+use crate::{models, schema};
+
+/// Allowed project statuses.
+const STATUSES: &[&str] = &["idea", "active", "paused", "done"];
+
+/// Maximum length of a project name.
+const MAX_NAME_CHARS: usize = 32;
+
+/// Number of log entries shown by `/project info`.
+const INFO_LOG_ENTRIES: i64 = 5;
+
+/// Color of created topics, one of the colors allowed by Telegram.
+const TOPIC_ICON_COLOR: u32 = 0x6F_B9_F0;
+
+const USAGE: &str = "Usage:
+/project create <name>
+/project info <name>
+/project join|leave <name>
+/project status <name> <idea|active|paused|done>
+/project log <name> <text>";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "manage shared projects, see \
+                             <code>/project help</code>.")]
+    #[custom(resident = true)]
+    Project(String),
+    #[command(description = "show the overview of projects.")]
+    #[custom(resident = true)]
+    Projects,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(handle_command)
+}
+
+async fn handle_command(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    command: Commands,
+) -> Result<()> {
+    match command {
+        Commands::Project(args) => cmd_project(bot, env, msg, args).await,
+        Commands::Projects => {
+            let text = overview(&env)?;
+            bot.reply_message(&msg, text)
+                .parse_mode(ParseMode::Html)
+                .disable_web_page_preview(true)
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+async fn cmd_project(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    args: String,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let (action, name, rest) = split_args(&args);
+    if name.is_empty() {
+        bot.reply_message(&msg, USAGE).await?;
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let user_id = DbUserId::from(from.id);
+    let result = match action {
+        "create" => {
+            if name.chars().count() > MAX_NAME_CHARS {
+                bot.reply_message(&msg, "The project name is too long.")
+                    .await?;
+                return Ok(());
+            }
+            let result = env.transaction(|conn| {
+                let inserted =
+                    diesel::insert_or_ignore_into(schema::projects::table)
+                        .values((
+                            schema::projects::name.eq(name),
+                            schema::projects::status.eq("idea"),
+                            schema::projects::owner_id.eq(user_id),
+                            schema::projects::chat_id.eq(msg
+                                .thread_id
+                                .map(|_| DbChatId::from(msg.chat.id))),
+                            schema::projects::thread_id
+                                .eq(msg.thread_id.map(DbThreadId::from)),
+                            schema::projects::created_at.eq(now),
+                            schema::projects::updated_at.eq(now),
+                        ))
+                        .execute(conn)?;
+                if inserted == 0 {
+                    return Ok(Err("A project with this name already exists."));
+                }
+                let project_id = find(conn, name)?
+                    .ok_or(diesel::result::Error::NotFound)?
+                    .rowid;
+                diesel::insert_into(schema::project_members::table)
+                    .values((
+                        schema::project_members::project_id.eq(project_id),
+                        schema::project_members::user_id.eq(user_id),
+                    ))
+                    .execute(conn)?;
+                Ok(Ok("Project created."))
+            })?;
+            if result.is_ok() && msg.thread_id.is_none() {
+                create_topic(&bot, &env, name)
+                    .await
+                    .log_error("projects: create topic");
+            }
+            result
+        }
+        "info" => {
+            let text = info(&env, name)?;
+            bot.reply_message(&msg, text)
+                .parse_mode(ParseMode::Html)
+                .disable_web_page_preview(true)
+                .await?;
+            return Ok(());
+        }
+        "join" => update(&env, name, |conn, project| {
+            diesel::insert_or_ignore_into(schema::project_members::table)
+                .values((
+                    schema::project_members::project_id.eq(project.rowid),
+                    schema::project_members::user_id.eq(user_id),
+                ))
+                .execute(conn)?;
+            Ok(Ok("You joined the project."))
+        })?,
+        "leave" => update(&env, name, |conn, project| {
+            let deleted = diesel::delete(schema::project_members::table)
+                .filter(schema::project_members::project_id.eq(project.rowid))
+                .filter(schema::project_members::user_id.eq(user_id))
+                .execute(conn)?;
+            Ok(if deleted == 0 {
+                Err("You are not a member of this project.")
+            } else {
+                Ok("You left the project.")
+            })
+        })?,
+        "status" if STATUSES.contains(&rest) => {
+            let mut was_done = None;
+            let result = update(&env, name, |conn, project| {
+                was_done = Some(project.status == "done");
+                diesel::update(schema::projects::table)
+                    .filter(schema::projects::rowid.eq(project.rowid))
+                    .set((
+                        schema::projects::status.eq(rest),
+                        schema::projects::updated_at.eq(now),
+                    ))
+                    .execute(conn)?;
+                Ok(Ok("Status updated."))
+            })?;
+            let done = rest == "done";
+            if was_done.is_some_and(|was_done| was_done != done) {
+                update_topic(&bot, &env, name, done)
+                    .await
+                    .log_error("projects: update topic");
+            }
+            result
+        }
+        "log" if !rest.is_empty() => update(&env, name, |conn, project| {
+            diesel::insert_into(schema::project_log::table)
+                .values((
+                    schema::project_log::project_id.eq(project.rowid),
+                    schema::project_log::user_id.eq(user_id),
+                    schema::project_log::text.eq(rest),
+                    schema::project_log::created_at.eq(now),
+                ))
+                .execute(conn)?;
+            diesel::update(schema::projects::table)
+                .filter(schema::projects::rowid.eq(project.rowid))
+                .set(schema::projects::updated_at.eq(now))
+                .execute(conn)?;
+            Ok(Ok("Progress logged."))
+        })?,
+        _ => Err(USAGE),
+    };
+
+    let text = match result {
+        Ok(text) => {
+            update_overview(&bot, &env).await.log_error("projects: overview");
+            text
+        }
+        Err(text) => text,
+    };
+    bot.reply_message(&msg, text).await?;
+    Ok(())
+}
+
+/// Split `/project` arguments into action, project name and the rest.
+fn split_args(args: &str) -> (&str, &str, &str) {
+    let (action, rest) = args.trim().split_once(' ').unwrap_or((args, ""));
+    let (name, rest) = rest.trim().split_once(' ').unwrap_or((rest, ""));
+    (action.trim(), name.trim(), rest.trim())
+}
+
+fn find(
+    conn: &mut SqliteConnection,
+    name: &str,
+) -> QueryResult<Option<models::Project>> {
+    schema::projects::table
+        .filter(schema::projects::name.eq(name))
+        .select(models::Project::as_select())
+        .first(conn)
+        .optional()
+}
+
+/// Run `f` in a transaction on an existing project.
+fn update(
+    env: &BotEnv,
+    name: &str,
+    f: impl FnOnce(
+        &mut SqliteConnection,
+        &models::Project,
+    ) -> QueryResult<Result<&'static str, &'static str>>,
+) -> QueryResult<Result<&'static str, &'static str>> {
+    env.transaction(|conn| {
+        let Some(project) = find(conn, name)? else {
+            return Ok(Err("No such project."));
+        };
+        f(conn, &project)
+    })
+}
+
+fn topics_forum(env: &BotEnv) -> Option<ChatId> {
+    env.config.telegram.projects.as_ref()?.topics
+}
+
+/// Create a topic for a new project in [`Projects::topics`] and link it to
+/// the project.
+///
+/// [`Projects::topics`]: crate::config::Projects::topics
+async fn create_topic(bot: &Bot, env: &BotEnv, name: &str) -> Result<()> {
+    let Some(forum) = topics_forum(env) else { return Ok(()) };
+    let topic =
+        bot.create_forum_topic(forum, name, TOPIC_ICON_COLOR, "").await?;
+    diesel::update(schema::projects::table)
+        .filter(schema::projects::name.eq(name))
+        .set((
+            schema::projects::chat_id.eq(DbChatId::from(forum)),
+            schema::projects::thread_id.eq(DbThreadId::from(topic.thread_id)),
+        ))
+        .execute(&mut *env.conn())?;
+    Ok(())
+}
+
+/// Close the topic of a done project, or reopen it if the project is
+/// resumed.  Topics outside of [`Projects::topics`] are left as is.
+///
+/// [`Projects::topics`]: crate::config::Projects::topics
+async fn update_topic(
+    bot: &Bot,
+    env: &BotEnv,
+    name: &str,
+    done: bool,
+) -> Result<()> {
+    let Some(forum) = topics_forum(env) else { return Ok(()) };
+    let Some(project) = find(&mut env.conn(), name)? else { return Ok(()) };
+    let (Some(chat), Some(thread)) = (project.chat_id, project.thread_id)
+    else {
+        return Ok(());
+    };
+    if ChatId::from(chat) != forum {
+        return Ok(());
+    }
+    if done {
+        bot.close_forum_topic(forum, thread.into()).await?;
+    } else {
+        bot.reopen_forum_topic(forum, thread.into()).await?;
+    }
+    Ok(())
+}
+
+fn members(
+    conn: &mut SqliteConnection,
+    project_id: i32,
+) -> QueryResult<Vec<(DbUserId, Option<models::TgUser>)>> {
+    schema::project_members::table
+        .left_join(
+            schema::tg_users::table
+                .on(schema::tg_users::id.eq(schema::project_members::user_id)),
+        )
+        .filter(schema::project_members::project_id.eq(project_id))
+        .select((
+            schema::project_members::user_id,
+            schema::tg_users::all_columns.nullable(),
+        ))
+        .load(conn)
+}
+
+fn info(env: &BotEnv, name: &str) -> Result<String> {
+    let mut conn = env.conn();
+    let Some(project) = find(&mut conn, name)? else {
+        return Ok("No such project.".to_string());
+    };
+    let members = members(&mut conn, project.rowid)?;
+    let log: Vec<(String, chrono::NaiveDateTime)> = schema::project_log::table
+        .filter(schema::project_log::project_id.eq(project.rowid))
+        .order(schema::project_log::created_at.desc())
+        .limit(INFO_LOG_ENTRIES)
+        .select((schema::project_log::text, schema::project_log::created_at))
+        .load(&mut *conn)?;
+
+    let mut text = String::new();
+    format_project(&mut text, &project);
+    text.push_str("Members: ");
+    format_users(&mut text, members.iter().map(|(id, u)| (*id, u.as_ref())));
+    text.push('\n');
+    for (entry, created_at) in log.iter().rev() {
+        format_to!(
+            text,
+            "\n{}: {}",
+            created_at.format("%Y-%m-%d"),
+            html::escape(entry),
+        );
+    }
+    Ok(text)
+}
+
+fn format_project(out: &mut String, project: &models::Project) {
+    format_to!(
+        out,
+        "<b>{}</b> — {}",
+        html::escape(&project.name),
+        project.status
+    );
+    let chat = project.chat_id.map(ChatId::from);
+    if let (Some(id), Some(thread)) =
+        (chat.and_then(|c| c.channel_t_me_id()), project.thread_id)
+    {
+        format_to!(
+            out,
+            " ({})",
+            html::link(
+                &format!("https://t.me/c/{id}/{}", ThreadId::from(thread).0 .0),
+                "topic",
+            ),
+        );
+    }
+    format_to!(out, ", updated {}\n", project.updated_at.format("%Y-%m-%d"));
+}
+
+fn overview(env: &BotEnv) -> Result<String> {
+    let projects: Vec<models::Project> = schema::projects::table
+        .filter(schema::projects::status.ne("done"))
+        .order(schema::projects::updated_at.desc())
+        .select(models::Project::as_select())
+        .load(&mut *env.conn())?;
+    let mut text = String::from("<b>Projects</b>\n\n");
+    if projects.is_empty() {
+        text.push_str("No projects yet. Create one with /project create.");
+    }
+    for project in &projects {
+        format_project(&mut text, project);
+    }
+    Ok(text)
+}
+
+/// Edit the pinned overview message, or post and pin a new one.
+async fn update_overview(bot: &Bot, env: &BotEnv) -> Result<()> {
+    let Some(conf) = &env.config.telegram.projects else { return Ok(()) };
+    let text = overview(env)?;
+    if let Some(message_id) = models::projects_overview.get(&mut env.conn())? {
+        let edited = bot
+            .edit_message_text(conf.thread.chat, message_id, &text)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await;
+        match edited {
+            Ok(_) => return Ok(()),
+            // Happens when the overview didn't change.
+            Err(teloxide::RequestError::Api(
+                teloxide::ApiError::MessageNotModified,
+            )) => return Ok(()),
+            Err(_) => (),
+        }
+    }
+    let sent = bot
+        .send_message(conf.thread.chat, text)
+        .message_thread_id(conf.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+    bot.pin_chat_message(sent.chat.id, sent.id)
+        .disable_notification(true)
+        .await?;
+    models::projects_overview.set(&mut env.conn(), &sent.id)?;
+    Ok(())
+}
+
+/// Nudge members of stale projects.
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.projects else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(60 * 60)) => {}
+        }
+        nudge_stale(&env, &bot, conf)
+            .await
+            .log_error("projects: nudge stale projects");
+    }
+}
+
+async fn nudge_stale(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &crate::config::Projects,
+) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let threshold = now - chrono::Duration::days(conf.stale_days.into());
+    let stale: Vec<models::Project> = schema::projects::table
+        .filter(schema::projects::status.eq("active"))
+        .filter(schema::projects::updated_at.lt(threshold))
+        .filter(
+            schema::projects::nudged_at
+                .is_null()
+                .or(schema::projects::nudged_at.lt(threshold)),
+        )
+        .select(models::Project::as_select())
+        .load(&mut *env.conn())?;
+
+    for project in stale {
+        let members = members(&mut env.conn(), project.rowid)?;
+        let mut text = format!(
+            "⏰ Project <b>{}</b> had no updates for {} days. ",
+            html::escape(&project.name),
+            conf.stale_days,
+        );
+        format_users(
+            &mut text,
+            members.iter().map(|(id, u)| (*id, u.as_ref())),
+        );
+        text.push_str(
+            ", please post progress with /project log, or change its status.",
+        );
+        let (chat, thread) = match (project.chat_id, project.thread_id) {
+            (Some(chat), thread) => (chat.into(), thread.map(ThreadId::from)),
+            (None, _) => (conf.thread.chat, Some(conf.thread.thread)),
+        };
+        let mut request = bot
+            .send_message(chat, text)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true);
+        request.message_thread_id = thread;
+        request.await?;
+        diesel::update(schema::projects::table)
+            .filter(schema::projects::rowid.eq(project.rowid))
+            .set(schema::projects::nudged_at.eq(now))
+            .execute(&mut *env.conn())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use teloxide::types::MessageId;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split_args(""), ("", "", ""));
+        assert_eq!(split_args("info"), ("info", "", ""));
+        assert_eq!(split_args(" info  botka "), ("info", "botka", ""));
+        assert_eq!(
+            split_args("log botka  added /projects command "),
+            ("log", "botka", "added /projects command"),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_project_topics() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let forum = t.env.config.telegram.projects.as_ref().unwrap().topics;
+        let forum = forum.unwrap();
+        t.telegram.respond(
+            "createForumTopic",
+            json!({
+                "message_thread_id": 77,
+                "name": "botka",
+                "icon_color": TOPIC_ICON_COLOR,
+            }),
+        );
+        let alice = testing::user_json(1, "Alice");
+        let command =
+            |text: &str| testing::message(forum.0, None, &alice, text);
+
+        t.dispatch(&command_handler(), command("/project create botka")).await;
+        let created = t.telegram.calls("createForumTopic");
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0]["name"], "botka");
+        let project = find(&mut t.env.conn(), "botka").unwrap().unwrap();
+        assert_eq!(project.chat_id, Some(DbChatId::from(forum)));
+        assert_eq!(
+            project.thread_id.map(ThreadId::from),
+            Some(ThreadId(MessageId(77)))
+        );
+
+        t.dispatch(&command_handler(), command("/project status botka done"))
+            .await;
+        let closed = t.telegram.calls("closeForumTopic");
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0]["message_thread_id"], 77);
+        // Already done, nothing to close.
+        t.dispatch(&command_handler(), command("/project status botka done"))
+            .await;
+        assert_eq!(t.telegram.calls("closeForumTopic").len(), 1);
+
+        t.dispatch(&command_handler(), command("/project status botka active"))
+            .await;
+        assert_eq!(t.telegram.calls("reopenForumTopic").len(), 1);
+    }
+}
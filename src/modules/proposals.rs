@@ -0,0 +1,446 @@
+//! Two-phase polls: `/proposal` opens a suggestion window where residents
+//! suggest options by replying to the bot message, then the creator starts a
+//! tracked poll assembled from the suggestions.
+//!
+//! **Scope**: `/proposal` command, available to residents in group chats.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, Me, ParseMode,
+};
+
+use crate::common::{
+    filter_command, format_user, is_resident, BotCommandsExt, BotEnv,
+    UpdateHandler,
+};
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::utils::{format_to, html, BotExt as _};
+use crate::{models, schema};
+
+// Telegram limits for polls.
+const MAX_QUESTION_CHARS: usize = 300;
+const MAX_OPTION_CHARS: usize = 100;
+const MAX_OPTIONS: usize = 10;
+
+type ProposalOption = (String, DbUserId, Option<models::TgUser>);
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(
+        description = "collect poll options from residents before voting."
+    )]
+    #[custom(resident = true, in_private = false)]
+    Proposal(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_proposal)
+}
+
+pub fn message_handler() -> UpdateHandler {
+    dptree::filter_map(filter_suggestions).endpoint(handle_suggestion)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_start)
+}
+
+async fn cmd_proposal(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Proposal(question): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let question = question.trim();
+    if question.is_empty() || question.chars().count() > MAX_QUESTION_CHARS {
+        bot.reply_message(
+            &msg,
+            format!(
+                "Usage: /proposal <question>, up to {MAX_QUESTION_CHARS} \
+                 characters."
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let creator = models::TgUser {
+        id: from.id.into(),
+        username: from.username.clone(),
+        first_name: from.first_name.clone(),
+        last_name: from.last_name.clone(),
+    };
+    let sent = bot
+        .reply_message(
+            &msg,
+            proposal_text((creator.id, Some(&creator)), question, &[], false),
+        )
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(start_keyboard())
+        .await?;
+
+    diesel::insert_into(schema::proposals::table)
+        .values(models::NewProposal {
+            chat_id: sent.chat.id.into(),
+            message_id: sent.id.into(),
+            creator_id: from.id.into(),
+            question,
+            created_at: chrono::Utc::now().naive_utc(),
+        })
+        .execute(&mut *env.conn())?;
+
+    Ok(())
+}
+
+fn filter_suggestions(
+    me: Me,
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Option<models::Proposal> {
+    let reply_to = msg.reply_to_message()?;
+    if reply_to.from.as_ref()?.id != me.user.id {
+        return None;
+    }
+    msg.text()?;
+    schema::proposals::table
+        .filter(schema::proposals::chat_id.eq(DbChatId::from(msg.chat.id)))
+        .filter(
+            schema::proposals::message_id.eq(DbMessageId::from(reply_to.id)),
+        )
+        .filter(schema::proposals::started_at.is_null())
+        .select(models::Proposal::as_select())
+        .first(&mut *env.conn())
+        .optional()
+        .ok()
+        .flatten()
+}
+
+async fn handle_suggestion(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    proposal: models::Proposal,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let text = msg.text().unwrap_or_default().trim();
+
+    let result = env.transaction(|conn| {
+        if !is_resident(conn, from) {
+            return Ok(Err("Only residents can suggest options."));
+        }
+        if text.is_empty() || text.chars().count() > MAX_OPTION_CHARS {
+            return Ok(Err("An option must be from 1 to 100 characters long."));
+        }
+        let options = load_options(conn, proposal.rowid)?;
+        if options
+            .iter()
+            .any(|(o, _, _)| o.to_lowercase() == text.to_lowercase())
+        {
+            return Ok(Err("This option is already suggested."));
+        }
+        if options.len() >= MAX_OPTIONS {
+            return Ok(Err("The maximum number of options is reached."));
+        }
+        diesel::insert_into(schema::proposal_options::table)
+            .values((
+                schema::proposal_options::proposal_id.eq(proposal.rowid),
+                schema::proposal_options::text.eq(text),
+                schema::proposal_options::author_id.eq(DbUserId::from(from.id)),
+            ))
+            .execute(conn)?;
+        let options = load_options(conn, proposal.rowid)?;
+        let creator = load_user(conn, proposal.creator_id)?;
+        Ok(Ok((options, creator)))
+    })?;
+
+    let (options, creator) = match result {
+        Ok(result) => result,
+        Err(error) => {
+            bot.reply_message(&msg, error).await?;
+            return Ok(());
+        }
+    };
+
+    bot.edit_message_text(
+        proposal.chat_id,
+        proposal.message_id.into(),
+        proposal_text(
+            (proposal.creator_id, creator.as_ref()),
+            &proposal.question,
+            &options,
+            false,
+        ),
+    )
+    .parse_mode(ParseMode::Html)
+    .disable_web_page_preview(true)
+    .reply_markup(start_keyboard())
+    .await?;
+
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<()> {
+    (callback.data.as_deref()? == "pr:start").then_some(())
+}
+
+async fn handle_start(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+) -> Result<()> {
+    let Some(message) = &callback.message else { return Ok(()) };
+
+    let result = env.transaction(|conn| {
+        use schema::proposals::dsl as p;
+        let proposal = p::proposals
+            .filter(p::chat_id.eq(DbChatId::from(message.chat.id)))
+            .filter(p::message_id.eq(DbMessageId::from(message.id)))
+            .select(models::Proposal::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(proposal) = proposal else {
+            return Ok(Err("Unknown proposal."));
+        };
+        if UserId::from(proposal.creator_id) != callback.from.id {
+            return Ok(Err("Only the creator can start the vote."));
+        }
+        if proposal.started_at.is_some() {
+            return Ok(Err("The vote is already started."));
+        }
+        let options = load_options(conn, proposal.rowid)?;
+        if options.len() < 2 {
+            return Ok(Err("At least two options are required."));
+        }
+        let creator = load_user(conn, proposal.creator_id)?;
+        Ok(Ok((proposal, options, creator)))
+    })?;
+
+    let (proposal, options, creator) = match result {
+        Ok(result) => result,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&callback.id).await?;
+
+    let mut poll = bot
+        .send_poll(
+            message.chat.id,
+            &proposal.question,
+            options.iter().map(|(text, _, _)| text.clone()),
+        )
+        .is_anonymous(false)
+        .reply_to_message_id(message.id);
+    poll.message_thread_id = message.thread_id;
+    let poll = poll.await?;
+    crate::modules::polls::track_poll(&bot, &env, &poll, &callback.from)
+        .await?;
+    // Only now, so that a failed vote can be started again.
+    diesel::update(schema::proposals::table)
+        .filter(schema::proposals::rowid.eq(proposal.rowid))
+        .set(schema::proposals::started_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(&mut *env.conn())?;
+
+    bot.edit_message_text(
+        message.chat.id,
+        message.id,
+        proposal_text(
+            (proposal.creator_id, creator.as_ref()),
+            &proposal.question,
+            &options,
+            true,
+        ),
+    )
+    .parse_mode(ParseMode::Html)
+    .disable_web_page_preview(true)
+    .await?;
+
+    Ok(())
+}
+
+fn load_options(
+    conn: &mut SqliteConnection,
+    proposal_id: i32,
+) -> QueryResult<Vec<ProposalOption>> {
+    schema::proposal_options::table
+        .filter(schema::proposal_options::proposal_id.eq(proposal_id))
+        .left_join(
+            schema::tg_users::table
+                .on(schema::proposal_options::author_id
+                    .eq(schema::tg_users::id)),
+        )
+        .order(schema::proposal_options::rowid.asc())
+        .select((
+            schema::proposal_options::text,
+            schema::proposal_options::author_id,
+            schema::tg_users::all_columns.nullable(),
+        ))
+        .load(conn)
+}
+
+fn load_user(
+    conn: &mut SqliteConnection,
+    id: DbUserId,
+) -> QueryResult<Option<models::TgUser>> {
+    schema::tg_users::table
+        .filter(schema::tg_users::id.eq(id))
+        .first(conn)
+        .optional()
+}
+
+fn start_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "Start vote",
+        "pr:start",
+    )]])
+}
+
+fn proposal_text(
+    creator: (DbUserId, Option<&models::TgUser>),
+    question: &str,
+    options: &[ProposalOption],
+    started: bool,
+) -> String {
+    let mut text = String::new();
+    text.push_str("📝 <b>Proposal</b> by ");
+    format_user(&mut text, creator.0, creator.1, true);
+    format_to!(text, ": {}\n\n", html::escape(question));
+    if started {
+        text.push_str("The vote has started. Options were suggested by:\n");
+    } else {
+        text.push_str(
+            "Reply to this message to suggest an option. The creator starts \
+             the vote when ready.\n\nSuggested options:\n",
+        );
+    }
+    if options.is_empty() {
+        text.push_str("(none yet)\n");
+    }
+    for (i, (option, author_id, author)) in options.iter().enumerate() {
+        format_to!(text, "{}. {} — ", i + 1, html::escape(option));
+        format_user(&mut text, *author_id, author, false);
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use teloxide::dispatching::UpdateFilterExt as _;
+
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    const CHAT: i64 = -1_001_234_567_890;
+
+    fn handler() -> UpdateHandler {
+        dptree::entry()
+            .branch(
+                Update::filter_message()
+                    .branch(command_handler())
+                    .branch(message_handler()),
+            )
+            .branch(Update::filter_callback_query().branch(callback_handler()))
+    }
+
+    fn suggest(proposal: &Value, from: &Value, text: &str) -> Value {
+        let mut update = testing::message(CHAT, None, from, text);
+        update["message"]["reply_to_message"] = proposal.clone();
+        update
+    }
+
+    /// Dispatch the update and return the replies of the bot.
+    async fn replies(t: &TestBot, update: Value) -> Vec<Value> {
+        t.telegram.clear();
+        t.dispatch(&handler(), update).await;
+        t.telegram.calls("sendMessage")
+    }
+
+    #[tokio::test]
+    async fn test_proposal() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        t.add_resident(2, "bob", "Bob");
+        let alice = testing::user_json(1, "Alice");
+        let bob = testing::user_json(2, "Bob");
+        let carol = testing::user_json(3, "Carol");
+
+        t.dispatch(
+            &handler(),
+            testing::message(CHAT, None, &alice, "/proposal Lunch?"),
+        )
+        .await;
+        let proposal = t.telegram.results("sendMessage")[0].clone();
+        assert!(proposal["text"].as_str().unwrap().contains("(none yet)"));
+
+        let sent = replies(&t, suggest(&proposal, &bob, "Pizza")).await;
+        assert!(sent.is_empty());
+        let edited = t.telegram.calls("editMessageText");
+        assert!(edited[0]["text"].as_str().unwrap().contains("1. Pizza"));
+
+        let sent = replies(&t, suggest(&proposal, &bob, "pizza")).await;
+        assert_eq!(sent[0]["text"], "This option is already suggested.");
+        let sent = replies(&t, suggest(&proposal, &carol, "Tacos")).await;
+        assert_eq!(sent[0]["text"], "Only residents can suggest options.");
+
+        t.telegram.clear();
+        t.dispatch(
+            &handler(),
+            testing::callback(&alice, &proposal, "pr:start"),
+        )
+        .await;
+        assert_eq!(
+            t.telegram.calls("answerCallbackQuery")[0]["text"],
+            "At least two options are required.",
+        );
+
+        replies(&t, suggest(&proposal, &alice, "Sushi")).await;
+        t.telegram.clear();
+        t.dispatch(&handler(), testing::callback(&bob, &proposal, "pr:start"))
+            .await;
+        assert_eq!(
+            t.telegram.calls("answerCallbackQuery")[0]["text"],
+            "Only the creator can start the vote.",
+        );
+        assert!(t.telegram.calls("sendPoll").is_empty());
+
+        // A vote that failed to start can be started again.
+        t.telegram.clear();
+        t.telegram.fail("sendPoll", "Bad Request: not enough rights");
+        let update = testing::callback(&alice, &proposal, "pr:start");
+        assert!(t.try_dispatch(&handler(), update).await.is_err());
+
+        t.telegram.clear();
+        t.dispatch(
+            &handler(),
+            testing::callback(&alice, &proposal, "pr:start"),
+        )
+        .await;
+        let polls = t.telegram.calls("sendPoll");
+        assert_eq!(polls.len(), 1);
+        assert_eq!(polls[0]["question"], "Lunch?");
+        assert_eq!(polls[0]["options"], serde_json::json!(["Pizza", "Sushi"]));
+        let edited = t.telegram.calls("editMessageText");
+        assert!(edited
+            .iter()
+            .any(|e| e["text"].as_str().unwrap().contains("vote has started")));
+
+        // Suggestions are closed once the vote is started.
+        t.telegram.clear();
+        let fallback: UpdateHandler = handler().endpoint(|| async { Ok(()) });
+        t.dispatch(&fallback, suggest(&proposal, &bob, "Soup")).await;
+        assert!(t.telegram.calls("editMessageText").is_empty());
+        assert!(t.telegram.calls("sendMessage").is_empty());
+    }
+}
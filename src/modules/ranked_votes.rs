@@ -0,0 +1,621 @@
+//! Ranked-choice votes.  Each active resident ranks the options in private
+//! messages, and the winner is determined by instant-runoff when the creator
+//! closes the vote.  Telegram polls can't do ranking, so ballots are collected
+//! with inline keyboards.
+//!
+//! **Scope**: `/ranked_vote` command, available to residents in group chats.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+
+use crate::common::{
+    filter_command, is_resident, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _, Sqlizer};
+use crate::{models, schema};
+
+/// Maximum number of options in a vote.
+const MAX_OPTIONS: usize = 10;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(
+        description = "create a ranked-choice vote: <code>/ranked_vote \
+                       Question</code>, then one option per line."
+    )]
+    #[custom(resident = true, in_private = false)]
+    RankedVote(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_ranked_vote)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+async fn cmd_ranked_vote(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::RankedVote(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let mut lines = args.lines().map(str::trim).filter(|l| !l.is_empty());
+    let question = lines.next().unwrap_or_default().to_string();
+    let options = lines.map(str::to_string).collect_vec();
+    if question.is_empty() || !(2..=MAX_OPTIONS).contains(&options.len()) {
+        bot.reply_message(
+            &msg,
+            format!(
+                "Usage: /ranked_vote Question\nOption 1\nOption 2\n...\n\n\
+                 A vote must have from 2 to {MAX_OPTIONS} options."
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let residents: Vec<DbUserId> = schema::residents::table
+        .filter(schema::residents::end_date.is_null())
+        .select(schema::residents::tg_id)
+        .distinct()
+        .load(&mut *env.conn())?;
+
+    let public = bot
+        .reply_message(
+            &msg,
+            progress_text(&question, &options, 0, residents.len()),
+        )
+        .parse_mode(ParseMode::Html)
+        .reply_markup(InlineKeyboardMarkup::new([[
+            InlineKeyboardButton::callback("Close vote", "rv:close"),
+        ]]))
+        .await?;
+
+    let vote_id = env.transaction(|conn| {
+        diesel::insert_into(schema::ranked_votes::table)
+            .values(models::NewRankedVote {
+                chat_id: public.chat.id.into(),
+                message_id: public.id.into(),
+                creator_id: from.id.into(),
+                question: &question,
+                options: Sqlizer::new(options.clone()).map_err(|e| {
+                    diesel::result::Error::SerializationError(Box::new(e))
+                })?,
+                created_at: chrono::Utc::now().naive_utc(),
+            })
+            .execute(conn)?;
+        schema::ranked_votes::table
+            .filter(
+                schema::ranked_votes::chat_id
+                    .eq(DbChatId::from(public.chat.id)),
+            )
+            .filter(
+                schema::ranked_votes::message_id
+                    .eq(DbMessageId::from(public.id)),
+            )
+            .select(schema::ranked_votes::rowid)
+            .first::<i32>(conn)
+    })?;
+
+    let mut undelivered = 0;
+    for resident in residents {
+        let result = bot
+            .send_message(
+                UserId::from(resident),
+                ballot_text(&question, &options, &[], false),
+            )
+            .parse_mode(ParseMode::Html)
+            .reply_markup(ballot_keyboard(vote_id, &options, &[], false))
+            .await;
+        if let Err(e) = result {
+            log::warn!(
+                "ranked_votes: failed to send ballot to {resident:?}: {e}"
+            );
+            undelivered += 1;
+        }
+    }
+    if undelivered > 0 {
+        bot.reply_message(
+            &public,
+            format!(
+                "Failed to deliver {undelivered} ballot(s). Residents should \
+                 start a private chat with the bot to receive them."
+            ),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BallotAction {
+    Rank(usize),
+    Reset,
+    Submit,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CallbackData {
+    Ballot(i32, BallotAction),
+    Close,
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<CallbackData> {
+    let data = callback.data.as_ref()?.strip_prefix("rv:")?;
+    if data == "close" {
+        return Some(CallbackData::Close);
+    }
+    let (vote_id, action) = data.split_once(':')?;
+    let action = match action {
+        "reset" => BallotAction::Reset,
+        "submit" => BallotAction::Submit,
+        option => BallotAction::Rank(option.parse().ok()?),
+    };
+    Some(CallbackData::Ballot(vote_id.parse().ok()?, action))
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    data: CallbackData,
+) -> Result<()> {
+    match data {
+        CallbackData::Ballot(vote_id, action) => {
+            handle_ballot(bot, env, callback, vote_id, action).await
+        }
+        CallbackData::Close => handle_close(bot, env, callback).await,
+    }
+}
+
+async fn handle_ballot(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    vote_id: i32,
+    action: BallotAction,
+) -> Result<()> {
+    let user_id = DbUserId::from(callback.from.id);
+    let result = env.transaction(|conn| {
+        let vote = schema::ranked_votes::table
+            .filter(schema::ranked_votes::rowid.eq(vote_id))
+            .select(models::RankedVote::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(vote) = vote else { return Ok(Err("Unknown vote.")) };
+        if vote.closed_at.is_some() {
+            return Ok(Err("This vote is closed."));
+        }
+        if !is_resident(conn, &callback.from) {
+            return Ok(Err("Only residents can vote."));
+        }
+
+        let ballot = schema::ranked_ballots::table
+            .filter(schema::ranked_ballots::vote_id.eq(vote_id))
+            .filter(schema::ranked_ballots::user_id.eq(user_id))
+            .select(models::RankedBallot::as_select())
+            .first(conn)
+            .optional()?;
+        let was_submitted = ballot.as_ref().is_some_and(|b| b.submitted);
+        let mut ranking =
+            ballot.map(|b| (*b.ranking).clone()).unwrap_or_default();
+        let mut submitted = was_submitted;
+        match action {
+            BallotAction::Rank(_) | BallotAction::Submit if submitted => {
+                return Ok(Err("Your ranking is already submitted."));
+            }
+            BallotAction::Rank(option)
+                if option >= vote.options.len()
+                    || ranking.contains(&option) =>
+            {
+                return Ok(Err("Invalid option."));
+            }
+            BallotAction::Rank(option) => {
+                ranking.push(option);
+                submitted = ranking.len() == vote.options.len();
+            }
+            BallotAction::Submit if ranking.is_empty() => {
+                return Ok(Err("Rank at least one option."));
+            }
+            BallotAction::Submit => submitted = true,
+            BallotAction::Reset => {
+                ranking.clear();
+                submitted = false;
+            }
+        }
+
+        let ballot = models::RankedBallot {
+            vote_id,
+            user_id,
+            ranking: Sqlizer::new(ranking).map_err(|e| {
+                diesel::result::Error::SerializationError(Box::new(e))
+            })?,
+            submitted,
+        };
+        diesel::replace_into(schema::ranked_ballots::table)
+            .values(&ballot)
+            .execute(conn)?;
+
+        let progress = if was_submitted == submitted {
+            None
+        } else {
+            Some((count_submitted(conn, vote_id)?, count_residents(conn)?))
+        };
+        Ok(Ok((vote, ballot, progress)))
+    })?;
+
+    let (vote, ballot, progress) = match result {
+        Ok(result) => result,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&callback.id).await?;
+
+    if let Some(message) = &callback.message {
+        bot.edit_message_text(
+            message.chat.id,
+            message.id,
+            ballot_text(
+                &vote.question,
+                &vote.options,
+                &ballot.ranking,
+                ballot.submitted,
+            ),
+        )
+        .parse_mode(ParseMode::Html)
+        .reply_markup(ballot_keyboard(
+            vote_id,
+            &vote.options,
+            &ballot.ranking,
+            ballot.submitted,
+        ))
+        .await
+        .log_error("ranked_votes: edit ballot message");
+    }
+
+    if let Some((submitted, residents)) = progress {
+        bot.edit_message_text(
+            vote.chat_id,
+            vote.message_id.into(),
+            progress_text(&vote.question, &vote.options, submitted, residents),
+        )
+        .parse_mode(ParseMode::Html)
+        .reply_markup(InlineKeyboardMarkup::new([[
+            InlineKeyboardButton::callback("Close vote", "rv:close"),
+        ]]))
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn handle_close(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+) -> Result<()> {
+    let Some(message) = &callback.message else { return Ok(()) };
+    let result = env.transaction(|conn| {
+        use schema::ranked_votes::dsl as v;
+        let vote = v::ranked_votes
+            .filter(v::chat_id.eq(DbChatId::from(message.chat.id)))
+            .filter(v::message_id.eq(DbMessageId::from(message.id)))
+            .select(models::RankedVote::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(vote) = vote else { return Ok(Err("Unknown vote.")) };
+        if UserId::from(vote.creator_id) != callback.from.id {
+            return Ok(Err("Only the creator can close this vote."));
+        }
+        if vote.closed_at.is_some() {
+            return Ok(Err("This vote is already closed."));
+        }
+        diesel::update(v::ranked_votes)
+            .filter(v::rowid.eq(vote.rowid))
+            .set(v::closed_at.eq(chrono::Utc::now().naive_utc()))
+            .execute(conn)?;
+        let ballots = schema::ranked_ballots::table
+            .filter(schema::ranked_ballots::vote_id.eq(vote.rowid))
+            .filter(schema::ranked_ballots::submitted.eq(true))
+            .select(schema::ranked_ballots::ranking)
+            .load::<Sqlizer<Vec<usize>>>(conn)?;
+        Ok(Ok((vote, ballots)))
+    })?;
+
+    let (vote, ballots) = match result {
+        Ok(result) => result,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&callback.id).await?;
+
+    let ballots = ballots.iter().map(|b| (**b).clone()).collect_vec();
+    let result = instant_runoff(vote.options.len(), &ballots);
+    bot.edit_message_text(
+        message.chat.id,
+        message.id,
+        results_text(&vote.question, &vote.options, ballots.len(), &result),
+    )
+    .parse_mode(ParseMode::Html)
+    .await?;
+
+    Ok(())
+}
+
+fn count_submitted(
+    conn: &mut SqliteConnection,
+    vote_id: i32,
+) -> QueryResult<usize> {
+    schema::ranked_ballots::table
+        .filter(schema::ranked_ballots::vote_id.eq(vote_id))
+        .filter(schema::ranked_ballots::submitted.eq(true))
+        .count()
+        .get_result::<i64>(conn)
+        .map(|n| n.try_into().unwrap_or_default())
+}
+
+fn count_residents(conn: &mut SqliteConnection) -> QueryResult<usize> {
+    schema::residents::table
+        .filter(schema::residents::end_date.is_null())
+        .select(schema::residents::tg_id)
+        .distinct()
+        .count()
+        .get_result::<i64>(conn)
+        .map(|n| n.try_into().unwrap_or_default())
+}
+
+fn progress_text(
+    question: &str,
+    options: &[String],
+    submitted: usize,
+    residents: usize,
+) -> String {
+    let mut text = String::new();
+    format_to!(text, "🏆 <b>Ranked vote</b>: {}\n\n", html::escape(question));
+    for option in options {
+        format_to!(text, "• {}\n", html::escape(option));
+    }
+    format_to!(
+        text,
+        "\nSubmitted {submitted} of {residents} residents. Ballots are sent \
+         in private messages.",
+    );
+    text
+}
+
+fn ballot_text(
+    question: &str,
+    options: &[String],
+    ranking: &[usize],
+    submitted: bool,
+) -> String {
+    let mut text = String::new();
+    format_to!(text, "🏆 <b>Ranked vote</b>: {}\n\n", html::escape(question));
+    if ranking.is_empty() {
+        text.push_str("Choose your most preferred option.");
+        return text;
+    }
+    text.push_str("Your ranking:\n");
+    for (place, &option) in ranking.iter().enumerate() {
+        let option = options.get(option).map_or("?", String::as_str);
+        format_to!(text, "{}. {}\n", place + 1, html::escape(option));
+    }
+    if submitted {
+        text.push_str(
+            "\n✅ Submitted. You can change it until the vote is closed.",
+        );
+    } else {
+        format_to!(
+            text,
+            "\nChoose your option #{}, or submit the ranking as is.",
+            ranking.len() + 1,
+        );
+    }
+    text
+}
+
+fn ballot_keyboard(
+    vote_id: i32,
+    options: &[String],
+    ranking: &[usize],
+    submitted: bool,
+) -> InlineKeyboardMarkup {
+    if submitted {
+        return InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+            "Change ranking",
+            format!("rv:{vote_id}:reset"),
+        )]]);
+    }
+    let mut rows = options
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !ranking.contains(i))
+        .map(|(i, option)| {
+            vec![InlineKeyboardButton::callback(
+                option,
+                format!("rv:{vote_id}:{i}"),
+            )]
+        })
+        .collect_vec();
+    if !ranking.is_empty() {
+        rows.push(vec![
+            InlineKeyboardButton::callback(
+                "Reset",
+                format!("rv:{vote_id}:reset"),
+            ),
+            InlineKeyboardButton::callback(
+                "Submit",
+                format!("rv:{vote_id}:submit"),
+            ),
+        ]);
+    }
+    InlineKeyboardMarkup::new(rows)
+}
+
+fn results_text(
+    question: &str,
+    options: &[String],
+    ballots: usize,
+    result: &RunoffResult,
+) -> String {
+    let name = |option: usize| {
+        html::escape(options.get(option).map_or("?", String::as_str))
+    };
+    let mut text = String::new();
+    format_to!(
+        text,
+        "🏆 <b>Ranked vote (closed)</b>: {}\n\n",
+        html::escape(question),
+    );
+    for (i, round) in result.rounds.iter().enumerate() {
+        format_to!(
+            text,
+            "Round {}: {}.",
+            i + 1,
+            round
+                .counts
+                .iter()
+                .map(|&(o, n)| format!("{} — {n}", name(o)))
+                .join(", "),
+        );
+        if !round.eliminated.is_empty() {
+            format_to!(
+                text,
+                " Eliminated: {}.",
+                round.eliminated.iter().map(|&o| name(o)).join(", "),
+            );
+        }
+        text.push('\n');
+    }
+    match result.winner {
+        Some(winner) => format_to!(text, "\nWinner: <b>{}</b>", name(winner)),
+        None => text.push_str("\nNo winner: the remaining options are tied."),
+    }
+    format_to!(text, "\nBallots: {ballots}");
+    text
+}
+
+/// A round of instant-runoff counting.
+#[derive(Debug, PartialEq, Eq)]
+struct Round {
+    /// Votes for each continuing option, as `(option, votes)`.
+    counts: Vec<(usize, usize)>,
+    /// Options eliminated after this round.
+    eliminated: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct RunoffResult {
+    rounds: Vec<Round>,
+    winner: Option<usize>,
+}
+
+/// Count ballots using instant-runoff voting.  Each ballot lists option
+/// indices from the most preferred; ballots may rank only some options.  All
+/// options tied for the last place are eliminated at once.
+fn instant_runoff(options: usize, ballots: &[Vec<usize>]) -> RunoffResult {
+    let mut continuing = (0..options).collect_vec();
+    let mut rounds = Vec::new();
+    loop {
+        let mut counts = vec![0; options];
+        let mut active = 0;
+        for ballot in ballots {
+            if let Some(&top) = ballot.iter().find(|o| continuing.contains(o)) {
+                counts[top] += 1;
+                active += 1;
+            }
+        }
+        let round_counts = continuing.iter().map(|&o| (o, counts[o])).collect();
+
+        let leader = continuing.iter().copied().max_by_key(|&o| counts[o]);
+        if let Some(leader) = leader {
+            if counts[leader] * 2 > active || continuing.len() == 1 {
+                rounds.push(Round { counts: round_counts, eliminated: vec![] });
+                return RunoffResult { rounds, winner: Some(leader) };
+            }
+        }
+
+        let min = continuing.iter().map(|&o| counts[o]).min().unwrap_or(0);
+        let eliminated = continuing
+            .iter()
+            .copied()
+            .filter(|&o| counts[o] == min)
+            .collect_vec();
+        let all_tied = eliminated.len() == continuing.len();
+        continuing.retain(|o| !eliminated.contains(o));
+        rounds.push(Round { counts: round_counts, eliminated });
+        if all_tied {
+            return RunoffResult { rounds, winner: None };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instant_runoff() {
+        let ballots =
+            [vec![vec![0, 1, 2]; 4], vec![vec![1, 2]; 3], vec![vec![2, 1]; 2]]
+                .concat();
+        assert_eq!(
+            instant_runoff(3, &ballots),
+            RunoffResult {
+                rounds: vec![
+                    Round {
+                        counts: vec![(0, 4), (1, 3), (2, 2)],
+                        eliminated: vec![2],
+                    },
+                    Round { counts: vec![(0, 4), (1, 5)], eliminated: vec![] },
+                ],
+                winner: Some(1),
+            },
+        );
+    }
+
+    #[test]
+    fn test_instant_runoff_majority() {
+        let ballots = vec![vec![0], vec![0, 1], vec![1]];
+        assert_eq!(instant_runoff(2, &ballots).winner, Some(0));
+    }
+
+    #[test]
+    fn test_instant_runoff_tie() {
+        let ballots = vec![vec![0], vec![1]];
+        assert_eq!(
+            instant_runoff(3, &ballots),
+            RunoffResult {
+                rounds: vec![
+                    Round {
+                        counts: vec![(0, 1), (1, 1), (2, 0)],
+                        eliminated: vec![2],
+                    },
+                    Round {
+                        counts: vec![(0, 1), (1, 1)],
+                        eliminated: vec![0, 1],
+                    },
+                ],
+                winner: None,
+            },
+        );
+        assert_eq!(instant_runoff(2, &[]).winner, None);
+    }
+}
@@ -0,0 +1,493 @@
+//! Reimbursement requests: residents submit expenses with `/reimburse`, and
+//! treasurers approve, reject, or mark them as paid using inline buttons.
+//!
+//! **Scope**: requests are posted to the [`telegram.treasury.requests`] thread.
+//! The module is disabled if the `telegram.treasury` section is absent.
+//!
+//! [`telegram.treasury.requests`]: crate::config::Treasury::requests
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::Datelike as _;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, InputFile, ParseMode, User,
+};
+
+use crate::common::{
+    filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::config::Treasury;
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(
+        description = "request a reimbursement: <code>/reimburse amount \
+                       description</code>, optionally with a receipt photo."
+    )]
+    #[custom(resident = true)]
+    Reimburse(String),
+
+    #[command(description = "show budget report.")]
+    #[custom(resident = true)]
+    Budget,
+}
+
+/// Status of a reimbursement request, stored as text in the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Approved,
+    Rejected,
+    Paid,
+}
+
+impl Status {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+            Self::Paid => "paid",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        [Self::Pending, Self::Approved, Self::Rejected, Self::Paid]
+            .into_iter()
+            .find(|status| status.as_str() == s)
+    }
+
+    const fn emoji(self) -> &'static str {
+        match self {
+            Self::Pending => "🕓",
+            Self::Approved => "✅",
+            Self::Rejected => "❌",
+            Self::Paid => "💸",
+        }
+    }
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(start)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+async fn start(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    command: Commands,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.treasury else {
+        bot.reply_message(&msg, "Reimbursements are not configured.").await?;
+        return Ok(());
+    };
+    match command {
+        Commands::Reimburse(args) => {
+            cmd_reimburse(bot, &env, conf, msg, &args).await
+        }
+        Commands::Budget => cmd_budget(bot, &env, conf, msg).await,
+    }
+}
+
+async fn cmd_reimburse(
+    bot: Bot,
+    env: &BotEnv,
+    conf: &Treasury,
+    msg: Message,
+    args: &str,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let parsed = args.trim().split_once(char::is_whitespace).and_then(
+        |(amount, description)| {
+            let description = description.trim();
+            (!description.is_empty())
+                .then_some((parse_amount(amount)?, description))
+        },
+    );
+    let Some((amount_cents, description)) = parsed else {
+        bot.reply_message(
+            &msg,
+            "Usage: /reimburse <amount> <description>\n\nAttach a receipt \
+             photo to the message, or reply to it.",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let receipt = msg
+        .photo()
+        .or_else(|| msg.reply_to_message().and_then(|m| m.photo()))
+        .and_then(|sizes| sizes.last())
+        .map(|photo| photo.file.id.clone());
+
+    let requester = models::TgUser {
+        id: from.id.into(),
+        username: from.username.clone(),
+        first_name: from.first_name.clone(),
+        last_name: from.last_name.clone(),
+    };
+    let text = request_text(
+        conf,
+        requester.id,
+        Some(&requester),
+        amount_cents,
+        description,
+        Status::Pending,
+        None,
+    );
+    let keyboard = keyboard(Status::Pending);
+    let posted = match &receipt {
+        Some(file_id) => {
+            bot.send_photo(conf.requests.chat, InputFile::file_id(file_id))
+                .message_thread_id(conf.requests.thread)
+                .caption(text)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(keyboard)
+                .await?
+        }
+        None => {
+            bot.send_message(conf.requests.chat, text)
+                .message_thread_id(conf.requests.thread)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(keyboard)
+                .await?
+        }
+    };
+
+    let rowid = env.transaction(|conn| {
+        diesel::insert_into(schema::reimbursements::table)
+            .values(models::NewReimbursement {
+                requester_id: from.id.into(),
+                amount_cents,
+                description,
+                receipt_file_id: receipt.as_deref(),
+                chat_id: posted.chat.id.into(),
+                message_id: posted.id.into(),
+                status: Status::Pending.as_str(),
+                created_at: chrono::Utc::now().naive_utc(),
+            })
+            .execute(conn)?;
+        schema::reimbursements::table
+            .filter(
+                schema::reimbursements::chat_id
+                    .eq(DbChatId::from(posted.chat.id)),
+            )
+            .filter(
+                schema::reimbursements::message_id
+                    .eq(DbMessageId::from(posted.id)),
+            )
+            .select(schema::reimbursements::rowid)
+            .first::<i32>(conn)
+    })?;
+
+    bot.reply_message(
+        &msg,
+        format!(
+            "Reimbursement request #{rowid} for {} is sent to the treasurer.",
+            format_amount(amount_cents, &conf.currency),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn cmd_budget(
+    bot: Bot,
+    env: &BotEnv,
+    conf: &Treasury,
+    msg: Message,
+) -> Result<()> {
+    let rows: Vec<(String, i64, Option<chrono::NaiveDateTime>)> =
+        schema::reimbursements::table
+            .select((
+                schema::reimbursements::status,
+                schema::reimbursements::amount_cents,
+                schema::reimbursements::paid_at,
+            ))
+            .load(&mut *env.conn())?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let (mut pending, mut approved) = ((0, 0), (0, 0));
+    let (mut paid_month, mut paid_total) = (0, 0);
+    for (status, amount, paid_at) in rows {
+        match Status::parse(&status) {
+            Some(Status::Pending) => {
+                pending.0 += 1;
+                pending.1 += amount;
+            }
+            Some(Status::Approved) => {
+                approved.0 += 1;
+                approved.1 += amount;
+            }
+            Some(Status::Paid) => {
+                paid_total += amount;
+                if paid_at.is_some_and(|t| {
+                    t.year() == now.year() && t.month() == now.month()
+                }) {
+                    paid_month += amount;
+                }
+            }
+            Some(Status::Rejected) | None => (),
+        }
+    }
+
+    let amount = |cents| format_amount(cents, &conf.currency);
+    let mut text = String::new();
+    text.push_str("💰 <b>Budget report</b>\n\n<b>Reimbursements</b>\n");
+    format_to!(
+        text,
+        "Pending: {} ({} requests)\n",
+        amount(pending.1),
+        pending.0
+    );
+    format_to!(
+        text,
+        "Approved, not paid: {} ({} requests)\n",
+        amount(approved.1),
+        approved.0,
+    );
+    format_to!(text, "Paid this month: {}\n", amount(paid_month));
+    format_to!(text, "Paid in total: {}\n", amount(paid_total));
+
+    let donations = crate::modules::donations::totals(&mut env.conn(), now)?;
+    text.push_str("\n<b>Donations</b>\n");
+    format_to!(
+        text,
+        "This month: {} ({} donors)\n",
+        amount(donations.this_month_cents),
+        donations.donors_this_month,
+    );
+    format_to!(text, "This year: {}\n", amount(donations.this_year_cents));
+    format_to!(text, "In total: {}\n", amount(donations.total_cents));
+
+    bot.reply_message(&msg, text).parse_mode(ParseMode::Html).await?;
+
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<Status> {
+    match callback.data.as_ref()?.strip_prefix("re:")? {
+        "approve" => Some(Status::Approved),
+        "reject" => Some(Status::Rejected),
+        "paid" => Some(Status::Paid),
+        _ => None,
+    }
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    new_status: Status,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.treasury else { return Ok(()) };
+    let Some(message) = &callback.message else { return Ok(()) };
+    if !conf.treasurers.contains(&callback.from.id) {
+        bot.answer_callback_query(&callback.id)
+            .text("Only treasurers can do this.")
+            .await?;
+        return Ok(());
+    }
+
+    let result = env.transaction(|conn| {
+        use schema::reimbursements::dsl as r;
+        let request = r::reimbursements
+            .filter(r::chat_id.eq(DbChatId::from(message.chat.id)))
+            .filter(r::message_id.eq(DbMessageId::from(message.id)))
+            .select(models::Reimbursement::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(request) = request else {
+            return Ok(Err("Unknown request."));
+        };
+        let old_status = Status::parse(&request.status);
+        let now = chrono::Utc::now().naive_utc();
+        let target = diesel::update(r::reimbursements)
+            .filter(r::rowid.eq(request.rowid))
+            .filter(r::status.eq(&request.status));
+        match (old_status, new_status) {
+            (Some(Status::Pending), Status::Approved | Status::Rejected) => {
+                target
+                    .set((
+                        r::status.eq(new_status.as_str()),
+                        r::decided_at.eq(now),
+                        r::decided_by.eq(DbUserId::from(callback.from.id)),
+                    ))
+                    .execute(conn)?;
+            }
+            (Some(Status::Approved), Status::Paid) => {
+                target
+                    .set((
+                        r::status.eq(new_status.as_str()),
+                        r::paid_at.eq(now),
+                    ))
+                    .execute(conn)?;
+            }
+            _ => return Ok(Err("This request is already handled.")),
+        }
+        let requester = schema::tg_users::table
+            .filter(schema::tg_users::id.eq(request.requester_id))
+            .select(models::TgUser::as_select())
+            .first(conn)
+            .optional()?;
+        Ok(Ok((request, requester)))
+    })?;
+
+    let (request, requester) = match result {
+        Ok(result) => result,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&callback.id).await?;
+
+    crate::modules::audit::record(
+        &mut env.conn(),
+        Some(callback.from.id),
+        &format!("reimbursement_{}", new_status.as_str()),
+        &serde_json::json!({
+            "id": request.rowid,
+            "requester_id": request.requester_id,
+            "amount_cents": request.amount_cents,
+        }),
+    )
+    .log_error("reimbursements: audit");
+
+    let text = request_text(
+        conf,
+        request.requester_id,
+        requester.as_ref(),
+        request.amount_cents,
+        &request.description,
+        new_status,
+        Some(&callback.from),
+    );
+    if request.receipt_file_id.is_some() {
+        bot.edit_message_caption(message.chat.id, message.id)
+            .caption(text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(keyboard(new_status))
+            .await?;
+    } else {
+        bot.edit_message_text(message.chat.id, message.id, text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(keyboard(new_status))
+            .await?;
+    }
+
+    bot.send_message(
+        UserId::from(request.requester_id),
+        format!(
+            "{} Your reimbursement request #{} for {} is {}.",
+            new_status.emoji(),
+            request.rowid,
+            format_amount(request.amount_cents, &conf.currency),
+            new_status.as_str(),
+        ),
+    )
+    .await
+    .log_error("reimbursements: notify requester");
+
+    Ok(())
+}
+
+fn request_text(
+    conf: &Treasury,
+    requester_id: DbUserId,
+    requester: Option<&models::TgUser>,
+    amount_cents: i64,
+    description: &str,
+    status: Status,
+    changed_by: Option<&User>,
+) -> String {
+    let mut text = String::new();
+    text.push_str("💶 <b>Reimbursement request</b>\n\nFrom: ");
+    format_user(&mut text, requester_id, requester, true);
+    format_to!(
+        text,
+        "\nAmount: {}\n\n{}\n\n",
+        format_amount(amount_cents, &conf.currency),
+        html::escape(description),
+    );
+    format_to!(text, "Status: {} {}", status.emoji(), status.as_str());
+    if let Some(user) = changed_by {
+        format_to!(text, " by {}", html::escape(&user.full_name()));
+    }
+    text
+}
+
+fn keyboard(status: Status) -> InlineKeyboardMarkup {
+    let buttons = match status {
+        Status::Pending => vec![
+            InlineKeyboardButton::callback("Approve", "re:approve"),
+            InlineKeyboardButton::callback("Reject", "re:reject"),
+        ],
+        Status::Approved => {
+            vec![InlineKeyboardButton::callback("Mark as paid", "re:paid")]
+        }
+        Status::Rejected | Status::Paid => Vec::new(),
+    };
+    InlineKeyboardMarkup::new([buttons])
+}
+
+/// Parse a positive amount with at most two decimal places into cents.
+pub fn parse_amount(s: &str) -> Option<i64> {
+    let s = s.replace(',', ".");
+    let (whole, frac) = s.split_once('.').unwrap_or((&s, ""));
+    if whole.is_empty()
+        || frac.len() > 2
+        || !whole.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let cents = whole
+        .parse::<i64>()
+        .ok()?
+        .checked_mul(100)?
+        .checked_add(format!("{frac:0<2}").parse::<i64>().ok()?)?;
+    (cents > 0).then_some(cents)
+}
+
+/// Format an amount in cents, e.g. `12.05 EUR`.
+pub fn format_amount(cents: i64, currency: &str) -> String {
+    format!("{}.{:02} {currency}", cents / 100, cents % 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("12"), Some(1200));
+        assert_eq!(parse_amount("12.5"), Some(1250));
+        assert_eq!(parse_amount("12,05"), Some(1205));
+        assert_eq!(parse_amount("0.01"), Some(1));
+        assert_eq!(parse_amount("0"), None);
+        assert_eq!(parse_amount("-5"), None);
+        assert_eq!(parse_amount("1.234"), None);
+        assert_eq!(parse_amount(".5"), None);
+        assert_eq!(parse_amount("abc"), None);
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(1205, "EUR"), "12.05 EUR");
+        assert_eq!(format_amount(7, "EUR"), "0.07 EUR");
+    }
+}
@@ -0,0 +1,470 @@
+//! Periodically import residents from an external source of truth, and
+//! export them back.
+//!
+//! **Scope**: users listed in the [`services.resident_sync.source`] are added
+//! to residents.  Residents missing from the source are never removed
+//! automatically; instead, they are reported as conflicts to the
+//! [`services.resident_sync.report_to`] thread.  After each sync, the current
+//! residents are uploaded to [`services.resident_sync.export`] as CSV.
+//!
+//! [`services.resident_sync.source`]: crate::config::ResidentSync::source
+//! [`services.resident_sync.report_to`]: crate::config::ResidentSync::report_to
+//! [`services.resident_sync.export`]: crate::config::ResidentSync::export
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use ldap3::{LdapConnAsync, Scope, SearchEntry};
+use serde::Deserialize;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{format_users, BotEnv};
+use crate::config::{ResidentExport, ResidentSource, ResidentSync};
+use crate::db::DbUserId;
+use crate::modules::role_changes;
+use crate::utils::ResultExt as _;
+use crate::{models, schema};
+
+/// Page size used for paginated HTTP sources.
+const PAGE_SIZE: usize = 100;
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.services.resident_sync else { return };
+    loop {
+        let result = fetch_source(&env.reqwest_client, &conf.source).await;
+        crate::metrics::update_service(
+            &env.health,
+            "resident_sync",
+            result.is_ok(),
+        );
+        match result {
+            Ok(ids) => {
+                sync(&env, &bot, conf, &ids)
+                    .await
+                    .log_error("resident_sync: sync");
+            }
+            Err(e) => log::error!("resident_sync: failed to fetch: {e:#}"),
+        }
+        if let Some(export_conf) = &conf.export {
+            let result = export(&env, export_conf).await;
+            crate::metrics::update_service(
+                &env.health,
+                "resident_sync_export",
+                result.is_ok(),
+            );
+            result.log_error("resident_sync: export");
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(conf.interval_minutes * 60)) => {}
+        }
+    }
+}
+
+async fn sync(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &ResidentSync,
+    source_ids: &BTreeSet<UserId>,
+) -> Result<()> {
+    let (added, conflicts, users) = env.transaction(|conn| {
+        let current: BTreeSet<UserId> = schema::residents::table
+            .filter(schema::residents::end_date.is_null())
+            .select(schema::residents::tg_id)
+            .load::<DbUserId>(conn)?
+            .into_iter()
+            .map(UserId::from)
+            .collect();
+
+        let added = source_ids.difference(&current).copied().collect_vec();
+        for &id in &added {
+            role_changes::track(conn, id, |conn| {
+                diesel::insert_into(schema::residents::table)
+                    .values((
+                        schema::residents::tg_id.eq(DbUserId::from(id)),
+                        schema::residents::begin_date.eq(diesel::dsl::now),
+                    ))
+                    .execute(conn)
+            })?;
+            crate::modules::audit::record(
+                conn,
+                None,
+                "resident_add",
+                &serde_json::json!({ "user_id": id.0, "source": "sync" }),
+            )
+            .log_error("resident_sync: audit");
+        }
+
+        let conflicts = current.difference(source_ids).copied().collect_vec();
+
+        let users: HashMap<UserId, models::TgUser> = schema::tg_users::table
+            .filter(schema::tg_users::id.eq_any(
+                added.iter().chain(&conflicts).map(|&id| DbUserId::from(id)),
+            ))
+            .load::<models::TgUser>(conn)?
+            .into_iter()
+            .map(|u| (u.id.into(), u))
+            .collect();
+
+        Ok((added, conflicts, users))
+    })?;
+    if !added.is_empty() {
+        role_changes::committed(env);
+    }
+
+    let last_conflicts =
+        models::resident_sync_conflicts.get(&mut env.conn())?;
+    let conflicts_changed =
+        last_conflicts.as_ref().map_or(true, |c| *c != conflicts);
+    if added.is_empty() && !conflicts_changed {
+        return Ok(());
+    }
+
+    let mut text = String::new();
+    if !added.is_empty() {
+        text.push_str("Added residents from the external source: ");
+        format_users(&mut text, added.iter().map(|id| (*id, users.get(id))));
+        text.push('\n');
+    }
+    if conflicts_changed {
+        if conflicts.is_empty() {
+            text.push_str("All residents are now present in the source.");
+        } else {
+            text.push_str("Residents missing from the external source: ");
+            format_users(
+                &mut text,
+                conflicts.iter().map(|id| (*id, users.get(id))),
+            );
+            text.push_str(
+                "\nPlease update the source or remove them from residents.",
+            );
+        }
+    }
+
+    bot.send_message(conf.report_to.chat, text)
+        .message_thread_id(conf.report_to.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await?;
+
+    models::resident_sync_conflicts.set(&mut env.conn(), &conflicts)?;
+
+    Ok(())
+}
+
+/// Upload the current residents.
+async fn export(env: &BotEnv, conf: &ResidentExport) -> Result<()> {
+    let body = export_csv(&mut env.conn(), &conf.column)?;
+    let mut request = env
+        .reqwest_client
+        .put(&conf.url)
+        .header(reqwest::header::CONTENT_TYPE, "text/csv")
+        .body(body);
+    if let Some(token) = &conf.token {
+        request = request.bearer_auth(token);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// Current residents as a CSV document readable by [`parse_csv`], with the
+/// user IDs in the `column`.
+fn export_csv(conn: &mut SqliteConnection, column: &str) -> Result<String> {
+    let residents: Vec<(DbUserId, Option<models::TgUser>)> =
+        schema::residents::table
+            .filter(schema::residents::end_date.is_null())
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::residents::tg_id.eq(schema::tg_users::id)),
+            )
+            .order(schema::residents::tg_id)
+            .select((
+                schema::residents::tg_id,
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(conn)?;
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([column, "username", "first_name", "last_name"])?;
+    for (id, user) in residents {
+        let user = user.as_ref();
+        writer.write_record([
+            UserId::from(id).0.to_string().as_str(),
+            user.and_then(|u| u.username.as_deref()).unwrap_or_default(),
+            user.map_or("", |u| u.first_name.as_str()),
+            user.and_then(|u| u.last_name.as_deref()).unwrap_or_default(),
+        ])?;
+    }
+    let body = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8(body)?)
+}
+
+async fn fetch_source(
+    client: &reqwest::Client,
+    source: &ResidentSource,
+) -> Result<BTreeSet<UserId>> {
+    match source {
+        ResidentSource::Authentik { url, token, group, attribute } => {
+            fetch_authentik(client, url, token, group, attribute).await
+        }
+        ResidentSource::Keycloak {
+            url,
+            realm,
+            client_id,
+            client_secret,
+            group_id,
+            attribute,
+        } => {
+            let token =
+                keycloak_token(client, url, realm, client_id, client_secret)
+                    .await?;
+            fetch_keycloak(client, url, realm, &token, group_id, attribute)
+                .await
+        }
+        ResidentSource::Ldap {
+            url,
+            bind_dn,
+            bind_password,
+            base_dn,
+            filter,
+            attribute,
+        } => {
+            fetch_ldap(url, bind_dn, bind_password, base_dn, filter, attribute)
+                .await
+        }
+        ResidentSource::Csv { url, column } => {
+            let body = client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            parse_csv(&body, column)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AuthentikPage {
+    pagination: AuthentikPagination,
+    results: Vec<AuthentikUser>,
+}
+
+#[derive(Deserialize)]
+struct AuthentikPagination {
+    next: u64,
+}
+
+#[derive(Deserialize)]
+struct AuthentikUser {
+    attributes: HashMap<String, serde_json::Value>,
+}
+
+async fn fetch_authentik(
+    client: &reqwest::Client,
+    url: &str,
+    token: &str,
+    group: &str,
+    attribute: &str,
+) -> Result<BTreeSet<UserId>> {
+    let mut ids = BTreeSet::new();
+    let mut page = 1;
+    loop {
+        let resp: AuthentikPage = client
+            .get(format!("{}/api/v3/core/users/", url.trim_end_matches('/')))
+            .bearer_auth(token)
+            .query(&[
+                ("groups_by_name", group),
+                ("page", &page.to_string()),
+                ("page_size", &PAGE_SIZE.to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        for user in resp.results {
+            if let Some(id) = user.attributes.get(attribute).and_then(json_id) {
+                ids.insert(id);
+            }
+        }
+        // Authentik uses 0 to indicate the last page.
+        if resp.pagination.next == 0 {
+            break;
+        }
+        page = resp.pagination.next;
+    }
+    Ok(ids)
+}
+
+#[derive(Deserialize)]
+struct KeycloakToken {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct KeycloakUser {
+    #[serde(default)]
+    attributes: HashMap<String, Vec<String>>,
+}
+
+async fn keycloak_token(
+    client: &reqwest::Client,
+    url: &str,
+    realm: &str,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<String> {
+    let resp: KeycloakToken = client
+        .post(format!(
+            "{}/realms/{realm}/protocol/openid-connect/token",
+            url.trim_end_matches('/'),
+        ))
+        .form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(resp.access_token)
+}
+
+async fn fetch_keycloak(
+    client: &reqwest::Client,
+    url: &str,
+    realm: &str,
+    token: &str,
+    group_id: &str,
+    attribute: &str,
+) -> Result<BTreeSet<UserId>> {
+    let mut ids = BTreeSet::new();
+    let mut first = 0;
+    loop {
+        let users: Vec<KeycloakUser> = client
+            .get(format!(
+                "{}/admin/realms/{realm}/groups/{group_id}/members",
+                url.trim_end_matches('/'),
+            ))
+            .bearer_auth(token)
+            .query(&[("first", first), ("max", PAGE_SIZE)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let count = users.len();
+        ids.extend(users.iter().filter_map(|u| {
+            u.attributes
+                .get(attribute)
+                .and_then(|v| v.first())
+                .and_then(|v| parse_id(v))
+        }));
+        if count < PAGE_SIZE {
+            break;
+        }
+        first += count;
+    }
+    Ok(ids)
+}
+
+async fn fetch_ldap(
+    url: &str,
+    bind_dn: &str,
+    bind_password: &str,
+    base_dn: &str,
+    filter: &str,
+    attribute: &str,
+) -> Result<BTreeSet<UserId>> {
+    let (conn, mut ldap) = LdapConnAsync::new(url).await?;
+    ldap3::drive!(conn);
+    ldap.simple_bind(bind_dn, bind_password).await?.success()?;
+    let (entries, _) = ldap
+        .search(base_dn, Scope::Subtree, filter, vec![attribute])
+        .await?
+        .success()?;
+    ldap.unbind().await.log_error("resident_sync: ldap unbind");
+
+    Ok(entries
+        .into_iter()
+        .map(SearchEntry::construct)
+        .filter_map(|e| {
+            e.attrs
+                .get(attribute)
+                .and_then(|v| v.first())
+                .and_then(|v| parse_id(v))
+        })
+        .collect())
+}
+
+/// Extract user IDs from a column of a CSV document with a header row.
+fn parse_csv(body: &str, column: &str) -> Result<BTreeSet<UserId>> {
+    let mut reader = csv::Reader::from_reader(body.as_bytes());
+    let Some(index) = reader.headers()?.iter().position(|h| h.trim() == column)
+    else {
+        bail!("Column {column:?} not found");
+    };
+    let mut ids = BTreeSet::new();
+    for record in reader.records() {
+        let record = record.context("Failed to parse CSV")?;
+        if let Some(id) = record.get(index).and_then(parse_id) {
+            ids.insert(id);
+        }
+    }
+    Ok(ids)
+}
+
+fn json_id(value: &serde_json::Value) -> Option<UserId> {
+    match value {
+        serde_json::Value::Number(n) => n.as_u64().map(UserId),
+        serde_json::Value::String(s) => parse_id(s),
+        _ => None,
+    }
+}
+
+fn parse_id(value: &str) -> Option<UserId> {
+    value.trim().parse().ok().map(UserId)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestBot;
+
+    #[test]
+    fn test_export_round_trip() {
+        let t = TestBot::new();
+        t.add_resident(123, "alice", "Alice, \"Al\"");
+        t.add_resident(456, "bob", "Bob");
+        let csv = export_csv(&mut t.env.conn(), "telegram_id").unwrap();
+        assert!(csv.starts_with("telegram_id,username,first_name,last_name\n"));
+        assert_eq!(
+            parse_csv(&csv, "telegram_id").unwrap(),
+            BTreeSet::from([UserId(123), UserId(456)]),
+        );
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let body = "name,telegram_id\nAlice,123\nBob,\nCarol, 456 \nDave,x\n";
+        assert_eq!(
+            parse_csv(body, "telegram_id").unwrap(),
+            BTreeSet::from([UserId(123), UserId(456)]),
+        );
+        assert!(parse_csv(body, "missing").is_err());
+    }
+}
@@ -15,6 +15,7 @@ use teloxide::types::{Chat, ChatKind, ChatPublic, UpdateKind, User};
 
 use crate::common::BotEnv;
 use crate::db::{DbChatId, DbUserId};
+use crate::modules::role_changes;
 use crate::schema;
 use crate::utils::ResultExt;
 
@@ -27,10 +28,20 @@ struct Filtered<'a> {
 pub fn inspect_update(env: Arc<BotEnv>, upd: Update) {
     let residential_chats = env.config.telegram.chats.residential.as_slice();
     let Some(filtered) = filter(&upd, residential_chats) else { return };
-    env.transaction(|conn| {
-        handle_update_transaction(conn, residential_chats, filtered)
-    })
-    .log_error("resident_tracker::handle_update");
+    let user = filtered.cm.new_chat_member.user.id;
+    let result = env.transaction(|conn| {
+        let action = role_changes::track(conn, user, |conn| {
+            handle_update_transaction(conn, residential_chats, &filtered)
+        })?;
+        if let Some(action) = action {
+            audit(conn, &filtered, action);
+        }
+        Ok(action.is_some())
+    });
+    result.log_error("resident_tracker::handle_update");
+    if matches!(result, Ok(true)) {
+        role_changes::committed(&env);
+    }
 }
 
 /// Scrape an update for residential chat joins/leaves and update the
@@ -41,7 +52,7 @@ pub fn scrape(
     residential_chats: &[ChatId],
 ) -> Result<(), diesel::result::Error> {
     let Some(filtered) = filter(upd, residential_chats) else { return Ok(()) };
-    handle_update_transaction(conn, residential_chats, filtered)
+    handle_update_transaction(conn, residential_chats, &filtered).map(|_| ())
 }
 
 fn filter<'a>(
@@ -72,11 +83,12 @@ fn filter<'a>(
     Some(Filtered { cm, is_joined })
 }
 
+/// Returns the audit log action if residency has changed.
 fn handle_update_transaction(
     conn: &mut SqliteConnection,
     residential_chats: &[ChatId],
-    f: Filtered<'_>,
-) -> Result<(), diesel::result::Error> {
+    f: &Filtered<'_>,
+) -> Result<Option<&'static str>, diesel::result::Error> {
     let user_id = DbUserId::from(f.cm.new_chat_member.user.id);
 
     let residential_chats =
@@ -116,6 +128,7 @@ fn handle_update_transaction(
                 .filter(r::end_date.is_null())
                 .set(r::end_date.eq(diesel::dsl::now))
                 .execute(conn)?;
+            return Ok(Some("resident_remove"));
         }
         (false, true, true) => {
             // Add to residency
@@ -131,6 +144,7 @@ fn handle_update_transaction(
                     r::begin_date.eq(diesel::dsl::now),
                 ))
                 .execute(conn)?;
+            return Ok(Some("resident_add"));
         }
         // Do not make any unintuitive changes. E.g. if a non-resident left
         // a residential chat, do not add them to residency, even if they
@@ -138,7 +152,20 @@ fn handle_update_transaction(
         _ => (),
     }
 
-    Ok(())
+    Ok(None)
+}
+
+fn audit(conn: &mut SqliteConnection, f: &Filtered<'_>, action: &str) {
+    crate::modules::audit::record(
+        conn,
+        Some(f.cm.from.id),
+        action,
+        &serde_json::json!({
+            "user_id": f.cm.new_chat_member.user.id.0,
+            "chat_id": f.cm.chat.id.0,
+        }),
+    )
+    .log_error("resident_tracker: audit");
 }
 
 fn user_text(user: &User) -> String {
@@ -0,0 +1,199 @@
+//! Data retention.
+//!
+//! Tables listed in [`TABLES`] have a `deleted_at` column.  Rows older than
+//! the number of days set in [`telegram.retention.tables`] are soft-deleted:
+//! they are hidden from reports but kept for
+//! [`telegram.retention.grace_days`] before being purged.  Tables without a
+//! policy are kept forever.  Modules that show rows of these tables to users
+//! should filter out rows with `deleted_at` set.
+//!
+//! **Scope**: background task; `/retention status` command, available to
+//! admins.
+//!
+//! [`telegram.retention.tables`]: crate::config::Retention::tables
+//! [`telegram.retention.grace_days`]: crate::config::Retention::grace_days
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::sql_types::{BigInt, Nullable, Timestamp};
+use diesel::{sql_query, QueryableByName, RunQueryDsl, SqliteConnection};
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::config::Retention;
+use crate::utils::{format_to, BotExt as _};
+
+/// How often to apply the retention policies.
+const RUN_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Tables supporting retention, with their timestamp columns.
+pub const TABLES: &[(&str, &str)] = &[
+    ("archived_links", "archived_at"),
+    ("audit_log", "timestamp"),
+    ("mail_messages", "created_at"),
+    ("presence_log", "timestamp"),
+];
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show data retention status: \
+                             <code>/retention status</code>.")]
+    #[custom(admin = true)]
+    Retention(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_retention)
+}
+
+#[derive(QueryableByName)]
+struct TableStats {
+    #[diesel(sql_type = BigInt)]
+    live: i64,
+    #[diesel(sql_type = BigInt)]
+    deleted: i64,
+    #[diesel(sql_type = Nullable<Timestamp>)]
+    oldest: Option<chrono::NaiveDateTime>,
+}
+
+async fn cmd_retention(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Retention(args): Commands,
+) -> Result<()> {
+    if args.trim() != "status" {
+        bot.reply_message(&msg, "Usage: /retention status").await?;
+        return Ok(());
+    }
+    let conf = env.config.telegram.retention.as_ref();
+
+    let mut text = String::from("🗃 <b>Data retention</b>\n");
+    for (table, column) in TABLES {
+        let stats: TableStats = sql_query(format!(
+            "SELECT COALESCE(SUM(deleted_at IS NULL), 0) AS live, \
+             COALESCE(SUM(deleted_at IS NOT NULL), 0) AS deleted, \
+             MIN(CASE WHEN deleted_at IS NULL THEN {column} END) AS oldest \
+             FROM {table}"
+        ))
+        .get_result(&mut *env.conn())?;
+        let policy = conf.and_then(|c| c.tables.get(*table)).map_or_else(
+            || "forever".to_string(),
+            |days| format!("{days} days"),
+        );
+        format_to!(
+            text,
+            "\n<b>{table}</b>: keep {policy}, {} rows",
+            stats.live,
+        );
+        if let Some(oldest) = stats.oldest {
+            format_to!(text, " since {}", oldest.format("%Y-%m-%d"));
+        }
+        if stats.deleted > 0 {
+            format_to!(text, ", {} soft-deleted", stats.deleted);
+        }
+    }
+    if let Some(conf) = conf {
+        format_to!(
+            text,
+            "\n\nSoft-deleted rows are purged after {} days.",
+            conf.grace_days,
+        );
+        for table in unknown_tables(conf) {
+            format_to!(text, "\n⚠️ Unsupported table in config: {table}");
+        }
+    } else {
+        text.push_str("\n\nRetention is not configured.");
+    }
+    bot.reply_message(&msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+/// Tables in the config that do not support retention.
+fn unknown_tables(conf: &Retention) -> impl Iterator<Item = &str> {
+    conf.tables
+        .keys()
+        .map(String::as_str)
+        .filter(|t| !TABLES.iter().any(|(name, _)| name == t))
+}
+
+pub async fn task(env: Arc<BotEnv>, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.retention else { return };
+    for table in unknown_tables(conf) {
+        log::warn!("retention: unsupported table {table}");
+    }
+    loop {
+        let result = env.transaction(|conn| apply(conn, conf));
+        if let Err(e) = result {
+            log::error!("retention: failed: {e}");
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(RUN_INTERVAL) => {}
+        }
+    }
+}
+
+/// Soft-delete expired rows and purge rows past the grace period.
+fn apply(
+    conn: &mut SqliteConnection,
+    conf: &Retention,
+) -> diesel::QueryResult<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let purge_before = now - chrono::Duration::days(i64::from(conf.grace_days));
+    for (table, column) in TABLES {
+        if let Some(days) = conf.tables.get(*table) {
+            let deleted = sql_query(format!(
+                "UPDATE {table} SET deleted_at = ? \
+                 WHERE deleted_at IS NULL AND {column} < ?"
+            ))
+            .bind::<Timestamp, _>(now)
+            .bind::<Timestamp, _>(
+                now - chrono::Duration::days(i64::from(*days)),
+            )
+            .execute(conn)?;
+            if deleted > 0 {
+                log::info!("retention: soft-deleted {deleted} rows of {table}");
+            }
+        }
+        let purged =
+            sql_query(format!("DELETE FROM {table} WHERE deleted_at < ?"))
+                .bind::<Timestamp, _>(purge_before)
+                .execute(conn)?;
+        if purged > 0 {
+            log::info!("retention: purged {purged} rows of {table}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_tables() {
+        let conf = Retention {
+            grace_days: 30,
+            tables: [
+                ("presence_log".to_string(), 365),
+                ("users".to_string(), 1),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        assert_eq!(unknown_tables(&conf).collect::<Vec<_>>(), ["users"]);
+    }
+}
@@ -0,0 +1,246 @@
+//! Hooks fired when a user's computed role changes.
+//!
+//! The role is computed from the `residents` table: a visitor was never a
+//! resident, a resident has an ongoing residency, and a former resident's
+//! residency has ended.  Code that changes residency wraps the change in
+//! [`track`], which records it in the `role_changes` table, and calls
+//! [`committed`] after the commit to publish [`Event::RoleChanged`].  The
+//! task then runs the hooks listed in [`run_hooks`] for each recorded change
+//! in order, so changes made while the task was busy or the bot was offline
+//! are not lost.
+//!
+//! **Scope**: background task.
+
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use teloxide::prelude::*;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::BotEnv;
+use crate::db::DbUserId;
+use crate::events::Event;
+use crate::utils::ResultExt as _;
+use crate::{models, schema};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Never was a resident.
+    Visitor,
+    Resident,
+    /// Was a resident, but the residency has ended.
+    Former,
+}
+
+impl Role {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Visitor => "visitor",
+            Self::Resident => "resident",
+            Self::Former => "former",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "visitor" => Some(Self::Visitor),
+            "resident" => Some(Self::Resident),
+            "former" => Some(Self::Former),
+            _ => None,
+        }
+    }
+}
+
+/// A change passed to hooks.
+#[derive(Clone, Copy, Debug)]
+pub struct RoleChange {
+    pub user: UserId,
+    pub old: Role,
+    pub new: Role,
+}
+
+/// Compute the current role of the user.
+pub fn current_role(
+    conn: &mut SqliteConnection,
+    user: UserId,
+) -> QueryResult<Role> {
+    let ongoing: Vec<bool> = schema::residents::table
+        .filter(schema::residents::tg_id.eq(DbUserId::from(user)))
+        .select(schema::residents::end_date.is_null())
+        .load(conn)?;
+    Ok(if ongoing.contains(&true) {
+        Role::Resident
+    } else if ongoing.is_empty() {
+        Role::Visitor
+    } else {
+        Role::Former
+    })
+}
+
+/// Notify about residency changes made with [`track`].  Call after the
+/// commit: drops cached residents and wakes up the task.
+pub fn committed(env: &BotEnv) {
+    env.cache.residents.invalidate();
+    env.events.publish(Event::RoleChanged);
+}
+
+/// Run `change` and record the resulting role change of the user, if any.
+/// Call inside a transaction, and call [`committed`] after the commit.
+pub fn track<T>(
+    conn: &mut SqliteConnection,
+    user: UserId,
+    change: impl FnOnce(&mut SqliteConnection) -> QueryResult<T>,
+) -> QueryResult<T> {
+    let old = current_role(conn, user)?;
+    let result = change(conn)?;
+    let new = current_role(conn, user)?;
+    if old != new {
+        diesel::insert_into(schema::role_changes::table)
+            .values(models::NewRoleChange {
+                tg_id: user.into(),
+                old_role: old.as_str(),
+                new_role: new.as_str(),
+                changed_at: chrono::Utc::now().naive_utc(),
+            })
+            .execute(conn)?;
+    }
+    Ok(result)
+}
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let mut events = env.events.subscribe();
+    loop {
+        process(&env, &bot).await;
+
+        loop {
+            select! {
+                () = shutdown.cancelled() => return,
+                event = events.recv() => match event {
+                    Ok(Event::RoleChanged) | Err(RecvError::Lagged(_)) => {
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Closed) => return,
+                },
+            }
+        }
+    }
+}
+
+/// Run hooks for all unhandled changes.  A change is marked handled even if
+/// some hooks fail, as hooks are not retried.
+pub async fn process(env: &Arc<BotEnv>, bot: &Bot) {
+    let changes: Vec<models::RoleChange> = match schema::role_changes::table
+        .filter(schema::role_changes::handled_at.is_null())
+        .order(schema::role_changes::rowid)
+        .select(models::RoleChange::as_select())
+        .load(&mut *env.conn())
+    {
+        Ok(changes) => changes,
+        Err(e) => {
+            log::error!("role_changes: failed to load changes: {e}");
+            return;
+        }
+    };
+
+    for change in changes {
+        let (Some(old), Some(new)) =
+            (Role::parse(&change.old_role), Role::parse(&change.new_role))
+        else {
+            log::error!("role_changes: invalid roles in {change:?}");
+            continue;
+        };
+        let role_change = RoleChange { user: change.tg_id.into(), old, new };
+        log::info!("role_changes: {role_change:?}");
+        run_hooks(env, bot, &role_change).await;
+
+        diesel::update(schema::role_changes::table)
+            .filter(schema::role_changes::rowid.eq(change.rowid))
+            .set(
+                schema::role_changes::handled_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            )
+            .execute(&mut *env.conn())
+            .log_error("role_changes: mark handled");
+    }
+}
+
+/// Reactions of other modules to a role change.
+async fn run_hooks(env: &Arc<BotEnv>, bot: &Bot, change: &RoleChange) {
+    crate::modules::command_scopes::on_role_change(env, bot, change)
+        .await
+        .log_error("role_changes: command_scopes");
+    crate::modules::polls::on_role_change(env, bot, change)
+        .log_error("role_changes: polls");
+    crate::modules::userctl::on_role_change(env, change)
+        .log_error("role_changes: userctl");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestBot;
+
+    fn set_residency(
+        t: &TestBot,
+        user: UserId,
+        change: impl FnOnce(&mut SqliteConnection) -> QueryResult<usize>,
+    ) {
+        t.env.transaction(|conn| track(conn, user, change)).unwrap();
+    }
+
+    fn load(t: &TestBot) -> Vec<models::RoleChange> {
+        schema::role_changes::table
+            .select(models::RoleChange::as_select())
+            .load(&mut *t.env.conn())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_role_changes() {
+        let t = TestBot::new();
+        let user = UserId(1);
+        let tg_id = DbUserId::from(user);
+
+        set_residency(&t, user, |conn| {
+            diesel::insert_into(schema::residents::table)
+                .values((
+                    schema::residents::tg_id.eq(tg_id),
+                    schema::residents::begin_date.eq(diesel::dsl::now),
+                ))
+                .execute(conn)
+        });
+        // Not a change of the role.
+        set_residency(&t, user, |_| Ok(0));
+        diesel::insert_into(schema::user_macs::table)
+            .values((
+                schema::user_macs::tg_id.eq(tg_id),
+                schema::user_macs::mac.eq("00:11:22:33:44:55"),
+            ))
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        set_residency(&t, user, |conn| {
+            diesel::update(schema::residents::table)
+                .set(schema::residents::end_date.eq(diesel::dsl::now))
+                .execute(conn)
+        });
+
+        let changes = load(&t);
+        let roles = changes
+            .iter()
+            .map(|c| (c.old_role.as_str(), c.new_role.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(roles, [("visitor", "resident"), ("resident", "former")]);
+
+        // Hooks run once per change.
+        process(&t.env, &t.bot).await;
+        assert!(load(&t).iter().all(|c| c.handled_at.is_some()));
+        let macs: i64 = schema::user_macs::table
+            .count()
+            .get_result(&mut *t.env.conn())
+            .unwrap();
+        assert_eq!(macs, 0);
+    }
+}
@@ -0,0 +1,162 @@
+//! Named roles assigned to users by admins, e.g. `keyholder` or `moderator`.
+//! Other modules use roles to grant extra privileges, see [`users_with_role`].
+//!
+//! **Scope**: `/role` command, available to admins.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{
+    filter_command, format_users, resolve_user, BotCommandsExt, BotEnv,
+    UpdateHandler,
+};
+use crate::db::DbUserId;
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "manage user roles: <code>/role grant|revoke \
+                             user role</code> or <code>/role list \
+                             [role]</code>.")]
+    #[custom(admin = true)]
+    Role(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_role)
+}
+
+/// Return IDs of users holding `role`.  Served from [`DbCache`], so don't
+/// call while holding the database connection.
+///
+/// [`DbCache`]: crate::cache::DbCache
+pub fn users_with_role(env: &BotEnv, role: &str) -> QueryResult<Vec<UserId>> {
+    let roles = env.cache.roles(&env.conn)?;
+    Ok(roles.get(role).cloned().unwrap_or_default())
+}
+
+async fn cmd_role(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Role(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let args = args.split_whitespace().collect_vec();
+    match args.as_slice() {
+        [action @ ("grant" | "revoke"), user, role] => {
+            let grant = *action == "grant";
+            let Some(user) = resolve_user(&bot, &env, &msg, user).await? else {
+                bot.reply_message(&msg, "Unknown user.").await?;
+                return Ok(());
+            };
+            let result = env.transaction(|conn| {
+                let changed = if grant {
+                    diesel::insert_or_ignore_into(schema::user_roles::table)
+                        .values(models::UserRole {
+                            user_id: user.into(),
+                            role: (*role).to_string(),
+                            granted_by: from.id.into(),
+                            granted_at: chrono::Utc::now().naive_utc(),
+                        })
+                        .execute(conn)?
+                } else {
+                    diesel::delete(schema::user_roles::table)
+                        .filter(
+                            schema::user_roles::user_id
+                                .eq(DbUserId::from(user)),
+                        )
+                        .filter(schema::user_roles::role.eq(*role))
+                        .execute(conn)?
+                };
+                if changed == 0 {
+                    return Ok(Err("Nothing to change."));
+                }
+                crate::modules::audit::record(
+                    conn,
+                    Some(from.id),
+                    if grant { "role_grant" } else { "role_revoke" },
+                    &serde_json::json!({ "user_id": user.0, "role": role }),
+                )
+                .log_error("roles: audit");
+                Ok(Ok(()))
+            })?;
+            env.cache.roles.invalidate();
+            let text = match result {
+                Ok(()) if grant => "Role granted.",
+                Ok(()) => "Role revoked.",
+                Err(e) => e,
+            };
+            bot.reply_message(&msg, text).await?;
+        }
+        ["list"] => {
+            let roles: Vec<(String, DbUserId, Option<models::TgUser>)> =
+                schema::user_roles::table
+                    .left_join(schema::tg_users::table.on(
+                        schema::user_roles::user_id.eq(schema::tg_users::id),
+                    ))
+                    .order(schema::user_roles::role.asc())
+                    .select((
+                        schema::user_roles::role,
+                        schema::user_roles::user_id,
+                        schema::tg_users::all_columns.nullable(),
+                    ))
+                    .load(&mut *env.conn())?;
+            let mut text = String::new();
+            for (role, users) in &roles.iter().group_by(|(role, _, _)| role) {
+                format_to!(text, "<b>{}</b>: ", html::escape(role));
+                format_users(
+                    &mut text,
+                    users.map(|(_, id, user)| (*id, user.as_ref())),
+                );
+                text.push('\n');
+            }
+            if text.is_empty() {
+                text.push_str("No roles assigned.");
+            }
+            bot.reply_message(&msg, text)
+                .parse_mode(ParseMode::Html)
+                .disable_web_page_preview(true)
+                .await?;
+        }
+        ["list", role] => {
+            let users: Vec<(DbUserId, Option<models::TgUser>)> =
+                schema::user_roles::table
+                    .left_join(schema::tg_users::table.on(
+                        schema::user_roles::user_id.eq(schema::tg_users::id),
+                    ))
+                    .filter(schema::user_roles::role.eq(*role))
+                    .select((
+                        schema::user_roles::user_id,
+                        schema::tg_users::all_columns.nullable(),
+                    ))
+                    .load(&mut *env.conn())?;
+            let mut text = String::new();
+            format_users(
+                &mut text,
+                users.iter().map(|(id, user)| (*id, user.as_ref())),
+            );
+            bot.reply_message(&msg, text)
+                .parse_mode(ParseMode::Html)
+                .disable_web_page_preview(true)
+                .await?;
+        }
+        _ => {
+            bot.reply_message(
+                &msg,
+                "Usage: /role grant|revoke <user> <role>, /role list [role]",
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
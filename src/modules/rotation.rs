@@ -0,0 +1,421 @@
+//! Weekly cleaning duty rotation.
+//!
+//! Every week, residents with the fewest past assignments are assigned to
+//! the duties listed in [`telegram.rotation.duties`].  Assignees confirm
+//! their duty with a button or by replying to the assignments message with a
+//! photo; duties not confirmed by the next week are counted as skipped.
+//!
+//! **Scope**: the [`telegram.rotation.thread`], and the `/duties`
+//! leaderboard command available to residents.
+//!
+//! [`telegram.rotation.duties`]: crate::config::Rotation::duties
+//! [`telegram.rotation.thread`]: crate::config::Rotation::thread
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{Datelike as _, Timelike as _};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode,
+};
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{
+    filter_command, format_user, BotCommandsExt, BotEnv, UpdateHandler,
+};
+use crate::config::Rotation;
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::modules::timezones;
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_DONE: &str = "done";
+const STATUS_SKIPPED: &str = "skipped";
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show the cleaning duty leaderboard.")]
+    #[custom(resident = true)]
+    Duties,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_duties)
+}
+
+pub fn message_handler() -> UpdateHandler {
+    dptree::filter_map(filter_photo_proofs).endpoint(handle_photo_proof)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+/// Pick `count` users with the fewest past assignments, skipping `exclude`.
+/// Ties are broken by user ID to keep the rotation stable.
+fn pick(
+    load: &HashMap<UserId, usize>,
+    count: usize,
+    exclude: &HashSet<UserId>,
+) -> Vec<UserId> {
+    load.iter()
+        .filter(|(user, _)| !exclude.contains(user))
+        .sorted_by_key(|(user, n)| (**n, user.0))
+        .take(count)
+        .map(|(user, _)| *user)
+        .collect()
+}
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    let Some(conf) = &env.config.telegram.rotation else { return };
+    loop {
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(Duration::from_secs(10 * 60)) => {}
+        }
+
+        let now = timezones::now(timezones::space_tz(&env));
+        if now.weekday() != conf.weekday || now.hour() < conf.hour {
+            continue;
+        }
+        let week = now.format("%G-W%V").to_string();
+        let posted = schema::duty_assignments::table
+            .filter(schema::duty_assignments::week.eq(&week))
+            .count()
+            .get_result::<i64>(&mut *env.conn());
+        if posted.map_or(true, |n| n > 0) {
+            continue;
+        }
+        post_assignments(&env, &bot, conf, &week)
+            .await
+            .log_error("rotation: post assignments");
+    }
+}
+
+async fn post_assignments(
+    env: &BotEnv,
+    bot: &Bot,
+    conf: &Rotation,
+    week: &str,
+) -> Result<()> {
+    let plan = env.transaction(|conn| {
+        // Unconfirmed duties of previous weeks are considered skipped.
+        diesel::update(schema::duty_assignments::table)
+            .filter(schema::duty_assignments::status.eq(STATUS_PENDING))
+            .set(schema::duty_assignments::status.eq(STATUS_SKIPPED))
+            .execute(conn)?;
+
+        let residents: Vec<DbUserId> = schema::residents::table
+            .filter(schema::residents::end_date.is_null())
+            .select(schema::residents::tg_id)
+            .load(conn)?;
+        let mut load: HashMap<UserId, usize> =
+            residents.into_iter().map(|id| (id.into(), 0)).collect();
+        let past: Vec<DbUserId> = schema::duty_assignments::table
+            .select(schema::duty_assignments::user_id)
+            .load(conn)?;
+        for user in past {
+            if let Some(n) = load.get_mut(&user.into()) {
+                *n += 1;
+            }
+        }
+
+        let mut assigned = HashSet::new();
+        let mut plan = Vec::new();
+        for duty in &conf.duties {
+            let users = pick(&load, duty.people, &assigned);
+            assigned.extend(users.iter().copied());
+            plan.push((duty.name.as_str(), users));
+        }
+        Ok(plan)
+    })?;
+
+    if plan.iter().all(|(_, users)| users.is_empty()) {
+        return Ok(());
+    }
+
+    let sent = bot
+        .send_message(conf.thread.chat, format!("🧹 Duties for {week}"))
+        .message_thread_id(conf.thread.thread)
+        .await?;
+
+    env.transaction(|conn| {
+        for (duty, users) in &plan {
+            for user in users {
+                diesel::insert_into(schema::duty_assignments::table)
+                    .values((
+                        schema::duty_assignments::week.eq(week),
+                        schema::duty_assignments::duty.eq(duty),
+                        schema::duty_assignments::user_id
+                            .eq(DbUserId::from(*user)),
+                        schema::duty_assignments::status.eq(STATUS_PENDING),
+                        schema::duty_assignments::chat_id
+                            .eq(DbChatId::from(sent.chat.id)),
+                        schema::duty_assignments::message_id
+                            .eq(DbMessageId::from(sent.id)),
+                    ))
+                    .execute(conn)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    update_message(bot, env, conf, sent.chat.id, sent.id).await
+}
+
+/// Render the assignments message with the current statuses.
+async fn update_message(
+    bot: &Bot,
+    env: &BotEnv,
+    conf: &Rotation,
+    chat_id: ChatId,
+    message_id: MessageId,
+) -> Result<()> {
+    let assignments: Vec<(models::DutyAssignment, Option<models::TgUser>)> =
+        schema::duty_assignments::table
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::tg_users::id
+                        .eq(schema::duty_assignments::user_id)),
+            )
+            .filter(
+                schema::duty_assignments::chat_id.eq(DbChatId::from(chat_id)),
+            )
+            .filter(
+                schema::duty_assignments::message_id
+                    .eq(DbMessageId::from(message_id)),
+            )
+            .order(schema::duty_assignments::rowid)
+            .select((
+                models::DutyAssignment::as_select(),
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+    let Some((first, _)) = assignments.first() else { return Ok(()) };
+
+    let mut text = format!("🧹 <b>Duties for {}</b>\n", first.week);
+    let mut buttons = Vec::new();
+    for (duty, group) in &assignments.iter().group_by(|(a, _)| &a.duty) {
+        format_to!(text, "\n<b>{}</b>: ", html::escape(duty));
+        for (i, (assignment, user)) in group.enumerate() {
+            if i > 0 {
+                text.push_str(", ");
+            }
+            format_user(&mut text, assignment.user_id, user.as_ref(), true);
+            text.push_str(match assignment.status.as_str() {
+                STATUS_DONE if assignment.proof_file_id.is_some() => " ✅📷",
+                STATUS_DONE => " ✅",
+                STATUS_SKIPPED => " ❌",
+                _ => "",
+            });
+            if assignment.status == STATUS_PENDING && !conf.require_photo {
+                let name = user
+                    .as_ref()
+                    .map_or_else(|| "?".to_string(), |u| u.first_name.clone());
+                buttons.push(vec![InlineKeyboardButton::callback(
+                    format!("✅ {duty}: {name}"),
+                    format!("du:done:{}", assignment.rowid),
+                )]);
+            }
+        }
+    }
+    text.push_str(if conf.require_photo {
+        "\n\nReply to this message with a photo to confirm your duty."
+    } else {
+        "\n\nPress the button or reply with a photo to confirm your duty."
+    });
+
+    bot.edit_message_text(chat_id, message_id, text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<i32> {
+    callback.data.as_ref()?.strip_prefix("du:done:")?.parse().ok()
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    rowid: i32,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.rotation else { return Ok(()) };
+    let result = env.transaction(|conn| {
+        let assignment = schema::duty_assignments::table
+            .filter(schema::duty_assignments::rowid.eq(rowid))
+            .select(models::DutyAssignment::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(assignment) = assignment else {
+            return Ok(Err("This duty no longer exists."));
+        };
+        if UserId::from(assignment.user_id) != callback.from.id {
+            return Ok(Err("This duty is assigned to someone else."));
+        }
+        if assignment.status != STATUS_PENDING {
+            return Ok(Err("This duty is already closed."));
+        }
+        diesel::update(schema::duty_assignments::table)
+            .filter(schema::duty_assignments::rowid.eq(rowid))
+            .set(schema::duty_assignments::status.eq(STATUS_DONE))
+            .execute(conn)?;
+        Ok(Ok(assignment))
+    })?;
+
+    match result {
+        Ok(assignment) => {
+            bot.answer_callback_query(&callback.id).text("Thank you!").await?;
+            update_message(
+                &bot,
+                &env,
+                conf,
+                assignment.chat_id.into(),
+                assignment.message_id.into(),
+            )
+            .await?;
+        }
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+        }
+    }
+    Ok(())
+}
+
+fn filter_photo_proofs(
+    env: Arc<BotEnv>,
+    msg: Message,
+) -> Option<models::DutyAssignment> {
+    env.config.telegram.rotation.as_ref()?;
+    msg.photo()?;
+    let reply_to = msg.reply_to_message()?;
+    let from = msg.from.as_ref()?;
+    schema::duty_assignments::table
+        .filter(
+            schema::duty_assignments::chat_id.eq(DbChatId::from(msg.chat.id)),
+        )
+        .filter(
+            schema::duty_assignments::message_id
+                .eq(DbMessageId::from(reply_to.id)),
+        )
+        .filter(schema::duty_assignments::user_id.eq(DbUserId::from(from.id)))
+        .filter(schema::duty_assignments::status.eq(STATUS_PENDING))
+        .select(models::DutyAssignment::as_select())
+        .first(&mut *env.conn())
+        .optional()
+        .ok()
+        .flatten()
+}
+
+async fn handle_photo_proof(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    assignment: models::DutyAssignment,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.rotation else { return Ok(()) };
+    let Some(photo) = msg.photo().and_then(|p| p.last()) else {
+        return Ok(());
+    };
+    diesel::update(schema::duty_assignments::table)
+        .filter(schema::duty_assignments::rowid.eq(assignment.rowid))
+        .set((
+            schema::duty_assignments::status.eq(STATUS_DONE),
+            schema::duty_assignments::proof_file_id.eq(&photo.file.id),
+        ))
+        .execute(&mut *env.conn())?;
+    bot.reply_message(
+        &msg,
+        format!("Thank you! {} duty is confirmed.", assignment.duty),
+    )
+    .await?;
+    update_message(
+        &bot,
+        &env,
+        conf,
+        assignment.chat_id.into(),
+        assignment.message_id.into(),
+    )
+    .await
+}
+
+async fn cmd_duties(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let rows: Vec<(DbUserId, String, Option<models::TgUser>)> =
+        schema::duty_assignments::table
+            .left_join(
+                schema::tg_users::table
+                    .on(schema::tg_users::id
+                        .eq(schema::duty_assignments::user_id)),
+            )
+            .filter(schema::duty_assignments::status.ne(STATUS_PENDING))
+            .select((
+                schema::duty_assignments::user_id,
+                schema::duty_assignments::status,
+                schema::tg_users::all_columns.nullable(),
+            ))
+            .load(&mut *env.conn())?;
+
+    let mut stats: HashMap<DbUserId, (usize, usize, Option<models::TgUser>)> =
+        HashMap::new();
+    for (user_id, status, user) in rows {
+        let entry = stats.entry(user_id).or_insert((0, 0, user));
+        if status == STATUS_DONE {
+            entry.0 += 1;
+        } else {
+            entry.1 += 1;
+        }
+    }
+
+    let mut text =
+        String::from("🧹 <b>Duty leaderboard</b> (done / skipped)\n\n");
+    if stats.is_empty() {
+        text.push_str("No duties yet.");
+    }
+    for (user_id, (done, skipped, user)) in stats
+        .iter()
+        .sorted_by_key(|(_, (done, skipped, _))| (*skipped, Reverse(*done)))
+    {
+        format_user(&mut text, *user_id, user.as_ref(), false);
+        format_to!(text, ": {done} / {skipped}\n");
+    }
+    bot.reply_message(&msg, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick() {
+        let load = HashMap::from([
+            (UserId(1), 2),
+            (UserId(2), 0),
+            (UserId(3), 1),
+            (UserId(4), 0),
+        ]);
+        assert_eq!(pick(&load, 2, &HashSet::new()), [UserId(2), UserId(4)]);
+        assert_eq!(
+            pick(&load, 2, &HashSet::from([UserId(2)])),
+            [UserId(4), UserId(3)],
+        );
+        assert_eq!(pick(&load, 10, &HashSet::new()).len(), 4);
+    }
+}
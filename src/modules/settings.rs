@@ -0,0 +1,177 @@
+//! Per-user notification preferences.
+//!
+//! `/settings` shows a menu with a toggle button for each preference defined
+//! in [`models`] with `user_preference_def!`, split into pages.  Modules check
+//! the preference before sending a private message.
+//!
+//! **Scope**: `/settings` command in private chats.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::UserPreferenceDef;
+use crate::models;
+use crate::utils::{BotExt as _, ResultExt as _};
+
+/// Number of preferences shown on a page.
+const PAGE_SIZE: usize = 5;
+
+/// Preferences listed in the menu, with their titles.
+const SETTINGS: &[(&str, &UserPreferenceDef<bool>)] = &[
+    ("Booking reminders", &models::booking_reminders),
+    ("Incident report updates", &models::incident_updates),
+    ("Followed tag notifications", &models::follow_notifications),
+    ("Broadcasts from admins", &models::broadcasts),
+];
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "manage your notification preferences.")]
+    #[custom(in_group = false)]
+    Settings,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Action {
+    /// Show the page.
+    Page(usize),
+    /// Toggle the preference and show the page.
+    Toggle(usize, String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_settings)
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+async fn cmd_settings(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let keyboard = keyboard(&mut env.conn(), from.id, 0)?;
+    bot.reply_message(&msg, "⚙️ Tap a setting to toggle it.")
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<Action> {
+    parse_callback(callback.data.as_ref()?)
+}
+
+fn parse_callback(data: &str) -> Option<Action> {
+    let data = data.strip_prefix("st:")?;
+    if let Some(page) = data.strip_prefix("p:") {
+        return Some(Action::Page(page.parse().ok()?));
+    }
+    let (page, name) = data.strip_prefix("t:")?.split_once(':')?;
+    Some(Action::Toggle(page.parse().ok()?, name.to_string()))
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    action: Action,
+) -> Result<()> {
+    let user = callback.from.id;
+    let page = match action {
+        Action::Page(page) => page,
+        Action::Toggle(page, name) => {
+            let Some((_, pref)) =
+                SETTINGS.iter().find(|(_, p)| p.name() == name)
+            else {
+                bot.answer_callback_query(&callback.id)
+                    .text("Unknown setting.")
+                    .await?;
+                return Ok(());
+            };
+            env.transaction(|conn| {
+                let value = pref.get(conn, user)?;
+                pref.set(conn, user, &!value)
+            })?;
+            page
+        }
+    };
+
+    bot.answer_callback_query(&callback.id).await?;
+    if let Some(message) = &callback.message {
+        let keyboard = keyboard(&mut env.conn(), user, page)?;
+        bot.edit_message_reply_markup(message.chat.id, message.id)
+            .reply_markup(keyboard)
+            .await
+            .log_error("settings: update menu");
+    }
+    Ok(())
+}
+
+/// Build the menu page with the current values for the user.
+fn keyboard(
+    conn: &mut SqliteConnection,
+    user: UserId,
+    page: usize,
+) -> QueryResult<InlineKeyboardMarkup> {
+    let pages = SETTINGS.len().div_ceil(PAGE_SIZE);
+    let page = page.min(pages.saturating_sub(1));
+    let mut rows = SETTINGS
+        .iter()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .map(|(title, pref)| {
+            let mark = if pref.get(conn, user)? { "✅" } else { "🚫" };
+            Ok(vec![InlineKeyboardButton::callback(
+                format!("{mark} {title}"),
+                format!("st:t:{page}:{}", pref.name()),
+            )])
+        })
+        .collect::<QueryResult<Vec<_>>>()?;
+    let nav = [
+        (page > 0).then(|| {
+            InlineKeyboardButton::callback("◀️", format!("st:p:{}", page - 1))
+        }),
+        (page + 1 < pages).then(|| {
+            InlineKeyboardButton::callback("▶️", format!("st:p:{}", page + 1))
+        }),
+    ]
+    .into_iter()
+    .flatten()
+    .collect_vec();
+    if !nav.is_empty() {
+        rows.push(nav);
+    }
+    Ok(InlineKeyboardMarkup::new(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_callback() {
+        assert_eq!(parse_callback("st:p:2"), Some(Action::Page(2)));
+        assert_eq!(
+            parse_callback("st:t:0:booking_reminders"),
+            Some(Action::Toggle(0, "booking_reminders".to_string()))
+        );
+        assert_eq!(parse_callback("st:x:0"), None);
+        assert_eq!(parse_callback("nd:mine:00"), None);
+    }
+
+    #[test]
+    fn test_callback_data_fits() {
+        // Telegram limits callback data to 64 bytes.
+        for (_, pref) in SETTINGS {
+            assert!(format!("st:t:99:{}", pref.name()).len() <= 64);
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Spam and scam join protection for residential chats.
+//!
+//! New members of the [`telegram.chats.residential`] chats are scored by
+//! simple heuristics: a high user id (a proxy for a recently created account)
+//! without Telegram Premium, a suspicious name, and posting a link right
+//! after joining.  Members reaching the [`telegram.spam_protection.threshold`]
+//! are muted and queued for admin review in the
+//! [`telegram.spam_protection.review`] thread.
+//!
+//! **Scope**: new members and their first messages in
+//! [`telegram.chats.residential`].
+//!
+//! [`telegram.chats.residential`]: crate::config::TelegramChats::residential
+//! [`telegram.spam_protection.threshold`]: crate::config::SpamProtection::threshold
+//! [`telegram.spam_protection.review`]: crate::config::SpamProtection::review
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use teloxide::prelude::*;
+use teloxide::types::{
+    ChatPermissions, InlineKeyboardButton, InlineKeyboardMarkup,
+    MessageEntityKind, ParseMode, User,
+};
+
+use crate::common::{format_user, BotEnv, UpdateHandler};
+use crate::config::SpamProtection;
+use crate::db::{DbChatId, DbUserId};
+use crate::utils::{format_to, html, ResultExt as _};
+use crate::{models, schema};
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_UNMUTED: &str = "unmuted";
+const STATUS_BANNED: &str = "banned";
+
+/// Score for posting a link right after joining.
+const LINK_SCORE: u32 = 2;
+
+/// Words often found in names of spam accounts.
+const SPAM_NAME_WORDS: &[&str] =
+    &["crypto", "bitcoin", "btc", "invest", "earn", "casino", "http", "t.me"];
+
+/// State contains the recently joined members and their scores.
+#[derive(Clone, Debug, Default)]
+pub struct State(HashMap<(ChatId, UserId), Newcomer>);
+
+#[derive(Clone, Debug)]
+struct Newcomer {
+    joined: Instant,
+    score: u32,
+    reasons: Vec<&'static str>,
+}
+
+pub fn state() -> Arc<Mutex<State>> {
+    Arc::new(Mutex::new(State::default()))
+}
+
+pub fn callback_handler() -> UpdateHandler {
+    dptree::filter_map(filter_callbacks).endpoint(handle_callback)
+}
+
+/// Score new members and their first messages.
+pub async fn inspect_message(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    state: Arc<Mutex<State>>,
+    msg: Message,
+) -> Result<()> {
+    let Some(conf) = &env.config.telegram.spam_protection else {
+        return Ok(());
+    };
+    if !env.config.telegram.chats.residential.contains(&msg.chat.id) {
+        return Ok(());
+    }
+    let threshold = conf
+        .chat_thresholds
+        .iter()
+        .find(|t| t.chat == msg.chat.id)
+        .map_or(conf.threshold, |t| t.threshold);
+    let window = Duration::from_secs(u64::from(conf.link_window_minutes) * 60);
+
+    if let Some(members) = msg.new_chat_members() {
+        let mut suspects = Vec::new();
+        {
+            let mut state = state.lock().unwrap();
+            state.0.retain(|_, n| n.joined.elapsed() < window);
+            for user in members.iter().filter(|u| !u.is_bot) {
+                let (score, reasons) = score_user(conf, user);
+                if score >= threshold {
+                    suspects.push((user, score, reasons));
+                } else {
+                    state.0.insert(
+                        (msg.chat.id, user.id),
+                        Newcomer { joined: Instant::now(), score, reasons },
+                    );
+                }
+            }
+        }
+        for (user, score, reasons) in suspects {
+            flag(&bot, &env, conf, msg.chat.id, user, score, &reasons).await?;
+        }
+        return Ok(());
+    }
+
+    let Some(from) = &msg.from else { return Ok(()) };
+    let newcomer = state.lock().unwrap().0.remove(&(msg.chat.id, from.id));
+    let Some(mut newcomer) = newcomer else { return Ok(()) };
+    if newcomer.joined.elapsed() >= window || !has_link(&msg) {
+        return Ok(());
+    }
+    newcomer.score += LINK_SCORE;
+    newcomer.reasons.push("link right after joining");
+    if newcomer.score >= threshold {
+        bot.delete_message(msg.chat.id, msg.id)
+            .await
+            .log_error("spam_protection: delete message");
+        flag(
+            &bot,
+            &env,
+            conf,
+            msg.chat.id,
+            from,
+            newcomer.score,
+            &newcomer.reasons,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Score the user by static heuristics.
+fn score_user(conf: &SpamProtection, user: &User) -> (u32, Vec<&'static str>) {
+    let mut reasons = Vec::new();
+    if user.id.0 > conf.new_account_id && !user.is_premium {
+        reasons.push("recent account");
+    }
+    let name = user.full_name().to_lowercase();
+    if SPAM_NAME_WORDS.iter().any(|w| name.contains(w))
+        || !name.chars().any(char::is_alphabetic)
+    {
+        reasons.push("suspicious name");
+    }
+    (u32::try_from(reasons.len()).unwrap_or(u32::MAX), reasons)
+}
+
+fn has_link(msg: &Message) -> bool {
+    msg.entities().or_else(|| msg.caption_entities()).is_some_and(|e| {
+        e.iter().any(|e| {
+            matches!(
+                e.kind,
+                MessageEntityKind::Url | MessageEntityKind::TextLink { .. }
+            )
+        })
+    })
+}
+
+/// Mute the user and queue them for review.
+async fn flag(
+    bot: &Bot,
+    env: &BotEnv,
+    conf: &SpamProtection,
+    chat_id: ChatId,
+    user: &User,
+    score: u32,
+    reasons: &[&str],
+) -> Result<()> {
+    bot.restrict_chat_member(chat_id, user.id, ChatPermissions::empty())
+        .await
+        .log_error("spam_protection: mute user");
+
+    let reasons = reasons.iter().join(", ");
+    let rowid = env.transaction(|conn| {
+        diesel::insert_into(schema::spam_suspects::table)
+            .values((
+                schema::spam_suspects::chat_id.eq(DbChatId::from(chat_id)),
+                schema::spam_suspects::user_id.eq(DbUserId::from(user.id)),
+                schema::spam_suspects::score
+                    .eq(i32::try_from(score).unwrap_or(i32::MAX)),
+                schema::spam_suspects::reasons.eq(&reasons),
+                schema::spam_suspects::status.eq(STATUS_PENDING),
+                schema::spam_suspects::created_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        schema::spam_suspects::table
+            .select(schema::spam_suspects::rowid)
+            .order(schema::spam_suspects::rowid.desc())
+            .first::<i32>(conn)
+    })?;
+
+    let chat_title = schema::tg_chats::table
+        .filter(schema::tg_chats::id.eq(DbChatId::from(chat_id)))
+        .select(schema::tg_chats::title)
+        .first::<Option<String>>(&mut *env.conn())
+        .optional()?
+        .flatten()
+        .unwrap_or_else(|| chat_id.to_string());
+    let mut text = String::from("🛡 <b>Suspected spam account</b> ");
+    format_user(&mut text, user.id, &tg_user(user), true);
+    format_to!(
+        text,
+        " is muted in {}.\nScore: {score} ({})",
+        html::escape(&chat_title),
+        html::escape(&reasons),
+    );
+    bot.send_message(conf.review.chat, text)
+        .message_thread_id(conf.review.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .reply_markup(InlineKeyboardMarkup::new([[
+            InlineKeyboardButton::callback(
+                "✅ Unmute",
+                format!("sp:{STATUS_UNMUTED}:{rowid}"),
+            ),
+            InlineKeyboardButton::callback(
+                "⛔ Ban",
+                format!("sp:{STATUS_BANNED}:{rowid}"),
+            ),
+        ]]))
+        .await?;
+    Ok(())
+}
+
+fn tg_user(user: &User) -> models::TgUser {
+    models::TgUser {
+        id: user.id.into(),
+        username: user.username.clone(),
+        first_name: user.first_name.clone(),
+        last_name: user.last_name.clone(),
+    }
+}
+
+fn filter_callbacks(callback: CallbackQuery) -> Option<(&'static str, i32)> {
+    let (status, rowid) =
+        callback.data.as_ref()?.strip_prefix("sp:")?.split_once(':')?;
+    let status =
+        [STATUS_UNMUTED, STATUS_BANNED].into_iter().find(|s| *s == status)?;
+    Some((status, rowid.parse().ok()?))
+}
+
+async fn handle_callback(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    callback: CallbackQuery,
+    (status, rowid): (&'static str, i32),
+) -> Result<()> {
+    if !env.config.telegram.admins.contains(&callback.from.id) {
+        bot.answer_callback_query(&callback.id)
+            .text("Only admins can do this.")
+            .await?;
+        return Ok(());
+    }
+    let result = env.transaction(|conn| {
+        let suspect = schema::spam_suspects::table
+            .filter(schema::spam_suspects::rowid.eq(rowid))
+            .select(models::SpamSuspect::as_select())
+            .first(conn)
+            .optional()?;
+        let Some(suspect) = suspect else {
+            return Ok(Err("This suspect no longer exists."));
+        };
+        if suspect.status != STATUS_PENDING {
+            return Ok(Err("This suspect is already reviewed."));
+        }
+        diesel::update(schema::spam_suspects::table)
+            .filter(schema::spam_suspects::rowid.eq(rowid))
+            .set((
+                schema::spam_suspects::status.eq(status),
+                schema::spam_suspects::reviewed_by
+                    .eq(DbUserId::from(callback.from.id)),
+            ))
+            .execute(conn)?;
+        crate::modules::audit::record(
+            conn,
+            Some(callback.from.id),
+            if status == STATUS_BANNED { "spam_ban" } else { "spam_unmute" },
+            &serde_json::json!({
+                "chat_id": ChatId::from(suspect.chat_id).0,
+                "user_id": UserId::from(suspect.user_id).0,
+            }),
+        )
+        .log_error("spam_protection: audit");
+        Ok(Ok(suspect))
+    })?;
+    let suspect = match result {
+        Ok(suspect) => suspect,
+        Err(error) => {
+            bot.answer_callback_query(&callback.id).text(error).await?;
+            return Ok(());
+        }
+    };
+
+    let chat_id = ChatId::from(suspect.chat_id);
+    let user_id = UserId::from(suspect.user_id);
+    let (action, answer) = if status == STATUS_BANNED {
+        (bot.ban_chat_member(chat_id, user_id).await, "Banned.")
+    } else {
+        (
+            bot.restrict_chat_member(chat_id, user_id, ChatPermissions::all())
+                .await,
+            "Unmuted.",
+        )
+    };
+    action.log_error("spam_protection: review action");
+    bot.answer_callback_query(&callback.id).text(answer).await?;
+    if let Some(message) = &callback.message {
+        bot.edit_message_reply_markup(message.chat.id, message.id)
+            .await
+            .log_error("spam_protection: remove buttons");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ThreadIdPair;
+
+    fn user(id: u64, first_name: &str, is_premium: bool) -> User {
+        User {
+            id: UserId(id),
+            is_bot: false,
+            first_name: first_name.to_string(),
+            last_name: None,
+            username: None,
+            language_code: None,
+            is_premium,
+            added_to_attachment_menu: false,
+        }
+    }
+
+    #[test]
+    fn test_score_user() {
+        let conf = SpamProtection {
+            review: ThreadIdPair {
+                chat: ChatId(-1),
+                thread: teloxide::types::ThreadId(teloxide::types::MessageId(
+                    1,
+                )),
+            },
+            new_account_id: 7_000_000_000,
+            link_window_minutes: 10,
+            threshold: 2,
+            chat_thresholds: Vec::new(),
+        };
+        assert_eq!(score_user(&conf, &user(123, "Alice", false)).0, 0);
+        assert_eq!(
+            score_user(&conf, &user(7_100_000_000, "Alice", false)),
+            (1, vec!["recent account"])
+        );
+        assert_eq!(score_user(&conf, &user(7_100_000_000, "Alice", true)).0, 0);
+        assert_eq!(
+            score_user(&conf, &user(7_100_000_000, "Crypto Earn 💰", false)),
+            (2, vec!["recent account", "suspicious name"])
+        );
+        assert_eq!(score_user(&conf, &user(123, "💰💰", false)).0, 1);
+    }
+}
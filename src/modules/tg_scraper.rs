@@ -2,7 +2,7 @@
 
 use std::sync::Arc;
 
-use diesel::{ExpressionMethods, RunQueryDsl, SqliteConnection};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, SqliteConnection};
 use teloxide::types::{
     Chat, ChatKind, ChatMemberUpdated, Message, MessageKind, PublicChatKind,
     Update, UpdateKind, User,
@@ -43,9 +43,7 @@ pub fn scrape(
     upd: &Update,
 ) -> Result<(), diesel::result::Error> {
     let scrape = ScrapedInfo::scrape(upd);
-    diesel::replace_into(schema::tg_users::table)
-        .values(scrape.users)
-        .execute(conn)?;
+    store_users(conn, &scrape.users)?;
     diesel::replace_into(schema::tg_chats::table)
         .values(scrape.chats)
         .execute(conn)?;
@@ -83,6 +81,40 @@ pub fn scrape(
     Ok(())
 }
 
+/// Insert or update users, keeping their previous names in `tg_user_names`.
+pub fn store_users(
+    conn: &mut SqliteConnection,
+    users: &[models::NewTgUser<'_>],
+) -> Result<(), diesel::result::Error> {
+    let known: Vec<models::TgUser> = schema::tg_users::table
+        .filter(schema::tg_users::id.eq_any(users.iter().map(|u| u.id)))
+        .load(conn)?;
+    let now = chrono::Utc::now().naive_utc();
+    let renamed = known.into_iter().filter(|old| {
+        users.iter().any(|new| {
+            new.id == old.id
+                && (new.username != old.username.as_deref()
+                    || new.first_name != old.first_name
+                    || new.last_name != old.last_name.as_deref())
+        })
+    });
+    for old in renamed {
+        diesel::insert_into(schema::tg_user_names::table)
+            .values(models::TgUserName {
+                user_id: old.id,
+                username: old.username,
+                first_name: old.first_name,
+                last_name: old.last_name,
+                replaced_at: now,
+            })
+            .execute(conn)?;
+    }
+    diesel::replace_into(schema::tg_users::table)
+        .values(users)
+        .execute(conn)?;
+    Ok(())
+}
+
 #[allow(clippy::option_map_unit_fn)] // allow for brevity
 impl<'a> ScrapedInfo<'a> {
     pub fn scrape(update: &'a Update) -> Self {
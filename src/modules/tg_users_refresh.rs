@@ -0,0 +1,158 @@
+//! Refresh names and usernames of residents.
+//!
+//! Users in `tg_users` are scraped from updates, so their names go stale if
+//! they rarely write.  Once a day, residents are looked up with
+//! `getChatMember` in residential chats, falling back to `getChat`.
+//! Previous names are kept in `tg_user_names` by
+//! [`tg_scraper::store_users`], so old usernames can still be resolved.
+//!
+//! **Scope**: background task.
+//!
+//! [`tg_scraper::store_users`]: crate::modules::tg_scraper::store_users
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use teloxide::prelude::*;
+use teloxide::RequestError;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::BotEnv;
+use crate::db::DbUserId;
+use crate::modules::tg_scraper;
+use crate::{models, schema};
+
+/// How often to refresh all residents.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Delay between requests to stay within rate limits.
+const REQUEST_DELAY: Duration = Duration::from_millis(100);
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    loop {
+        if let Err(e) = refresh(&env, &bot).await {
+            log::error!("tg_users_refresh: {e:#}");
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(REFRESH_INTERVAL) => {}
+        }
+    }
+}
+
+async fn refresh(env: &BotEnv, bot: &Bot) -> Result<()> {
+    let residents: Vec<DbUserId> = schema::residents::table
+        .filter(schema::residents::end_date.is_null())
+        .select(schema::residents::tg_id)
+        .distinct()
+        .load(&mut *env.conn())?;
+
+    for id in residents {
+        match fetch_user(env, bot, id.into()).await {
+            Ok(Some(user)) => {
+                let user = models::NewTgUser {
+                    id,
+                    username: user.username.as_deref(),
+                    first_name: &user.first_name,
+                    last_name: user.last_name.as_deref(),
+                };
+                env.transaction(|conn| tg_scraper::store_users(conn, &[user]))?;
+            }
+            Ok(None) => log::warn!("tg_users_refresh: user {id:?} not found"),
+            Err(e) => log::warn!("tg_users_refresh: user {id:?}: {e}"),
+        }
+        sleep(REQUEST_DELAY).await;
+    }
+    Ok(())
+}
+
+/// Look up the user in residential chats, then in the private chat with the
+/// bot.
+async fn fetch_user(
+    env: &BotEnv,
+    bot: &Bot,
+    user: UserId,
+) -> Result<Option<models::TgUser>, RequestError> {
+    for &chat in &env.config.telegram.chats.residential {
+        match bot.get_chat_member(chat, user).await {
+            Ok(member) => {
+                let user = member.user;
+                return Ok(Some(models::TgUser {
+                    id: user.id.into(),
+                    username: user.username,
+                    first_name: user.first_name,
+                    last_name: user.last_name,
+                }));
+            }
+            // Not a member, or the bot is not in the chat.
+            Err(RequestError::Api(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let chat = match bot.get_chat(user).await {
+        Ok(chat) => chat,
+        // The user never started the bot.
+        Err(RequestError::Api(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(chat.first_name().map(|first_name| models::TgUser {
+        id: user.into(),
+        username: chat.username().map(str::to_string),
+        first_name: first_name.to_string(),
+        last_name: chat.last_name().map(str::to_string),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::TestBot;
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let t = TestBot::new();
+        t.add_resident(1, "alice", "Alice");
+        let member = json!({
+            "status": "member",
+            "user": {
+                "id": 1,
+                "is_bot": false,
+                "first_name": "Alicia",
+                "username": "alicia",
+            },
+        });
+        t.telegram.respond("getChatMember", member.clone());
+        t.telegram.respond("getChatMember", member);
+
+        refresh(&t.env, &t.bot).await.unwrap();
+        let user: models::TgUser =
+            schema::tg_users::table.first(&mut *t.env.conn()).unwrap();
+        assert_eq!(user.first_name, "Alicia");
+        assert_eq!(user.username.as_deref(), Some("alicia"));
+        let names: Vec<models::TgUserName> = schema::tg_user_names::table
+            .select(models::TgUserName::as_select())
+            .load(&mut *t.env.conn())
+            .unwrap();
+        assert_eq!(names.len(), 1);
+        assert_eq!(names[0].username.as_deref(), Some("alice"));
+        assert_eq!(names[0].first_name, "Alice");
+
+        // Unchanged names are not recorded again.
+        refresh(&t.env, &t.bot).await.unwrap();
+        let count: i64 = schema::tg_user_names::table
+            .count()
+            .get_result(&mut *t.env.conn())
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
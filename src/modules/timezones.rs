@@ -0,0 +1,274 @@
+//! Per-user time zones.
+//!
+//! Users set their time zone with `/tz Europe/Tbilisi`, or by sharing a
+//! location with the bot in private, which picks the zone of the nearest
+//! principal city in the tz database.  Modules render times with
+//! [`chat_tz`]: private messages use the time zone of the user, and groups
+//! use [`telegram.timezone`] of the space.
+//!
+//! **Scope**: `/tz` command, and location messages in private chats.
+//!
+//! [`telegram.timezone`]: crate::config::Telegram::timezone
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{NaiveDateTime, TimeZone as _};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::dispatching::UpdateFilterExt as _;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::Location;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::models;
+use crate::utils::BotExt as _;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show or set your time zone, e.g. \
+                             <code>/tz Europe/Tbilisi</code>.")]
+    Tz(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_tz)
+}
+
+pub fn message_handler() -> UpdateHandler {
+    Update::filter_message()
+        .filter_map(filter_locations)
+        .endpoint(handle_location)
+}
+
+fn filter_locations(msg: Message) -> Option<Location> {
+    if !msg.chat.is_private() {
+        return None;
+    }
+    msg.location().cloned()
+}
+
+/// Time zone of the space.
+pub fn space_tz(env: &BotEnv) -> Tz {
+    env.config.telegram.timezone.unwrap_or(Tz::UTC)
+}
+
+/// Time zone of the user, or of the space if the user hasn't set one.
+pub fn user_tz(
+    env: &BotEnv,
+    conn: &mut SqliteConnection,
+    user: UserId,
+) -> QueryResult<Tz> {
+    Ok(models::timezone.get(conn, user)?.unwrap_or_else(|| space_tz(env)))
+}
+
+/// Time zone of times shown in the chat: of the user in private chats, of
+/// the space in groups.
+pub fn chat_tz(
+    env: &BotEnv,
+    conn: &mut SqliteConnection,
+    chat: ChatId,
+) -> QueryResult<Tz> {
+    match u64::try_from(chat.0) {
+        Ok(user) if chat.is_user() => user_tz(env, conn, UserId(user)),
+        _ => Ok(space_tz(env)),
+    }
+}
+
+/// Convert a UTC time from the database to local time.
+pub fn to_local(tz: Tz, utc: NaiveDateTime) -> NaiveDateTime {
+    tz.from_utc_datetime(&utc).naive_local()
+}
+
+/// Convert a local time entered by a user to UTC.  Times skipped by a DST
+/// change are read with the offset before the change, so 02:30 in a gap from
+/// 02:00 to 03:00 becomes 03:30.
+pub fn to_utc(tz: Tz, local: NaiveDateTime) -> NaiveDateTime {
+    let mut probe = local;
+    loop {
+        if let Some(time) = tz.from_local_datetime(&probe).earliest() {
+            return time.naive_utc() + (local - probe);
+        }
+        probe -= chrono::Duration::minutes(15);
+    }
+}
+
+/// Current local time.
+pub fn now(tz: Tz) -> NaiveDateTime {
+    to_local(tz, chrono::Utc::now().naive_utc())
+}
+
+async fn cmd_tz(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Tz(arg): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let arg = arg.trim();
+    let text = if arg.is_empty() {
+        match models::timezone.get(&mut env.conn(), from.id)? {
+            Some(tz) => format!(
+                "Your time zone is {tz}, now {}.",
+                now(tz).format("%H:%M"),
+            ),
+            None => format!(
+                "You haven't set a time zone, so times are shown in {} of \
+                 the space.  Use /tz Europe/Tbilisi, or share a location \
+                 with me in private, to set it.",
+                space_tz(&env),
+            ),
+        }
+    } else if arg == "reset" {
+        models::timezone.set(&mut env.conn(), from.id, &None)?;
+        format!("Your time zone is reset to {}.", space_tz(&env))
+    } else {
+        match parse_tz(arg) {
+            Some(tz) => set_tz(&env, from.id, tz)?,
+            None => "Unknown time zone.  Use a name from the tz database, \
+                     e.g. Europe/Tbilisi, or reset."
+                .to_string(),
+        }
+    };
+    bot.reply_message(&msg, text).await?;
+    Ok(())
+}
+
+async fn handle_location(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    location: Location,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(tz) = nearest_tz(location.latitude, location.longitude) else {
+        return Ok(());
+    };
+    let text = set_tz(&env, from.id, tz)?;
+    bot.reply_message(&msg, text).await?;
+    Ok(())
+}
+
+fn set_tz(env: &BotEnv, user: UserId, tz: Tz) -> Result<String> {
+    models::timezone.set(&mut env.conn(), user, &Some(tz))?;
+    Ok(format!(
+        "Your time zone is set to {tz}, now {}.",
+        now(tz).format("%H:%M"),
+    ))
+}
+
+/// Parse a zone name, ignoring case.
+fn parse_tz(name: &str) -> Option<Tz> {
+    Tz::from_str_insensitive(name).ok()
+}
+
+lazy_static::lazy_static! {
+    /// Principal cities of time zones: latitude, longitude, zone.
+    static ref CITIES: Vec<(f64, f64, Tz)> =
+        include_str!("timezones/zone1970.tab")
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(parse_zone_line)
+            .collect();
+}
+
+/// Parse a line of `zone1970.tab`, skipping zones unknown to `chrono_tz`.
+fn parse_zone_line(line: &str) -> Option<(f64, f64, Tz)> {
+    let mut fields = line.split('\t').skip(1);
+    let coordinates = fields.next()?;
+    let tz = fields.next()?.parse().ok()?;
+    // ISO 6709: ±DDMM±DDDMM or ±DDMMSS±DDDMMSS.
+    let split = coordinates[1..].find(['+', '-'])? + 1;
+    let (lat, lon) = coordinates.split_at(split);
+    Some((parse_degrees(lat, 2)?, parse_degrees(lon, 3)?, tz))
+}
+
+/// Parse `±DDMM[SS]` with `digits` digits of degrees.
+fn parse_degrees(s: &str, digits: usize) -> Option<f64> {
+    let sign = match s.get(..1)? {
+        "+" => 1.0,
+        "-" => -1.0,
+        _ => return None,
+    };
+    let s = &s[1..];
+    let part = |range: std::ops::Range<usize>| -> Option<f64> {
+        s.get(range)?.parse::<u32>().ok().map(f64::from)
+    };
+    let degrees = part(0..digits)?;
+    let minutes = part(digits..digits + 2)?;
+    let seconds = match s.len().checked_sub(digits)? {
+        2 => 0.0,
+        4 => part(digits + 2..digits + 4)?,
+        _ => return None,
+    };
+    Some(sign * (degrees + minutes / 60.0 + seconds / 3600.0))
+}
+
+/// Zone of the principal city nearest to the point.
+fn nearest_tz(latitude: f64, longitude: f64) -> Option<Tz> {
+    let (lat, lon) = (latitude.to_radians(), longitude.to_radians());
+    // Haversine, without the constant factors.
+    let distance = |(city_lat, city_lon): (f64, f64)| {
+        let (city_lat, city_lon) =
+            (city_lat.to_radians(), city_lon.to_radians());
+        ((city_lat - lat) / 2.0).sin().powi(2)
+            + lat.cos()
+                * city_lat.cos()
+                * ((city_lon - lon) / 2.0).sin().powi(2)
+    };
+    CITIES
+        .iter()
+        .min_by(|a, b| distance((a.0, a.1)).total_cmp(&distance((b.0, b.1))))
+        .map(|&(_, _, tz)| tz)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_zone_line() {
+        let (lat, lon, tz) =
+            parse_zone_line("GE\t+4143+04449\tAsia/Tbilisi").unwrap();
+        assert_eq!(tz, Tz::Asia__Tbilisi);
+        assert!((lat - 41.716).abs() < 0.01 && (lon - 44.816).abs() < 0.01);
+
+        let (lat, lon, _) = parse_zone_line(
+            "US\t+404251-0740023\tAmerica/New_York\tEastern (most areas)",
+        )
+        .unwrap();
+        assert!((lat - 40.714).abs() < 0.01 && (lon + 74.006).abs() < 0.01);
+        assert!(parse_zone_line("XX\t+4143+04449\tNowhere/City").is_none());
+    }
+
+    #[test]
+    fn test_nearest_tz() {
+        assert!(CITIES.len() > 300);
+        assert_eq!(nearest_tz(41.69, 44.80), Some(Tz::Asia__Tbilisi));
+        assert_eq!(nearest_tz(48.85, 2.35), Some(Tz::Europe__Paris));
+        assert_eq!(nearest_tz(-33.9, 151.2), Some(Tz::Australia__Sydney));
+    }
+
+    #[test]
+    fn test_conversions() {
+        let tz = Tz::Europe__Berlin;
+        let local = |d, h, m| {
+            NaiveDate::from_ymd_opt(2026, 3, d)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        let utc = to_utc(tz, local(1, 12, 0));
+        assert_eq!(utc, local(1, 11, 0));
+        assert_eq!(to_local(tz, utc), local(1, 12, 0));
+        // 02:30 doesn't exist on the DST change, 03:30 does.
+        assert_eq!(to_utc(tz, local(29, 2, 30)), local(29, 1, 30));
+        assert_eq!(parse_tz("europe/berlin"), Some(tz));
+        assert_eq!(parse_tz("Mars/Olympus"), None);
+    }
+}
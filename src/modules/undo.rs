@@ -0,0 +1,217 @@
+//! Undo of recent actions of the bot.
+//!
+//! Noisy or destructive actions of the bot [`push`] their compensating
+//! actions onto the `undo_stack`: replacing a poll with the bot's copy,
+//! pinning messages, and replying with archived links.  `/undo` in the chat
+//! reverses the latest action affecting the user, or the latest action in
+//! the chat if used by an admin, within [`telegram.undo_minutes`].
+//!
+//! Messages deleted by the bot, e.g. by [`message_ttl`] or the old shopping
+//! list replaced by `/needs`, can't be restored, so these deletions are not
+//! undoable.  Undoing a poll replacement removes the bot's tracked copy and
+//! sends the original question and options back as a plain poll, since the
+//! original poll is already gone and its votes are lost.
+//!
+//! **Scope**: `/undo` command.
+//!
+//! [`telegram.undo_minutes`]: crate::config::Telegram::undo_minutes
+//! [`message_ttl`]: crate::modules::message_ttl
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::models::UndoAction;
+use crate::modules::activity_feed;
+use crate::outbox;
+use crate::utils::{BotExt as _, Sqlizer};
+use crate::{models, schema};
+
+/// Default of [`telegram.undo_minutes`].
+///
+/// [`telegram.undo_minutes`]: crate::config::Telegram::undo_minutes
+const DEFAULT_WINDOW_MINUTES: u32 = 10;
+
+/// Entries older than this are removed, whatever the window is.
+const KEEP_HOURS: i64 = 24;
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "undo the last action of the bot affecting you.")]
+    Undo,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_undo)
+}
+
+/// Register `actions` reversing an action of the bot in the chat.  `user` is
+/// the user affected by the action, e.g. the author of the pinned message.
+/// Call inside the transaction with the action.
+pub fn push(
+    conn: &mut SqliteConnection,
+    chat: ChatId,
+    user: UserId,
+    description: &str,
+    actions: Vec<UndoAction>,
+) -> QueryResult<()> {
+    let now = chrono::Utc::now().naive_utc();
+    diesel::delete(schema::undo_stack::table)
+        .filter(
+            schema::undo_stack::created_at
+                .lt(now - chrono::Duration::hours(KEEP_HOURS)),
+        )
+        .execute(conn)?;
+    diesel::insert_into(schema::undo_stack::table)
+        .values(models::NewUndoEntry {
+            chat_id: chat.into(),
+            user_id: user.into(),
+            description,
+            actions: Sqlizer::new(actions).expect("UndoAction is serializable"),
+            created_at: now,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Pop the latest entry of the chat undoable by the user and perform its
+/// actions.  Telegram API calls are enqueued to the outbox.
+fn undo(
+    conn: &mut SqliteConnection,
+    chat: ChatId,
+    user: UserId,
+    is_admin: bool,
+    since: chrono::NaiveDateTime,
+) -> QueryResult<Option<models::UndoEntry>> {
+    let mut query = schema::undo_stack::table
+        .filter(schema::undo_stack::chat_id.eq(DbChatId::from(chat)))
+        .filter(schema::undo_stack::undone_at.is_null())
+        .filter(schema::undo_stack::created_at.ge(since))
+        .order(schema::undo_stack::rowid.desc())
+        .select(models::UndoEntry::as_select())
+        .into_boxed();
+    if !is_admin {
+        query =
+            query.filter(schema::undo_stack::user_id.eq(DbUserId::from(user)));
+    }
+    let Some(entry) = query.first(conn).optional()? else {
+        return Ok(None);
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    diesel::update(schema::undo_stack::table)
+        .filter(schema::undo_stack::rowid.eq(entry.rowid))
+        .set(schema::undo_stack::undone_at.eq(now))
+        .execute(conn)?;
+    for action in entry.actions.iter() {
+        match action {
+            UndoAction::Telegram { action } => {
+                outbox::enqueue(conn, action.clone(), Vec::new())?;
+            }
+            UndoAction::ClosePoll { poll_id } => {
+                diesel::update(schema::tracked_polls::table)
+                    .filter(schema::tracked_polls::tg_poll_id.eq(poll_id))
+                    .filter(schema::tracked_polls::closed_at.is_null())
+                    .set(schema::tracked_polls::closed_at.eq(now))
+                    .execute(conn)?;
+            }
+            UndoAction::ForgetArchivedLinks { chat_id, message_id } => {
+                diesel::delete(schema::archived_links::table)
+                    .filter(
+                        schema::archived_links::chat_id
+                            .eq(DbChatId::from(*chat_id)),
+                    )
+                    .filter(
+                        schema::archived_links::message_id
+                            .eq(DbMessageId::from(*message_id)),
+                    )
+                    .execute(conn)?;
+            }
+        }
+    }
+    activity_feed::record(
+        conn,
+        "undo",
+        Some(user),
+        "undid",
+        &entry.description,
+        None,
+    )?;
+    Ok(Some(entry))
+}
+
+async fn cmd_undo(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let is_admin = env.config.telegram.admins.contains(&from.id);
+    let window =
+        env.config.telegram.undo_minutes.unwrap_or(DEFAULT_WINDOW_MINUTES);
+    let since = chrono::Utc::now().naive_utc()
+        - chrono::Duration::minutes(i64::from(window));
+
+    let entry = env.transaction(|conn| {
+        undo(conn, msg.chat.id, from.id, is_admin, since)
+    })?;
+    outbox::wake(&env);
+
+    let text = match entry {
+        Some(entry) => format!("Undone the {}.", entry.description),
+        None => format!("Nothing to undo in the last {window} minutes."),
+    };
+    bot.reply_message(&msg, text).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageId;
+
+    use super::*;
+    use crate::models::OutboxAction;
+    use crate::testing;
+
+    #[test]
+    fn test_undo() {
+        let mut conn = testing::memory_db();
+        let chat = ChatId(-1);
+        let unpin = |id| UndoAction::Telegram {
+            action: OutboxAction::UnpinMessage {
+                chat_id: chat,
+                message_id: MessageId(id),
+            },
+        };
+        push(&mut conn, chat, UserId(1), "pin one", vec![unpin(1)]).unwrap();
+        push(&mut conn, chat, UserId(2), "pin two", vec![unpin(2)]).unwrap();
+        let since = chrono::Utc::now().naive_utc() - chrono::Duration::hours(1);
+
+        // Users undo only actions affecting them, admins any action.
+        let undone = |conn: &mut SqliteConnection, user, is_admin| {
+            undo(conn, chat, UserId(user), is_admin, since)
+                .unwrap()
+                .map(|e| e.description)
+        };
+        assert_eq!(undone(&mut conn, 1, false).as_deref(), Some("pin one"));
+        assert_eq!(undone(&mut conn, 1, false), None);
+        assert_eq!(undone(&mut conn, 3, true).as_deref(), Some("pin two"));
+        assert_eq!(undone(&mut conn, 3, true), None);
+
+        let outbox = schema::outbox::table
+            .select(models::OutboxEntry::as_select())
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(outbox.len(), 2);
+
+        // Entries outside of the window are ignored.
+        push(&mut conn, chat, UserId(1), "pin three", vec![unpin(3)]).unwrap();
+        let later = chrono::Utc::now().naive_utc() + chrono::Duration::hours(1);
+        assert!(undo(&mut conn, chat, UserId(1), false, later)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,111 @@
+//! Skip updates that were already processed.
+//!
+//! Telegram may deliver an update again, e.g. when the bot restarts before
+//! confirming it in the next `getUpdates` call.  Ids of processed updates
+//! are stored in the `processed_updates` table for [`TTL`], and repeated
+//! updates are dropped before reaching other handlers.
+//!
+//! **Scope**: all updates; background task.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use teloxide::types::Update;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::{BotEnv, UpdateHandler};
+use crate::schema;
+
+/// How long to remember processed updates.  Telegram keeps undelivered
+/// updates for 24 hours.
+const TTL: Duration = Duration::from_secs(48 * 60 * 60);
+
+/// How often to forget expired updates.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Handles repeated updates, letting new ones through.  Should be placed
+/// before other handlers.
+pub fn duplicate_handler() -> UpdateHandler {
+    dptree::filter(|env: Arc<BotEnv>, upd: Update| !mark_processed(&env, &upd))
+        .endpoint(drop_duplicate)
+}
+
+/// Record the update as processed.  Returns `false` if it already was.
+fn mark_processed(env: &BotEnv, upd: &Update) -> bool {
+    let result =
+        diesel::insert_or_ignore_into(schema::processed_updates::table)
+            .values((
+                schema::processed_updates::update_id.eq(upd.id),
+                schema::processed_updates::processed_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(&mut *env.conn());
+    match result {
+        Ok(inserted) => inserted > 0,
+        Err(e) => {
+            // Better to process an update twice than to lose it.
+            log::error!("update_dedup: failed to record update: {e}");
+            true
+        }
+    }
+}
+
+async fn drop_duplicate(upd: Update) -> Result<()> {
+    log::info!("update_dedup: skipping repeated update {}", upd.id);
+    Ok(())
+}
+
+pub async fn task(env: Arc<BotEnv>, shutdown: CancellationToken) {
+    loop {
+        if let Err(e) = prune(&mut env.conn()) {
+            log::error!("update_dedup: failed to prune: {e}");
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(PRUNE_INTERVAL) => {}
+        }
+    }
+}
+
+fn prune(conn: &mut SqliteConnection) -> QueryResult<usize> {
+    let cutoff = chrono::Utc::now().naive_utc()
+        - chrono::Duration::from_std(TTL).expect("TTL is too large");
+    diesel::delete(schema::processed_updates::table)
+        .filter(schema::processed_updates::processed_at.lt(cutoff))
+        .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestBot};
+
+    #[tokio::test]
+    async fn test_mark_processed() {
+        let t = TestBot::new();
+        let user = testing::user_json(1, "Alice");
+        let upd: Update =
+            serde_json::from_value(testing::message(1, None, &user, "hi"))
+                .unwrap();
+        assert!(mark_processed(&t.env, &upd));
+        assert!(!mark_processed(&t.env, &upd));
+
+        assert_eq!(prune(&mut t.env.conn()).unwrap(), 0);
+        diesel::update(schema::processed_updates::table)
+            .set(
+                schema::processed_updates::processed_at
+                    .eq(chrono::NaiveDateTime::default()),
+            )
+            .execute(&mut *t.env.conn())
+            .unwrap();
+        assert_eq!(prune(&mut t.env.conn()).unwrap(), 1);
+        assert!(mark_processed(&t.env, &upd));
+    }
+}
@@ -26,9 +26,11 @@ pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
         }
 
         match check_wikijs_updates(&env, &bot, initial).await {
-            Ok(()) => crate::metrics::update_service("wikijs", true),
+            Ok(()) => {
+                crate::metrics::update_service(&env.health, "wikijs", true)
+            }
             Err(e) => {
-                crate::metrics::update_service("wikijs", false);
+                crate::metrics::update_service(&env.health, "wikijs", false);
                 log::error!("check_wikijs_updates: {e}");
             }
         }
@@ -62,14 +64,16 @@ async fn check_wikijs_updates(
     }
 
     if let Some(updates) = updates {
-        bot.send_message(
-            env.config.telegram.chats.wikijs_updates.chat,
-            updates.to_html(),
-        )
-        .message_thread_id(env.config.telegram.chats.wikijs_updates.thread)
-        .parse_mode(ParseMode::Html)
-        .disable_web_page_preview(true)
-        .await?;
+        let text = updates.to_html();
+        let sent = bot
+            .send_message(env.config.telegram.chats.wikijs_updates.chat, &text)
+            .message_thread_id(env.config.telegram.chats.wikijs_updates.thread)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await?;
+        crate::modules::matrix_bridge::mirror(env, &sent, Some(&text))
+            .await
+            .log_error("Failed to mirror Wiki.js updates to Matrix");
     }
 
     // XXX: Not sure if this check makes sense.  I want to avoid spurious
@@ -1,4 +1,5 @@
-//! `/userctl` command to add or remove MAC addresses.
+//! `/userctl` command to add or remove MAC addresses.  Addresses of former
+//! residents are removed.
 
 use std::sync::Arc;
 
@@ -11,6 +12,7 @@ use teloxide::prelude::*;
 
 use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
 use crate::db::DbUserId;
+use crate::modules::role_changes::{Role, RoleChange};
 use crate::utils::BotExt;
 
 #[derive(Clone, BotCommands, BotCommandsExt!)]
@@ -89,3 +91,15 @@ async fn cmd_userctl(
 
     Ok(())
 }
+
+/// Forget MAC addresses of former residents.
+pub fn on_role_change(env: &BotEnv, change: &RoleChange) -> Result<()> {
+    if change.new == Role::Former {
+        diesel::delete(crate::schema::user_macs::table)
+            .filter(
+                crate::schema::user_macs::tg_id.eq(DbUserId::from(change.user)),
+            )
+            .execute(&mut *env.conn())?;
+    }
+    Ok(())
+}
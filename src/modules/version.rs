@@ -0,0 +1,181 @@
+//! Version of the running bot.
+//!
+//! `/version` shows the revision, the build time and whether all database
+//! migrations known to this build are applied.  On startup, when the
+//! revision differs from the one that ran before, the bot announces it to
+//! [`telegram.chats.admin`] along with the first section of `CHANGELOG.md`.
+//!
+//! **Scope**: `/version` command; startup announcement.
+//!
+//! [`telegram.chats.admin`]: crate::config::TelegramChats::admin
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::Text;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::models;
+use crate::utils::{format_to, html, BotExt as _, ResultExt as _};
+
+const CHANGELOG: &str = include_str!("../../CHANGELOG.md");
+
+/// Name of the latest migration directory, set by `build.rs`.
+const LATEST_MIGRATION: &str = env!("LATEST_MIGRATION");
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "show bot version.")]
+    Version,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_version)
+}
+
+async fn cmd_version(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    bot.reply_message(&msg, version_text(&env))
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+fn version_text(env: &BotEnv) -> String {
+    let mut text =
+        format!("Version: <code>{}</code>\n", html::escape(crate::version()));
+    if let Some(time) = build_time() {
+        format_to!(text, "Built: {} UTC\n", time.format("%Y-%m-%d %H:%M"));
+    }
+    format_to!(
+        text,
+        "Migrations: {}",
+        html::escape(&migration_state(&mut env.conn()))
+    );
+    text
+}
+
+fn build_time() -> Option<NaiveDateTime> {
+    let secs = env!("BUILD_TIME").parse().ok()?;
+    NaiveDateTime::from_timestamp_opt(secs, 0)
+}
+
+#[derive(QueryableByName)]
+struct AppliedMigration {
+    #[diesel(sql_type = Text)]
+    version: String,
+}
+
+/// Version of a migration as recorded by diesel: the digits of the
+/// directory name before the `_`.
+fn migration_version(name: &str) -> String {
+    let prefix = name.split_once('_').map_or(name, |(prefix, _)| prefix);
+    prefix.chars().filter(char::is_ascii_digit).collect()
+}
+
+/// Compare the latest migration applied to the database with the latest one
+/// known to this build.
+fn migration_state(conn: &mut SqliteConnection) -> String {
+    let applied = sql_query(
+        "SELECT version FROM __diesel_schema_migrations \
+         ORDER BY version DESC LIMIT 1",
+    )
+    .get_result::<AppliedMigration>(conn)
+    .optional();
+    let latest = migration_version(LATEST_MIGRATION);
+    match applied {
+        // E.g. the database was not created with the diesel CLI.
+        Err(_) | Ok(None) => "unknown".to_string(),
+        Ok(Some(m)) if m.version == latest => {
+            format!("up to date ({LATEST_MIGRATION})")
+        }
+        Ok(Some(m)) if m.version < latest => {
+            format!("pending, applied up to {}, latest is {latest}", m.version)
+        }
+        Ok(Some(m)) => {
+            format!("database is ahead of this build ({})", m.version)
+        }
+    }
+}
+
+/// The first section of the changelog, without its heading.
+fn latest_changes(changelog: &str) -> Option<(&str, &str)> {
+    let (_, rest) = changelog.split_once("\n## ")?;
+    let section = rest.split_once("\n## ").map_or(rest, |(s, _)| s);
+    let (title, body) = section.split_once('\n').unwrap_or((section, ""));
+    Some((title.trim(), body.trim()))
+}
+
+/// Announce a new version to the admin thread.  The first start is only
+/// recorded, to avoid announcing the changelog to a fresh install.
+pub async fn announce(env: Arc<BotEnv>, bot: Bot) {
+    announce_version(&env, &bot)
+        .await
+        .log_error("version: announce new version");
+}
+
+async fn announce_version(env: &BotEnv, bot: &Bot) -> Result<()> {
+    let Some(thread) = env.config.telegram.chats.admin else { return Ok(()) };
+    let current = crate::version();
+    let previous = models::version_last_announced.get(&mut env.conn())?;
+    if previous.as_deref() == Some(current) {
+        return Ok(());
+    }
+    if let Some(previous) = previous {
+        let mut text = format!(
+            "🤖 <b>Bot updated</b>: <code>{}</code> → <code>{}</code>\n\
+             Migrations: {}",
+            html::escape(&previous),
+            html::escape(current),
+            html::escape(&migration_state(&mut env.conn())),
+        );
+        if let Some((title, changes)) = latest_changes(CHANGELOG) {
+            format_to!(
+                text,
+                "\n\n<b>{}</b>\n{}",
+                html::escape(title),
+                html::escape(changes),
+            );
+        }
+        bot.send_message(thread.chat, text)
+            .message_thread_id(thread.thread)
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview(true)
+            .await?;
+    }
+    models::version_last_announced
+        .set(&mut env.conn(), &current.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_latest_changes() {
+        let changelog = "# Changelog\n\nIntro.\n\n## v2\n\n- New.\n\n## v1\n\n\
+                         - Old.\n";
+        assert_eq!(latest_changes(changelog), Some(("v2", "- New.")));
+        assert!(latest_changes(CHANGELOG).is_some());
+        assert_eq!(latest_changes("# Changelog\n"), None);
+    }
+
+    #[test]
+    fn test_migration_state() {
+        assert_eq!(
+            migration_version("2026-10-16-005100_message_ttl"),
+            "20261016005100"
+        );
+        // Migrations of tests are applied without the diesel CLI.
+        assert_eq!(migration_state(&mut testing::memory_db()), "unknown");
+    }
+}
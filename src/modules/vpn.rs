@@ -0,0 +1,351 @@
+//! WireGuard peers for residents on the `MikroTik` router.
+//!
+//! `/vpn request` generates a key pair, adds a peer to the
+//! [`telegram.vpn.interface`] with the next free address from the pool, and
+//! sends the client configuration file.  The private key is not stored.
+//! `/vpn revoke` removes the peer.  Peers of former residents are revoked
+//! automatically.  All changes are recorded in the audit log.
+//!
+//! **Scope**: `/vpn` command in private chats, available to residents;
+//! background task.
+//!
+//! [`telegram.vpn.interface`]: crate::config::Vpn::interface
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use base64::Engine as _;
+use diesel::prelude::*;
+use macro_rules_attribute::derive;
+use rand_core::OsRng;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::InputFile;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::config::Vpn;
+use crate::db::DbUserId;
+use crate::utils::{mikrotik, BotExt as _, ResultExt as _};
+use crate::{models, schema};
+
+/// How often to check for peers of former residents.
+const REVOKE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "manage your VPN access: \
+                             <code>/vpn request</code> or \
+                             <code>/vpn revoke</code>.")]
+    #[custom(resident = true, in_group = false)]
+    Vpn(String),
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(cmd_vpn)
+}
+
+async fn cmd_vpn(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    Commands::Vpn(args): Commands,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) = &env.config.telegram.vpn else {
+        bot.reply_message(&msg, "VPN is not configured.").await?;
+        return Ok(());
+    };
+    let peer: Option<models::VpnPeer> = schema::vpn_peers::table
+        .filter(schema::vpn_peers::user_id.eq(DbUserId::from(from.id)))
+        .filter(schema::vpn_peers::revoked_at.is_null())
+        .select(models::VpnPeer::as_select())
+        .first(&mut *env.conn())
+        .optional()?;
+    match (args.trim(), peer) {
+        ("request", None) => cmd_request(&bot, &env, &msg, conf).await,
+        ("request", Some(peer)) => {
+            bot.reply_message(
+                &msg,
+                format!(
+                    "You already have a peer with address {}.  Revoke it \
+                     first with /vpn revoke.",
+                    peer.address
+                ),
+            )
+            .await?;
+            Ok(())
+        }
+        ("revoke", Some(peer)) => {
+            let text = match revoke(&env, &peer, Some(from.id)).await {
+                Ok(()) => "Your VPN peer is revoked.",
+                Err(e) => {
+                    log::error!("vpn: failed to revoke #{}: {e}", peer.rowid);
+                    "Failed to revoke the peer."
+                }
+            };
+            bot.reply_message(&msg, text).await?;
+            Ok(())
+        }
+        ("revoke", None) => {
+            bot.reply_message(&msg, "You have no VPN peer.").await?;
+            Ok(())
+        }
+        (_, peer) => {
+            let mut text = String::from("Usage: /vpn request|revoke");
+            if let Some(peer) = peer {
+                write!(
+                    text,
+                    "\nYour peer: {}, since {}.",
+                    peer.address,
+                    peer.created_at.format("%Y-%m-%d"),
+                )
+                .unwrap();
+            }
+            bot.reply_message(&msg, text).await?;
+            Ok(())
+        }
+    }
+}
+
+async fn cmd_request(
+    bot: &Bot,
+    env: &BotEnv,
+    msg: &Message,
+    conf: &Vpn,
+) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+
+    // Reserve the address before talking to the router.
+    let reserved = env.transaction(|conn| {
+        let used: HashSet<Ipv4Addr> = schema::vpn_peers::table
+            .filter(schema::vpn_peers::revoked_at.is_null())
+            .select(schema::vpn_peers::address)
+            .load::<String>(conn)?
+            .iter()
+            .filter_map(|a| a.parse().ok())
+            .collect();
+        let Some(address) = free_address(conf.pool_start, conf.pool_end, &used)
+        else {
+            return Ok(None);
+        };
+        diesel::insert_into(schema::vpn_peers::table)
+            .values((
+                schema::vpn_peers::user_id.eq(DbUserId::from(from.id)),
+                schema::vpn_peers::address.eq(address.to_string()),
+                schema::vpn_peers::public_key.eq(""),
+                schema::vpn_peers::router_id.eq(""),
+                schema::vpn_peers::created_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        let rowid = schema::vpn_peers::table
+            .select(schema::vpn_peers::rowid)
+            .order(schema::vpn_peers::rowid.desc())
+            .first::<i32>(conn)?;
+        Ok(Some((rowid, address)))
+    })?;
+    let Some((rowid, address)) = reserved else {
+        bot.reply_message(msg, "No free VPN addresses left.").await?;
+        return Ok(());
+    };
+
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let b64 = base64::engine::general_purpose::STANDARD;
+    let private_key = b64.encode(secret.to_bytes());
+    let public_key = b64.encode(PublicKey::from(&secret).as_bytes());
+    let router_id = mikrotik::add_wireguard_peer(
+        &env.health,
+        &env.reqwest_client,
+        &env.config.services.mikrotik,
+        &conf.interface,
+        &public_key,
+        &format!("{address}/32"),
+        &format!("botka: tg {}", from.id.0),
+    )
+    .await;
+    let router_id = match router_id {
+        Ok(router_id) => router_id,
+        Err(e) => {
+            log::error!("vpn: failed to add peer: {e}");
+            diesel::delete(schema::vpn_peers::table)
+                .filter(schema::vpn_peers::rowid.eq(rowid))
+                .execute(&mut *env.conn())?;
+            bot.reply_message(msg, "Failed to create the VPN peer.").await?;
+            return Ok(());
+        }
+    };
+    env.transaction(|conn| {
+        diesel::update(schema::vpn_peers::table)
+            .filter(schema::vpn_peers::rowid.eq(rowid))
+            .set((
+                schema::vpn_peers::public_key.eq(&public_key),
+                schema::vpn_peers::router_id.eq(&router_id),
+            ))
+            .execute(conn)?;
+        crate::modules::audit::record(
+            conn,
+            Some(from.id),
+            "vpn_peer_add",
+            &serde_json::json!({
+                "peer_id": rowid,
+                "user_id": from.id.0,
+                "address": address.to_string(),
+            }),
+        )
+        .log_error("vpn: audit");
+        Ok(())
+    })?;
+
+    bot.send_document(
+        msg.chat.id,
+        InputFile::memory(
+            client_config(conf, &private_key, address).into_bytes(),
+        )
+        .file_name("f0rth-vpn.conf"),
+    )
+    .caption(
+        "Import this file into the WireGuard app.  The private key is not \
+         stored by the bot, keep the file safe.",
+    )
+    .await?;
+    Ok(())
+}
+
+/// Find the first address in the range that is not used.
+fn free_address(
+    start: Ipv4Addr,
+    end: Ipv4Addr,
+    used: &HashSet<Ipv4Addr>,
+) -> Option<Ipv4Addr> {
+    (u32::from(start)..=u32::from(end))
+        .map(Ipv4Addr::from)
+        .find(|a| !used.contains(a))
+}
+
+fn client_config(conf: &Vpn, private_key: &str, address: Ipv4Addr) -> String {
+    let mut text = String::new();
+    writeln!(text, "[Interface]").unwrap();
+    writeln!(text, "PrivateKey = {private_key}").unwrap();
+    writeln!(text, "Address = {address}/32").unwrap();
+    if let Some(dns) = &conf.dns {
+        writeln!(text, "DNS = {dns}").unwrap();
+    }
+    writeln!(text, "\n[Peer]").unwrap();
+    writeln!(text, "PublicKey = {}", conf.server_public_key).unwrap();
+    writeln!(text, "AllowedIPs = {}", conf.allowed_ips).unwrap();
+    writeln!(text, "Endpoint = {}", conf.endpoint).unwrap();
+    writeln!(text, "PersistentKeepalive = 25").unwrap();
+    text
+}
+
+/// Remove the peer from the router and mark it as revoked.  `actor` is
+/// `None` for automatic revocation.
+async fn revoke(
+    env: &BotEnv,
+    peer: &models::VpnPeer,
+    actor: Option<UserId>,
+) -> Result<()> {
+    if !peer.router_id.is_empty() {
+        mikrotik::remove_wireguard_peer(
+            &env.health,
+            &env.reqwest_client,
+            &env.config.services.mikrotik,
+            &peer.router_id,
+        )
+        .await?;
+    }
+    env.transaction(|conn| {
+        diesel::update(schema::vpn_peers::table)
+            .filter(schema::vpn_peers::rowid.eq(peer.rowid))
+            .set(
+                schema::vpn_peers::revoked_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            )
+            .execute(conn)?;
+        crate::modules::audit::record(
+            conn,
+            actor,
+            "vpn_peer_revoke",
+            &serde_json::json!({
+                "peer_id": peer.rowid,
+                "user_id": UserId::from(peer.user_id).0,
+                "address": peer.address,
+            }),
+        )
+        .log_error("vpn: audit");
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Revoke peers of former residents.
+pub async fn task(env: Arc<BotEnv>, shutdown: CancellationToken) {
+    if env.config.telegram.vpn.is_none() {
+        return;
+    }
+    loop {
+        let peers = schema::vpn_peers::table
+            .filter(schema::vpn_peers::revoked_at.is_null())
+            .filter(
+                schema::vpn_peers::user_id.ne_all(
+                    schema::residents::table
+                        .filter(schema::residents::end_date.is_null())
+                        .select(schema::residents::tg_id),
+                ),
+            )
+            .select(models::VpnPeer::as_select())
+            .load(&mut *env.conn());
+        match peers {
+            Ok(peers) => {
+                for peer in &peers {
+                    log::info!(
+                        "vpn: revoking #{} of former resident {}",
+                        peer.rowid,
+                        UserId::from(peer.user_id).0,
+                    );
+                    revoke(&env, peer, None)
+                        .await
+                        .log_error("vpn: auto-revoke");
+                }
+            }
+            Err(e) => log::error!("vpn: failed to load peers: {e}"),
+        }
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = sleep(REVOKE_INTERVAL) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_address() {
+        let start = Ipv4Addr::new(10, 8, 0, 2);
+        let end = Ipv4Addr::new(10, 8, 0, 4);
+        let mut used = HashSet::new();
+        assert_eq!(free_address(start, end, &used), Some(start));
+        used.insert(start);
+        used.insert(Ipv4Addr::new(10, 8, 0, 3));
+        assert_eq!(
+            free_address(start, end, &used),
+            Some(Ipv4Addr::new(10, 8, 0, 4))
+        );
+        used.insert(end);
+        assert_eq!(free_address(start, end, &used), None);
+    }
+}
@@ -0,0 +1,184 @@
+//! Login links for the web dashboard.
+//!
+//! `/weblogin` replies with a short-lived link that logs the admin into the
+//! dashboard served at `/dashboard`, see [`web_srv::dashboard`].  `/link`
+//! replies with a link that links an account of the OIDC provider to the
+//! user, see [`web_srv::oidc`].
+//!
+//! **Scope**: `/weblogin` command in private chats, available to admins;
+//! `/link` command in private chats.
+//!
+//! [`web_srv::dashboard`]: crate::web_srv::dashboard
+//! [`web_srv::oidc`]: crate::web_srv::oidc
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use macro_rules_attribute::derive;
+use teloxide::macros::BotCommands;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::common::{filter_command, BotCommandsExt, BotEnv, UpdateHandler};
+use crate::utils::{html, BotExt as _};
+use crate::web_srv::dashboard::{login_url, LOGIN_LINK_MINUTES};
+use crate::web_srv::oidc::{link_url, LINK_CODE_MINUTES};
+
+#[derive(Clone, BotCommands, BotCommandsExt!)]
+#[command(rename_rule = "snake_case")]
+pub enum Commands {
+    #[command(description = "get a link to log into the web dashboard.")]
+    #[custom(admin = true, in_group = false)]
+    Weblogin,
+    #[command(description = "link your SSO account to log into the web \
+                             dashboard and APIs.")]
+    #[custom(in_group = false)]
+    Link,
+}
+
+pub fn command_handler() -> UpdateHandler {
+    filter_command::<Commands>().endpoint(handle_command)
+}
+
+async fn handle_command(
+    bot: Bot,
+    env: Arc<BotEnv>,
+    msg: Message,
+    command: Commands,
+) -> Result<()> {
+    match command {
+        Commands::Weblogin => cmd_weblogin(bot, env, msg).await,
+        Commands::Link => cmd_link(bot, env, msg).await,
+    }
+}
+
+async fn cmd_weblogin(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) = &env.config.server_dashboard else {
+        bot.reply_message(&msg, "The web dashboard is not configured.").await?;
+        return Ok(());
+    };
+    let url = login_url(conf, from.id);
+    bot.reply_message(
+        &msg,
+        format!(
+            "{}\n\nThe link is valid for {LOGIN_LINK_MINUTES} minutes.  Do \
+             not share it.",
+            html::link(&url, "Open the dashboard"),
+        ),
+    )
+    .parse_mode(ParseMode::Html)
+    .disable_web_page_preview(true)
+    .await?;
+    Ok(())
+}
+
+async fn cmd_link(bot: Bot, env: Arc<BotEnv>, msg: Message) -> Result<()> {
+    let Some(from) = &msg.from else { return Ok(()) };
+    let Some(conf) =
+        env.config.server_dashboard.as_ref().filter(|c| c.oidc.is_some())
+    else {
+        bot.reply_message(&msg, "SSO login is not configured.").await?;
+        return Ok(());
+    };
+    let url = link_url(&mut env.conn(), conf, from.id)?;
+    bot.reply_message(
+        &msg,
+        format!(
+            "{}\n\nThe link is valid for {LINK_CODE_MINUTES} minutes and can \
+             be used once.  Do not share it.",
+            html::link(&url, "Log in with SSO to link your account"),
+        ),
+    )
+    .parse_mode(ParseMode::Html)
+    .disable_web_page_preview(true)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+    use teloxide::dispatching::UpdateFilterExt as _;
+
+    use super::*;
+    use crate::db::DbUserId;
+    use crate::schema;
+    use crate::testing::{self, TestBot};
+
+    const ADMIN: u64 = 1_234_567_890;
+
+    /// Send the command and return the text of the first reply.
+    async fn reply(t: &TestBot, chat_id: i64, from: u64, text: &str) -> String {
+        t.telegram.clear();
+        let handler: UpdateHandler = Update::filter_message()
+            .branch(command_handler())
+            .endpoint(|| async { Ok(()) });
+        t.dispatch(
+            &handler,
+            testing::message(
+                chat_id,
+                None,
+                &testing::user_json(from, "User"),
+                text,
+            ),
+        )
+        .await;
+        t.telegram.calls("sendMessage")[0]["text"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_weblogin() {
+        let t = TestBot::new();
+        let text = reply(&t, ADMIN as i64, ADMIN, "/weblogin").await;
+        assert!(
+            text.contains(&format!(
+                "href=\"https://bot.example.com/dashboard/login?token={ADMIN}."
+            )),
+            "{text}",
+        );
+
+        assert_eq!(
+            reply(&t, 1, 1, "/weblogin").await,
+            "You must be an admin to execute this command",
+        );
+        assert_eq!(
+            reply(&t, -1, ADMIN, "/weblogin").await,
+            "This command is not allowed in group chats",
+        );
+
+        let t = TestBot::with_config(|c| c.server_dashboard = None);
+        assert_eq!(
+            reply(&t, ADMIN as i64, ADMIN, "/weblogin").await,
+            "The web dashboard is not configured.",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link() {
+        let t = TestBot::new();
+        let text = reply(&t, 1, 1, "/link").await;
+        let code: String = schema::oidc_link_codes::table
+            .filter(
+                schema::oidc_link_codes::tg_id.eq(DbUserId::from(UserId(1))),
+            )
+            .select(schema::oidc_link_codes::code)
+            .first(&mut *t.env.conn())
+            .unwrap();
+        assert!(
+            text.contains(&format!(
+                "href=\"https://bot.example.com/dashboard/oidc/login?link=\
+                 {code}\""
+            )),
+            "{text}",
+        );
+
+        let t = TestBot::with_config(|c| {
+            c.server_dashboard.as_mut().unwrap().oidc = None;
+        });
+        assert_eq!(
+            reply(&t, 1, 1, "/link").await,
+            "SSO login is not configured.",
+        );
+    }
+}
@@ -119,6 +119,10 @@ async fn handle_join(
 
     state.lock().unwrap().0.extend(newcomers.0.iter().map(|m| m.id));
 
+    for newcomer in &newcomers.0 {
+        crate::modules::intros::prompt(&bot, newcomer).await;
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,261 @@
+//! Transactional outbox for Telegram side effects.
+//!
+//! Handlers [`enqueue`] Telegram API calls in the same transaction as the
+//! database changes they belong to, and [`wake`] the worker after the commit.
+//! The worker performs the calls, retrying on network errors.  If a call
+//! fails permanently, its compensation actions are performed instead, e.g.
+//! deleting the bot's own message that should not exist without the call.
+//! Failed entries are kept in the `outbox` table with the last error.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use teloxide::prelude::*;
+use teloxide::{ApiError, RequestError};
+use tokio::select;
+use tokio::sync::Notify;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::BotEnv;
+use crate::models::{self, OutboxAction};
+use crate::modules::broadcasts;
+use crate::schema;
+use crate::utils::Sqlizer;
+
+/// How often to check for due retries when not woken.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of attempts before giving up on a call that fails with network
+/// errors.
+const MAX_ATTEMPTS: i32 = 8;
+
+/// Delay before the first retry, doubled on each attempt.
+const BASE_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Schedule `action`, with `compensation` to be performed if it fails
+/// permanently.  Call inside the transaction with the related changes.
+pub fn enqueue(
+    conn: &mut SqliteConnection,
+    action: OutboxAction,
+    compensation: Vec<OutboxAction>,
+) -> QueryResult<()> {
+    enqueue_at(conn, action, compensation, chrono::Utc::now().naive_utc())
+}
+
+/// Same as [`enqueue`], but perform `action` not earlier than `at` (UTC).
+pub fn enqueue_at(
+    conn: &mut SqliteConnection,
+    action: OutboxAction,
+    compensation: Vec<OutboxAction>,
+    at: chrono::NaiveDateTime,
+) -> QueryResult<()> {
+    let now = chrono::Utc::now().naive_utc();
+    diesel::insert_into(schema::outbox::table)
+        .values(models::NewOutboxEntry {
+            action: Sqlizer::new(action).expect("OutboxAction is serializable"),
+            compensation: Sqlizer::new(compensation)
+                .expect("OutboxAction is serializable"),
+            created_at: now,
+            next_attempt_at: at.max(now),
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Wakes the worker of an instance.  Lives in [`BotEnv::outbox`].
+#[derive(Default)]
+pub struct Wake(Notify);
+
+/// Make the worker process the outbox now.  Call after committing.
+pub fn wake(env: &BotEnv) {
+    env.outbox.0.notify_one();
+}
+
+pub async fn task(env: Arc<BotEnv>, bot: Bot, shutdown: CancellationToken) {
+    loop {
+        process(&env, &bot).await;
+
+        select! {
+            () = shutdown.cancelled() => {
+                break;
+            }
+            () = env.outbox.0.notified() => {}
+            () = sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Perform all due actions.
+pub async fn process(env: &BotEnv, bot: &Bot) {
+    let now = chrono::Utc::now().naive_utc();
+    let entries: Vec<models::OutboxEntry> = match schema::outbox::table
+        .filter(schema::outbox::failed_at.is_null())
+        .filter(schema::outbox::next_attempt_at.le(now))
+        .order(schema::outbox::rowid)
+        .select(models::OutboxEntry::as_select())
+        .load(&mut *env.conn())
+    {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("outbox: failed to load entries: {e}");
+            return;
+        }
+    };
+
+    for entry in entries {
+        let result = match perform(env, bot, &entry.action).await {
+            Ok(()) => diesel::delete(schema::outbox::table)
+                .filter(schema::outbox::rowid.eq(entry.rowid))
+                .execute(&mut *env.conn()),
+            Err(e) => failed(env, bot, &entry, &e).await,
+        };
+        if let Err(e) = result {
+            log::error!("outbox: failed to update entry {}: {e}", entry.rowid);
+        }
+    }
+}
+
+/// Schedule a retry, or give up and perform the compensation.
+async fn failed(
+    env: &BotEnv,
+    bot: &Bot,
+    entry: &models::OutboxEntry,
+    error: &RequestError,
+) -> QueryResult<usize> {
+    let attempts = entry.attempts + 1;
+    let now = chrono::Utc::now().naive_utc();
+    let retry_after = match error {
+        RequestError::RetryAfter(d) => Some(*d),
+        RequestError::Network(_) | RequestError::Io(_) => {
+            Some(BASE_BACKOFF * 2u32.pow(attempts.unsigned_abs() - 1))
+        }
+        _ => None,
+    }
+    .filter(|_| attempts < MAX_ATTEMPTS);
+
+    let update = diesel::update(schema::outbox::table)
+        .filter(schema::outbox::rowid.eq(entry.rowid));
+    if let Some(retry_after) = retry_after {
+        let next_attempt_at =
+            now + chrono::Duration::from_std(retry_after).unwrap_or_default();
+        return update
+            .set((
+                schema::outbox::attempts.eq(attempts),
+                schema::outbox::next_attempt_at.eq(next_attempt_at),
+                schema::outbox::last_error.eq(error.to_string()),
+            ))
+            .execute(&mut *env.conn());
+    }
+
+    log::error!("outbox: giving up on {:?}: {error}", *entry.action);
+    for action in entry.compensation.iter() {
+        if let Err(e) = perform(env, bot, action).await {
+            log::error!("outbox: compensation {action:?} failed: {e}");
+        }
+    }
+    update
+        .set((
+            schema::outbox::attempts.eq(attempts),
+            schema::outbox::last_error.eq(error.to_string()),
+            schema::outbox::failed_at.eq(now),
+        ))
+        .execute(&mut *env.conn())
+}
+
+/// Perform the action.  Succeeds if the action has no effect because it was
+/// already done, e.g. the message to delete is gone.
+async fn perform(
+    env: &BotEnv,
+    bot: &Bot,
+    action: &OutboxAction,
+) -> Result<(), RequestError> {
+    let result = match *action {
+        OutboxAction::DeleteMessage { chat_id, message_id } => {
+            bot.delete_message(chat_id, message_id).await.map(drop)
+        }
+        OutboxAction::PinMessage { chat_id, message_id } => {
+            bot.pin_chat_message(chat_id, message_id).await.map(drop)
+        }
+        OutboxAction::UnpinMessage { chat_id, message_id } => bot
+            .unpin_chat_message(chat_id)
+            .message_id(message_id)
+            .await
+            .map(drop),
+        OutboxAction::SendBroadcast { broadcast_id, user_id } => {
+            broadcasts::deliver(env, bot, broadcast_id, user_id).await
+        }
+        OutboxAction::FailBroadcast { broadcast_id, user_id } => {
+            broadcasts::give_up(env, bot, broadcast_id, user_id).await;
+            Ok(())
+        }
+        OutboxAction::SendPoll {
+            chat_id,
+            thread_id,
+            ref question,
+            ref options,
+            is_anonymous,
+            allows_multiple_answers,
+        } => {
+            let mut poll = bot
+                .send_poll(chat_id, question, options.iter().cloned())
+                .is_anonymous(is_anonymous)
+                .allows_multiple_answers(allows_multiple_answers);
+            poll.message_thread_id = thread_id;
+            poll.await.map(drop)
+        }
+    };
+    match result {
+        Err(RequestError::Api(ApiError::MessageToDeleteNotFound)) => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools as _;
+
+    use super::*;
+    use crate::testing::TestBot;
+
+    fn delete(message_id: i32) -> OutboxAction {
+        OutboxAction::DeleteMessage {
+            chat_id: ChatId(-1),
+            message_id: teloxide::types::MessageId(message_id),
+        }
+    }
+
+    fn load(t: &TestBot) -> Vec<models::OutboxEntry> {
+        schema::outbox::table
+            .select(models::OutboxEntry::as_select())
+            .load(&mut *t.env.conn())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_outbox() {
+        let t = TestBot::new();
+        t.env
+            .transaction(|conn| {
+                enqueue(conn, delete(1), vec![])?;
+                enqueue(conn, delete(2), vec![delete(3)])
+            })
+            .unwrap();
+        t.telegram.fail("deleteMessage", "Bad Request: unknown error");
+        t.telegram.fail("deleteMessage", "Bad Request: unknown error");
+
+        process(&t.env, &t.bot).await;
+        let calls = t.telegram.calls("deleteMessage");
+        let ids = calls.iter().map(|c| c["message_id"].clone()).collect_vec();
+        assert_eq!(ids, [1, 2, 3]);
+        let entries = load(&t);
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.failed_at.is_some()));
+
+        // Failed entries are not retried.
+        t.telegram.clear();
+        process(&t.env, &t.bot).await;
+        assert!(t.telegram.calls("deleteMessage").is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+//! Replay recorded updates to reproduce bugs.
+//!
+//! Updates are read from a JSONL file in the format of `trace.jsonl` and fed
+//! one by one through the same handlers as in the `bot` subcommand.  The bot
+//! works on a scratch copy of the database, with the record of processed
+//! updates cleared, and talks to a [`MockTelegram`] instead of the Telegram
+//! API.  Other external services are called as
+//! usual, so use a config with them disabled.
+//!
+//! For each update, a JSON line with the outcome and the Telegram API
+//! requests made by the bot is printed to stdout.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use diesel::{sql_query, Connection as _, RunQueryDsl as _, SqliteConnection};
+use serde::Serialize;
+use teloxide::requests::Requester as _;
+use teloxide::types::Update;
+use teloxide::Bot;
+
+use crate::mock_telegram::{MockTelegram, Request};
+use crate::schema;
+
+#[derive(Serialize)]
+struct Outcome {
+    update_id: i32,
+    /// `ok`, `ignored`, or an error message.
+    result: String,
+    requests: Vec<Request>,
+}
+
+pub async fn run(
+    config_fpath: &OsStr,
+    updates_fpath: &OsStr,
+    db_fpath: &str,
+) -> Result<()> {
+    let scratch_db = std::env::temp_dir()
+        .join(format!("botka-replay-{}.sqlite3", std::process::id()));
+    let result =
+        replay(config_fpath, updates_fpath, db_fpath, &scratch_db).await;
+    std::fs::remove_file(&scratch_db).ok();
+    result
+}
+
+async fn replay(
+    config_fpath: &OsStr,
+    updates_fpath: &OsStr,
+    db_fpath: &str,
+    scratch_db: &Path,
+) -> Result<()> {
+    let scratch_db = scratch_db.to_str().context("Non-UTF-8 temp dir")?;
+    sql_query("VACUUM INTO ?")
+        .bind::<diesel::sql_types::Text, _>(scratch_db)
+        .execute(&mut SqliteConnection::establish(db_fpath)?)
+        .context("Failed to copy the database")?;
+
+    diesel::delete(schema::processed_updates::table)
+        .execute(&mut SqliteConnection::establish(scratch_db)?)?;
+    let bot_env =
+        crate::load_bot_env("replay", config_fpath, Path::new(scratch_db))?;
+    let telegram = MockTelegram::start();
+    let bot =
+        Bot::new(&bot_env.config.telegram.token).set_api_url(telegram.url());
+    let me = bot.get_me().await?;
+    telegram.take_requests();
+
+    let command_handlers = crate::command_handlers();
+    let handler = crate::update_handler(command_handlers.clone());
+    let mut deps = crate::dependencies(Arc::clone(&bot_env), command_handlers);
+    deps.insert(bot);
+    deps.insert(me);
+
+    let file = File::open(updates_fpath).context("Failed to open updates")?;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with(r#"{"__f0bot":""#) {
+            // Requests/responses of the original run
+            continue;
+        }
+        let update: Update = serde_json::from_str(&line)?;
+        let update_id = update.id;
+        let mut update_deps = deps.clone();
+        update_deps.insert(update);
+        let result = match handler.dispatch(update_deps).await {
+            ControlFlow::Break(Ok(())) => "ok".to_string(),
+            ControlFlow::Break(Err(e)) => format!("{e:#}"),
+            ControlFlow::Continue(_) => "ignored".to_string(),
+        };
+        let outcome =
+            Outcome { update_id, result, requests: telegram.take_requests() };
+        println!("{}", serde_json::to_string(&outcome)?);
+    }
+
+    Ok(())
+}
@@ -1,5 +1,139 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    action_items (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        thread_id -> Nullable<Integer>,
+        message_id -> Nullable<Integer>,
+        created_by -> BigInt,
+        assignee_id -> BigInt,
+        text -> Text,
+        created_at -> Timestamp,
+        due_at -> Nullable<Timestamp>,
+        reminded -> Bool,
+        done_at -> Nullable<Timestamp>,
+        done_by -> Nullable<BigInt>,
+        meeting_id -> Nullable<Integer>,
+        decision_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    activity_feed (rowid) {
+        rowid -> Integer,
+        timestamp -> Timestamp,
+        module -> Text,
+        actor_id -> Nullable<BigInt>,
+        verb -> Text,
+        object -> Text,
+        url -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    archived_links (rowid) {
+        rowid -> Integer,
+        url -> Text,
+        archive_url -> Text,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        archived_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    audit_log (rowid) {
+        rowid -> Integer,
+        timestamp -> Timestamp,
+        actor_id -> Nullable<BigInt>,
+        action -> Text,
+        payload -> Text,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    backup_media (chat_id, message_id) {
+        chat_id -> BigInt,
+        message_id -> Integer,
+        file_unique_id -> Text,
+        file_name -> Nullable<Text>,
+        mime_type -> Nullable<Text>,
+        size -> BigInt,
+        storage_key -> Text,
+        stored_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    backup_messages (chat_id, message_id) {
+        chat_id -> BigInt,
+        message_id -> Integer,
+        backup_chat_id -> BigInt,
+        backup_message_id -> Integer,
+    }
+}
+
+diesel::table! {
+    ballot_tallies (ballot_id, option) {
+        ballot_id -> Integer,
+        option -> Integer,
+        votes -> Integer,
+    }
+}
+
+diesel::table! {
+    ballot_voters (ballot_id, user_id) {
+        ballot_id -> Integer,
+        user_id -> BigInt,
+    }
+}
+
+diesel::table! {
+    ballots (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        creator_id -> BigInt,
+        question -> Text,
+        options -> Text,
+        created_at -> Timestamp,
+        closed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    bandwidth_counters (mac) {
+        mac -> Text,
+        bytes_down -> BigInt,
+        bytes_up -> BigInt,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    bandwidth_usage (date, mac) {
+        date -> Date,
+        mac -> Text,
+        bytes_down -> BigInt,
+        bytes_up -> BigInt,
+    }
+}
+
+diesel::table! {
+    bookings (rowid) {
+        rowid -> Integer,
+        resource -> Text,
+        user_id -> BigInt,
+        start_at -> Timestamp,
+        end_at -> Timestamp,
+        reminded -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     borrowed_items (chat_id, user_message_id) {
         chat_id -> BigInt,
@@ -8,6 +142,56 @@ diesel::table! {
         bot_message_id -> Integer,
         user_id -> BigInt,
         items -> Text,
+        created_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    broadcast_deliveries (broadcast_id, user_id) {
+        broadcast_id -> Integer,
+        user_id -> BigInt,
+        status -> Text,
+        error -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    broadcasts (rowid) {
+        rowid -> Integer,
+        created_by -> BigInt,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        audience -> Text,
+        text -> Text,
+        created_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    chores (rowid) {
+        rowid -> Integer,
+        name -> Text,
+        due_at -> Timestamp,
+        stage -> Integer,
+        last_done_at -> Nullable<Timestamp>,
+        last_done_by -> Nullable<BigInt>,
+        chat_id -> Nullable<BigInt>,
+        message_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    countdowns (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        title -> Text,
+        target_at -> Timestamp,
+        created_by -> BigInt,
+        text -> Text,
+        finished -> Bool,
     }
 }
 
@@ -20,6 +204,307 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    donations (rowid) {
+        rowid -> Integer,
+        donor -> Text,
+        amount_cents -> BigInt,
+        private -> Bool,
+        recorded_by -> BigInt,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    door_credentials (rowid) {
+        rowid -> Integer,
+        user_id -> BigInt,
+        kind -> Text,
+        code -> Text,
+        provisioned_by -> BigInt,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        revoked_by -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    duty_assignments (rowid) {
+        rowid -> Integer,
+        week -> Text,
+        duty -> Text,
+        user_id -> BigInt,
+        status -> Text,
+        proof_file_id -> Nullable<Text>,
+        chat_id -> BigInt,
+        message_id -> Integer,
+    }
+}
+
+diesel::table! {
+    expiring_messages (chat_id, message_id) {
+        chat_id -> BigInt,
+        message_id -> Integer,
+        delete_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    faq_entries (rowid) {
+        rowid -> Integer,
+        pattern -> Text,
+        is_regex -> Bool,
+        answer -> Text,
+        uses -> Integer,
+        created_by -> BigInt,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    feature_flags (name, chat_id) {
+        name -> Text,
+        chat_id -> BigInt,
+        enabled -> Bool,
+    }
+}
+
+diesel::table! {
+    feed_entries (feed_id, guid) {
+        feed_id -> Integer,
+        guid -> Text,
+        seen_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    feeds (rowid) {
+        rowid -> Integer,
+        url -> Text,
+        chat_id -> BigInt,
+        thread_id -> Integer,
+        include -> Text,
+        exclude -> Text,
+        added_by -> BigInt,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    follow_mutes (user_id) {
+        user_id -> BigInt,
+        until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    follows (user_id, tag) {
+        user_id -> BigInt,
+        tag -> Text,
+    }
+}
+
+diesel::table! {
+    fridge_items (rowid) {
+        rowid -> Integer,
+        owner -> BigInt,
+        name -> Text,
+        expires_on -> Date,
+        status -> Text,
+        added_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    frontdesk_inquiries (rowid) {
+        rowid -> Integer,
+        visitor_id -> BigInt,
+        asked_at -> Timestamp,
+        answered_at -> Nullable<Timestamp>,
+        answered_by -> Nullable<BigInt>,
+        pinged_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    frontdesk_threads (visitor_id) {
+        visitor_id -> BigInt,
+        chat_id -> BigInt,
+        thread_id -> Integer,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    guest_vouchers (rowid) {
+        rowid -> Integer,
+        issued_by -> BigInt,
+        username -> Text,
+        router_id -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        removed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    incident_updates (rowid) {
+        rowid -> Integer,
+        incident_id -> Integer,
+        author -> BigInt,
+        status -> Text,
+        note -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    incidents (rowid) {
+        rowid -> Integer,
+        reporter -> BigInt,
+        kind -> Text,
+        description -> Text,
+        status -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    inventory_intakes (rowid) {
+        rowid -> Integer,
+        user_id -> BigInt,
+        items -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    inventory_items (rowid) {
+        rowid -> Integer,
+        name -> Text,
+        quantity -> Integer,
+        added_by -> BigInt,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    invite_link_joins (rowid) {
+        rowid -> Integer,
+        invite_link_id -> Integer,
+        user_id -> BigInt,
+        joined_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    invite_links (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        invite_link -> Text,
+        created_by -> BigInt,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        member_limit -> Nullable<Integer>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    karma_thanks (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        from_user -> BigInt,
+        to_user -> BigInt,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    kv_entries (namespace, key) {
+        namespace -> Text,
+        key -> Text,
+        value -> Binary,
+        content_type -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    mail_messages (message_id) {
+        message_id -> Text,
+        inbound -> Bool,
+        chat_id -> BigInt,
+        tg_message_id -> Integer,
+        created_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    meeting_notes (rowid) {
+        rowid -> Integer,
+        meeting_id -> Integer,
+        kind -> Text,
+        user_id -> BigInt,
+        text -> Text,
+        created_at -> Timestamp,
+        message_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    meetings (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        thread_id -> Integer,
+        started_by -> BigInt,
+        started_at -> Timestamp,
+        ended_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    member_intros (user_id) {
+        user_id -> BigInt,
+        skills -> Text,
+        interests -> Text,
+        pronouns -> Text,
+        updated_at -> Timestamp,
+        chat_id -> Nullable<BigInt>,
+        message_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    message_ttl_policies (chat_id, thread_id) {
+        chat_id -> BigInt,
+        thread_id -> Integer,
+        ttl_seconds -> BigInt,
+        include_others -> Bool,
+        set_by -> BigInt,
+        set_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    moderation_cases (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        user_id -> BigInt,
+        text -> Text,
+        reason -> Text,
+        status -> Text,
+        moderator -> Nullable<BigInt>,
+        appeal -> Nullable<Text>,
+        created_at -> Timestamp,
+        resolved_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     needed_items (rowid) {
         rowid -> Integer,
@@ -30,6 +515,37 @@ diesel::table! {
         pinned_message_id -> Integer,
         buyer_user_id -> Nullable<BigInt>,
         item -> Text,
+        created_at -> Nullable<Timestamp>,
+        bought_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    network_devices (mac) {
+        mac -> Text,
+        host_name -> Nullable<Text>,
+        address -> Nullable<Text>,
+        dhcp_server -> Nullable<Text>,
+        first_seen -> Timestamp,
+        last_seen -> Timestamp,
+        infrastructure -> Bool,
+        alert_message_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    oidc_link_codes (code) {
+        code -> Text,
+        tg_id -> BigInt,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oidc_links (subject) {
+        subject -> Text,
+        tg_id -> BigInt,
+        linked_at -> Timestamp,
     }
 }
 
@@ -40,6 +556,160 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    outbox (rowid) {
+        rowid -> Integer,
+        action -> Text,
+        compensation -> Text,
+        created_at -> Timestamp,
+        attempts -> Integer,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+        failed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    packages (rowid) {
+        rowid -> Integer,
+        recipient -> BigInt,
+        description -> Text,
+        accepted_by -> BigInt,
+        accepted_at -> Timestamp,
+        picked_up_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    pending_approvals (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        requester_id -> BigInt,
+        message -> Text,
+        created_at -> Timestamp,
+        approver_id -> Nullable<BigInt>,
+        executed -> Bool,
+    }
+}
+
+diesel::table! {
+    poll_announcements (chat_id, message_id) {
+        chat_id -> BigInt,
+        message_id -> Integer,
+        tg_poll_id -> Text,
+        question -> Text,
+        poll_url -> Text,
+    }
+}
+
+diesel::table! {
+    presence_log (rowid) {
+        rowid -> Integer,
+        timestamp -> Timestamp,
+        person_count -> Integer,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    processed_updates (update_id) {
+        update_id -> Integer,
+        processed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    project_log (rowid) {
+        rowid -> Integer,
+        project_id -> Integer,
+        user_id -> BigInt,
+        text -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    project_members (project_id, user_id) {
+        project_id -> Integer,
+        user_id -> BigInt,
+    }
+}
+
+diesel::table! {
+    projects (rowid) {
+        rowid -> Integer,
+        name -> Text,
+        status -> Text,
+        owner_id -> BigInt,
+        chat_id -> Nullable<BigInt>,
+        thread_id -> Nullable<Integer>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        nudged_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    proposal_options (rowid) {
+        rowid -> Integer,
+        proposal_id -> Integer,
+        text -> Text,
+        author_id -> BigInt,
+    }
+}
+
+diesel::table! {
+    proposals (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        creator_id -> BigInt,
+        question -> Text,
+        created_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    ranked_ballots (vote_id, user_id) {
+        vote_id -> Integer,
+        user_id -> BigInt,
+        ranking -> Text,
+        submitted -> Bool,
+    }
+}
+
+diesel::table! {
+    ranked_votes (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        creator_id -> BigInt,
+        question -> Text,
+        options -> Text,
+        created_at -> Timestamp,
+        closed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    reimbursements (rowid) {
+        rowid -> Integer,
+        requester_id -> BigInt,
+        amount_cents -> BigInt,
+        description -> Text,
+        receipt_file_id -> Nullable<Text>,
+        chat_id -> BigInt,
+        message_id -> Integer,
+        status -> Text,
+        created_at -> Timestamp,
+        decided_at -> Nullable<Timestamp>,
+        decided_by -> Nullable<BigInt>,
+        paid_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     residents (rowid) {
         rowid -> Integer,
@@ -49,6 +719,40 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    role_changes (rowid) {
+        rowid -> Integer,
+        tg_id -> BigInt,
+        old_role -> Text,
+        new_role -> Text,
+        changed_at -> Timestamp,
+        handled_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    service_status (name) {
+        name -> Text,
+        up -> Bool,
+        since -> Timestamp,
+        checked_at -> Timestamp,
+        error -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    spam_suspects (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        user_id -> BigInt,
+        score -> Integer,
+        reasons -> Text,
+        status -> Text,
+        reviewed_by -> Nullable<BigInt>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     tg_chat_topics (chat_id, topic_id) {
         chat_id -> BigInt,
@@ -72,6 +776,17 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    tg_user_names (rowid) {
+        rowid -> Integer,
+        user_id -> BigInt,
+        username -> Nullable<Text>,
+        first_name -> Text,
+        last_name -> Nullable<Text>,
+        replaced_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     tg_users (id) {
         id -> BigInt,
@@ -90,6 +805,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    tracked_poll_votes (rowid) {
+        rowid -> Integer,
+        tg_poll_id -> Text,
+        user_id -> BigInt,
+        option_ids -> Text,
+        voted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     tracked_polls (tg_poll_id) {
         tg_poll_id -> Text,
@@ -97,6 +822,23 @@ diesel::table! {
         info_chat_id -> BigInt,
         info_message_id -> Integer,
         voted_users -> Text,
+        poll_message_id -> Nullable<Integer>,
+        closed_at -> Nullable<Timestamp>,
+        options -> Nullable<Text>,
+        quorum_reached_at -> Nullable<Timestamp>,
+        private -> Bool,
+    }
+}
+
+diesel::table! {
+    undo_stack (rowid) {
+        rowid -> Integer,
+        chat_id -> BigInt,
+        user_id -> BigInt,
+        description -> Text,
+        actions -> Text,
+        created_at -> Timestamp,
+        undone_at -> Nullable<Timestamp>,
     }
 }
 
@@ -107,16 +849,125 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    user_preferences (user_id, name) {
+        user_id -> BigInt,
+        name -> Text,
+        value -> Text,
+    }
+}
+
+diesel::table! {
+    user_roles (user_id, role) {
+        user_id -> BigInt,
+        role -> Text,
+        granted_by -> BigInt,
+        granted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    vpn_peers (rowid) {
+        rowid -> Integer,
+        user_id -> BigInt,
+        address -> Text,
+        public_key -> Text,
+        router_id -> Text,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    wiki_embeddings (path, chunk) {
+        path -> Text,
+        chunk -> Integer,
+        content -> Text,
+        embedding -> Binary,
+    }
+}
+
 diesel::allow_tables_to_appear_in_same_query!(
+    action_items,
+    activity_feed,
+    archived_links,
+    audit_log,
+    backup_media,
+    backup_messages,
+    ballot_tallies,
+    ballot_voters,
+    ballots,
+    bandwidth_counters,
+    bandwidth_usage,
+    bookings,
     borrowed_items,
+    broadcast_deliveries,
+    broadcasts,
+    chores,
+    countdowns,
     dashboard_messages,
+    donations,
+    door_credentials,
+    duty_assignments,
+    expiring_messages,
+    faq_entries,
+    feature_flags,
+    feed_entries,
+    feeds,
+    follow_mutes,
+    follows,
+    fridge_items,
+    frontdesk_inquiries,
+    frontdesk_threads,
+    guest_vouchers,
+    incident_updates,
+    incidents,
+    inventory_intakes,
+    inventory_items,
+    invite_link_joins,
+    invite_links,
+    karma_thanks,
+    kv_entries,
+    mail_messages,
+    meeting_notes,
+    meetings,
+    member_intros,
+    message_ttl_policies,
+    moderation_cases,
     needed_items,
+    network_devices,
+    oidc_link_codes,
+    oidc_links,
     options,
+    outbox,
+    packages,
+    pending_approvals,
+    poll_announcements,
+    presence_log,
+    processed_updates,
+    project_log,
+    project_members,
+    projects,
+    proposal_options,
+    proposals,
+    ranked_ballots,
+    ranked_votes,
+    reimbursements,
     residents,
+    role_changes,
+    service_status,
+    spam_suspects,
     tg_chat_topics,
     tg_chats,
+    tg_user_names,
     tg_users,
     tg_users_in_chats,
+    tracked_poll_votes,
     tracked_polls,
+    undo_stack,
     user_macs,
+    user_preferences,
+    user_roles,
+    vpn_peers,
+    wiki_embeddings,
 );
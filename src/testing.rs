@@ -0,0 +1,279 @@
+//! Test harness for end-to-end handler tests.
+//!
+//! [`TestBot`] runs update handlers against an in-memory database with all
+//! migrations applied and a [`MockTelegram`] Bot API server.  Updates are
+//! built from JSON with [`message`], [`callback`], etc.
+
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use diesel::connection::SimpleConnection as _;
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use serde_json::{json, Value};
+use teloxide::types::{Me, Update, UpdateKind};
+use teloxide::Bot;
+
+use crate::cache::DbCache;
+use crate::common::{BotEnv, UpdateHandler};
+use crate::config::Config;
+use crate::cooldowns::Cooldowns;
+use crate::db::DbUserId;
+use crate::health::Health;
+use crate::mock_telegram::{
+    chat_json, me_json, message_json, poll_json, MockTelegram,
+};
+use crate::{events, models, outbox, schema};
+
+/// A bot connected to [`MockTelegram`], with an in-memory database.
+pub struct TestBot {
+    pub env: Arc<BotEnv>,
+    pub bot: Bot,
+    pub telegram: MockTelegram,
+}
+
+impl TestBot {
+    /// Start with `config.example.yaml` as the config, see [`with_config`].
+    ///
+    /// [`with_config`]: Self::with_config
+    pub fn new() -> Self {
+        Self::with_config(|_| ())
+    }
+
+    /// Start with `config.example.yaml` as the config, changed by `edit`.
+    /// Approvals are disabled, so that admin commands run at once.
+    pub fn with_config(edit: impl FnOnce(&mut Config)) -> Self {
+        let mut config: Config =
+            serde_yaml::from_str(include_str!("../config.example.yaml"))
+                .expect("Failed to parse config.example.yaml");
+        config.telegram.approvals = None;
+        edit(&mut config);
+        let telegram = MockTelegram::start();
+        let bot = Bot::new(&config.telegram.token).set_api_url(telegram.url());
+        let env = Arc::new(BotEnv {
+            conn: Mutex::new(memory_db()),
+            config: Arc::new(config),
+            config_path: "config.example.yaml".into(),
+            instance: "test".to_string(),
+            db_path: ":memory:".into(),
+            reqwest_client: reqwest::Client::new(),
+            openai_client: async_openai::Client::new(),
+            cache: DbCache::default(),
+            cooldowns: Cooldowns::default(),
+            health: Health::new("test"),
+            events: events::Bus::default(),
+            outbox: outbox::Wake::default(),
+        });
+        Self { env, bot, telegram }
+    }
+
+    /// Add a user who is a resident since the start of the epoch.
+    pub fn add_resident(&self, id: u64, username: &str, first_name: &str) {
+        let id = teloxide::types::UserId(id);
+        self.env
+            .transaction(|conn| {
+                diesel::insert_into(schema::tg_users::table)
+                    .values(models::NewTgUser {
+                        id: id.into(),
+                        username: Some(username),
+                        first_name,
+                        last_name: None,
+                    })
+                    .execute(conn)?;
+                diesel::insert_into(schema::residents::table)
+                    .values((
+                        schema::residents::tg_id.eq(DbUserId::from(id)),
+                        schema::residents::begin_date
+                            .eq(chrono::NaiveDateTime::default()),
+                    ))
+                    .execute(conn)
+            })
+            .expect("Failed to add resident");
+        self.env.cache.residents.invalidate();
+    }
+
+    /// Run the update through the handler, panicking if the handler fails
+    /// or does not handle the update.
+    pub async fn dispatch(&self, handler: &UpdateHandler, update: Value) {
+        self.try_dispatch(handler, update)
+            .await
+            .unwrap_or_else(|e| panic!("Handler failed: {e:#}"));
+    }
+
+    /// Run the update through the handler, panicking if the handler does not
+    /// handle the update.  The message or the callback query of the update is
+    /// also provided to the handler.
+    pub async fn try_dispatch(
+        &self,
+        handler: &UpdateHandler,
+        update: Value,
+    ) -> anyhow::Result<()> {
+        let update: Update =
+            serde_json::from_value(update).expect("Invalid update");
+        let me: Me = serde_json::from_value(me_json()).unwrap();
+        let kind = update.kind.clone();
+        let mut deps =
+            dptree::deps![Arc::clone(&self.env), self.bot.clone(), me, update];
+        // As `Update::filter_*` do, so that command and callback handlers can
+        // be dispatched without them.
+        match kind {
+            UpdateKind::Message(msg) => deps.insert(msg),
+            UpdateKind::CallbackQuery(callback) => deps.insert(callback),
+            _ => (),
+        }
+        match handler.dispatch(deps).await {
+            ControlFlow::Break(result) => result,
+            ControlFlow::Continue(_) => panic!("Update was not handled"),
+        }
+    }
+}
+
+/// An in-memory database with all migrations applied.
+pub fn memory_db() -> SqliteConnection {
+    let mut conn = SqliteConnection::establish(":memory:").unwrap();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    let migrations =
+        std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).sorted();
+    for migration in migrations {
+        let sql = std::fs::read_to_string(migration.join("up.sql")).unwrap();
+        conn.batch_execute(&sql).unwrap_or_else(|e| {
+            panic!("Migration {} failed: {e}", migration.display())
+        });
+    }
+    conn
+}
+
+static LAST_UPDATE_ID: AtomicI32 = AtomicI32::new(0);
+static LAST_MESSAGE_ID: AtomicI32 = AtomicI32::new(0);
+
+fn next_message_id() -> i32 {
+    LAST_MESSAGE_ID.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+fn update_json(kind: &str, value: Value) -> Value {
+    let id = LAST_UPDATE_ID.fetch_add(1, Ordering::Relaxed) + 1;
+    let mut update = json!({ "update_id": id });
+    update[kind] = value;
+    update
+}
+
+pub fn user_json(id: u64, first_name: &str) -> Value {
+    json!({ "id": id, "is_bot": false, "first_name": first_name })
+}
+
+/// An update with a text message from the user.
+pub fn message(
+    chat_id: i64,
+    thread_id: Option<i64>,
+    from: &Value,
+    text: &str,
+) -> Value {
+    let mut msg = message_json(chat_id, next_message_id(), thread_id, from);
+    msg["text"] = text.into();
+    update_json("message", msg)
+}
+
+/// An update with a new poll from the user.
+pub fn poll_message(
+    chat_id: i64,
+    from: &Value,
+    question: &str,
+    options: &[&str],
+) -> Value {
+    let mut msg = message_json(chat_id, next_message_id(), None, from);
+    msg.as_object_mut().unwrap().remove("text");
+    msg["poll"] = poll_json("user-poll", question, options);
+    update_json("message", msg)
+}
+
+/// An update with a press of an inline button under `message`, which is a
+/// message as returned by [`MockTelegram`].
+pub fn callback(from: &Value, message: &Value, data: &str) -> Value {
+    update_json(
+        "callback_query",
+        json!({
+            "id": "callback",
+            "from": from,
+            "message": message,
+            "chat_instance": "instance",
+            "data": data,
+        }),
+    )
+}
+
+/// An update with a request of the user to join the chat.
+pub fn chat_join_request(chat_id: i64, from: &Value) -> Value {
+    update_json(
+        "chat_join_request",
+        json!({
+            "chat": chat_json(chat_id),
+            "from": from,
+            "user_chat_id": from["id"],
+            "date": 0,
+        }),
+    )
+}
+
+/// An update with the user joining the chat, optionally with the invite link.
+pub fn chat_member(
+    chat_id: i64,
+    user: &Value,
+    invite_link: Option<&str>,
+) -> Value {
+    let mut update = json!({
+        "chat": chat_json(chat_id),
+        "from": user,
+        "date": 0,
+        "old_chat_member": { "user": user, "status": "left" },
+        "new_chat_member": { "user": user, "status": "member" },
+    });
+    if let Some(invite_link) = invite_link {
+        update["invite_link"] = json!({
+            "invite_link": invite_link,
+            "creator": me_json(),
+            "creates_join_request": false,
+            "is_primary": false,
+            "is_revoked": false,
+        });
+    }
+    update_json("chat_member", update)
+}
+
+/// An update with the new state of a poll sent by the bot.
+pub fn poll(poll_id: &str, is_closed: bool) -> Value {
+    let mut poll = poll_json(poll_id, "Poll", &["Yes", "No"]);
+    poll["is_closed"] = is_closed.into();
+    update_json("poll", poll)
+}
+
+/// An update with a vote in the poll.
+pub fn poll_answer(poll_id: &str, from: &Value, option_ids: &[u8]) -> Value {
+    update_json(
+        "poll_answer",
+        json!({ "poll_id": poll_id, "user": from, "option_ids": option_ids }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::requests::Requester as _;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scripted_responses() {
+        let t = TestBot::new();
+        let mut me = me_json();
+        me["first_name"] = "Scripted".into();
+        t.telegram.respond("getMe", me);
+        t.telegram.fail("getMe", "Unauthorized");
+
+        assert_eq!(t.bot.get_me().await.unwrap().first_name, "Scripted");
+        assert!(t.bot.get_me().await.is_err());
+        assert_eq!(t.bot.get_me().await.unwrap().first_name, "Botka");
+        assert_eq!(t.telegram.calls("GETME").len(), 3);
+        assert!(t.telegram.results("getMe")[1].is_null());
+    }
+}
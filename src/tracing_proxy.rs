@@ -2,6 +2,7 @@ use std::convert::Infallible;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -11,13 +12,17 @@ use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server, Uri};
 use reqwest::Client;
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 
+use crate::common::BotEnv;
 use crate::utils::parse_tgapi_method;
 
 struct Proxy {
+    env: Arc<BotEnv>,
     client: Client,
     log_file: Mutex<File>,
+    reactions: mpsc::UnboundedSender<serde_json::Value>,
+    sent_messages: mpsc::UnboundedSender<serde_json::Value>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -28,9 +33,22 @@ struct GetUpdatesResponse {
 }
 
 /// Start a proxy server that forwards requests to the Telegram API and logs
-/// getUpdates responses, as well as all other requests and responses.
-/// Returns the URL of the proxy server.
-pub async fn start() -> Result<reqwest::Url> {
+/// getUpdates responses, as well as all other requests and responses, to
+/// `trace_file`.  Returns the URL of the proxy server.  The availability of
+/// the API is reported to the health registry of `env`.
+///
+/// teloxide can't parse `message_reaction` updates yet, so their payloads
+/// are sent to `reactions` instead.  Messages sent by the bot, as returned by
+/// successful requests, are sent to `sent_messages`.
+///
+/// Votes in secret ballots are redacted from the log, see
+/// [`crate::modules::ballots::redact_update`].
+pub async fn start(
+    env: Arc<BotEnv>,
+    trace_file: &Path,
+    reactions: mpsc::UnboundedSender<serde_json::Value>,
+    sent_messages: mpsc::UnboundedSender<serde_json::Value>,
+) -> Result<reqwest::Url> {
     // Make client from teloxide::net::default_reqwest_settings, plus 3 seconds.
     let client = reqwest::Client::builder()
         .connect_timeout(Duration::from_secs(5 + 3))
@@ -39,13 +57,11 @@ pub async fn start() -> Result<reqwest::Url> {
         .build()?;
 
     let log_file = Mutex::new(
-        OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(crate::TRACE_FILENAME)?,
+        OpenOptions::new().create(true).append(true).open(trace_file)?,
     );
 
-    let proxy = Arc::new(Proxy { client, log_file });
+    let proxy =
+        Arc::new(Proxy { env, client, log_file, reactions, sent_messages });
 
     let listener =
         TcpListener::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).await?;
@@ -133,13 +149,48 @@ async fn handle_request(
         if let Ok(response_body) =
             serde_json::from_slice::<GetUpdatesResponse>(&out_response_body)
         {
-            crate::metrics::update_service("telegram", true);
-            append_values_to_log_file(&proxy, response_body.result.iter())
-                .await;
+            crate::metrics::update_service(&proxy.env.health, "telegram", true);
+            for update in &response_body.result {
+                if let Some(reaction) = update.get("message_reaction") {
+                    // Only fails if the receiver is gone, e.g. in passive
+                    // mode.
+                    proxy.reactions.send(reaction.clone()).ok();
+                }
+            }
+            let updates = response_body.result.into_iter().map(|mut update| {
+                crate::modules::ballots::redact_update(&mut update);
+                update
+            });
+            append_values_to_log_file(&proxy, updates).await;
         } else {
-            crate::metrics::update_service("telegram", false);
+            crate::metrics::update_service(
+                &proxy.env.health,
+                "telegram",
+                false,
+            );
         }
     } else {
+        let out_response_json =
+            serde_json::from_slice::<serde_json::Value>(&out_response_body)
+                .ok();
+        if let Some(result) = out_response_json.as_ref().map(|r| &r["result"]) {
+            // `result` is a message for `sendMessage` and the like, or an
+            // array of messages for `sendMediaGroup`.
+            let messages = match result {
+                serde_json::Value::Array(values) => values.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            for message in messages {
+                if message.get("message_id").is_some()
+                    && message.get("chat").is_some()
+                {
+                    // Only fails if the receiver is gone, e.g. in passive
+                    // mode.
+                    proxy.sent_messages.send(message.clone()).ok();
+                }
+            }
+        }
+
         // Log request and response
         append_values_to_log_file(
             &proxy,
@@ -149,9 +200,7 @@ async fn handle_request(
                 "method": method,
                 "status": out_response_status.as_u16(),
                 "request": in_request_body_json,
-                "response": serde_json::from_slice::<serde_json::Value>(
-                    out_response_body.as_ref(),
-                ).ok(),
+                "response": out_response_json,
             })),
         )
         .await;
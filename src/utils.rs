@@ -3,23 +3,34 @@
 mod diesel_json;
 mod dptree_ext;
 mod format_to;
+pub mod home_assistant;
+pub mod html;
 mod log_error;
+pub mod mikrotik;
+mod paginator;
 mod parsers;
 mod replace_urls;
 mod teloxide;
 mod wikijs;
 
-pub use diesel_json::Sqlizer;
+pub(crate) use diesel_json::sqlizer_type;
+pub use diesel_json::{migrate_column, Sqlizer, SqlizerType};
 pub use dptree_ext::HandlerExt;
 pub(crate) use format_to::format_to;
 pub use log_error::ResultExt;
+pub use paginator::Paginator;
 pub use parsers::{
-    deserealize_duration, parse_tg_thread_link, parse_tgapi_method,
+    deserealize_duration, parse_duration, parse_tg_message_link,
+    parse_tg_thread_link, parse_tgapi_method,
 };
 pub use replace_urls::replace_urls_with_titles;
-pub use wikijs::{get_wikijs_page, get_wikijs_updates, WikiJsUpdateState};
+pub use wikijs::{
+    get_wikijs_page, get_wikijs_page_with_id, get_wikijs_updates,
+    search_wikijs_pages, update_wikijs_page, WikiJsPage, WikiJsSearchResult,
+    WikiJsUpdateState,
+};
 
 pub use self::teloxide::{
-    write_message_link, BotExt, ChatIdExt, MessageExt, ThreadIdPair, UserExt,
-    GENERAL_THREAD_ID,
+    remove_button_row, write_message_link, BotExt, ChatIdExt, MessageExt,
+    ThreadIdPair, UserExt, GENERAL_THREAD_ID,
 };
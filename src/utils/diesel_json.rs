@@ -1,5 +1,12 @@
 //! Store any serde-serializable type as JSON string in diesel database.
 //! TODO: use <https://github.com/PPakalns/diesel_json>
+//!
+//! Values are wrapped in an envelope with a type tag and a schema version:
+//! `{"type":"strings","v":1,"data":[...]}`.  When the version of a stored
+//! value is older than [`SqlizerType::VERSION`], it is upgraded with
+//! [`SqlizerType::migrate`] on read.  Rows written before the envelope was
+//! introduced are treated as version 1.  The `migrate-blobs` CLI command
+//! rewrites old rows with [`migrate_column`].
 
 use std::fmt::Debug;
 use std::ops::Deref;
@@ -7,17 +14,57 @@ use std::ops::Deref;
 use diesel::backend::Backend;
 use diesel::deserialize::FromSql;
 use diesel::serialize::ToSql;
-use diesel::{AsExpression, FromSqlRow};
+use diesel::sql_types::{BigInt, Text};
+use diesel::{
+    sql_query, AsExpression, FromSqlRow, QueryableByName, RunQueryDsl,
+    SqliteConnection,
+};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A type that can be stored in a [`Sqlizer`].  Implement it with
+/// [`sqlizer_type!`], or manually when [`migrate`](Self::migrate) is needed.
+pub trait SqlizerType: Serialize + DeserializeOwned + Debug {
+    /// Unique tag stored in the envelope.
+    const TAG: &'static str;
+    /// Current schema version.  Bump it when the serialized form changes,
+    /// and handle the previous version in [`migrate`](Self::migrate).
+    const VERSION: u32;
+
+    /// Upgrade a value from `version` to `version + 1`.
+    fn migrate(version: u32, _value: Value) -> Result<Value, String> {
+        Err(format!("no migration from version {version}"))
+    }
+}
+
+/// Implement [`SqlizerType`] for a type without migrations.
+macro_rules! sqlizer_type {
+    ($type:ty, $tag:literal, $version:literal) => {
+        impl crate::utils::SqlizerType for $type {
+            const TAG: &'static str = $tag;
+            const VERSION: u32 = $version;
+        }
+    };
+}
+pub(crate) use sqlizer_type;
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    #[serde(rename = "type")]
+    tag: String,
+    v: u32,
+    data: Value,
+}
 
 /// A wrapper type for any serde-serializable type to store in diesel database.
 #[derive(Clone, Debug, FromSqlRow, AsExpression)]
 #[diesel(sql_type = diesel::sql_types::Text)]
 pub struct Sqlizer<T>(T, String);
 
-impl<T: Serialize + Debug> Sqlizer<T> {
+impl<T: SqlizerType> Sqlizer<T> {
     pub fn new(t: T) -> Result<Self, serde_json::Error> {
-        let s = serde_json::to_string(&t)?;
+        let s = encode(&t)?;
         Ok(Self(t, s))
     }
     pub fn map(
@@ -25,9 +72,61 @@ impl<T: Serialize + Debug> Sqlizer<T> {
         f: impl FnOnce(&T) -> T,
     ) -> Result<Self, serde_json::Error> {
         let t = f(&self.0);
-        let s = serde_json::to_string(&t)?;
+        let s = encode(&t)?;
         Ok(Self(t, s))
     }
+
+    /// Decode a stored value, upgrading it to the current version.  The
+    /// result holds the re-encoded value, which differs from `s` if the
+    /// stored value was old.
+    pub fn decode(s: &str) -> Result<Self, String> {
+        let value: Value =
+            serde_json::from_str(s).map_err(|e| e.to_string())?;
+        let (mut version, mut data) = if is_envelope(&value) {
+            let envelope: Envelope =
+                serde_json::from_value(value).map_err(|e| e.to_string())?;
+            if envelope.tag != T::TAG {
+                return Err(format!(
+                    "expected type {}, found {}",
+                    T::TAG,
+                    envelope.tag,
+                ));
+            }
+            (envelope.v, envelope.data)
+        } else {
+            (1, value)
+        };
+        if version > T::VERSION {
+            return Err(format!(
+                "{} version {version} is newer than supported {}",
+                T::TAG,
+                T::VERSION,
+            ));
+        }
+        while version < T::VERSION {
+            data = T::migrate(version, data)?;
+            version += 1;
+        }
+        let t: T = serde_json::from_value(data).map_err(|e| e.to_string())?;
+        Self::new(t).map_err(|e| e.to_string())
+    }
+}
+
+fn encode<T: SqlizerType>(t: &T) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&Envelope {
+        tag: T::TAG.to_string(),
+        v: T::VERSION,
+        data: serde_json::to_value(t)?,
+    })
+}
+
+fn is_envelope(value: &Value) -> bool {
+    value.as_object().is_some_and(|o| {
+        o.len() == 3
+            && o.get("type").is_some_and(Value::is_string)
+            && o.get("v").is_some_and(Value::is_u64)
+            && o.contains_key("data")
+    })
 }
 
 impl<T> Deref for Sqlizer<T> {
@@ -60,12 +159,126 @@ where
 impl<DB, T> FromSql<diesel::sql_types::Text, DB> for Sqlizer<T>
 where
     DB: diesel::backend::Backend,
-    T: for<'de> Deserialize<'de> + Debug,
+    T: SqlizerType,
     String: FromSql<diesel::sql_types::Text, DB>,
 {
     fn from_sql(bytes: DB::RawValue<'_>) -> diesel::deserialize::Result<Self> {
         let s = String::from_sql(bytes)?;
-        let t = serde_json::from_str(&s)?;
-        Ok(Self(t, s))
+        Ok(Self::decode(&s)?)
+    }
+}
+
+#[derive(QueryableByName)]
+struct BlobRow {
+    #[diesel(sql_type = BigInt)]
+    rowid: i64,
+    #[diesel(sql_type = Text)]
+    value: String,
+}
+
+/// Statistics of a [`migrate_column`] run.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MigrateStats {
+    pub total: usize,
+    pub rewritten: usize,
+    pub failed: usize,
+}
+
+/// Rewrite values of `table.column` that are not in the current format.
+/// Values that fail to decode are logged and left as is.
+pub fn migrate_column<T: SqlizerType>(
+    conn: &mut SqliteConnection,
+    table: &str,
+    column: &str,
+) -> diesel::QueryResult<MigrateStats> {
+    let rows: Vec<BlobRow> = sql_query(format!(
+        "SELECT rowid, {column} AS value FROM {table} \
+         WHERE {column} IS NOT NULL"
+    ))
+    .load(conn)?;
+    let mut stats = MigrateStats { total: rows.len(), ..Default::default() };
+    for row in rows {
+        match Sqlizer::<T>::decode(&row.value) {
+            Ok(decoded) if decoded.1 != row.value => {
+                sql_query(format!(
+                    "UPDATE {table} SET {column} = ? WHERE rowid = ?"
+                ))
+                .bind::<Text, _>(&decoded.1)
+                .bind::<BigInt, _>(row.rowid)
+                .execute(conn)?;
+                stats.rewritten += 1;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("{table}.{column} rowid={}: {e}", row.rowid);
+                stats.failed += 1;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Item {
+        name: String,
+        count: u32,
+    }
+
+    impl SqlizerType for Item {
+        const TAG: &'static str = "test_item";
+        const VERSION: u32 = 3;
+
+        fn migrate(version: u32, value: Value) -> Result<Value, String> {
+            match version {
+                // v1 was a bare string.
+                1 => Ok(serde_json::json!({ "title": value })),
+                // v2 had `title` instead of `name`, without `count`.
+                2 => Ok(serde_json::json!({
+                    "name": value["title"],
+                    "count": 1,
+                })),
+                _ => Err(format!("no migration from version {version}")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let item = Item { name: "drill".to_string(), count: 2 };
+        let s = Sqlizer::new(item).unwrap();
+        assert!(s.1.starts_with(r#"{"type":"test_item","v":3,"data":{"#));
+        let decoded = Sqlizer::<Item>::decode(&s.1).unwrap();
+        assert_eq!(*decoded, *s);
+        assert_eq!(decoded.1, s.1);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let expected = Item { name: "drill".to_string(), count: 1 };
+        let legacy = Sqlizer::<Item>::decode(r#""drill""#).unwrap();
+        assert_eq!(*legacy, expected);
+        let v2 = Sqlizer::<Item>::decode(
+            r#"{"type":"test_item","v":2,"data":{"title":"drill"}}"#,
+        )
+        .unwrap();
+        assert_eq!(*v2, expected);
+        assert_eq!(v2.1, Sqlizer::new(expected).unwrap().1);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Sqlizer::<Item>::decode(
+            r#"{"type":"other","v":1,"data":"drill"}"#
+        )
+        .is_err());
+        assert!(Sqlizer::<Item>::decode(
+            r#"{"type":"test_item","v":4,"data":{}}"#
+        )
+        .is_err());
+        assert!(Sqlizer::<Item>::decode("not json").is_err());
     }
 }
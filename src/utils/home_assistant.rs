@@ -0,0 +1,82 @@
+//! Helpers to access the Home Assistant REST API.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::config::HomeAssistant;
+use crate::health::Health;
+
+/// A state change as returned by `/api/history/period`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct HistoryState {
+    pub entity_id: String,
+    pub state: String,
+    pub last_changed: DateTime<Utc>,
+}
+
+/// Call a Home Assistant service, e.g. `esphome.door_add_code`.
+pub async fn call_service(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &HomeAssistant,
+    service: &str,
+    data: &serde_json::Value,
+) -> Result<()> {
+    let (domain, service) = service
+        .split_once('.')
+        .ok_or_else(|| anyhow::anyhow!("Invalid service name: {service}"))?;
+    let result = client
+        .post(format!("https://{}/api/services/{domain}/{service}", conf.host))
+        .timeout(Duration::from_secs(10))
+        .bearer_auth(&conf.token)
+        .json(data)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+    crate::metrics::update_service(health, "home_assistant", result.is_ok());
+    result?;
+    Ok(())
+}
+
+/// Get the state history of the entities between `start` and `end`, keyed by
+/// entity ID.  The first state of each entity is the state at `start`.
+pub async fn get_history(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &HomeAssistant,
+    entity_ids: &[&str],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<HashMap<String, Vec<HistoryState>>> {
+    const FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+    let history = async {
+        client
+            .get(format!(
+                "https://{}/api/history/period/{}",
+                conf.host,
+                start.format(FORMAT),
+            ))
+            .query(&[
+                ("filter_entity_id", entity_ids.join(",")),
+                ("end_time", end.format(FORMAT).to_string()),
+                ("no_attributes", String::new()),
+            ])
+            .timeout(Duration::from_secs(30))
+            .bearer_auth(&conf.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<Vec<HistoryState>>>()
+            .await
+    }
+    .await;
+    crate::metrics::update_service(health, "home_assistant", history.is_ok());
+    Ok(history?
+        .into_iter()
+        .filter_map(|states| Some((states.first()?.entity_id.clone(), states)))
+        .collect())
+}
@@ -0,0 +1,225 @@
+//! Building blocks for messages with [`ParseMode::Html`].
+//!
+//! Every piece of text that comes from users or external services (names,
+//! chat titles, message texts, URLs) must pass through one of these helpers
+//! before being put into an HTML message, otherwise a stray `<` or `&` makes
+//! Telegram reject the whole message.
+//!
+//! [`ParseMode::Html`]: teloxide::types::ParseMode::Html
+
+use std::cmp::Reverse;
+
+use teloxide::types::{
+    Message, MessageEntity, MessageEntityKind, MessageEntityRef, User, UserId,
+};
+pub use teloxide::utils::html::{code_block, code_inline, escape};
+
+use crate::utils::format_to;
+
+/// Build a link.  Unlike [`teloxide::utils::html::link`], also escapes quotes
+/// in the URL, so it can't break out of the attribute.
+pub fn link(url: &str, text: &str) -> String {
+    format!("<a href=\"{}\">{}</a>", escape_attr(url), escape(text))
+}
+
+/// Build a mention of the user with the given text, works without a username.
+pub fn mention(user_id: UserId, text: &str) -> String {
+    link(&format!("tg://user?id={user_id}"), text)
+}
+
+/// Build a mention of the user with their full name.
+pub fn user_mention(user: &User) -> String {
+    mention(user.id, &user.full_name())
+}
+
+/// Render the text with its formatting entities, truncated to `max_chars`
+/// characters, as a block quote.
+pub fn quote(
+    text: &str,
+    entities: &[MessageEntity],
+    max_chars: usize,
+) -> String {
+    let len = text.char_indices().nth(max_chars).map_or(text.len(), |(i, _)| i);
+    let mut out = String::from("<blockquote>");
+    render(&mut out, text, entities, len);
+    if len < text.len() {
+        out.push('…');
+    }
+    out.push_str("</blockquote>");
+    out
+}
+
+/// Same as [`quote`], for the text or the caption of the message.
+pub fn quote_message(msg: &Message, max_chars: usize) -> Option<String> {
+    let (text, entities) = match msg.text() {
+        Some(text) => (text, msg.entities().unwrap_or_default()),
+        None => (msg.caption()?, msg.caption_entities().unwrap_or_default()),
+    };
+    Some(quote(text, entities, max_chars))
+}
+
+/// Strip the tags from HTML built with these helpers and unescape the text,
+/// e.g. for places where Telegram accepts only plain text.
+pub fn to_plain(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        rest =
+            rest[start..].find('>').map_or("", |end| &rest[start + end + 1..]);
+    }
+    text.push_str(rest);
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// Render the text with its formatting entities, e.g. to resend a message
+/// with additions.
+pub fn format_entities(text: &str, entities: &[MessageEntity]) -> String {
+    let mut out = String::new();
+    render(&mut out, text, entities, text.len());
+    out
+}
+
+/// Render the first `len` bytes of the text.  Entities are expected to be
+/// nested, as Telegram produces them; partially overlapping ones are cut at
+/// the end of the enclosing entity.
+fn render(
+    out: &mut String,
+    text: &str,
+    entities: &[MessageEntity],
+    len: usize,
+) {
+    let mut entities = MessageEntityRef::parse(text, entities);
+    entities.retain(|e| e.start() < len);
+    entities.sort_by_key(|e| (e.start(), Reverse(e.end())));
+
+    // Ends and closing tags of the entities being rendered.
+    let mut open: Vec<(usize, &str)> = Vec::new();
+    let mut pos = 0;
+    for entity in &entities {
+        let Some((open_tag, close_tag)) = tags(entity.kind()) else {
+            continue;
+        };
+        close(out, text, &mut pos, &mut open, entity.start());
+        out.push_str(&escape(&text[pos..entity.start()]));
+        pos = entity.start();
+        out.push_str(&open_tag);
+        let end = open.last().map_or(len, |&(end, _)| end).min(entity.end());
+        open.push((end, close_tag));
+    }
+    close(out, text, &mut pos, &mut open, len);
+    out.push_str(&escape(&text[pos..len]));
+}
+
+/// Close all open entities ending at or before `until`.
+fn close(
+    out: &mut String,
+    text: &str,
+    pos: &mut usize,
+    open: &mut Vec<(usize, &str)>,
+    until: usize,
+) {
+    while let Some(&(end, close_tag)) = open.last() {
+        if end > until {
+            break;
+        }
+        out.push_str(&escape(&text[*pos..end]));
+        *pos = end;
+        out.push_str(close_tag);
+        open.pop();
+    }
+}
+
+/// Opening and closing tags for the entity, or `None` if it doesn't need
+/// markup, e.g. URLs and hashtags are detected by Telegram on its own.
+fn tags(kind: &MessageEntityKind) -> Option<(String, &'static str)> {
+    let tags = match kind {
+        MessageEntityKind::Bold => ("<b>".to_string(), "</b>"),
+        MessageEntityKind::Italic => ("<i>".to_string(), "</i>"),
+        MessageEntityKind::Underline => ("<u>".to_string(), "</u>"),
+        MessageEntityKind::Strikethrough => ("<s>".to_string(), "</s>"),
+        MessageEntityKind::Spoiler => {
+            ("<tg-spoiler>".to_string(), "</tg-spoiler>")
+        }
+        MessageEntityKind::Code => ("<code>".to_string(), "</code>"),
+        MessageEntityKind::Pre { language: None } => {
+            ("<pre>".to_string(), "</pre>")
+        }
+        MessageEntityKind::Pre { language: Some(language) } => {
+            let mut tag = String::from("<pre><code class=\"language-");
+            format_to!(tag, "{}\">", escape_attr(language));
+            (tag, "</code></pre>")
+        }
+        MessageEntityKind::TextLink { url } => {
+            (format!("<a href=\"{}\">", escape_attr(url.as_str())), "</a>")
+        }
+        MessageEntityKind::TextMention { user } => {
+            (format!("<a href=\"tg://user?id={}\">", user.id), "</a>")
+        }
+        _ => return None,
+    };
+    Some(tags)
+}
+
+fn escape_attr(s: &str) -> String {
+    escape(s).replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(
+        kind: MessageEntityKind,
+        offset: usize,
+        length: usize,
+    ) -> MessageEntity {
+        MessageEntity { kind, offset, length }
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            mention(UserId(1), "<Alice> & Bob"),
+            "<a href=\"tg://user?id=1\">&lt;Alice&gt; &amp; Bob</a>",
+        );
+        assert_eq!(
+            link("https://example.com/?a=\"b\"&c", "x"),
+            "<a href=\"https://example.com/?a=&quot;b&quot;&amp;c\">x</a>",
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        // "Hi <b>" with "Hi" bold and the whole text italic; the emoji takes
+        // two UTF-16 code units.
+        let text = "Hi <b> 👋 there";
+        let entities = [
+            entity(MessageEntityKind::Italic, 0, 9),
+            entity(MessageEntityKind::Bold, 0, 2),
+            entity(MessageEntityKind::Url, 3, 3),
+            entity(MessageEntityKind::Code, 10, 5),
+        ];
+        assert_eq!(
+            format_entities(text, &entities),
+            "<i><b>Hi</b> &lt;b&gt; 👋</i> <code>there</code>",
+        );
+        assert_eq!(
+            quote(text, &entities, 9),
+            "<blockquote><i><b>Hi</b> &lt;b&gt; 👋</i> …</blockquote>",
+        );
+        assert_eq!(quote("a<", &[], 10), "<blockquote>a&lt;</blockquote>");
+    }
+
+    #[test]
+    fn test_to_plain() {
+        assert_eq!(
+            to_plain("use <code>/book a &lt;b&gt; &amp;amp;</code>."),
+            "use /book a <b> &amp;.",
+        );
+        assert_eq!(to_plain(&link("https://a/?b=\"", "x")), "x");
+    }
+}
@@ -0,0 +1,240 @@
+//! Helpers to access the `MikroTik` REST API.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer};
+
+use crate::config::Microtik;
+use crate::health::Health;
+
+/// A DHCP lease as returned by `/rest/ip/dhcp-server/lease/print`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Lease {
+    pub mac_address: String,
+    #[serde(deserialize_with = "super::deserealize_duration")]
+    pub last_seen: Duration,
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub host_name: Option<String>,
+    /// Name of the DHCP server, usually one per VLAN.
+    #[serde(default)]
+    pub server: Option<String>,
+}
+
+/// Leases seen within this interval are considered active.
+pub const ACTIVE_LEASE_INTERVAL: Duration = Duration::from_secs(11 * 60);
+
+/// Get the list of DHCP leases from the router.
+pub async fn get_dhcp_leases(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &Microtik,
+) -> Result<Vec<Lease>> {
+    let leases = async {
+        client
+            .post(format!(
+                "https://{}/rest/ip/dhcp-server/lease/print",
+                conf.host
+            ))
+            .timeout(Duration::from_secs(5))
+            .basic_auth(&conf.username, Some(&conf.password))
+            .json(&serde_json::json!({
+                ".proplist": [
+                    "mac-address",
+                    "last-seen",
+                    "address",
+                    "host-name",
+                    "server",
+                ]
+            }))
+            .send()
+            .await?
+            .json::<Vec<Lease>>()
+            .await
+    }
+    .await;
+    crate::metrics::update_service(health, "mikrotik", leases.is_ok());
+    Ok(leases?)
+}
+
+/// Get MAC addresses of the devices seen recently.
+pub async fn get_active_macs(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &Microtik,
+) -> Result<Vec<String>> {
+    Ok(get_dhcp_leases(health, client, conf)
+        .await?
+        .into_iter()
+        .filter(|l| l.last_seen < ACTIVE_LEASE_INTERVAL)
+        .map(|l| l.mac_address)
+        .collect())
+}
+
+/// Traffic counters of a device as returned by
+/// `/rest/ip/kid-control/device/print`.  The counters are cumulative and
+/// reset when the router reboots.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeviceTraffic {
+    pub mac_address: String,
+    #[serde(deserialize_with = "deserialize_counter")]
+    pub bytes_down: u64,
+    #[serde(deserialize_with = "deserialize_counter")]
+    pub bytes_up: u64,
+}
+
+/// The REST API returns all numbers as strings.
+fn deserialize_counter<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+/// Get traffic counters of the devices tracked by kid control.
+pub async fn get_device_traffic(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &Microtik,
+) -> Result<Vec<DeviceTraffic>> {
+    let devices = async {
+        client
+            .post(format!(
+                "https://{}/rest/ip/kid-control/device/print",
+                conf.host
+            ))
+            .timeout(Duration::from_secs(5))
+            .basic_auth(&conf.username, Some(&conf.password))
+            .json(&serde_json::json!({
+                ".proplist": ["mac-address", "bytes-down", "bytes-up"]
+            }))
+            .send()
+            .await?
+            .json::<Vec<DeviceTraffic>>()
+            .await
+    }
+    .await;
+    crate::metrics::update_service(health, "mikrotik", devices.is_ok());
+    Ok(devices?)
+}
+
+/// A record created with the REST API.
+#[derive(Deserialize, Debug)]
+struct Created {
+    #[serde(rename = ".id")]
+    id: String,
+}
+
+/// Create a record under the REST API `path`, e.g. `ip/hotspot/user`.
+/// Returns the router ID of the record.
+async fn add_record(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &Microtik,
+    path: &str,
+    record: &serde_json::Value,
+) -> Result<String> {
+    let created = client
+        .put(format!("https://{}/rest/{path}", conf.host))
+        .timeout(Duration::from_secs(5))
+        .basic_auth(&conf.username, Some(&conf.password))
+        .json(record)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+    crate::metrics::update_service(health, "mikrotik", created.is_ok());
+    Ok(created?.json::<Created>().await?.id)
+}
+
+/// Remove a record under the REST API `path` by its router ID.
+async fn remove_record(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &Microtik,
+    path: &str,
+    id: &str,
+) -> Result<()> {
+    let result = client
+        .delete(format!("https://{}/rest/{path}/{id}", conf.host))
+        .timeout(Duration::from_secs(5))
+        .basic_auth(&conf.username, Some(&conf.password))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+    crate::metrics::update_service(health, "mikrotik", result.is_ok());
+    result?;
+    Ok(())
+}
+
+/// Add a WireGuard peer.  Returns the router ID of the peer.
+pub async fn add_wireguard_peer(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &Microtik,
+    interface: &str,
+    public_key: &str,
+    allowed_address: &str,
+    comment: &str,
+) -> Result<String> {
+    let peer = serde_json::json!({
+        "interface": interface,
+        "public-key": public_key,
+        "allowed-address": allowed_address,
+        "comment": comment,
+    });
+    add_record(health, client, conf, "interface/wireguard/peers", &peer).await
+}
+
+/// Remove a WireGuard peer by its router ID.
+pub async fn remove_wireguard_peer(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &Microtik,
+    id: &str,
+) -> Result<()> {
+    remove_record(health, client, conf, "interface/wireguard/peers", id).await
+}
+
+/// A hotspot user to be created with [`add_hotspot_user`].
+#[derive(Debug)]
+pub struct HotspotUser<'a> {
+    pub server: &'a str,
+    pub profile: &'a str,
+    pub name: &'a str,
+    pub password: &'a str,
+    pub limit_uptime: Duration,
+    pub comment: &'a str,
+}
+
+/// Add a hotspot user.  Returns the router ID of the user.
+pub async fn add_hotspot_user(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &Microtik,
+    user: &HotspotUser<'_>,
+) -> Result<String> {
+    let user = serde_json::json!({
+        "server": user.server,
+        "profile": user.profile,
+        "name": user.name,
+        "password": user.password,
+        "limit-uptime": format!("{}s", user.limit_uptime.as_secs()),
+        "comment": user.comment,
+    });
+    add_record(health, client, conf, "ip/hotspot/user", &user).await
+}
+
+/// Remove a hotspot user by its router ID.
+pub async fn remove_hotspot_user(
+    health: &Health,
+    client: &reqwest::Client,
+    conf: &Microtik,
+    id: &str,
+) -> Result<()> {
+    remove_record(health, client, conf, "ip/hotspot/user", id).await
+}
@@ -0,0 +1,138 @@
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup,
+    Message,
+};
+
+/// Splits long lists into pages with a row of navigation buttons, so
+/// messages stay within the Telegram limit of 4096 characters.
+///
+/// The page number is kept in the callback data of the buttons as
+/// `{prefix}{page}`, so no state is stored.  The navigation row always has
+/// three buttons, the middle one showing the current page.
+#[derive(Clone, Copy, Debug)]
+pub struct Paginator {
+    prefix: &'static str,
+    per_page: usize,
+}
+
+/// A page of a list produced by [`Paginator::page`].
+#[derive(Debug)]
+pub struct Page<'a, T> {
+    /// Items on this page.
+    pub items: &'a [T],
+    /// Index of the first item of the page in the whole list.
+    pub offset: usize,
+    /// Zero-based page number.
+    pub index: usize,
+    /// Total number of pages, at least one.
+    pub count: usize,
+    prefix: &'static str,
+}
+
+impl Paginator {
+    pub const fn new(prefix: &'static str, per_page: usize) -> Self {
+        Self { prefix, per_page }
+    }
+
+    /// Parse the page number from the callback data of a navigation button.
+    pub fn parse(&self, data: &str) -> Option<usize> {
+        data.strip_prefix(self.prefix)?.parse().ok()
+    }
+
+    /// The page shown in the message, or the first one if the message has
+    /// no navigation buttons.
+    pub fn current(&self, msg: &Message) -> usize {
+        msg.reply_markup()
+            .and_then(|markup| self.current_in(markup))
+            .unwrap_or_default()
+    }
+
+    fn current_in(&self, markup: &InlineKeyboardMarkup) -> Option<usize> {
+        markup.inline_keyboard.iter().find_map(|row| match row.as_slice() {
+            [_, middle, _] => match &middle.kind {
+                InlineKeyboardButtonKind::CallbackData(data) => {
+                    self.parse(data)
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// Take the given page of the items.  Pages past the end are clamped to
+    /// the last page, as the list may shrink while the message is shown.
+    pub fn page<'a, T>(&self, items: &'a [T], page: usize) -> Page<'a, T> {
+        let count = items.len().div_ceil(self.per_page).max(1);
+        let index = page.min(count - 1);
+        let offset = index * self.per_page;
+        let end = (offset + self.per_page).min(items.len());
+        Page {
+            items: &items[offset..end],
+            offset,
+            index,
+            count,
+            prefix: self.prefix,
+        }
+    }
+}
+
+impl<T> Page<'_, T> {
+    /// Navigation buttons, or `None` if the whole list fits on one page.
+    pub fn buttons(&self) -> Option<Vec<InlineKeyboardButton>> {
+        if self.count == 1 {
+            return None;
+        }
+        let button = |text: &str, page: usize| {
+            InlineKeyboardButton::callback(
+                text,
+                format!("{}{page}", self.prefix),
+            )
+        };
+        Some(vec![
+            match self.index.checked_sub(1) {
+                Some(prev) => button("« Prev", prev),
+                None => button("·", self.index),
+            },
+            button(&format!("{}/{}", self.index + 1, self.count), self.index),
+            if self.index + 1 < self.count {
+                button("Next »", self.index + 1)
+            } else {
+                button("·", self.index)
+            },
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginator() {
+        let paginator = Paginator::new("x:page:", 2);
+        let items = [1, 2, 3, 4, 5];
+
+        let page = paginator.page(&items, 1);
+        assert_eq!(page.items, [3, 4]);
+        assert_eq!(page.offset, 2);
+        let markup = InlineKeyboardMarkup::new([page.buttons().unwrap()]);
+        assert_eq!(paginator.current_in(&markup), Some(1));
+        let data = markup.inline_keyboard[0]
+            .iter()
+            .map(|b| match &b.kind {
+                InlineKeyboardButtonKind::CallbackData(data) => data.as_str(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(data, ["x:page:0", "x:page:1", "x:page:2"]);
+        assert_eq!(paginator.parse(data[2]), Some(2));
+        assert_eq!(paginator.parse("y:page:2"), None);
+
+        // Out of range pages are clamped.
+        let page = paginator.page(&items, 10);
+        assert_eq!((page.index, page.items), (2, &[5][..]));
+        let page = paginator.page::<i32>(&[], 3);
+        assert_eq!((page.index, page.count), (0, 1));
+        assert!(page.buttons().is_none());
+    }
+}
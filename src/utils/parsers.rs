@@ -24,6 +24,15 @@ where
     Ok(duration)
 }
 
+/// Parse a duration entered by a user in the same format, e.g. `7d` or
+/// `1d12h`.
+pub fn parse_duration(input: &str) -> Option<Duration> {
+    if input.is_empty() || input == "never" {
+        return None;
+    }
+    duration(input).ok().map(|(_, duration)| duration)
+}
+
 fn duration(mut input: &str) -> IResult<&str, Duration> {
     if input == "never" {
         return Ok(("", Duration::new(u64::MAX, 0)));
@@ -46,14 +55,15 @@ fn duration_segment(input: &str) -> IResult<&str, Duration> {
             nom::error::ErrorKind::Digit,
         )));
     };
-    let duration = match unit {
-        'w' => Duration::from_secs(value * 7 * 24 * 60 * 60),
-        'd' => Duration::from_secs(value * 24 * 60 * 60),
-        'h' => Duration::from_secs(value * 60 * 60),
-        'm' => Duration::from_secs(value * 60),
-        's' => Duration::from_secs(value),
+    let unit_secs = match unit {
+        'w' => 7 * 24 * 60 * 60,
+        'd' => 24 * 60 * 60,
+        'h' => 60 * 60,
+        'm' => 60,
+        's' => 1,
         _ => unreachable!(),
     };
+    let duration = Duration::from_secs(value.saturating_mul(unit_secs));
     Ok((input, duration))
 }
 
@@ -84,6 +94,20 @@ pub fn parse_tg_thread_link(input: &str) -> Option<ThreadIdPair> {
     })
 }
 
+/// Parse a Telegram message link, e.g. `"https://t.me/c/1234567890/321"`,
+/// optionally with a thread, e.g. `"https://t.me/c/1234567890/4321/321"`.
+/// XXX: This doesn't support chats with @username.
+pub fn parse_tg_message_link(input: &str) -> Option<(ChatId, MessageId)> {
+    let input = input.strip_prefix("https://t.me/c/")?;
+    let input = input.split(['?', '#']).next()?;
+    let (chat, rest) = input.split_once('/')?;
+    let message = rest.rsplit('/').next()?;
+    Some((
+        ChatId(-1_000_000_000_000 - chat.parse::<i64>().ok()?),
+        MessageId(message.parse().ok()?),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,6 +131,16 @@ mod tests {
         assert_eq!(duration(DURATION_STR), Ok(("", DURATION)));
     }
 
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("7d"), Some(DAY.saturating_mul(7)));
+        assert_eq!(parse_duration(DURATION_STR), Some(DURATION));
+        assert_eq!(parse_duration("7"), None);
+        assert_eq!(parse_duration("7d "), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("never"), None);
+    }
+
     #[test]
     fn test_deserialize_duration() {
         #[derive(Debug, Deserialize)]
@@ -142,4 +176,20 @@ mod tests {
             None
         );
     }
+
+    #[test]
+    fn test_parse_tg_message_link() {
+        #[allow(clippy::unreadable_literal)]
+        let chat = ChatId(-1001234567890);
+        assert_eq!(
+            parse_tg_message_link("https://t.me/c/1234567890/321"),
+            Some((chat, MessageId(321))),
+        );
+        assert_eq!(
+            parse_tg_message_link("https://t.me/c/1234567890/4321/321?single"),
+            Some((chat, MessageId(321))),
+        );
+        assert_eq!(parse_tg_message_link("https://t.me/c/1234567890"), None);
+        assert_eq!(parse_tg_message_link("https://t.me/durov/1"), None);
+    }
 }
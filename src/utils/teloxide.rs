@@ -5,10 +5,12 @@ use teloxide::payloads;
 use teloxide::prelude::*;
 use teloxide::requests::{JsonRequest, MultipartRequest};
 use teloxide::types::{
-    ChatId, ChatKind, ChatPublic, InputFile, MessageId, PublicChatKind,
+    ChatId, ChatKind, ChatPublic, InlineKeyboardButtonKind,
+    InlineKeyboardMarkup, InputFile, MessageId, PublicChatKind,
     PublicChatSupergroup, ThreadId, User,
 };
-use teloxide::utils::html;
+
+use super::{html, ResultExt as _};
 
 /// The ID of the "general" thread in Telegram.
 pub const GENERAL_THREAD_ID: ThreadId = ThreadId(MessageId(1));
@@ -36,6 +38,13 @@ pub trait BotExt {
         msg: &Message,
         photo: InputFile,
     ) -> MultipartRequest<payloads::SendPhoto>;
+
+    /// Similar to [`Bot::send_document`], but replies to the given message.
+    fn reply_document(
+        &self,
+        msg: &Message,
+        document: InputFile,
+    ) -> MultipartRequest<payloads::SendDocument>;
 }
 
 impl BotExt for Bot {
@@ -73,6 +82,18 @@ impl BotExt for Bot {
         reply.message_thread_id = msg.thread_id;
         reply
     }
+
+    fn reply_document(
+        &self,
+        msg: &Message,
+        document: InputFile,
+    ) -> MultipartRequest<payloads::SendDocument> {
+        let mut reply = self
+            .send_document(msg.chat.id, document)
+            .reply_to_message_id(msg.id);
+        reply.message_thread_id = msg.thread_id;
+        reply
+    }
 }
 
 /// An extension trait for [`Message`].
@@ -119,6 +140,30 @@ impl ChatIdExt for ChatId {
     }
 }
 
+/// Remove the keyboard row containing the pressed callback button.
+pub async fn remove_button_row(bot: &Bot, callback: &CallbackQuery) {
+    let Some(message) = &callback.message else { return };
+    let Some(markup) = message.reply_markup() else { return };
+    let pressed = callback.data.as_deref();
+    let rows = markup
+        .inline_keyboard
+        .iter()
+        .filter(|row| {
+            !row.iter().any(|b| match &b.kind {
+                InlineKeyboardButtonKind::CallbackData(d) => {
+                    Some(d.as_str()) == pressed
+                }
+                _ => false,
+            })
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    bot.edit_message_reply_markup(message.chat.id, message.id)
+        .reply_markup(InlineKeyboardMarkup::new(rows))
+        .await
+        .log_error("remove_button_row");
+}
+
 pub struct UserHtmlLink<'a>(&'a User);
 
 /// An extension trait for [`teloxide::types::User`].
@@ -134,16 +179,7 @@ impl UserExt for User {
 
 impl Display for UserHtmlLink<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "<a href=\"tg://user?id={}\">{}",
-            self.0.id,
-            html::escape(&self.0.first_name)
-        )?;
-        if let Some(last_name) = &self.0.last_name {
-            write!(f, " {}", html::escape(last_name))?;
-        }
-        write!(f, "</a>")
+        f.write_str(&html::user_mention(self.0))
     }
 }
 
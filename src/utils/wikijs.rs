@@ -7,9 +7,8 @@ use gql_client::Client;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use tap::Pipe as _;
-use teloxide::utils::html;
 
-use crate::utils::format_to;
+use crate::utils::{format_to, html};
 
 /// Get markdown page source from Wiki.js GraphQL API.
 pub async fn get_wikijs_page(
@@ -17,6 +16,24 @@ pub async fn get_wikijs_page(
     token: &str,
     path: &str,
 ) -> Result<String> {
+    Ok(get_wikijs_page_with_id(endpoint, token, path).await?.content)
+}
+
+/// A page returned by [`get_wikijs_page_with_id`].
+#[derive(Debug, Clone)]
+pub struct WikiJsPage {
+    pub id: u32,
+    pub content: String,
+    pub tags: Vec<String>,
+}
+
+/// Get markdown page source along with the data needed to update the page
+/// with [`update_wikijs_page`].
+pub async fn get_wikijs_page_with_id(
+    endpoint: &str,
+    token: &str,
+    path: &str,
+) -> Result<WikiJsPage> {
     let client = mk_client(endpoint, token);
     let (locale, path) =
         path.trim_start_matches('/').split_once('/').context("Invalid path")?;
@@ -26,7 +43,11 @@ pub async fn get_wikijs_page(
         #[strikethrough[serde(rename_all = "camelCase")]]
         struct Response {
             pages: struct Response1 {
-                single_by_path: struct Response2 { content: String }
+                single_by_path: struct Response2 {
+                    id: u32,
+                    content: String,
+                    tags: Vec<struct Response3 { tag: String }>,
+                }
             }
         }
     }
@@ -36,14 +57,127 @@ pub async fn get_wikijs_page(
         "query($locale: String!, $path: String!) {\
             pages {\
                 singleByPath(locale: $locale, path: $path) {\
-                    content\
+                    id content tags { tag }\
                 }\
             }\
         }",
         Some(serde_json::json!({ "locale": locale, "path": path })),
     )
     .await?;
-    Ok(response.pages.single_by_path.content)
+    let page = response.pages.single_by_path;
+    Ok(WikiJsPage {
+        id: page.id,
+        content: page.content,
+        tags: page.tags.into_iter().map(|t| t.tag).collect(),
+    })
+}
+
+/// Replace the content of the page, keeping it published and its tags.
+pub async fn update_wikijs_page(
+    endpoint: &str,
+    token: &str,
+    page: &WikiJsPage,
+    content: &str,
+) -> Result<()> {
+    let client = mk_client(endpoint, token);
+
+    structstruck::strike! {
+        #[strikethrough[derive(Deserialize, Debug)]]
+        #[strikethrough[serde(rename_all = "camelCase")]]
+        struct Response {
+            pages: struct Response1 {
+                update: struct Response2 {
+                    response_result: struct Response3 {
+                        succeeded: bool,
+                        message: Option<String>,
+                    }
+                }
+            }
+        }
+    }
+
+    let response = make_query::<Response>(
+        &client,
+        "mutation($id: Int!, $content: String!, $tags: [String]!) {\
+            pages {\
+                update(\
+                    id: $id, content: $content, tags: $tags, isPublished: true\
+                ) {\
+                    responseResult { succeeded message }\
+                }\
+            }\
+        }",
+        Some(serde_json::json!({
+            "id": page.id,
+            "content": content,
+            "tags": page.tags,
+        })),
+    )
+    .await?;
+    let result = response.pages.update.response_result;
+    if !result.succeeded {
+        anyhow::bail!(
+            "Failed to update page: {}",
+            result.message.unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+/// A page found by [`search_wikijs_pages`].
+#[derive(Debug, Clone)]
+pub struct WikiJsSearchResult {
+    /// Path including the locale, e.g. `/en/rules`.
+    pub path: String,
+    pub title: String,
+}
+
+/// Search pages using Wiki.js GraphQL API.
+pub async fn search_wikijs_pages(
+    endpoint: &str,
+    token: &str,
+    query: &str,
+) -> Result<Vec<WikiJsSearchResult>> {
+    let client = mk_client(endpoint, token);
+
+    structstruck::strike! {
+        #[strikethrough[derive(Deserialize, Debug)]]
+        #[strikethrough[serde(rename_all = "camelCase")]]
+        struct Response {
+            pages: struct Response1 {
+                search: struct Response2 {
+                    results: Vec<struct Response3 {
+                        title: String,
+                        path: String,
+                        locale: String,
+                    }>,
+                }
+            }
+        }
+    }
+
+    let response = make_query::<Response>(
+        &client,
+        "query($query: String!) {\
+            pages {\
+                search(query: $query) {\
+                    results { title path locale }\
+                }\
+            }\
+        }",
+        Some(serde_json::json!({ "query": query })),
+    )
+    .await?;
+    Ok(response
+        .pages
+        .search
+        .results
+        .into_iter()
+        .map(|r| WikiJsSearchResult {
+            path: format!("/{}/{}", r.locale, r.path),
+            title: r.title,
+        })
+        .collect())
 }
 
 pub struct WikiJsUpdates {
@@ -382,7 +516,9 @@ impl WikiJsUpdates {
                     human_readable_join(
                         x.actions.iter().map(|s| humanize_action_type(s))
                     ),
-                    human_readable_join(x.authors.iter()),
+                    human_readable_join(
+                        x.authors.iter().map(|a| html::escape(a))
+                    ),
                     match x.changes {
                         (0, 0) => String::new(),
                         (0, del) => format!(" (-{del})"),
@@ -1,53 +1,209 @@
 use std::sync::{Arc, Mutex, OnceLock};
 
 use diesel::prelude::*;
-use itertools::Itertools;
+use itertools::Itertools as _;
 use metrics_exporter_prometheus::PrometheusHandle;
+use salvo::catcher::Catcher;
 use salvo::conn::TcpListener;
+use salvo::http::header::AUTHORIZATION;
 use salvo::writing::{Json, Text};
-use salvo::{Listener, Router, Server};
+use salvo::{
+    async_trait, Depot, FlowCtrl, Handler, Listener, Request, Response, Router,
+    Server, Service,
+};
 use salvo_oapi::{endpoint, OpenApi};
 use tap::Pipe as _;
+use teloxide::types::UserId;
+use teloxide::Bot;
 use tokio_util::sync::CancellationToken;
 
+use self::error::ApiError;
+use self::oidc::Role;
+use crate::common::BotEnv;
 use crate::config::Config;
-use crate::db::DbUserId;
 use crate::{models, schema};
 
+mod audit;
+pub mod dashboard;
+mod donations;
+mod error;
+mod feed;
+mod generic_hooks;
+mod git_hooks;
+mod health;
+mod kv;
+mod mini_app;
+mod needs;
+pub mod oidc;
+mod polls;
+mod residents;
+mod stats;
+mod status_page;
+
 struct AppState {
+    bot: Bot,
     conn: Mutex<SqliteConnection>,
     config: Arc<Config>,
+    /// For endpoints that act through bot modules.  Don't use its
+    /// connection for plain reads, use `conn` instead.
+    env: Arc<BotEnv>,
     prometheus: PrometheusHandle,
+    cancel: CancellationToken,
+    /// Read on the first use, see [`oidc`].
+    oidc_discovery: tokio::sync::OnceCell<oidc::Discovery>,
+    /// Path of the routes of the instance: empty or `/<instance>`.
+    prefix: String,
+    /// URL of the routes of the instance, e.g. `http://127.0.0.1:8080/name`.
+    base_url: String,
+}
+
+tokio::task_local! {
+    /// State of the instance serving the current request, see [`WithState`].
+    static STATE: &'static AppState;
 }
 
-static STATE: OnceLock<AppState> = OnceLock::new();
+/// States of all instances, for the metrics of the whole process.
+static INSTANCES: OnceLock<Vec<&'static AppState>> = OnceLock::new();
 
 fn state() -> &'static AppState {
-    STATE.get().expect("AppState not initialized")
+    STATE.with(|state| *state)
 }
 
-fn server_addr() -> String {
-    format!("http://{}", state().config.server_addr)
+/// Run the rest of the request with the state of an instance.
+struct WithState(&'static AppState);
+
+#[async_trait]
+impl Handler for WithState {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        STATE.scope(self.0, ctrl.call_next(req, depot, res)).await;
+    }
 }
 
+/// Serve the API of the instances.  A single instance is served at the root,
+/// several are served under `/<instance>`, see [`crate::config::Instances`].
 pub async fn run(
-    conn: SqliteConnection,
-    config: Arc<Config>,
+    instances: Vec<(Bot, SqliteConnection, Arc<BotEnv>)>,
     prometheus: PrometheusHandle,
     cancel: CancellationToken,
 ) {
-    let app_state = AppState {
-        conn: Mutex::new(conn),
-        config: Arc::clone(&config),
-        prometheus,
-    };
-    STATE.set(app_state).ok().expect("AppState already initialized");
+    let Some((_, _, first)) = instances.first() else { return };
+    let server_addr = first.config.server_addr;
+    let prefixed = instances.len() > 1;
+    let states = instances
+        .into_iter()
+        .map(|(bot, conn, env)| {
+            let prefix = if prefixed {
+                format!("/{}", env.instance)
+            } else {
+                String::new()
+            };
+            let app_state = AppState {
+                bot,
+                conn: Mutex::new(conn),
+                config: Arc::clone(&env.config),
+                env,
+                prometheus: prometheus.clone(),
+                cancel: cancel.clone(),
+                oidc_discovery: tokio::sync::OnceCell::new(),
+                base_url: format!("http://{server_addr}{prefix}"),
+                prefix,
+            };
+            // Lives until the process exits.
+            &*Box::leak(Box::new(app_state))
+        })
+        .collect_vec();
+    INSTANCES.set(states.clone()).ok().expect("AppState already initialized");
+
+    let mut router = Router::new();
+    for state in states {
+        let instance = instance_router(state);
+        router = router.push(if prefixed {
+            Router::with_path(&state.env.instance).push(instance)
+        } else {
+            instance
+        });
+    }
+    let service =
+        Service::new(router).catcher(Catcher::default().hoop(error::catcher));
 
+    let listener = TcpListener::new(server_addr).bind().await;
+    Server::new(listener)
+        .serve_with_graceful_shutdown(
+            service,
+            async move { cancel.cancelled().await },
+            None,
+        )
+        .await;
+}
+
+/// Routes and API docs of an instance.
+fn instance_router(state: &'static AppState) -> Router {
     let router = Router::new()
         .get(get_index)
         .push(Router::with_path("/metrics").get(get_metrics))
-        .push(Router::with_path("/residents/v0").get(get_residents_v0))
-        .push(Router::with_path("/all_residents/v0").get(get_all_residents_v0));
+        .push(Router::with_path("/healthz").get(health::get_healthz))
+        .push(Router::with_path("/readyz").get(health::get_readyz))
+        .push(
+            Router::with_path("/residents/v0").get(residents::get_residents_v0),
+        )
+        .push(
+            Router::with_path("/all_residents/v0")
+                .get(residents::get_all_residents_v0),
+        )
+        .push(
+            Router::with_path("/stats/timeseries")
+                .get(stats::get_stats_timeseries),
+        )
+        .push(Router::with_path("/audit_log").get(audit::get_audit_log))
+        .push(Router::with_path("/feed").get(feed::get_feed))
+        .push(
+            Router::with_path("/status")
+                .filter_fn(move |req, _| {
+                    status_page::is_page_request(state, req)
+                })
+                .get(status_page::get_status_page),
+        )
+        .push(Router::with_path("/status").get(get_status))
+        .push(Router::with_path("/needs/stream").get(needs::get_needs_stream))
+        .push(Router::with_path("/polls").post(polls::post_polls))
+        .push(Router::with_path("/dashboard").get(dashboard::get_dashboard))
+        .push(Router::with_path("/dashboard/login").get(dashboard::get_login))
+        .push(Router::with_path("/dashboard/oidc/login").get(oidc::get_login))
+        .push(
+            Router::with_path("/dashboard/oidc/callback")
+                .get(oidc::get_callback),
+        )
+        .push(
+            Router::with_path("/donations/v0").get(donations::get_donations_v0),
+        )
+        .push(Router::with_path("/hooks/git").post(git_hooks::post_git_hook))
+        .push(
+            Router::with_path("/hooks/generic/<name>")
+                .post(generic_hooks::post_generic_hook),
+        )
+        .push(
+            Router::with_path("/kv/<namespace>/<key>")
+                .get(kv::get_kv)
+                .put(kv::put_kv)
+                .delete(kv::delete_kv),
+        )
+        .push(Router::with_path("/miniapp/auth").post(mini_app::post_auth))
+        .push(
+            Router::with_path("/miniapp/me/borrows")
+                .get(mini_app::get_my_borrows),
+        )
+        .push(
+            Router::with_path("/miniapp/me/needs").get(mini_app::get_my_needs),
+        )
+        .push(
+            Router::with_path("/miniapp/me/polls").get(mini_app::get_my_polls),
+        );
 
     let doc = OpenApi::with_info(
         salvo_oapi::Info::new("Botka HTTP API", "0.1").description(
@@ -57,7 +213,7 @@ pub async fn run(
                 ",
         ),
     )
-    .add_server(salvo_oapi::Server::new(server_addr()))
+    .add_server(salvo_oapi::Server::new(state.base_url.clone()))
     .add_path(
         "/openapi.json",
         salvo_oapi::PathItem::new(
@@ -67,16 +223,7 @@ pub async fn run(
     )
     .merge_router(&router);
 
-    let router = router.unshift(doc.into_router("/openapi.json"));
-
-    let listener = TcpListener::new(config.server_addr).bind().await;
-    Server::new(listener)
-        .serve_with_graceful_shutdown(
-            router,
-            async move { cancel.cancelled().await },
-            None,
-        )
-        .await;
+    router.unshift(doc.into_router("/openapi.json")).hoop(WithState(state))
 }
 
 #[salvo::prelude::handler]
@@ -98,52 +245,104 @@ async fn get_index() -> Text<String> {
     />
 </body>
 </html>
-"#, server_addr())
+"#, state().base_url)
 .pipe(Text::Html)
 }
 
-/// Prometheus metrics endpoint.
-#[endpoint()]
-async fn get_metrics() -> String {
-    let state = state();
-    crate::metrics::refresh(&mut state.conn.lock().unwrap());
-    state.prometheus.render()
+/// Token of a request to an endpoint with its own token: the
+/// `Authorization: Bearer <token>` header or the `token` query parameter.
+fn request_token(req: &Request) -> Option<String> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or_else(|| req.query::<String>("token"))
 }
 
-/// Get a list of current residents.
-#[endpoint()]
-async fn get_residents_v0() -> Json<Vec<models::DataResident>> {
-    let residents: Vec<(DbUserId, models::TgUser)> = schema::residents::table
-        .filter(schema::residents::end_date.is_null())
-        .inner_join(
-            schema::tg_users::table
-                .on(schema::residents::tg_id.eq(schema::tg_users::id)),
-        )
-        .order(schema::residents::begin_date.desc())
-        .select((schema::residents::tg_id, schema::tg_users::all_columns))
-        .load(&mut *state().conn.lock().unwrap())
-        .unwrap();
+/// Whether the [`request_token`] is `expected`.  Compared in constant time to
+/// not leak the token through response timing.
+fn has_token(req: &Request, expected: &str) -> bool {
+    request_token(req).is_some_and(|token| {
+        constant_time_eq(token.as_bytes(), expected.as_bytes())
+    })
+}
 
-    let residents = residents
-        .into_iter()
-        .map(|(id, user)| models::DataResident {
-            id: id.into(),
-            username: user.username,
-            first_name: user.first_name,
-            last_name: user.last_name,
-        })
-        .collect_vec();
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
 
-    Json(residents)
+/// Check the bearer token of a request to a privileged endpoint: either the
+/// `server_api_token`, or an access token of the OIDC provider of a user
+/// with at least the `role`, see [`oidc`].  Returns the linked user of the
+/// OIDC token and their role, or `None` for the `server_api_token`.
+async fn authorize(
+    req: &Request,
+    role: Role,
+) -> Result<Option<(UserId, Role)>, ApiError> {
+    let config = &state().config;
+    let oidc = config.server_dashboard.as_ref().and_then(|d| d.oidc.as_ref());
+    if config.server_api_token.is_none() && oidc.is_none() {
+        return Err(ApiError::not_found());
+    }
+    let Some(token) = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    else {
+        return Err(ApiError::unauthorized());
+    };
+    if config
+        .server_api_token
+        .as_deref()
+        .is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+    {
+        return Ok(None);
+    }
+    let Some(oidc) = oidc else { return Err(ApiError::unauthorized()) };
+    match oidc::token_user(oidc, token).await? {
+        Some((user, user_role)) if user_role >= role => {
+            Ok(Some((user, user_role)))
+        }
+        _ => Err(ApiError::unauthorized()),
+    }
 }
 
-/// Get a list of current and past residents.
-/// The same resident may appear multiple times if they have left and returned.
+/// Prometheus metrics endpoint.  Includes the metrics of all instances.
 #[endpoint()]
-async fn get_all_residents_v0() -> Json<Vec<models::Resident>> {
-    schema::residents::table
-        .order(schema::residents::begin_date.desc())
-        .load(&mut *state().conn.lock().unwrap())
-        .map(Json)
-        .unwrap()
+async fn get_metrics() -> String {
+    for state in INSTANCES.get().into_iter().flatten() {
+        crate::metrics::refresh(
+            &mut state.conn.lock().unwrap(),
+            &state.env.db_path,
+            &state.env.instance,
+        );
+    }
+    state().prometheus.render()
+}
+
+/// Get the state of the monitored services.  Browsers get the public status
+/// page instead, if it's enabled.
+#[endpoint()]
+async fn get_status() -> Result<Json<Vec<models::ServiceStatus>>, ApiError> {
+    let status = schema::service_status::table
+        .order(schema::service_status::name)
+        .select(models::ServiceStatus::as_select())
+        .load(&mut *state().conn.lock().unwrap())?;
+    Ok(Json(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(constant_time_eq(b"", b""));
+    }
 }
@@ -0,0 +1,50 @@
+//! Audit log endpoint for the board.
+
+use diesel::prelude::*;
+use salvo::writing::Json;
+use salvo::Request;
+use salvo_oapi::{endpoint, ToParameters};
+use serde::Deserialize;
+
+use super::oidc::Role;
+use super::{authorize, state, ApiError};
+use crate::{models, schema};
+
+/// Maximum value of the `per_page` parameter.
+const MAX_PER_PAGE: i64 = 500;
+
+#[derive(Deserialize, Debug, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct AuditLogQuery {
+    /// Page number, starting from 0.
+    page: Option<i64>,
+    /// Number of entries per page, 50 by default.
+    per_page: Option<i64>,
+}
+
+/// Get audit log entries, newest first.
+///
+/// Requires `Authorization: Bearer <token>` header with the
+/// `server_api_token` or an OIDC access token of an admin.
+#[endpoint()]
+pub async fn get_audit_log(
+    req: &mut Request,
+    query: AuditLogQuery,
+) -> Result<Json<Vec<models::AuditLogEntry>>, ApiError> {
+    authorize(req, Role::Admin).await?;
+    let per_page = query.per_page.unwrap_or(50).clamp(1, MAX_PER_PAGE);
+    let offset = query
+        .page
+        .unwrap_or(0)
+        .max(0)
+        .checked_mul(per_page)
+        .ok_or_else(|| ApiError::invalid("page is too large"))?;
+    let entries = schema::audit_log::table
+        .filter(schema::audit_log::deleted_at.is_null())
+        .order(schema::audit_log::rowid.desc())
+        .limit(per_page)
+        .offset(offset)
+        .select(models::AuditLogEntry::as_select())
+        .load(&mut *state().conn.lock().unwrap())?;
+    Ok(Json(entries))
+}
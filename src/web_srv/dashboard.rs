@@ -0,0 +1,470 @@
+//! Read-only HTML dashboard for admins.
+//!
+//! Admins get a short-lived login link with the `/weblogin` command.  The
+//! link sets a signed session cookie valid for
+//! [`server_dashboard.session_hours`].
+//!
+//! [`server_dashboard.session_hours`]: crate::config::Dashboard::session_hours
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use hmac::{Hmac, Mac as _};
+use minijinja::Environment;
+use salvo::http::header::{COOKIE, SET_COOKIE};
+use salvo::http::{HeaderValue, StatusCode};
+use salvo::writing::{Redirect, Text as TextBody};
+use salvo::{handler, Request, Response};
+use serde::Serialize;
+use sha2::Sha256;
+use teloxide::types::{ChatId, UserId};
+
+use super::{state, ApiError};
+use crate::config::Dashboard;
+use crate::db::{DbChatId, DbMessageId, DbUserId};
+use crate::health::Health;
+use crate::utils::ChatIdExt as _;
+use crate::{models, schema};
+
+const COOKIE_NAME: &str = "botka_session";
+
+/// How long a login link is valid.
+pub const LOGIN_LINK_MINUTES: i64 = 10;
+
+/// Number of failed Telegram calls shown.
+const MAX_ERRORS: i64 = 20;
+
+/// Build a login link for the user.
+pub fn login_url(conf: &Dashboard, user: UserId) -> String {
+    let expires = Utc::now().timestamp() + LOGIN_LINK_MINUTES * 60;
+    format!(
+        "{}/dashboard/login?token={}",
+        conf.public_url.trim_end_matches('/'),
+        sign(&conf.secret, "login", user, expires),
+    )
+}
+
+/// Sign a token for the given purpose: `<user>.<expires>.<hmac>`.
+fn sign(secret: &str, purpose: &str, user: UserId, expires: i64) -> String {
+    let signature = mac(secret, purpose, user, expires).finalize();
+    format!("{user}.{expires}.{}", hex::encode(signature.into_bytes()))
+}
+
+/// Verify the token and return its user.
+fn verify(
+    secret: &str,
+    purpose: &str,
+    token: &str,
+    now: i64,
+) -> Option<UserId> {
+    let mut parts = token.splitn(3, '.');
+    let user = UserId(parts.next()?.parse().ok()?);
+    let expires: i64 = parts.next()?.parse().ok()?;
+    let signature = hex::decode(parts.next()?).ok()?;
+    mac(secret, purpose, user, expires).verify_slice(&signature).ok()?;
+    (now < expires).then_some(user)
+}
+
+fn mac(
+    secret: &str,
+    purpose: &str,
+    user: UserId,
+    expires: i64,
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts any key length");
+    mac.update(format!("{purpose}:{user}:{expires}").as_bytes());
+    mac
+}
+
+fn config() -> Result<&'static Dashboard, ApiError> {
+    state().config.server_dashboard.as_ref().ok_or_else(ApiError::not_found)
+}
+
+/// Exchange a login link for a session cookie.
+#[handler]
+pub async fn get_login(
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<(), ApiError> {
+    let conf = config()?;
+    let now = Utc::now().timestamp();
+    let token = req.query::<String>("token").unwrap_or_default();
+    let Some(user) = verify(&conf.secret, "login", &token, now) else {
+        return Err(ApiError::unauthorized());
+    };
+    start_session(res, conf, user, now)
+}
+
+/// Set the session cookie of the user and redirect to the dashboard.
+pub(super) fn start_session(
+    res: &mut Response,
+    conf: &Dashboard,
+    user: UserId,
+    now: i64,
+) -> Result<(), ApiError> {
+    let max_age = i64::from(conf.session_hours) * 60 * 60;
+    let session = sign(&conf.secret, "session", user, now + max_age);
+    set_cookie(res, conf, COOKIE_NAME, &session, max_age)?;
+    res.render(Redirect::found(format!("{}/dashboard", state().prefix)));
+    Ok(())
+}
+
+/// Set a cookie for the paths under `/dashboard`.
+pub(super) fn set_cookie(
+    res: &mut Response,
+    conf: &Dashboard,
+    name: &str,
+    value: &str,
+    max_age: i64,
+) -> Result<(), ApiError> {
+    let secure =
+        if conf.public_url.starts_with("https://") { "; Secure" } else { "" };
+    let cookie = format!(
+        "{name}={value}; Path={}/dashboard; Max-Age={max_age}; HttpOnly; \
+         SameSite=Lax{secure}",
+        state().prefix,
+    );
+    res.headers_mut().append(
+        SET_COOKIE,
+        HeaderValue::from_str(&cookie).map_err(|_| ApiError::internal())?,
+    );
+    Ok(())
+}
+
+/// The dashboard page.
+#[handler]
+pub async fn get_dashboard(
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<(), ApiError> {
+    let conf = config()?;
+    let state = state();
+    let now = Utc::now();
+    let viewer = cookie(req, COOKIE_NAME)
+        .and_then(|s| verify(&conf.secret, "session", s, now.timestamp()))
+        .filter(|u| state.config.telegram.admins.contains(u));
+    let Some(viewer) = viewer else {
+        let sso = if conf.oidc.is_some() {
+            format!(
+                r#" or <a href="{}/dashboard/oidc/login">log in with SSO</a>"#,
+                state.prefix,
+            )
+        } else {
+            String::new()
+        };
+        res.status_code(StatusCode::UNAUTHORIZED);
+        res.render(TextBody::Html(format!(
+            "<!doctype html><p>Send <code>/weblogin</code> to the bot in a \
+             private chat to log in{sso}.</p>",
+        )));
+        return Ok(());
+    };
+
+    let data =
+        load(&mut state.conn.lock().unwrap(), &state.env.health, viewer)?;
+    let html = render(&data).map_err(|e| {
+        log::error!("dashboard: failed to render: {e}");
+        ApiError::internal()
+    })?;
+    res.render(TextBody::Html(html));
+    Ok(())
+}
+
+/// Value of the cookie of the request.
+pub(super) fn cookie<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|c| c.trim().strip_prefix(name)?.strip_prefix('='))
+}
+
+#[derive(Serialize)]
+struct Person {
+    name: String,
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Data {
+    now: String,
+    viewer: Person,
+    presence: Option<Presence>,
+    services: Vec<Service>,
+    errors: Vec<RecentError>,
+    residents: Vec<Resident>,
+    needs: Vec<Need>,
+    borrows: Vec<Borrow>,
+    polls: Vec<Poll>,
+}
+
+#[derive(Serialize)]
+struct Presence {
+    count: i32,
+    at: String,
+}
+
+#[derive(Serialize)]
+struct Service {
+    name: String,
+    since: String,
+    error: Option<String>,
+}
+
+/// A failed access to an external service or a failed Telegram call of the
+/// outbox.
+#[derive(Serialize)]
+struct RecentError {
+    source: String,
+    at: String,
+    error: String,
+}
+
+#[derive(Serialize)]
+struct Resident {
+    person: Person,
+    since: String,
+}
+
+#[derive(Serialize)]
+struct Need {
+    item: String,
+    person: Person,
+    since: Option<String>,
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Borrow {
+    items: Vec<String>,
+    person: Person,
+    since: Option<String>,
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Poll {
+    person: Person,
+    /// Number of residents who voted.
+    voted: usize,
+    url: Option<String>,
+}
+
+fn load(
+    conn: &mut SqliteConnection,
+    health: &Health,
+    viewer: UserId,
+) -> QueryResult<Data> {
+    let users: HashMap<DbUserId, models::TgUser> = schema::tg_users::table
+        .load::<models::TgUser>(conn)?
+        .into_iter()
+        .map(|u| (u.id, u))
+        .collect();
+    let person = |id: DbUserId| {
+        users.get(&id).map_or_else(
+            || Person { name: format!("id={}", UserId::from(id)), url: None },
+            |u| Person {
+                name: [Some(u.first_name.as_str()), u.last_name.as_deref()]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                url: u.username.as_ref().map(|n| format!("https://t.me/{n}")),
+            },
+        )
+    };
+    let date = |t: NaiveDateTime| t.format("%Y-%m-%d %H:%M").to_string();
+
+    let presence = schema::presence_log::table
+        .order(schema::presence_log::timestamp.desc())
+        .select((
+            schema::presence_log::timestamp,
+            schema::presence_log::person_count,
+        ))
+        .first::<(NaiveDateTime, i32)>(conn)
+        .optional()?
+        .map(|(at, count)| Presence { count, at: date(at) });
+
+    let services = schema::service_status::table
+        .filter(schema::service_status::up.eq(false))
+        .order(schema::service_status::name)
+        .select(models::ServiceStatus::as_select())
+        .load(conn)?
+        .into_iter()
+        .map(|s| Service { name: s.name, since: date(s.since), error: s.error })
+        .collect();
+
+    let mut errors = health
+        .services()
+        .into_iter()
+        .filter(|(_, h)| !h.ok)
+        .map(|(name, h)| RecentError {
+            source: name.to_string(),
+            at: date(h.last_check.naive_utc()),
+            error: "The last access failed.".to_string(),
+        })
+        .collect::<Vec<_>>();
+    errors.extend(
+        schema::outbox::table
+            .filter(schema::outbox::last_error.is_not_null())
+            .order(schema::outbox::rowid.desc())
+            .limit(MAX_ERRORS)
+            .select(models::OutboxEntry::as_select())
+            .load(conn)?
+            .into_iter()
+            .map(|e| RecentError {
+                source: format!("Telegram call #{}", e.rowid),
+                at: date(e.failed_at.unwrap_or(e.created_at)),
+                error: match e.failed_at {
+                    Some(_) => e.last_error.unwrap_or_default(),
+                    None => format!(
+                        "{} (retrying at {})",
+                        e.last_error.unwrap_or_default(),
+                        date(e.next_attempt_at),
+                    ),
+                },
+            }),
+    );
+
+    let residents = schema::residents::table
+        .filter(schema::residents::end_date.is_null())
+        .order(schema::residents::begin_date.desc())
+        .load::<models::Resident>(conn)?;
+    let resident_ids =
+        residents.iter().map(|r| r.tg_id).collect::<HashSet<_>>();
+    let residents = residents
+        .into_iter()
+        .map(|r| Resident {
+            person: person(r.tg_id),
+            since: date(r.begin_date),
+        })
+        .collect();
+
+    let needs = schema::needed_items::table
+        .filter(schema::needed_items::bought_at.is_null())
+        .order(schema::needed_items::rowid.asc())
+        .select(models::NeededItem::as_select())
+        .load(conn)?
+        .into_iter()
+        .map(|n| Need {
+            url: message_url(n.pinned_chat_id, n.pinned_message_id),
+            item: n.item,
+            person: person(n.request_user_id),
+            since: n.created_at.map(date),
+        })
+        .collect();
+
+    let borrows = schema::borrowed_items::table
+        .select(models::BorrowedItems::as_select())
+        .load(conn)?
+        .into_iter()
+        .filter_map(|b| {
+            let items = b
+                .items
+                .iter()
+                .filter(|i| i.returned.is_none())
+                .map(|i| i.name.clone())
+                .collect::<Vec<_>>();
+            (!items.is_empty()).then(|| Borrow {
+                items,
+                person: person(b.user_id),
+                since: b.created_at.map(date),
+                url: message_url(b.chat_id, b.bot_message_id),
+            })
+        })
+        .collect();
+
+    let polls = schema::tracked_polls::table
+        .filter(schema::tracked_polls::closed_at.is_null())
+        .select(models::TrackedPoll::as_select())
+        .load(conn)?
+        .into_iter()
+        .map(|p| Poll {
+            person: person(p.creator_id),
+            voted: p
+                .voted_users
+                .iter()
+                .filter(|u| resident_ids.contains(u))
+                .count(),
+            url: message_url(p.info_chat_id, p.info_message_id),
+        })
+        .collect();
+
+    Ok(Data {
+        now: date(Utc::now().naive_utc()),
+        viewer: person(viewer.into()),
+        presence,
+        services,
+        errors,
+        residents,
+        needs,
+        borrows,
+        polls,
+    })
+}
+
+/// Link to the message, if the chat is a supergroup.
+pub(super) fn message_url(
+    chat: DbChatId,
+    message: DbMessageId,
+) -> Option<String> {
+    let chat = ChatId::from(chat).channel_t_me_id()?;
+    Some(format!("https://t.me/c/{chat}/{}", message.0))
+}
+
+fn render(data: &Data) -> Result<String, minijinja::Error> {
+    let mut env = Environment::new();
+    env.add_template("dashboard.html", include_str!("dashboard.html"))?;
+    env.get_template("dashboard.html")?.render(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        let token = sign("key", "login", UserId(42), 1000);
+        assert_eq!(verify("key", "login", &token, 999), Some(UserId(42)));
+        assert_eq!(verify("key", "login", &token, 1000), None);
+        assert_eq!(verify("key", "session", &token, 999), None);
+        assert_eq!(verify("other", "login", &token, 999), None);
+        let forged = token.replacen("42", "43", 1);
+        assert_eq!(verify("key", "login", &forged, 999), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let data = Data {
+            now: "2024-01-31 12:00".to_string(),
+            viewer: Person { name: "<Admin>".to_string(), url: None },
+            presence: None,
+            services: Vec::new(),
+            errors: vec![RecentError {
+                source: "openai".to_string(),
+                at: "2024-01-31 11:00".to_string(),
+                error: "The last access failed.".to_string(),
+            }],
+            residents: Vec::new(),
+            needs: vec![Need {
+                item: "milk".to_string(),
+                person: Person {
+                    name: "Alice".to_string(),
+                    url: Some("https://t.me/alice".to_string()),
+                },
+                since: None,
+                url: Some("https://t.me/c/1/2".to_string()),
+            }],
+            borrows: Vec::new(),
+            polls: Vec::new(),
+        };
+        let html = render(&data).unwrap();
+        assert!(html.contains("&lt;Admin&gt;"));
+        assert!(html.contains(r#"<a href="https://t.me/c/1/2">milk</a>"#));
+        assert!(html.contains(r#"<a href="https://t.me/alice">Alice</a>"#));
+        assert!(html.contains("<td>openai</td>"));
+    }
+}
@@ -0,0 +1,20 @@
+//! Donation totals, e.g. for a fundraising widget on the space website.
+
+use salvo::writing::Json;
+use salvo_oapi::endpoint;
+
+use super::{state, ApiError};
+use crate::models::DonationTotals;
+
+/// Get donation totals.  Amounts are in cents of the configured currency.
+#[endpoint()]
+pub async fn get_donations_v0() -> Result<Json<DonationTotals>, ApiError> {
+    let state = state();
+    let mut totals = crate::modules::donations::totals(
+        &mut state.conn.lock().unwrap(),
+        chrono::Utc::now().naive_utc(),
+    )?;
+    totals.currency =
+        state.config.telegram.treasury.as_ref().map(|t| t.currency.clone());
+    Ok(Json(totals))
+}
@@ -0,0 +1,203 @@
+//! API errors, rendered as RFC 7807 `application/problem+json`.
+//!
+//! Endpoints return [`ApiError`].  Errors raised by salvo itself, e.g. for
+//! unknown paths or invalid query parameters, are converted by [`catcher`].
+
+use salvo::http::header::CONTENT_TYPE;
+use salvo::http::{HeaderValue, ResBody, StatusCode};
+use salvo::{async_trait, handler, Depot, FlowCtrl, Request, Response, Writer};
+use salvo_oapi::{
+    Components, Content, EndpointOutRegister, Operation, ToSchema,
+};
+use serde::Serialize;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Machine-readable error code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Missing or invalid credentials.
+    Unauthorized,
+    /// The credentials don't allow this request.
+    Forbidden,
+    /// The resource does not exist or is disabled in the config.
+    NotFound,
+    /// The request is malformed, e.g. invalid parameters or payload.
+    InvalidRequest,
+    /// Too many requests, retry later.
+    RateLimited,
+    /// Something went wrong on the server side.
+    Internal,
+}
+
+/// Problem details, see RFC 7807.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Problem {
+    /// Always `about:blank`, use `code` to tell errors apart.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// Reason phrase of the status code.
+    title: String,
+    /// HTTP status code.
+    status: u16,
+    code: ErrorCode,
+    /// Human-readable explanation, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    pub(super) code: ErrorCode,
+    detail: Option<String>,
+}
+
+impl ApiError {
+    pub const fn unauthorized() -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized)
+    }
+
+    pub const fn forbidden() -> Self {
+        Self::new(StatusCode::FORBIDDEN, ErrorCode::Forbidden)
+    }
+
+    pub const fn not_found() -> Self {
+        Self::new(StatusCode::NOT_FOUND, ErrorCode::NotFound)
+    }
+
+    pub fn invalid(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest)
+            .with_detail(detail)
+    }
+
+    pub const fn too_many_requests() -> Self {
+        Self::new(StatusCode::TOO_MANY_REQUESTS, ErrorCode::RateLimited)
+    }
+
+    pub const fn internal() -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal)
+    }
+
+    const fn new(status: StatusCode, code: ErrorCode) -> Self {
+        Self { status, code, detail: None }
+    }
+
+    fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    fn from_status(status: StatusCode) -> Self {
+        let code = match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::RateLimited,
+            s if s.is_client_error() => ErrorCode::InvalidRequest,
+            _ => ErrorCode::Internal,
+        };
+        Self::new(status, code)
+    }
+
+    fn problem(&self) -> Problem {
+        Problem {
+            kind: "about:blank",
+            title: self
+                .status
+                .canonical_reason()
+                .unwrap_or("Error")
+                .to_string(),
+            status: self.status.as_u16(),
+            code: self.code,
+            detail: self.detail.clone(),
+        }
+    }
+
+    fn render(&self, res: &mut Response) {
+        let body = serde_json::to_vec(&self.problem()).unwrap_or_default();
+        res.status_code(self.status);
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        res.body(body);
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(e: diesel::result::Error) -> Self {
+        log::error!("web_srv: database error: {e}");
+        Self::internal()
+    }
+}
+
+#[async_trait]
+impl Writer for ApiError {
+    async fn write(
+        self,
+        _req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+    ) {
+        self.render(res);
+    }
+}
+
+impl EndpointOutRegister for ApiError {
+    fn register(components: &mut Components, operation: &mut Operation) {
+        let schema = Problem::to_schema(components);
+        for (status, description) in [
+            (StatusCode::BAD_REQUEST, "Invalid request"),
+            (StatusCode::UNAUTHORIZED, "Unauthorized"),
+            (StatusCode::FORBIDDEN, "Forbidden"),
+            (StatusCode::NOT_FOUND, "Not found"),
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal error"),
+        ] {
+            operation.responses.insert(
+                status.as_str(),
+                salvo_oapi::Response::new(description)
+                    .add_content(PROBLEM_JSON, Content::new(schema.clone())),
+            );
+        }
+    }
+}
+
+/// Convert errors raised outside of endpoints into problem details.
+#[handler]
+pub async fn catcher(res: &mut Response, ctrl: &mut FlowCtrl) {
+    let Some(status) = res.status_code else { return };
+    if res.headers().get(CONTENT_TYPE).is_some_and(|v| v == PROBLEM_JSON) {
+        // Already rendered by an endpoint.
+        return;
+    }
+    let mut error = ApiError::from_status(status);
+    if let ResBody::Error(e) = &res.body {
+        error.detail = e.detail.clone();
+    }
+    error.render(res);
+    ctrl.skip_rest();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problem() {
+        let problem = ApiError::invalid("bad page").problem();
+        assert_eq!(
+            serde_json::to_value(problem).unwrap(),
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Bad Request",
+                "status": 400,
+                "code": "invalid_request",
+                "detail": "bad page",
+            }),
+        );
+        assert_eq!(
+            ApiError::from_status(StatusCode::METHOD_NOT_ALLOWED).code,
+            ErrorCode::InvalidRequest,
+        );
+    }
+}
@@ -0,0 +1,50 @@
+//! Activity feed endpoint, see [`activity_feed`].
+//!
+//! [`activity_feed`]: crate::modules::activity_feed
+
+use salvo::writing::Json;
+use salvo::Request;
+use salvo_oapi::{endpoint, ToParameters};
+use serde::Deserialize;
+use teloxide::types::UserId;
+
+use super::oidc::Role;
+use super::{authorize, state, ApiError};
+use crate::models;
+use crate::modules::activity_feed::{self, Filter};
+
+#[derive(Deserialize, Debug, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct FeedQuery {
+    /// Only entries of this module, e.g. `needs`.
+    module: Option<String>,
+    /// Only entries of this Telegram user.
+    actor: Option<u64>,
+    /// Only entries with `rowid` less than this, for pagination.
+    before: Option<i32>,
+    /// Number of entries, 50 by default.
+    limit: Option<i64>,
+}
+
+/// Get activity feed entries, newest first.
+///
+/// Requires `Authorization: Bearer <token>` header with the
+/// `server_api_token` or an OIDC access token of a resident.
+#[endpoint()]
+pub async fn get_feed(
+    req: &mut Request,
+    query: FeedQuery,
+) -> Result<Json<Vec<models::ActivityEntry>>, ApiError> {
+    authorize(req, Role::Resident).await?;
+    let filter = Filter {
+        module: query.module,
+        actor: query.actor.map(UserId),
+        before: query.before,
+    };
+    let entries = activity_feed::load(
+        &mut state().conn.lock().unwrap(),
+        &filter,
+        query.limit.unwrap_or(50),
+    )?;
+    Ok(Json(entries))
+}
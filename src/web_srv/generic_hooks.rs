@@ -0,0 +1,104 @@
+//! Generic inbound webhooks.
+//!
+//! Each of the [`telegram.generic_hooks`] is available at
+//! `POST /hooks/generic/<name>`.  The JSON payload is rendered with the
+//! hook's [minijinja] template and posted to its thread.  The object keys of
+//! the payload are available as template variables, the whole payload is
+//! also available as `payload`.  Values are HTML-escaped, so the template may
+//! use Telegram HTML tags.
+//!
+//! [`telegram.generic_hooks`]: crate::config::Telegram::generic_hooks
+//! [minijinja]: https://docs.rs/minijinja
+
+use minijinja::{AutoEscape, Environment};
+use salvo::Request;
+use salvo_oapi::endpoint;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use super::{has_token, state, ApiError};
+
+/// Receive a generic webhook.
+///
+/// Requires `Authorization: Bearer <token>` header or `token` query
+/// parameter.
+#[endpoint()]
+pub async fn post_generic_hook(req: &mut Request) -> Result<String, ApiError> {
+    let state = state();
+    let name = req.param::<String>("name").unwrap_or_default();
+    let Some(hook) =
+        state.config.telegram.generic_hooks.iter().find(|h| h.name == name)
+    else {
+        return Err(ApiError::not_found());
+    };
+    if !has_token(req, &hook.token) {
+        return Err(ApiError::unauthorized());
+    }
+    let payload: serde_json::Value = req.parse_json().await.map_err(|e| {
+        log::warn!("generic_hooks: {name}: failed to parse payload: {e}");
+        ApiError::invalid(format!("invalid JSON payload: {e}"))
+    })?;
+
+    let text = render(&hook.template, payload).map_err(|e| {
+        log::warn!("generic_hooks: {name}: failed to render template: {e}");
+        ApiError::internal()
+    })?;
+    if text.trim().is_empty() {
+        return Ok("ignored".to_string());
+    }
+    state
+        .bot
+        .send_message(hook.thread.chat, text)
+        .message_thread_id(hook.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await
+        .map_err(|e| {
+            log::error!("generic_hooks: {name}: failed to send message: {e}");
+            ApiError::internal()
+        })?;
+    Ok("ok".to_string())
+}
+
+/// Render the template with the payload.
+fn render(
+    template: &str,
+    payload: serde_json::Value,
+) -> Result<String, minijinja::Error> {
+    let mut env = Environment::new();
+    env.set_auto_escape_callback(|_| AutoEscape::Html);
+    let mut context = match &payload {
+        serde_json::Value::Object(map) => map.clone(),
+        _ => serde_json::Map::new(),
+    };
+    context.insert("payload".to_string(), payload);
+    env.render_str(template, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let payload = serde_json::json!({
+            "monitor": { "name": "Wiki <main>" },
+            "heartbeat": { "status": 0 },
+        });
+        assert_eq!(
+            render(
+                "{% if heartbeat.status == 0 %}🔴{% else %}🟢{% endif %} \
+                 <b>{{ monitor.name }}</b>",
+                payload,
+            )
+            .unwrap(),
+            "🔴 <b>Wiki &lt;main&gt;</b>",
+        );
+        assert_eq!(
+            render("{{ payload | length }}", serde_json::json!([1, 2]))
+                .unwrap(),
+            "2",
+        );
+        assert!(render("{% if %}", serde_json::json!({})).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! GitHub/Gitea webhook receiver.
+//!
+//! Pushes, issues and releases of the repositories listed in
+//! [`telegram.git_hooks.repos`] are posted to their threads.  Requests are
+//! authenticated by the HMAC-SHA256 signature made with
+//! [`telegram.git_hooks.secret`].
+//!
+//! [`telegram.git_hooks.repos`]: crate::config::GitHooks::repos
+//! [`telegram.git_hooks.secret`]: crate::config::GitHooks::secret
+
+use hmac::{Hmac, Mac as _};
+use salvo::Request;
+use salvo_oapi::endpoint;
+use serde::Deserialize;
+use sha2::Sha256;
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use super::{state, ApiError};
+use crate::utils::{format_to, html};
+
+/// Maximum number of commits listed in a push notification.
+const MAX_COMMITS: usize = 5;
+
+#[derive(Deserialize, Debug)]
+struct Payload {
+    repository: Repository,
+    sender: Option<Sender>,
+    action: Option<String>,
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+    #[serde(default)]
+    commits: Vec<Commit>,
+    /// Set by GitHub.
+    compare: Option<String>,
+    /// Set by Gitea.
+    compare_url: Option<String>,
+    issue: Option<Issue>,
+    release: Option<Release>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Repository {
+    full_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Sender {
+    login: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Commit {
+    id: String,
+    message: String,
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Issue {
+    number: u64,
+    title: String,
+    html_url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    tag_name: String,
+    name: Option<String>,
+    html_url: String,
+}
+
+/// Receive a GitHub or Gitea webhook.
+///
+/// Requires a valid `X-Hub-Signature-256` or `X-Gitea-Signature` header.
+#[endpoint()]
+pub async fn post_git_hook(req: &mut Request) -> Result<String, ApiError> {
+    let state = state();
+    let Some(conf) = &state.config.telegram.git_hooks else {
+        return Err(ApiError::not_found());
+    };
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let event = header("X-GitHub-Event").or_else(|| header("X-Gitea-Event"));
+    let signature =
+        header("X-Hub-Signature-256").or_else(|| header("X-Gitea-Signature"));
+    let body = req.payload().await.map_err(|e| {
+        log::warn!("git_hooks: failed to read body: {e}");
+        ApiError::invalid("failed to read body")
+    })?;
+
+    if !signature.is_some_and(|s| verify_signature(&conf.secret, body, &s)) {
+        return Err(ApiError::unauthorized());
+    }
+    let payload: Payload = serde_json::from_slice(body).map_err(|e| {
+        log::warn!("git_hooks: failed to parse payload: {e}");
+        ApiError::invalid(format!("invalid payload: {e}"))
+    })?;
+
+    let Some(repo) =
+        conf.repos.iter().find(|r| r.name == payload.repository.full_name)
+    else {
+        return Ok("ignored".to_string());
+    };
+    let Some(text) = event.and_then(|e| format_event(&e, &payload)) else {
+        return Ok("ignored".to_string());
+    };
+    state
+        .bot
+        .send_message(repo.thread.chat, text)
+        .message_thread_id(repo.thread.thread)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true)
+        .await
+        .map_err(|e| {
+            log::error!("git_hooks: failed to send message: {e}");
+            ApiError::internal()
+        })?;
+    Ok("ok".to_string())
+}
+
+/// Check the HMAC-SHA256 signature of the body.  The signature is a hex
+/// string, optionally prefixed with `sha256=`.
+fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    let Ok(signature) = hex::decode(signature) else { return false };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Format the event as a HTML message.  Returns `None` for events that
+/// should not be posted.
+fn format_event(event: &str, payload: &Payload) -> Option<String> {
+    let repo = html::escape(&payload.repository.full_name);
+    let sender = payload
+        .sender
+        .as_ref()
+        .map_or_else(|| "someone".to_string(), |s| html::escape(&s.login));
+    match event {
+        "push" => {
+            let branch =
+                payload.git_ref.as_ref()?.strip_prefix("refs/heads/")?;
+            if payload.commits.is_empty() {
+                return None;
+            }
+            let mut text = format!(
+                "🔨 <b>{repo}</b>: {sender} pushed {} to {}",
+                match payload.commits.len() {
+                    1 => "1 commit".to_string(),
+                    n => format!("{n} commits"),
+                },
+                html::code_inline(branch),
+            );
+            for commit in payload.commits.iter().take(MAX_COMMITS) {
+                format_to!(
+                    text,
+                    "\n• {} {}",
+                    html::link(
+                        &commit.url,
+                        commit.id.get(..7).unwrap_or(&commit.id)
+                    ),
+                    html::escape(
+                        commit.message.lines().next().unwrap_or_default()
+                    ),
+                );
+            }
+            if payload.commits.len() > MAX_COMMITS {
+                let more = payload.commits.len() - MAX_COMMITS;
+                match payload.compare.as_ref().or(payload.compare_url.as_ref())
+                {
+                    Some(url) => format_to!(
+                        text,
+                        "\n{}",
+                        html::link(url, &format!("…and {more} more"))
+                    ),
+                    None => format_to!(text, "\n…and {more} more"),
+                }
+            }
+            Some(text)
+        }
+        "issues" => {
+            let action = payload.action.as_deref()?;
+            if !["opened", "closed", "reopened"].contains(&action) {
+                return None;
+            }
+            let issue = payload.issue.as_ref()?;
+            Some(format!(
+                "🐛 <b>{repo}</b>: {sender} {action} issue {}",
+                html::link(
+                    &issue.html_url,
+                    &format!("#{} {}", issue.number, issue.title),
+                ),
+            ))
+        }
+        "release" => {
+            if payload.action.as_deref() != Some("published") {
+                return None;
+            }
+            let release = payload.release.as_ref()?;
+            let name = release
+                .name
+                .as_deref()
+                .filter(|n| !n.is_empty())
+                .unwrap_or(&release.tag_name);
+            Some(format!(
+                "🚀 <b>{repo}</b>: {sender} published release {}",
+                html::link(&release.html_url, name),
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(json: serde_json::Value) -> Payload {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        // Example from the GitHub documentation.
+        let signature = concat!(
+            "sha256=757107ea0eb2509fc211221cce984b8a",
+            "37570b6d7586c22c46f4379c8b043e17",
+        );
+        assert!(verify_signature(
+            "It's a Secret to Everybody",
+            b"Hello, World!",
+            signature
+        ));
+        assert!(verify_signature(
+            "It's a Secret to Everybody",
+            b"Hello, World!",
+            signature.strip_prefix("sha256=").unwrap(),
+        ));
+        assert!(!verify_signature("wrong", b"Hello, World!", signature));
+        assert!(!verify_signature("secret", b"", "not hex"));
+    }
+
+    #[test]
+    fn test_format_event() {
+        let push = payload(serde_json::json!({
+            "repository": { "full_name": "org/repo" },
+            "sender": { "login": "alice" },
+            "ref": "refs/heads/main",
+            "commits": [{
+                "id": "0123456789abcdef",
+                "message": "Fix <bug>\n\nDetails",
+                "url": "https://example.com/c/0123456",
+            }],
+        }));
+        assert_eq!(
+            format_event("push", &push).unwrap(),
+            "🔨 <b>org/repo</b>: alice pushed 1 commit to <code>main</code>\n\
+             • <a href=\"https://example.com/c/0123456\">0123456</a> \
+             Fix &lt;bug&gt;",
+        );
+        assert_eq!(format_event("ping", &push), None);
+
+        let tag = payload(serde_json::json!({
+            "repository": { "full_name": "org/repo" },
+            "ref": "refs/tags/v1.0",
+            "commits": [],
+        }));
+        assert_eq!(format_event("push", &tag), None);
+
+        let issue = payload(serde_json::json!({
+            "repository": { "full_name": "org/repo" },
+            "sender": { "login": "bob" },
+            "action": "opened",
+            "issue": {
+                "number": 42,
+                "title": "Broken",
+                "html_url": "https://example.com/i/42",
+            },
+        }));
+        assert_eq!(
+            format_event("issues", &issue).unwrap(),
+            "🐛 <b>org/repo</b>: bob opened issue \
+             <a href=\"https://example.com/i/42\">#42 Broken</a>",
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Liveness and readiness probes for container orchestration.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use diesel::{sql_query, RunQueryDsl};
+use salvo::http::StatusCode;
+use salvo::writing::Json;
+use salvo::Response;
+use salvo_oapi::{endpoint, ToSchema};
+use serde::Serialize;
+use teloxide::requests::Requester as _;
+
+use super::state;
+use crate::health::ServiceHealth;
+
+/// Timeout of the Telegram API check.
+const TELEGRAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Liveness {
+    status: &'static str,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Readiness {
+    /// `ok`, `degraded` if some external services fail, or `unavailable` if
+    /// the database or the Telegram API is not reachable.
+    status: &'static str,
+    database: Check,
+    telegram: Check,
+    /// Result of the last access to each external service, e.g. `mikrotik`,
+    /// `home_assistant` or `wikijs`.
+    services: BTreeMap<String, ServiceHealth>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl<E: std::fmt::Display> From<Result<(), E>> for Check {
+    fn from(result: Result<(), E>) -> Self {
+        match result {
+            Ok(()) => Self { ok: true, error: None },
+            Err(e) => Self { ok: false, error: Some(e.to_string()) },
+        }
+    }
+}
+
+/// Liveness probe.  Always succeeds while the HTTP server is running.
+#[endpoint()]
+pub async fn get_healthz() -> Json<Liveness> {
+    Json(Liveness { status: "ok" })
+}
+
+/// Readiness probe.  Checks the database and the Telegram API, and reports
+/// the health of external services.  Responds with 503 if the bot can't
+/// work.
+#[endpoint()]
+pub async fn get_readyz(res: &mut Response) -> Json<Readiness> {
+    let state = state();
+    let database: Check = sql_query("SELECT 1")
+        .execute(&mut *state.conn.lock().unwrap())
+        .map(|_| ())
+        .into();
+    let telegram: Check = match tokio::time::timeout(
+        TELEGRAM_TIMEOUT,
+        state.bot.get_me(),
+    )
+    .await
+    {
+        Ok(result) => result.map(|_| ()).into(),
+        Err(e) => Err::<(), _>(e).into(),
+    };
+    let services = state
+        .env
+        .health
+        .services()
+        .into_iter()
+        .map(|(name, health)| (name.to_string(), health))
+        .collect::<BTreeMap<_, _>>();
+
+    let status = if !database.ok || !telegram.ok {
+        res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+        "unavailable"
+    } else if services.values().any(|s| !s.ok) {
+        "degraded"
+    } else {
+        "ok"
+    };
+    Json(Readiness { status, database, telegram, services })
+}
@@ -0,0 +1,224 @@
+//! Key-value store for small integrations.
+//!
+//! Gadgets of the space, e.g. a door sign or a vending machine, can keep
+//! their state at `/kv/<namespace>/<key>` instead of running a backend of
+//! their own.  `PUT` stores the request body along with its `Content-Type`,
+//! `GET` returns it, and `DELETE` removes it.
+//!
+//! Each of the [`server_kv`] namespaces is accessed with its own token,
+//! passed in the `Authorization: Bearer <token>` header or the `token` query
+//! parameter, and has its own limits on the number and size of values.
+//! Writes are recorded in the [audit log](crate::modules::audit).
+//!
+//! Values are served as attachments in a sandbox, so a stored HTML page
+//! can't run scripts on the origin of the dashboard.
+//!
+//! [`server_kv`]: crate::config::Config::server_kv
+
+use diesel::prelude::*;
+use salvo::http::header::{
+    CONTENT_DISPOSITION, CONTENT_SECURITY_POLICY, CONTENT_TYPE,
+    X_CONTENT_TYPE_OPTIONS,
+};
+use salvo::http::HeaderValue;
+use salvo::{handler, Request, Response};
+
+use super::{has_token, state, ApiError};
+use crate::config::KvNamespace;
+use crate::schema;
+use crate::utils::ResultExt as _;
+
+const MAX_KEY_CHARS: usize = 200;
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// The namespace and the key of the request, if the token is valid.
+fn namespace_key(
+    req: &Request,
+) -> Result<(&'static KvNamespace, String), ApiError> {
+    let name = req.param::<String>("namespace").unwrap_or_default();
+    let Some(namespace) =
+        state().config.server_kv.iter().find(|n| n.name == name)
+    else {
+        return Err(ApiError::not_found());
+    };
+    if !has_token(req, &namespace.token) {
+        return Err(ApiError::unauthorized());
+    }
+    let key = req.param::<String>("key").unwrap_or_default();
+    if key.is_empty() || key.chars().count() > MAX_KEY_CHARS {
+        return Err(ApiError::invalid(format!(
+            "the key must be 1 to {MAX_KEY_CHARS} characters long"
+        )));
+    }
+    Ok((namespace, key))
+}
+
+/// Read a value.
+#[handler]
+pub async fn get_kv(
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<(), ApiError> {
+    let (namespace, key) = namespace_key(req)?;
+    let entry = load(&mut state().conn.lock().unwrap(), namespace, &key)?;
+    let Some((value, content_type)) = entry else {
+        return Err(ApiError::not_found());
+    };
+    let headers = res.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(&content_type)
+            .map_err(|_| ApiError::internal())?,
+    );
+    headers.insert(CONTENT_DISPOSITION, HeaderValue::from_static("attachment"));
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers
+        .insert(CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+    res.body(value);
+    Ok(())
+}
+
+/// Store a value.
+#[handler]
+pub async fn put_kv(req: &mut Request) -> Result<String, ApiError> {
+    let (namespace, key) = namespace_key(req)?;
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or(DEFAULT_CONTENT_TYPE)
+        .to_string();
+    let value = req.payload().await.map_err(|e| {
+        log::warn!("kv: failed to read body: {e}");
+        ApiError::invalid("failed to read body")
+    })?;
+    let mut conn = state().conn.lock().unwrap();
+    store(&mut conn, namespace, &key, value, &content_type)?;
+    crate::modules::audit::record(
+        &mut conn,
+        None,
+        "kv_put",
+        &serde_json::json!({
+            "namespace": namespace.name,
+            "key": key,
+            "content_type": content_type,
+            "bytes": value.len(),
+        }),
+    )
+    .log_error("kv: audit");
+    Ok("ok".to_string())
+}
+
+/// Delete a value.
+#[handler]
+pub async fn delete_kv(req: &mut Request) -> Result<String, ApiError> {
+    let (namespace, key) = namespace_key(req)?;
+    let mut conn = state().conn.lock().unwrap();
+    let deleted = diesel::delete(schema::kv_entries::table)
+        .filter(schema::kv_entries::namespace.eq(&namespace.name))
+        .filter(schema::kv_entries::key.eq(&key))
+        .execute(&mut *conn)?;
+    if deleted == 0 {
+        return Err(ApiError::not_found());
+    }
+    crate::modules::audit::record(
+        &mut conn,
+        None,
+        "kv_delete",
+        &serde_json::json!({ "namespace": namespace.name, "key": key }),
+    )
+    .log_error("kv: audit");
+    Ok("ok".to_string())
+}
+
+fn load(
+    conn: &mut SqliteConnection,
+    namespace: &KvNamespace,
+    key: &str,
+) -> QueryResult<Option<(Vec<u8>, String)>> {
+    schema::kv_entries::table
+        .filter(schema::kv_entries::namespace.eq(&namespace.name))
+        .filter(schema::kv_entries::key.eq(key))
+        .select((schema::kv_entries::value, schema::kv_entries::content_type))
+        .first(conn)
+        .optional()
+}
+
+/// Store the value within the limits of the namespace.
+fn store(
+    conn: &mut SqliteConnection,
+    namespace: &KvNamespace,
+    key: &str,
+    value: &[u8],
+    content_type: &str,
+) -> Result<(), ApiError> {
+    if value.len() > namespace.max_value_bytes {
+        return Err(ApiError::invalid(format!(
+            "the value is larger than {} bytes",
+            namespace.max_value_bytes
+        )));
+    }
+    conn.exclusive_transaction(|conn| {
+        let keys = schema::kv_entries::table
+            .filter(schema::kv_entries::namespace.eq(&namespace.name))
+            .select(schema::kv_entries::key)
+            .load::<String>(conn)?;
+        if keys.len() >= namespace.max_keys && !keys.iter().any(|k| k == key) {
+            return Ok(Err(ApiError::invalid(format!(
+                "the namespace already has {} keys",
+                namespace.max_keys
+            ))));
+        }
+        diesel::replace_into(schema::kv_entries::table)
+            .values((
+                schema::kv_entries::namespace.eq(&namespace.name),
+                schema::kv_entries::key.eq(key),
+                schema::kv_entries::value.eq(value),
+                schema::kv_entries::content_type.eq(content_type),
+                schema::kv_entries::updated_at
+                    .eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        Ok(Ok(()))
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_store() {
+        let mut conn = testing::memory_db();
+        let namespace = |name: &str| KvNamespace {
+            name: name.to_string(),
+            token: "secret".to_string(),
+            max_value_bytes: 8,
+            max_keys: 2,
+        };
+        let (namespace, other) = (namespace("door-sign"), namespace("vending"));
+
+        store(&mut conn, &namespace, "state", b"open", "text/plain").unwrap();
+        assert_eq!(
+            load(&mut conn, &namespace, "state").unwrap(),
+            Some((b"open".to_vec(), "text/plain".to_string())),
+        );
+        assert_eq!(load(&mut conn, &other, "state").unwrap(), None);
+
+        // Too large.
+        assert!(
+            store(&mut conn, &namespace, "state", b"closed!!!", "").is_err()
+        );
+        // Full, but existing keys can be overwritten.
+        store(&mut conn, &namespace, "since", b"18:00", "").unwrap();
+        assert!(store(&mut conn, &namespace, "note", b"", "").is_err());
+        store(&mut conn, &namespace, "state", b"closed", "").unwrap();
+        store(&mut conn, &other, "note", b"", "").unwrap();
+        assert_eq!(
+            load(&mut conn, &namespace, "state").unwrap(),
+            Some((b"closed".to_vec(), String::new())),
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! Backend of a Telegram Mini App.
+//!
+//! The app sends its [`initData`] to `POST /miniapp/auth` and gets a JWT
+//! valid for [`server_mini_app.session_hours`].  With the JWT in the
+//! `Authorization: Bearer <token>` header, the app reads the data of its
+//! user: items not returned yet, needs not bought yet, and open polls the
+//! user hasn't voted in.
+//!
+//! [`initData`]: https://core.telegram.org/bots/webapps#validating-data-received-via-the-mini-app
+//! [`server_mini_app.session_hours`]: crate::config::MiniApp::session_hours
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use hmac::{Hmac, Mac as _};
+use itertools::Itertools as _;
+use salvo::writing::Json;
+use salvo::Request;
+use salvo_oapi::{endpoint, ToSchema};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use teloxide::types::UserId;
+
+use super::dashboard::message_url;
+use super::{request_token, state, ApiError};
+use crate::config::MiniApp;
+use crate::db::DbUserId;
+use crate::{models, schema};
+
+/// How long the `initData` of an opened app is accepted, in seconds.
+const MAX_INIT_DATA_AGE: i64 = 24 * 60 * 60;
+
+const JWT_HEADER: &str = r#"{"alg":"HS256","typ":"JWT"}"#;
+
+fn config() -> Result<&'static MiniApp, ApiError> {
+    state().config.server_mini_app.as_ref().ok_or_else(ApiError::not_found)
+}
+
+#[derive(Deserialize, Debug)]
+struct AuthRequest {
+    init_data: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Session {
+    /// JWT to pass in the `Authorization: Bearer <token>` header.
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Exchange the `initData` of the mini app for a JWT.
+///
+/// The JSON payload has `init_data`, the `Telegram.WebApp.initData` string
+/// as is.
+#[endpoint()]
+pub async fn post_auth(req: &mut Request) -> Result<Json<Session>, ApiError> {
+    let conf = config()?;
+    let auth: AuthRequest = req
+        .parse_json()
+        .await
+        .map_err(|e| ApiError::invalid(format!("invalid JSON payload: {e}")))?;
+    let now = Utc::now();
+    let user = validate_init_data(
+        &state().config.telegram.token,
+        &auth.init_data,
+        now.timestamp(),
+    )
+    .ok_or_else(ApiError::unauthorized)?;
+    let expires_at = now + Duration::hours(i64::from(conf.session_hours));
+    Ok(Json(Session {
+        token: issue_jwt(
+            &conf.secret,
+            user,
+            now.timestamp(),
+            expires_at.timestamp(),
+        ),
+        expires_at,
+    }))
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MyBorrow {
+    /// Items that are not returned yet.
+    items: Vec<String>,
+    created_at: Option<NaiveDateTime>,
+    /// Link to the message of the bot, if the chat is a supergroup.
+    url: Option<String>,
+}
+
+/// Items borrowed by the user and not returned yet.
+///
+/// Requires `Authorization: Bearer <token>` header with a token from
+/// `/miniapp/auth`.
+#[endpoint()]
+pub async fn get_my_borrows(
+    req: &mut Request,
+) -> Result<Json<Vec<MyBorrow>>, ApiError> {
+    let user = DbUserId::from(session_user(req)?);
+    let borrows = schema::borrowed_items::table
+        .filter(schema::borrowed_items::user_id.eq(user))
+        .select(models::BorrowedItems::as_select())
+        .load(&mut *state().conn.lock().unwrap())?
+        .into_iter()
+        .filter_map(|b| {
+            let items = b
+                .items
+                .iter()
+                .filter(|i| i.returned.is_none())
+                .map(|i| i.name.clone())
+                .collect::<Vec<_>>();
+            (!items.is_empty()).then(|| MyBorrow {
+                items,
+                created_at: b.created_at,
+                url: message_url(b.chat_id, b.bot_message_id),
+            })
+        })
+        .collect();
+    Ok(Json(borrows))
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MyNeed {
+    id: i32,
+    item: String,
+    created_at: Option<NaiveDateTime>,
+    /// Link to the pinned shopping list, if the chat is a supergroup.
+    url: Option<String>,
+}
+
+/// Items requested by the user and not bought yet.
+///
+/// Requires `Authorization: Bearer <token>` header with a token from
+/// `/miniapp/auth`.
+#[endpoint()]
+pub async fn get_my_needs(
+    req: &mut Request,
+) -> Result<Json<Vec<MyNeed>>, ApiError> {
+    let user = DbUserId::from(session_user(req)?);
+    let needs = schema::needed_items::table
+        .filter(schema::needed_items::request_user_id.eq(user))
+        .filter(schema::needed_items::bought_at.is_null())
+        .order(schema::needed_items::rowid.asc())
+        .select(models::NeededItem::as_select())
+        .load(&mut *state().conn.lock().unwrap())?
+        .into_iter()
+        .map(|n| MyNeed {
+            id: n.rowid,
+            url: message_url(n.pinned_chat_id, n.pinned_message_id),
+            item: n.item,
+            created_at: n.created_at,
+        })
+        .collect();
+    Ok(Json(needs))
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PendingPoll {
+    /// Telegram poll ID.
+    poll_id: String,
+    /// Option texts, if known.
+    options: Option<Vec<String>>,
+    /// Link to the message listing pending voters, if the chat is a
+    /// supergroup.
+    url: Option<String>,
+}
+
+/// Open polls the user is expected to vote in but hasn't yet.  Only
+/// residents are expected to vote.
+///
+/// Requires `Authorization: Bearer <token>` header with a token from
+/// `/miniapp/auth`.
+#[endpoint()]
+pub async fn get_my_polls(
+    req: &mut Request,
+) -> Result<Json<Vec<PendingPoll>>, ApiError> {
+    let user = DbUserId::from(session_user(req)?);
+    let mut conn = state().conn.lock().unwrap();
+    let resident = schema::residents::table
+        .filter(schema::residents::tg_id.eq(user))
+        .filter(schema::residents::end_date.is_null())
+        .count()
+        .get_result::<i64>(&mut *conn)?
+        > 0;
+    if !resident {
+        return Ok(Json(Vec::new()));
+    }
+    let polls = schema::tracked_polls::table
+        .filter(schema::tracked_polls::closed_at.is_null())
+        .select(models::TrackedPoll::as_select())
+        .load(&mut *conn)?
+        .into_iter()
+        .filter(|p| !p.voted_users.contains(&user))
+        .map(|p| PendingPoll {
+            url: message_url(p.info_chat_id, p.info_message_id),
+            options: p.options.as_ref().map(|o| o.to_vec()),
+            poll_id: p.tg_poll_id,
+        })
+        .collect();
+    Ok(Json(polls))
+}
+
+/// The user of the JWT of the request.
+fn session_user(req: &Request) -> Result<UserId, ApiError> {
+    let conf = config()?;
+    request_token(req)
+        .and_then(|t| verify_jwt(&conf.secret, &t, Utc::now().timestamp()))
+        .ok_or_else(ApiError::unauthorized)
+}
+
+#[derive(Deserialize)]
+struct InitDataUser {
+    id: u64,
+}
+
+/// Check the signature and the age of the `initData` of a mini app and
+/// return its user.
+pub fn validate_init_data(
+    bot_token: &str,
+    init_data: &str,
+    now: i64,
+) -> Option<UserId> {
+    let mut hash = None;
+    let mut fields = Vec::new();
+    for pair in init_data.split('&') {
+        let (key, value) = pair.split_once('=')?;
+        let value = form_decode(value)?;
+        if key == "hash" {
+            hash = Some(value);
+        } else {
+            fields.push((key, value));
+        }
+    }
+    fields.sort_unstable();
+    let check_string =
+        fields.iter().map(|(key, value)| format!("{key}={value}")).join("\n");
+
+    let secret = Hmac::<Sha256>::new_from_slice(b"WebAppData")
+        .expect("HMAC accepts any key length")
+        .chain_update(bot_token)
+        .finalize()
+        .into_bytes();
+    Hmac::<Sha256>::new_from_slice(&secret)
+        .expect("HMAC accepts any key length")
+        .chain_update(check_string)
+        .verify_slice(&hex::decode(hash?).ok()?)
+        .ok()?;
+
+    let field = |name: &str| {
+        fields.iter().find(|(key, _)| *key == name).map(|(_, value)| value)
+    };
+    let auth_date: i64 = field("auth_date")?.parse().ok()?;
+    if now - auth_date > MAX_INIT_DATA_AGE {
+        return None;
+    }
+    let user: InitDataUser = serde_json::from_str(field("user")?).ok()?;
+    Some(UserId(user.id))
+}
+
+/// Decode a value of `application/x-www-form-urlencoded` data.
+fn form_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        bytes.push(match b {
+            b'+' => b' ',
+            b'%' => hex::decode([iter.next()?, iter.next()?]).ok()?[0],
+            b => b,
+        });
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    /// Telegram user ID.
+    sub: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// Issue an HS256 JWT for the user.
+fn issue_jwt(secret: &str, user: UserId, iat: i64, exp: i64) -> String {
+    let claims = Claims { sub: user.0.to_string(), iat, exp };
+    let claims = serde_json::to_vec(&claims).expect("claims are serializable");
+    let payload = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(JWT_HEADER),
+        URL_SAFE_NO_PAD.encode(claims),
+    );
+    let signature = jwt_mac(secret, &payload).finalize().into_bytes();
+    format!("{payload}.{}", URL_SAFE_NO_PAD.encode(signature))
+}
+
+/// Verify a JWT issued by [`issue_jwt`] and return its user.
+fn verify_jwt(secret: &str, token: &str, now: i64) -> Option<UserId> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    jwt_mac(secret, payload).verify_slice(&signature).ok()?;
+    let (header, claims) = payload.split_once('.')?;
+    if URL_SAFE_NO_PAD.decode(header).ok()? != JWT_HEADER.as_bytes() {
+        return None;
+    }
+    let claims: Claims =
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).ok()?).ok()?;
+    let user = UserId(claims.sub.parse().ok()?);
+    (now < claims.exp).then_some(user)
+}
+
+fn jwt_mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts any key length")
+        .chain_update(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sign `fields` the way Telegram does.
+    fn init_data(bot_token: &str, fields: &[(&str, &str)]) -> String {
+        let check_string = fields
+            .iter()
+            .sorted()
+            .map(|(key, value)| format!("{key}={value}"))
+            .join("\n");
+        let secret = Hmac::<Sha256>::new_from_slice(b"WebAppData")
+            .unwrap()
+            .chain_update(bot_token)
+            .finalize()
+            .into_bytes();
+        let hash = Hmac::<Sha256>::new_from_slice(&secret)
+            .unwrap()
+            .chain_update(check_string)
+            .finalize()
+            .into_bytes();
+        fields
+            .iter()
+            .map(|(key, value)| {
+                format!("{key}={}", value.replace('%', "%25").replace(' ', "+"))
+            })
+            .chain([format!("hash={}", hex::encode(hash))])
+            .join("&")
+    }
+
+    #[test]
+    fn test_validate_init_data() {
+        let data = init_data(
+            "123:abc",
+            &[
+                ("query_id", "AAH"),
+                ("user", r#"{"id":42,"first_name":"Alice 100%"}"#),
+                ("auth_date", "1000"),
+            ],
+        );
+        assert_eq!(
+            validate_init_data("123:abc", &data, 2000),
+            Some(UserId(42))
+        );
+        assert_eq!(validate_init_data("123:abd", &data, 2000), None);
+        assert_eq!(
+            validate_init_data("123:abc", &data, 1000 + MAX_INIT_DATA_AGE + 1),
+            None
+        );
+        let forged = data.replacen("42", "43", 1);
+        assert_eq!(validate_init_data("123:abc", &forged, 2000), None);
+        assert_eq!(validate_init_data("123:abc", "", 2000), None);
+    }
+
+    #[test]
+    fn test_jwt() {
+        let token = issue_jwt("key", UserId(42), 900, 1000);
+        assert_eq!(verify_jwt("key", &token, 999), Some(UserId(42)));
+        assert_eq!(verify_jwt("key", &token, 1000), None);
+        assert_eq!(verify_jwt("other", &token, 999), None);
+        let (payload, signature) = token.rsplit_once('.').unwrap();
+        let forged_claims =
+            URL_SAFE_NO_PAD.encode(r#"{"sub":"43","iat":900,"exp":1000}"#);
+        let header = payload.split_once('.').unwrap().0;
+        let forged = format!("{header}.{forged_claims}.{signature}");
+        assert_eq!(verify_jwt("key", &forged, 999), None);
+    }
+}
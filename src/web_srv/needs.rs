@@ -0,0 +1,118 @@
+//! Live shopping list for the info screen.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use salvo::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use salvo::http::HeaderValue;
+use salvo::{handler, Response};
+use serde::Serialize;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use super::{state, ApiError};
+use crate::events::Event;
+use crate::{models, schema};
+
+/// Interval between keep-alive comments, so proxies don't drop idle
+/// connections.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Debug)]
+pub struct NeedItem {
+    id: i32,
+    item: String,
+    /// First name of the resident who requested the item.
+    requested_by: Option<String>,
+    created_at: Option<NaiveDateTime>,
+}
+
+/// Stream the shopping list as Server-Sent Events.
+///
+/// A `needs` event with a JSON array of [`NeedItem`] is sent on connect and
+/// after each change of the list.
+#[handler]
+pub async fn get_needs_stream(res: &mut Response) -> Result<(), ApiError> {
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    res.headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    // The stream outlives the request, so it can't use `state()`.
+    let state = state();
+    let stream = futures::stream::unfold(
+        (state.env.events.subscribe(), true),
+        move |(mut rx, first)| async move {
+            if !first {
+                match next_change(&mut rx, &state.cancel).await? {
+                    Change::Changed => {}
+                    Change::KeepAlive => {
+                        return Some((
+                            Ok::<_, Infallible>(": keep-alive\n\n".to_string()),
+                            (rx, false),
+                        ));
+                    }
+                }
+            }
+            let needs = load(&mut state.conn.lock().unwrap())
+                .map_err(|e| log::error!("needs stream: {e}"))
+                .ok()?;
+            let json = serde_json::to_string(&needs).ok()?;
+            Some((Ok(format!("event: needs\ndata: {json}\n\n")), (rx, false)))
+        },
+    );
+    res.streaming(stream).map_err(|e| {
+        log::error!("needs stream: {e}");
+        ApiError::internal()
+    })
+}
+
+enum Change {
+    Changed,
+    KeepAlive,
+}
+
+/// Wait for the next change of the list.  Returns `None` on shutdown.
+async fn next_change(
+    rx: &mut Receiver<Event>,
+    cancel: &CancellationToken,
+) -> Option<Change> {
+    select! {
+        event = rx.recv() => match event {
+            Ok(Event::NeedsChanged) | Err(RecvError::Lagged(_)) => {
+                Some(Change::Changed)
+            }
+            Err(RecvError::Closed) => None,
+        },
+        () = sleep(KEEP_ALIVE) => Some(Change::KeepAlive),
+        () = cancel.cancelled() => None,
+    }
+}
+
+fn load(conn: &mut SqliteConnection) -> QueryResult<Vec<NeedItem>> {
+    let items: Vec<(models::NeededItem, Option<String>)> =
+        schema::needed_items::table
+            .left_join(schema::tg_users::table.on(
+                schema::tg_users::id.eq(schema::needed_items::request_user_id),
+            ))
+            .filter(schema::needed_items::buyer_user_id.is_null())
+            .order(schema::needed_items::rowid)
+            .select((
+                models::NeededItem::as_select(),
+                schema::tg_users::first_name.nullable(),
+            ))
+            .load(conn)?;
+    Ok(items
+        .into_iter()
+        .map(|(item, requested_by)| NeedItem {
+            id: item.rowid,
+            item: item.item,
+            requested_by,
+            created_at: item.created_at,
+        })
+        .collect())
+}
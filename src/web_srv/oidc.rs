@@ -0,0 +1,381 @@
+//! OpenID Connect login for the dashboard and the write APIs.
+//!
+//! The [`server_dashboard.oidc`] provider, e.g. Authentik or Keycloak, is
+//! used with the authorization code flow.  Subjects of the provider are
+//! mapped to Telegram users in the `oidc_links` table.  To link an account,
+//! the user sends `/link` to the bot and opens the returned link, which
+//! logs in at the provider with a one-time code.
+//!
+//! Roles come from the data of the bot: [`telegram.admins`] are admins, and
+//! current residents are residents.  The write APIs also accept access
+//! tokens of the provider, see [`token_user`].
+//!
+//! [`server_dashboard.oidc`]: crate::config::Dashboard::oidc
+//! [`telegram.admins`]: crate::config::Telegram::admins
+
+use anyhow::Result;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rand_core::{OsRng, RngCore as _};
+use reqwest::{StatusCode, Url};
+use salvo::http::StatusCode as HttpStatusCode;
+use salvo::writing::{Redirect, Text};
+use salvo::{handler, Request, Response};
+use serde::Deserialize;
+use teloxide::types::UserId;
+
+use super::dashboard::{cookie, set_cookie, start_session};
+use super::{state, ApiError};
+use crate::config::{Config, Dashboard, Oidc};
+use crate::db::DbUserId;
+use crate::schema;
+
+const STATE_COOKIE: &str = "botka_oidc";
+
+/// How long the login at the provider may take, in seconds.
+const STATE_MAX_AGE: i64 = 10 * 60;
+
+/// How long a code of `/link` is valid.
+pub const LINK_CODE_MINUTES: i64 = 10;
+
+/// Role of a user, in the order of increasing privileges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Resident,
+    Admin,
+}
+
+/// Role of the user, if any.
+pub fn user_role(
+    conn: &mut SqliteConnection,
+    config: &Config,
+    user: UserId,
+) -> QueryResult<Option<Role>> {
+    if config.telegram.admins.contains(&user) {
+        return Ok(Some(Role::Admin));
+    }
+    let resident = schema::residents::table
+        .filter(schema::residents::tg_id.eq(DbUserId::from(user)))
+        .filter(schema::residents::end_date.is_null())
+        .count()
+        .get_result::<i64>(conn)?
+        > 0;
+    Ok(resident.then_some(Role::Resident))
+}
+
+/// Build a link to log in at the provider and link the account to the user.
+pub fn link_url(
+    conn: &mut SqliteConnection,
+    conf: &Dashboard,
+    user: UserId,
+) -> QueryResult<String> {
+    let code = random_hex();
+    let expires_at =
+        Utc::now().naive_utc() + chrono::Duration::minutes(LINK_CODE_MINUTES);
+    diesel::insert_into(schema::oidc_link_codes::table)
+        .values((
+            schema::oidc_link_codes::code.eq(&code),
+            schema::oidc_link_codes::tg_id.eq(DbUserId::from(user)),
+            schema::oidc_link_codes::expires_at.eq(expires_at),
+        ))
+        .execute(conn)?;
+    Ok(format!(
+        "{}/dashboard/oidc/login?link={code}",
+        conf.public_url.trim_end_matches('/'),
+    ))
+}
+
+fn random_hex() -> String {
+    let mut bytes = [0; 16];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn config() -> Result<(&'static Dashboard, &'static Oidc), ApiError> {
+    let conf = state().config.server_dashboard.as_ref();
+    conf.and_then(|c| Some((c, c.oidc.as_ref()?)))
+        .ok_or_else(ApiError::not_found)
+}
+
+fn redirect_uri(conf: &Dashboard) -> String {
+    format!("{}/dashboard/oidc/callback", conf.public_url.trim_end_matches('/'))
+}
+
+/// Redirect to the provider.  The `link` parameter is a code of `/link`.
+#[handler]
+pub async fn get_login(
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<(), ApiError> {
+    let (conf, oidc) = config()?;
+    let discovery = discovery(oidc).await?;
+    let csrf = random_hex();
+    let link = req.query::<String>("link").unwrap_or_default();
+    if !link.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::invalid("invalid link code"));
+    }
+    let url = Url::parse_with_params(
+        &discovery.authorization_endpoint,
+        [
+            ("response_type", "code"),
+            ("client_id", oidc.client_id.as_str()),
+            ("redirect_uri", redirect_uri(conf).as_str()),
+            ("scope", "openid"),
+            ("state", csrf.as_str()),
+        ],
+    )
+    .map_err(|e| {
+        log::error!("oidc: invalid authorization endpoint: {e}");
+        ApiError::internal()
+    })?;
+    set_cookie(
+        res,
+        conf,
+        STATE_COOKIE,
+        &format!("{csrf}.{link}"),
+        STATE_MAX_AGE,
+    )?;
+    res.render(Redirect::found(url.as_str()));
+    Ok(())
+}
+
+/// Finish the login at the provider: link the account if the login was
+/// started with a code of `/link`, and start a session of the linked user.
+#[handler]
+pub async fn get_callback(
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<(), ApiError> {
+    let (conf, oidc) = config()?;
+    let (csrf, link) = cookie(req, STATE_COOKIE)
+        .and_then(|c| c.split_once('.'))
+        .map(|(csrf, link)| (csrf.to_string(), link.to_string()))
+        .unwrap_or_default();
+    if csrf.is_empty() || req.query::<String>("state") != Some(csrf) {
+        return Err(ApiError::unauthorized());
+    }
+    let Some(code) = req.query::<String>("code") else {
+        return Err(ApiError::invalid("missing code"));
+    };
+    let discovery = discovery(oidc).await?;
+    let subject = login_subject(discovery, oidc, &redirect_uri(conf), &code)
+        .await
+        .map_err(|e| {
+            log::warn!("oidc: failed to log in: {e:#}");
+            ApiError::unauthorized()
+        })?;
+
+    let now = Utc::now();
+    let user = {
+        let mut conn = state().conn.lock().unwrap();
+        if !link.is_empty()
+            && !link_subject(&mut conn, &link, &subject, now.naive_utc())?
+        {
+            return Err(ApiError::invalid(
+                "the link has expired, send /link to the bot again",
+            ));
+        }
+        linked_user(&mut conn, &subject)?
+    };
+    set_cookie(res, conf, STATE_COOKIE, "", 0)?;
+    let Some(user) = user else {
+        res.status_code(HttpStatusCode::UNAUTHORIZED);
+        res.render(Text::Html(
+            "<!doctype html><p>This account is not linked yet.  Send \
+             <code>/link</code> to the bot in a private chat and open the \
+             link.</p>",
+        ));
+        return Ok(());
+    };
+    start_session(res, conf, user, now.timestamp())
+}
+
+/// The linked user of an access token of the provider and their role, if
+/// any.
+pub async fn token_user(
+    oidc: &Oidc,
+    token: &str,
+) -> Result<Option<(UserId, Role)>, ApiError> {
+    let discovery = discovery(oidc).await?;
+    let subject = userinfo(discovery, token).await.map_err(|e| {
+        log::error!("oidc: failed to read user info: {e:#}");
+        ApiError::internal()
+    })?;
+    let Some(subject) = subject else { return Ok(None) };
+    let mut conn = state().conn.lock().unwrap();
+    let Some(user) = linked_user(&mut conn, &subject)? else {
+        return Ok(None);
+    };
+    let role = user_role(&mut conn, &state().config, user)?;
+    Ok(role.map(|role| (user, role)))
+}
+
+/// Consume the code of `/link` and link the subject to its user.  Returns
+/// `false` if the code is unknown or expired.
+fn link_subject(
+    conn: &mut SqliteConnection,
+    code: &str,
+    subject: &str,
+    now: NaiveDateTime,
+) -> QueryResult<bool> {
+    conn.immediate_transaction(|conn| {
+        let user = schema::oidc_link_codes::table
+            .filter(schema::oidc_link_codes::code.eq(code))
+            .filter(schema::oidc_link_codes::expires_at.gt(now))
+            .select(schema::oidc_link_codes::tg_id)
+            .first::<DbUserId>(conn)
+            .optional()?;
+        diesel::delete(schema::oidc_link_codes::table)
+            .filter(
+                schema::oidc_link_codes::code
+                    .eq(code)
+                    .or(schema::oidc_link_codes::expires_at.le(now)),
+            )
+            .execute(conn)?;
+        let Some(user) = user else { return Ok(false) };
+        diesel::replace_into(schema::oidc_links::table)
+            .values((
+                schema::oidc_links::subject.eq(subject),
+                schema::oidc_links::tg_id.eq(user),
+                schema::oidc_links::linked_at.eq(now),
+            ))
+            .execute(conn)?;
+        Ok(true)
+    })
+}
+
+fn linked_user(
+    conn: &mut SqliteConnection,
+    subject: &str,
+) -> QueryResult<Option<UserId>> {
+    Ok(schema::oidc_links::table
+        .filter(schema::oidc_links::subject.eq(subject))
+        .select(schema::oidc_links::tg_id)
+        .first::<DbUserId>(conn)
+        .optional()?
+        .map(UserId::from))
+}
+
+#[derive(Deserialize)]
+pub(super) struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct UserInfo {
+    sub: String,
+}
+
+/// The discovery document of the provider, read once.
+async fn discovery(oidc: &Oidc) -> Result<&'static Discovery, ApiError> {
+    state()
+        .oidc_discovery
+        .get_or_try_init(|| async {
+            let result = reqwest::get(format!(
+                "{}/.well-known/openid-configuration",
+                oidc.issuer.trim_end_matches('/'),
+            ))
+            .await
+            .and_then(reqwest::Response::error_for_status);
+            crate::metrics::update_service(
+                &state().env.health,
+                "oidc",
+                result.is_ok(),
+            );
+            result?.json::<Discovery>().await
+        })
+        .await
+        .map_err(|e| {
+            log::error!("oidc: failed to read the discovery document: {e}");
+            ApiError::internal()
+        })
+}
+
+/// Exchange the authorization code for an access token and return the
+/// subject of its user.
+async fn login_subject(
+    discovery: &Discovery,
+    oidc: &Oidc,
+    redirect_uri: &str,
+    code: &str,
+) -> Result<String> {
+    let token: TokenResponse = reqwest::Client::new()
+        .post(&discovery.token_endpoint)
+        .basic_auth(&oidc.client_id, Some(&oidc.client_secret))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    userinfo(discovery, &token.access_token)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("the new access token is rejected"))
+}
+
+/// Subject of the access token, or `None` if the provider rejects it.
+async fn userinfo(
+    discovery: &Discovery,
+    token: &str,
+) -> Result<Option<String>> {
+    let resp = reqwest::Client::new()
+        .get(&discovery.userinfo_endpoint)
+        .bearer_auth(token)
+        .send()
+        .await;
+    crate::metrics::update_service(&state().env.health, "oidc", resp.is_ok());
+    let resp = resp?;
+    if matches!(resp.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+    {
+        return Ok(None);
+    }
+    let info: UserInfo = resp.error_for_status()?.json().await?;
+    Ok(Some(info.sub))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_link_subject() {
+        let mut conn = testing::memory_db();
+        let now = Utc::now().naive_utc();
+        let link = |conn: &mut SqliteConnection, code: &str, user: u64| {
+            diesel::insert_into(schema::oidc_link_codes::table)
+                .values((
+                    schema::oidc_link_codes::code.eq(code),
+                    schema::oidc_link_codes::tg_id
+                        .eq(DbUserId::from(UserId(user))),
+                    schema::oidc_link_codes::expires_at
+                        .eq(now + chrono::Duration::minutes(1)),
+                ))
+                .execute(conn)
+                .unwrap();
+        };
+
+        link(&mut conn, "aa", 1);
+        assert!(link_subject(&mut conn, "aa", "alice", now).unwrap());
+        assert_eq!(linked_user(&mut conn, "alice").unwrap(), Some(UserId(1)));
+        // One-time.
+        assert!(!link_subject(&mut conn, "aa", "mallory", now).unwrap());
+        assert_eq!(linked_user(&mut conn, "mallory").unwrap(), None);
+        // Expired.
+        link(&mut conn, "bb", 2);
+        let later = now + chrono::Duration::minutes(2);
+        assert!(!link_subject(&mut conn, "bb", "alice", later).unwrap());
+        assert_eq!(linked_user(&mut conn, "alice").unwrap(), Some(UserId(1)));
+    }
+}
@@ -0,0 +1,190 @@
+//! Poll creation endpoint for external tools, e.g. meeting software.
+
+use salvo::writing::Json;
+use salvo::Request;
+use salvo_oapi::{endpoint, ToSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use teloxide::types::{ChatId, MessageId, ThreadId};
+
+use super::oidc::Role;
+use super::{authorize, state, ApiError};
+use crate::config::Config;
+use crate::modules::polls::{create_poll, PollTags};
+use crate::utils::ResultExt as _;
+
+/// Telegram limits for polls.
+const MAX_QUESTION_LEN: usize = 300;
+const MAX_OPTION_LEN: usize = 100;
+const OPTIONS_RANGE: std::ops::RangeInclusive<usize> = 2..=10;
+
+#[derive(Deserialize, Debug)]
+struct NewPoll {
+    chat_id: i64,
+    #[serde(default)]
+    thread_id: Option<i32>,
+    question: String,
+    options: Vec<String>,
+    #[serde(default)]
+    allows_multiple_answers: bool,
+    #[serde(default)]
+    private: Option<bool>,
+    #[serde(default)]
+    announce: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedPoll {
+    /// Telegram poll ID.
+    poll_id: String,
+    /// ID of the poll message in the chat.
+    poll_message_id: i32,
+    /// ID of the message listing residents who haven't voted yet.
+    info_message_id: i32,
+}
+
+/// Create a tracked poll.
+///
+/// The JSON payload has `chat_id`, optional `thread_id`, `question`,
+/// `options` (2 to 10 strings), optional `allows_multiple_answers`, and
+/// optional `private` to show only counts of voters in the info message,
+/// by default as set for the chat, and optional `announce` to cross-post
+/// the poll to the announcement topics.  The poll is sent by the bot and is
+/// not anonymous.
+///
+/// Requires `Authorization: Bearer <token>` header with the
+/// `server_api_token` or an OIDC access token of a resident.  Residents can
+/// create polls only in residential chats, other chats return 403.
+#[endpoint()]
+pub async fn post_polls(
+    req: &mut Request,
+) -> Result<Json<CreatedPoll>, ApiError> {
+    let caller = authorize(req, Role::Resident).await?;
+    let poll: NewPoll = req
+        .parse_json()
+        .await
+        .map_err(|e| ApiError::invalid(format!("invalid JSON payload: {e}")))?;
+    validate(&poll).map_err(ApiError::invalid)?;
+    check_chat(&state().config, caller.map(|(_, role)| role), &poll)?;
+
+    let state = state();
+    let created = create_poll(
+        &state.bot,
+        &state.env,
+        ChatId(poll.chat_id),
+        poll.thread_id.map(|t| ThreadId(MessageId(t))),
+        &poll.question,
+        poll.options,
+        poll.allows_multiple_answers,
+        PollTags { private: poll.private, announce: poll.announce },
+    )
+    .await
+    .map_err(|e| {
+        log::error!("web_srv: failed to create poll: {e:#}");
+        ApiError::internal()
+    })?;
+    crate::modules::audit::record(
+        &mut state.conn.lock().unwrap(),
+        caller.map(|(user, _)| user),
+        "poll_create",
+        &json!({
+            "chat_id": poll.chat_id,
+            "thread_id": poll.thread_id,
+            "question": poll.question,
+            "poll_id": created.poll_id,
+            "via": if caller.is_some() { "oidc" } else { "api_token" },
+        }),
+    )
+    .log_error("web_srv: audit");
+    Ok(Json(CreatedPoll {
+        poll_id: created.poll_id,
+        poll_message_id: created.poll_message.0,
+        info_message_id: created.info_message.0,
+    }))
+}
+
+/// Residents may create polls only in residential chats; other chats require
+/// the `server_api_token` or an admin.
+fn check_chat(
+    config: &Config,
+    role: Option<Role>,
+    poll: &NewPoll,
+) -> Result<(), ApiError> {
+    let residential =
+        config.telegram.chats.residential.contains(&ChatId(poll.chat_id));
+    match role {
+        Some(Role::Resident) if !residential => Err(ApiError::forbidden()),
+        _ => Ok(()),
+    }
+}
+
+fn validate(poll: &NewPoll) -> Result<(), String> {
+    let len = |s: &str| s.chars().count();
+    if !(1..=MAX_QUESTION_LEN).contains(&len(poll.question.trim())) {
+        return Err(format!(
+            "question must be 1 to {MAX_QUESTION_LEN} characters long"
+        ));
+    }
+    if !OPTIONS_RANGE.contains(&poll.options.len()) {
+        return Err(format!(
+            "there must be {} to {} options",
+            OPTIONS_RANGE.start(),
+            OPTIONS_RANGE.end(),
+        ));
+    }
+    if poll
+        .options
+        .iter()
+        .any(|o| !(1..=MAX_OPTION_LEN).contains(&len(o.trim())))
+    {
+        return Err(format!(
+            "options must be 1 to {MAX_OPTION_LEN} characters long"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web_srv::error::ErrorCode;
+
+    const RESIDENTIAL: i64 = -1_001_234_567_890;
+
+    fn poll(question: &str, options: &[&str]) -> NewPoll {
+        NewPoll {
+            chat_id: RESIDENTIAL,
+            thread_id: None,
+            question: question.to_string(),
+            options: options.iter().map(ToString::to_string).collect(),
+            allows_multiple_answers: false,
+            private: None,
+            announce: false,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate(&poll("Pizza?", &["Yes", "No"])), Ok(()));
+        assert!(validate(&poll(" ", &["Yes", "No"])).is_err());
+        assert!(validate(&poll("Pizza?", &["Yes"])).is_err());
+        assert!(validate(&poll("Pizza?", &["Yes", ""])).is_err());
+        assert!(validate(&poll(&"?".repeat(301), &["Yes", "No"])).is_err());
+    }
+
+    #[test]
+    fn test_check_chat() {
+        let config: Config =
+            serde_yaml::from_str(include_str!("../../config.example.yaml"))
+                .unwrap();
+        let residential = poll("Pizza?", &["Yes", "No"]);
+        let mut unknown = poll("Pizza?", &["Yes", "No"]);
+        unknown.chat_id = -1_009_999_999_999;
+
+        assert!(check_chat(&config, Some(Role::Resident), &residential).is_ok());
+        let error = check_chat(&config, Some(Role::Resident), &unknown);
+        assert_eq!(error.unwrap_err().code, ErrorCode::Forbidden);
+        assert!(check_chat(&config, Some(Role::Admin), &unknown).is_ok());
+        assert!(check_chat(&config, None, &unknown).is_ok());
+    }
+}
@@ -0,0 +1,140 @@
+//! Residents endpoints for membership tooling.
+
+use chrono::{NaiveDate, NaiveTime};
+use diesel::prelude::*;
+use itertools::Itertools as _;
+use salvo::writing::Json;
+use salvo_oapi::{endpoint, ToParameters};
+use serde::Deserialize;
+
+use super::{state, ApiError};
+use crate::db::DbUserId;
+use crate::{models, schema};
+
+/// Maximum value of the `per_page` parameter.
+const MAX_PER_PAGE: i64 = 500;
+
+#[derive(Deserialize, Debug, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct ResidentsQuery {
+    /// Only include residents who were residents at any time during this
+    /// day (UTC), e.g. `2024-01-31`.
+    active_at: Option<NaiveDate>,
+    /// Only include the resident with this Telegram username, with or
+    /// without the leading `@`.  Case-insensitive.
+    username: Option<String>,
+    /// Page number, starting from 0.
+    page: Option<i64>,
+    /// Number of entries per page.  If omitted, all entries are returned.
+    per_page: Option<i64>,
+}
+
+type Boxed<'a> = diesel::dsl::IntoBoxed<
+    'a,
+    diesel::dsl::LeftJoinOn<
+        schema::residents::table,
+        schema::tg_users::table,
+        diesel::dsl::Eq<schema::residents::tg_id, schema::tg_users::id>,
+    >,
+    diesel::sqlite::Sqlite,
+>;
+
+impl ResidentsQuery {
+    /// Residents joined with their users, filtered and paginated.
+    fn apply(&self) -> Boxed<'_> {
+        use schema::residents::dsl as r;
+        use schema::tg_users::dsl as u;
+        let mut query = r::residents
+            .left_join(u::tg_users.on(r::tg_id.eq(u::id)))
+            .into_boxed();
+        if let Some(date) = self.active_at {
+            let start = date.and_time(NaiveTime::MIN);
+            let end = start + chrono::Duration::days(1);
+            query = query
+                .filter(r::begin_date.lt(end))
+                .filter(r::end_date.is_null().or(r::end_date.ge(start)));
+        }
+        if let Some(username) = &self.username {
+            let username = username.strip_prefix('@').unwrap_or(username);
+            query = query
+                .filter(u::username.like(like_exact(username)).escape('\\'));
+        }
+        if let Some(per_page) = self.per_page {
+            let per_page = per_page.clamp(1, MAX_PER_PAGE);
+            let page = self.page.unwrap_or(0).max(0);
+            query = query.limit(per_page).offset(page * per_page);
+        }
+        query.order((r::begin_date.desc(), r::rowid.desc()))
+    }
+}
+
+/// Get a list of current residents, or residents at the `active_at` date.
+#[endpoint()]
+pub async fn get_residents_v0(
+    query: ResidentsQuery,
+) -> Result<Json<Vec<models::DataResident>>, ApiError> {
+    let mut q = query.apply();
+    if query.active_at.is_none() {
+        q = q.filter(schema::residents::end_date.is_null());
+    }
+    let residents: Vec<(DbUserId, Option<models::TgUser>)> = q
+        .select((
+            schema::residents::tg_id,
+            schema::tg_users::all_columns.nullable(),
+        ))
+        .load(&mut *state().conn.lock().unwrap())?;
+
+    let residents = residents
+        .into_iter()
+        // A resident may have left and returned within the same day.
+        .unique_by(|(id, _)| *id)
+        .filter_map(|(id, user)| {
+            let user = user?;
+            Some(models::DataResident {
+                id: id.into(),
+                username: user.username,
+                first_name: user.first_name,
+                last_name: user.last_name,
+            })
+        })
+        .collect_vec();
+
+    Ok(Json(residents))
+}
+
+/// Get a list of current and past residents.
+/// The same resident may appear multiple times if they have left and returned.
+#[endpoint()]
+pub async fn get_all_residents_v0(
+    query: ResidentsQuery,
+) -> Result<Json<Vec<models::Resident>>, ApiError> {
+    let residents = query
+        .apply()
+        .select(schema::residents::all_columns)
+        .load(&mut *state().conn.lock().unwrap())?;
+    Ok(Json(residents))
+}
+
+/// Build a `LIKE` pattern matching `s` literally, with `\` as escape.
+fn like_exact(s: &str) -> String {
+    let mut pattern = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_like_exact() {
+        assert_eq!(like_exact("alice"), "alice");
+        assert_eq!(like_exact("john_doe"), "john\\_doe");
+        assert_eq!(like_exact("100%"), "100\\%");
+    }
+}
@@ -0,0 +1,174 @@
+//! Historical statistics for external dashboards, e.g. Grafana.
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use salvo::writing::Json;
+use salvo_oapi::{endpoint, ToParameters, ToSchema};
+use serde::{Deserialize, Serialize};
+
+use super::state;
+use crate::utils::Sqlizer;
+use crate::{models, schema};
+
+#[derive(Deserialize, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeseriesMetric {
+    /// Number of people in the space.
+    Presence,
+    /// Number of items in the shopping list.
+    Needs,
+    /// Number of borrowed and not yet returned items.
+    Borrows,
+}
+
+#[derive(Deserialize, Debug, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+pub struct TimeseriesQuery {
+    /// Metric to query.
+    metric: TimeseriesMetric,
+    /// Start of the interval (UTC). Defaults to 7 days before `to`.
+    from: Option<NaiveDateTime>,
+    /// End of the interval (UTC). Defaults to now.
+    to: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, ToSchema)]
+pub struct TimeseriesPoint {
+    timestamp: NaiveDateTime,
+    value: i64,
+}
+
+/// Get a time series of the given metric.
+///
+/// For `needs` and `borrows`, a point is emitted on each change, plus the
+/// initial value at the start of the interval.
+#[endpoint()]
+pub async fn get_stats_timeseries(
+    query: TimeseriesQuery,
+) -> Json<Vec<TimeseriesPoint>> {
+    let to = query.to.unwrap_or_else(|| Utc::now().naive_utc());
+    let from = query.from.unwrap_or(to - Duration::days(7));
+    let conn = &mut *state().conn.lock().unwrap();
+
+    let points = match query.metric {
+        TimeseriesMetric::Presence => {
+            use schema::presence_log::dsl as p;
+            p::presence_log
+                .filter(p::timestamp.between(from, to))
+                .order(p::timestamp.asc())
+                .select((p::timestamp, p::person_count))
+                .load::<(NaiveDateTime, i32)>(conn)?
+                .into_iter()
+                .map(|(timestamp, value)| TimeseriesPoint {
+                    timestamp,
+                    value: value.into(),
+                })
+                .collect()
+        }
+        TimeseriesMetric::Needs => {
+            use schema::needed_items::dsl as n;
+            let items = n::needed_items
+                .filter(n::created_at.is_not_null())
+                .select((n::created_at, n::bought_at))
+                .load::<(Option<NaiveDateTime>, Option<NaiveDateTime>)>(conn)?;
+            let events = items.into_iter().flat_map(|(created, bought)| {
+                created
+                    .map(|t| (t, 1))
+                    .into_iter()
+                    .chain(bought.map(|t| (t, -1)))
+            });
+            running_count(events, from, to)
+        }
+        TimeseriesMetric::Borrows => {
+            use schema::borrowed_items::dsl as b;
+            let items =
+                b::borrowed_items
+                    .filter(b::created_at.is_not_null())
+                    .select((b::created_at, b::items))
+                    .load::<(
+                        Option<NaiveDateTime>,
+                        Sqlizer<Vec<models::BorrowedItem>>,
+                    )>(conn)?;
+            let mut events = Vec::new();
+            for (created, items) in items {
+                let Some(created) = created else { continue };
+                for item in items.iter() {
+                    events.push((created, 1));
+                    if let Some(returned) = item.returned {
+                        events.push((returned.naive_utc(), -1));
+                    }
+                }
+            }
+            running_count(events.into_iter(), from, to)
+        }
+    };
+
+    Ok(Json(points))
+}
+
+/// Convert a list of `(time, delta)` events into a running total within
+/// the interval `[from, to]`.
+fn running_count(
+    events: impl Iterator<Item = (NaiveDateTime, i64)>,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Vec<TimeseriesPoint> {
+    let mut events = events.collect::<Vec<_>>();
+    events.sort_by_key(|(t, _)| *t);
+
+    let mut value = 0;
+    let mut points = Vec::new();
+    for (timestamp, delta) in events {
+        if timestamp > to {
+            break;
+        }
+        if timestamp >= from && points.is_empty() {
+            points.push(TimeseriesPoint { timestamp: from, value });
+        }
+        value += delta;
+        if timestamp >= from {
+            match points.last_mut() {
+                Some(last) if last.timestamp == timestamp => {
+                    last.value = value;
+                }
+                _ => points.push(TimeseriesPoint { timestamp, value }),
+            }
+        }
+    }
+    if points.is_empty() {
+        points.push(TimeseriesPoint { timestamp: from, value });
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(minutes: i64) -> NaiveDateTime {
+        chrono::DateTime::from_timestamp(minutes * 60, 0).unwrap().naive_utc()
+    }
+
+    fn p(minutes: i64, value: i64) -> TimeseriesPoint {
+        TimeseriesPoint { timestamp: t(minutes), value }
+    }
+
+    #[test]
+    fn test_running_count() {
+        assert_eq!(running_count([].into_iter(), t(0), t(10)), vec![p(0, 0)]);
+
+        let events = [(t(1), 1), (t(2), 1), (t(5), -1), (t(5), 1), (t(7), -1)];
+        assert_eq!(
+            running_count(events.into_iter(), t(0), t(10)),
+            vec![p(0, 0), p(1, 1), p(2, 2), p(5, 2), p(7, 1)],
+        );
+        assert_eq!(
+            running_count(events.into_iter(), t(3), t(6)),
+            vec![p(3, 2), p(5, 2)],
+        );
+        assert_eq!(
+            running_count(events.into_iter(), t(8), t(10)),
+            vec![p(8, 1)],
+        );
+    }
+}
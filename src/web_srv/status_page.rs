@@ -0,0 +1,309 @@
+//! Public status page for the website of the space.
+//!
+//! Browsers requesting `/status` get a page showing whether the space is
+//! open, the number of people inside, the next public event and the contact
+//! info of [`server_status_page`].  Names are never shown.  The page is
+//! cached for [`CACHE_TTL`] and requests are limited per client IP address,
+//! so the page can be linked publicly.  Behind a reverse proxy, its address
+//! must be listed in `trusted_proxies` for the limit to be per client.
+//!
+//! [`server_status_page`]: crate::config::Config::server_status_page
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use minijinja::Environment;
+use salvo::http::header::{ACCEPT, CACHE_CONTROL};
+use salvo::http::HeaderValue;
+use salvo::writing::Text;
+use salvo::{handler, Request, Response};
+use serde::Serialize;
+
+use super::{state, ApiError, AppState};
+use crate::config::StatusPage;
+use crate::db::DbChatId;
+use crate::modules::timezones;
+use crate::{models, schema};
+
+/// How long a rendered page is served.
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Presence samples older than this don't tell whether the space is open.
+const PRESENCE_MAX_AGE_MINUTES: i64 = 15;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+lazy_static::lazy_static! {
+    /// Rendered page of each instance.
+    static ref CACHE: Mutex<HashMap<String, (Instant, String)>> =
+        Mutex::new(HashMap::new());
+    static ref LIMITER: RateLimiter = RateLimiter::default();
+}
+
+/// Whether the request to `/status` is for the page rather than for the
+/// JSON state of services.  Called while routing, before the request
+/// has a [`state`].
+pub(super) fn is_page_request(state: &AppState, req: &Request) -> bool {
+    state.config.server_status_page.is_some()
+        && req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("text/html"))
+}
+
+/// The status page.
+#[handler]
+pub async fn get_status_page(
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<(), ApiError> {
+    let state = state();
+    let Some(conf) = &state.config.server_status_page else {
+        return Err(ApiError::not_found());
+    };
+    let now = Instant::now();
+    if !LIMITER.allow(
+        client_ip(req, &conf.trusted_proxies),
+        conf.requests_per_minute,
+        now,
+    ) {
+        return Err(ApiError::too_many_requests());
+    }
+
+    let html = {
+        let mut cache = CACHE.lock().unwrap();
+        match cache.get(&state.env.instance) {
+            Some((at, html)) if now.duration_since(*at) < CACHE_TTL => {
+                html.clone()
+            }
+            _ => {
+                let page = load(
+                    &mut state.conn.lock().unwrap(),
+                    conf,
+                    timezones::space_tz(&state.env),
+                    Utc::now().naive_utc(),
+                )?;
+                let html = render(&page).map_err(|e| {
+                    log::error!("status page: failed to render: {e}");
+                    ApiError::internal()
+                })?;
+                cache.insert(state.env.instance.clone(), (now, html.clone()));
+                html
+            }
+        }
+    };
+    res.headers_mut().insert(
+        CACHE_CONTROL,
+        HeaderValue::from_str(&format!(
+            "public, max-age={}",
+            CACHE_TTL.as_secs()
+        ))
+        .map_err(|_| ApiError::internal())?,
+    );
+    res.render(Text::Html(html));
+    Ok(())
+}
+
+fn client_ip(req: &Request, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let addr = req.remote_addr();
+    let remote = addr
+        .as_ipv4()
+        .map(|a| IpAddr::V4(*a.ip()))
+        .or_else(|| addr.as_ipv6().map(|a| IpAddr::V6(*a.ip())));
+    let forwarded = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    forwarded_client(remote, &forwarded, trusted_proxies)
+}
+
+/// The client of a request from `remote`: if it's a trusted proxy, the last
+/// address in `X-Forwarded-For` that isn't one.  Earlier addresses are set by
+/// the client, so they can't be trusted.
+fn forwarded_client(
+    remote: Option<IpAddr>,
+    forwarded_for: &str,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let mut client = remote;
+    for hop in forwarded_for.rsplit(',') {
+        if !client.is_some_and(|c| trusted_proxies.contains(&c)) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(hop) => client = Some(hop),
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// Fixed-window request counter per client.
+#[derive(Default)]
+struct RateLimiter {
+    windows: Mutex<HashMap<Option<IpAddr>, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    /// Count a request of the client and return whether it's within the
+    /// limit.
+    fn allow(
+        &self,
+        client: Option<IpAddr>,
+        per_minute: u32,
+        now: Instant,
+    ) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+        windows
+            .retain(|_, (start, _)| now.duration_since(*start) < RATE_WINDOW);
+        let (_, count) = windows.entry(client).or_insert((now, 0));
+        *count = count.saturating_add(1);
+        *count <= per_minute
+    }
+}
+
+#[derive(Serialize)]
+struct Page {
+    title: String,
+    /// `None` if there are no recent presence samples.
+    people: Option<i32>,
+    event: Option<Event>,
+    contact: String,
+    updated: String,
+}
+
+#[derive(Serialize)]
+struct Event {
+    title: String,
+    at: String,
+}
+
+/// Collect the page as of `now` (UTC), with times in the time zone `tz`.
+fn load(
+    conn: &mut SqliteConnection,
+    conf: &StatusPage,
+    tz: Tz,
+    now: NaiveDateTime,
+) -> QueryResult<Page> {
+    let people = schema::presence_log::table
+        .filter(schema::presence_log::deleted_at.is_null())
+        .filter(
+            schema::presence_log::timestamp
+                .gt(now - chrono::Duration::minutes(PRESENCE_MAX_AGE_MINUTES)),
+        )
+        .order(schema::presence_log::timestamp.desc())
+        .select(schema::presence_log::person_count)
+        .first::<i32>(conn)
+        .optional()?;
+
+    let chats = conf.event_chats.iter().map(|&c| DbChatId::from(c));
+    let event = schema::countdowns::table
+        .filter(schema::countdowns::finished.eq(false))
+        .filter(schema::countdowns::target_at.gt(now))
+        .filter(schema::countdowns::chat_id.eq_any(chats.collect::<Vec<_>>()))
+        .order(schema::countdowns::target_at.asc())
+        .select(models::Countdown::as_select())
+        .first(conn)
+        .optional()?
+        .map(|c| Event {
+            title: c.title,
+            at: timezones::to_local(tz, c.target_at)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+        });
+
+    Ok(Page {
+        title: conf.title.clone(),
+        people,
+        event,
+        contact: conf.contact.clone(),
+        updated: timezones::to_local(tz, now).format("%H:%M").to_string(),
+    })
+}
+
+fn render(page: &Page) -> Result<String, minijinja::Error> {
+    let mut env = Environment::new();
+    env.add_template("status_page.html", include_str!("status_page.html"))?;
+    env.get_template("status_page.html")?.render(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::default();
+        let alice = Some(IpAddr::from([192, 0, 2, 1]));
+        let bob = Some(IpAddr::from([192, 0, 2, 2]));
+        let now = Instant::now();
+        assert!(limiter.allow(alice, 2, now));
+        assert!(limiter.allow(alice, 2, now));
+        assert!(!limiter.allow(alice, 2, now));
+        assert!(limiter.allow(bob, 2, now));
+        assert!(limiter.allow(alice, 2, now + RATE_WINDOW));
+    }
+
+    #[test]
+    fn test_forwarded_client() {
+        let proxy = IpAddr::from([127, 0, 0, 1]);
+        let alice = IpAddr::from([192, 0, 2, 1]);
+        let trusted = [proxy];
+        assert_eq!(forwarded_client(Some(alice), "", &trusted), Some(alice));
+        // Only trusted proxies can set the client address.
+        assert_eq!(
+            forwarded_client(Some(alice), "192.0.2.2", &trusted),
+            Some(alice),
+        );
+        assert_eq!(
+            forwarded_client(Some(proxy), "192.0.2.1", &trusted),
+            Some(alice),
+        );
+        // Spoofed addresses before the one added by the proxy are ignored.
+        assert_eq!(
+            forwarded_client(Some(proxy), "192.0.2.2, 192.0.2.1", &trusted),
+            Some(alice),
+        );
+        assert_eq!(
+            forwarded_client(Some(proxy), "192.0.2.1, 127.0.0.1", &trusted),
+            Some(alice),
+        );
+        assert_eq!(forwarded_client(Some(proxy), "", &trusted), Some(proxy));
+        assert_eq!(
+            forwarded_client(Some(proxy), "192.0.2.1", &[]),
+            Some(proxy),
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let mut page = Page {
+            title: "F0".to_string(),
+            people: Some(3),
+            event: Some(Event {
+                title: "<Party>".to_string(),
+                at: "2024-01-31 20:00".to_string(),
+            }),
+            contact: "@f0".to_string(),
+            updated: "12:00".to_string(),
+        };
+        let html = render(&page).unwrap();
+        assert!(html.contains("Open"), "{html}");
+        assert!(html.contains("3 people inside"), "{html}");
+        assert!(html.contains("&lt;Party&gt;"), "{html}");
+
+        page.people = Some(0);
+        assert!(render(&page).unwrap().contains("Closed"));
+        page.people = None;
+        assert!(render(&page).unwrap().contains("Unknown"));
+    }
+}